@@ -0,0 +1,5 @@
+//! srcpd Library: SRCP Messages und deren Parser.
+//! Erlaubt externen Rust Tools und Tests SRCP Messages zu erstellen und zu parsen, ohne Code zu kopieren.
+//! Mit Feature "serde" sind alle SRCP Message Typen serialisierbar.
+
+pub mod srcp_server_types;
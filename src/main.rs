@@ -13,6 +13,8 @@ use log::{error, info, warn, LevelFilter};
 use nix::libc::{SIGHUP, SIGINT, SIGQUIT, SIGTERM};
 use signal_hook::iterator::Signals;
 use srcp_server_types::{SRCPMessage, SRCPMessageDevice, SRCPMessageID, SRCPMessageType};
+//SRCP Messages aus der Library, für alle Module als crate::srcp_server_types verfügbar
+use srcpd::srcp_server_types;
 use std::io::Write;
 use std::str::FromStr;
 use std::{
@@ -20,28 +22,50 @@ use std::{
   collections::HashMap,
   env, fs, process,
   rc::Rc,
-  sync::mpsc::{self, Sender},
+  sync::mpsc::{self, SyncSender},
   thread,
   time::Duration,
 };
 
-use crate::{srcp_server_ddl::DDL, srcp_server_s88::S88, srcp_server_types::Message};
+use crate::{
+  srcp_server_analog::AnalogServer, srcp_server_ddl::DDL, srcp_server_gpio::GpioServer,
+  srcp_server_i2c::I2cServer, srcp_server_s88::S88, srcp_server_types::Message,
+};
 
 mod srcp;
+mod srcp_acl;
+mod srcp_alias;
+mod srcp_client;
 mod srcp_dcc_prog;
+mod srcp_dekoder_ident;
 mod srcp_devices_ddl;
 mod srcp_devices_ddl_ga;
 mod srcp_devices_ddl_gl;
 mod srcp_devices_ddl_power;
+mod srcp_devices_ddl_server;
 mod srcp_devices_ddl_sm;
+mod srcp_ga_rueckmeldung;
+mod srcp_hardware;
+mod srcp_http;
+mod srcp_info_queue;
+mod srcp_journal;
+mod srcp_mfx_lok_cache;
 mod srcp_mfx_rds;
+mod srcp_mqtt;
+mod srcp_nothalt;
 mod srcp_protocol_ddl;
 mod srcp_protocol_ddl_dcc;
 mod srcp_protocol_ddl_mfx;
 mod srcp_protocol_ddl_mm;
+mod srcp_protocol_ddl_selbsttest;
+mod srcp_protocol_ddl_sx;
+mod srcp_roster;
+mod srcp_server_analog;
 mod srcp_server_ddl;
+mod srcp_server_ga_fb;
+mod srcp_server_gpio;
+mod srcp_server_i2c;
 mod srcp_server_s88;
-mod srcp_server_types;
 
 /// PID Filename
 const PID_FILE: &str = "/run/srcpd.pid";
@@ -51,6 +75,9 @@ fn get_alle_srcp_server() -> Vec<Rc<RefCell<dyn srcp_server_types::SRCPServer>>>
   vec![
     Rc::new(RefCell::new(S88::new())),
     Rc::new(RefCell::new(DDL::new())),
+    Rc::new(RefCell::new(GpioServer::new())),
+    Rc::new(RefCell::new(I2cServer::new())),
+    Rc::new(RefCell::new(AnalogServer::new())),
   ]
 }
 
@@ -61,6 +88,12 @@ struct CmdLineConfig {
   config_file: String,
   //fork() ja/nein. Default: ja
   fork: bool,
+  //Replay eines Journal Files an laufenden srcpd statt Start srcpd. Default: kein Replay
+  replay: Option<String>,
+  //Ausgabe DDL Referenztelegramme statt Start srcpd. Default: nein
+  selbsttest: bool,
+  //Einzelnes Kommando an laufenden srcpd statt Start srcpd. Default: kein Kommando
+  send: Option<String>,
 }
 
 impl CmdLineConfig {
@@ -69,6 +102,9 @@ impl CmdLineConfig {
   /// -? -> Hilfetext, Programmabruch
   /// -n -> No fork()
   /// -f configfile -> zu verwendendes Configfile
+  /// -r journalfile -> Replay Journal an laufenden srcpd
+  /// -t -> DDL Selbsttest, Ausgabe Referenztelegramme
+  /// -s / --send kommando -> Kommando an laufenden srcpd, Ausgabe Antwort
   /// # Arguments
   /// * args - Kommandozeilenargumente
   fn parse_cmd_line(mut args: impl Iterator<Item = String>) -> Result<CmdLineConfig, String> {
@@ -78,6 +114,9 @@ impl CmdLineConfig {
     let mut cmd_line_config = CmdLineConfig {
       config_file: format!("/etc/{}.conf", env!("CARGO_PKG_NAME")).to_string(),
       fork: true,
+      replay: None,
+      selbsttest: false,
+      send: None,
     };
     loop {
       match args.next() {
@@ -94,6 +133,21 @@ impl CmdLineConfig {
               _ => return Err("-f ohne Configfile".to_string()),
             }
           }
+          "-r" => {
+            cmd_line_config.replay = match args.next() {
+              Some(val) => Some(val),
+              _ => return Err("-r ohne Journalfile".to_string()),
+            }
+          }
+          "-t" => {
+            cmd_line_config.selbsttest = true;
+          }
+          "-s" | "--send" => {
+            cmd_line_config.send = match args.next() {
+              Some(val) => Some(val),
+              _ => return Err(format!("{val} ohne Kommando")),
+            }
+          }
           _ => {
             return Err(format!("Unbekannter Parameter {val}"));
           }
@@ -137,6 +191,8 @@ fn main() {
     .init();
   if let Err(msg) = start(env::args()) {
     error!("Start Error: {}", msg);
+    //Z.B. für Shell Scripts mit --send
+    process::exit(1);
   }
 }
 
@@ -145,7 +201,7 @@ fn main() {
 /// an alle Busse gesendet wurde.
 /// # Arguments
 /// * all_cmd_tx - alle Sender für alle vorhandene SRCP Server
-fn terminate_poweroff(all_cmd_tx: HashMap<usize, Sender<Message>>) {
+fn terminate_poweroff(all_cmd_tx: HashMap<usize, SyncSender<Message>>) {
   let mut signals = Signals::new(&[SIGTERM, SIGINT, SIGHUP, SIGQUIT]).unwrap();
   for _ in signals.forever() {
     //Allen SRCP Server Power Off senden
@@ -164,8 +220,10 @@ fn terminate_poweroff(all_cmd_tx: HashMap<usize, Sender<Message>>) {
       {
         warn!("Terminate Send Power Off fail");
       }
+      //Danach Hilfsthreads der Server (z.B. MFX RDS) beenden
+      let _ = server.send(Message::new_beenden());
     }
-    //Kurze Pause damit alles ausgeschaltet werden kann
+    //Kurze Pause damit alles ausgeschaltet und beendet werden kann
     thread::sleep(Duration::from_millis(200));
     break;
   }
@@ -189,27 +247,47 @@ fn del_pidfile() {
 /// # Arguments
 /// * args - Kommandozeilenargumente
 fn start(args: impl Iterator<Item = String>) -> Result<(), String> {
-  println!(
-    "{} V{} {}",
-    env!("CARGO_PKG_NAME"),
-    env!("CARGO_PKG_VERSION"),
-    env!("CARGO_PKG_HOMEPAGE")
-  );
-  println!("Raspberry PI: in /boot/config.txt core_freq=250 und core_freq_min=250 setzen!");
-  println!("Dies ist notwendig um einen stabilen und für S88 keinen zu hohen SPI Clock zu haben.");
-  println!("GPIO Zugriff erfolgt über GPIO ABI, GPIO's dürfen NICHT in sysfs exportiert sein -> echo x >/sys/class/gpio/unexport");
-  let cmd_line_config = match CmdLineConfig::parse_cmd_line(args) {
+  let cmd_line_config = CmdLineConfig::parse_cmd_line(args);
+  //Mit --send wird auf stdout nur die Antwort ausgegeben
+  if !cmd_line_config
+    .as_ref()
+    .is_ok_and(|cmd_line_config| cmd_line_config.send.is_some())
+  {
+    println!(
+      "{} V{} {}",
+      env!("CARGO_PKG_NAME"),
+      env!("CARGO_PKG_VERSION"),
+      env!("CARGO_PKG_HOMEPAGE")
+    );
+    println!("Raspberry PI: in /boot/config.txt core_freq=250 und core_freq_min=250 setzen!");
+    println!(
+      "Dies ist notwendig um einen stabilen und für S88 keinen zu hohen SPI Clock zu haben."
+    );
+    println!("GPIO Zugriff erfolgt über GPIO ABI, GPIO's dürfen NICHT in sysfs exportiert sein -> echo x >/sys/class/gpio/unexport");
+  }
+  let cmd_line_config = match cmd_line_config {
     Ok(v) => v,
     Err(message) => {
-      println!("Aufruf: {} [-n] [-f configfile]", env!("CARGO_PKG_NAME"));
+      println!(
+        "Aufruf: {} [-n] [-f configfile] [-r journalfile] [-t] [--send kommando]",
+        env!("CARGO_PKG_NAME")
+      );
       println!("-n No fork()");
       println!("-f configfile Verwende configfile");
+      println!("-r journalfile Replay aller Kommandos aus journalfile an laufenden srcpd");
+      println!("-t DDL Selbsttest, Ausgabe Referenztelegramme für Logic Analyzer");
+      println!("-s, --send kommando Kommando an laufenden srcpd senden und Antwort ausgeben, z.B. \"SET 1 POWER OFF\"");
       println!("{message}");
       return Ok(());
     }
   };
-  //fork() wenn notwendig
-  if cmd_line_config.fork {
+  //DDL Selbsttest ohne Hardware und Konfigfile
+  if cmd_line_config.selbsttest {
+    srcp_protocol_ddl_selbsttest::ausgeben();
+    return Ok(());
+  }
+  //fork() wenn notwendig, nicht für Replay und Send
+  if cmd_line_config.fork && cmd_line_config.replay.is_none() && cmd_line_config.send.is_none() {
     info!("fork()");
     match fork() {
       Ok(Fork::Parent(child)) => {
@@ -230,10 +308,27 @@ fn start(args: impl Iterator<Item = String>) -> Result<(), String> {
     )
     .as_str(),
   );
+  //Replay Journal oder Send an laufenden srcpd, dieser wird über den konfigurierten SRCP Port erreicht
+  if cmd_line_config.replay.is_some() || cmd_line_config.send.is_some() {
+    let port = config_file_values
+      .get("srcp")
+      .and_then(|srcp| srcp.get("port"))
+      .and_then(|port| port.as_ref())
+      .and_then(|port| port.parse::<u16>().ok())
+      .ok_or("Keine gültige [srcp] port-Angabe in Konfigfile")?;
+    if let Some(cmd) = &cmd_line_config.send {
+      return srcp_client::send(cmd, port);
+    }
+    if let Some(journal) = &cmd_line_config.replay {
+      return srcp_journal::replay(journal, port);
+    }
+  }
+  //Hardware Erkennung für Defaults der SRCP Server
+  srcp_hardware::startup(config_file_values.get("hardware"))?;
   //EIN Channel Receiver der Info Messages aller Server
-  let (info_tx, info_rx) = mpsc::channel();
+  let (info_tx, info_rx) = mpsc::sync_channel(srcp::SERVER_INFO_QUEUE_MAX);
   //Alle Channel Sender für Kommandos zu den SRCP Servern. Key ist die Busnummer.
-  let mut all_cmd_tx: HashMap<usize, Sender<Message>> = HashMap::new();
+  let mut all_cmd_tx: HashMap<usize, SyncSender<Message>> = HashMap::new();
   //Start aller über Konfiguration verlangter Modellbahn Schnittstellen Server
   //Alle belegten SRCP Busnummern, Key ist die Busnummer
   let mut aktive_srcp_busse: HashMap<usize, bool> = HashMap::new();
@@ -259,36 +354,42 @@ fn start(args: impl Iterator<Item = String>) -> Result<(), String> {
           srcpsrv.get_name()
         ))?;
       //Server wird verwendet, gültige Busnummer vorhanden
+      //Init vor Belegung der SRCP Busse, die Anzahl belegter Busse kann von der Konfiguration abhängen (z.B. S88)
+      if aktive_srcp_busse.contains_key(&bus_nr) {
+        error!(
+          "SRCP bussnummer {} doppelt vergeben. Ignoriert für {}",
+          bus_nr,
+          srcpsrv.get_name()
+        );
+        continue;
+      }
+      if let Err(msg) = srcpsrv.init(bus_nr, &config_server_values) {
+        error!("Error Server init: {}", msg);
+        continue;
+      }
+      //Durch diesen Server belegte SRCP Busse
+      let mut belegte_busse: Vec<usize> = Vec::new();
       for n in 0..srcpsrv.get_srcp_bus_count() {
-        if aktive_srcp_busse.contains_key(&(bus_nr + n)) {
+        let bus = srcpsrv.get_busnr() + n;
+        if aktive_srcp_busse.contains_key(&bus) {
           error!(
             "SRCP bussnummer {} doppelt vergeben. Ignoriert für {}",
-            bus_nr + n,
+            bus,
             srcpsrv.get_name()
           );
         } else {
-          info!(
-            "Neuer SRCP Server {} auf Bus {}",
-            srcpsrv.get_name(),
-            bus_nr + n
-          );
-          //Init nur auf erstem Bus (nur eine Instanz vorhanden)
-          if n == 0 {
-            if let Err(msg) = srcpsrv.init(bus_nr, &config_server_values) {
-              error!("Error Server init: {}", msg);
-              break;
-            }
-          }
-          aktive_srcp_busse.insert(bus_nr + n, true);
+          info!("Neuer SRCP Server {} auf Bus {}", srcpsrv.get_name(), bus);
+          aktive_srcp_busse.insert(bus, true);
+          belegte_busse.push(bus);
         }
       }
       //Start Server wenn konfiguriert
-      if aktive_srcp_busse.contains_key(&srcpsrv.get_busnr()) {
-        let (cmd_tx, cmd_rx) = mpsc::channel();
+      if !belegte_busse.is_empty() {
+        let (cmd_tx, cmd_rx) = mpsc::sync_channel(srcp::CMD_QUEUE_MAX);
         srcpsrv.start(cmd_rx, info_tx.clone());
         //Für alle SRCP Busse des Servers falls er mehrere unterstützt (wie z.B. S88)
-        for sub_bus in 0..srcpsrv.get_srcp_bus_count() {
-          all_cmd_tx.insert(srcpsrv.get_busnr() + sub_bus, cmd_tx.clone());
+        for bus in belegte_busse {
+          all_cmd_tx.insert(bus, cmd_tx.clone());
         }
       }
     }
@@ -311,12 +412,452 @@ fn start(args: impl Iterator<Item = String>) -> Result<(), String> {
 #[cfg(test)]
 mod tests {
   use super::*;
+  use srcp_server_types::SRCPServer;
+  use std::{
+    io::{BufRead, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::{mpsc::Receiver, Arc, Barrier},
+  };
+
+  /// Test SRCP Server ohne Hardware: INIT, SET und GET GL werden nur gespeichert und mit
+  /// OK / INFO beantwortet. TERM GL 99 simuliert einen Ausfall (panic).
+  struct FakeBusServer {
+    //SRCP Busnr
+    busnr: usize,
+    //Wenn vorhanden: Start wird nach dem Warten auf die Barriere ohne Ready abgebrochen
+    start_abbruch: Option<Arc<Barrier>>,
+  }
+  impl FakeBusServer {
+    /// Alle Kommandos abarbeiten bis der Kommando Channel geschlossen wird
+    /// # Arguments
+    /// * bus - SRCP Bus
+    /// * rx - Channel Receiver über denn Kommandos empfangen werden
+    /// * tx - Channel Sender über den Info Messages zurück gesendet werden
+    fn execute(bus: usize, rx: Receiver<Message>, tx: SyncSender<SRCPMessage>) {
+      //Parameter aller GL's ab Adresse, Key ist die Adresse
+      let mut all_gl: HashMap<u32, Vec<String>> = HashMap::new();
+      tx.send(SRCPMessage::new_ready(bus)).unwrap();
+      while let Ok(msg) = rx.recv() {
+        let Message::SRCPMessage { srcp_message } = msg else {
+          continue;
+        };
+        let SRCPMessageID::Command { msg_type } = srcp_message.message_id else {
+          continue;
+        };
+        let Some(adr) = srcp_message.get_adr() else {
+          tx.send(SRCPMessage::new_err(&srcp_message, "412", "wrong value"))
+            .unwrap();
+          continue;
+        };
+        let info = |session_id: Option<u32>, info_code: &str, parameter: Vec<String>| {
+          SRCPMessage::new(
+            session_id,
+            bus,
+            SRCPMessageID::Info {
+              info_code: info_code.to_string(),
+            },
+            SRCPMessageDevice::GL,
+            parameter,
+          )
+        };
+        match msg_type {
+          SRCPMessageType::TERM if adr == 99 => panic!("Test Ausfall"),
+          SRCPMessageType::INIT => {
+            tx.send(SRCPMessage::new_ok(&srcp_message, "200")).unwrap();
+            tx.send(info(None, "101", srcp_message.parameter.clone()))
+              .unwrap();
+            all_gl.insert(adr, vec![adr.to_string()]);
+          }
+          SRCPMessageType::SET if all_gl.contains_key(&adr) => {
+            tx.send(SRCPMessage::new_ok(&srcp_message, "200")).unwrap();
+            tx.send(info(None, "100", srcp_message.parameter.clone()))
+              .unwrap();
+            all_gl.insert(adr, srcp_message.parameter.clone());
+          }
+          SRCPMessageType::GET if adr == 0 => {
+            //Adresse 0: alle GL's als Zwischenantworten, abgeschlossen mit OK
+            let mut all_adr: Vec<&u32> = all_gl.keys().collect();
+            all_adr.sort();
+            for adr in all_adr {
+              tx.send(
+                info(srcp_message.session_id, "100", all_gl[adr].clone()).mit_weiteren_antworten(),
+              )
+              .unwrap();
+            }
+            tx.send(SRCPMessage::new_ok(&srcp_message, "200")).unwrap();
+          }
+          SRCPMessageType::GET if all_gl.contains_key(&adr) => {
+            tx.send(info(srcp_message.session_id, "100", all_gl[&adr].clone()))
+              .unwrap();
+          }
+          _ => {
+            tx.send(SRCPMessage::new_err(&srcp_message, "412", "wrong value"))
+              .unwrap();
+          }
+        }
+      }
+    }
+  }
+  impl SRCPServer for FakeBusServer {
+    fn get_name(&self) -> &'static str {
+      "fake"
+    }
+    fn get_busnr(&self) -> usize {
+      self.busnr
+    }
+    fn init(
+      &mut self, busnr: usize, _config_file_bus: &HashMap<String, Option<String>>,
+    ) -> Result<(), String> {
+      self.busnr = busnr;
+      Ok(())
+    }
+    fn start(&self, rx: Receiver<Message>, tx: SyncSender<SRCPMessage>) {
+      let bus = self.busnr;
+      let start_abbruch = self.start_abbruch.clone();
+      srcp_server_types::spawn_server_thread("FakeBusServer", bus..bus + 1, tx, move |tx| {
+        if let Some(start_abbruch) = start_abbruch {
+          start_abbruch.wait();
+          return Err("Test Startabbruch".to_string());
+        }
+        FakeBusServer::execute(bus, rx, tx);
+        Ok(())
+      });
+    }
+  }
+
+  /// SRCP Client einer Test Session
+  struct TestClient {
+    reader: BufReader<TcpStream>,
+  }
+  impl TestClient {
+    /// Neue Session eröffnen, Handshake bis GO.
+    /// Wartet bis der SRCP Server Verbindungen annimmt.
+    /// # Arguments
+    /// * port - TCP Port des SRCP Servers auf localhost
+    /// * mode - COMMAND oder INFO
+    /// * gl_kompakt - Kompaktes INFO GL Format verlangen
+    fn connect(port: u16, mode: &str, gl_kompakt: bool) -> TestClient {
+      let mut versuche = 0;
+      let stream = loop {
+        match TcpStream::connect(("127.0.0.1", port)) {
+          Ok(stream) => break stream,
+          Err(msg) => {
+            versuche += 1;
+            assert!(versuche < 100, "SRCP Server nicht erreichbar: {}", msg);
+            thread::sleep(Duration::from_millis(20));
+          }
+        }
+      };
+      stream
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .unwrap();
+      let mut client = TestClient {
+        reader: BufReader::new(stream),
+      };
+      //Willkommensmessage ohne Timestamp
+      let mut line = String::new();
+      client.reader.read_line(&mut line).unwrap();
+      assert!(line.starts_with("srcpd V"), "Willkommensmessage: {}", line);
+      assert_eq!(
+        client.send(format!("SET PROTOCOL SRCP {}", srcp::SRCP_VERSION).as_str()),
+        "201 OK PROTOCOL SRCP"
+      );
+      if gl_kompakt {
+        assert_eq!(
+          client.send("SET INFOFORMAT GL COMPACT"),
+          "201 OK INFOFORMAT"
+        );
+      }
+      assert_eq!(
+        client.send(format!("SET CONNECTIONMODE SRCP {}", mode).as_str()),
+        "202 OK CONNECTIONMODE"
+      );
+      assert!(client.send("GO").starts_with("200 OK GO "));
+      client
+    }
+    /// Nächste Zeile ohne Timestamp und abschliessende Spaces lesen
+    fn read(&mut self) -> String {
+      let mut line = String::new();
+      self
+        .reader
+        .read_line(&mut line)
+        .expect("Keine Antwort vom SRCP Server");
+      let (_timestamp, msg) = line.split_once(' ').expect("Zeile ohne Timestamp");
+      msg.trim_end().to_string()
+    }
+    /// Zeile senden und Antwort lesen
+    /// # Arguments
+    /// * line - Zu sendende Zeile ohne \n
+    fn send(&mut self, line: &str) -> String {
+      self
+        .reader
+        .get_mut()
+        .write_all(format!("{}\n", line).as_bytes())
+        .unwrap();
+      self.read()
+    }
+  }
+
+  #[test]
+  fn srcp_session_test() {
+    //Freier Port für den SRCP Server
+    let port = TcpListener::bind("127.0.0.1:0")
+      .unwrap()
+      .local_addr()
+      .unwrap()
+      .port();
+    //Fake Bus 1 starten
+    let (info_tx, info_rx) = mpsc::sync_channel(srcp::SERVER_INFO_QUEUE_MAX);
+    let (cmd_tx, cmd_rx) = mpsc::sync_channel(srcp::CMD_QUEUE_MAX);
+    let mut fake_bus = FakeBusServer {
+      busnr: 0,
+      start_abbruch: None,
+    };
+    fake_bus.init(1, &HashMap::new()).unwrap();
+    //info_tx bleibt erhalten, damit der Dispatcher auch nach dem Ausfall des Fake Bus weiterläuft
+    fake_bus.start(cmd_rx, info_tx.clone());
+    //Fake Bus 3 bricht den Start ab
+    let (cmd_tx_3, cmd_rx_3) = mpsc::sync_channel(srcp::CMD_QUEUE_MAX);
+    let start_abbruch = Arc::new(Barrier::new(2));
+    let mut fake_bus_3 = FakeBusServer {
+      busnr: 0,
+      start_abbruch: Some(start_abbruch.clone()),
+    };
+    fake_bus_3.init(3, &HashMap::new()).unwrap();
+    fake_bus_3.start(cmd_rx_3, info_tx.clone());
+    let all_cmd_tx: HashMap<usize, SyncSender<Message>> =
+      HashMap::from([(1, cmd_tx), (3, cmd_tx_3)]);
+    let config: HashMap<String, HashMap<String, Option<String>>> = HashMap::from([(
+      "srcp".to_string(),
+      HashMap::from([("port".to_string(), Some(port.to_string()))]),
+    )]);
+    thread::spawn(move || srcp::startup(&config, info_rx, &all_cmd_tx));
+
+    let mut info = TestClient::connect(port, "INFO", false);
+    let mut cmd = TestClient::connect(port, "COMMAND", false);
+    let mut info_kompakt = TestClient::connect(port, "INFO", true);
+    let mut cmd_kompakt = TestClient::connect(port, "COMMAND", true);
+    //INIT
+    assert_eq!(cmd.send("INIT 1 GL 3 N 1 28 5"), "200 OK 1 GL");
+    assert_eq!(info.read(), "101 INFO 1 GL 3 N 1 28 5");
+    //SET
+    assert_eq!(cmd.send("SET 1 GL 3 1 10 28 1 0 0 0 0"), "200 OK 1 GL");
+    assert_eq!(info.read(), "100 INFO 1 GL 3 1 10 28 1 0 0 0 0");
+    //GET wird nur der Session beantwortet
+    assert_eq!(cmd.send("GET 1 GL 3"), "100 INFO 1 GL 3 1 10 28 1 0 0 0 0");
+    //Fehler durch Server
+    assert_eq!(cmd.send("GET 1 GL 4"), "412 ERROR wrong value");
+    //Mehrzeilige Antwort in Reihenfolge
+    assert_eq!(cmd.send("INIT 1 GL 5 N 1 28 5"), "200 OK 1 GL");
+    assert_eq!(info.read(), "101 INFO 1 GL 5 N 1 28 5");
+    assert_eq!(cmd.send("GET 1 GL 0"), "100 INFO 1 GL 3 1 10 28 1 0 0 0 0");
+    assert_eq!(cmd.read(), "100 INFO 1 GL 5");
+    assert_eq!(cmd.read(), "200 OK 1 GL");
+    //Fehler durch Parser
+    assert_eq!(cmd.send("GET 1 XY 4"), "421 ERROR unsupported device");
+    //Nicht vorhandener Bus
+    assert_eq!(cmd.send("GET 2 GL 3"), "412 ERROR wrong value");
+    //Leere Zeile als NOP
+    assert_eq!(cmd.send(""), "200 OK");
+    //Kompaktes INFO GL Format
+    assert_eq!(info_kompakt.read(), "101 INFO 1 GL 3 N 1 28 5");
+    assert_eq!(info_kompakt.read(), "100 INFO 1 GL 3 1 10 28 0x1");
+    assert_eq!(info_kompakt.read(), "101 INFO 1 GL 5 N 1 28 5");
+    assert_eq!(
+      cmd_kompakt.send("SET 1 GL 3 1 12 28 0 1 0 1 1"),
+      "200 OK 1 GL"
+    );
+    assert_eq!(info.read(), "100 INFO 1 GL 3 1 12 28 0 1 0 1 1");
+    assert_eq!(info_kompakt.read(), "100 INFO 1 GL 3 1 12 28 0x1A");
+    assert_eq!(
+      cmd_kompakt.send("GET 1 GL 3"),
+      "100 INFO 1 GL 3 1 12 28 0x1A"
+    );
+    //Session Liste, sortiert nach Session ID
+    assert_eq!(
+      cmd.send("GET 0 SERVER SESSIONS"),
+      "100 INFO 0 SERVER SESSIONS 4"
+    );
+    for mode in ["INFO", "COMMAND", "INFO", "COMMAND"] {
+      let session = cmd.read();
+      let teile: Vec<&str> = session.split(' ').collect();
+      assert_eq!(teile[..4], ["100", "INFO", "0", "SERVER"]);
+      assert_eq!(
+        teile[4..],
+        ["SESSION", teile[5], mode, teile[7], teile[8], teile[9]]
+      );
+      assert!(teile[7].starts_with("127.0.0.1:"));
+    }
+    //Nur validieren
+    assert_eq!(cmd.send("CHECK GET 0 SERVER SESSIONS"), "200 OK 0 SERVER");
+    assert_eq!(cmd.send("CHECK GET 0 SERVER XY"), "412 ERROR wrong value");
+    //Einzelnes Kommando über eigene Session, ERROR Antwort liefert Err
+    assert!(srcp_client::send("GET 1 GL 3", port).is_ok());
+    assert!(srcp_client::send("GET 1 GL 4", port).is_err());
+    //Ausfall Server: Kommando ohne Antwort, danach sofort Error und GM Info an alle
+    assert_eq!(cmd.send("TERM 1 GL 99"), "417 ERROR timeout");
+    assert_eq!(cmd.send("GET 1 GL 3"), "499 ERROR bus failed");
+    assert_eq!(
+      info.read(),
+      "100 INFO 1 GM 0 0 SRCP_BUS_FAILED \"FakeBusServer: Test Ausfall\""
+    );
+    //Abbruch beim Start ohne Ready: Ausfall wird gemeldet, Kommandos werden sofort mit Error beantwortet
+    start_abbruch.wait();
+    assert_eq!(
+      info.read(),
+      "100 INFO 3 GM 0 0 SRCP_BUS_FAILED \"FakeBusServer: Test Startabbruch\""
+    );
+    assert_eq!(cmd.send("GET 3 GL 3"), "499 ERROR bus failed");
+  }
+  #[test]
+  fn is_erweiterung_test() {
+    let msg = |msg_type: SRCPMessageType, device: SRCPMessageDevice| {
+      SRCPMessage::new(
+        Some(1),
+        1,
+        SRCPMessageID::Command { msg_type },
+        device,
+        vec!["1".to_string()],
+      )
+    };
+    //SRCP 0.8.4 Kommandos
+    assert!(!msg(SRCPMessageType::GET, SRCPMessageDevice::FB).is_erweiterung());
+    assert!(!msg(SRCPMessageType::VERIFY, SRCPMessageDevice::SM).is_erweiterung());
+    assert!(!msg(SRCPMessageType::SET, SRCPMessageDevice::GL).is_erweiterung());
+    //srcpd_rust Erweiterung Verdrahtungstest S88
+    assert!(msg(SRCPMessageType::VERIFY, SRCPMessageDevice::FB).is_erweiterung());
+    //Erweiterungen GL / FB Statistik und INIT Parameter, Antworten mit eigenem INFO Code
+    let cmd = |cmd: &str| SRCPMessage::parse(1, cmd).unwrap().is_erweiterung();
+    assert!(!cmd("GET 1 FB 3"));
+    assert!(cmd("GET 1 FB 3 STATS"));
+    assert!(!cmd("SET 1 FB 3 1"));
+    assert!(cmd("SET 1 FB TRIGGER 12 1,2"));
+    assert!(!cmd("GET 1 GA 0 1"));
+    assert!(cmd("GET 1 GA 0 DELAYED"));
+    assert!(cmd("TERM 1 GA 0 DELAYED"));
+    assert!(!cmd("GET 1 GL 3"));
+    assert!(cmd("GET 1 GL 3 STATS"));
+    assert!(cmd("GET 1 GL 3 INIT"));
+    let info = |info_code: &str| {
+      SRCPMessage::new(
+        None,
+        1,
+        SRCPMessageID::Info {
+          info_code: info_code.to_string(),
+        },
+        SRCPMessageDevice::GL,
+        vec!["3".to_string(), "STATS".to_string()],
+      )
+      .is_erweiterung()
+    };
+    assert!(!info("100"));
+    assert!(info(srcp_server_types::INFO_ERWEITERUNG));
+  }
+  #[test]
+  fn info_queue_test() {
+    let (tx, rx) = srcp_info_queue::info_queue(2);
+    assert_eq!(tx.send(1), Ok(false));
+    assert_eq!(tx.send(2), Ok(false));
+    //Voll, die älteste Message wird verworfen
+    assert_eq!(tx.send(3), Ok(true));
+    assert_eq!(rx.try_recv(), Ok(2));
+    assert_eq!(rx.recv_timeout(Duration::from_millis(10)), Ok(3));
+    assert_eq!(
+      rx.recv_timeout(Duration::from_millis(10)),
+      Err(mpsc::RecvTimeoutError::Timeout)
+    );
+    //Wartende Messages werden nach dem Ende des Senders noch geliefert
+    tx.send(4).unwrap();
+    drop(tx);
+    assert_eq!(rx.iter().collect::<Vec<_>>(), vec![4]);
+    assert_eq!(rx.try_recv(), Err(mpsc::TryRecvError::Disconnected));
+    //Ohne Empfänger liefert send Err
+    let (tx, rx) = srcp_info_queue::info_queue(2);
+    drop(rx);
+    assert_eq!(tx.send(5), Err(5));
+  }
+  #[test]
+  fn nothalt_test() {
+    use srcp_nothalt::NothaltZonen;
+    let config = HashMap::from([("zone_1".to_string(), Some("8:5 9:4".to_string()))]);
+    let mut zonen = NothaltZonen::from_config(Some(&config)).unwrap();
+    let (cmd_tx, cmd_rx) = mpsc::sync_channel(srcp::CMD_QUEUE_MAX);
+    let all_cmd_tx: HashMap<usize, SyncSender<Message>> = HashMap::from([(9, cmd_tx)]);
+    let info = |bus: usize, device: SRCPMessageDevice, parameter: &[&str]| {
+      SRCPMessage::new(
+        None,
+        bus,
+        SRCPMessageID::Info {
+          info_code: "100".to_string(),
+        },
+        device,
+        parameter.iter().map(|p| p.to_string()).collect(),
+      )
+    };
+    let anfahren = SRCPMessage::parse(1, "SET 9 GL 4 1 50 100 0").unwrap();
+    zonen.eval_info(&info(9, SRCPMessageDevice::Power, &["ON"]), &all_cmd_tx);
+    zonen.eval_info(&info(8, SRCPMessageDevice::FB, &["5", "1"]), &all_cmd_tx);
+    //Nothalt wird als Sofortkommando erkannt, ein Client Kommando nie
+    let Ok(Message::SRCPMessage { srcp_message }) = cmd_rx.try_recv() else {
+      panic!("Kein Nothalt gesendet");
+    };
+    assert_eq!(srcp_message.to_string().trim_end(), "SET 9 GL 4 2 0 1");
+    assert!(NothaltZonen::is_nothalt_cmd(&srcp_message));
+    assert!(!NothaltZonen::is_nothalt_cmd(
+      &SRCPMessage::parse(1, "SET 9 GL 4 2 0 1").unwrap()
+    ));
+    //Solange der FB aktiv ist kein Anfahren, Anhalten und andere GL's bleiben erlaubt
+    assert_eq!(
+      NothaltZonen::pruefen(&anfahren),
+      Err(("413", "temporarily prohibited"))
+    );
+    for cmd in ["SET 9 GL 4 1 0 100 1", "SET 9 GL 4 2 0 100 0", "SET 9 GL 5 1 50 100 0"] {
+      assert_eq!(
+        NothaltZonen::pruefen(&SRCPMessage::parse(1, cmd).unwrap()),
+        Ok(()),
+        "{}",
+        cmd
+      );
+    }
+    zonen.eval_info(&info(8, SRCPMessageDevice::FB, &["5", "0"]), &all_cmd_tx);
+    assert_eq!(NothaltZonen::pruefen(&anfahren), Ok(()));
+  }
+  #[test]
+  fn srcp_acl_test() {
+    //Nur Localhost darf verändern, andere Tests verwenden 127.0.0.1
+    let config = HashMap::from([("command".to_string(), Some("127.0.0.0/8".to_string()))]);
+    srcp_acl::startup(Some(&config)).unwrap();
+    let (_info_tx, info_rx) = srcp_info_queue::info_queue(1);
+    let all_cmd_tx: HashMap<usize, SyncSender<Message>> = HashMap::new();
+    let cmd = |cmd: &str| SRCPMessage::parse(1, cmd).unwrap();
+    let fremd = "192.0.2.1".parse().ok();
+    //Gemeinsamer Pfad aller Anbindungen (TCP, HTTP, MQTT) lehnt verbotene Kommandos ab
+    assert_eq!(
+      srcp::execute_command(cmd("SET 1 GL 3 0 1 128 0"), fremd, &info_rx, &all_cmd_tx).err(),
+      Some(("415", "forbidden"))
+    );
+    //GET ist erlaubt, Bus 1 existiert hier nicht
+    assert_eq!(
+      srcp::execute_command(cmd("GET 1 GL 3"), fremd, &info_rx, &all_cmd_tx).err(),
+      Some(("412", "wrong value"))
+    );
+    //Lokal und interne Clients ohne Adresse sind erlaubt
+    let lokal = "127.0.0.1".parse().ok();
+    assert_eq!(
+      srcp::execute_command(cmd("SET 1 GL 3 0 1 128 0"), lokal, &info_rx, &all_cmd_tx).err(),
+      Some(("412", "wrong value"))
+    );
+    assert_eq!(
+      srcp::execute_command(cmd("SET 1 GL 3 0 1 128 0"), None, &info_rx, &all_cmd_tx).err(),
+      Some(("412", "wrong value"))
+    );
+  }
   #[test]
   fn parse_cmd_line_test() {
     //Keine Kommandozeilenargumente
     let cmd_line_config = CmdLineConfig::parse_cmd_line(vec!["".to_string()].into_iter())
       .expect("Keine Kommandozeilen Argumente sind gültig");
-    assert_eq!(cmd_line_config.fork, true);
+    assert!(cmd_line_config.fork);
     assert_eq!(
       cmd_line_config.config_file,
       format!("/etc/{}.conf", env!("CARGO_PKG_NAME"))
@@ -334,6 +875,10 @@ mod tests {
     let msg = CmdLineConfig::parse_cmd_line(vec!["".to_string(), "-f".to_string()].into_iter())
       .expect_err("Keine Kommandozeilen Argumente sind gültig");
     assert_eq!(msg, "-f ohne Configfile");
+    //-r ohne File
+    let msg = CmdLineConfig::parse_cmd_line(vec!["".to_string(), "-r".to_string()].into_iter())
+      .expect_err("-r ohne Journalfile muss Err liefern");
+    assert_eq!(msg, "-r ohne Journalfile");
     //-n und -f gültig
     let cmd_line_config = CmdLineConfig::parse_cmd_line(
       vec![
@@ -345,7 +890,877 @@ mod tests {
       .into_iter(),
     )
     .expect("Keine Kommandozeilen Argumente sind gültig");
-    assert_eq!(cmd_line_config.fork, false);
+    assert!(!cmd_line_config.fork);
     assert_eq!(cmd_line_config.config_file, "configfilename");
+    assert_eq!(cmd_line_config.replay, None);
+    //-r gültig
+    let cmd_line_config = CmdLineConfig::parse_cmd_line(
+      vec!["".to_string(), "-r".to_string(), "journalfile".to_string()].into_iter(),
+    )
+    .expect("-r mit Journalfile ist gültig");
+    assert_eq!(cmd_line_config.replay, Some("journalfile".to_string()));
+    assert!(!cmd_line_config.selbsttest);
+    //-t gültig
+    let cmd_line_config =
+      CmdLineConfig::parse_cmd_line(vec!["".to_string(), "-t".to_string()].into_iter())
+        .expect("-t ist gültig");
+    assert!(cmd_line_config.selbsttest);
+    assert_eq!(cmd_line_config.send, None);
+    //--send gültig
+    let cmd_line_config = CmdLineConfig::parse_cmd_line(
+      vec![
+        "".to_string(),
+        "--send".to_string(),
+        "SET 1 POWER ON".to_string(),
+      ]
+      .into_iter(),
+    )
+    .expect("--send mit Kommando ist gültig");
+    assert_eq!(cmd_line_config.send, Some("SET 1 POWER ON".to_string()));
+    //--send ohne Kommando
+    let msg = CmdLineConfig::parse_cmd_line(vec!["".to_string(), "--send".to_string()].into_iter())
+      .expect_err("--send ohne Kommando muss Err liefern");
+    assert_eq!(msg, "--send ohne Kommando");
+  }
+  #[test]
+  fn pi_modell_test() {
+    use srcp_hardware::PiModell;
+    assert_eq!(
+      PiModell::from_model("Raspberry Pi 4 Model B Rev 1.4\0"),
+      PiModell::Pi4
+    );
+    assert_eq!(
+      PiModell::from_model("Raspberry Pi 400 Rev 1.0"),
+      PiModell::Pi4
+    );
+    assert_eq!(
+      PiModell::from_model("Raspberry Pi Zero W Rev 1.1"),
+      PiModell::Zero
+    );
+    assert_eq!(
+      PiModell::from_model("Raspberry Pi Zero 2 W Rev 1.0"),
+      PiModell::Zero2
+    );
+    assert_eq!(
+      PiModell::from_model("Raspberry Pi 3 Model B Plus Rev 1.3"),
+      PiModell::Pi2_3
+    );
+    assert_eq!(
+      PiModell::from_model("Raspberry Pi 5 Model B Rev 1.0"),
+      PiModell::Pi5
+    );
+    assert_eq!(
+      PiModell::from_model("Raspberry Pi Model B Rev 2"),
+      PiModell::Pi1
+    );
+    assert_eq!(PiModell::from_model("Generic x86"), PiModell::Unbekannt);
+    //Nur Raspberry PI 5 hat einen anderen GPIO Chip
+    assert_eq!(PiModell::Pi5.get_gpiochip(), "/dev/gpiochip4");
+    assert_eq!(PiModell::Zero.get_gpiochip(), "/dev/gpiochip0");
+    //S88 SPI Clock: Hardware Minimum bei 250MHz, genau erreichbar, aufgerundeter Teiler
+    let core_freq = 250_000_000;
+    assert_eq!(
+      srcp_hardware::get_spi_aux_clock_effektiv(core_freq, 20_000),
+      30_517
+    );
+    assert_eq!(
+      srcp_hardware::get_spi_aux_clock_effektiv(core_freq, 40_000),
+      40_000
+    );
+    assert_eq!(
+      srcp_hardware::get_spi_aux_clock_effektiv(core_freq, 35_000),
+      34_994
+    );
+  }
+  #[test]
+  fn ddl_selbsttest_test() {
+    let alle_spi_daten = || -> Vec<Vec<(u32, Vec<u8>)>> {
+      srcp_protocol_ddl_selbsttest::get_referenz_telegramme()
+        .iter()
+        .flat_map(|(_, tel)| (0..tel.daten.len()).map(|index| tel.get_spi_daten(index)))
+        .collect()
+    };
+    //Referenztelegramme sind immer gleich
+    let spi_daten = alle_spi_daten();
+    assert_eq!(spi_daten, alle_spi_daten());
+    //DCC Idle: Sync 1..1 0 11111111 0 00000000 0 11111111 1 und Abschluss 1
+    let mut dcc_idle: Vec<u8> = Vec::new();
+    let bit_0: &[u8] = &[0xFF, 0xFF, 0x00, 0x00];
+    let bit_1: &[u8] = &[0xFF, 0x00];
+    let bits = [
+      "1111111111111111",
+      "0",
+      "11111111",
+      "0",
+      "00000000",
+      "0",
+      "11111111",
+      "1",
+      "1",
+    ];
+    for bit in bits.concat().chars() {
+      dcc_idle.extend_from_slice(if bit == '0' { bit_0 } else { bit_1 });
+    }
+    assert_eq!(
+      spi_daten[0],
+      vec![(srcp_protocol_ddl_dcc::SPI_BAUDRATE_NMRA_2, dcc_idle)]
+    );
+  }
+  #[test]
+  fn ddl_tel_segment_test() {
+    use srcp_protocol_ddl::{spi_dauer, DdlTel};
+    //8000 Baud: 1 Byte pro ms
+    let mut tel = DdlTel::new(0, 8000, Duration::ZERO, false, 2, 1, false);
+    tel.add_pause(Duration::from_millis(2));
+    tel.payload_mut().extend_from_slice(&[1, 2]);
+    tel.add_repeat(3, Duration::from_micros(500));
+    assert_eq!(
+      tel.get_spi_daten(0),
+      vec![(8000, vec![0, 0, 1, 2, 0, 1, 2, 0, 1, 2])]
+    );
+    assert_eq!(tel.get_len(), 10);
+    //Pause endet mit Pegel 0
+    tel.add_pause(Duration::from_millis(1));
+    assert_eq!(tel.get_letztes_byte(), 0);
+    //Neues Telegramm mit anderer Baudrate, Pausen gelten mit Baudrate der Nutzdaten
+    tel.neues_tel(1);
+    tel.hz = 16000;
+    tel.add_pause(Duration::from_millis(1));
+    tel.payload_mut().push(0xFF);
+    assert_eq!(tel.get_letztes_byte(), 0xFF);
+    assert_eq!(tel.get_spi_daten(1), vec![(16000, vec![0, 0, 0xFF])]);
+    //Umrechnung Bytes in Pause und zurück ergibt wieder genau gleich viele Bytes
+    let mut tel = DdlTel::new(0, 76922, Duration::ZERO, false, 0, 1, false);
+    tel.add_pause(spi_dauer(42, 76922));
+    assert_eq!(tel.get_len(), 42);
+  }
+  #[test]
+  fn railcom_cutout_test() {
+    use srcp_protocol_ddl::{DdlProtokoll, DdlTel};
+    use srcp_protocol_ddl_dcc::{DccProtokoll, DccVersion, SPI_BAUDRATE_NMRA_2};
+    //Ohne RailCom endet jedes DCC Paket mit zusätzlichem 1 Bit (0xFF, 0x00)
+    let ohne = DccProtokoll::from(DccVersion::V1, None, false, false)
+      .get_idle_tel()
+      .unwrap()
+      .get_spi_daten(0);
+    //Mit RailCom statt dessen TCS Byte und Cutout, total 8 Bytes à 58us = 464us nach dem Schlussbit
+    let mit = DccProtokoll::from(DccVersion::V1, None, false, true)
+      .get_idle_tel()
+      .unwrap()
+      .get_spi_daten(0);
+    let mut erwartet = ohne[0].1[..ohne[0].1.len() - 2].to_vec();
+    erwartet.extend_from_slice(&[0xF0, 0, 0, 0, 0, 0, 0, 0]);
+    assert_eq!(mit, vec![(SPI_BAUDRATE_NMRA_2, erwartet)]);
+    //Cutout wird bei Repeat nach jedem Paket wiederholt, nicht aber eine folgende Pause
+    let mut tel = DdlTel::new(0, 8000, Duration::ZERO, false, 2, 1, false);
+    tel.payload_mut().extend_from_slice(&[1, 2]);
+    tel.add_cutout(0xF0, Duration::from_millis(1));
+    tel.add_repeat(2, Duration::ZERO);
+    tel.add_pause(Duration::from_millis(2));
+    assert_eq!(
+      tel.get_spi_daten(0),
+      vec![(8000, vec![1, 2, 0xF0, 0, 1, 2, 0xF0, 0, 0, 0])]
+    );
+  }
+  #[test]
+  fn gl_funktionen_begrenzung_test() {
+    use srcp_protocol_ddl::{
+      funktionen_maske, DdlProtokoll, GLDriveMode, GlInitParams, TelOptions,
+    };
+    use srcp_protocol_ddl_dcc::{DccProtokoll, DccVersion};
+    use srcp_protocol_ddl_mm::{MMProtokoll, MmVersion};
+    assert_eq!(funktionen_maske(0), 0);
+    assert_eq!(funktionen_maske(5), 0b11111);
+    assert_eq!(funktionen_maske(64), u64::MAX);
+    assert_eq!(funktionen_maske(100), u64::MAX);
+    //Alle SPI Daten von Basis und Zusatztelegrammen einer Lok mit "funk_anz" Funktionen
+    let spi_daten = |protokoll: &mut dyn DdlProtokoll, funk_anz: usize, funktionen: u64| {
+      let params = GlInitParams {
+        funk_anz,
+        ..Default::default()
+      };
+      protokoll.init_gl(1, &params);
+      let mut tel = protokoll.get_gl_new_tel(1, TelOptions::refresh(false));
+      protokoll.get_gl_basis_tel(1, GLDriveMode::Vorwaerts, 5, 14, funktionen, &mut tel);
+      protokoll.get_gl_zusatz_tel(1, true, funktionen, &mut tel);
+      (0..tel.daten.len())
+        .map(|index| tel.get_spi_daten(index))
+        .collect::<Vec<Vec<(u32, Vec<u8>)>>>()
+    };
+    let mut dcc = DccProtokoll::from(DccVersion::V1, None, false, false);
+    let mut mm = MMProtokoll::from(MmVersion::V2);
+    for protokoll in [&mut dcc as &mut dyn DdlProtokoll, &mut mm] {
+      //Funktionen über der mit INIT angegebenen Anzahl werden nicht gesendet
+      assert_eq!(
+        spi_daten(protokoll, 2, 0b11111),
+        spi_daten(protokoll, 2, 0b00011)
+      );
+      assert_ne!(
+        spi_daten(protokoll, 2, 0b00011),
+        spi_daten(protokoll, 2, 0b00001)
+      );
+      //INIT mit mehr Funktionen als das Protokoll kennt wird gekürzt
+      let anz_f = protokoll.get_gl_anz_f();
+      assert_eq!(
+        spi_daten(protokoll, anz_f + 10, u64::MAX),
+        spi_daten(protokoll, anz_f, u64::MAX)
+      );
+    }
+  }
+  #[test]
+  fn gl_fnkt_map_test() {
+    use srcp_devices_ddl_gl::GLInit;
+    use srcp_protocol_ddl::DdlProtokolle;
+    //Physische Funktionen einer DCC GL mit 29 Funktionen und INIT Parametern "param"
+    let physisch = |param: &str, fnkt: u64| {
+      let mut gl = GLInit::new(
+        DdlProtokolle::Dcc,
+        "1".to_string(),
+        128,
+        29,
+        None,
+        &vec![param.to_string()],
+        false,
+      );
+      gl.fnkt = fnkt;
+      gl.get_fnkt_physisch()
+    };
+    //Ohne Umleitung unverändert
+    assert_eq!(GLInit::eval_fnkt_map("1", 29), None);
+    assert_eq!(physisch("1", 0b10101), 0b10101);
+    assert_eq!(GLInit::eval_fnkt_map("MAP=3:3", 29), Some(vec![(3, 3)]));
+    assert_eq!(physisch("MAP=3:3", 0b01000), 0b01000);
+    //F2 -> F5, F5 des Clients bleibt unverändert
+    assert_eq!(
+      GLInit::eval_fnkt_map("MAP=2:5,1:0", 29),
+      Some(vec![(2, 5), (1, 0)])
+    );
+    assert_eq!(physisch("MAP=2:5", 0b00100), 0b100000);
+    assert_eq!(physisch("MAP=2:5", 0b100001), 0b100001);
+    assert_eq!(physisch("MAP=2:5,1:0", 0b00110), 0b100001);
+    //Funktionen ausserhalb der Anzahl Funktionen oder ungültige Einträge: ganze Umleitung ungültig
+    assert_eq!(GLInit::eval_fnkt_map("MAP=2:29", 29), None);
+    assert_eq!(GLInit::eval_fnkt_map("MAP=29:2", 29), None);
+    assert_eq!(GLInit::eval_fnkt_map("MAP=2:5,3", 29), None);
+    assert_eq!(GLInit::eval_fnkt_map("MAP=2:x", 29), None);
+    assert_eq!(physisch("MAP=2:5,40:1", 0b00100), 0b00100);
+  }
+  #[test]
+  fn mm1_richtungswechsel_test() {
+    use srcp_protocol_ddl::{DdlProtokoll, GLDriveMode, GlInitParams, TelOptions};
+    use srcp_protocol_ddl_mm::{MMProtokoll, MmVersion};
+    use std::time::Duration;
+    //Telegramm bei Fahrtrichtung "drive_mode" nach vorherigem Vorwärts
+    let tel = |mm: &mut MMProtokoll, drive_mode: GLDriveMode| {
+      let params = GlInitParams {
+        funk_anz: 1,
+        ..Default::default()
+      };
+      mm.init_gl(1, &params);
+      let mut tel = mm.get_gl_new_tel(1, TelOptions::neu(false));
+      mm.get_gl_basis_tel(1, GLDriveMode::Vorwaerts, 5, 14, 0, &mut tel);
+      let mut tel = mm.get_gl_new_tel(1, TelOptions::neu(false));
+      mm.get_gl_basis_tel(1, drive_mode, 5, 14, 0, &mut tel);
+      mm.get_gl_zusatz_tel(1, false, 0, &mut tel);
+      tel
+    };
+    //Ohne Pause nur ein Telegramm mit Richtungsimpuls
+    let mut mm = MMProtokoll::from(MmVersion::V1);
+    assert_eq!(tel(&mut mm, GLDriveMode::Rueckwaerts).daten.len(), 1);
+    //Mit Pause: Halt, Richtungsimpulse, neue Fahrstufe
+    mm.set_richtungswechsel(Duration::from_millis(100), 3);
+    let tel_wechsel = tel(&mut mm, GLDriveMode::Rueckwaerts);
+    assert_eq!(tel_wechsel.daten.len(), 3);
+    assert_eq!(tel_wechsel.delay, Duration::from_millis(100));
+    //3 Richtungsimpulse, jeder als Doppelpaket gleich lang wie der Halt
+    assert_eq!(
+      tel_wechsel.get_spi_daten(1)[0].1.len(),
+      3 * tel_wechsel.get_spi_daten(0)[0].1.len()
+    );
+    //Ohne Richtungswechsel keine Sequenz
+    assert_eq!(tel(&mut mm, GLDriveMode::Vorwaerts).daten.len(), 1);
+  }
+  #[test]
+  fn mm5_verzoegerung_test() {
+    use spidev::Spidev;
+    use srcp_devices_ddl::{DdlAusgabe, SRCPDeviceDDL};
+    use srcp_devices_ddl_gl::{DdlGL, DdlGLKonfig, GLUebernahme};
+    use srcp_protocol_ddl::{DdlProtokolle, HashMapVersion};
+    use srcp_protocol_ddl_mm::{MMProtokoll, MmVersion};
+    use srcp_server_types::ThreadSender;
+    use std::{fs::File, time::Instant};
+    //Ausgabe ohne SPI Hardware, die Transfers schlagen fehl und die Telegramme werden verworfen
+    let ausgabe = DdlAusgabe::new(Spidev::new(
+      File::options().write(true).open("/dev/null").unwrap(),
+    ));
+    let mut mm: HashMapVersion = HashMap::new();
+    mm.insert("5", Rc::new(RefCell::new(MMProtokoll::from(MmVersion::V5))));
+    let (tx, _rx) = mpsc::channel();
+    let mut gl = DdlGL::new(
+      1,
+      ThreadSender::new(tx, "Test Antworten"),
+      &ausgabe,
+      HashMap::from([(DdlProtokolle::Maerklin, mm)]),
+      DdlGLKonfig {
+        trigger_port: None,
+        trigger_adr: None,
+        momentary: None,
+        speedcurve: HashMap::new(),
+        speedmax: HashMap::new(),
+        uebernahme: GLUebernahme::Keine,
+        gl_max: None,
+      },
+    );
+    let mut ausfuehren = |cmd: &str| {
+      let cmd_msg = SRCPMessage::parse(1, cmd).unwrap();
+      assert!(gl.validate_cmd(&cmd_msg));
+      gl.execute_cmd(&cmd_msg, true);
+    };
+    //MM ist erst ab 2 GL's nicht mehr Idle
+    ausfuehren("INIT 1 GL 5 M 5 28 5");
+    ausfuehren("INIT 1 GL 6 M 5 28 5");
+    //Weniger als MIN_ANZ_GL_NO_DELAY GL's: die 50ms zwischen den beiden MM5 Telegrammen werden nicht
+    //blockierend gewartet, sondern das 2. Telegramm über den Buffer geplant
+    let start = Instant::now();
+    ausfuehren("SET 1 GL 5 1 11 28 0 0 0 0 0");
+    ausfuehren("SET 1 GL 6 1 12 28 0 0 0 0 0");
+    let ende = Instant::now();
+    assert!(ende - start < Duration::from_millis(50));
+    let naechster_zeitpunkt = gl.get_naechster_zeitpunkt(true).unwrap();
+    assert!(naechster_zeitpunkt >= start + Duration::from_millis(50));
+    assert!(naechster_zeitpunkt <= ende + Duration::from_millis(50));
+  }
+  #[test]
+  fn gl_last_test() {
+    use spidev::Spidev;
+    use srcp_devices_ddl::{DdlAusgabe, SRCPDeviceDDL};
+    use srcp_devices_ddl_gl::{DdlGL, DdlGLKonfig, GLUebernahme};
+    use srcp_protocol_ddl::{DdlProtokolle, HashMapVersion};
+    use srcp_protocol_ddl_dcc::{DccProtokoll, DccVersion};
+    use srcp_protocol_ddl_mm::{MMProtokoll, MmVersion};
+    use srcp_server_types::ThreadSender;
+    use std::fs::File;
+    //Ausgabe ohne SPI Hardware, die Transfers schlagen fehl und die Telegramme werden verworfen
+    let ausgabe = DdlAusgabe::new(Spidev::new(
+      File::options().write(true).open("/dev/null").unwrap(),
+    ));
+    let mut mm: HashMapVersion = HashMap::new();
+    mm.insert("2", Rc::new(RefCell::new(MMProtokoll::from(MmVersion::V2))));
+    mm.insert("5", Rc::new(RefCell::new(MMProtokoll::from(MmVersion::V5))));
+    let mut dcc: HashMapVersion = HashMap::new();
+    dcc.insert("1", Rc::new(RefCell::new(DccProtokoll::from(DccVersion::V1, None, false, false))));
+    dcc.insert("2", Rc::new(RefCell::new(DccProtokoll::from(DccVersion::V2, None, false, false))));
+    //Jede 3. GL mit Oszi Trigger, ohne Triggerport wird nur das Trigger Flag der Telegramme gesetzt
+    let trigger_adr: Vec<String> = (1..=60).step_by(3).map(|adr| adr.to_string()).collect();
+    let (tx, _rx) = mpsc::channel();
+    let mut gl = DdlGL::new(
+      1,
+      ThreadSender::new(tx, "Test Antworten"),
+      &ausgabe,
+      HashMap::from([(DdlProtokolle::Maerklin, mm), (DdlProtokolle::Dcc, dcc)]),
+      DdlGLKonfig {
+        trigger_port: None,
+        trigger_adr: Some(trigger_adr.join(",")),
+        momentary: None,
+        speedcurve: HashMap::new(),
+        speedmax: HashMap::new(),
+        uebernahme: GLUebernahme::Keine,
+        gl_max: None,
+      },
+    );
+    let ausfuehren = |gl: &mut DdlGL, cmd: String| {
+      let cmd_msg = SRCPMessage::parse(1, &cmd).unwrap();
+      assert!(gl.validate_cmd(&cmd_msg), "{}", cmd);
+      gl.execute_cmd(&cmd_msg, true);
+    };
+    //Mehr als MIN_ANZ_GL_NO_DELAY GL's, MM mit und ohne 2. Telegramm, DCC kurze und lange Adressen
+    for adr in 1..=60 {
+      let init = match adr % 4 {
+        0 => "M 2 14 5",
+        1 => "M 5 28 5",
+        2 => "N 1 28 5",
+        _ => "N 2 128 29",
+      };
+      ausfuehren(&mut gl, format!("INIT 1 GL {} {}", adr, init));
+    }
+    //Kommandos, Refresh und zyklische Ausführung abwechselnd, die Protokolle dürfen dabei nie
+    //doppelt ausgeliehen werden (BorrowMutError -> panic)
+    for durchgang in 0..4 {
+      for adr in 1..=60 {
+        let funktionen = vec!["1"; if adr % 4 == 3 { 29 } else { 5 }].join(" ");
+        ausfuehren(
+          &mut gl,
+          format!("SET 1 GL {} {} {} 28 {}", adr, durchgang % 2, adr % 28, funktionen),
+        );
+        gl.execute(true);
+        gl.send_refresh();
+      }
+      for _ in 0..60 {
+        gl.send_refresh();
+        gl.execute(true);
+      }
+    }
+    assert!(gl.get_naechster_zeitpunkt(true).is_some());
+  }
+  #[test]
+  fn mfx_lok_cache_test() {
+    use srcp_mfx_lok_cache::{MfxLokCache, MfxLokDaten};
+    let path = std::env::temp_dir().join(format!("srcpd_mfxlok_test_{}", std::process::id()));
+    let path = path.to_str().unwrap().to_string();
+    let _ = std::fs::remove_file(&path);
+    //Ohne File kein Cache
+    let mut cache = MfxLokCache::new(None);
+    cache.set(
+      0x7F000001,
+      MfxLokDaten {
+        param: vec!["\"BR 185\"".to_string()],
+        anz_f: 8,
+      },
+    );
+    assert!(cache.get(0x7F000001).is_none());
+    //Gespeicherte Lokparameter sind nach neuem Laden wieder vorhanden
+    let mut cache = MfxLokCache::new(Some(path.clone()));
+    cache.set(
+      0x7F000001,
+      MfxLokDaten {
+        param: vec!["\"BR 185\"".to_string(), "1234".to_string()],
+        anz_f: 16,
+      },
+    );
+    let mut cache = MfxLokCache::new(Some(path.clone()));
+    let lok_daten = cache.get(0x7F000001).expect("Lok muss im Cache sein");
+    assert_eq!(lok_daten.param, vec!["\"BR 185\"", "1234"]);
+    assert_eq!(lok_daten.anz_f, 16);
+    assert!(cache.get(0x7F000002).is_none());
+    //Tab, Zeilenumbruch und Backslash im Loknamen zerstören das File nicht
+    let name = "\"BR\t185\n\\2\"";
+    cache.set(
+      0x7F000002,
+      MfxLokDaten {
+        param: vec![name.to_string(), "1234".to_string()],
+        anz_f: 8,
+      },
+    );
+    let mut cache = MfxLokCache::new(Some(path.clone()));
+    assert_eq!(cache.get(0x7F000002).unwrap().param, vec![name, "1234"]);
+    assert_eq!(cache.get(0x7F000001).unwrap().anz_f, 16);
+    //Entfernen wird ebenfalls gespeichert
+    cache.entfernen(0x7F000001);
+    let cache = MfxLokCache::new(Some(path.clone()));
+    assert!(cache.get(0x7F000001).is_none());
+    let _ = std::fs::remove_file(&path);
+  }
+  #[test]
+  fn mfx_text_ca_test() {
+    use srcp_devices_ddl_sm::{DdlSM, DdlSMKonfig};
+    use srcp_mfx_rds::{MfxRdsFeedbackThread, SM_TYPE_LOKNAME};
+    use srcp_protocol_ddl::{SmReadWrite, SmReadWriteType};
+    use srcp_server_types::ThreadSender;
+    //Schreiben: mit Spaces auf die CA Länge aufgefüllt
+    assert_eq!(
+      MfxRdsFeedbackThread::text_to_ca("BR 185", 8),
+      Some(b"BR 185  ".to_vec())
+    );
+    assert_eq!(
+      MfxRdsFeedbackThread::text_to_ca("BR 185", 6),
+      Some(b"BR 185".to_vec())
+    );
+    //Zu lang oder nicht ASCII wird abgelehnt
+    assert_eq!(MfxRdsFeedbackThread::text_to_ca("BR 185 001", 8), None);
+    assert_eq!(MfxRdsFeedbackThread::text_to_ca("Gläserzug", 16), None);
+    //Lesen: Auffüllung mit 0 oder Spaces entfernen
+    let text = |bytes: &[u8]| MfxRdsFeedbackThread::ca_to_text(bytes.to_vec());
+    assert_eq!(text(b"BR 185\0\0"), Some("BR 185".to_string()));
+    assert_eq!(text(b"BR 185  "), Some("BR 185".to_string()));
+    assert_eq!(text(b"BR 185 \0 \0"), Some("BR 185".to_string()));
+    assert_eq!(text(b"\0\0\0\0"), Some(String::new()));
+    assert_eq!(text(&[b'B', b'R', 0xE4]), None);
+    //Gelesener Text als SRCP Antwort in Anführungszeichen
+    let (tx, rx) = mpsc::channel();
+    let sm = DdlSM::new(
+      1,
+      ThreadSender::new(tx, "Test Antworten"),
+      HashMap::new(),
+      &None,
+      &None,
+      DdlSMKonfig {
+        trigger_port: None,
+        trigger_adr: None,
+        prog_gleis_invers: false,
+        write_verify: false,
+      },
+    );
+    sm.send_antwort(SmReadWrite {
+      adr: 5,
+      prog_gleis: false,
+      sm_type: SM_TYPE_LOKNAME.to_string(),
+      para: vec![],
+      val: SmReadWriteType::ResultText("BR 185".to_string()),
+      session_id: 1,
+      auftrag_id: 0,
+      trigger: false,
+      write_verify: false,
+    });
+    let antwort = rx.try_recv().unwrap();
+    assert!(matches!(antwort.message_id, SRCPMessageID::Ok { ok_code } if ok_code == "200"));
+    assert_eq!(antwort.parameter, vec!["5", "LOKNAME", "\"BR 185\""]);
+  }
+  #[test]
+  fn kurzschluss_sperre_test() {
+    use srcp_devices_ddl_power::KurzschlussSperre;
+    use std::time::{Duration, Instant};
+    //Ohne shortcut_lockout keine Sperre
+    let mut sperre = KurzschlussSperre::new(None);
+    assert!(!sperre.sperren(Instant::now()));
+    assert!(!sperre.is_gesperrt());
+    //Kurzschluss -> gesperrt bis Sperrdauer ab Abschaltung abgelaufen ist
+    let mut sperre = KurzschlussSperre::new(Some(Duration::from_millis(2000)));
+    let abschaltung = Instant::now();
+    assert!(sperre.sperren(abschaltung));
+    assert!(sperre.is_gesperrt());
+    assert!(!sperre.is_abgelaufen(abschaltung + Duration::from_millis(1999)));
+    assert!(sperre.is_abgelaufen(abschaltung + Duration::from_millis(2000)));
+    //Wieder freigegeben
+    assert!(sperre.aufheben());
+    assert!(!sperre.is_gesperrt());
+    assert!(!sperre.is_abgelaufen(abschaltung + Duration::from_millis(3000)));
+    assert!(!sperre.aufheben());
+    //Sperrdauer 0: nur mit SET POWER RESET
+    let mut sperre = KurzschlussSperre::new(Some(Duration::ZERO));
+    assert!(sperre.sperren(abschaltung));
+    assert!(!sperre.is_abgelaufen(abschaltung + Duration::from_secs(3600)));
+    assert!(sperre.aufheben());
+  }
+  #[test]
+  fn ga_puls_dauer_test() {
+    use srcp_devices_ddl_ga::GAPulsKonfig;
+    let konfig = |werte: &[(&str, &str)]| {
+      let config: HashMap<String, Option<String>> = werte
+        .iter()
+        .map(|(name, wert)| (name.to_string(), Some(wert.to_string())))
+        .collect();
+      GAPulsKonfig::from_config(&config).unwrap()
+    };
+    //Ohne Konfiguration keine Begrenzung
+    let puls = konfig(&[]);
+    assert_eq!(puls.get_puls_dauer(1, 0, 0), 0);
+    assert_eq!(puls.get_puls_dauer(1, 0, -1), -1);
+    assert_eq!(puls.get_puls_dauer(1, 0, 20000), 20000);
+    //Nur Spulenschutz: dauerhaft wird auf ga_coil_max begrenzt
+    let puls = konfig(&[("ga_coil_max", "5000")]);
+    assert_eq!(puls.get_puls_dauer(1, 0, 0), 5000);
+    assert_eq!(puls.get_puls_dauer(1, 0, -1), 5000);
+    assert_eq!(puls.get_puls_dauer(1, 0, 300), 300);
+    assert_eq!(puls.get_puls_dauer(1, 0, 20000), 5000);
+    //Mit Grenzen und Default: dauerhaft wird min(default, ga_coil_max)
+    let puls = konfig(&[
+      ("ga_pulse", "50 1000 250"),
+      ("ga_pulse_12", "100 8000 6000"),
+      ("ga_coil_max", "5000"),
+    ]);
+    assert_eq!(puls.get_puls_dauer(1, 0, 0), 250);
+    assert_eq!(puls.get_puls_dauer(1, 0, -1), 250);
+    assert_eq!(puls.get_puls_dauer(1, 0, 300), 300);
+    assert_eq!(puls.get_puls_dauer(1, 0, 20), 50);
+    assert_eq!(puls.get_puls_dauer(1, 0, 3000), 1000);
+    assert_eq!(puls.get_puls_dauer(12, 0, 0), 5000);
+    assert_eq!(puls.get_puls_dauer(12, 0, -1), 5000);
+    assert_eq!(puls.get_puls_dauer(12, 0, 7000), 5000);
+  }
+  #[test]
+  fn fb_statistik_test() {
+    use srcp_server_s88::FbStatistik;
+    let mut statistik = FbStatistik::new();
+    //Keine Abweichung, keine Änderung
+    statistik.update(false, false);
+    assert_eq!((statistik.aenderungen, statistik.glitches), (0, 0));
+    assert!(statistik.letzte_aenderung.is_none());
+    //Abweichung, die ohne gefilterte Änderung wieder verschwindet, ist ein Glitch
+    statistik.update(false, true);
+    statistik.update(false, true);
+    assert_eq!(statistik.glitches, 0);
+    statistik.update(false, false);
+    assert_eq!((statistik.aenderungen, statistik.glitches), (0, 1));
+    assert_eq!(statistik.glitches_intervall, 1);
+    //Abweichung, die zu einer gefilterten Änderung führt, ist kein Glitch
+    statistik.update(false, true);
+    statistik.update(true, false);
+    statistik.update(false, false);
+    assert_eq!((statistik.aenderungen, statistik.glitches), (1, 1));
+    assert!(statistik.letzte_aenderung.is_some());
+  }
+  #[test]
+  fn parse_trigger_cmd_test() {
+    use srcp_server_s88::S88;
+    let para = |para: &[&str]| para.iter().map(|p| p.to_string()).collect::<Vec<String>>();
+    //FB's ab 1 auf SRCP, ab 0 intern
+    assert_eq!(
+      S88::parse_trigger_cmd(&para(&["12", "1,16"]), 16, Some(12)),
+      Ok((12, vec![0, 15]))
+    );
+    //Ohne FB's wird der Trigger ausgeschaltet
+    assert_eq!(
+      S88::parse_trigger_cmd(&para(&["12"]), 16, Some(12)),
+      Ok((12, vec![]))
+    );
+    //Nur der konfigurierte GPIO ist erlaubt
+    assert!(S88::parse_trigger_cmd(&para(&["13", "1"]), 16, Some(12)).is_err());
+    assert!(S88::parse_trigger_cmd(&para(&["12", "1"]), 16, None).is_err());
+    //Ungültige Parameter
+    assert!(S88::parse_trigger_cmd(&para(&[]), 16, Some(12)).is_err());
+    assert!(S88::parse_trigger_cmd(&para(&["x"]), 16, Some(12)).is_err());
+    assert!(S88::parse_trigger_cmd(&para(&["12", "0"]), 16, Some(12)).is_err());
+    assert!(S88::parse_trigger_cmd(&para(&["12", "1,17"]), 16, Some(12)).is_err());
+  }
+  #[test]
+  fn mfx_ping_test() {
+    use srcp_mfx_rds::MfxRdsKonfig;
+    use srcp_protocol_ddl::{DdlProtokoll, GLAnwesenheit};
+    use srcp_protocol_ddl_mfx::{MfxProtokoll, MfxVersion};
+    use std::time::Duration;
+    let path = std::env::temp_dir().join(format!("srcpd_mfxreg_test_{}", std::process::id()));
+    let mut mfx = MfxProtokoll::from(
+      MfxVersion::V0,
+      0x12345678,
+      path.to_str().unwrap().to_string(),
+      None,
+      false,
+      MfxRdsKonfig::default(),
+    );
+    //Ohne Anwesenheitsprüfung immer Dekodersuche
+    mfx.eval_ping(3, true);
+    mfx.eval_ping(5, true);
+    assert_eq!(mfx.get_naechste_ping_adr(), None);
+    assert_eq!(mfx.get_naechste_ping_adr(), None);
+    //Abwechselnd Ping der Reihe nach und Dekodersuche
+    mfx.set_anwesenheit(Some(Duration::ZERO), false);
+    assert_eq!(mfx.get_naechste_ping_adr(), Some(3));
+    assert_eq!(mfx.get_naechste_ping_adr(), None);
+    assert_eq!(mfx.get_naechste_ping_adr(), Some(5));
+    assert_eq!(mfx.get_naechste_ping_adr(), None);
+    assert_eq!(mfx.get_naechste_ping_adr(), Some(3));
+    //Keine Antwort nach Timeout wird einmal gemeldet
+    mfx.eval_ping(5, false);
+    assert!(matches!(
+      mfx.get_gl_anwesenheit()[..],
+      [GLAnwesenheit::Abwesend(5)]
+    ));
+    mfx.eval_ping(5, false);
+    assert!(mfx.get_gl_anwesenheit().is_empty());
+    //Antwort meldet den Dekoder wieder vorhanden
+    mfx.eval_ping(5, true);
+    assert!(matches!(
+      mfx.get_gl_anwesenheit()[..],
+      [GLAnwesenheit::Vorhanden(5)]
+    ));
+    //Mit Freigabe wird die SID nicht mehr abgefragt
+    mfx.set_anwesenheit(Some(Duration::ZERO), true);
+    mfx.eval_ping(3, false);
+    assert!(matches!(
+      mfx.get_gl_anwesenheit()[..],
+      [GLAnwesenheit::Freigegeben(3)]
+    ));
+    assert_eq!(mfx.get_naechste_ping_adr(), None);
+    assert_eq!(mfx.get_naechste_ping_adr(), Some(5));
+    assert_eq!(mfx.get_naechste_ping_adr(), None);
+    assert_eq!(mfx.get_naechste_ping_adr(), Some(5));
+  }
+  #[test]
+  fn ga_rueckmeldung_test() {
+    use srcp_ga_rueckmeldung::GaRueckmeldungen;
+    let config: HashMap<String, Option<String>> = HashMap::from([
+      ("timeout".to_string(), Some("1".to_string())),
+      ("ga_1".to_string(), Some("5:1 0 1:20".to_string())),
+    ]);
+    let mut ga_rueckmeldungen = GaRueckmeldungen::from_config(Some(&config)).unwrap();
+    let info = |device: SRCPMessageDevice, parameter: &[&str]| {
+      let bus = match device {
+        SRCPMessageDevice::GA => 5,
+        _ => 1,
+      };
+      SRCPMessage::new(
+        None,
+        bus,
+        SRCPMessageID::Info {
+          info_code: "100".to_string(),
+        },
+        device,
+        parameter.iter().map(|p| p.to_string()).collect(),
+      )
+    };
+    let texte = |alle_msg: Vec<SRCPMessage>| -> Vec<String> {
+      alle_msg
+        .iter()
+        .map(|m| m.to_string().trim_end().to_string())
+        .collect()
+    };
+    //Nicht verknüpfter Port wird sofort weitergeleitet
+    assert_eq!(
+      texte(ga_rueckmeldungen.eval_info(info(SRCPMessageDevice::GA, &["1", "1", "1"]))),
+      vec!["100 INFO 5 GA 1 1 1"]
+    );
+    //Verknüpfter Port wird bis zur Rückmeldung zurückgehalten, auch das Ausschalten
+    assert!(ga_rueckmeldungen
+      .eval_info(info(SRCPMessageDevice::GA, &["1", "0", "1"]))
+      .is_empty());
+    assert!(ga_rueckmeldungen
+      .eval_info(info(SRCPMessageDevice::GA, &["1", "0", "0"]))
+      .is_empty());
+    assert!(ga_rueckmeldungen.get_naechste_frist().is_some());
+    assert_eq!(
+      texte(ga_rueckmeldungen.eval_info(info(SRCPMessageDevice::FB, &["20", "1"]))),
+      vec![
+        "100 INFO 1 FB 20 1",
+        "100 INFO 5 GA 1 0 1",
+        "100 INFO 5 GA 1 0 0"
+      ]
+    );
+    assert!(ga_rueckmeldungen.get_naechste_frist().is_none());
+    //FB bereits aktiv: sofort weiterleiten
+    assert_eq!(
+      texte(ga_rueckmeldungen.eval_info(info(SRCPMessageDevice::GA, &["1", "0", "1"]))),
+      vec!["100 INFO 5 GA 1 0 1"]
+    );
+    //Ohne Rückmeldung innerhalb Timeout: GM Info
+    ga_rueckmeldungen.eval_info(info(SRCPMessageDevice::FB, &["20", "0"]));
+    assert!(ga_rueckmeldungen
+      .eval_info(info(SRCPMessageDevice::GA, &["1", "0", "1"]))
+      .is_empty());
+    thread::sleep(Duration::from_millis(5));
+    assert_eq!(
+      texte(ga_rueckmeldungen.eval_timeout()),
+      vec!["100 INFO 5 GM 0 0 SRCP_GA_FEEDBACK_FAILED \"GA 1 0 FB 1:20\""]
+    );
+    assert!(ga_rueckmeldungen.get_naechste_frist().is_none());
+    //Ungültige Konfiguration
+    let config: HashMap<String, Option<String>> =
+      HashMap::from([("ga_1".to_string(), Some("5:1 x 1:20".to_string()))]);
+    assert!(GaRueckmeldungen::from_config(Some(&config)).is_err());
+  }
+  #[test]
+  fn parse_srcp_message_test() {
+    //Gültiges Kommando, Teile in Anführungszeichen bleiben zusammen
+    let msg = SRCPMessage::parse(7, "INIT 1 GL 3 X 0 126 16 12345 \"BR 89\"")
+      .expect("Gültiges Kommando muss SRCPMessage liefern");
+    assert_eq!(msg.session_id, Some(7));
+    assert_eq!(msg.bus, 1);
+    assert_eq!(msg.device, SRCPMessageDevice::GL);
+    assert!(matches!(
+      msg.message_id,
+      SRCPMessageID::Command {
+        msg_type: SRCPMessageType::INIT
+      }
+    ));
+    assert_eq!(msg.get_adr(), Some(3));
+    assert_eq!(msg.parameter.last().map(String::as_str), Some("BR 89"));
+    //Zu kurz
+    let err = SRCPMessage::parse(7, "GET 1").expect_err("Zu kurzes Kommando muss Err liefern");
+    assert_eq!(err.0, "419");
+    //Unbekanntes Device
+    let err = SRCPMessage::parse(7, "GET 1 XY 1").expect_err("Unbekanntes Device muss Err liefern");
+    assert_eq!(err.0, "421");
+  }
+  #[test]
+  fn dcc_prog_abbruch_test() {
+    use srcp_dcc_prog::{DccCvTelType, DccProgThread, ProgAck, DCC_SM_TYPE_CVBIT};
+    use srcp_protocol_ddl::{SmReadWrite, SmReadWriteType};
+    use srcp_server_types::ThreadSender;
+    use std::sync::{
+      atomic::{AtomicBool, Ordering},
+      Arc,
+    };
+    use std::time::Duration;
+    /// Dekoder Quittierung durch Test gesteuert
+    struct TestProgAck(Arc<AtomicBool>);
+    impl ProgAck for TestProgAck {
+      fn start(&mut self) {
+        self.0.store(false, Ordering::Relaxed);
+      }
+      fn is_ack(&mut self) -> bool {
+        self.0.load(Ordering::Relaxed)
+      }
+    }
+    let ack = Arc::new(AtomicBool::new(false));
+    let abbruch = Arc::new(AtomicBool::new(false));
+    let (tx_auftrag, rx_auftrag) = mpsc::channel();
+    let (tx_antwort, rx_antwort) = mpsc::channel();
+    let (tx_tel, rx_tel) = mpsc::channel();
+    let mut prog_thread = DccProgThread::new(
+      rx_auftrag,
+      ThreadSender::new(tx_antwort, "Test Antworten"),
+      ThreadSender::new(tx_tel, "Test Telegramme"),
+      Box::new(TestProgAck(ack.clone())),
+      abbruch.clone(),
+    );
+    thread::spawn(move || prog_thread.execute());
+    //Dekoder mit allen CV Bits 1: quittiert nur Verify mit 1
+    thread::spawn(move || {
+      for tel in rx_tel {
+        if let DccCvTelType::VerifyBit(val, _) = tel.dcc_cv_type {
+          ack.store(val, Ordering::Relaxed);
+        }
+      }
+    });
+    let lesen = |auftrag_id: u32| SmReadWrite {
+      adr: 0,
+      prog_gleis: true,
+      sm_type: DCC_SM_TYPE_CVBIT.to_string(),
+      para: vec![1, 0],
+      val: SmReadWriteType::Read,
+      session_id: 1,
+      auftrag_id,
+      trigger: false,
+      write_verify: false,
+    };
+    tx_auftrag.send(lesen(1)).unwrap();
+    let antwort = rx_antwort
+      .recv_timeout(Duration::from_secs(2))
+      .expect("Prog Thread muss antworten");
+    assert!(matches!(antwort.val, SmReadWriteType::ResultOk(1)));
+    //TERM SM während der Prog Thread ohne wartende Aufträge auf den nächsten Auftrag wartet
+    thread::sleep(Duration::from_millis(10));
+    abbruch.store(true, Ordering::Relaxed);
+    thread::sleep(Duration::from_millis(10));
+    //Nachfolgendes Lesen muss normal ausgeführt werden
+    tx_auftrag.send(lesen(2)).unwrap();
+    let antwort = rx_antwort
+      .recv_timeout(Duration::from_secs(2))
+      .expect("Prog Thread muss antworten");
+    assert_eq!(antwort.auftrag_id, 2);
+    assert!(matches!(antwort.val, SmReadWriteType::ResultOk(1)));
+  }
+  #[test]
+  fn thread_sender_test() {
+    use srcp_server_types::ThreadSender;
+    let (tx, rx) = mpsc::channel();
+    let sender = ThreadSender::new(tx, "Test");
+    let kopie = sender.clone();
+    assert!(sender.send(1));
+    assert!(kopie.send(2));
+    assert_eq!(rx.try_iter().collect::<Vec<u32>>(), vec![1, 2]);
+    assert!(!sender.is_gestoert());
+    assert_eq!(sender.take_stoerung(), None);
+    //Empfänger nicht mehr vorhanden: gestört, auch für alle Kopien
+    drop(rx);
+    assert!(!sender.send(3));
+    assert!(kopie.is_gestoert());
+    assert!(!kopie.send(4));
+    //Störung wird nur einmal gemeldet
+    assert_eq!(kopie.take_stoerung(), Some("Test gestört".to_string()));
+    assert_eq!(sender.take_stoerung(), None);
+    //Als gestört markieren ohne zu senden, z.B. wenn der Antwortkanal geschlossen wurde
+    let (tx, _rx) = mpsc::channel::<u32>();
+    let sender = ThreadSender::new(tx, "Test");
+    sender.set_gestoert();
+    assert!(sender.is_gestoert());
+    assert_eq!(sender.take_stoerung(), Some("Test gestört".to_string()));
+  }
+  #[test]
+  fn websocket_handshake_test() {
+    //Beispiel aus RFC 6455, Abschnitt 1.3
+    assert_eq!(
+      srcp_http::websocket_accept("dGhlIHNhbXBsZSBub25jZQ=="),
+      "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+    );
   }
 }
@@ -4,41 +4,195 @@
 //!   - INIT
 //!   - SET
 //!
+//! - UDP Discovery (optional)
+//! - Journal aller akzeptierten SET Kommandos (optional, siehe srcp_journal)
+//! - HTTP/WebSocket Bridge (optional, siehe srcp_http)
+//! - MQTT Anbindung (optional, siehe srcp_mqtt)
+//! - Vordefinierte GL's und GA's (optional, siehe srcp_roster)
+//! - Namen anstelle von GL Adressen (siehe srcp_alias)
+//! - Zugriffskontrolle pro Client IP Adresse für Command Mode Clients (optional, siehe srcp_acl)
+//! - Während dem Start werden Kommandos verzögert, bis der Server des Busses bereit ist
+//! - Ist ein Server ausgefallen (panic), dann werden Kommandos an seine Busse sofort mit
+//!   "499 ERROR bus failed" beantwortet und alle Clients mit "INFO <bus> GM 0 0 SRCP_BUS_FAILED" informiert
+//! - GA Rückmeldung über FB Kontakte (optional, siehe srcp_ga_rueckmeldung)
+//! - Kompaktes INFO GL Format (optional, Handshake "SET INFOFORMAT GL COMPACT" nach
+//!   "SET PROTOCOL SRCP 0.8.4"): Funktionen als ein Hex Wert "0x<hex>", Bit 0 ist f0
+//! - Kommandos nur validieren ohne Ausführung (srcpd_rust Erweiterung): "CHECK <cmd> <bus> <device> ...",
+//!   z.B. für die Formularprüfung in Stellpulten. Antwort "200 OK <bus> <device>" oder der Fehler, den die
+//!   Ausführung geliefert hätte. Nur DDL Busse validieren, alle anderen: "423 ERROR unsupported operation"
+//! - Abfrage aller verbundenen Sessions (srcpd_rust Erweiterung): "GET 0 SERVER SESSIONS"
+//!   INFO 0 SERVER SESSIONS <anzahl>
+//!   INFO 0 SERVER SESSION <session_id> <COMMAND|INFO> <adresse> <verbunden> <letzte aktivität>
+//!   Zeiten in Sekunden seit 1.1.1970 UTC, Adresse "intern" für interne Clients (z.B. HTTP Bridge)
+//! - Begrenzte Queues: pro Client max. "info_queue" wartende Messages, bei einem blockierten Client wird
+//!   jeweils die älteste Message verworfen (siehe srcp_info_queue). Pro SRCP Server max. CMD_QUEUE_MAX
+//!   wartende Kommandos, weitere Kommandos werden mit "413 ERROR temporarily prohibited" abgelehnt. Anzahl
+//!   verworfene / abgelehnte siehe "get_queue_statistik". Die Queue von den SRCP Servern zum Dispatcher ist
+//!   auf SERVER_INFO_QUEUE_MAX begrenzt, ist sie voll wartet der Server. Da der Dispatcher selbst nie
+//!   blockiert, kommt das nur bei Überlast kurzzeitig vor.
+//! - Automatisches Power Off (optional, "power_off_idle" in Sekunden): wird die letzte Command Mode
+//!   Verbindung getrennt und innerhalb dieser Zeit keine neue aufgebaut, dann wird an alle Busse
+//!   "SET <bus> POWER OFF" gesendet. Interne Clients (z.B. HTTP Bridge) zählen nicht.
+//!
 //! INI File:
 //! [srcp]
 //! port = xxxxxx
+//! discovery_port = xxxxxx
+//! journal = xxxxxx
+//! keepalive = xxxxxx
+//! info_queue = xxxxxx
+//! power_off_idle = xxxxxx
 
 use std::{
-  collections::HashMap,
+  collections::{BTreeMap, HashMap},
+  fmt, fs,
   io::{Read, Write},
-  net::{TcpListener, TcpStream},
+  mem,
+  net::{IpAddr, SocketAddr, TcpListener, TcpStream, UdpSocket},
+  os::fd::AsRawFd,
   sync::{
-    mpsc::{self, Receiver, Sender},
-    Mutex,
+    atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering},
+    mpsc::{Receiver, RecvTimeoutError, SyncSender, TrySendError},
+    Condvar, Mutex,
   },
   thread,
-  time::{Duration, SystemTime, UNIX_EPOCH},
+  time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use log::{error, info, warn};
-use splitty::split_unquoted_char;
+use nix::libc;
 
-use crate::srcp_server_types::{Message, SRCPMessage, SRCPMessageDevice};
+use crate::{
+  srcp_acl, srcp_alias,
+  srcp_ga_rueckmeldung::GaRueckmeldungen,
+  srcp_http, srcp_info_queue,
+  srcp_info_queue::{InfoQueueReceiver, InfoQueueSender},
+  srcp_journal,
+  srcp_mqtt::{self, MqttConfig},
+  srcp_nothalt::NothaltZonen,
+  srcp_roster::{self, Roster},
+  srcp_server_types::{
+    Message, SRCPMessage, SRCPMessageDevice, SRCPMessageID, SRCPMessageType, PRUEFEN,
+  },
+};
 
 /// Unterstützte SRCP version
-const SRCP_VERSION: &'static str = "0.8.4";
+pub const SRCP_VERSION: &'static str = "0.8.4";
 /// Timeout für alle Kommandos ausser SM, diese werden immer sofort beantwortet, keine Verzögerungen
 const CMD_TIMEOUT: Duration = Duration::from_millis(200);
 /// Timeout für alle SM Kommandos, diese werden erst bei vorliegen Resultat beantwortet
 /// In einer Kommandosession wird nur immer ein Kommando um das andere abgearbeitet, blockiert bis Antwort vorliegt.
 /// Deshalb sollten Clients SM Kommandos jeweils in einer eigenen Session senden um die Abarbeitung von anderen
 /// Kommandos nicht zu verzögern!
-const CMD_SM_TIMEOUT: Duration = Duration::from_millis(10000);
+pub const CMD_SM_TIMEOUT: Duration = Duration::from_millis(10000);
+/// UDP Discovery Anfrage, auf die geantwortet wird
+const DISCOVERY_ANFRAGE: &str = "srcpd?";
+/// File mit dem Hostnamen für die Discovery Antwort
+const PATH_HOSTNAME: &str = "/proc/sys/kernel/hostname";
+
+/// Anzahl unbeantworteter TCP Keepalive Proben bis eine Verbindung als unterbrochen gilt
+const KEEPALIVE_PROBEN: libc::c_int = 3;
+/// Default max. Anzahl wartende Messages pro Client ([srcp] info_queue).
+/// Muss für das Update aller Zustände bei der Anmeldung eines Info Clients reichen.
+const INFO_QUEUE_DEFAULT: usize = 10000;
+/// Max. Anzahl wartende Kommandos pro SRCP Server
+pub const CMD_QUEUE_MAX: usize = 100;
+/// Max. Anzahl wartende Messages von allen SRCP Servern zum Dispatcher.
+/// Muss für das Update aller Zustände eines Servers bei der Anmeldung eines Info Clients reichen.
+pub const SERVER_INFO_QUEUE_MAX: usize = 10000;
+/// Parameter für Abfrage aller Sessions: GET 0 SERVER SESSIONS
+const SERVER_SESSIONS: &str = "SESSIONS";
+/// Parameter einer einzelnen Session in der Antwort auf "GET 0 SERVER SESSIONS"
+const SERVER_SESSION: &str = "SESSION";
+/// Adresse interner Clients in der Session Liste
+const ADRESSE_INTERN: &str = "intern";
+/// Max. Zeit ab Start, während der Kommandos an noch nicht bereite Busse verzögert werden.
+/// Danach werden sie sofort mit 417 abgelehnt (z.B. wenn ein Server die Hardware nicht öffnen konnte).
+const START_FRIST: Duration = Duration::from_secs(10);
+
+/// SRCP Version x.y.z
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+struct SrcpVersion(u32, u32, u32);
+impl SrcpVersion {
+  /// Version aus String "x.y.z" erstellen
+  /// Liefert None wenn ungültig
+  /// # Arguments
+  /// * version - Versionsstring
+  fn from_str(version: &str) -> Option<SrcpVersion> {
+    let mut teile = version.trim().split('.').map(|teil| teil.parse::<u32>());
+    let version = SrcpVersion(
+      teile.next()?.ok()?,
+      teile.next()?.ok()?,
+      teile.next()?.ok()?,
+    );
+    if teile.next().is_some() {
+      return None;
+    }
+    Some(version)
+  }
+}
+
+/// Durch den Client mit "SET PROTOCOL SRCP <version>" ausgehandelte Fähigkeiten einer Session
+#[derive(Clone, Copy, Debug)]
+struct ClientCapabilities {
+  /// Verlangte SRCP Version, None wenn durch Client nicht angegeben
+  version: Option<SrcpVersion>,
+  /// Mit "SET INFOFORMAT GL COMPACT" verlangt: Funktionen in INFO GL hex kodiert
+  gl_kompakt: bool,
+}
+impl ClientCapabilities {
+  /// Liefert true, wenn die Session srcpd_rust Erweiterungen (siehe "SRCPMessage::is_erweiterung")
+  /// verwenden darf. Das ist nur der Fall, wenn der Client die aktuelle Version explizit verlangt hat.
+  fn erweiterungen(&self) -> bool {
+    self.version == SrcpVersion::from_str(SRCP_VERSION)
+  }
+  /// Fähigkeiten für interne Clients (z.B. HTTP Bridge), diese unterstützen immer alle Erweiterungen
+  fn intern() -> ClientCapabilities {
+    ClientCapabilities {
+      version: SrcpVersion::from_str(SRCP_VERSION),
+      gl_kompakt: false,
+    }
+  }
+
+  /// Message für diese Session formatieren.
+  /// Mit "gl_kompakt" werden die Funktionen <f0> . . <fn> in INFO 100 GL durch einen einzigen Hex Wert
+  /// "0x<hex>" ersetzt, Bit 0 ist f0. Das reduziert z.B. bei 64 Funktionen die Zeilenlänge massiv.
+  /// # Arguments
+  /// * msg - Die zu formatierende Message
+  fn format(&self, msg: &SRCPMessage) -> String {
+    //INFO <bus> GL <addr> <drivemode> <V> <V_max> <f0> . . <fn>
+    const GL_ANZ_PARAM_OHNE_F: usize = 4;
+    if !self.gl_kompakt
+      || (msg.device != SRCPMessageDevice::GL)
+      || !matches!(&msg.message_id, SRCPMessageID::Info { info_code } if info_code == "100")
+      || (msg.parameter.len() <= GL_ANZ_PARAM_OHNE_F)
+      || (msg.parameter.len() > GL_ANZ_PARAM_OHNE_F + u64::BITS as usize)
+    {
+      return msg.to_string();
+    }
+    let mut funktionen: u64 = 0;
+    for (i, f) in msg.parameter[GL_ANZ_PARAM_OHNE_F..].iter().enumerate() {
+      match f.as_str() {
+        "0" => {}
+        "1" => funktionen |= 1 << i,
+        //Unbekanntes Format, unverändert senden
+        _ => return msg.to_string(),
+      }
+    }
+    let mut kompakt = msg.clone();
+    kompakt.parameter.truncate(GL_ANZ_PARAM_OHNE_F);
+    kompakt.parameter.push(format!("0x{:X}", funktionen));
+    kompakt.to_string()
+  }
+}
 
 /// Verwaltung Sender und Session
 struct SenderSession {
-  sender: Sender<SRCPMessage>,
+  sender: InfoQueueSender<SRCPMessage>,
   session_id: u32,
+  capabilities: ClientCapabilities,
+  /// Anzahl verworfene, älteste Messages seit die Queue dieses Clients voll ist
+  verworfen: u64,
 }
 //Info Messages können für Info und Command clients relevant sein
 struct InfoSenderForClient {
@@ -50,13 +204,64 @@ static ALLE_SRCP_INFO_SENDER: Mutex<InfoSenderForClient> = Mutex::new(InfoSender
   command_client: Vec::new(),
   info_client: Vec::new(),
 });
+/// Letzte vergebene Session ID, für SRCP Clients und interne Clients
+static SESSION_ID: AtomicU32 = AtomicU32::new(0);
+/// Max. Anzahl wartende Messages pro Client
+static INFO_QUEUE: AtomicUsize = AtomicUsize::new(INFO_QUEUE_DEFAULT);
+/// Anzahl wegen voller Client Queue verworfene Messages seit Start
+static INFO_VERWORFEN: AtomicU64 = AtomicU64::new(0);
+/// Anzahl wegen voller Server Queue abgelehnte Kommandos seit Start
+static CMD_ABGELEHNT: AtomicU64 = AtomicU64::new(0);
+
+/// Wartezeit in Sekunden für automatisches Power Off ohne Command Mode Client, 0: kein Power Off
+static POWER_OFF_IDLE: AtomicU32 = AtomicU32::new(0);
+/// Anzahl angemeldete Command Mode Verbindungen seit Start, erkennt Verbindungen während der Wartezeit
+static CMD_VERBINDUNGEN: AtomicU64 = AtomicU64::new(0);
+
+/// Eintrag einer verbundenen Session für "GET 0 SERVER SESSIONS"
+struct SessionEintrag {
+  mode: SrcpMode,
+  /// Remote Adresse des Clients, "intern" für interne Clients
+  adresse: String,
+  /// Zeitpunkt der Anmeldung
+  verbunden: SystemTime,
+  /// Zeitpunkt des letzten Kommandos (Command Mode) oder der letzten gesendeten Message (Info Mode)
+  letzte_aktivitaet: SystemTime,
+}
+/// Alle verbundenen Sessions, Key ist die Session ID
+static ALLE_SESSIONS: Mutex<BTreeMap<u32, SessionEintrag>> = Mutex::new(BTreeMap::new());
+
+/// Start- und Ausfallzustand aller SRCP Busse
+struct BusBereitschaft {
+  /// Alle Busse, deren Server "SRCPMessageID::Ready" gemeldet hat
+  bereit: Vec<usize>,
+  /// Alle Busse, deren Server "SRCPMessageID::Failed" gemeldet hat
+  ausgefallen: Vec<usize>,
+  /// Ende der Startfrist, None solange "startup" nicht aufgerufen wurde
+  frist: Option<Instant>,
+}
+static BUS_BEREITSCHAFT: Mutex<BusBereitschaft> = Mutex::new(BusBereitschaft {
+  bereit: Vec::new(),
+  ausgefallen: Vec::new(),
+  frist: None,
+});
+/// Signalisiert neu bereite Busse
+static BUS_BEREIT: Condvar = Condvar::new();
 
 //enum für SRCP Command- oder Infomode
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 enum SrcpMode {
   Command,
   Info,
 }
+impl fmt::Display for SrcpMode {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      SrcpMode::Command => write!(f, "COMMAND"),
+      SrcpMode::Info => write!(f, "INFO"),
+    }
+  }
+}
 
 /// Read line function die tolerant gegenüber nicht ASCII Zeichen ist, diese werden ignoriert.
 /// Es wird jeweils bis \n gelesen. Blockiert solange kein \n gelesen wurde oder Verbindung abbricht.
@@ -115,14 +320,18 @@ fn send_srcp_error(client_stream: &TcpStream, err_code: &str, msg: &str) -> Resu
 }
 
 /// SRCP Server Handshake mit Client.
-/// Liefert den gewünschten SRCP Mode oder Error
+/// Liefert den gewünschten SRCP Mode und die ausgehandelten Fähigkeiten oder Error
 /// # Arguments
 /// * client_stream - TCP Stream von/zu diesem Client
 /// * session_id - Die zu verwendende Session ID
 fn handle_srcp_handshake(
   mut client_stream: &TcpStream, session_id: u32,
-) -> Result<SrcpMode, String> {
+) -> Result<(SrcpMode, ClientCapabilities), String> {
   let mut line = String::new();
+  let mut capabilities = ClientCapabilities {
+    version: None,
+    gl_kompakt: false,
+  };
   //SRCP Willkommensmessage senden
   //srcpd Vx.x.x; SRCP x.x.x
   client_stream
@@ -143,10 +352,34 @@ fn handle_srcp_handshake(
     let mode = match line.to_uppercase().as_str() {
       "SET CONNECTIONMODE SRCP COMMAND" => SrcpMode::Command,
       "SET CONNECTIONMODE SRCP INFO" => SrcpMode::Info,
+      "SET INFOFORMAT GL COMPACT" => {
+        //srcpd_rust Erweiterung, nur wenn vorher die aktuelle Version verlangt wurde
+        if capabilities.erweiterungen() {
+          capabilities.gl_kompakt = true;
+          send_srcp_message(client_stream, "201 OK INFOFORMAT")?;
+        } else {
+          send_srcp_error(client_stream, "423", "unsupported operation")?;
+        }
+        continue;
+      }
       _ => {
-        if line.starts_with("SET PROTOCOL SRCP") {
-          //Wird ignoriert, eibfach mit OK beantworten
-          send_srcp_message(client_stream, "201 OK PROTOCOL SRCP")?;
+        if let Some(version) = line.strip_prefix("SET PROTOCOL SRCP") {
+          //Unterstützt werden alle 0.8.x Versionen bis zur aktuellen Version
+          let unterstuetzt = SrcpVersion::from_str(SRCP_VERSION).unwrap();
+          match SrcpVersion::from_str(version) {
+            Some(version)
+              if (version.0 == unterstuetzt.0)
+                && (version.1 == unterstuetzt.1)
+                && (version <= unterstuetzt) =>
+            {
+              capabilities.version = Some(version);
+              send_srcp_message(client_stream, "201 OK PROTOCOL SRCP")?;
+            }
+            _ => {
+              warn!("Nicht unterstützte SRCP Version verlangt: {}", line);
+              send_srcp_error(client_stream, "400", "unsupported protocol")?;
+            }
+          }
         } else {
           warn!("Ungültiges SRCP Kommando empfangen: {}", line);
           send_srcp_error(client_stream, "401", "unsupported connection mode")?;
@@ -165,50 +398,512 @@ fn handle_srcp_handshake(
     };
     //Start neue Session
     send_srcp_message(client_stream, format!("200 OK GO {}", session_id).as_str())?;
-    return Ok(mode);
+    return Ok((mode, capabilities));
+  }
+}
+
+/// Eine wegen voller Queue verworfene Message eines internen Clients (z.B. WebSocket) zählen
+pub fn add_info_verworfen() {
+  INFO_VERWORFEN.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Liefert (Anzahl verworfene Messages an Clients, Anzahl abgelehnte Kommandos) seit Start
+pub fn get_queue_statistik() -> (u64, u64) {
+  (
+    INFO_VERWORFEN.load(Ordering::Relaxed),
+    CMD_ABGELEHNT.load(Ordering::Relaxed),
+  )
+}
+
+/// Liefert einen Zeitpunkt in Sekunden seit 1.1.1970 UTC
+/// # Arguments
+/// * zeit - Der umzurechnende Zeitpunkt
+fn unix_sekunden(zeit: SystemTime) -> u64 {
+  zeit
+    .duration_since(UNIX_EPOCH)
+    .map(|dauer| dauer.as_secs())
+    .unwrap_or(0)
+}
+
+/// Aktivität einer Session für "GET 0 SERVER SESSIONS" festhalten
+/// # Arguments
+/// * session_id - Session ID des aktiven Clients
+fn session_aktivitaet(session_id: u32) {
+  if let Some(eintrag) = ALLE_SESSIONS.lock().unwrap().get_mut(&session_id) {
+    eintrag.letzte_aktivitaet = SystemTime::now();
+  }
+}
+
+/// Beendete Session aus der Liste für "GET 0 SERVER SESSIONS" entfernen
+/// # Arguments
+/// * session_id - Session ID des beendeten Clients
+fn session_beendet(session_id: u32) {
+  ALLE_SESSIONS.lock().unwrap().remove(&session_id);
+}
+
+/// Liefert die Anzahl verbundener Command Mode Clients ohne interne Clients
+fn anzahl_command_clients() -> usize {
+  ALLE_SESSIONS
+    .lock()
+    .unwrap()
+    .values()
+    .filter(|eintrag| {
+      matches!(eintrag.mode, SrcpMode::Command) && (eintrag.adresse != ADRESSE_INTERN)
+    })
+    .count()
+}
+
+/// Nach dem Ende einer Command Mode Verbindung: war das die letzte, dann nach "power_off_idle"
+/// Sekunden Power Off an alle Busse senden, sofern in der Zwischenzeit keine neue Verbindung aufgebaut wurde.
+/// # Arguments
+/// * all_cmd_tx - Alle Channel Sender für Kommandos zu den SRCP Servern. Key ist die Busnummer.
+fn power_off_wenn_letzter(all_cmd_tx: &HashMap<usize, SyncSender<Message>>) {
+  let sekunden = POWER_OFF_IDLE.load(Ordering::Relaxed);
+  if (sekunden == 0) || (anzahl_command_clients() > 0) {
+    return;
+  }
+  let verbindungen = CMD_VERBINDUNGEN.load(Ordering::Relaxed);
+  let all_cmd_tx = all_cmd_tx.clone();
+  info!(
+    "Letzter SRCP Command Client beendet, Power Off in {} s",
+    sekunden
+  );
+  thread::Builder::new()
+    .name("Power_Off_Idle".to_string())
+    .spawn(move || {
+      thread::sleep(Duration::from_secs(sekunden as u64));
+      if (CMD_VERBINDUNGEN.load(Ordering::Relaxed) != verbindungen)
+        || (anzahl_command_clients() > 0)
+      {
+        return;
+      }
+      warn!("Kein SRCP Command Client mehr verbunden, Power Off für alle Busse");
+      for (&bus, sender) in &all_cmd_tx {
+        //Ausgefallene Server ignorieren
+        let _ = sender.send(Message::new_srcpmessage(SRCPMessage::new(
+          Some(0),
+          bus,
+          SRCPMessageID::Command {
+            msg_type: SRCPMessageType::SET,
+          },
+          SRCPMessageDevice::Power,
+          vec!["OFF".to_string()],
+        )));
+      }
+    })
+    .unwrap();
+}
+
+/// Beantwortet "GET 0 SERVER SESSIONS" mit der Liste aller verbundenen Sessions.
+/// Liefert wie "execute_command" alle Zwischenantworten und die letzte Antwort.
+/// # Arguments
+/// * srcp_msg - Das Kommando
+fn get_sessions(
+  srcp_msg: &SRCPMessage,
+) -> Result<(Vec<SRCPMessage>, SRCPMessage), (&'static str, &'static str)> {
+  let SRCPMessageID::Command {
+    msg_type: SRCPMessageType::GET,
+  } = srcp_msg.message_id
+  else {
+    return Err(("423", "unsupported operation"));
+  };
+  match srcp_msg.parameter.first() {
+    None => return Err(("419", "list too short")),
+    Some(para) if (para == SERVER_SESSIONS) && (srcp_msg.parameter.len() == 1) => {}
+    Some(_) => return Err(("412", "wrong value")),
+  }
+  let info = |parameter: Vec<String>| {
+    SRCPMessage::new(
+      srcp_msg.session_id,
+      0,
+      SRCPMessageID::Info {
+        info_code: "100".to_string(),
+      },
+      SRCPMessageDevice::Server,
+      parameter,
+    )
+  };
+  let alle_sessions = ALLE_SESSIONS.lock().unwrap();
+  let mut antworten = vec![info(vec![
+    SERVER_SESSIONS.to_string(),
+    alle_sessions.len().to_string(),
+  ])];
+  for (session_id, eintrag) in alle_sessions.iter() {
+    antworten.push(info(vec![
+      SERVER_SESSION.to_string(),
+      session_id.to_string(),
+      eintrag.mode.to_string(),
+      eintrag.adresse.clone(),
+      unix_sekunden(eintrag.verbunden).to_string(),
+      unix_sekunden(eintrag.letzte_aktivitaet).to_string(),
+    ]));
+  }
+  //Alle ausser der letzten Antwort sind Zwischenantworten
+  let antwort = antworten.pop().unwrap();
+  Ok((
+    antworten
+      .into_iter()
+      .map(SRCPMessage::mit_weiteren_antworten)
+      .collect(),
+    antwort,
+  ))
+}
+
+/// Liefert eine neue, eindeutige Session ID
+pub fn neue_session_id() -> u32 {
+  SESSION_ID.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+/// Anmelden eines Clients beim Info Message Dispatcher.
+/// Liefert den Channel Receiver über den alle für diesen Client relevanten Messages empfangen werden.
+/// # Arguments
+/// * session_id - Session ID des Clients
+/// * capabilities - Fähigkeiten der Session
+/// * info_mode - true: Info Mode Client, false: Command Mode Client
+/// * adresse - Remote Adresse des Clients für "GET 0 SERVER SESSIONS"
+fn anmelden_client(
+  session_id: u32, capabilities: ClientCapabilities, info_mode: bool, adresse: String,
+) -> InfoQueueReceiver<SRCPMessage> {
+  let jetzt = SystemTime::now();
+  if !info_mode {
+    CMD_VERBINDUNGEN.fetch_add(1, Ordering::Relaxed);
+  }
+  ALLE_SESSIONS.lock().unwrap().insert(
+    session_id,
+    SessionEintrag {
+      mode: if info_mode {
+        SrcpMode::Info
+      } else {
+        SrcpMode::Command
+      },
+      adresse,
+      verbunden: jetzt,
+      letzte_aktivitaet: jetzt,
+    },
+  );
+  let (info_tx, info_rx) = srcp_info_queue::info_queue(INFO_QUEUE.load(Ordering::Relaxed));
+  let mut guard = ALLE_SRCP_INFO_SENDER.lock().unwrap();
+  let prot_alle_info_sender = &mut *guard; // take a &mut borrow of the value
+  let sender_session = SenderSession {
+    sender: info_tx,
+    session_id,
+    capabilities,
+    verworfen: 0,
+  };
+  if info_mode {
+    prot_alle_info_sender.info_client.push(sender_session);
+  } else {
+    prot_alle_info_sender.command_client.push(sender_session);
+  }
+  info_rx
+}
+
+/// Anmelden eines internen Info Mode Clients (z.B. HTTP Bridge).
+/// Allen Servern wird der neue Client mitgeteilt, so dass diese ein Update aller Zustände senden.
+/// Liefert den Channel Receiver über den alle Info Messages empfangen werden.
+/// # Arguments
+/// * session_id - Session ID des Clients
+/// * all_cmd_tx - Alle Channel Sender für Kommandos zu den SRCP Servern. Key ist die Busnummer.
+pub fn anmelden_intern_info_client(
+  session_id: u32, all_cmd_tx: &HashMap<usize, SyncSender<Message>>,
+) -> InfoQueueReceiver<SRCPMessage> {
+  let info_rx = anmelden_client(
+    session_id,
+    ClientCapabilities::intern(),
+    true,
+    ADRESSE_INTERN.to_string(),
+  );
+  //Interne Clients bleiben bestehen, sie erhalten die Zustände mit den nächsten Veränderungen
+  send_new_info_client(session_id, all_cmd_tx);
+  info_rx
+}
+
+/// Anmelden eines internen Command Mode Clients (z.B. HTTP Bridge).
+/// Liefert den Channel Receiver über den die Antworten auf Kommandos empfangen werden.
+/// # Arguments
+/// * session_id - Session ID des Clients
+pub fn anmelden_intern_command_client(session_id: u32) -> InfoQueueReceiver<SRCPMessage> {
+  anmelden_client(
+    session_id,
+    ClientCapabilities::intern(),
+    false,
+    ADRESSE_INTERN.to_string(),
+  )
+}
+
+/// Allen Servern einen neuen Info Mode Client mitteilen so dass diese ein Update aller Zustände senden können.
+/// Blockiert nie. Liefert false zurück, wenn die Kommando Queue eines Servers voll ist, der Client würde
+/// dann nicht alle Zustände erhalten.
+/// # Arguments
+/// * session_id - Session ID des neuen Clients
+/// * all_cmd_tx - Alle Channel Sender für Kommandos zu den SRCP Servern. Key ist die Busnummer.
+fn send_new_info_client(session_id: u32, all_cmd_tx: &HashMap<usize, SyncSender<Message>>) -> bool {
+  let message = Message::new_info_client(session_id);
+  let mut result = true;
+  for (bus, sender) in all_cmd_tx {
+    match sender.try_send(message.clone()) {
+      Ok(()) => {}
+      Err(TrySendError::Full(_)) => {
+        CMD_ABGELEHNT.fetch_add(1, Ordering::Relaxed);
+        warn!(
+          "send_new_info_client: Kommando Queue SRCP Server an Bus {} voll, session_id={}",
+          bus, session_id
+        );
+        result = false;
+      }
+      //Ausgefallene Server ignorieren
+      Err(TrySendError::Disconnected(_)) => {
+        warn!(
+          "send_new_info_client: Server an Bus {} nicht mehr vorhanden",
+          bus
+        );
+      }
+    }
+  }
+  result
+}
+
+/// Warten bis der Server eines Busses bereit ist, längstens bis zum Ende der Startfrist.
+/// Liefert Err 417 "starting up" wenn der Bus nicht bereit ist, Err 499 "bus failed" wenn der Server
+/// ausgefallen ist.
+/// # Arguments
+/// * bus - SRCP Bus
+fn warten_bus_bereit(bus: usize) -> Result<(), (&'static str, &'static str)> {
+  let mut guard = BUS_BEREITSCHAFT.lock().unwrap();
+  loop {
+    if guard.ausgefallen.contains(&bus) {
+      return Err(("499", "bus failed"));
+    }
+    if guard.bereit.contains(&bus) {
+      return Ok(());
+    }
+    let Some(warten) = guard
+      .frist
+      .and_then(|frist| frist.checked_duration_since(Instant::now()))
+    else {
+      warn!("SRCP Bus {} ist nicht bereit", bus);
+      return Err(("417", "starting up"));
+    };
+    guard = BUS_BEREIT.wait_timeout(guard, warten).unwrap().0;
+  }
+}
+
+/// Bereitschaft eines Busses eintragen
+/// # Arguments
+/// * bus - Bereiter SRCP Bus
+/// * anz_busse - Anzahl aller SRCP Busse
+fn set_bus_bereit(bus: usize, anz_busse: usize) {
+  let mut guard = BUS_BEREITSCHAFT.lock().unwrap();
+  if !guard.bereit.contains(&bus) {
+    guard.bereit.push(bus);
+    info!("SRCP Bus {} bereit", bus);
+    if guard.bereit.len() == anz_busse {
+      info!("Alle SRCP Busse bereit");
+    }
+  }
+  BUS_BEREIT.notify_all();
+}
+
+/// Ausfall eines Busses eintragen und alle Clients darüber informieren.
+/// Liefert die GM Info Message für alle Clients:
+/// INFO <bus> GM <send_to> <reply_to> SRCP_BUS_FAILED "<Grund>"
+/// # Arguments
+/// * msg - Failed Message des Servers
+fn set_bus_ausgefallen(msg: &SRCPMessage) -> SRCPMessage {
+  let grund = msg.parameter.first().cloned().unwrap_or_default();
+  error!("SRCP Bus {} ausgefallen: {}", msg.bus, grund);
+  let mut guard = BUS_BEREITSCHAFT.lock().unwrap();
+  guard.bereit.retain(|bus| *bus != msg.bus);
+  if !guard.ausgefallen.contains(&msg.bus) {
+    guard.ausgefallen.push(msg.bus);
   }
+  //Wartende Kommandos sofort abbrechen
+  BUS_BEREIT.notify_all();
+  SRCPMessage::new(
+    None,
+    msg.bus,
+    SRCPMessageID::Info {
+      info_code: "100".to_string(),
+    },
+    SRCPMessageDevice::GM,
+    vec![
+      "0".to_string(),
+      "0".to_string(),
+      "SRCP_BUS_FAILED".to_string(),
+      format!("\"{}\"", grund),
+    ],
+  )
+}
+
+/// Ein Kommando an den zuständigen SRCP Server senden und auf die Antwort warten.
+/// Ein SRCP Server kann auf ein Kommando mehrere Zeilen antworten (z.B. SM), alle Antworten ausser
+/// der letzten sind mit "weitere_folgen" markiert. Auf jede Antwort wird max. der Timeout gewartet.
+/// Liefert alle Zwischenantworten in empfangener Reihenfolge und die letzte Antwort des Servers
+/// oder Errorcode und Errortext.
+/// # Arguments
+/// * srcp_msg - Das Kommando
+/// * adresse - IP Adresse des Clients für die Zugriffskontrolle, None für interne Clients
+/// * info_rx - Channel Receiver des angemeldeten Command Mode Clients
+/// * all_cmd_tx - Alle Channel Sender für Kommandos zu den SRCP Servern. Key ist die Busnummer.
+pub fn execute_command(
+  srcp_msg: SRCPMessage, adresse: Option<IpAddr>, info_rx: &InfoQueueReceiver<SRCPMessage>,
+  all_cmd_tx: &HashMap<usize, SyncSender<Message>>,
+) -> Result<(Vec<SRCPMessage>, SRCPMessage), (&'static str, &'static str)> {
+  senden_und_warten(srcp_msg, adresse, info_rx, all_cmd_tx, false)
+}
+
+/// Ein Kommando durch den zuständigen SRCP Server nur validieren lassen, es wird nicht ausgeführt
+/// ("CHECK <cmd> ...", srcpd_rust Erweiterung).
+/// Liefert "200 OK" wenn das Kommando gültig ist, sonst Errorcode und Errortext, den auch die Ausführung
+/// geliefert hätte.
+/// # Arguments
+/// * srcp_msg - Das zu prüfende Kommando
+/// * adresse - IP Adresse des Clients für die Zugriffskontrolle
+/// * info_rx - Channel Receiver des angemeldeten Command Mode Clients
+/// * all_cmd_tx - Alle Channel Sender für Kommandos zu den SRCP Servern. Key ist die Busnummer.
+fn pruefen_command(
+  srcp_msg: SRCPMessage, adresse: Option<IpAddr>, info_rx: &InfoQueueReceiver<SRCPMessage>,
+  all_cmd_tx: &HashMap<usize, SyncSender<Message>>,
+) -> Result<(Vec<SRCPMessage>, SRCPMessage), (&'static str, &'static str)> {
+  senden_und_warten(srcp_msg, adresse, info_rx, all_cmd_tx, true)
+}
+
+/// Ein Kommando zum Ausführen oder nur Validieren an den zuständigen SRCP Server senden und auf die
+/// Antwort warten, siehe "execute_command" und "pruefen_command".
+/// # Arguments
+/// * srcp_msg - Das Kommando
+/// * adresse - IP Adresse des Clients für die Zugriffskontrolle, None für interne Clients
+/// * info_rx - Channel Receiver des angemeldeten Command Mode Clients
+/// * all_cmd_tx - Alle Channel Sender für Kommandos zu den SRCP Servern. Key ist die Busnummer.
+/// * pruefen - true: Kommando nur validieren, nicht ausführen
+fn senden_und_warten(
+  srcp_msg: SRCPMessage, adresse: Option<IpAddr>, info_rx: &InfoQueueReceiver<SRCPMessage>,
+  all_cmd_tx: &HashMap<usize, SyncSender<Message>>, pruefen: bool,
+) -> Result<(Vec<SRCPMessage>, SRCPMessage), (&'static str, &'static str)> {
+  //Zugriffskontrolle vor dem Weitersenden an die Busse, gilt für alle Anbindungen
+  if let Some(adresse) = adresse {
+    srcp_acl::pruefen(adresse, &srcp_msg)?;
+  }
+  //Kein Anfahren von GL's in einer Nothalt Zone mit aktivem FB
+  NothaltZonen::pruefen(&srcp_msg)?;
+  if let Some(session_id) = srcp_msg.session_id {
+    session_aktivitaet(session_id);
+  }
+  //Empfangsqueue sollte leer sein.
+  //Wenn nicht, dann ist eine Antwort erst nach dem Timeout eines früheren Kommandos eingetroffen.
+  while let Ok(msg) = info_rx.try_recv() {
+    warn!(
+      "execute_command: Nicht erwartete Message in info_rx: {}",
+      msg.to_string()
+    );
+  }
+  //Bus 0 ist der srcpd selbst, hier wird nur die Session Liste unterstützt
+  if (srcp_msg.bus == 0) && (srcp_msg.device == SRCPMessageDevice::Server) {
+    let antworten = get_sessions(&srcp_msg)?;
+    return Ok(if pruefen {
+      (vec![], SRCPMessage::new_ok(&srcp_msg, "200"))
+    } else {
+      antworten
+    });
+  }
+  //Prüfen ob verlangter Bus existiert
+  let sender = all_cmd_tx
+    .get(&srcp_msg.bus)
+    .ok_or(("412", "wrong value"))?;
+  //Während dem Start erst wenn der Server bereit ist
+  warten_bus_bereit(srcp_msg.bus)?;
+  //Die Validierung wird auch für SM sofort beantwortet
+  let timeout = if (srcp_msg.device == SRCPMessageDevice::SM) && !pruefen {
+    CMD_SM_TIMEOUT
+  } else {
+    CMD_TIMEOUT
+  };
+  let message = if pruefen {
+    Message::new_pruefen(srcp_msg.clone())
+  } else {
+    Message::new_srcpmessage(srcp_msg.clone())
+  };
+  match sender.try_send(message) {
+    Ok(()) => {}
+    //Server kommt mit der Abarbeitung nicht nach
+    Err(TrySendError::Full(_)) => {
+      CMD_ABGELEHNT.fetch_add(1, Ordering::Relaxed);
+      warn!(
+        "Kommando Queue SRCP Server an Bus {} voll, abgelehnt: {:?}",
+        srcp_msg.bus, srcp_msg
+      );
+      return Err(("413", "temporarily prohibited"));
+    }
+    //Server Thread beendet, z.B. weil die Hardware nicht geöffnet werden konnte
+    Err(TrySendError::Disconnected(_)) => return Err(("499", "bus failed")),
+  }
+  //Warten auf Antworten bis zur letzten
+  let mut zwischenantworten: Vec<SRCPMessage> = Vec::new();
+  let antwort = loop {
+    let antwort = info_rx.recv_timeout(timeout).map_err(|_| {
+      warn!(
+        "Keine Antwort von SRCP Server an Bus {} erhalten. Kommando {:?}",
+        srcp_msg.bus, srcp_msg
+      );
+      ("417", "timeout")
+    })?;
+    if pruefen {
+      //Ein Ok der Validierung selbst ist noch nicht der Abschluss der Prüfung
+      if antwort.is_pruefen_ok() {
+        break SRCPMessage::new_ok(&srcp_msg, "200");
+      }
+      if matches!(antwort.message_id, SRCPMessageID::Ok { .. }) {
+        continue;
+      }
+      //Fehler der Validierung
+      break antwort;
+    }
+    if !antwort.weitere_folgen {
+      break antwort;
+    }
+    zwischenantworten.push(antwort);
+  };
+  //Akzeptierte SET Kommandos ins Journal, nur geprüfte wurden nicht ausgeführt
+  if !pruefen {
+    srcp_journal::write(&srcp_msg, &antwort);
+  }
+  Ok((zwischenantworten, antwort))
 }
 
 /// Info Mode SRCP Client bedienen
 /// # Arguments
 /// * client_stream - TCP Stream von/zu diesem Client
 /// * session_id - Die zu verwendende Session ID
+/// * capabilities - Mit Handshake ausgehandelte Fähigkeiten
+/// * adresse - Remote Adresse des Clients
 /// * all_cmd_tx - Alle Channel Sender für Kommandos zu den SRCP Servern. Key ist die Busnummer.
 fn handle_srcp_infomode(
-  mut client_stream: &TcpStream, session_id: u32, all_cmd_tx: &HashMap<usize, Sender<Message>>,
+  mut client_stream: &TcpStream, session_id: u32, capabilities: ClientCapabilities,
+  adresse: SocketAddr, all_cmd_tx: &HashMap<usize, SyncSender<Message>>,
 ) {
   //No blocking read um Rx Buffer leeren zu können
   client_stream
     .set_nonblocking(true)
     .expect("handle_srcp_infomode set_nonblocking call failed");
   //Channel zum Empfang von Info Message aufbauen und anmelden
-  let (info_tx, info_rx) = mpsc::channel();
-  //und anmelden
-  {
-    let mut guard = ALLE_SRCP_INFO_SENDER.lock().unwrap();
-    let prot_alle_info_sender = &mut *guard; // take a &mut borrow of the value
-    prot_alle_info_sender.info_client.push(SenderSession {
-      sender: info_tx,
-      session_id: session_id,
-    });
-  }
-  //Allen Servern den neuen Info Mode Client mitteilen so dass diese ein Update aller Zustände senden können
-  let message = Message::new_info_client(session_id);
-  for (_, sender) in all_cmd_tx {
-    sender
-      .send(message.clone())
-      .expect("handle_srcp_infomode Error Send to Server fail");
+  let info_rx = anmelden_client(session_id, capabilities, true, adresse.to_string());
+  //Allen Servern den neuen Info Mode Client mitteilen so dass diese ein Update aller Zustände senden können.
+  //Wenn ein Server überlastet ist, wird der Client getrennt, statt ihm unvollständige Zustände zu liefern.
+  if !send_new_info_client(session_id, all_cmd_tx) {
+    warn!(
+      "SRCP Info Client {} getrennt, Server überlastet",
+      session_id
+    );
+    return;
   }
   //Und ab jetzt einfach alle Info Meldungen weitersenden
   loop {
-    let srcp_msg = info_rx
-      .recv()
-      .expect("handle_srcp_infomode Error recv")
-      .to_string();
+    let srcp_msg = capabilities.format(&info_rx.recv().expect("handle_srcp_infomode Error recv"));
     if send_srcp_message(client_stream, srcp_msg.as_str()).is_err() {
       //Abbruch, Client ist gestorben
       break;
     }
+    session_aktivitaet(session_id);
     //Für Info Verbindungen wird nichts empfangen. Zur Sicherheit Eingangsbuffer löschen
     let mut buf = vec![];
     let _ = client_stream.read_to_end(&mut buf); //Alle Fehler ignorieren
@@ -220,21 +915,15 @@ fn handle_srcp_infomode(
 /// # Arguments
 /// * client_stream - TCP Stream von/zu diesem Client
 /// * session_id - Die zu verwendende Session ID
+/// * capabilities - Mit Handshake ausgehandelte Fähigkeiten
+/// * adresse - Remote Adresse des Clients
 /// * all_cmd_tx - Alle Channel Sender für Kommandos zu den SRCP Servern. Key ist die Busnummer.
 fn handle_srcp_commandmode(
-  client_stream: &TcpStream, session_id: u32, all_cmd_tx: &HashMap<usize, Sender<Message>>,
+  client_stream: &TcpStream, session_id: u32, capabilities: ClientCapabilities,
+  adresse: SocketAddr, all_cmd_tx: &HashMap<usize, SyncSender<Message>>,
 ) {
   //Channel zum Empfang von Info Message aufbauen und anmelden
-  let (info_tx, info_rx) = mpsc::channel();
-  //und anmelden
-  {
-    let mut guard = ALLE_SRCP_INFO_SENDER.lock().unwrap();
-    let prot_alle_info_sender = &mut *guard; // take a &mut borrow of the value
-    prot_alle_info_sender.command_client.push(SenderSession {
-      sender: info_tx,
-      session_id: session_id,
-    });
-  }
+  let info_rx = anmelden_client(session_id, capabilities, false, adresse.to_string());
   //Solange auf Kommandos warten, auswerten und weitersenden, auf Antwort warten und zurück senden bis der Client gestorben ist
   let mut line = String::new();
   loop {
@@ -242,58 +931,60 @@ fn handle_srcp_commandmode(
     if read_line(client_stream, &mut line).is_err() {
       break;
     }
-    //Jedes Kommando muss folgendes Format haben:
-    //<cmd> <busnr> <dev_group> [<param1> [<param2> ....]]
-    let cmd_parts: Vec<&str> = split_unquoted_char(line.as_str(), ' ')
-      .unwrap_quotes(true)
-      .collect();
-    //Empfangsqueue sollte leer sein.
-    //Wenn nicht, dann gab es mal mehr als eine Antwort auf eine Kommando, was nicht sein sollte...
-    while let Ok(msg) = info_rx.try_recv() {
-      warn!(
-        "handle_srcp_commandmode: Nicht erwartete Message in info_rx: {}",
-        msg.to_string()
-      );
+    //Leere Zeile: NOP, z.B. damit Clients eine Verbindung aktiv halten können
+    if line.trim().is_empty() {
+      session_aktivitaet(session_id);
+      if let Err(msg) = send_srcp_message(client_stream, "200 OK") {
+        warn!("{}", msg);
+        break;
+      }
+      continue;
     }
-    //Kommando Auswerten
-    match SRCPMessage::from(session_id, &cmd_parts) {
-      Ok(srcp_msg) => {
-        //Prüfen ob verlangter Bus existiert
-        match all_cmd_tx.get(&srcp_msg.bus) {
-          Some(sender) => {
-            sender
-              .send(Message::new_srcpmessage(srcp_msg.clone()))
-              .unwrap();
-            //Warten auf Antwort
-            if let Ok(msg) = info_rx.recv_timeout(if srcp_msg.device == SRCPMessageDevice::SM {
-              CMD_SM_TIMEOUT
-            } else {
-              CMD_TIMEOUT
-            }) {
-              //info!("SRCP Antwort: {}", msg.to_string());
-              if let Err(errmsg) = send_srcp_message(client_stream, msg.to_string().as_str()) {
-                warn!("Send SRCP Error {}, SRCP Message {:?}", errmsg, msg);
-                break;
-              }
-            } else {
-              warn!(
-                "Keine Antwort von SRCP Server an Bus {} erhalten. Kommando {:?}",
-                cmd_parts[1], srcp_msg
-              );
-              if let Err(msg) = send_srcp_error(client_stream, "417", "timeout") {
-                warn!("{}", msg);
-                break;
-              }
-            }
+    //Nur validieren, nicht ausführen: "CHECK <cmd> ...", Erweiterung nur wenn durch Client ausgehandelt
+    let (pruefen, kommando) = match line.split_once(' ') {
+      Some((praefix, kommando)) if (praefix == PRUEFEN) && capabilities.erweiterungen() => {
+        (true, kommando)
+      }
+      _ => (false, line.as_str()),
+    };
+    let ausfuehren = if pruefen {
+      pruefen_command
+    } else {
+      execute_command
+    };
+    //Kommando Auswerten, Name anstelle GL Adresse auflösen
+    match SRCPMessage::parse(session_id, kommando).map(srcp_alias::aufloesen) {
+      Ok(srcp_msg) if srcp_msg.is_erweiterung() && !capabilities.erweiterungen() => {
+        //Erweiterungen nur wenn durch Client ausgehandelt
+        if let Err(msg) = send_srcp_error(client_stream, "423", "unsupported operation") {
+          warn!("{}", msg);
+          break;
+        }
+      }
+      Ok(srcp_msg) => match ausfuehren(srcp_msg, Some(adresse.ip()), &info_rx, all_cmd_tx) {
+        Ok((zwischenantworten, antwort)) => {
+          //Alle Antworten in empfangener Reihenfolge zurück senden
+          //info!("SRCP Antwort: {}", antwort.to_string());
+          if let Some((errmsg, msg)) = zwischenantworten
+            .iter()
+            .chain(std::iter::once(&antwort))
+            .find_map(|msg| {
+              send_srcp_message(client_stream, capabilities.format(msg).as_str())
+                .err()
+                .map(|errmsg| (errmsg, msg))
+            })
+          {
+            warn!("Send SRCP Error {}, SRCP Message {:?}", errmsg, msg);
+            break;
           }
-          None => {
-            if let Err(msg) = send_srcp_error(client_stream, "412", "wrong value") {
-              warn!("{}", msg);
-              break;
-            }
+        }
+        Err((errcode, errmsg)) => {
+          if let Err(msg) = send_srcp_error(client_stream, errcode, errmsg) {
+            warn!("{}", msg);
+            break;
           }
         }
-      }
+      },
       Err((errcode, errmsg)) => {
         info!("Ungültiger Befehl empfangen: {}", line);
         if let Err(msg) = send_srcp_error(client_stream, errcode, errmsg) {
@@ -303,6 +994,11 @@ fn handle_srcp_commandmode(
       }
     }
   }
+  //Alle SRCP Server über das Ende dieser Session informieren
+  for sender in all_cmd_tx.values() {
+    //Ausgefallene Server ignorieren
+    let _ = sender.send(Message::new_session_end(session_id));
+  }
   info!("SRCP Command Client {} beendet", session_id);
 }
 
@@ -310,34 +1006,95 @@ fn handle_srcp_commandmode(
 /// # Arguments
 /// * client_stream - TCP Stream von/zu diesem Client
 /// * session_id - Die zu verwendende Session ID
+/// * adresse - Remote Adresse des Clients
 /// * all_cmd_tx - Alle Channel Sender für Kommandos zu den SRCP Servern. Key ist die Busnummer.
 fn handle_srcp_connection(
-  client_stream: &TcpStream, session_id: u32, all_cmd_tx: HashMap<usize, Sender<Message>>,
+  client_stream: &TcpStream, session_id: u32, adresse: SocketAddr,
+  all_cmd_tx: HashMap<usize, SyncSender<Message>>,
 ) {
   match handle_srcp_handshake(client_stream, session_id) {
     Err(msg) => {
       error!("SRCP Handshake Error: {}", msg);
       return;
     }
-    Ok(mode) => {
+    Ok((mode, capabilities)) => {
       info!(
-        "Neuer Client SRCP Mode={:?} session_id={}",
-        mode, session_id
+        "Neuer Client SRCP Mode={:?} session_id={} {:?}",
+        mode, session_id, capabilities
       );
       match mode {
-        SrcpMode::Command => handle_srcp_commandmode(client_stream, session_id, &all_cmd_tx),
-        SrcpMode::Info => handle_srcp_infomode(client_stream, session_id, &all_cmd_tx),
+        SrcpMode::Command => {
+          handle_srcp_commandmode(
+            client_stream,
+            session_id,
+            capabilities,
+            adresse,
+            &all_cmd_tx,
+          );
+          session_beendet(session_id);
+          power_off_wenn_letzter(&all_cmd_tx);
+        }
+        SrcpMode::Info => {
+          handle_srcp_infomode(
+            client_stream,
+            session_id,
+            capabilities,
+            adresse,
+            &all_cmd_tx,
+          );
+          session_beendet(session_id);
+        }
       }
     }
   }
 }
 
+/// TCP Keepalive für eine Client Verbindung einschalten, damit z.B. NAT Router die Verbindung bei
+/// längerer Inaktivität nicht verwerfen und unterbrochene Verbindungen erkannt werden.
+/// Liefert Err wenn eine Socket Option nicht gesetzt werden konnte.
+/// # Arguments
+/// * client_stream - TCP Stream von/zu diesem Client
+/// * sekunden - Inaktivität bis zur ersten Probe und Intervall aller weiteren Proben
+fn set_tcp_keepalive(client_stream: &TcpStream, sekunden: u32) -> Result<(), String> {
+  let sekunden = sekunden as libc::c_int;
+  for (level, option, wert) in [
+    (libc::SOL_SOCKET, libc::SO_KEEPALIVE, 1),
+    (libc::IPPROTO_TCP, libc::TCP_KEEPIDLE, sekunden),
+    (libc::IPPROTO_TCP, libc::TCP_KEEPINTVL, sekunden),
+    (libc::IPPROTO_TCP, libc::TCP_KEEPCNT, KEEPALIVE_PROBEN),
+  ] {
+    // SAFETY: Der Filedescriptor gehört zum geöffneten "client_stream" und bleibt während dem Aufruf
+    // gültig. "wert" ist ein c_int auf dem Stack, Zeiger und Länge beschreiben genau diesen Wert,
+    // der von setsockopt nur gelesen wird.
+    let result = unsafe {
+      libc::setsockopt(
+        client_stream.as_raw_fd(),
+        level,
+        option,
+        &wert as *const libc::c_int as *const libc::c_void,
+        mem::size_of::<libc::c_int>() as libc::socklen_t,
+      )
+    };
+    if result != 0 {
+      return Err(format!(
+        "TCP Keepalive Option {} konnte nicht gesetzt werden: {}",
+        option,
+        std::io::Error::last_os_error()
+      ));
+    }
+  }
+  Ok(())
+}
+
 /// SRCP Server der auf eingehende Verbindungen wartet, diese entgegennimmt und für jede Verbindung
 /// einen Rx und Tx Thread startet
 /// # Arguments
 /// * port - TCP Port auf dem der Server gestartet werden soll
+/// * keepalive - TCP Keepalive in Sekunden für alle Verbindungen, None: kein Keepalive
 /// * all_cmd_tx - Alle Channel Sender für Kommandos zu den SRCP Servern. Key ist die Busnummer.
-fn srcp_server(port: u16, all_cmd_tx: &HashMap<usize, Sender<Message>>) -> ! {
+fn srcp_server(
+  port: u16, keepalive: Option<u32>, all_cmd_tx: &HashMap<usize, SyncSender<Message>>,
+) -> ! {
   let server_adr = format!("0.0.0.0:{}", port);
   info!("Start SRCP Server: {}", server_adr);
   let listener = TcpListener::bind(server_adr).expect(
@@ -347,12 +1104,16 @@ fn srcp_server(port: u16, all_cmd_tx: &HashMap<usize, Sender<Message>>) -> ! {
     )
     .as_str(),
   );
-  let mut session_id: u32 = 0;
   loop {
     info!("Warte auf SRCP Server Client");
     let (client_stream, addr) = listener.accept().expect("SRCP Server Accept fail");
     info!("SRCP Server neuer Client:{}", addr);
-    session_id = session_id + 1;
+    if let Some(sekunden) = keepalive {
+      if let Err(msg) = set_tcp_keepalive(&client_stream, sekunden) {
+        warn!("{}", msg);
+      }
+    }
+    let session_id = neue_session_id();
     //Alle Sender müssen geklont werden damit sie im anderen Thread verwendet werden können
     let all_cmd_tx_kopie = all_cmd_tx.clone();
     //Neuer Thread für diesen Client starten
@@ -361,14 +1122,63 @@ fn srcp_server(port: u16, all_cmd_tx: &HashMap<usize, Sender<Message>>) -> ! {
         "SRCP_Client_Thread Session={} Client={}",
         session_id, addr
       ))
-      .spawn(move || handle_srcp_connection(&client_stream, session_id, all_cmd_tx_kopie))
+      .spawn(move || handle_srcp_connection(&client_stream, session_id, addr, all_cmd_tx_kopie))
       .unwrap();
   }
 }
 
+/// UDP Discovery Server: beantwortet (Broadcast) Anfragen "srcpd?" mit
+/// "srcpd host=<hostname> port=<srcp port> version=<srcpd version> srcp=<SRCP version>"
+/// damit Clients den srcpd im LAN ohne manuelle Eingabe der IP Adresse finden können.
+/// Die IP Adresse ergibt sich für den Client aus dem Absender der Antwort.
+/// # Arguments
+/// * discovery_port - UDP Port auf dem Anfragen empfangen werden
+/// * srcp_port - TCP Port des SRCP Servers
+fn discovery_server(discovery_port: u16, srcp_port: u16) {
+  let socket = match UdpSocket::bind(("0.0.0.0", discovery_port)) {
+    Ok(socket) => socket,
+    Err(msg) => {
+      error!(
+        "Discovery Server konnte nicht auf UDP Port {} gestartet werden: {}",
+        discovery_port, msg
+      );
+      return;
+    }
+  };
+  let hostname = fs::read_to_string(PATH_HOSTNAME)
+    .map(|host| host.trim().to_string())
+    .unwrap_or_default();
+  let antwort = format!(
+    "srcpd host={} port={} version={} srcp={}",
+    hostname,
+    srcp_port,
+    env!("CARGO_PKG_VERSION"),
+    SRCP_VERSION
+  );
+  info!("Start Discovery Server: UDP Port {}", discovery_port);
+  let mut buffer = [0u8; 64];
+  loop {
+    match socket.recv_from(&mut buffer) {
+      Ok((len, absender)) => {
+        if String::from_utf8_lossy(&buffer[..len]).trim() == DISCOVERY_ANFRAGE {
+          info!("Discovery Anfrage von {}", absender);
+          if let Err(msg) = socket.send_to(antwort.as_bytes(), absender) {
+            warn!("Discovery Antwort an {} fail: {}", absender, msg);
+          }
+        }
+      }
+      Err(msg) => {
+        warn!("Discovery Empfang fail: {}", msg);
+      }
+    }
+  }
+}
+
 /// Senden einer SRCP Info Message an eine Clientgruppe
-/// Wenn eine Message nicht versendet werden konnte, dann wird der entsprechende Client gelöscht.
+/// Wenn der Client nicht mehr vorhanden ist, dann wird er gelöscht.
+/// Ist die Queue eines Clients voll, dann wird dort die älteste Message verworfen.
 /// Wenn in der Message eine Session ID vorhanden ist, dan wird die Message nur an diesen Client gesendet.
+/// Messages von Erweiterungen werden nur an Clients gesendet, die diese ausgehandelt haben.
 /// # Arguments
 /// * clients - Die Clientsgruppe
 /// * msg - Die zu versendende Message
@@ -381,18 +1191,42 @@ fn send_info_msg_for_client_group(
   }
   let mut i = 0;
   while i < clients.len() {
-    if srcp_message.session_id.is_none()
-      || (clients[i].session_id == srcp_message.session_id.unwrap())
+    if (srcp_message.session_id.is_none()
+      || (clients[i].session_id == srcp_message.session_id.unwrap()))
+      && (!srcp_message.is_erweiterung() || clients[i].capabilities.erweiterungen())
     {
-      if clients[i].sender.send(srcp_message.clone()).is_err() {
-        //Diesen Client gibt es nicht mehr
-        info!(
-          "dispachter_srcp_info delete Client session_id={}",
-          clients[i].session_id
-        );
-        clients.remove(i);
-      } else {
-        i += 1;
+      //Nie blockieren, ein hängender Client darf die anderen nicht aufhalten. Ist seine Queue voll, dann
+      //wird die älteste Message verworfen, der Client erhält so immer die neusten Zustände.
+      match clients[i].sender.send(srcp_message.clone()) {
+        Ok(false) => {
+          if clients[i].verworfen > 0 {
+            warn!(
+              "dispachter_srcp_info Client session_id={} wieder bereit, {} Messages verworfen",
+              clients[i].session_id, clients[i].verworfen
+            );
+            clients[i].verworfen = 0;
+          }
+          i += 1;
+        }
+        Ok(true) => {
+          if clients[i].verworfen == 0 {
+            warn!(
+              "dispachter_srcp_info Queue Client session_id={} voll, älteste Messages werden verworfen",
+              clients[i].session_id
+            );
+          }
+          clients[i].verworfen += 1;
+          INFO_VERWORFEN.fetch_add(1, Ordering::Relaxed);
+          i += 1;
+        }
+        Err(_) => {
+          //Diesen Client gibt es nicht mehr
+          info!(
+            "dispachter_srcp_info delete Client session_id={}",
+            clients[i].session_id
+          );
+          clients.remove(i);
+        }
       }
     } else {
       i += 1;
@@ -404,16 +1238,52 @@ fn send_info_msg_for_client_group(
 /// aktuell angemeldeten Info Clients
 /// # Arguments
 /// * info_rx - Channel über die die Info Messages empfangen werden
-fn dispachter_srcp_info(info_rx: Receiver<SRCPMessage>) {
+/// * nothalt_zonen - Nothalt Zonen, werden über alle Info Messages informiert
+/// * ga_rueckmeldungen - GA Rückmeldungen, können INFO GA bis zur Bestätigung durch einen FB zurückhalten
+/// * all_cmd_tx - Alle Channel Sender für Kommandos zu den SRCP Servern. Key ist die Busnummer.
+fn dispachter_srcp_info(
+  info_rx: Receiver<SRCPMessage>, mut nothalt_zonen: NothaltZonen,
+  mut ga_rueckmeldungen: GaRueckmeldungen, all_cmd_tx: HashMap<usize, SyncSender<Message>>,
+) {
   loop {
-    let msg = info_rx
-      .recv()
-      .expect("Error: dispachter_srcp_info info_rx.recv() fail");
-    {
-      //Info/Ok/Err Message an alle oder einen angemeldeten SRCP Info Clients versenden
-      let mut guard = ALLE_SRCP_INFO_SENDER.lock().unwrap();
-      let prot_alle_info_sender = &mut *guard; // take a &mut borrow of the value
-
+    //Bei ausstehenden GA Rückmeldungen nur bis zur nächsten Frist warten
+    let msg = match ga_rueckmeldungen.get_naechste_frist() {
+      Some(frist) => match info_rx.recv_timeout(frist.saturating_duration_since(Instant::now())) {
+        Ok(msg) => Some(msg),
+        Err(RecvTimeoutError::Timeout) => None,
+        Err(RecvTimeoutError::Disconnected) => {
+          panic!("Error: dispachter_srcp_info info_rx.recv() fail")
+        }
+      },
+      None => Some(
+        info_rx
+          .recv()
+          .expect("Error: dispachter_srcp_info info_rx.recv() fail"),
+      ),
+    };
+    let alle_msg = match msg {
+      Some(msg) => {
+        //Bereitschaft eines Servers nur intern, Ausfall wird allen Clients als GM Info gemeldet
+        let msg = match msg.message_id {
+          SRCPMessageID::Ready => {
+            set_bus_bereit(msg.bus, all_cmd_tx.len());
+            continue;
+          }
+          SRCPMessageID::Failed => set_bus_ausgefallen(&msg),
+          _ => msg,
+        };
+        //Nothalt Zonen vor allen Clients auswerten
+        nothalt_zonen.eval_info(&msg, &all_cmd_tx);
+        //Namen aus GL INIT Parametern
+        srcp_alias::eval_info(&msg);
+        ga_rueckmeldungen.eval_info(msg)
+      }
+      None => ga_rueckmeldungen.eval_timeout(),
+    };
+    //Info/Ok/Err Message an alle oder einen angemeldeten SRCP Info Clients versenden
+    let mut guard = ALLE_SRCP_INFO_SENDER.lock().unwrap();
+    let prot_alle_info_sender = &mut *guard; // take a &mut borrow of the value
+    for msg in alle_msg {
       //Zuerst alle Info Clients abarbeiten
       send_info_msg_for_client_group(&mut prot_alle_info_sender.info_client, &msg, false);
       //Dann alle Command Clients, hier aber nur wenn Session ID angegeben ist
@@ -429,7 +1299,7 @@ fn dispachter_srcp_info(info_rx: Receiver<SRCPMessage>) {
 /// * all_cmd_tx - Alle Channel Sender für Kommandos zu den SRCP Servern. Key ist die Busnummer.
 pub fn startup(
   config_file_values: &HashMap<String, HashMap<String, Option<String>>>,
-  info_rx: Receiver<SRCPMessage>, all_cmd_tx: &HashMap<usize, Sender<Message>>,
+  info_rx: Receiver<SRCPMessage>, all_cmd_tx: &HashMap<usize, SyncSender<Message>>,
 ) -> Result<(), String> {
   let port = config_file_values
     .get("srcp")
@@ -442,18 +1312,117 @@ pub fn startup(
     .ok()
     .ok_or("[srcp] port muss eine Zahl sein")?;
 
+  //Optionaler UDP Discovery Server
+  let discovery_port = match config_file_values["srcp"].get("discovery_port") {
+    Some(discovery_port) => Some(
+      discovery_port
+        .as_ref()
+        .ok_or("[srcp] discovery_port-Angabe ohne Wert")?
+        .parse::<u16>()
+        .ok()
+        .ok_or("[srcp] discovery_port muss eine Zahl sein")?,
+    ),
+    None => None,
+  };
+
+  //Optionaler TCP Keepalive für alle Verbindungen
+  let keepalive = match config_file_values["srcp"].get("keepalive") {
+    Some(keepalive) => Some(
+      keepalive
+        .as_ref()
+        .ok_or("[srcp] keepalive-Angabe ohne Wert")?
+        .parse::<u32>()
+        .ok()
+        .filter(|&sekunden| sekunden > 0)
+        .ok_or("[srcp] keepalive muss eine Zahl > 0 sein")?,
+    ),
+    None => None,
+  };
+
+  //Optionale max. Anzahl wartende Messages pro Client
+  if let Some(info_queue) = config_file_values["srcp"].get("info_queue") {
+    INFO_QUEUE.store(
+      info_queue
+        .as_ref()
+        .ok_or("[srcp] info_queue-Angabe ohne Wert")?
+        .parse::<usize>()
+        .ok()
+        .filter(|&anzahl| anzahl > 0)
+        .ok_or("[srcp] info_queue muss eine Zahl > 0 sein")?,
+      Ordering::Relaxed,
+    );
+  }
+
+  //Optionales automatisches Power Off ohne Command Mode Client
+  if let Some(power_off_idle) = config_file_values["srcp"].get("power_off_idle") {
+    POWER_OFF_IDLE.store(
+      power_off_idle
+        .as_ref()
+        .ok_or("[srcp] power_off_idle-Angabe ohne Wert")?
+        .parse::<u32>()
+        .ok()
+        .filter(|&sekunden| sekunden > 0)
+        .ok_or("[srcp] power_off_idle muss eine Zahl > 0 sein")?,
+      Ordering::Relaxed,
+    );
+  }
+
+  //Optionales Journal aller akzeptierten SET Kommandos
+  if let Some(journal) = config_file_values["srcp"].get("journal") {
+    srcp_journal::start(
+      journal
+        .as_ref()
+        .ok_or("[srcp] journal-Angabe ohne Wert")?
+        .as_str(),
+    )?;
+  }
+
+  //Alle weiteren Konfigurationen prüfen bevor Kommandos über irgend eine Anbindung angenommen werden
+  //Optionale Zugriffskontrolle
+  srcp_acl::startup(config_file_values.get("acl"))?;
+  //Nothalt Zonen
+  let nothalt_zonen = NothaltZonen::from_config(config_file_values.get("estop"))?;
+  //GA Rückmeldungen
+  let ga_rueckmeldungen = GaRueckmeldungen::from_config(config_file_values.get("ga_feedback"))?;
+  //Optionale HTTP/WebSocket Bridge
+  let http_port = srcp_http::port_from_config(config_file_values.get("http"))?;
+  //Optionale MQTT Anbindung
+  let mqtt_config = MqttConfig::from_config(config_file_values.get("mqtt"))?;
+  //Optionale vordefinierte GL's und GA's
+  let roster = Roster::from_config(config_file_values.get("roster"))?;
+
   info!("srcp start port={port}");
+  //Ab jetzt werden Kommandos an noch nicht bereite Busse längstens bis zum Ende der Startfrist verzögert
+  BUS_BEREITSCHAFT.lock().unwrap().frist = Some(Instant::now() + START_FRIST);
+  if let Some(discovery_port) = discovery_port {
+    thread::Builder::new()
+      .name("Discovery".to_string())
+      .spawn(move || discovery_server(discovery_port, port))
+      .unwrap();
+  }
+  //Optionale HTTP/WebSocket Bridge
+  srcp_http::startup(http_port, all_cmd_tx)?;
+  //Optionale MQTT Anbindung
+  srcp_mqtt::startup(mqtt_config, all_cmd_tx);
+  //Optionale vordefinierte GL's und GA's
+  srcp_roster::startup(roster, all_cmd_tx);
+  let all_cmd_tx_dispatcher = all_cmd_tx.clone();
   //Info Message Dispacther Thread starten
   //Alle Infos Messages der verschiedenen srcp_server_ Instanzen werden von diesem Thread an alle angemeldeten
   //Clients mit Info Mode gesendet
   thread::Builder::new()
     .name("Dispatcher".to_string())
     .spawn(move || {
-      dispachter_srcp_info(info_rx);
+      dispachter_srcp_info(
+        info_rx,
+        nothalt_zonen,
+        ga_rueckmeldungen,
+        all_cmd_tx_dispatcher,
+      );
     })
     .unwrap();
 
   //Hier geht es weiter mit als Hauptthread der auf eingehende Verbindungen wartet
   //und die Verbindung zwischen den für die Verbindungen gestarteten SRCP Servern und den Bus-Servern herstellt
-  srcp_server(port, all_cmd_tx);
+  srcp_server(port, keepalive, all_cmd_tx);
 }
@@ -0,0 +1,146 @@
+//! Zugriffskontrolle für SRCP Command Mode Clients pro Client IP Adresse und Device Gruppe
+//! - command: nur Clients aus diesen Netzen dürfen Zustände verändern (INIT, SET, TERM, VERIFY),
+//!   alle anderen haben nur lesenden Zugriff (GET). Info Mode ist immer erlaubt.
+//! - power: nur Clients aus diesen Netzen dürfen POWER schalten
+//! - sm: nur Clients aus diesen Netzen dürfen Service Mode Kommandos (auch GET) senden
+//!
+//! Ist ein Eintrag nicht vorhanden, dann gilt keine Einschränkung. Nicht erlaubte Kommandos werden mit
+//! "415 ERROR forbidden" abgelehnt. Für Kommandos über die HTTP Bridge gilt die Adresse des HTTP Clients,
+//! für MQTT die Adresse des Brokers. Nur der Roster als interner Client unterliegt keiner Kontrolle.
+//!
+//! INI File:
+//! [acl]
+//! command = <netz>,<netz>,...
+//! power = <netz>,<netz>,...
+//! sm = <netz>,<netz>,...
+//! Netz: <ip>/<prefix> oder <ip> für eine einzelne Adresse, IPv4 oder IPv6
+
+use std::{collections::HashMap, net::IpAddr, sync::Mutex};
+
+use log::{info, warn};
+
+use crate::srcp_server_types::{SRCPMessage, SRCPMessageDevice, SRCPMessageID, SRCPMessageType};
+
+/// Ein Netz: Adresse und Anzahl relevanter Bits
+#[derive(Debug)]
+struct Netz {
+  adresse: IpAddr,
+  prefix: u32,
+}
+impl Netz {
+  /// Netz aus "<ip>/<prefix>" oder "<ip>" erstellen.
+  /// Liefert None wenn ungültig.
+  /// # Arguments
+  /// * netz - Netz aus Konfigfile
+  fn from_str(netz: &str) -> Option<Netz> {
+    let (adresse, prefix) = match netz.split_once('/') {
+      Some((adresse, prefix)) => (adresse, Some(prefix)),
+      None => (netz, None),
+    };
+    let adresse = adresse.trim().parse::<IpAddr>().ok()?;
+    let max_prefix = if adresse.is_ipv4() { 32 } else { 128 };
+    let prefix = match prefix {
+      Some(prefix) => prefix.trim().parse::<u32>().ok()?,
+      None => max_prefix,
+    };
+    (prefix <= max_prefix).then_some(Netz { adresse, prefix })
+  }
+
+  /// Liefert true wenn die Adresse in diesem Netz liegt
+  /// # Arguments
+  /// * adresse - Zu prüfende Client Adresse
+  fn enthaelt(&self, adresse: IpAddr) -> bool {
+    match (self.adresse, adresse) {
+      (IpAddr::V4(netz), IpAddr::V4(adresse)) => {
+        let maske = u32::MAX.checked_shl(32 - self.prefix).unwrap_or(0);
+        (u32::from(netz) & maske) == (u32::from(adresse) & maske)
+      }
+      (IpAddr::V6(netz), IpAddr::V6(adresse)) => {
+        let maske = u128::MAX.checked_shl(128 - self.prefix).unwrap_or(0);
+        (u128::from(netz) & maske) == (u128::from(adresse) & maske)
+      }
+      _ => false,
+    }
+  }
+}
+
+/// Alle Einschränkungen, None: keine Einschränkung
+struct Zugriffsregeln {
+  command: Option<Vec<Netz>>,
+  power: Option<Vec<Netz>>,
+  sm: Option<Vec<Netz>>,
+}
+
+/// Konfigurierte Einschränkungen
+static REGELN: Mutex<Zugriffsregeln> = Mutex::new(Zugriffsregeln {
+  command: None,
+  power: None,
+  sm: None,
+});
+
+/// Liefert true wenn die Adresse in einem der Netze liegt oder keine Einschränkung besteht
+/// # Arguments
+/// * netze - Erlaubte Netze, None: keine Einschränkung
+/// * adresse - Zu prüfende Client Adresse
+fn erlaubt(netze: &Option<Vec<Netz>>, adresse: IpAddr) -> bool {
+  netze
+    .as_ref()
+    .is_none_or(|netze| netze.iter().any(|netz| netz.enthaelt(adresse)))
+}
+
+/// Prüft ob ein Client ein Kommando senden darf.
+/// Liefert Err mit SRCP Fehler wenn nicht erlaubt.
+/// # Arguments
+/// * adresse - IP Adresse des Clients
+/// * srcp_msg - Das Kommando
+pub fn pruefen(
+  adresse: IpAddr, srcp_msg: &SRCPMessage,
+) -> Result<(), (&'static str, &'static str)> {
+  let regeln = REGELN.lock().unwrap();
+  let nur_lesen = matches!(
+    srcp_msg.message_id,
+    SRCPMessageID::Command {
+      msg_type: SRCPMessageType::GET
+    }
+  );
+  let device_erlaubt = match srcp_msg.device {
+    SRCPMessageDevice::SM => erlaubt(&regeln.sm, adresse),
+    SRCPMessageDevice::Power if !nur_lesen => erlaubt(&regeln.power, adresse),
+    _ => true,
+  };
+  if device_erlaubt && (nur_lesen || erlaubt(&regeln.command, adresse)) {
+    Ok(())
+  } else {
+    warn!("Zugriff verweigert für {}: {:?}", adresse, srcp_msg);
+    Err(("415", "forbidden"))
+  }
+}
+
+/// Zugriffsregeln aus Konfigfile übernehmen.
+/// Liefert Err zurück wenn die Konfiguration ungültig ist.
+/// # Arguments
+/// * config - Abschnitt [acl] aus Konfigfile, None wenn nicht vorhanden
+pub fn startup(config: Option<&HashMap<String, Option<String>>>) -> Result<(), String> {
+  let Some(config) = config else {
+    return Ok(());
+  };
+  let netze = |key: &str| -> Result<Option<Vec<Netz>>, String> {
+    let Some(wert) = config.get(key) else {
+      return Ok(None);
+    };
+    let wert = wert.as_ref().ok_or(format!("[acl] {} ohne Wert", key))?;
+    let netze = wert
+      .split(',')
+      .filter(|netz| !netz.trim().is_empty())
+      .map(|netz| Netz::from_str(netz).ok_or(format!("[acl] {}: ungültiges Netz {}", key, netz)))
+      .collect::<Result<Vec<Netz>, String>>()?;
+    info!("Zugriffskontrolle {}: {:?}", key, netze);
+    Ok(Some(netze))
+  };
+  *REGELN.lock().unwrap() = Zugriffsregeln {
+    command: netze("command")?,
+    power: netze("power")?,
+    sm: netze("sm")?,
+  };
+  Ok(())
+}
@@ -0,0 +1,128 @@
+//! Namen (Alias) für GL Adressen
+//! In SET und GET GL Kommandos kann anstelle der Adresse ein Name verwendet werden:
+//! SET <bus> GL <name> <drivemode> <V> <V_max> <f0> . . <fn>
+//! Namen stammen aus:
+//! - Roster im Konfigfile (siehe srcp_roster), bleiben immer erhalten
+//! - INIT Parameter der GL (Lokname bei MFX, optionaler Name bei allen andern Protokollen), werden
+//!   aus INFO 101 übernommen und mit INFO 102 wieder entfernt
+//!
+//! Namen dürfen keine Zahlen sein und werden, wie alle SRCP Kommandos, ohne Berücksichtigung der
+//! Gross- / Kleinschreibung verglichen.
+
+use std::{collections::HashMap, sync::Mutex};
+
+use log::info;
+
+use crate::srcp_server_types::{SRCPMessage, SRCPMessageDevice, SRCPMessageID, SRCPMessageType};
+
+/// Ein Name einer GL
+struct Alias {
+  /// GL Adresse
+  adr: u32,
+  /// true: aus Roster, wird nie entfernt
+  roster: bool,
+}
+
+/// Alle Namen, Key ist Bus und Name in Grossbuchstaben
+static ALIAS: Mutex<Option<HashMap<(usize, String), Alias>>> = Mutex::new(None);
+
+/// Ersten Parameter nach den zwingenden INIT Parametern in INFO 101 GL
+/// <adr> <protocol> <protocolversion> <decoderspeedsteps> <numberofdecoderfunctions>
+const INFO_GL_INIT_PARAMETER: usize = 5;
+
+/// Namen für eine GL setzen
+/// # Arguments
+/// * bus - SRCP Bus der GL
+/// * name - Name der GL
+/// * adr - Adresse der GL
+/// * roster - true: Name aus Roster, wird nie entfernt
+pub fn set(bus: usize, name: &str, adr: u32, roster: bool) {
+  let mut guard = ALIAS.lock().unwrap();
+  let alias = guard.get_or_insert_with(HashMap::new);
+  let key = (bus, name.to_uppercase());
+  //Namen aus dem Roster werden nicht durch INIT Parameter überschrieben
+  if alias.get(&key).is_some_and(|alias| alias.roster && !roster) {
+    return;
+  }
+  if alias
+    .insert(key, Alias { adr, roster })
+    .is_none_or(|alt| alt.adr != adr)
+  {
+    info!("Alias GL {}:{} = {}", bus, adr, name);
+  }
+}
+
+/// Alle nicht aus dem Roster stammenden Namen einer GL entfernen
+/// # Arguments
+/// * bus - SRCP Bus der GL
+/// * adr - Adresse der GL
+fn remove(bus: usize, adr: u32) {
+  if let Some(alias) = ALIAS.lock().unwrap().as_mut() {
+    alias.retain(|(alias_bus, _), alias| (*alias_bus != bus) || (alias.adr != adr) || alias.roster);
+  }
+}
+
+/// Auswertung aller INFO Messages der SRCP Server.
+/// INFO 101 GL: Name aus INIT Parametern übernehmen, das ist der erste Parameter nach den zwingenden
+/// INIT Parametern, der keine Zahl ist.
+/// INFO 102 GL: Namen entfernen.
+/// # Arguments
+/// * msg - Empfangene Message eines SRCP Servers
+pub fn eval_info(msg: &SRCPMessage) {
+  if msg.device != SRCPMessageDevice::GL {
+    return;
+  }
+  let SRCPMessageID::Info { info_code } = &msg.message_id else {
+    return;
+  };
+  let Some(adr) = msg.get_adr() else {
+    return;
+  };
+  match info_code.as_str() {
+    "101" => {
+      //Bei erneutem INIT gilt nur noch der neue Name
+      remove(msg.bus, adr);
+      if let Some(name) = msg
+        .parameter
+        .iter()
+        .skip(INFO_GL_INIT_PARAMETER)
+        .find(|para| !para.is_empty() && para.parse::<u32>().is_err())
+      {
+        set(msg.bus, name, adr, false);
+      }
+    }
+    "102" => remove(msg.bus, adr),
+    _ => {}
+  }
+}
+
+/// Name anstelle der Adresse in SET und GET GL Kommando durch die Adresse ersetzen.
+/// Unbekannte Namen werden nicht ersetzt, das Kommando wird dann durch den SRCP Server abgelehnt.
+/// # Arguments
+/// * cmd - Empfangenes Kommando
+pub fn aufloesen(mut cmd: SRCPMessage) -> SRCPMessage {
+  if (cmd.device != SRCPMessageDevice::GL) || cmd.get_adr().is_some() {
+    return cmd;
+  }
+  if !matches!(
+    cmd.message_id,
+    SRCPMessageID::Command {
+      msg_type: SRCPMessageType::SET | SRCPMessageType::GET
+    }
+  ) {
+    return cmd;
+  }
+  let Some(name) = cmd.parameter.first() else {
+    return cmd;
+  };
+  let adr = ALIAS
+    .lock()
+    .unwrap()
+    .as_ref()
+    .and_then(|alias| alias.get(&(cmd.bus, name.to_uppercase())))
+    .map(|alias| alias.adr);
+  if let Some(adr) = adr {
+    cmd.parameter[0] = adr.to_string();
+  }
+  cmd
+}
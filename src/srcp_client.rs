@@ -0,0 +1,111 @@
+//! SRCP Client für Verbindungen zu einem laufenden srcpd auf localhost
+//! - Command Mode Session eröffnen (für Replay, siehe srcp_journal)
+//! - Einzelnes Kommando senden und Antwort ausgeben, z.B. für Shell Scripts oder cron:
+//!   srcpd -f configfile --send "SET 1 POWER OFF"
+//!   Antworten werden ohne Timestamp auf stdout ausgegeben. Bei einer ERROR Antwort wird Err geliefert.
+
+use std::{
+  io::{BufRead, BufReader, ErrorKind, Write},
+  net::TcpStream,
+  time::Duration,
+};
+
+use log::info;
+
+use crate::srcp::{CMD_SM_TIMEOUT, SRCP_VERSION};
+
+/// Nach der ersten Antwort wird so lange auf weitere Antworten (z.B. GET <bus> GL 0) gewartet
+const WEITERE_ANTWORTEN_TIMEOUT: Duration = Duration::from_millis(300);
+
+/// Eine Zeile einer SRCP Verbindung lesen
+/// # Arguments
+/// * reader - Reader der SRCP Verbindung
+pub fn read_srcp_line(reader: &mut BufReader<TcpStream>) -> Result<String, String> {
+  let mut line = String::new();
+  match reader.read_line(&mut line) {
+    Ok(0) | Err(_) => Err("SRCP Verbindung unterbrochen".to_string()),
+    Ok(_) => Ok(line.trim_end().to_string()),
+  }
+}
+
+/// Zeile an SRCP Verbindung senden und Antwort lesen
+/// # Arguments
+/// * reader - Reader der SRCP Verbindung
+/// * line - Zu sendende Zeile, ohne \n
+pub fn send_srcp_line(reader: &mut BufReader<TcpStream>, line: &str) -> Result<String, String> {
+  reader
+    .get_mut()
+    .write_all(format!("{}\n", line).as_bytes())
+    .or(Err("SRCP write Error"))?;
+  read_srcp_line(reader)
+}
+
+/// Neue SRCP Command Mode Session zum srcpd eröffnen
+/// # Arguments
+/// * port - TCP Port des srcpd auf localhost
+pub fn neue_session(port: u16) -> Result<BufReader<TcpStream>, String> {
+  let stream = TcpStream::connect(("127.0.0.1", port))
+    .or(Err(format!("srcpd auf Port {} nicht erreichbar", port)))?;
+  let mut reader = BufReader::new(stream);
+  //Willkommensmessage
+  read_srcp_line(&mut reader)?;
+  //Aktuelle Version verlangen damit auch Kommandos für Erweiterungen akzeptiert werden
+  send_srcp_line(
+    &mut reader,
+    format!("SET PROTOCOL SRCP {}", SRCP_VERSION).as_str(),
+  )?;
+  send_srcp_line(&mut reader, "SET CONNECTIONMODE SRCP COMMAND")?;
+  let go = send_srcp_line(&mut reader, "GO")?;
+  info!("Neue SRCP Session: {}", go);
+  Ok(reader)
+}
+
+/// Liefert true wenn die Antwort (ohne Timestamp) ein SRCP ERROR ist, das sind alle Codes ab 400
+/// # Arguments
+/// * antwort - Antwort ohne Timestamp
+fn is_error(antwort: &str) -> bool {
+  antwort
+    .split_whitespace()
+    .next()
+    .and_then(|code| code.parse::<u32>().ok())
+    .is_some_and(|code| code >= 400)
+}
+
+/// Ein Kommando an einen laufenden srcpd senden und alle Antworten ohne Timestamp auf stdout ausgeben.
+/// Liefert Err wenn der srcpd nicht erreichbar ist oder mit ERROR antwortet.
+/// # Arguments
+/// * cmd - Das Kommando, z.B. "SET 1 POWER ON"
+/// * port - TCP Port des srcpd auf localhost
+pub fn send(cmd: &str, port: u16) -> Result<(), String> {
+  let mut reader = neue_session(port)?;
+  //SM Kommandos werden erst mit Vorliegen des Resultates beantwortet
+  reader
+    .get_ref()
+    .set_read_timeout(Some(CMD_SM_TIMEOUT + WEITERE_ANTWORTEN_TIMEOUT))
+    .or(Err("SRCP set_read_timeout Error"))?;
+  let mut antwort = send_srcp_line(&mut reader, cmd)?;
+  //Mehrzeilige Antworten: weitere Zeilen bis keine mehr kommen
+  reader
+    .get_ref()
+    .set_read_timeout(Some(WEITERE_ANTWORTEN_TIMEOUT))
+    .or(Err("SRCP set_read_timeout Error"))?;
+  let mut error = false;
+  loop {
+    let ohne_timestamp = antwort
+      .split_once(' ')
+      .map_or(antwort.as_str(), |(_, msg)| msg);
+    println!("{}", ohne_timestamp);
+    error |= is_error(ohne_timestamp);
+    let mut line = String::new();
+    match reader.read_line(&mut line) {
+      Ok(0) => break,
+      Ok(_) => antwort = line.trim_end().to_string(),
+      Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => break,
+      Err(_) => return Err("SRCP Verbindung unterbrochen".to_string()),
+    }
+  }
+  if error {
+    return Err(format!("srcpd Error Antwort auf {}", cmd));
+  }
+  Ok(())
+}
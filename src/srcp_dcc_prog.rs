@@ -1,21 +1,279 @@
 use std::{
-  sync::mpsc::{Receiver, Sender},
+  fs::{File, OpenOptions},
+  io::Read,
+  os::{fd::AsRawFd, unix::fs::OpenOptionsExt},
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc::{Receiver, TryRecvError},
+    Arc,
+  },
   thread,
   time::{Duration, Instant},
 };
 
-use gpio_cdev::LineHandle;
+use gpio_cdev::{Chip, LineHandle, LineRequestFlags};
+use lazy_static::lazy_static;
 use log::{error, debug, warn, info};
+use nix::libc;
 
-use crate::srcp_protocol_ddl::{SmReadWrite, SmReadWriteType};
+use crate::{
+  srcp_dekoder_ident::{DekoderIdent, SM_TYPE_IDENT},
+  srcp_hardware,
+  srcp_protocol_ddl::{SmReadWrite, SmReadWriteType},
+  srcp_server_i2c::I2cBus,
+  srcp_server_types::ThreadSender,
+};
 
 /// SRCP Type für CV Byte Zugriff
 pub static DCC_SM_TYPE_CV: &str = "CV";
 pub static DCC_SM_TYPE_CVBIT: &str = "CVBIT";
+/// SRCP Type für Auslesen eines ganzen CV Bereiches (Scan)
+pub static DCC_SM_TYPE_CVSCAN: &str = "CVSCAN";
+/// Default CV Bereich für CVSCAN, wenn keiner angegeben ist (erste und letzte CV = 0)
+const CV_SCAN_DEFAULT: (u32, u32) = (1, 256);
+/// Max. CV Nummer
+const CV_MAX: u32 = 1024;
+/// CV mit Dekoderversion
+const CV_VERSION: u32 = 7;
+/// CV mit NMRA Herstellerkennung
+const CV_HERSTELLER: u32 = 8;
+/// CV mit Dekoderkonfiguration
+const CV_KONFIG: u32 = 29;
 
 /// Timeout für Quittierungsimpuls vom Dekoder, 100ms mit Reserve weil Timeout mit versenden startet,
 /// 5 * Prog Befehl senden dauert auch ca. 60 ms.
 const DEC_ACK_TIMEOUT: Duration = Duration::from_millis(200);
+/// Input Prog Ack Signal GPIO 22 (= Pin 15, RI von RS232)
+const GPIO_PROG_ACK: u32 = 22;
+/// ADS1115 Register: Wandlungsresultat und Konfiguration
+const ADS1115_CONVERSION: u8 = 0x00;
+const ADS1115_CONFIG: u8 = 0x01;
+/// ADS1115 Konfiguration ohne Kanal (MUX): Messbereich +-2.048V, Dauerwandlung, 860 SPS, Komparator aus
+const ADS1115_CONFIG_BASIS: u16 = 0x0400 | 0x00E0 | 0x0003;
+/// ADS1115 Auflösung bei Messbereich +-2.048V: 62.5uV pro Bit -> 16 Bit pro mV
+const ADS1115_BIT_PRO_MV: i32 = 16;
+/// Max. Anzahl Wiederholungen beim Lesen des ADS1115 bei I2C Fehlern
+const ADS1115_READ_VERSUCHE: u32 = 3;
+
+//Wegen V1 und 2 zwei Instanzen, beide brauchen ACK GPIO Input -> wird einmal hier erstellt.
+lazy_static! {
+  static ref GPIO_PROG_ACK_LINE_HANDLE: LineHandle = Chip::new(srcp_hardware::get_gpiochip())
+    .expect("GPIO Chip konnte nicht geöffnet werden")
+    .get_line(GPIO_PROG_ACK)
+    .expect("GPIO_PROG_ACK konnte nicht geöffnet werden")
+    .request(LineRequestFlags::INPUT, 0, "input_dcc_prog_ack")
+    .expect("GPIO_PROG_ACK konnte nicht als Input geöffnet werden");
+}
+
+/// Erkennung des Quittierungsimpulses (Stromimpuls min. 60mA während 6ms) eines Dekoders auf dem Programmiergleis
+pub trait ProgAck: Send {
+  /// Wird vor jedem Prog. Befehl aufgerufen, z.B. um den Ruhestrom zu messen oder alte Meldungen zu verwerfen.
+  fn start(&mut self) {}
+  /// Liefert true wenn aktuell ein Quittierungsimpuls erkannt wird.
+  /// Wird während dem Warten auf die Quittierung alle 0.5ms aufgerufen.
+  fn is_ack(&mut self) -> bool;
+}
+
+/// Quittierung über einfachen Stromimpulsdetektor an GPIO_PROG_ACK
+struct ProgAckGpio {
+  gpio_prog_ack: &'static LineHandle,
+}
+impl ProgAck for ProgAckGpio {
+  fn is_ack(&mut self) -> bool {
+    self.gpio_prog_ack.get_value().unwrap() == 1
+  }
+}
+
+/// Quittierung über Strommessung (Spannung an Shunt) mit I2C ADC ADS1115.
+/// Quittierung wenn der Strom um "schwelle_mv" über dem vor dem Befehl gemessenen Ruhestrom liegt.
+struct ProgAckAdc {
+  i2c: I2cBus,
+  /// I2C Adresse ADS1115
+  adresse: u16,
+  /// Schwelle in ADC Bits über dem Ruhestrom
+  schwelle: i32,
+  /// Vor dem Befehl gemessener Ruhestrom
+  ruhestrom: i32,
+}
+impl ProgAckAdc {
+  /// ADS1115 für Dauerwandlung des Kanals konfigurieren
+  /// # Arguments
+  /// * port - I2C Device, z.B. /dev/i2c-1
+  /// * adresse - I2C Adresse ADS1115
+  /// * kanal - ADC Eingang 0..3 (gegen GND)
+  /// * schwelle_mv - Schwelle in mV über dem Ruhestrom für Quittierung
+  fn open(port: &str, adresse: u16, kanal: u8, schwelle_mv: u32) -> Result<ProgAckAdc, String> {
+    let mut i2c = I2cBus::open(port)?;
+    //MUX: Eingang gegen GND
+    let config = ADS1115_CONFIG_BASIS | ((0x4 | kanal as u16) << 12);
+    i2c.write(
+      adresse,
+      &[ADS1115_CONFIG, (config >> 8) as u8, config as u8],
+    )?;
+    //Alle folgenden Lesezugriffe liefern das Wandlungsresultat
+    i2c.write(adresse, &[ADS1115_CONVERSION])?;
+    Ok(ProgAckAdc {
+      i2c,
+      adresse,
+      schwelle: schwelle_mv as i32 * ADS1115_BIT_PRO_MV,
+      ruhestrom: 0,
+    })
+  }
+
+  /// Letztes Wandlungsresultat lesen, bei wiederholten I2C Fehlern None
+  fn read(&mut self) -> Option<i32> {
+    let mut daten = [0_u8; 2];
+    for _ in 0..ADS1115_READ_VERSUCHE {
+      match self.i2c.read(self.adresse, &mut daten) {
+        Ok(()) => return Some(i16::from_be_bytes(daten) as i32),
+        Err(msg) => warn!("DccProgThread ADC: {}", msg),
+      }
+    }
+    None
+  }
+}
+impl ProgAck for ProgAckAdc {
+  fn start(&mut self) {
+    self.ruhestrom = self.read().unwrap_or(0);
+  }
+  fn is_ack(&mut self) -> bool {
+    self
+      .read()
+      .is_some_and(|strom| strom - self.ruhestrom >= self.schwelle)
+  }
+}
+
+/// Quittierung über externen Detektor (z.B. Mikrocontroller) an einer seriellen Schnittstelle.
+/// Jedes empfangene Zeichen gilt als Quittierung.
+struct ProgAckUart {
+  uart: File,
+}
+impl ProgAckUart {
+  /// Serielle Schnittstelle nicht blockierend öffnen, 8N1, keine Flusskontrolle
+  /// # Arguments
+  /// * port - Serielles Device, z.B. /dev/ttyAMA0
+  /// * baudrate - Baudrate
+  fn open(port: &str, baudrate: u32) -> Result<ProgAckUart, String> {
+    let speed = match baudrate {
+      9600 => libc::B9600,
+      19200 => libc::B19200,
+      38400 => libc::B38400,
+      57600 => libc::B57600,
+      115200 => libc::B115200,
+      _ => {
+        return Err(format!(
+          "{}: nicht unterstützte Baudrate {}",
+          port, baudrate
+        ))
+      }
+    };
+    let uart = OpenOptions::new()
+      .read(true)
+      .write(true)
+      .custom_flags(libc::O_NOCTTY | libc::O_NONBLOCK)
+      .open(port)
+      .map_err(|err| format!("{} {}", port, err))?;
+    let result = unsafe {
+      let mut termios: libc::termios = std::mem::zeroed();
+      if libc::tcgetattr(uart.as_raw_fd(), &mut termios) < 0 {
+        -1
+      } else {
+        libc::cfmakeraw(&mut termios);
+        termios.c_cflag |= libc::CLOCAL | libc::CREAD;
+        libc::cfsetspeed(&mut termios, speed);
+        libc::tcsetattr(uart.as_raw_fd(), libc::TCSANOW, &termios)
+      }
+    };
+    if result < 0 {
+      return Err(format!("{}: {}", port, std::io::Error::last_os_error()));
+    }
+    Ok(ProgAckUart { uart })
+  }
+}
+impl ProgAck for ProgAckUart {
+  fn start(&mut self) {
+    //Alte, nicht zu diesem Befehl gehörende Meldungen verwerfen
+    unsafe {
+      libc::tcflush(self.uart.as_raw_fd(), libc::TCIFLUSH);
+    }
+  }
+  fn is_ack(&mut self) -> bool {
+    let mut daten = [0_u8; 16];
+    matches!(self.uart.read(&mut daten), Ok(anzahl) if anzahl > 0)
+  }
+}
+
+/// Konfiguration der Quittierungserkennung Programmiergleis (prog_ack)
+#[derive(Clone, Debug, PartialEq)]
+pub enum ProgAckKonfig {
+  /// Einfacher Stromimpulsdetektor an GPIO_PROG_ACK
+  Gpio,
+  /// Strommessung über ADS1115: I2C Device, I2C Adresse, Kanal, Schwelle in mV über Ruhestrom
+  Adc(String, u16, u8, u32),
+  /// Externer Detektor an serieller Schnittstelle: Device, Baudrate
+  Uart(String, u32),
+}
+impl ProgAckKonfig {
+  /// Konfiguration aus Konfigfile Wert lesen.
+  /// Format: "gpio" | "adc <i2c device> <adresse> <kanal> <schwelle mV>" | "uart <device> <baudrate>"
+  /// # Arguments
+  /// * value - Wert aus Konfigfile
+  pub fn from_config(value: &str) -> Result<ProgAckKonfig, String> {
+    let para: Vec<&str> = value.split_whitespace().collect();
+    match para.as_slice() {
+      ["gpio"] => Ok(ProgAckKonfig::Gpio),
+      ["adc", port, adresse, kanal, schwelle_mv] => {
+        let adresse = match adresse.strip_prefix("0x") {
+          Some(hex) => u16::from_str_radix(hex, 16).ok(),
+          None => adresse.parse::<u16>().ok(),
+        }
+        .filter(|adresse| *adresse < 0x80)
+        .ok_or("DDL: prog_ack adc: I2C Adresse ungültig")?;
+        let kanal = kanal
+          .parse::<u8>()
+          .ok()
+          .filter(|kanal| *kanal <= 3)
+          .ok_or("DDL: prog_ack adc: Kanal muss 0..3 sein")?;
+        let schwelle_mv = schwelle_mv
+          .parse::<u32>()
+          .ok()
+          .filter(|schwelle_mv| (1..=2048).contains(schwelle_mv))
+          .ok_or("DDL: prog_ack adc: Schwelle muss 1..2048 mV sein")?;
+        Ok(ProgAckKonfig::Adc(
+          port.to_string(),
+          adresse,
+          kanal,
+          schwelle_mv,
+        ))
+      }
+      ["uart", port, baudrate] => Ok(ProgAckKonfig::Uart(
+        port.to_string(),
+        baudrate
+          .parse::<u32>()
+          .ok()
+          .ok_or("DDL: prog_ack uart: Baudrate ungültig")?,
+      )),
+      _ => Err(format!("DDL: prog_ack ungültig: {}", value)),
+    }
+  }
+
+  /// Quittierungserkennung gemäss Konfiguration öffnen
+  pub fn open(&self) -> Result<Box<dyn ProgAck>, String> {
+    Ok(match self {
+      ProgAckKonfig::Gpio => Box::new(ProgAckGpio {
+        gpio_prog_ack: &GPIO_PROG_ACK_LINE_HANDLE,
+      }),
+      ProgAckKonfig::Adc(port, adresse, kanal, schwelle_mv) => {
+        Box::new(ProgAckAdc::open(port, *adresse, *kanal, *schwelle_mv)?)
+      }
+      ProgAckKonfig::Uart(port, baudrate) => Box::new(ProgAckUart::open(port, *baudrate)?),
+    })
+  }
+}
+
+/// Max. Dauer eines Auftrages (bei CVSCAN pro CV). Kürzer als das Timeout eines SRCP SM Kommandos,
+/// damit der Client das Timeout als Antwort auf sein Kommando erhält.
+const SM_AUFTRAG_TIMEOUT: Duration = Duration::from_millis(9000);
 
 /// Read / Write für DccCvTel
 #[derive(PartialEq, Clone, Debug)]
@@ -43,6 +301,17 @@ pub struct DccCvTel {
   pub trigger: bool,
 }
 
+/// Zustand eines CV Scans (GET SM <adr> CVSCAN <von> <bis>)
+#[derive(Clone, Debug)]
+struct CvScan {
+  /// Auslösender Auftrag, Parameter sind erste und letzte CV
+  auftrag: SmReadWrite,
+  /// Nächste auszulesende CV
+  cv_next: u32,
+  /// Anzahl bisher erfolgreich ausgelesener CV's
+  anz_gelesen: u32,
+}
+
 /// Thread zur Ausführung DCC Dekoder Prog. Read/Write/Verify Befehlen inkl. Rückmeldungen Prog.Gleis.
 /// Abarbeitung der Aufträge.
 /// - Aufträge werden empfangen aus DDL Thread, DCC Protokoll
@@ -51,14 +320,22 @@ pub struct DccCvTel {
 /// - Antworten werden zurück gesendet.
 ///   Es erfolgt immer eine Antwort auf eine Anfrage, im Fehlerfalle "Error".
 pub struct DccProgThread {
-  /// GPIO zum Einlesen Quittungsimpuls
-  gpio_prog_ack: &'static LineHandle,
+  /// Erkennung Quittungsimpuls
+  prog_ack: Box<dyn ProgAck>,
   /// Receiver für Aufträge
   rx: Receiver<SmReadWrite>,
   /// Sender für Ergenisse der Aufträge, als Antwort auf "ReadCV"/"WriteCV"/"Verify"
-  tx: Sender<SmReadWrite>,
+  tx: ThreadSender<SmReadWrite>,
   /// Sender für über SPI zu versendende Telegramme
-  tx_tel: Sender<DccCvTel>,
+  tx_tel: ThreadSender<DccCvTel>,
+  /// Aktuell laufender CV Scan
+  cv_scan: Option<CvScan>,
+  /// Abgebrochener CV Scan, kann wieder aufgenommen werden
+  cv_scan_abgebrochen: Option<CvScan>,
+  /// Abbruch aller Aufträge verlangt (TERM SM)
+  abbruch: Arc<AtomicBool>,
+  /// Startzeitpunkt aktueller Auftrag für Timeout
+  auftrag_start: Instant,
 }
 
 impl DccProgThread {
@@ -67,18 +344,29 @@ impl DccProgThread {
   /// * rx - Empfang von Aufträge.
   /// * tx - Sender zum versenden er eingelesen Rückmeldungen als Antwort auf "ReadCA"/"WriteCA"
   /// * tx_tel - Sender zum versenden von auszugebenden Telegrammen
-  /// * ack_line_handle - GPIO Handle über das der Programmier ACK Impuls eingelesen werden kann.
+  /// * prog_ack - Erkennung des Programmier ACK Impulses.
+  /// * abbruch - Wird bei TERM SM gesetzt, alle laufenden und wartenden Aufträge werden abgebrochen.
   pub fn new(
-    rx: Receiver<SmReadWrite>, tx: Sender<SmReadWrite>, tx_tel: Sender<DccCvTel>, ack_line_handle: &'static LineHandle,
+    rx: Receiver<SmReadWrite>, tx: ThreadSender<SmReadWrite>, tx_tel: ThreadSender<DccCvTel>, prog_ack: Box<dyn ProgAck>,
+    abbruch: Arc<AtomicBool>,
   ) -> DccProgThread {
     DccProgThread {
-      gpio_prog_ack: ack_line_handle,
+      prog_ack,
       rx,
       tx,
       tx_tel,
+      cv_scan: None,
+      cv_scan_abgebrochen: None,
+      abbruch,
+      auftrag_start: Instant::now(),
     }
   }
 
+  /// Muss der aktuelle Auftrag abgebrochen werden? (TERM SM oder Timeout)
+  fn is_abbruch(&self) -> bool {
+    self.abbruch.load(Ordering::Relaxed) || (self.auftrag_start.elapsed() > SM_AUFTRAG_TIMEOUT)
+  }
+
   /// Senden eines CV Write/Verify Kommandos.
   /// Wenn "prog_gleis" wird bei Dekoder Quittierung true, sonst false zurück geliefert.
   /// Bei einem Fehler, wenn Quittung bereits vor Befehl ansteht, wird None zurück geliefert
@@ -88,8 +376,12 @@ impl DccProgThread {
   /// * prog_gleis - true wenn Prog Gleis und Dekoder Quittierung erwartet wird.
   fn send_dcc_cv_tel(&mut self, dcc_cv_tel: &DccCvTel, prog_gleis: bool) -> Option<bool> {
     debug!("DccProgThread tx_tel dcc_cv_tel={:?} prog_gleis={}", dcc_cv_tel, prog_gleis);
-    let ack_vorher = self.gpio_prog_ack.get_value().unwrap() == 1;
-    self.tx_tel.send(dcc_cv_tel.clone()).unwrap();
+    self.prog_ack.start();
+    let ack_vorher = self.prog_ack.is_ack();
+    if !self.tx_tel.send(dcc_cv_tel.clone()) {
+      //Ohne Empfänger wird das Telegramm nie gesendet
+      return None;
+    }
     if prog_gleis {
       let mut ack = Some(false);
       //Warten auf Quittierungsimpuls. Dieser sollte nach spätestens 100ms vorhanden sein und min. 5ms lang sein.
@@ -97,7 +389,7 @@ impl DccProgThread {
       while (timeout + DEC_ACK_TIMEOUT) > Instant::now() {
         //Impuls ist sicher 5ms lang, also reicht es, alle 0.5ms zu prüfen
         thread::sleep(Duration::from_micros(500));
-        if self.gpio_prog_ack.get_value().unwrap() == 1 {
+        if self.prog_ack.is_ack() {
           //Immer ganzen Timeout warten auch wenn Impuls erkannt wurde.
           //Grund: Prog. Paket muss 5 mal gesendet werden, Dekoder darf aber nach 2. Paket antworten.
           //Damit kann er in einem 5er Paket zweimal Antworten und es muss vermieden werden, dass
@@ -127,6 +419,9 @@ impl DccProgThread {
   /// # Arguments
   /// * smcmd - Auszuführendes SM Kommando.
   fn execute_sm_cmd_write_ver(&mut self, smcmd: &SmReadWrite) -> Option<bool> {
+    if self.is_abbruch() {
+      return None;
+    }
     let mut result = None;
     //Bei Prog Gleis geht Write und Verify, sonst nur Write
     if smcmd.prog_gleis || matches!(smcmd.val, SmReadWriteType::Write(_)) {
@@ -171,6 +466,10 @@ impl DccProgThread {
   /// * bitnr - Die Bitnr 0 bis 7 des auszulesenden Bits
   /// * trigger - Oszi Trigger?
   fn read_cv_bit(&mut self, adr: u32, cv: u16, bitnr: u8, trigger: bool) -> Option<u8> {
+    if self.is_abbruch() {
+      warn!("DccProgThread read_cv_bit Abbruch. adr={}, CV={}, Bitnr={}", adr, cv, bitnr);
+      return None;
+    }
     //Zuerst Ver Bit mit 0
     let mut dcc_cv_tel: DccCvTel = DccCvTel { adr, dcc_cv_type: DccCvTelType::VerifyBit(false, bitnr), cv, trigger };
     let result_bit0 = self.send_dcc_cv_tel(&dcc_cv_tel, true);
@@ -239,14 +538,226 @@ impl DccProgThread {
     }
   }
 
+  /// CV (Byte oder Bit) schreiben und danach zurücklesen.
+  /// Liefert den zurückgelesenen Wert wenn er mit dem geschriebenen übereinstimmt, sonst Fehler.
+  /// Die Quittierung des Write wird nicht verlangt, da einige Dekoder Write nicht quittieren, massgebend ist der gelesene Wert.
+  /// # Arguments
+  /// * smcmd - Auszuführendes SET SM Kommando.
+  /// * val - Zu schreibender Wert
+  fn write_cv_readback(&mut self, smcmd: &SmReadWrite, val: u32) -> SmReadWriteType {
+    if self.execute_sm_cmd_write_ver(smcmd).is_none() {
+      return SmReadWriteType::ResultErr;
+    }
+    let mut read_cmd = smcmd.clone();
+    read_cmd.val = SmReadWriteType::Read;
+    match self.read_cv(&read_cmd) {
+      Some(gelesen) if gelesen as u32 == val => {
+        info!("DccProgThread write_cv_readback OK. smcmd={:?}", smcmd);
+        SmReadWriteType::ResultOk(gelesen as u32)
+      }
+      gelesen => {
+        warn!("DccProgThread write_cv_readback Error. smcmd={:?}, gelesen={:?}", smcmd, gelesen);
+        SmReadWriteType::ResultErr
+      }
+    }
+  }
+
+  /// Dekoderidentifikation auslesen: Hersteller (CV8), Version (CV7) und aus der Konfiguration (CV29)
+  /// die Protokollfähigkeiten. Liefert None bei Fehler.
+  /// Von CV29 werden nur die benötigten Bits gelesen, damit das Ganze innerhalb Auftragstimeout bleibt.
+  /// # Arguments
+  /// * smcmd - Auszuführendes GET SM IDENT Kommando.
+  fn read_ident(&mut self, smcmd: &SmReadWrite) -> Option<DekoderIdent> {
+    let mut read_cmd = smcmd.clone();
+    read_cmd.sm_type = DCC_SM_TYPE_CV.to_string();
+    read_cmd.para = vec![CV_HERSTELLER];
+    let hersteller_id = self.read_cv(&read_cmd)? as u32;
+    read_cmd.para = vec![CV_VERSION];
+    let version = self.read_cv(&read_cmd)?.to_string();
+    let mut read_konfig_bit = |bitnr: u8| {
+      self
+        .read_cv_bit(smcmd.adr, CV_KONFIG as u16, bitnr, smcmd.trigger)
+        .map(|bit| bit != 0)
+    };
+    let mut faehigkeiten = vec![if read_konfig_bit(1)? { "FS28" } else { "FS14" }.to_string()];
+    for (bitnr, faehigkeit) in [(2, "ANALOG"), (3, "RAILCOM"), (5, "LONGADR")] {
+      if read_konfig_bit(bitnr)? {
+        faehigkeiten.push(faehigkeit.to_string());
+      }
+    }
+    Some(DekoderIdent { hersteller_id, version, protokoll: "NMRA".to_string(), faehigkeiten })
+  }
+
+  /// Ausführung eines CVSCAN Kommandos, liefert die Antwort auf das Kommando.
+  /// - GET: Startet einen neuen Scan von erster bis letzter CV (beide 0: CV1 bis CV256).
+  ///   Antwort ist die Anzahl zu lesender CV's, die Ergebnisse werden danach laufend als INFO versandt.
+  /// - SET mit Value 0: Bricht laufenden Scan ab, er kann später wieder aufgenommen werden.
+  /// - SET mit Value 1: Nimmt abgebrochenen Scan für gleiche Adresse und Bereich wieder auf.
+  /// # Arguments
+  /// * smcmd - Auszuführendes CVSCAN Kommando.
+  fn execute_cv_scan_cmd(&mut self, smcmd: &SmReadWrite) -> SmReadWriteType {
+    let (cv_von, cv_bis) = if (smcmd.para[0] == 0) && (smcmd.para[1] == 0) {
+      CV_SCAN_DEFAULT
+    } else {
+      (smcmd.para[0], smcmd.para[1])
+    };
+    //Auslesen geht nur auf Prog. Gleis
+    if !smcmd.prog_gleis || (cv_von < 1) || (cv_von > cv_bis) || (cv_bis > CV_MAX) {
+      return SmReadWriteType::ResultErr;
+    }
+    //Abbruch und Wiederaufnahme beziehen sich immer auf gleiche Adresse und gleichen Bereich
+    let gleicher_scan =
+      |scan: &CvScan| (scan.auftrag.adr == smcmd.adr) && (scan.auftrag.para == [cv_von, cv_bis]);
+    match smcmd.val {
+      SmReadWriteType::Read if self.cv_scan.is_none() => {
+        let mut auftrag = smcmd.clone();
+        auftrag.para = vec![cv_von, cv_bis];
+        info!(
+          "DccProgThread CV Scan Start adr={}, CV{} bis CV{}",
+          smcmd.adr, cv_von, cv_bis
+        );
+        self.cv_scan = Some(CvScan {
+          auftrag,
+          cv_next: cv_von,
+          anz_gelesen: 0,
+        });
+        self.cv_scan_abgebrochen = None;
+        SmReadWriteType::ResultOk(cv_bis - cv_von + 1)
+      }
+      SmReadWriteType::Write(0) if self.cv_scan.as_ref().is_some_and(gleicher_scan) => {
+        let scan = self.cv_scan.take().unwrap();
+        info!(
+          "DccProgThread CV Scan Abbruch adr={}, nächste CV{}",
+          scan.auftrag.adr, scan.cv_next
+        );
+        self.cv_scan_abgebrochen = Some(scan);
+        SmReadWriteType::ResultOk(0)
+      }
+      SmReadWriteType::Write(1)
+        if self.cv_scan.is_none()
+          && self.cv_scan_abgebrochen.as_ref().is_some_and(gleicher_scan) =>
+      {
+        let mut scan = self.cv_scan_abgebrochen.take().unwrap();
+        info!(
+          "DccProgThread CV Scan Wiederaufnahme adr={}, ab CV{}",
+          scan.auftrag.adr, scan.cv_next
+        );
+        //Antworten gehen ab jetzt an die Session, die wieder aufgenommen hat
+        scan.auftrag.session_id = smcmd.session_id;
+        self.cv_scan = Some(scan);
+        SmReadWriteType::ResultOk(1)
+      }
+      _ => SmReadWriteType::ResultErr,
+    }
+  }
+
+  /// Nächste CV eines laufenden CV Scans auslesen.
+  /// Erfolgreich gelesene CV's werden als INFO "<adr> CV <cv> <value>" versandt,
+  /// am Schluss noch INFO "<adr> CVSCAN <von> <bis> <Anzahl gelesene CV's>".
+  fn execute_cv_scan_next(&mut self) {
+    let Some(mut scan) = self.cv_scan.take() else {
+      return;
+    };
+    let mut smcmd = scan.auftrag.clone();
+    smcmd.sm_type = DCC_SM_TYPE_CV.to_string();
+    smcmd.para = vec![scan.cv_next];
+    self.auftrag_start = Instant::now();
+    if let Some(val) = self.read_cv(&smcmd) {
+      scan.anz_gelesen += 1;
+      smcmd.val = SmReadWriteType::ResultInfo(val as u32);
+      self.tx.send(smcmd);
+    } else {
+      warn!(
+        "DccProgThread CV Scan adr={}, CV{} konnte nicht gelesen werden",
+        scan.auftrag.adr, scan.cv_next
+      );
+    }
+    scan.cv_next += 1;
+    if scan.cv_next > scan.auftrag.para[1] {
+      info!(
+        "DccProgThread CV Scan Ende adr={}, {} CV's gelesen",
+        scan.auftrag.adr, scan.anz_gelesen
+      );
+      let mut ende = scan.auftrag;
+      ende.val = SmReadWriteType::ResultInfo(scan.anz_gelesen);
+      self.tx.send(ende);
+    } else {
+      self.cv_scan = Some(scan);
+    }
+  }
+
+  /// Liefert das Ergebnis eines fehlgeschlagenen Auftrages: Timeout wenn abgebrochen, sonst Fehler.
+  fn get_result_err(&self) -> SmReadWriteType {
+    if self.is_abbruch() {
+      SmReadWriteType::ResultTimeout
+    } else {
+      SmReadWriteType::ResultErr
+    }
+  }
+
   /// Als Thread ausführen
   /// Thread wäre eigentlich für Write und Verify Kommandos nicht notwendig.
   /// Aber für GET schon, da dies mit Verify von einzelnen Bits gemacht werden muss.
   /// Deshalb als Thread und bei allen Befehlen gleich.
+  /// Während einem laufenden CV Scan wird zwischen jeder CV auf neue Aufträge geprüft,
+  /// ausser CVSCAN Abbruch werden dann alle mit Fehler beantwortet.
   pub fn execute(&mut self) {
     loop {
-      let mut smcmd = self.rx.recv().unwrap();
+      if self.abbruch.load(Ordering::Relaxed) {
+        //TERM SM: Laufender CV Scan kann später wieder aufgenommen werden
+        if let Some(scan) = self.cv_scan.take() {
+          self.cv_scan_abgebrochen = Some(scan);
+        }
+        //Alle wartenden Aufträge abbrechen
+        while let Ok(mut smcmd) = self.rx.try_recv() {
+          smcmd.val = SmReadWriteType::ResultTimeout;
+          self.tx.send(smcmd);
+        }
+        info!("DccProgThread alle Aufträge abgebrochen");
+        self.abbruch.store(false, Ordering::Relaxed);
+      }
+      let mut smcmd = if self.cv_scan.is_some() {
+        match self.rx.try_recv() {
+          Ok(smcmd) => smcmd,
+          Err(TryRecvError::Empty) => {
+            self.execute_cv_scan_next();
+            continue;
+          }
+          Err(TryRecvError::Disconnected) => {
+            info!("DccProgThread Auftragskanal geschlossen, beendet");
+            return;
+          }
+        }
+      } else {
+        let Ok(smcmd) = self.rx.recv() else {
+          info!("DccProgThread Auftragskanal geschlossen, beendet");
+          return;
+        };
+        //Während dem Warten war kein Auftrag vorhanden, ein in dieser Zeit verlangter Abbruch (TERM SM)
+        //betrifft keinen Auftrag und darf den neuen nicht abbrechen.
+        self.abbruch.store(false, Ordering::Relaxed);
+        smcmd
+      };
       debug!("DccProgThread neues SM Kommando: {:?}", smcmd);
+      self.auftrag_start = Instant::now();
+      if smcmd.sm_type == DCC_SM_TYPE_CVSCAN {
+        smcmd.val = self.execute_cv_scan_cmd(&smcmd);
+        debug!("DccProgThread Sende Antwort: {:?}", smcmd);
+        self.tx.send(smcmd);
+        continue;
+      }
+      if smcmd.sm_type == SM_TYPE_IDENT {
+        //Auslesen geht nur auf Prog. Gleis und wenn es nicht durch CV Scan belegt ist
+        smcmd.val = match smcmd.val {
+          SmReadWriteType::Read if smcmd.prog_gleis && self.cv_scan.is_none() => {
+            self.read_ident(&smcmd).map_or(self.get_result_err(), SmReadWriteType::ResultIdent)
+          }
+          _ => SmReadWriteType::ResultErr,
+        };
+        debug!("DccProgThread Sende Antwort: {:?}", smcmd);
+        self.tx.send(smcmd);
+        continue;
+      }
       //Default = Fehler
       let mut ans = SmReadWriteType::ResultErr;
       //Gültigkeit der Parameter prüfen
@@ -269,7 +780,8 @@ impl DccProgThread {
           //Value: bei CV 0 bis 255
           val <= 255
         };
-      if para_valid {
+      //Während CV Scan ist das Prog. Gleis belegt
+      if para_valid && self.cv_scan.is_none() {
         match smcmd.val {
           SmReadWriteType::Read => {
             if let Some(val) = self.read_cv(&smcmd) {
@@ -277,6 +789,9 @@ impl DccProgThread {
               ans = SmReadWriteType::ResultOk(val as u32);
             }
           }
+          SmReadWriteType::Write(val) if smcmd.write_verify && smcmd.prog_gleis => {
+            ans = self.write_cv_readback(&smcmd, val);
+          }
           SmReadWriteType::Write(val) | SmReadWriteType::Verify(val) => {
             if let Some(result) = self.execute_sm_cmd_write_ver(&smcmd) {
               if result {
@@ -290,10 +805,13 @@ impl DccProgThread {
           }
         }
       }
+      if matches!(ans, SmReadWriteType::ResultErr) {
+        ans = self.get_result_err();
+      }
       //Antwort zurücksenden
       smcmd.val = ans;
       debug!("DccProgThread Sende Antwort: {:?}", smcmd);
-      self.tx.send(smcmd).unwrap();
+      self.tx.send(smcmd);
     }
   }
 }
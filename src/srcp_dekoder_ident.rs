@@ -0,0 +1,75 @@
+/// SRCP SM Type für Dekoderidentifikation (Hersteller, Version, Fähigkeiten)
+pub static SM_TYPE_IDENT: &str = "IDENT";
+
+/// NMRA Herstellerkennungen (DCC CV8, bei MFX CA Hersteller), Auszug aus der NMRA Liste
+const NMRA_HERSTELLER: [(u32, &str); 33] = [
+  (1, "CML Electronics"),
+  (11, "NCE"),
+  (12, "Wangrow Electronics"),
+  (13, "Public Domain & Do-It-Yourself"),
+  (42, "Digikeijs"),
+  (48, "Hornby"),
+  (62, "Tams Elektronik"),
+  (78, "Train-O-Matic"),
+  (85, "Uhlenbrock"),
+  (97, "Doehler & Haass"),
+  (98, "Harman DCC"),
+  (99, "Lenz Elektronik"),
+  (101, "Bachmann"),
+  (109, "Viessmann"),
+  (113, "QSI"),
+  (115, "Dietz"),
+  (117, "CT Elektronik"),
+  (123, "Massoth"),
+  (127, "Atlas"),
+  (129, "Digitrax"),
+  (131, "Trix / Märklin"),
+  (141, "SoundTraxx"),
+  (143, "MRC"),
+  (145, "Zimo"),
+  (151, "ESU"),
+  (153, "TCS"),
+  (155, "Fleischmann"),
+  (157, "Kühn"),
+  (159, "LGB"),
+  (161, "Roco"),
+  (162, "Piko"),
+  (186, "Brawa"),
+  (238, "NMRA reserviert"),
+];
+
+/// Ergebnis einer Dekoderidentifikation (SM IDENT)
+#[derive(Clone, Debug, PartialEq)]
+pub struct DekoderIdent {
+  /// NMRA Herstellerkennung
+  pub hersteller_id: u32,
+  /// Softwareversion des Dekoders
+  pub version: String,
+  /// Protokoll über das der Dekoder ausgelesen wurde
+  pub protokoll: String,
+  /// Protokollfähigkeiten des Dekoders, z.B. "FS28", "RAILCOM"
+  pub faehigkeiten: Vec<String>,
+}
+
+impl DekoderIdent {
+  /// Liefert den Herstellernamen zur Herstellerkennung, "unbekannt" wenn nicht in der Liste
+  pub fn hersteller_name(&self) -> &'static str {
+    NMRA_HERSTELLER
+      .iter()
+      .find(|(id, _)| *id == self.hersteller_id)
+      .map_or("unbekannt", |(_, name)| name)
+  }
+
+  /// Liefert alle Parameter für die SRCP Antwort:
+  /// <Herstellerkennung> "<Herstellername>" <Version> <Protokoll> [<Fähigkeiten> ...]
+  pub fn get_srcp_parameter(&self) -> Vec<String> {
+    let mut result = vec![
+      self.hersteller_id.to_string(),
+      format!("\"{}\"", self.hersteller_name()),
+      self.version.clone(),
+      self.protokoll.clone(),
+    ];
+    result.extend(self.faehigkeiten.iter().cloned());
+    result
+  }
+}
@@ -1,107 +1,161 @@
 use std::{
+  cell::Cell,
+  sync::{
+    atomic::{AtomicU32, AtomicU64, Ordering},
+    mpsc::{self, SyncSender, TrySendError},
+    Arc,
+  },
   thread,
   time::{Duration, Instant},
 };
 
 use gpio_cdev::{Chip, LineHandle, LineRequestFlags};
-use log::warn;
+use log::{error, warn};
 use spidev::{Spidev, SpidevTransfer};
 
 use crate::{
-  srcp_protocol_ddl::DdlTel, srcp_protocol_ddl::DdlTelRx, srcp_server_types::SRCPMessage,
+  srcp_hardware,
+  srcp_protocol_ddl::DdlTel,
+  srcp_protocol_ddl::DdlTelRx,
+  srcp_protocol_ddl::{DdlProtokolle, HashMapVersion},
+  srcp_server_types::SRCPMessage,
 };
 
-/// Schnittstelle für alle Devices die in einem SRCP DDL Server bearbeitet werden
-pub trait SRCPDeviceDDL {
-  /// Empfangenes Kommando validieren.
-  /// Return true wenn Kommando Ok.
-  /// Sendet die Antwort Message (Ok / Err) an Sender zurück.
-  /// # Arguments
-  /// * cmd_msg - Empfangenes Kommando
-  fn validate_cmd(&self, cmd_msg: &SRCPMessage) -> bool;
-  /// Empfangenes Kommando ausführen und versenden, ggf. interne Daten Updaten für späteren Refresh.
-  /// Das Kommando muss gültig sein (validate_cmd), es wird hier nicht mehr überprüft.
-  /// # Arguments
-  /// * cmd_msg - Empfangenes Kommando
-  /// * power - true wenn Power eingeschaltet, Booster On sind
-  fn execute_cmd(&mut self, cmd_msg: &SRCPMessage, power: bool);
-  /// Refresh Zyklus Telegramm senden (wird nur für GL aufgerufen)
-  fn send_refresh(&mut self) {}
-  /// Muss zyklisch aufgerufen werden. Erlaubt dem Device die Ausführung von
-  /// von neuen Kommando oder refresh unabhängigen Aufgaben.
-  /// Liefert true zurück, wenn durch den Aufruf min. ein DDL Telegramm gesendet wurde, sonst false.
-  /// # Arguments
-  /// * power - true: Power / Booster ist ein, Strom auf den Schienen
-  ///           false: Power / Booster ist aus
-  fn execute(&mut self, _power: bool) -> bool {
-    false
+/// Anzahl Wiederholungsversuche nach einem fehlgeschlagenen SPI Transfer (z.B. EIO)
+const SPI_WIEDERHOLUNGEN: usize = 2;
+/// Pause vor einem Wiederholungsversuch nach fehlgeschlagenem SPI Transfer
+const SPI_WIEDERHOLUNG_PAUSE: Duration = Duration::from_millis(1);
+/// Anzahl Telegramme, die an die Ausgabe separater Programmiergleis übergeben sein können, ohne dass sie
+/// gesendet wurden. Klein, damit CV Telegramme nicht hinter vielen Rücksetzpaketen warten müssen.
+const PROG_AUSGABE_QUEUE: usize = 2;
+
+/// SPI Schreibfehler einer Ausgabe, wird durch das Power Device überwacht
+#[derive(Default)]
+pub struct SpiFehler {
+  ///Anzahl aufeinanderfolgend nicht gesendeter Telegramme (auch nach allen Wiederholungsversuchen)
+  folge: AtomicU32,
+  ///Anzahl fehlgeschlagene SPI Transfers total (inkl. erfolgreich wiederholte)
+  total: AtomicU64,
+}
+impl SpiFehler {
+  /// Liefert die Anzahl aufeinanderfolgend wegen SPI Schreibfehler nicht gesendeter Telegramme
+  /// und die Anzahl fehlgeschlagener SPI Transfers total.
+  pub fn get(&self) -> (u32, u64) {
+    (
+      self.folge.load(Ordering::Relaxed),
+      self.total.load(Ordering::Relaxed),
+    )
   }
-  /// Alle internen zustände als Info Message versenden
+
+  /// Zähler aufeinanderfolgend nicht gesendeter Telegramme zurücksetzen (z.B. nach Power Off).
+  pub fn reset_folge(&self) {
+    self.folge.store(0, Ordering::Relaxed);
+  }
+}
+
+/// Ausgabe von Schienentelegrammen über einen SPI Bus an einen Booster.
+/// Jede Ausgabe hat ihren eigenen Zustand (Pause am Telegrammende, SPI Schreibfehler).
+pub struct DdlAusgabe {
+  ///Geöffnetes SPI interface über das Telegramme zum Booster gesendet werden
+  spidev: Spidev,
+  ///Verlangte Pause am Ende des zuletzt gesendeten Telegrammes
+  letzte_pause_ende: Cell<Duration>,
+  ///SPI Schreibfehler dieser Ausgabe
+  spi_fehler: Arc<SpiFehler>,
+}
+impl DdlAusgabe {
+  /// Neue Ausgabe erstellen
   /// # Arguments
-  /// * session_id - SRCOP Client Session ID an die die Zustände gesendet werden sollen.
-  ///                None -> Info an alle SRCP Clients
-  fn send_all_info(&self, session_id: Option<u32>);
-  /// Abfrage eines Device spezifischen Wertes / Zustandes
-  fn is_dev_spezifisch(&self) -> bool {
-    false
+  /// * spidev - Geöffnetes SPI interface über das Telegramme zum Booster gesendet werden
+  pub fn new(spidev: Spidev) -> DdlAusgabe {
+    DdlAusgabe {
+      spidev,
+      letzte_pause_ende: Cell::new(Duration::ZERO),
+      spi_fehler: Arc::new(SpiFehler::default()),
+    }
   }
+
+  /// Liefert die SPI Schreibfehler dieser Ausgabe zur Überwachung
+  pub fn get_spi_fehler(&self) -> Arc<SpiFehler> {
+    self.spi_fehler.clone()
+  }
+
   /// Senden von Schienentelegrammen über SPI Bus
   /// Das gesendete Teleramm wird aus "ddl_tel" gelöscht.
+  /// Ein fehlgeschlagener SPI Transfer wird bis "SPI_WIEDERHOLUNGEN" mal wiederholt. Gelingt dies nicht,
+  /// wird das Telegramm verworfen und als Fehler gezählt (siehe "get_spi_fehler"), die Reaktion darauf
+  /// (Power Off) erfolgt durch den DDL Server.
   /// # Arguments
-  /// * spidev - Geöffnetes SPI interface über das Telegramme zum Booster gesendet werden können
   /// * ddl_tel - Das zu sendende Telegramm. Es wird hier nur das erste Teleramm gesendet und dann gelöscht.
   /// * trigger_port - Oszi trigger Port aus Konfigfile
-  fn send(spidev: &Option<Spidev>, ddl_tel: &mut DdlTel, trigger_port: Option<u32>)
-  where
-    Self: Sized,
-  {
+  pub fn send(&self, ddl_tel: &mut DdlTel, trigger_port: Option<u32>) {
     assert!(
       ddl_tel.daten.len() > 0,
-      "Aufruf SRCPDeviceDDL::send mit leerem ddl_tel"
+      "Aufruf DdlAusgabe::send mit leerem ddl_tel"
     );
     //Debug Oszi Trigger
     let mut gpio_trigger_out: Option<LineHandle> = None;
     if ddl_tel.trigger && trigger_port.is_some() {
       gpio_trigger_out = Some(
-        Chip::new("/dev/gpiochip0")
-          .expect("/dev/gpiochip0 konnte nicht geöffnet werden")
+        Chip::new(srcp_hardware::get_gpiochip())
+          .expect("GPIO Chip konnte nicht geöffnet werden")
           .get_line(trigger_port.unwrap())
           .expect("GPIO für Oszi Trigger konnte nicht geöffnet werden")
           .request(LineRequestFlags::OUTPUT, 1, "output_trigger_ddl")
           .expect("GPIO für Oszi Trigger konnte nicht als Output geöffnet werden"),
       );
     }
-    //Verlangte Pause nach Telegramme des letzten gesendten Telegrammes. Gilt für alle Instanzen "SRCPDeviceDDL".
-    static mut LETZTE_PAUSE_ENDE: Duration = Duration::ZERO;
-    //Zugriff erfolgt nur aus einem Thread, also OK
-    unsafe {
-      if LETZTE_PAUSE_ENDE > ddl_tel.pause_start {
-        //Es ist noch eine Pause zum Start aufgrund verlangter Pause am Ende des letztens Telegrammes notwendig
-        thread::sleep(LETZTE_PAUSE_ENDE - ddl_tel.pause_start);
-      }
-      LETZTE_PAUSE_ENDE = ddl_tel.pause_ende;
+    //Verlangte Pause am Ende des letzten über diese Ausgabe gesendeten Telegrammes
+    let letzte_pause_ende = self.letzte_pause_ende.replace(ddl_tel.pause_ende);
+    if letzte_pause_ende > ddl_tel.pause_start {
+      //Es ist noch eine Pause zum Start aufgrund verlangter Pause am Ende des letztens Telegrammes notwendig
+      thread::sleep(letzte_pause_ende - ddl_tel.pause_start);
     }
 
-    let mut transfer = match ddl_tel.daten_rx {
+    //Segmente in SPI Bytes auflösen, pro Baudrate ein Transfer
+    let spi_daten = ddl_tel.get_spi_daten(0);
+    let mut transfers: Vec<SpidevTransfer> = match ddl_tel.daten_rx {
       DdlTelRx::SpiRx(ref mut daten_rx) if ddl_tel.daten.len() == 1 => {
-        assert_eq!(
-          ddl_tel.daten[0].len(),
-          daten_rx.len(),
+        assert!(
+          (spi_daten.len() == 1) && (spi_daten[0].1.len() == daten_rx.len()),
           "Bei Verwendung DdlTel::daten_rx muss dessen Länge gleich wie letztes gesendetes Tel sein."
         );
-        SpidevTransfer::read_write(ddl_tel.daten[0].as_slice(), daten_rx.as_mut_slice())
-      }
-      DdlTelRx::SpiRx(_) | DdlTelRx::None | DdlTelRx::Udp => {
-        SpidevTransfer::write(ddl_tel.daten[0].as_slice())
+        vec![SpidevTransfer::read_write(
+          spi_daten[0].1.as_slice(),
+          daten_rx.as_mut_slice(),
+        )]
       }
+      DdlTelRx::SpiRx(_) | DdlTelRx::None | DdlTelRx::Udp => spi_daten
+        .iter()
+        .map(|(_, daten)| SpidevTransfer::write(daten.as_slice()))
+        .collect(),
     };
-    transfer.speed_hz = ddl_tel.hz;
+    for (transfer, (hz, _)) in transfers.iter_mut().zip(spi_daten.iter()) {
+      transfer.speed_hz = *hz;
+    }
+    let mut gesendet = true;
     for _ in 0..ddl_tel.tel_wiederholungen {
-      spidev
-        .as_ref()
-        .unwrap()
-        .transfer(&mut transfer)
-        .expect("DDL SPI write fail");
+      let mut result = self.spidev.transfer_multiple(&mut transfers);
+      for _ in 0..SPI_WIEDERHOLUNGEN {
+        let Err(msg) = &result else {
+          break;
+        };
+        self.spi_fehler.total.fetch_add(1, Ordering::Relaxed);
+        warn!("DDL SPI write fail, Wiederholung: {}", msg);
+        thread::sleep(SPI_WIEDERHOLUNG_PAUSE);
+        result = self.spidev.transfer_multiple(&mut transfers);
+      }
+      if let Err(msg) = result {
+        self.spi_fehler.total.fetch_add(1, Ordering::Relaxed);
+        error!("DDL SPI write fail, Telegramm verworfen: {}", msg);
+        gesendet = false;
+        break;
+      }
+    }
+    if gesendet {
+      self.spi_fehler.folge.store(0, Ordering::Relaxed);
+    } else {
+      self.spi_fehler.folge.fetch_add(1, Ordering::Relaxed);
     }
     //Oszi Trigger zurücknehmen wenn ausgegeben
     if gpio_trigger_out.is_some() {
@@ -112,7 +166,113 @@ pub trait SRCPDeviceDDL {
     //Wann darf das nächste Telegramm (wenn vorhanden) gesendet werden
     ddl_tel.instant_next = Some(Instant::now() + ddl_tel.delay);
   }
+}
 
+/// Ausgabe separater Programmiergleis in einem eigenen Thread mit eigener "DdlAusgabe".
+/// Damit verzögern die Telegramme auf dem Programmiergleis (z.B. dauernde Rücksetzpakete während SM)
+/// die Ausgabe und den Refresh auf dem Hauptgleis nicht.
+pub struct ProgAusgabe {
+  ///Sender der zu sendenden Telegramme mit Oszi Trigger Port zum Ausgabethread
+  tx: SyncSender<(DdlTel, Option<u32>)>,
+}
+impl ProgAusgabe {
+  /// Ausgabethread starten. Er endet, wenn diese "ProgAusgabe" gelöscht wird.
+  /// # Arguments
+  /// * spidev - Geöffnetes SPI interface des separaten Programmiergleis Ausgangs
+  pub fn start(spidev: Spidev) -> ProgAusgabe {
+    let (tx, rx) = mpsc::sync_channel::<(DdlTel, Option<u32>)>(PROG_AUSGABE_QUEUE);
+    thread::Builder::new()
+      .name("DDL_Prog_Ausgabe".to_string())
+      .spawn(move || {
+        let ausgabe = DdlAusgabe::new(spidev);
+        for (mut ddl_tel, trigger_port) in rx {
+          //Programmiergleis Telegramme müssen ohne Pause direkt aufeinander folgen
+          while !ddl_tel.daten.is_empty() {
+            ausgabe.send(&mut ddl_tel, trigger_port);
+          }
+        }
+      })
+      .unwrap();
+    ProgAusgabe { tx }
+  }
+
+  /// Telegramm zur Ausgabe übergeben, blockiert nie.
+  /// Liefert das Telegramm zurück, wenn die Ausgabe noch mit vorherigen Telegrammen belegt ist.
+  /// # Arguments
+  /// * ddl_tel - Das zu sendende Telegramm
+  /// * trigger_port - Oszi trigger Port aus Konfigfile
+  pub fn try_send(&self, ddl_tel: DdlTel, trigger_port: Option<u32>) -> Option<DdlTel> {
+    match self.tx.try_send((ddl_tel, trigger_port)) {
+      Ok(()) => None,
+      Err(TrySendError::Full((ddl_tel, _)) | TrySendError::Disconnected((ddl_tel, _))) => {
+        Some(ddl_tel)
+      }
+    }
+  }
+}
+
+/// Schnittstelle für alle Devices die in einem SRCP DDL Server bearbeitet werden
+pub trait SRCPDeviceDDL {
+  /// Empfangenes Kommando validieren.
+  /// Return true wenn Kommando Ok.
+  /// Sendet die Antwort Message (Ok / Err) an Sender zurück.
+  /// # Arguments
+  /// * cmd_msg - Empfangenes Kommando
+  fn validate_cmd(&self, cmd_msg: &SRCPMessage) -> bool;
+  /// Empfangenes Kommando ausführen und versenden, ggf. interne Daten Updaten für späteren Refresh.
+  /// Das Kommando muss gültig sein (validate_cmd), es wird hier nicht mehr überprüft.
+  /// # Arguments
+  /// * cmd_msg - Empfangenes Kommando
+  /// * power - true wenn Power eingeschaltet, Booster On sind
+  fn execute_cmd(&mut self, cmd_msg: &SRCPMessage, power: bool);
+  /// Refresh Zyklus Telegramm senden (wird nur für GL aufgerufen)
+  /// Liefert true zurück, wenn min. ein DDL Telegramm gesendet wurde, sonst false.
+  fn send_refresh(&mut self) -> bool {
+    false
+  }
+  /// Muss zyklisch aufgerufen werden. Erlaubt dem Device die Ausführung von
+  /// von neuen Kommando oder refresh unabhängigen Aufgaben.
+  /// Liefert true zurück, wenn durch den Aufruf min. ein DDL Telegramm gesendet wurde, sonst false.
+  /// # Arguments
+  /// * power - true: Power / Booster ist ein, Strom auf den Schienen
+  ///           false: Power / Booster ist aus
+  fn execute(&mut self, _power: bool) -> bool {
+    false
+  }
+  /// Liefert den frühesten Zeitpunkt, an dem das Device wieder etwas senden muss (verzögerte Telegramme,
+  /// automatisches Ausschalten, periodische Protokolltelegramme).
+  /// None wenn nichts zeitabhängiges ansteht.
+  /// # Arguments
+  /// * power - true: Power / Booster ist ein, Strom auf den Schienen
+  fn get_naechster_zeitpunkt(&self, _power: bool) -> Option<Instant> {
+    None
+  }
+  /// Alle internen zustände als Info Message versenden
+  /// # Arguments
+  /// * session_id - SRCOP Client Session ID an die die Zustände gesendet werden sollen.
+  ///                None -> Info an alle SRCP Clients
+  fn send_all_info(&self, session_id: Option<u32>);
+  /// Eine SRCP Kommando Session wurde beendet.
+  /// Default: Device hat keine Session abhängigen Zustände.
+  /// # Arguments
+  /// * session_id - Session ID der beendeten Session
+  fn session_end(&mut self, _session_id: u32) {}
+  /// Abfrage eines Device spezifischen Wertes / Zustandes
+  fn is_dev_spezifisch(&self) -> bool {
+    false
+  }
+  /// Ein Protokoll mit allen Versionen zur Laufzeit hinzufügen.
+  /// Default: Device verwendet keine Protokolle.
+  /// # Arguments
+  /// * protokoll - Das neue Protokoll
+  /// * versionen - Alle Versionen des neuen Protokolls
+  fn register_protokoll(&mut self, _protokoll: DdlProtokolle, _versionen: &HashMapVersion) {}
+  /// Ein Protokoll zur Laufzeit entfernen. Alles was mit diesem Protokoll initialisiert wurde
+  /// wird ebenfalls entfernt.
+  /// Default: Device verwendet keine Protokolle.
+  /// # Arguments
+  /// * protokoll - Das zu entfernende Protokoll
+  fn unregister_protokoll(&mut self, _protokoll: DdlProtokolle) {}
   /// Auswerten Oszi Trigger Konfiguration.
   /// Liefert Oszi Triggerport zurück.
   /// # Arguments
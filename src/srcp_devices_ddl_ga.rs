@@ -1,15 +1,20 @@
 use std::{
+  cell::RefCell,
   collections::HashMap,
-  sync::mpsc::Sender,
+  rc::Rc,
   time::{Duration, Instant},
 };
 
-use spidev::Spidev;
+use log::{info, warn};
 
 use crate::{
-  srcp_devices_ddl::SRCPDeviceDDL,
-  srcp_protocol_ddl::{DdlProtokolle, HashMapProtokollVersion},
-  srcp_server_types::{SRCPMessage, SRCPMessageDevice, SRCPMessageID, SRCPMessageType},
+  srcp_devices_ddl::{DdlAusgabe, SRCPDeviceDDL},
+  srcp_protocol_ddl::{
+    DdlProtokoll, DdlProtokolle, DdlTel, HashMapProtokollVersion, HashMapVersion, TelOptions,
+  },
+  srcp_server_types::{
+    SRCPMessage, SRCPMessageDevice, SRCPMessageID, SRCPMessageType, ThreadSender, INFO_ERWEITERUNG,
+  },
 };
 
 ///Verwaltung eines initialisierten GA's
@@ -19,13 +24,13 @@ struct GAInit {
   value: [usize; 2],
   //Gewähltes Protokoll
   protokoll: DdlProtokolle,
-  //Optional: Protokoll Version
-  protokoll_version: Option<String>,
+  //Verwendete Protokoll Version, Default Version wenn bei INIT keine angegeben wurde
+  protokoll_version: String,
   //Oszi Trigger?
   trigger: bool,
 }
 impl GAInit {
-  fn new(protokoll: DdlProtokolle, protokoll_version: Option<String>, trigger: bool) -> GAInit {
+  fn new(protokoll: DdlProtokolle, protokoll_version: String, trigger: bool) -> GAInit {
     GAInit {
       value: [0, 0],
       protokoll,
@@ -35,6 +40,210 @@ impl GAInit {
   }
 }
 
+///Verhalten bei INIT einer GA auf einem Dekoder (4 GA Adressen), auf dem bereits GA's mit anderem Protokoll
+///oder anderer Protokollversion initialisiert sind. Auch erneutes INIT einer GA mit anderem Protokoll.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GAKollision {
+  ///INIT ausführen, Warnung im Log
+  Warnen,
+  ///INIT mit 412 ablehnen
+  Ablehnen,
+}
+
+/// Erweiterung GET <bus> GA 0 DELAYED: Liste aller verzögerten Ein- und Ausschaltungen,
+/// TERM <bus> GA 0 DELAYED: alle verzögerten Ein- und Ausschaltungen verwerfen
+const GA_DELAYED: &str = "DELAYED";
+
+///Grenzen für die Einschaltdauer in ms von "SET <bus> GA <addr> <port> <value> <time>" zum Schutz von Spulenantrieben
+#[derive(Clone, Copy)]
+pub struct GAPulsGrenzen {
+  ///Kürzeste erlaubte Einschaltdauer
+  min: u32,
+  ///Längste erlaubte Einschaltdauer
+  max: u32,
+  ///Einschaltdauer wenn vom Client 0 angegeben wird, None: 0 bleibt dauerhaftes Einschalten
+  default: Option<u32>,
+}
+impl GAPulsGrenzen {
+  /// Konfiguration "<min> <max> [<default>]" auswerten.
+  /// # Arguments
+  /// * name - Name des Konfigparameters, für Fehlermeldungen
+  /// * config - Konfigurationswert
+  fn from(name: &str, config: &str) -> Result<GAPulsGrenzen, String> {
+    let werte = config
+      .split_whitespace()
+      .map(|wert| wert.parse::<u32>())
+      .collect::<Result<Vec<u32>, _>>()
+      .ok()
+      .ok_or(format!(
+        "DDL: {}: Einschaltdauern müssen Zahlen (ms) sein",
+        name
+      ))?;
+    let (min, max, default) = match werte[..] {
+      [min, max] => (min, max, None),
+      [min, max, default] => (min, max, Some(default)),
+      _ => Err(format!("DDL: {}: Format <min> <max> [<default>]", name))?,
+    };
+    if (min == 0)
+      || (min > max)
+      || default.is_some_and(|default| (default < min) || (default > max))
+    {
+      Err(format!(
+        "DDL: {}: es muss 0 < min <= default <= max gelten",
+        name
+      ))?;
+    }
+    Ok(GAPulsGrenzen { min, max, default })
+  }
+}
+
+///Einschaltdauer Konfiguration aller GA's eines Busses
+#[derive(Clone, Default)]
+pub struct GAPulsKonfig {
+  ///Grenzen für alle GA's ohne eigene Konfiguration, None: keine Begrenzung
+  bus: Option<GAPulsGrenzen>,
+  ///Grenzen pro GA, Key Adresse
+  pro_ga: HashMap<u32, GAPulsGrenzen>,
+  ///Spulenschutz: max. Einschaltdauer für alle GA's, gilt auch für GA's mit eigener Konfiguration
+  spule_max: Option<u32>,
+}
+impl GAPulsKonfig {
+  /// Konfiguration aus dem DDL Abschnitt des Konfigfiles erstellen
+  /// ga_pulse = <min> <max> [<default>]: Grenzen in ms für alle GA's
+  /// ga_pulse_<adr> = <min> <max> [<default>]: Grenzen in ms für eine GA
+  /// ga_coil_max = <ms>: max. Einschaltdauer für alle GA's
+  /// # Arguments
+  /// * config_file_bus - Der diesen Bus betreffende Teil des Konfigfiles
+  pub fn from_config(
+    config_file_bus: &HashMap<String, Option<String>>,
+  ) -> Result<GAPulsKonfig, String> {
+    let mut result = GAPulsKonfig::default();
+    for (name, wert) in config_file_bus {
+      if name == "ga_coil_max" {
+        result.spule_max = Some(
+          wert
+            .as_ref()
+            .ok_or("DDL: ga_coil_max ohne Wert")?
+            .parse::<u32>()
+            .ok()
+            .filter(|max| *max > 0)
+            .ok_or("DDL: ga_coil_max muss eine Zahl > 0 (ms) sein")?,
+        );
+        continue;
+      }
+      if (name != "ga_pulse") && !name.starts_with("ga_pulse_") {
+        continue;
+      }
+      let grenzen = GAPulsGrenzen::from(
+        name,
+        wert.as_ref().ok_or(format!("DDL: {} ohne Wert", name))?,
+      )?;
+      match name.strip_prefix("ga_pulse_") {
+        None => result.bus = Some(grenzen),
+        Some(adr) => {
+          let adr = adr
+            .parse::<u32>()
+            .ok()
+            .ok_or(format!("DDL: {}: ungültige GA Adresse", name))?;
+          result.pro_ga.insert(adr, grenzen);
+        }
+      }
+    }
+    Ok(result)
+  }
+
+  /// Liefert die zu verwendende Einschaltdauer, begrenzt durch die Konfiguration.
+  /// Mit Spulenschutz wird auch dauerhaftes Einschalten auf min(default, ga_coil_max) begrenzt.
+  /// Jede Veränderung der vom Client verlangten Zeit wird als Warnung geloggt.
+  /// # Arguments
+  /// * adr - GA Adresse
+  /// * port - GA Port
+  /// * time - Vom Client verlangte Einschaltdauer in ms, <= 0 dauerhaft
+  pub fn get_puls_dauer(&self, adr: u32, port: usize, time: i32) -> i32 {
+    let mut dauer = time;
+    let grenzen = self.pro_ga.get(&adr).or(self.bus.as_ref());
+    if let Some(grenzen) = grenzen {
+      if dauer == 0 {
+        if let Some(default) = grenzen.default {
+          dauer = default as i32;
+        }
+      } else if dauer > 0 {
+        dauer = dauer.clamp(grenzen.min as i32, grenzen.max as i32);
+      }
+    }
+    if let Some(spule_max) = self.spule_max {
+      let spule_max = spule_max as i32;
+      if dauer <= 0 {
+        //Dauerhaftes Einschalten ist genau das, was Spulen zerstört
+        dauer = grenzen
+          .and_then(|grenzen| grenzen.default)
+          .map_or(spule_max, |default| spule_max.min(default as i32));
+      } else if dauer > spule_max {
+        dauer = spule_max;
+      }
+    }
+    if dauer != time {
+      warn!(
+        "GA {} Port {}: Einschaltdauer {} ms auf {} ms begrenzt",
+        adr, port, time, dauer
+      );
+    }
+    dauer
+  }
+}
+
+///Standardabstand zwischen zwei wiederhergestellten GA Ausgängen nach Power On
+const GA_RESTORE_INTERVALL: Duration = Duration::from_millis(200);
+
+///Wiederherstellen GA Zustände nach Power On für Dekoder ohne eigenen Speicher (z.B. Lichtsignale)
+#[derive(Clone)]
+pub struct GARestoreKonfig {
+  ///GA Adressen in der Reihenfolge der Wiederherstellung
+  ga: Vec<u32>,
+  ///Abstand zwischen zwei Ausgaben, damit nicht alle Dekoder gleichzeitig Einschaltstrom ziehen
+  intervall: Duration,
+}
+impl Default for GARestoreKonfig {
+  fn default() -> Self {
+    GARestoreKonfig {
+      ga: Vec::new(),
+      intervall: GA_RESTORE_INTERVALL,
+    }
+  }
+}
+impl GARestoreKonfig {
+  /// Konfiguration aus dem DDL Abschnitt des Konfigfiles erstellen
+  /// ga_restore = <adr>,<adr>,...: GA's, deren eingeschaltete Ausgänge nach Power On erneut gesendet werden
+  /// ga_restore_interval = <ms>: Abstand zwischen zwei Ausgaben
+  /// # Arguments
+  /// * config_file_bus - Der diesen Bus betreffende Teil des Konfigfiles
+  pub fn from_config(
+    config_file_bus: &HashMap<String, Option<String>>,
+  ) -> Result<GARestoreKonfig, String> {
+    let mut result = GARestoreKonfig::default();
+    if let Some(ga) = config_file_bus.get("ga_restore") {
+      result.ga = ga
+        .as_ref()
+        .ok_or("DDL: ga_restore ohne Wert")?
+        .split(',')
+        .map(|adr| adr.trim().parse::<u32>().ok().filter(|adr| *adr > 0))
+        .collect::<Option<Vec<u32>>>()
+        .ok_or("DDL: ga_restore muss eine Liste von GA Adressen sein")?;
+    }
+    if let Some(intervall) = config_file_bus.get("ga_restore_interval") {
+      result.intervall = Duration::from_millis(
+        intervall
+          .as_ref()
+          .ok_or("DDL: ga_restore_interval ohne Wert")?
+          .parse::<u64>()
+          .ok()
+          .ok_or("DDL: ga_restore_interval muss eine Zahl (ms) sein")?,
+      );
+    }
+    Ok(result)
+  }
+}
+
 ///Grund für GA in "GADelay"
 enum GADelayGrund {
   ///Einschaltung war noch nicht möglich weil auf dem gleichen Dekoder noch eine andere Ausgabe aktiv war
@@ -43,6 +252,9 @@ enum GADelayGrund {
   ///Verzögertes Ausschalten
   ///value: wann soll der Ausgang ausgeschaltet werden
   Ausschalten(Instant),
+  ///Wiederherstellen eines eingeschalteten Ausgangs nach Power On
+  ///value: wann soll der Ausgang gesendet werden
+  Wiederherstellen(Instant),
 }
 ///Verwaltung verzögerte Ausgabe und automatisches Ausschalten nach Delay
 struct GADelay {
@@ -51,13 +263,27 @@ struct GADelay {
   ga_delay_grund: GADelayGrund,
 }
 
+///GA Konfiguration eines Busses aus dem Konfigfile
+pub struct DdlGAKonfig {
+  ///Oszi Triggerport
+  pub trigger_port: Option<String>,
+  ///Oszi Trigger Adressen
+  pub trigger_adr: Option<String>,
+  ///Grenzen für die Einschaltdauer der GA's
+  pub puls_konfig: GAPulsKonfig,
+  ///Verhalten bei Adresskollisionen
+  pub kollision: GAKollision,
+  ///Wiederherstellen GA Zustände nach Power On
+  pub restore_konfig: GARestoreKonfig,
+}
+
 pub struct DdlGA<'a> {
   //SRCP Bus auf dem gearbeitet wird
   bus: usize,
   //Sender für SRCP Antworten
-  tx: Sender<SRCPMessage>,
+  tx: ThreadSender<SRCPMessage>,
   //SPI Bus für Ausgabe
-  spidev: &'a Option<Spidev>,
+  ausgabe: &'a DdlAusgabe,
   //Alle vorhandenen Protokollimplementierungen mit allen Versionen
   all_protokolle: HashMapProtokollVersion,
   //Alle initialisierten GA, Key Adresse
@@ -68,6 +294,16 @@ pub struct DdlGA<'a> {
   trigger: Vec<u32>,
   ///Und Port für Oszi trigger
   trigger_port: Option<u32>,
+  ///Grenzen für die Einschaltdauer der GA's
+  puls_konfig: GAPulsKonfig,
+  ///Verhalten bei Adresskollisionen
+  kollision: GAKollision,
+  ///Wiederherstellen GA Zustände nach Power On
+  restore_konfig: GARestoreKonfig,
+  ///Power Zustand beim letzten Aufruf von "execute", zur Erkennung von Power Off
+  power: bool,
+  ///Telegramme die verzögert gesendet werden müssen (z.B. Broadcast aus), Ausgabe in "execute"
+  tel_buffer: Vec<DdlTel>,
 }
 
 impl DdlGA<'_> {
@@ -75,27 +311,30 @@ impl DdlGA<'_> {
   /// # Arguments
   /// * bus - SRCP Bus auf dem dieses Device arbeitet
   /// * tx - Sender für Info Messages / Antworten an SRCP Clients
-  /// * spidev - geöffnetes Spidev zur Ausgabe an Booster
+  /// * ausgabe - SPI Ausgabe an Booster
   /// * all_protokolle - Alle vorhandenen Protokollimplementierungen mit allen Versionen
-  /// * trigger_port - Oszi Triggerport aus Konfigfile
-  /// * trigger_adr - Oszi Trigger Adressen aus Konfigfile
-  pub fn new(
-    bus: usize, tx: Sender<SRCPMessage>, spidev: &Option<Spidev>,
-    all_protokolle: HashMapProtokollVersion, trigger_port: Option<String>,
-    trigger_adr: Option<String>,
-  ) -> DdlGA<'_> {
+  /// * konfig - GA Konfiguration aus Konfigfile
+  pub fn new<'a>(
+    bus: usize, tx: ThreadSender<SRCPMessage>, ausgabe: &'a DdlAusgabe,
+    all_protokolle: HashMapProtokollVersion, konfig: DdlGAKonfig,
+  ) -> DdlGA<'a> {
     let mut result = DdlGA {
       bus,
       tx,
-      spidev,
+      ausgabe,
       all_protokolle,
       all_ga: HashMap::new(),
       all_ga_delay: Vec::new(),
       trigger: vec![],
       trigger_port: None,
+      puls_konfig: konfig.puls_konfig,
+      kollision: konfig.kollision,
+      restore_konfig: konfig.restore_konfig,
+      power: false,
+      tel_buffer: Vec::new(),
     };
-    result.trigger_port = result.eval_trigger_port_config(trigger_port);
-    result.trigger = result.eval_trigger_config(trigger_adr);
+    result.trigger_port = result.eval_trigger_port_config(konfig.trigger_port);
+    result.trigger = result.eval_trigger_config(konfig.trigger_adr);
     result
   }
 
@@ -118,32 +357,27 @@ impl DdlGA<'_> {
             } else {
               self
                 .tx
-                .send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"))
-                .unwrap();
+                .send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"));
             }
           } else {
             self
               .tx
-              .send(SRCPMessage::new_err(cmd_msg, "416", "no data"))
-              .unwrap();
+              .send(SRCPMessage::new_err(cmd_msg, "416", "no data"));
           }
         } else {
           self
             .tx
-            .send(SRCPMessage::new_err(cmd_msg, "416", "no data"))
-            .unwrap();
+            .send(SRCPMessage::new_err(cmd_msg, "416", "no data"));
         }
       } else {
         self
           .tx
-          .send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"))
-          .unwrap();
+          .send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"));
       }
     } else {
       self
         .tx
-        .send(SRCPMessage::new_err(cmd_msg, "419", "list too short"))
-        .unwrap();
+        .send(SRCPMessage::new_err(cmd_msg, "419", "list too short"));
     }
     result
   }
@@ -156,22 +390,61 @@ impl DdlGA<'_> {
   /// * value - GA Port Zustand
   fn send_info_msg(&self, session_id: Option<u32>, adr: u32, port: usize, value: usize) {
     //INFO <bus> GA <adr> <port> <value>
-    self
-      .tx
-      .send(SRCPMessage::new(
-        session_id,
-        self.bus,
-        SRCPMessageID::Info {
-          info_code: "100".to_string(),
-        },
-        SRCPMessageDevice::GA,
-        vec![
-          adr.to_string(),
-          port.to_string(),
-          value.to_string(),
-        ],
-      ))
-      .unwrap();
+    self.tx.send(SRCPMessage::new(
+      session_id,
+      self.bus,
+      SRCPMessageID::Info {
+        info_code: "100".to_string(),
+      },
+      SRCPMessageDevice::GA,
+      vec![adr.to_string(), port.to_string(), value.to_string()],
+    ));
+  }
+
+  /// Initialisierte GA als SRCP Info 101 melden
+  /// INFO <bus> GA <adr> <protokoll> <protokollversion>
+  /// # Arguments
+  /// * session_id - None: an alle SRCP Info Clients, sonst nur an den mit SessionID
+  /// * adr - GA Adresse
+  fn send_info_init(&self, session_id: Option<u32>, adr: u32) {
+    let Some(ga) = self.all_ga.get(&adr) else {
+      return;
+    };
+    let parameter = vec![
+      adr.to_string(),
+      ga.protokoll.to_string(),
+      ga.protokoll_version.clone(),
+    ];
+    self.tx.send(SRCPMessage::new(
+      session_id,
+      self.bus,
+      SRCPMessageID::Info {
+        info_code: "101".to_string(),
+      },
+      SRCPMessageDevice::GA,
+      parameter,
+    ));
+  }
+
+  /// GA entfernen (TERM, Protokoll entfernt), noch ausstehende verzögerte Ausgaben löschen
+  /// und als SRCP Info 102 melden.
+  /// # Arguments
+  /// * adr - GA Adresse
+  fn remove_ga(&mut self, adr: u32) {
+    if self.all_ga.remove(&adr).is_none() {
+      return;
+    }
+    self.all_ga_delay.retain(|ga_delay| ga_delay.adr != adr);
+    //INFO <bus> GA <adr>
+    self.tx.send(SRCPMessage::new(
+      None,
+      self.bus,
+      SRCPMessageID::Info {
+        info_code: "102".to_string(),
+      },
+      SRCPMessageDevice::GA,
+      vec![adr.to_string()],
+    ));
   }
 
   /// GA Port Ausgänge senden und Zustand speichern
@@ -187,36 +460,42 @@ impl DdlGA<'_> {
     let ga = self.all_ga.get_mut(&adr).unwrap();
     //Neuen Zustand speichern
     ga.value[port] = value;
-    //Zum Booster Versenden, wenn keine Version angegeben ist das Default-Protokoll verwenden, ansonsten verlangte Version
-    let prot_ver = if let Some(protokoll_version) = &ga.protokoll_version {
-      protokoll_version
-    }
-    else {
-      let mut def_ver_gefunden: Option<&str> = None;
-      for (ver, prot) in &self.all_protokolle[&ga.protokoll] {
-        if prot.borrow().is_default() {
-          def_ver_gefunden = Some(ver);
-        }
-      }
-      if let Some(def_ver) = def_ver_gefunden {
-        def_ver
-      }
-      else {
-        panic!("Für Protokoll {:?} ist keine Default Version vorhanden.", ga.protokoll);
-      }
-    };
-    let protokoll = self.all_protokolle[&ga.protokoll].get(prot_ver).unwrap();
-    let mut ddl_tel = protokoll.borrow().get_ga_new_tel(adr, ga.trigger);
+    //Zum Booster Versenden mit der bei INIT festgelegten Version
+    let protokoll = &self.all_protokolle[&ga.protokoll][ga.protokoll_version.as_str()];
+    let mut ddl_tel = protokoll
+      .borrow()
+      .get_ga_new_tel(adr, TelOptions::neu(ga.trigger));
     let result = protokoll
       .borrow_mut()
       .get_ga_tel(adr, port, value, timeout, &mut ddl_tel);
     //Es ist nur ein Telegramm, keine Behandlung verzögertes Senden notwendig
-    <DdlGA<'_> as SRCPDeviceDDL>::send(self.spidev, &mut ddl_tel, self.trigger_port);
+    self.ausgabe.send(&mut ddl_tel, self.trigger_port);
     //Alle Info Clients über neuen Zustand Informieren
     self.send_info_msg(None, adr, port, value);
     return result;
   }
 
+  /// Liefert die zu verwendende Version eines Protokolls für INIT GA.
+  /// Ohne Angabe die Default Version, mit Angabe nur wenn diese Version vorhanden ist.
+  /// None wenn das Protokoll oder die verlangte Version nicht vorhanden ist.
+  /// # Arguments
+  /// * protokoll - Protokoll der GA
+  /// * protokoll_version - Bei INIT angegebene Version, None wenn keine angegeben wurde
+  fn get_protokoll_version(
+    &self, protokoll: DdlProtokolle, protokoll_version: Option<&String>,
+  ) -> Option<String> {
+    let versionen = self.all_protokolle.get(&protokoll)?;
+    match protokoll_version {
+      Some(version) => versionen
+        .contains_key(version.as_str())
+        .then(|| version.clone()),
+      None => versionen
+        .iter()
+        .find(|(_, prot)| prot.borrow().is_default())
+        .map(|(version, _)| version.to_string()),
+    }
+  }
+
   /// Stellt fest ob ein Dekoder bereits eine aktive Ausgabe hat für alle GA's, die über SRCP automatisch nach
   /// Timout in SET GA Kommando ausgeschaltet werden.
   /// # Arguments
@@ -226,7 +505,7 @@ impl DdlGA<'_> {
     //Durchsuchen ob für diesen Dekoder eine Ausschaltung hängig ist
     for ga_delay in &self.all_ga_delay {
       match ga_delay.ga_delay_grund {
-        GADelayGrund::Einschalten(_) => (),
+        GADelayGrund::Einschalten(_) | GADelayGrund::Wiederherstellen(_) => (),
         GADelayGrund::Ausschalten(_) => {
           if dek_adr == ((ga_delay.adr - 1) / 4) {
             //Dekoder bereits aktiv
@@ -238,6 +517,185 @@ impl DdlGA<'_> {
     return false;
   }
 
+  /// Liefert alle Protokollimplementierungen (alle Versionen), die einen Broadcast an alle Zubehördekoder unterstützen.
+  fn get_broadcast_protokolle(&self) -> Vec<Rc<RefCell<dyn DdlProtokoll>>> {
+    self
+      .all_protokolle
+      .values()
+      .flat_map(|versionen| versionen.values())
+      .filter(|prot| prot.borrow().ga_broadcast())
+      .cloned()
+      .collect()
+  }
+
+  /// Broadcast "alle Ausgänge aus" an alle Zubehördekoder mit allen Protokollen die das unterstützen senden.
+  /// Alle initialisierten GA's dieser Protokolle sind danach aus, Veränderungen werden als INFO gemeldet.
+  fn send_ga_broadcast_off(&mut self) {
+    for protokoll in self.get_broadcast_protokolle() {
+      let mut ddl_tel = protokoll.borrow().get_ga_new_tel(0, TelOptions::neu(false));
+      protokoll
+        .borrow_mut()
+        .get_ga_broadcast_off_tel(&mut ddl_tel);
+      self.ausgabe.send(&mut ddl_tel, self.trigger_port);
+      //Es können mehrere Telegramme sein, die mit Verzögerung gesendet werden müssen.
+      //Diese werden in "execute" ausgegeben, damit der DDL Thread nicht blockiert wird.
+      if !ddl_tel.daten.is_empty() {
+        self.tel_buffer.push(ddl_tel);
+      }
+    }
+    let mut aus: Vec<(u32, usize)> = Vec::new();
+    for (adr, ga) in self.all_ga.iter_mut() {
+      let broadcast = self
+        .all_protokolle
+        .get(&ga.protokoll)
+        .is_some_and(|versionen| versionen.values().any(|prot| prot.borrow().ga_broadcast()));
+      if broadcast {
+        for port in 0..ga.value.len() {
+          if ga.value[port] != 0 {
+            ga.value[port] = 0;
+            aus.push((*adr, port));
+          }
+        }
+      }
+    }
+    for (adr, port) in aus {
+      //Automatisches Ausschalten ist nicht mehr notwendig
+      self.all_ga_delay.retain(|ga_delay| {
+        !((ga_delay.adr == adr)
+          && (ga_delay.port == port)
+          && matches!(ga_delay.ga_delay_grund, GADelayGrund::Ausschalten(_)))
+      });
+      self.send_info_msg(None, adr, port, 0);
+    }
+  }
+
+  /// Prüft ob das Kommando die Erweiterung "<cmd> <bus> GA 0 DELAYED" ist
+  /// # Arguments
+  /// * cmd_msg - Empfangenes Kommando
+  fn is_delayed_cmd(cmd_msg: &SRCPMessage) -> bool {
+    (cmd_msg.parameter.first().map(String::as_str) == Some("0"))
+      && (cmd_msg.parameter.get(1).map(String::as_str) == Some(GA_DELAYED))
+  }
+
+  /// Alle verzögerten Ein- und Ausschaltungen an eine Session senden
+  /// 190 INFO <bus> GA 0 DELAYED <anzahl>
+  /// und pro Eintrag
+  /// 190 INFO <bus> GA <adr> <port> DELAYED ON <einschaltdauer ms>: Einschalten, wartet auf anderen Ausgang des Dekoders
+  /// 190 INFO <bus> GA <adr> <port> DELAYED OFF <ms bis Ausschalten>: automatisches Ausschalten
+  /// # Arguments
+  /// * session_id - Session an die gesendet wird
+  fn send_info_delayed(&self, session_id: Option<u32>) {
+    let info = |parameter: Vec<String>| {
+      self.tx.send(SRCPMessage::new(
+        session_id,
+        self.bus,
+        SRCPMessageID::Info {
+          info_code: INFO_ERWEITERUNG.to_string(),
+        },
+        SRCPMessageDevice::GA,
+        parameter,
+      ));
+    };
+    info(vec![
+      "0".to_string(),
+      GA_DELAYED.to_string(),
+      self.all_ga_delay.len().to_string(),
+    ]);
+    let jetzt = Instant::now();
+    for ga_delay in &self.all_ga_delay {
+      let (art, ms) = match ga_delay.ga_delay_grund {
+        GADelayGrund::Einschalten(einschaltzeit) => ("ON", einschaltzeit.as_millis()),
+        GADelayGrund::Ausschalten(off_zeit) => {
+          ("OFF", off_zeit.saturating_duration_since(jetzt).as_millis())
+        }
+        GADelayGrund::Wiederherstellen(zeit) => {
+          ("RESTORE", zeit.saturating_duration_since(jetzt).as_millis())
+        }
+      };
+      info(vec![
+        ga_delay.adr.to_string(),
+        ga_delay.port.to_string(),
+        GA_DELAYED.to_string(),
+        art.to_string(),
+        ms.to_string(),
+      ]);
+    }
+  }
+
+  /// Alle verzögerten Ein- und Ausschaltungen verwerfen (TERM <bus> GA 0 DELAYED, Power Off).
+  /// Noch nicht ausgeführte Einschaltungen werden nie mehr ausgeführt, Ausgänge mit ausstehender automatischer
+  /// Ausschaltung werden ohne Telegramm als ausgeschaltet gemeldet, damit nach Power On nichts unerwartet schaltet.
+  /// # Arguments
+  /// * grund - Grund für Log Ausgabe
+  fn flush_delayed(&mut self, grund: &str) {
+    self.tel_buffer.clear();
+    if self.all_ga_delay.is_empty() {
+      return;
+    }
+    info!(
+      "GA: {} verzögerte Ein-/Ausschaltungen verworfen ({})",
+      self.all_ga_delay.len(),
+      grund
+    );
+    for ga_delay in std::mem::take(&mut self.all_ga_delay) {
+      if let GADelayGrund::Ausschalten(_) = ga_delay.ga_delay_grund {
+        if let Some(ga) = self.all_ga.get_mut(&ga_delay.adr) {
+          ga.value[ga_delay.port] = 0;
+          self.send_info_msg(None, ga_delay.adr, ga_delay.port, 0);
+        }
+      }
+    }
+  }
+
+  /// Prüft ob eine neue GA mit bereits initialisierten GA's kollidiert: gleiche Adresse oder gleicher
+  /// Dekoder (4 GA Adressen) mit anderem Protokoll oder anderer Protokollversion.
+  /// Jede Kollision wird als Warnung geloggt.
+  /// # Arguments
+  /// * adr - Adresse der neuen GA
+  /// * protokoll - Protokoll der neuen GA
+  /// * protokoll_version - Protokollversion der neuen GA
+  fn is_kollision(&self, adr: u32, protokoll: DdlProtokolle, protokoll_version: &str) -> bool {
+    let mut result = false;
+    for (ga_adr, ga) in &self.all_ga {
+      if (((ga_adr - 1) / 4) == ((adr - 1) / 4))
+        && ((ga.protokoll != protokoll) || (ga.protokoll_version != protokoll_version))
+      {
+        warn!(
+          "DDL: INIT GA {} {} {} kollidiert mit GA {} {} {}",
+          adr,
+          protokoll.to_string(),
+          protokoll_version,
+          ga_adr,
+          ga.protokoll.to_string(),
+          ga.protokoll_version
+        );
+        result = true;
+      }
+    }
+    result
+  }
+
+  /// Nach Power On die eingeschalteten Ausgänge aller konfigurierten GA's gestaffelt zum erneuten Senden
+  /// in die Verwaltung verzögerter Ausgaben übernehmen.
+  fn restore_nach_power_on(&mut self) {
+    let mut zeitpunkt = Instant::now();
+    for &adr in &self.restore_konfig.ga {
+      let Some(ga) = self.all_ga.get(&adr) else {
+        continue;
+      };
+      for port in 0..ga.value.len() {
+        if ga.value[port] != 0 {
+          self.all_ga_delay.push(GADelay {
+            adr,
+            port,
+            ga_delay_grund: GADelayGrund::Wiederherstellen(zeitpunkt),
+          });
+          zeitpunkt += self.restore_konfig.intervall;
+        }
+      }
+    }
+  }
+
   /// GA einschalten mit Timeout für automatische Ausschaltung
   /// # Arguments
   /// * adr - GA Adresse
@@ -270,131 +728,142 @@ impl SRCPDeviceDDL for DdlGA<'_> {
         SRCPMessageType::INIT => {
           //Format ist INIT <bus> GA <addr> <protocol> <optional further parameters>
           //Zwei Parameter müssen vorhanden sein: <addr> <protocol>
-          //<optional further parameters> für Protokoll "N" ist "protocolversion".
-          // "1" = GA "Einfache Zubehördecoder", Default wenn keine Angabe
+          //<optional further parameters> ist "protocolversion", z.B. für Protokoll "N":
+          // "1" = GA "Einfache Zubehördecoder"
           // "2" = GA "Erweiterte Zubehördecoder"
+          //Ohne Angabe wird die Default Version des Protokolls verwendet.
           if cmd_msg.parameter.len() >= 2 {
             //Zuerst das Protokoll
             if let Some(protokoll) = DdlProtokolle::from_str(cmd_msg.parameter[1].as_str()) {
               if let Some(protokolle_impl) = self.all_protokolle.get(&protokoll) {
-                //Keine Protokollversionsangabe bei INIT GA -> immer die erste vorhandene Version verwenden
-                //Wenn <optional further parameters> für Protokoll "N" angegeben ist"X" für "Erweiterte Zubehördecoder"
-                let prot_version = if cmd_msg.parameter.len() >= 3 {
-                  cmd_msg.parameter[2].as_str()
-                }
-                else {
-                  "1"
-                };
-                //Protokollversion ist angebeben, muss "1" oder "2" sein
-                if prot_version != "1" && prot_version != "2" {
-                  self
-                    .tx
-                    .send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"))
-                    .unwrap();
-                }
-                else {
-                  let prot_impl = protokolle_impl.get(prot_version).unwrap();
-                  //Adressprüfung
-                  if let Ok(adr) = cmd_msg.parameter[0].parse::<u32>() {
-                    if (adr > 0) && (adr <= prot_impl.borrow_mut().get_ga_max_adr()) {
-                      //OK an diese Session
-                      self.tx.send(SRCPMessage::new_ok(cmd_msg, "200")).unwrap();
-                      result = true;
+                //Protokollversion muss vorhanden sein
+                match self.get_protokoll_version(protokoll, cmd_msg.parameter.get(2)) {
+                  None => {
+                    self
+                      .tx
+                      .send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"));
+                  }
+                  Some(prot_version) => {
+                    //Adressprüfung mit der verwendeten Version
+                    if let (Ok(adr), Some(prot_impl)) = (
+                      cmd_msg.parameter[0].parse::<u32>(),
+                      protokolle_impl.get(prot_version.as_str()),
+                    ) {
+                      if (adr > 0) && (adr <= prot_impl.borrow_mut().get_ga_max_adr()) {
+                        if self.is_kollision(adr, protokoll, &prot_version)
+                          && (self.kollision == GAKollision::Ablehnen)
+                        {
+                          self.tx.send(SRCPMessage::new_err_detail(
+                            cmd_msg,
+                            "412",
+                            "wrong value",
+                            format!("addr={}", adr),
+                          ));
+                        } else {
+                          //OK an diese Session
+                          self.tx.send(SRCPMessage::new_ok(cmd_msg, "200"));
+                          result = true;
+                        }
+                      } else {
+                        self
+                          .tx
+                          .send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"));
+                      }
                     } else {
                       self
                         .tx
-                        .send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"))
-                        .unwrap();
+                        .send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"));
                     }
-                  } else {
-                    self
-                      .tx
-                      .send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"))
-                      .unwrap();
                   }
                 }
               } else {
-                self
-                  .tx
-                  .send(SRCPMessage::new_err(
-                    cmd_msg,
-                    "420",
-                    "unsupported device protocol",
-                  ))
-                  .unwrap();
-              }
-            } else {
-              self
-                .tx
-                .send(SRCPMessage::new_err(
+                self.tx.send(SRCPMessage::new_err(
                   cmd_msg,
                   "420",
                   "unsupported device protocol",
-                ))
-                .unwrap();
+                ));
+              }
+            } else {
+              self.tx.send(SRCPMessage::new_err(
+                cmd_msg,
+                "420",
+                "unsupported device protocol",
+              ));
             }
           } else {
             self
               .tx
-              .send(SRCPMessage::new_err(cmd_msg, "419", "list too short"))
-              .unwrap();
+              .send(SRCPMessage::new_err(cmd_msg, "419", "list too short"));
           }
         }
         SRCPMessageType::TERM => {
           //Format ist TERM <bus> GA <addr>
+          //Erweiterung TERM <bus> GA 0 DELAYED
+          if DdlGA::is_delayed_cmd(cmd_msg) {
+            self.tx.send(SRCPMessage::new_ok(cmd_msg, "200"));
+            result = true;
+          }
           //Adressprüfung
-          if let Ok(adr) = cmd_msg.parameter[0].parse::<u32>() {
+          else if let Ok(adr) = cmd_msg.parameter[0].parse::<u32>() {
             if self.all_ga.contains_key(&adr) {
               //OK an diese Session
-              self.tx.send(SRCPMessage::new_ok(cmd_msg, "200")).unwrap();
+              self.tx.send(SRCPMessage::new_ok(cmd_msg, "200"));
               result = true;
             } else {
               self
                 .tx
-                .send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"))
-                .unwrap();
+                .send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"));
             }
           } else {
             self
               .tx
-              .send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"))
-              .unwrap();
+              .send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"));
           }
         }
         SRCPMessageType::GET => {
           //Format ist GET <bus> GA <addr> <port>
-          if self.validate_get_set(cmd_msg, 2) {
+          //Erweiterung GET <bus> GA 0 DELAYED
+          if DdlGA::is_delayed_cmd(cmd_msg) || self.validate_get_set(cmd_msg, 2) {
             result = true;
           }
         }
         SRCPMessageType::SET => {
           //Format ist SET <bus> GA <addr> <port> <value> <time>
-          if self.validate_get_set(cmd_msg, 4) {
+          //SET <bus> GA 0 ist Broadcast alle Ausgänge aus an alle Zubehördekoder, weitere Parameter werden ignoriert
+          if cmd_msg.parameter.first().map(String::as_str) == Some("0") {
+            if self.get_broadcast_protokolle().is_empty() {
+              self.tx.send(SRCPMessage::new_err(
+                cmd_msg,
+                "420",
+                "unsupported device protocol",
+              ));
+            } else {
+              //OK an diese Session
+              self.tx.send(SRCPMessage::new_ok(cmd_msg, "200"));
+              result = true;
+            }
+          } else if self.validate_get_set(cmd_msg, 4) {
             //Jetzt noch <value> und <time> prüfen
             if cmd_msg.parameter[2].parse::<u8>().is_ok()
               && cmd_msg.parameter[3].parse::<i32>().is_ok()
             {
               //OK an diese Session
-              self.tx.send(SRCPMessage::new_ok(cmd_msg, "200")).unwrap();
+              self.tx.send(SRCPMessage::new_ok(cmd_msg, "200"));
               result = true;
             } else {
               self
                 .tx
-                .send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"))
-                .unwrap();
+                .send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"));
             }
           }
         }
         SRCPMessageType::VERIFY => {
           //Verify wird für GA's nicht unterstützt
-          self
-            .tx
-            .send(SRCPMessage::new_err(
-              cmd_msg,
-              "423",
-              "unsupported operation",
-            ))
-            .unwrap();
+          self.tx.send(SRCPMessage::new_err(
+            cmd_msg,
+            "423",
+            "unsupported operation",
+          ));
         }
       };
     }
@@ -420,31 +889,34 @@ impl SRCPDeviceDDL for DdlGA<'_> {
         };
         //Adresse
         let adr = cmd_msg.parameter[0].parse::<u32>().unwrap();
-        self
-          .all_ga
-          .insert(adr, GAInit::new(protokoll, if cmd_msg.parameter.len() >= 3 {Some(cmd_msg.parameter[2].clone())} else {None}, self.trigger.contains(&adr)));
-        //INFO <bus> GA <adr> <protokoll>
-        self
-          .tx
-          .send(SRCPMessage::new(
-            None,
-            cmd_msg.bus,
-            SRCPMessageID::Info {
-              info_code: "100".to_string(),
-            },
-            cmd_msg.device.clone(),
-            cmd_msg.parameter.clone(),
-          ))
-          .unwrap();
+        let Some(protokoll_version) =
+          self.get_protokoll_version(protokoll, cmd_msg.parameter.get(2))
+        else {
+          return;
+        };
+        self.all_ga.insert(
+          adr,
+          GAInit::new(protokoll, protokoll_version, self.trigger.contains(&adr)),
+        );
+        //INFO <bus> GA <adr> <protokoll> <protokollversion>
+        self.send_info_init(None, adr);
       }
       SRCPMessageType::TERM => {
         //Format ist TERM <bus> GA <addr>
+        if DdlGA::is_delayed_cmd(cmd_msg) {
+          self.flush_delayed(GA_DELAYED);
+          return;
+        }
         //Adresse
         let adr = cmd_msg.parameter[0].parse::<u32>().unwrap();
-        self.all_ga.remove(&adr);
+        self.remove_ga(adr);
       }
       SRCPMessageType::GET => {
         //Format ist GET <bus> GA <addr> <port>
+        if DdlGA::is_delayed_cmd(cmd_msg) {
+          self.send_info_delayed(cmd_msg.session_id);
+          return;
+        }
         let adr = cmd_msg.parameter[0].parse::<u32>().unwrap();
         let port = cmd_msg.parameter[1].parse::<usize>().unwrap();
         let ga = &self.all_ga[&adr];
@@ -453,11 +925,21 @@ impl SRCPDeviceDDL for DdlGA<'_> {
       }
       SRCPMessageType::SET => {
         let adr = cmd_msg.parameter[0].parse::<u32>().unwrap();
+        if adr == 0 {
+          self.send_ga_broadcast_off();
+          return;
+        }
         //Da SET verzögert über Queue ausgeführt wird könnte ein TERM dazwischen gekommen sein, Adresse nochmals prüfen
         if self.all_ga.contains_key(&adr) {
           let port = cmd_msg.parameter[1].parse::<usize>().unwrap();
           let value = cmd_msg.parameter[2].parse::<usize>().unwrap();
-          let switch_off_timeout = cmd_msg.parameter[3].parse::<i32>().unwrap();
+          let mut switch_off_timeout = cmd_msg.parameter[3].parse::<i32>().unwrap();
+          if value != 0 {
+            //Spulenschutz: Einschaltdauer gemäss Konfiguration begrenzen
+            switch_off_timeout = self
+              .puls_konfig
+              .get_puls_dauer(adr, port, switch_off_timeout);
+          }
           if (value != 0) && (switch_off_timeout > 0) {
             //Zumindest die alten Märklin k83 Dekoder könne nicht mehrere Ausgänge gleichzeitig aktiviert haben.
             //Wenn Ausschalten hier gemacht wird, dann stellen wir hier auch sicher, dass nicht mehr als ein
@@ -496,8 +978,9 @@ impl SRCPDeviceDDL for DdlGA<'_> {
   /// * session_id - SRCP Client Session ID an die die Zustände gesendet werden sollen.
   ///                None -> Info an alle SRCP Clients
   fn send_all_info(&self, session_id: Option<u32>) {
-    //Über alle initialisierten GA's
+    //Über alle initialisierten GA's: INFO 101 mit den INIT Parametern, dann aktueller Zustand
     for (adr, ga) in &self.all_ga {
+      self.send_info_init(session_id, *adr);
       //Über alle Ports dieses GA's
       for port in 0..ga.value.len() {
         self.send_info_msg(session_id, *adr, port, ga.value[port]);
@@ -514,8 +997,28 @@ impl SRCPDeviceDDL for DdlGA<'_> {
   ///           false: Power / Booster ist aus
   fn execute(&mut self, power: bool) -> bool {
     let mut tel_gesendet = false;
+    //Bei Power Off alle verzögerten Ausgaben verwerfen
+    if self.power && !power {
+      self.flush_delayed("Power Off");
+    }
+    //Bei Power On Zustände der GA's ohne eigenen Speicher wiederherstellen
+    if !self.power && power {
+      self.restore_nach_power_on();
+    }
+    self.power = power;
     //Ein- Ausschaltkommando senden macht nur Sinn, wenn Power vorhanden ist
     if power {
+      //Fällige verzögerte Telegramme senden
+      for ddl_tel in self.tel_buffer.iter_mut() {
+        if ddl_tel
+          .instant_next
+          .is_none_or(|instant_next| instant_next <= Instant::now())
+        {
+          self.ausgabe.send(ddl_tel, self.trigger_port);
+          tel_gesendet = true;
+        }
+      }
+      self.tel_buffer.retain(|ddl_tel| !ddl_tel.daten.is_empty());
       let mut i = 0;
       while i < self.all_ga_delay.len() {
         let ga_delay = &self.all_ga_delay[i];
@@ -533,7 +1036,7 @@ impl SRCPDeviceDDL for DdlGA<'_> {
           }
           GADelayGrund::Ausschalten(off_zeit) => {
             if Instant::now() > off_zeit {
-              //Auto off
+              //Auto off, explizites Ausschalttelegramm (DCC C=0)
               tel_gesendet = true;
               self.send_ga(ga_delay.adr, ga_delay.port, 0, None);
               self.all_ga_delay.remove(i);
@@ -541,9 +1044,73 @@ impl SRCPDeviceDDL for DdlGA<'_> {
               i += 1;
             }
           }
+          GADelayGrund::Wiederherstellen(zeit) => {
+            if Instant::now() > zeit {
+              //Nur wenn der Ausgang in der Zwischenzeit nicht durch einen Client verändert wurde
+              let (adr, port) = (ga_delay.adr, ga_delay.port);
+              self.all_ga_delay.remove(i);
+              if let Some(value) = self.all_ga.get(&adr).map(|ga| ga.value[port]) {
+                if value != 0 {
+                  tel_gesendet = true;
+                  self.send_ga(adr, port, value, None);
+                }
+              }
+            } else {
+              i += 1;
+            }
+          }
         }
       }
     }
     tel_gesendet
   }
+
+  /// Liefert den frühesten Zeitpunkt für automatisches Ausschalten eines GA oder für verzögerte Telegramme
+  /// # Arguments
+  /// * power - true: Power / Booster ist ein, Strom auf den Schienen
+  fn get_naechster_zeitpunkt(&self, power: bool) -> Option<Instant> {
+    if !power {
+      return None;
+    }
+    let tel_buffer = self
+      .tel_buffer
+      .iter()
+      .filter_map(|ddl_tel| ddl_tel.instant_next);
+    self
+      .all_ga_delay
+      .iter()
+      .filter_map(|ga_delay| match ga_delay.ga_delay_grund {
+        GADelayGrund::Ausschalten(off_zeit) | GADelayGrund::Wiederherstellen(off_zeit) => {
+          Some(off_zeit)
+        }
+        GADelayGrund::Einschalten(_) => None,
+      })
+      .chain(tel_buffer)
+      .min()
+  }
+
+  /// Ein Protokoll mit allen Versionen zur Laufzeit hinzufügen.
+  /// # Arguments
+  /// * protokoll - Das neue Protokoll
+  /// * versionen - Alle Versionen des neuen Protokolls
+  fn register_protokoll(&mut self, protokoll: DdlProtokolle, versionen: &HashMapVersion) {
+    self.all_protokolle.insert(protokoll, versionen.clone());
+  }
+
+  /// Ein Protokoll zur Laufzeit entfernen.
+  /// Alle GA's mit diesem Protokoll werden inkl. verzögerter Ausgaben entfernt und als INFO 102 gemeldet.
+  /// # Arguments
+  /// * protokoll - Das zu entfernende Protokoll
+  fn unregister_protokoll(&mut self, protokoll: DdlProtokolle) {
+    let adr_entfernen: Vec<u32> = self
+      .all_ga
+      .iter()
+      .filter(|(_, ga)| ga.protokoll == protokoll)
+      .map(|(adr, _)| *adr)
+      .collect();
+    for adr in adr_entfernen {
+      self.remove_ga(adr);
+    }
+    self.all_protokolle.remove(&protokoll);
+  }
 }
@@ -1,20 +1,22 @@
 use std::{
+  cell::RefCell,
   collections::HashMap,
-  sync::mpsc::Sender,
+  rc::Rc,
   thread,
   time::{Duration, Instant},
 };
 
-use log::{info, warn};
-use spidev::Spidev;
+use log::{debug, info, warn};
 
 use crate::{
-  srcp_devices_ddl::SRCPDeviceDDL,
+  srcp_devices_ddl::{DdlAusgabe, SRCPDeviceDDL},
   srcp_protocol_ddl::{
-    DdlProtokoll, DdlProtokolle, DdlTel, DdlTelRx, GLDriveMode, HashMapProtokollVersion,
-    ResultNeuAnmeldung, ResultReadGlParameter,
+    DdlProtokoll, DdlProtokolle, DdlTel, DdlTelRx, GLAnwesenheit, GLDriveMode, GlInitParams,
+    HashMapProtokollVersion, HashMapVersion, ResultNeuAnmeldung, ResultReadGlParameter, TelOptions,
+  },
+  srcp_server_types::{
+    SRCPMessage, SRCPMessageDevice, SRCPMessageID, SRCPMessageType, ThreadSender, INFO_ERWEITERUNG,
   },
-  srcp_server_types::{SRCPMessage, SRCPMessageDevice, SRCPMessageID, SRCPMessageType},
 };
 
 /// Anzahl initialisierter GL damit in Modus ohne extra Delays ziwschen Telegrammen
@@ -27,16 +29,37 @@ const MIN_ANZ_GL_NO_DELAY: usize = 15;
 /// Grund: Bei DCC die Möglichkeit haben 5ms Verzögerungen zu machen, bei MM darf nicht nur eine
 /// MM Adresse vorhanden sein wegen Dekoder Prog. Modus.
 const IDLE_COUNT_MM_DCC: usize = 2;
+/// Nach dieser Anzahl GL Refresh Telegramme wird ein Idle Telegramm eines unbenutzten Protokolls eingeschoben,
+/// damit dessen Dekoder auch bei vielen GL's regelmässig ein Signal sehen.
+const IDLE_TEL_INTERVALL_GL: usize = 8;
+/// Intervall für Log Ausgabe der Refresh Statistik
+const STATISTIK_INTERVALL: Duration = Duration::from_secs(60);
+/// Ab dieser Refreshzykluszeit wird gewarnt, wenn sie durch DCC 5ms oder MM5 50ms Pausen verlängert wurde
+const REFRESH_ZYKLUS_WARNUNG: Duration = Duration::from_millis(250);
+/// Erweiterung GET <bus> GL <addr> STATS: Abfrage der Telegrammstatistik einer GL
+const GL_STATISTIK: &str = "STATS";
+/// Erweiterung GET <bus> GL <addr> INIT: Abfrage der INIT Parameter einer GL,
+/// Antwort INFO 190 <bus> GL <addr> INIT mit den Parametern von INFO 101.
+/// Damit kann z.B. ein neu verbundener Client Protokoll, Speedsteps und Anzahl Funktionen aller GL's ermitteln.
+const GL_INIT: &str = "INIT";
+/// Erweiterung INIT <bus> GL <addr> ... MAP=<logisch>:<physisch>,...: optionaler letzter Parameter zur Umleitung
+/// von Funktionen, z.B. schaltet mit "MAP=2:5" F2 des Clients die Funktion F5 des Dekoders.
+/// Nicht aufgeführte Funktionen werden unverändert übernommen.
+const GL_FNKT_MAP: &str = "MAP=";
 
 ///Verwaltung einer initialisierten GL
 #[derive(Clone)]
-struct GLInit {
+pub struct GLInit {
   //Aktuelles Fahrtrichtung
   direction: GLDriveMode,
-  //Aktuelle Geschwindigkeit
+  //Aktuelle Geschwindigkeit in Protokoll Fahrstufen (0 bis protokoll_speedsteps)
   speed: usize,
+  //Aktuelle Geschwindigkeit in der Skalierung V_max des Clients (für INFO)
+  client_v: usize,
+  //V_max des letzten SET Kommandos mit V > 0, zu Beginn protokoll_speedsteps
+  client_v_max: usize,
   //Zusatzfunktionen
-  fnkt: u64,
+  pub fnkt: u64,
   //Gewähltes Protokoll
   protokoll: DdlProtokolle,
   //Gewählte Protokollversion
@@ -49,11 +72,13 @@ struct GLInit {
   protokoll_uid: Option<u32>,
   //Optionale Init Parameter (z.B. MFX UID, Name, Funktionen)
   param: Vec<String>,
+  //Funktionsumleitung (logische Funktion des Clients, physische Funktion des Dekoders)
+  fnkt_map: Vec<(usize, usize)>,
   //Oszi Trigger bei Telegrammausgabe?
   trigger: bool,
 }
 impl GLInit {
-  fn new(
+  pub fn new(
     protokoll: DdlProtokolle, protokoll_version: String, protokoll_speedsteps: usize,
     protokoll_number_functions: usize, protokoll_uid: Option<u32>, param: &Vec<String>,
     trigger: bool,
@@ -65,21 +90,133 @@ impl GLInit {
       protokoll_number_functions,
       direction: GLDriveMode::Vorwaerts,
       speed: 0,
+      client_v: 0,
+      client_v_max: protokoll_speedsteps,
       fnkt: 0,
       protokoll_uid,
       param: param.clone(),
+      fnkt_map: param
+        .last()
+        .and_then(|map| GLInit::eval_fnkt_map(map, protokoll_number_functions))
+        .unwrap_or_default(),
       trigger,
     }
   }
+
+  /// Auswerten INIT Parameter Funktionsumleitung "MAP=<logisch>:<physisch>,...".
+  /// Liefert None wenn es kein gültiger MAP Parameter ist.
+  /// # Arguments
+  /// * map - INIT Parameter
+  /// * anz_f - Anzahl Funktionen der GL, logische und physische Funktionen müssen kleiner sein
+  pub fn eval_fnkt_map(map: &str, anz_f: usize) -> Option<Vec<(usize, usize)>> {
+    map
+      .strip_prefix(GL_FNKT_MAP)?
+      .split(',')
+      .map(|eintrag| {
+        let (logisch, physisch) = eintrag.split_once(':')?;
+        let logisch = logisch.parse::<usize>().ok()?;
+        let physisch = physisch.parse::<usize>().ok()?;
+        ((logisch < anz_f) && (physisch < anz_f)).then_some((logisch, physisch))
+      })
+      .collect()
+  }
+
+  /// Liefert die Funktionen wie sie an den Dekoder gesendet werden (Funktionsumleitung angewandt).
+  /// Umgeleitete logische Funktionen werden an ihrer ursprünglichen Stelle entfernt.
+  pub fn get_fnkt_physisch(&self) -> u64 {
+    let mut fnkt = self.fnkt;
+    for &(logisch, _) in &self.fnkt_map {
+      fnkt &= !(1 << logisch);
+    }
+    for &(logisch, physisch) in &self.fnkt_map {
+      if (self.fnkt & (1 << logisch)) != 0 {
+        fnkt |= 1 << physisch;
+      }
+    }
+    fnkt
+  }
+}
+
+///Verhalten wenn eine GL durch eine andere Session als den bisherigen Besitzer gesteuert wird
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GLUebernahme {
+  ///Kein Besitz, jede Session darf jede GL steuern
+  Keine,
+  ///Übernahme erlaubt, bisheriger Besitzer wird über INFO GM informiert
+  Melden,
+  ///Übernahme nicht erlaubt solange der Besitzer die GL nicht freigibt (TERM oder Session Ende)
+  Sperren,
+}
+
+///Refresh Statistik einer GL
+struct GLRefreshStatistik {
+  ///Protokoll mit dem die Telegramme gesendet wurden
+  protokoll: DdlProtokolle,
+  ///Zeitpunkt letztes gesendetes Telegramm, None wenn seit Power On noch keines gesendet wurde
+  letztes_tel: Option<Instant>,
+  ///Gleitender Mittelwert der Zeit zwischen zwei Telegrammen
+  mittel: Duration,
+  ///Grösste Zeit zwischen zwei Telegrammen seit letzter Log Ausgabe
+  max: Duration,
+  ///Anzahl gesendete Telegramme (inkl. Zusatztelegramme für Funktionen)
+  telegramme: u64,
+  ///Anzahl zusätzliche Wiederholungen der Telegramme (neue Kommandos werden mehrfach gesendet)
+  wiederholungen: u64,
+  ///Zeitpunkt letztes Telegramm aus dem Refreshzyklus, None wenn noch keines
+  letzter_refresh: Option<Instant>,
+}
+impl GLRefreshStatistik {
+  /// Neue, leere Statistik
+  /// # Arguments
+  /// * protokoll - Protokoll der GL
+  fn new(protokoll: DdlProtokolle) -> GLRefreshStatistik {
+    GLRefreshStatistik {
+      protokoll,
+      letztes_tel: None,
+      mittel: Duration::ZERO,
+      max: Duration::ZERO,
+      telegramme: 0,
+      wiederholungen: 0,
+      letzter_refresh: None,
+    }
+  }
+}
+
+///Verwaltung automatisches Ausschalten von Momentfunktionen
+struct GLFnktDelay {
+  ///GL Adresse
+  adr: u32,
+  ///Funktionsnummer (0 = F0)
+  fnkt: usize,
+  ///Wann soll die Funktion ausgeschaltet werden
+  off_zeit: Instant,
+}
+
+///GL Konfiguration eines Busses aus dem Konfigfile
+pub struct DdlGLKonfig {
+  ///Oszi Triggerport
+  pub trigger_port: Option<String>,
+  ///Oszi Trigger Adressen
+  pub trigger_adr: Option<String>,
+  ///Momentfunktionen
+  pub momentary: Option<String>,
+  ///Geschwindigkeitskennlinien, Key GL Adresse
+  pub speedcurve: HashMap<u32, Vec<u8>>,
+  ///Max. Fahrstufe, Key GL Adresse
+  pub speedmax: HashMap<u32, usize>,
+  ///Verhalten bei Steuerung einer GL durch eine andere Session
+  pub uebernahme: GLUebernahme,
+  ///Max. Anzahl initialisierter GL's, None wenn unbegrenzt
+  pub gl_max: Option<usize>,
 }
 
 pub struct DdlGL<'a> {
   ///SRCP Bus auf dem gearbeitet wird
   bus: usize,
   ///Sender für SRCP Antworten
-  tx: Sender<SRCPMessage>,
+  tx: ThreadSender<SRCPMessage>,
   ///SPI Bus für Ausgabe
-  spidev: &'a Option<Spidev>,
+  ausgabe: &'a DdlAusgabe,
   ///Alle vorhandenen Protokollimplementierungen mit allen Versionen
   all_protokolle: HashMapProtokollVersion,
   ///Alle initialisierten GL, Key Adresse
@@ -88,6 +225,12 @@ pub struct DdlGL<'a> {
   adr_refresh: u32,
   ///Alle noch nicht durch GL verwendeten aber vorhandenen Protokolle für Idle Telegramme
   all_idle_protokolle: Vec<DdlProtokolle>,
+  ///Pro Idle Protokoll Index der Version, deren Idle Telegramm als nächstes gesendet wird (Rotation über alle Versionen)
+  idle_version: HashMap<DdlProtokolle, usize>,
+  ///Index in "all_idle_protokolle" für das nächste zwischen GL Refresh eingeschobene Idle Telegramm
+  idle_naechstes_protokoll: usize,
+  ///Anzahl GL Refresh Telegramme seit dem letzten Idle Telegramm
+  gl_seit_idle: usize,
   ///Buffer für verzögertes senden
   tel_buffer: Vec<DdlTel>,
   ///GL's, die automatisch angemeldet wurden und bei der noch die optionalen Parameter ausgelesen werden
@@ -97,6 +240,34 @@ pub struct DdlGL<'a> {
   trigger: Vec<u32>,
   ///Und Port für Oszi trigger
   trigger_port: Option<u32>,
+  ///Momentfunktionen aus Konfigfile, Key GL Adresse, dann Key Funktionsnummer mit Einschaltdauer
+  gl_momentary: HashMap<u32, HashMap<usize, Duration>>,
+  ///Alle eingeschalteten Momentfunktionen, die noch automatisch ausgeschaltet werden müssen
+  all_fnkt_delay: Vec<GLFnktDelay>,
+  ///Geschwindigkeitskennlinien aus Konfigfile, Key GL Adresse
+  gl_speedcurve: HashMap<u32, Vec<u8>>,
+  ///Max. Fahrstufe aus Konfigfile, Key GL Adresse
+  gl_speedmax: HashMap<u32, usize>,
+  ///Verhalten bei Steuerung einer GL durch eine andere Session
+  gl_uebernahme: GLUebernahme,
+  ///Besitzer (Session ID der letzten SET Kommandos) der GL's, Key GL Adresse
+  gl_besitzer: HashMap<u32, u32>,
+  ///Max. Anzahl initialisierter GL's, None wenn unbegrenzt
+  gl_max: Option<usize>,
+  ///Start des aktuellen Refreshzyklus über alle GL's
+  refresh_zyklus_start: Instant,
+  ///Dauer des letzten vollständigen Refreshzyklus über alle GL's
+  refresh_zyklus_dauer: Duration,
+  ///Summe der Wartezeiten (DCC 5ms, MM5 50ms) im aktuellen Refreshzyklus
+  refresh_zyklus_delay: Duration,
+  ///Längster Refreshzyklus und dessen Wartezeiten seit letzter Log Ausgabe
+  refresh_zyklus_max: (Duration, Duration),
+  ///Refresh Statistik aller GL's, Key GL Adresse
+  gl_statistik: HashMap<u32, GLRefreshStatistik>,
+  ///Zeitpunkt letzte Log Ausgabe der Refresh Statistik
+  statistik_start: Instant,
+  ///Power war aus, bei Power On müssen GL's von Protokollen mit "power_on_resync" zuerst neu gesendet werden
+  power_on_resync: bool,
 }
 
 impl DdlGL<'_> {
@@ -104,15 +275,13 @@ impl DdlGL<'_> {
   /// # Arguments
   /// * bus - SRCP Bus auf dem dieses Device arbeitet
   /// * tx - Sender für Info Messages / Antworten an SRCP Clients
-  /// * spidev - geöffnetes Spidev zur Ausgabe an Booster
+  /// * ausgabe - SPI Ausgabe an Booster
   /// * all_protokolle - Alle vorhandenen Protokollimplementierungen mit allen Versionen
-  /// * trigger_port - Oszi Triggerport aus Konfigfile
-  /// * trigger_adr - Oszi Trigger Adressen aus Konfigfile
-  pub fn new(
-    bus: usize, tx: Sender<SRCPMessage>, spidev: &Option<Spidev>,
-    all_protokolle: HashMapProtokollVersion, trigger_port: Option<String>,
-    trigger_adr: Option<String>,
-  ) -> DdlGL<'_> {
+  /// * konfig - GL Konfiguration aus Konfigfile
+  pub fn new<'a>(
+    bus: usize, tx: ThreadSender<SRCPMessage>, ausgabe: &'a DdlAusgabe,
+    all_protokolle: HashMapProtokollVersion, konfig: DdlGLKonfig,
+  ) -> DdlGL<'a> {
     let mut all_idle_protokolle: Vec<DdlProtokolle> = Vec::new();
     //Zuerst sind mal alle Protokolle nicht verwendet
     for (protokoll, _) in &all_protokolle {
@@ -121,21 +290,122 @@ impl DdlGL<'_> {
     let mut result = DdlGL {
       bus,
       tx,
-      spidev,
+      ausgabe,
       all_protokolle,
       all_gl: HashMap::new(),
       adr_refresh: 0,
       all_idle_protokolle,
+      idle_version: HashMap::new(),
+      idle_naechstes_protokoll: 0,
+      gl_seit_idle: 0,
       tel_buffer: Vec::new(),
       gl_param_read: None,
       trigger: vec![],
       trigger_port: None,
+      gl_momentary: DdlGL::eval_momentary_config(konfig.momentary),
+      all_fnkt_delay: Vec::new(),
+      gl_speedcurve: konfig.speedcurve,
+      gl_speedmax: konfig.speedmax,
+      gl_uebernahme: konfig.uebernahme,
+      gl_besitzer: HashMap::new(),
+      gl_max: konfig.gl_max,
+      refresh_zyklus_start: Instant::now(),
+      refresh_zyklus_dauer: Duration::ZERO,
+      refresh_zyklus_delay: Duration::ZERO,
+      refresh_zyklus_max: (Duration::ZERO, Duration::ZERO),
+      gl_statistik: HashMap::new(),
+      statistik_start: Instant::now(),
+      power_on_resync: false,
     };
-    result.trigger_port = result.eval_trigger_port_config(trigger_port);
-    result.trigger = result.eval_trigger_config(trigger_adr);
+    result.trigger_port = result.eval_trigger_port_config(konfig.trigger_port);
+    result.trigger = result.eval_trigger_config(konfig.trigger_adr);
+    result
+  }
+
+  /// Auswerten Konfiguration Momentfunktionen.
+  /// Format: <adr>:<fnkt>:<ms>, mehrere Einträge getrennt mit Kommas.
+  /// Die Funktion <fnkt> der GL <adr> wird nach <ms> automatisch ausgeschaltet.
+  /// # Arguments
+  /// * momentary - Momentfunktionen als String aus Konfigfile, None wenn nicht vorhanden
+  fn eval_momentary_config(momentary: Option<String>) -> HashMap<u32, HashMap<usize, Duration>> {
+    let mut result: HashMap<u32, HashMap<usize, Duration>> = HashMap::new();
+    if let Some(eintraege) = momentary {
+      for eintrag in eintraege.split(',') {
+        let werte: Vec<&str> = eintrag.trim().split(':').collect();
+        if let [adr, fnkt, ms] = werte[..] {
+          if let (Ok(adr), Ok(fnkt), Ok(ms)) =
+            (adr.parse::<u32>(), fnkt.parse::<usize>(), ms.parse::<u64>())
+          {
+            if fnkt < 64 {
+              result
+                .entry(adr)
+                .or_default()
+                .insert(fnkt, Duration::from_millis(ms));
+              continue;
+            }
+          }
+        }
+        warn!("DDL: Ungültige GL Momentfunktion: {}", eintrag);
+      }
+    }
     result
   }
 
+  /// Für Momentfunktionen bei neu eingeschalteter Funktion die automatische Ausschaltung planen,
+  /// bei Ausschaltung durch den Anwender eine noch geplante automatische Ausschaltung entfernen.
+  /// # Arguments
+  /// * adr - GL Adresse
+  /// * fnkt_alt - Funktionen vor dem SET Kommando
+  /// * fnkt_neu - Funktionen gemäss SET Kommando
+  fn update_fnkt_delay(&mut self, adr: u32, fnkt_alt: u64, fnkt_neu: u64) {
+    let Some(momentary) = self.gl_momentary.get(&adr) else {
+      return;
+    };
+    for (&fnkt, &dauer) in momentary {
+      let maske: u64 = 1 << fnkt;
+      if (fnkt_neu & maske) == 0 {
+        //Durch Anwender ausgeschaltet
+        self
+          .all_fnkt_delay
+          .retain(|fnkt_delay| !((fnkt_delay.adr == adr) && (fnkt_delay.fnkt == fnkt)));
+      } else if (fnkt_alt & maske) == 0 {
+        //Neu eingeschaltet
+        self.all_fnkt_delay.push(GLFnktDelay {
+          adr,
+          fnkt,
+          off_zeit: Instant::now() + dauer,
+        });
+      }
+    }
+  }
+
+  /// Hier wird das automatische Ausschalten von Momentfunktionen nach Ablauf der Einschaltdauer ausgeführt.
+  /// Liefert true zurück, wenn dadurch min. ein Telegramm gesendet wurde.
+  fn execute_fnkt_delay(&mut self) -> bool {
+    let jetzt = Instant::now();
+    let (abgelaufen, offen): (Vec<GLFnktDelay>, Vec<GLFnktDelay>) =
+      std::mem::take(&mut self.all_fnkt_delay)
+        .into_iter()
+        .partition(|fnkt_delay| fnkt_delay.off_zeit <= jetzt);
+    self.all_fnkt_delay = offen;
+    let mut adressen: Vec<u32> = Vec::new();
+    for fnkt_delay in abgelaufen {
+      //Falls es die GL in der Zwischenzeit nicht mehr gibt
+      if let Some(gl) = self.all_gl.get_mut(&fnkt_delay.adr) {
+        gl.fnkt &= !(1 << fnkt_delay.fnkt);
+        if !adressen.contains(&fnkt_delay.adr) {
+          adressen.push(fnkt_delay.adr);
+        }
+      }
+    }
+    for &adr in &adressen {
+      self.send_gl_tel(adr, false, false);
+      //Alle Info Clients über neuen Zustand Informieren
+      self.send_info_msg(None, adr);
+    }
+    !adressen.is_empty()
+  }
+
   /// GET und SET (ohne Values für SET) validieren
   /// return true wenn OK.
   /// # Arguments
@@ -150,56 +420,152 @@ impl DdlGL<'_> {
         if let Some(_) = self.all_gl.get(&adr) {
           result = true;
         } else {
-          self
-            .tx
-            .send(SRCPMessage::new_err(cmd_msg, "416", "no data"))
-            .unwrap();
+          self.send_err(cmd_msg, "416", "no data", format!("addr={}", adr));
         }
       } else {
-        self
-          .tx
-          .send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"))
-          .unwrap();
+        self.send_err(
+          cmd_msg,
+          "412",
+          "wrong value",
+          format!("addr={}", cmd_msg.parameter[0]),
+        );
       }
     } else {
-      self
-        .tx
-        .send(SRCPMessage::new_err(cmd_msg, "419", "list too short"))
-        .unwrap();
+      self.send_err(
+        cmd_msg,
+        "419",
+        "list too short",
+        ["addr", "drivemode", "V", "V_max"][cmd_msg.parameter.len()].to_string(),
+      );
     }
     result
   }
 
+  /// Values von SET und VERIFY <drivemode> <V> <V_max> <f0> . . <fn> validieren.
+  /// Die Adresse muss bereits mit "validate_get_set" geprüft sein.
+  /// return true wenn OK, sonst wird Error mit dem fehlerhaften Parameter an Sender zurückgesendet.
+  /// # Arguments
+  /// * cmd_msg - Empfangenes Kommando
+  fn validate_set_values(&self, cmd_msg: &SRCPMessage) -> bool {
+    let anz_f =
+      self.all_gl[&cmd_msg.parameter[0].parse::<u32>().unwrap()].protokoll_number_functions;
+    let fehler = if GLDriveMode::from_str(cmd_msg.parameter[1].as_str()).is_none() {
+      Some((
+        "412",
+        "wrong value",
+        format!("drivemode={}", cmd_msg.parameter[1]),
+      ))
+    } else if cmd_msg.parameter[2].parse::<u8>().is_err() {
+      Some(("412", "wrong value", format!("V={}", cmd_msg.parameter[2])))
+    }
+    //vmax muss > 0 sein
+    else if cmd_msg.parameter[3].parse::<u8>().is_err() || (cmd_msg.parameter[3] == "0") {
+      Some((
+        "412",
+        "wrong value",
+        format!("V_max={}", cmd_msg.parameter[3]),
+      ))
+    }
+    //Nicht mehr Funktionen als mit INIT angegeben
+    else if cmd_msg.parameter.len() > 4 + anz_f {
+      Some(("418", "list too long", format!("f{}", anz_f)))
+    }
+    //Wenn Funktionen vorhanden sind, dann müssen die alle 0 oder 1 sein
+    else {
+      cmd_msg.parameter[4..]
+        .iter()
+        .position(|f| (f != "0") && (f != "1"))
+        .map(|i| {
+          (
+            "412",
+            "wrong value",
+            format!("f{}={}", i, cmd_msg.parameter[4 + i]),
+          )
+        })
+    };
+    match fehler {
+      Some((err_code, err_text, detail)) => {
+        self.send_err(cmd_msg, err_code, err_text, detail);
+        false
+      }
+      None => true,
+    }
+  }
+
+  /// Error mit fehlerhaftem Parameter im Freitext an Sender zurücksenden
+  /// # Arguments
+  /// * cmd_msg - Empfangenes Kommando
+  /// * err_code - SRCP Error Code
+  /// * err_text - SRCP Error Text
+  /// * detail - Fehlerhafter Parameter, z.B. "V_max=0"
+  fn send_err(
+    &self, cmd_msg: &SRCPMessage, err_code: &'static str, err_text: &'static str, detail: String,
+  ) {
+    self.tx.send(SRCPMessage::new_err_detail(
+      cmd_msg, err_code, err_text, detail,
+    ));
+  }
+
+  /// Liefert den Namen eines INIT Parameters für Fehlermeldungen
+  /// # Arguments
+  /// * index - Index des Parameters, 0 ist <addr>
+  /// * uid - true wenn das Protokoll eine UID verlangt
+  fn get_init_parameter_name(index: usize, uid: bool) -> String {
+    match index {
+      3 => "decoderspeedsteps".to_string(),
+      4 => "numberofdecoderfunctions".to_string(),
+      5 if uid => "lokUid".to_string(),
+      i if uid => format!("mfxfunctioncode{}", i - 6),
+      i => format!("parameter{}", i + 1),
+    }
+  }
+
+  /// SET / VERIFY Values <drivemode> <V> <V_max> <f0> . . <fn> auswerten.
+  /// Liefert Richtung, Geschwindigkeit bezogen auf V_max und Funktionen als Bits zurück.
+  /// Das Kommando muss gültig sein.
+  /// # Arguments
+  /// * cmd_msg - Empfangenes SET oder VERIFY Kommando
+  fn eval_set_values(cmd_msg: &SRCPMessage) -> (GLDriveMode, usize, usize, u64) {
+    let drivemode = GLDriveMode::from_str(cmd_msg.parameter[1].as_str()).unwrap();
+    let v = cmd_msg.parameter[2].parse::<usize>().unwrap();
+    let v_max = cmd_msg.parameter[3].parse::<usize>().unwrap();
+    let mut funktionen: u64 = 0;
+    for i in 4..cmd_msg.parameter.len() {
+      if cmd_msg.parameter[i] == "1" {
+        funktionen |= 1 << (i - 4);
+      }
+    }
+    (drivemode, v, v_max, funktionen)
+  }
+
   /// INFO Message versenden
   /// # Arguments
   /// * session_id - None: an alle SRCP Info Clients, sonst nur an den mit SessionID
   /// * adr - GA Adresse -> Muss gültig, d.h. initialisiert sein
   fn send_info_msg(&self, session_id: Option<u32>, adr: u32) {
     //INFO <bus> GL <addr> <drivemode> <V> <V_max> <f0> . . <fn>
+    //V und V_max in der Skalierung wie vom Client gesendet, nicht in Protokoll Fahrstufen
     let Some(gl) = self.all_gl.get(&adr) else {
       return;
     };
     let mut param: Vec<String> = vec![
       adr.to_string(),
       gl.direction.to_string(),
-      gl.speed.to_string(),
-      gl.protokoll_speedsteps.to_string(),
+      gl.client_v.to_string(),
+      gl.client_v_max.to_string(),
     ];
     for i in 0..gl.protokoll_number_functions {
       param.push((if (gl.fnkt & (1 << i)) == 0 { "0" } else { "1" }).to_string());
     }
-    self
-      .tx
-      .send(SRCPMessage::new(
-        session_id,
-        self.bus,
-        SRCPMessageID::Info {
-          info_code: "100".to_string(),
-        },
-        SRCPMessageDevice::GL,
-        param,
-      ))
-      .unwrap();
+    self.tx.send(SRCPMessage::new(
+      session_id,
+      self.bus,
+      SRCPMessageID::Info {
+        info_code: "100".to_string(),
+      },
+      SRCPMessageDevice::GL,
+      param,
+    ));
   }
 
   /// GL senden und Zustand speichern
@@ -215,8 +581,10 @@ impl DdlGL<'_> {
     refresh: bool,
   ) {
     let mut doppelt = false;
+    let fnkt_alt;
     {
       let gl = self.all_gl.get_mut(&adr).unwrap();
+      fnkt_alt = gl.fnkt;
       //Speed bezogen auf v_max von Initkommando berechnen
       let speed = (gl.protokoll_speedsteps * v) / v_max;
       if (gl.speed > 0) && (speed == 0) {
@@ -227,13 +595,167 @@ impl DdlGL<'_> {
       gl.direction = drivemode;
       gl.speed = speed;
       gl.fnkt = funktionen;
+      //Skalierung des Clients für INFO, bei Stillstand (z.B. Nothalt mit V_max 1) bisherige beibehalten
+      gl.client_v = v;
+      if v > 0 {
+        gl.client_v_max = v_max;
+      }
     }
+    //Automatisches Ausschalten von Momentfunktionen
+    self.update_fnkt_delay(adr, fnkt_alt, funktionen);
     //Und versenden
     self.send_gl_tel(adr, doppelt, refresh);
     //Alle Info Clients über neuen Zustand Informieren
     self.send_info_msg(None, adr);
   }
 
+  /// Prüft, ob die GL des SET Kommandos im Modus "Sperren" einer anderen Session gehört.
+  /// Kommandos des SRCP Servers selbst (Session 0 oder keine) sind nie gesperrt.
+  /// # Arguments
+  /// * cmd_msg - Empfangenes SET Kommando, Adresse muss gültig sein
+  fn is_gl_gesperrt(&self, cmd_msg: &SRCPMessage) -> bool {
+    if self.gl_uebernahme != GLUebernahme::Sperren {
+      return false;
+    }
+    let Some(session_id) = cmd_msg.session_id.filter(|&id| id != 0) else {
+      return false;
+    };
+    let adr = cmd_msg.parameter[0].parse::<u32>().unwrap();
+    self
+      .gl_besitzer
+      .get(&adr)
+      .is_some_and(|&besitzer| besitzer != session_id)
+  }
+
+  /// Besitz einer GL durch die Session des SET Kommandos übernehmen.
+  /// Im Modus "Melden" wird der bisherige Besitzer über INFO GM informiert.
+  /// Liefert false zurück, wenn die GL im Modus "Sperren" einer anderen Session gehört, das Kommando
+  /// darf dann nicht ausgeführt werden.
+  /// # Arguments
+  /// * cmd_msg - Empfangenes SET Kommando
+  /// * adr - GL Adresse
+  fn uebernahme_gl(&mut self, cmd_msg: &SRCPMessage, adr: u32) -> bool {
+    if self.gl_uebernahme == GLUebernahme::Keine {
+      return true;
+    }
+    let Some(session_id) = cmd_msg.session_id.filter(|&id| id != 0) else {
+      return true;
+    };
+    if let Some(&besitzer) = self.gl_besitzer.get(&adr) {
+      if besitzer != session_id {
+        if self.gl_uebernahme == GLUebernahme::Sperren {
+          //Zwischen Validierung und Ausführung hat eine andere Session die GL übernommen
+          warn!(
+            "GL {} gehört Session {}, SET von Session {} ignoriert",
+            adr, besitzer, session_id
+          );
+          return false;
+        }
+        self.srcp_info_gl_uebernahme(besitzer, session_id, adr);
+      }
+    }
+    self.gl_besitzer.insert(adr, session_id);
+    true
+  }
+
+  /// Übernahme einer GL durch eine andere Session dem bisherigen Besitzer als SRCP Message über Info melden
+  /// # Arguments
+  /// * besitzer - Session ID des bisherigen Besitzers
+  /// * session_id - Session ID des neuen Besitzers
+  /// * adr - GL Adresse
+  fn srcp_info_gl_uebernahme(&self, besitzer: u32, session_id: u32, adr: u32) {
+    //INFO <bus> GM <send_to> <reply_to> <MSGTYPE> <MESSAGE>
+    // - bus: aktueller Bus, Abweichung von SRCP Spezifikation, die GM nur für Bus 0 erlaubt!
+    // - send_to: bisheriger Besitzer, reply_to: neuer Besitzer
+    // - MSGTYPE: SRCP_GL_TAKEOVER
+    // - MESSAGE: GL Adresse
+    //An alle Info Clients, der Client des bisherigen Besitzers erkennt die Message an <send_to>
+    self.tx.send(SRCPMessage::new(
+      None,
+      self.bus,
+      SRCPMessageID::Info {
+        info_code: "100".to_string(),
+      },
+      SRCPMessageDevice::GM,
+      vec![
+        besitzer.to_string(),
+        session_id.to_string(),
+        "SRCP_GL_TAKEOVER".to_string(),
+        adr.to_string(),
+      ],
+    ));
+  }
+
+  /// Geschwindigkeit über die Kennlinie der GL in die zu sendende Fahrstufe umrechnen.
+  /// Ohne Kennlinie wird die Geschwindigkeit unverändert übernommen.
+  /// Die Kennlinie besteht aus gleichmässig über 0 bis "speedsteps" verteilten Stützpunkten
+  /// (0..255 = 0..100% der Fahrstufen), dazwischen wird linear interpoliert.
+  /// Eine Geschwindigkeit > 0 führt immer zu min. Fahrstufe 1, angehalten wird nur mit 0.
+  /// # Arguments
+  /// * adr - GL Adresse
+  /// * speed - Geschwindigkeit 0..speedsteps wie vom SRCP Client verlangt
+  /// * speedsteps - Anzahl Fahrstufen der GL
+  fn get_speed_kennlinie(&self, adr: u32, speed: usize, speedsteps: usize) -> usize {
+    let Some(kurve) = self.gl_speedcurve.get(&adr) else {
+      return speed;
+    };
+    if (speed == 0) || (speedsteps == 0) {
+      return 0;
+    }
+    //Position auf der Kennlinie, Einheit 1/speedsteps Stützpunktabstand
+    let pos = speed.min(speedsteps) * (kurve.len() - 1);
+    let index = pos / speedsteps;
+    let rest = pos % speedsteps;
+    //Interpolierter Wert 0..255, multipliziert mit speedsteps
+    let wert = if index + 1 < kurve.len() {
+      (kurve[index] as usize * (speedsteps - rest)) + (kurve[index + 1] as usize * rest)
+    } else {
+      kurve[index] as usize * speedsteps
+    };
+    //Runden auf nächste Fahrstufe
+    ((wert + 127) / 255).clamp(1, speedsteps)
+  }
+
+  /// Nach Power On alle GL's der Protokolle, die das verlangen (z.B. MFX SID Zuordnung), mit ihrem
+  /// letzten Zustand senden, bevor Kommandos und der normale Refresh weiterlaufen.
+  /// Macht nichts, wenn seit dem letzten Aufruf kein Power Off war.
+  fn send_power_on_resync(&mut self) {
+    if !self.power_on_resync {
+      return;
+    }
+    self.power_on_resync = false;
+    for (protokoll, prot_versionen) in &self.all_protokolle.clone() {
+      for (version, prot_impl) in prot_versionen {
+        if !prot_impl.borrow_mut().power_on_resync() {
+          continue;
+        }
+        let mut adressen: Vec<u32> = self
+          .all_gl
+          .iter()
+          .filter(|(_, gl)| (gl.protokoll == *protokoll) && (gl.protokoll_version == *version))
+          .map(|(adr, _)| *adr)
+          .collect();
+        adressen.sort();
+        for adr in adressen {
+          //Vollständiger Zustand inkl. aller Funktionen, doppelt
+          self.send_gl_tel(adr, true, true);
+        }
+      }
+    }
+  }
+
+  /// Nothalt für alle initialisierten GL's, die Funktionen bleiben unverändert.
+  fn nothalt_alle_gl(&mut self) {
+    let alle_gl: Vec<(u32, u64)> = self
+      .all_gl
+      .iter()
+      .map(|(adr, gl)| (*adr, gl.fnkt))
+      .collect();
+    for (adr, fnkt) in alle_gl {
+      self.send_gl(adr, GLDriveMode::Nothalt, 0, 1, fnkt, false);
+    }
+  }
+
   /// Versenden Telegram einer GL.
   /// # Arguments
   /// * adr - GA Adresse
@@ -243,6 +765,14 @@ impl DdlGL<'_> {
   ///             Wenn true: es wird immer allles versendet (Lok in Refresh Zyklus)
   fn send_gl_tel(&mut self, adr: u32, doppelt: bool, refresh: bool) {
     let gl = &self.all_gl[&adr];
+    //Zu sendende Fahrstufe gemäss Kennlinie und Begrenzung
+    let speed = self.get_speed_kennlinie(adr, gl.speed, gl.protokoll_speedsteps);
+    let speed = self
+      .gl_speedmax
+      .get(&adr)
+      .map_or(speed, |&speed_max| speed.min(speed_max));
+    //Zu sendende Funktionen gemäss Funktionsumleitung
+    let fnkt = gl.get_fnkt_physisch();
     //Passendes Protokoll / Version suchen
     let mut protokoll = self
       .all_protokolle
@@ -252,39 +782,186 @@ impl DdlGL<'_> {
       .unwrap()
       .borrow_mut();
     //Basis GL Telegram erzeugen und zum Booster Versenden
-    let mut ddl_tel = protokoll.get_gl_new_tel(adr, refresh, gl.trigger);
+    let mut ddl_tel = protokoll.get_gl_new_tel(
+      adr,
+      TelOptions {
+        refresh,
+        trigger: gl.trigger,
+      },
+    );
     if doppelt {
       ddl_tel.tel_wiederholungen *= 2;
     }
     protokoll.get_gl_basis_tel(
       adr,
       gl.direction,
-      gl.speed,
+      speed,
       gl.protokoll_speedsteps,
-      gl.fnkt,
+      fnkt,
       &mut ddl_tel,
     );
     //Zusatztelegramm mit weiteren Fx wenn sich diese verändert haben
-    protokoll.get_gl_zusatz_tel(adr, refresh, gl.fnkt, &mut ddl_tel);
+    protokoll.get_gl_zusatz_tel(adr, refresh, fnkt, &mut ddl_tel);
     drop(protokoll);
+    self.update_statistik(adr, &ddl_tel, refresh);
     self.send_tel(&mut ddl_tel);
   }
+  /// Refresh Statistik einer GL beim Senden eines Telegrammes nachführen
+  /// # Arguments
+  /// * adr - GL Adresse
+  /// * ddl_tel - Das zu sendende Telegramm
+  /// * refresh - Telegramm aus dem Refreshzyklus
+  fn update_statistik(&mut self, adr: u32, ddl_tel: &DdlTel, refresh: bool) {
+    let jetzt = Instant::now();
+    let protokoll = self.all_gl[&adr].protokoll;
+    let statistik = self
+      .gl_statistik
+      .entry(adr)
+      .or_insert_with(|| GLRefreshStatistik::new(protokoll));
+    //Mit neuem INIT auf anderes Protokoll gewechselt -> Statistik neu beginnen
+    if statistik.protokoll != protokoll {
+      *statistik = GLRefreshStatistik::new(protokoll);
+    }
+    if let Some(letztes_tel) = statistik.letztes_tel {
+      let luecke = jetzt - letztes_tel;
+      statistik.mittel = if statistik.mittel.is_zero() {
+        luecke
+      } else {
+        (statistik.mittel * 7 + luecke) / 8
+      };
+      statistik.max = statistik.max.max(luecke);
+    }
+    statistik.letztes_tel = Some(jetzt);
+    let anzahl = ddl_tel.daten.len() as u64;
+    statistik.telegramme += anzahl;
+    statistik.wiederholungen += anzahl * ddl_tel.tel_wiederholungen.saturating_sub(1) as u64;
+    if refresh {
+      statistik.letzter_refresh = Some(jetzt);
+    }
+  }
+
+  /// Telegrammstatistik einer GL an eine Session senden
+  /// INFO 190 <bus> GL <addr> STATS <protokoll> <telegramme> <wiederholungen> <ms seit letztem Refresh> <mittel ms> <max ms>
+  /// Ms seit letztem Refresh ist -1, wenn noch kein Refresh gesendet wurde.
+  /// # Arguments
+  /// * session_id - Session an die die Statistik gesendet wird
+  /// * adr - GL Adresse
+  fn send_info_statistik(&self, session_id: Option<u32>, adr: u32) {
+    let Some(gl) = self.all_gl.get(&adr) else {
+      return;
+    };
+    let leer = GLRefreshStatistik::new(gl.protokoll);
+    let statistik = self.gl_statistik.get(&adr).unwrap_or(&leer);
+    self.tx.send(SRCPMessage::new(
+      session_id,
+      self.bus,
+      SRCPMessageID::Info {
+        info_code: INFO_ERWEITERUNG.to_string(),
+      },
+      SRCPMessageDevice::GL,
+      vec![
+        adr.to_string(),
+        GL_STATISTIK.to_string(),
+        statistik.protokoll.to_string(),
+        statistik.telegramme.to_string(),
+        statistik.wiederholungen.to_string(),
+        statistik
+          .letzter_refresh
+          .map_or("-1".to_string(), |refresh| {
+            refresh.elapsed().as_millis().to_string()
+          }),
+        statistik.mittel.as_millis().to_string(),
+        statistik.max.as_millis().to_string(),
+      ],
+    ));
+  }
+
+  /// Ende eines Refreshzyklus über alle GL's: Zykluszeit nachführen und periodisch die
+  /// Refresh Statistik pro Protokoll ausgeben.
+  /// Warnung, wenn der Refreshzyklus durch DCC 5ms oder MM5 50ms Pausen über "REFRESH_ZYKLUS_WARNUNG" liegt.
+  fn refresh_zyklus_ende(&mut self) {
+    self.refresh_zyklus_dauer = self.refresh_zyklus_start.elapsed();
+    self.refresh_zyklus_start = Instant::now();
+    if self.refresh_zyklus_dauer > self.refresh_zyklus_max.0 {
+      self.refresh_zyklus_max = (self.refresh_zyklus_dauer, self.refresh_zyklus_delay);
+    }
+    self.refresh_zyklus_delay = Duration::ZERO;
+    if self.statistik_start.elapsed() < STATISTIK_INTERVALL {
+      return;
+    }
+    //Pro Protokoll: Anzahl GL's, Summe Mittelwerte, Max. Lücke mit GL Adresse
+    let mut pro_protokoll: HashMap<DdlProtokolle, (u32, Duration, Duration, u32)> = HashMap::new();
+    for (adr, statistik) in &self.gl_statistik {
+      let Some(gl) = self.all_gl.get(adr) else {
+        continue;
+      };
+      debug!(
+        "GL {} {}: {} Telegramme, {} Wiederholungen, Refresh Mittel {} ms, Max {} ms",
+        adr,
+        statistik.protokoll.to_string(),
+        statistik.telegramme,
+        statistik.wiederholungen,
+        statistik.mittel.as_millis(),
+        statistik.max.as_millis()
+      );
+      let werte =
+        pro_protokoll
+          .entry(gl.protokoll)
+          .or_insert((0, Duration::ZERO, Duration::ZERO, 0));
+      werte.0 += 1;
+      werte.1 += statistik.mittel;
+      if statistik.max >= werte.2 {
+        werte.2 = statistik.max;
+        werte.3 = *adr;
+      }
+    }
+    for (protokoll, (anzahl, summe, max, max_adr)) in pro_protokoll {
+      info!(
+        "GL Refresh {}: {} GL's, Mittel {} ms, Max {} ms (GL {})",
+        protokoll.to_string(),
+        anzahl,
+        (summe / anzahl).as_millis(),
+        max.as_millis(),
+        max_adr
+      );
+    }
+    let (zyklus_max, zyklus_max_delay) = self.refresh_zyklus_max;
+    if (zyklus_max > REFRESH_ZYKLUS_WARNUNG) && !zyklus_max_delay.is_zero() {
+      warn!(
+        "GL Refreshzyklus {} ms, davon {} ms Wartezeit für DCC 5ms / MM5 50ms Pausen",
+        zyklus_max.as_millis(),
+        zyklus_max_delay.as_millis()
+      );
+    }
+    //Neues Statistikintervall
+    for statistik in self.gl_statistik.values_mut() {
+      statistik.max = Duration::ZERO;
+    }
+    self.refresh_zyklus_max = (Duration::ZERO, Duration::ZERO);
+    self.statistik_start = Instant::now();
+  }
+
   /// Senden von GL Telegrammen.
   /// Bis "MIN_ANZ_GL_NO_DELAY" Anzahl initalisierter GL's wird mit Wartezeit zwischen Telegrammen in einem Paket
   /// gearbeitet.
   /// Ab dieser Anzahl GL's über Buffer mit einschieben eines anderen Telegramms optimiert.
+  /// Ein Delay nur für das 2. Telegramm (MM5 50ms) wird nie blockierend gewartet, sondern immer über
+  /// den Buffer (instant_next) geplant.
   /// # Arguments
   /// * ddl_tel - Das Telegramm, das gesendet werden soll.
   fn send_tel(&mut self, ddl_tel: &mut DdlTel) {
     while ddl_tel.daten.len() > 0 {
-      <DdlGL<'_> as SRCPDeviceDDL>::send(self.spidev, ddl_tel, self.trigger_port);
+      self.ausgabe.send(ddl_tel, self.trigger_port);
 
-      //Direktes weitersenden wenn nicht genügend GL's vorhanden sind oder wenn kein Delay verlangt wird.
+      //Direktes weitersenden wenn kein Delay verlangt wird oder wenn nicht genügend GL's vorhanden sind
+      //und es kein MM5 Delay ist.
       if (ddl_tel.daten.len() > 0)
-        && ((self.all_gl.len() < MIN_ANZ_GL_NO_DELAY) || ddl_tel.delay.is_zero())
+        && (ddl_tel.delay.is_zero()
+          || ((self.all_gl.len() < MIN_ANZ_GL_NO_DELAY) && !ddl_tel.delay_only2nd))
       {
         if (!ddl_tel.delay.is_zero()) && (ddl_tel.daten.len() > 0) {
           thread::sleep(ddl_tel.delay);
+          self.refresh_zyklus_delay += ddl_tel.delay;
         }
       } else {
         //Wenn ein delay vorhanden ist und dieser nur auf das 2. Telegramm wirken soll, dann kann er jetzt sicher weg
@@ -309,21 +986,57 @@ impl DdlGL<'_> {
   /// 50ms bei MM5 zwischen den beiden Telegrammen für 28 v Stufen.
   /// Es wird immer der ganze Buffer abgearbeitet und alles, was möglich ist, gesendet.
   /// Abbruch erfolgt erst dann, wenn Buffer leer ist oder in einem Durchgang gar nichts gesendet werden konnte.
-  fn send_buffer(&mut self) {
+  /// Liefert true zurück, wenn min. ein Telegramm gesendet wurde.
+  fn send_buffer(&mut self) -> bool {
+    let mut gesendet = false;
     let mut done = false;
     while !done {
       done = true;
       for ddl_tel in self.tel_buffer.iter_mut() {
         if ddl_tel.instant_next.unwrap() <= Instant::now() {
-          <DdlGL<'_> as SRCPDeviceDDL>::send(self.spidev, ddl_tel, self.trigger_port);
+          self.ausgabe.send(ddl_tel, self.trigger_port);
           done = false;
         }
       }
       if !done {
         //Es wurde etwas gesendet, alle nun leeren Telegramme löschen
         self.tel_buffer.retain(|ddl_tel| !ddl_tel.daten.is_empty());
+        gesendet = true;
       }
     }
+    gesendet
+  }
+
+  /// Idle Telegramm eines Protokolls senden, bei jedem Aufruf von der nächsten Version des Protokolls.
+  /// Liefert true zurück, wenn ein Idle Telegramm gesendet wurde.
+  /// # Arguments
+  /// * protokoll - Protokoll, dessen Idle Telegramm gesendet werden soll
+  fn send_idle_tel(&mut self, protokoll: DdlProtokolle) -> bool {
+    let idle_tel = {
+      let Some(versionen) = self.all_protokolle.get(&protokoll) else {
+        return false;
+      };
+      //Sortiert, damit die Rotation nicht von der Reihenfolge in der HashMap abhängt
+      let mut alle_versionen: Vec<&&str> = versionen.keys().collect();
+      alle_versionen.sort();
+      let index = self.idle_version.entry(protokoll).or_insert(0);
+      let version = alle_versionen[*index % alle_versionen.len()];
+      *index = (*index + 1) % alle_versionen.len();
+      versionen[version].borrow_mut().get_idle_tel()
+    };
+    if let Some(mut tel) = idle_tel {
+      self.send_tel(&mut tel);
+      true
+    } else {
+      false
+    }
+  }
+
+  /// Prüft, ob für eine GL noch verzögert zu sendende Telegramme im Buffer sind (z.B. MM5 2. Telegramm).
+  /// # Arguments
+  /// * adr - GL Adresse
+  fn is_tel_buffer_adr(&self, adr: u32) -> bool {
+    self.tel_buffer.iter().any(|ddl_tel| ddl_tel.adr == adr)
   }
 
   /// Ermittlung, durch wieviele GL's ein Protokoll verwendet wird
@@ -372,38 +1085,92 @@ impl DdlGL<'_> {
   /// * protokoll - Protokollname das zu dieser Information geführt hat.
   /// * message - Message die versendet werdne soll
   fn srcp_info_new_gl_state(&self, protokoll: &String, message: &String) {
+    self.srcp_info_gm(None, "SRCP_GL_REGISTRATON", protokoll, message);
+  }
+
+  /// Veränderung der Anwesenheit eines GL Dekoders als SRCP Message über Info melden
+  /// # Arguments
+  /// * protokoll - Protokollname das zu dieser Information geführt hat.
+  /// * message - Message die versendet werdne soll
+  fn srcp_info_gl_presence(&self, protokoll: &String, message: &String) {
+    self.srcp_info_gm(None, "SRCP_GL_PRESENCE", protokoll, message);
+  }
+
+  /// GM Info senden
+  /// # Arguments
+  /// * session_id - None: an alle SRCP Info Clients, sonst nur an den mit SessionID
+  /// * msg_type - SRCP GM Messagetyp
+  /// * protokoll - Protokollname das zu dieser Information geführt hat.
+  /// * message - Message die versendet werdne soll
+  fn srcp_info_gm(
+    &self, session_id: Option<u32>, msg_type: &str, protokoll: &String, message: &String,
+  ) {
     //INFO <bus> GM <send_to> <reply_to> <MSGTYPE> <MESSAGE>
     // - bus: aktueller Bus, Abweichung von SRCP Spezifikation, die GM nur für Bus 0 erlaubt!
-    // - send_to, reply_to: immer 0
-    // - MSGTYPE: SRCP_GL_REGISTRATON
-    // - MESSAGE: Info über Zustand der automatischen GL Anmeldung im Format "<protokoll_id>:<Message>"
+    // - send_to: Session ID des Empfängers, 0 an alle
+    // - reply_to: immer 0
+    // - MSGTYPE: z.B. SRCP_GL_REGISTRATON
+    // - MESSAGE: Info im Format "<protokoll_id>:<Message>"
     //Alles nach GM sind Parameter
     let mut parameter: Vec<String> = vec![];
-    parameter.push("0".to_string()); //send_to
+    parameter.push(session_id.unwrap_or(0).to_string()); //send_to
     parameter.push("0".to_string()); //reply_to
-    parameter.push("SRCP_GL_REGISTRATON".to_string());
+    parameter.push(msg_type.to_string());
     parameter.push(format!("{}:\"{}\"", protokoll, message));
-    self
-      .tx
-      .send(SRCPMessage::new(
-        None,
-        self.bus,
-        SRCPMessageID::Info {
-          info_code: "100".to_string(),
-        },
-        SRCPMessageDevice::GM,
-        parameter,
-      ))
-      .unwrap();
+    self.tx.send(SRCPMessage::new(
+      session_id,
+      self.bus,
+      SRCPMessageID::Info {
+        info_code: "100".to_string(),
+      },
+      SRCPMessageDevice::GM,
+      parameter,
+    ));
   }
 
   /// Neue GL als SRCP Info melden
   /// # Arguments
+  /// * session_id - None: an alle SRCP Info Clients, sonst nur an den mit SessionID
   /// * adr - Adresse der GL.
   /// * new_gl - Neue GL die gemeldet werden soll
-  fn srcp_info_new_gl(&mut self, adr: u32, new_gl: &GLInit) {
+  fn srcp_info_new_gl(&self, session_id: Option<u32>, adr: u32, new_gl: &GLInit) {
     //INFO <bus> GL <adr> <protokoll> <protocolversion> <decoderspeedsteps> <numberofdecoderfunctions> .....
-    //Alles nach GL sind Parameter
+    self.tx.send(SRCPMessage::new(
+      session_id,
+      self.bus,
+      SRCPMessageID::Info {
+        info_code: "101".to_string(),
+      },
+      SRCPMessageDevice::GL,
+      DdlGL::get_init_parameter(adr, new_gl),
+    ));
+  }
+
+  /// INIT Parameter einer GL an eine Session senden (GET <bus> GL <addr> INIT)
+  /// INFO 190 <bus> GL <addr> INIT <protokoll> <protocolversion> <decoderspeedsteps> <numberofdecoderfunctions> .....
+  /// # Arguments
+  /// * session_id - Session an die die INIT Parameter gesendet werden
+  /// * adr - Adresse der GL.
+  /// * gl - Die GL
+  fn send_info_init(&self, session_id: Option<u32>, adr: u32, gl: &GLInit) {
+    let mut parameter = DdlGL::get_init_parameter(adr, gl);
+    parameter.insert(1, GL_INIT.to_string());
+    self.tx.send(SRCPMessage::new(
+      session_id,
+      self.bus,
+      SRCPMessageID::Info {
+        info_code: INFO_ERWEITERUNG.to_string(),
+      },
+      SRCPMessageDevice::GL,
+      parameter,
+    ));
+  }
+
+  /// Liefert die Parameter von INFO 101 einer GL: <adr> <protokoll> <protocolversion> <decoderspeedsteps> ...
+  /// # Arguments
+  /// * adr - Adresse der GL.
+  /// * new_gl - Die GL
+  fn get_init_parameter(adr: u32, new_gl: &GLInit) -> Vec<String> {
     let mut parameter: Vec<String> = vec![];
     parameter.push(adr.to_string());
     parameter.push(new_gl.protokoll.to_string());
@@ -414,19 +1181,210 @@ impl DdlGL<'_> {
       parameter.push(uid.to_string());
     }
     parameter.extend(new_gl.param.clone());
+    parameter
+  }
 
+  /// GL entfernen (TERM, Protokoll entfernt): alle zugehörigen Zustände und noch verzögerte Telegramme
+  /// löschen, Refreshzyklus mit der nachfolgenden GL fortsetzen und INFO 102 an alle senden.
+  /// Liefert die entfernte GL zurück, None wenn sie nicht vorhanden war.
+  /// # Arguments
+  /// * adr - Adresse der GL
+  fn remove_gl(&mut self, adr: u32) -> Option<GLInit> {
+    if self.adr_refresh == adr {
+      //Vorgängerin im Refreshzyklus, damit mit der nachfolgenden GL weitergefahren wird. 0 wenn es die Erste war.
+      self.adr_refresh = self
+        .all_gl
+        .keys()
+        .take_while(|gl_adr| **gl_adr != adr)
+        .last()
+        .copied()
+        .unwrap_or(0);
+    }
+    let gl = self.all_gl.remove(&adr)?;
+    self.gl_besitzer.remove(&adr);
+    self.gl_statistik.remove(&adr);
     self
-      .tx
-      .send(SRCPMessage::new(
-        None,
-        self.bus,
-        SRCPMessageID::Info {
-          info_code: "101".to_string(),
+      .all_fnkt_delay
+      .retain(|fnkt_delay| fnkt_delay.adr != adr);
+    self.tel_buffer.retain(|ddl_tel| ddl_tel.adr != adr);
+    if self.gl_param_read == Some(adr) {
+      self.gl_param_read = None;
+    }
+    //INFO <bus> GL <addr>
+    self.tx.send(SRCPMessage::new(
+      None,
+      self.bus,
+      SRCPMessageID::Info {
+        info_code: "102".to_string(),
+      },
+      SRCPMessageDevice::GL,
+      vec![adr.to_string()],
+    ));
+    Some(gl)
+  }
+
+  /// Prüft, ob ein erneutes INIT einer bestehenden GL das Gleisprotokoll wechseln würde während die GL fährt.
+  /// # Arguments
+  /// * adr - Adresse der GL
+  /// * protokoll - Protokoll des neuen INIT
+  fn is_protokollwechsel_in_fahrt(&self, adr: u32, protokoll: DdlProtokolle) -> bool {
+    let Some(gl) = self.all_gl.get(&adr) else {
+      return false;
+    };
+    if (gl.protokoll != protokoll) && (gl.speed > 0) {
+      warn!(
+        "GL {}: Protokollwechsel {} -> {} während Fahrt abgelehnt",
+        adr,
+        gl.protokoll.to_string(),
+        protokoll.to_string()
+      );
+      true
+    } else {
+      false
+    }
+  }
+
+  /// Veränderung der Anwesenheit eines GL Dekoders melden.
+  /// Bei freigegebener Adresse wird die GL entfernt.
+  /// # Arguments
+  /// * protokoll - Protokoll das die Veränderung gemeldet hat
+  /// * anwesenheit - Veränderung
+  fn eval_gl_anwesenheit(&mut self, protokoll: DdlProtokolle, anwesenheit: GLAnwesenheit) {
+    let (adr, message) = match anwesenheit {
+      GLAnwesenheit::Vorhanden(adr) => (adr, format!("GL {} wieder vorhanden", adr)),
+      GLAnwesenheit::Abwesend(adr) => (adr, format!("GL {} abwesend", adr)),
+      GLAnwesenheit::Freigegeben(adr) => (adr, format!("GL {} abwesend, entfernt", adr)),
+    };
+    //Nur für GL's die es (noch) gibt
+    if !self.all_gl.contains_key(&adr) {
+      return;
+    }
+    self.srcp_info_gl_presence(&protokoll.to_string(), &message);
+    if let GLAnwesenheit::Freigegeben(_) = anwesenheit {
+      self.remove_gl(adr);
+      self.update_idle_protokoll(protokoll);
+    }
+  }
+
+  /// Ein Protokoll könnte wieder Idle geworden sein (TERM, Protokollwechsel einer GL).
+  /// Märklin & DCC bei < IDLE_COUNT_MM_DCC GL's, siehe INIT.
+  /// # Arguments
+  /// * protokoll - Protokoll, das nicht mehr von einer GL verwendet wird
+  fn update_idle_protokoll(&mut self, protokoll: DdlProtokolle) {
+    let prot_count = self.count_protokoll(protokoll);
+    if (prot_count == 0)
+      || (((protokoll == DdlProtokolle::Maerklin) || (protokoll == DdlProtokolle::Dcc))
+        && (prot_count < IDLE_COUNT_MM_DCC))
+    {
+      //Es ist Idle
+      if !self.all_idle_protokolle.contains(&protokoll) {
+        self.all_idle_protokolle.push(protokoll);
+      }
+    }
+  }
+
+  /// Prüft ob noch eine weitere GL initialisiert werden darf.
+  /// Wenn nicht wird eine Warnung mit der aktuellen Refreshzykluszeit ausgegeben.
+  /// # Arguments
+  /// * adr - Adresse der neuen GL
+  fn is_gl_max_erreicht(&self, adr: u32) -> bool {
+    match self.gl_max {
+      Some(gl_max) if self.all_gl.len() >= gl_max => {
+        warn!(
+          "GL: max. Anzahl {} GL's erreicht, GL {} abgelehnt. Aktueller Refreshzyklus {} ms",
+          gl_max,
+          adr,
+          self.refresh_zyklus_dauer.as_millis()
+        );
+        true
+      }
+      _ => false,
+    }
+  }
+
+  /// Neu gefundenen Dekoder anmelden.
+  /// Wenn es die GL mit dieser UID schon gibt, dann wird dessen Adresse verwendet, sonst wird die erste
+  /// freie Adresse zugewiesen.
+  /// Die Protokollimplementierung wird nur für die einzelnen Aufrufe ausgeliehen, nie während dem Senden.
+  /// Liefert true zurück, wenn eine neue GL angemeldet wurde (keine weitere parallele Anmeldung).
+  /// # Arguments
+  /// * protokoll - Protokoll, das den Dekoder gefunden hat
+  /// * version - Protokollversion
+  /// * prot_impl - Protokollimplementierung
+  /// * uid - UID des gefundenen Dekoders
+  /// * power - true: Power / Booster ist ein
+  fn neu_anmeldung_gl(
+    &mut self, protokoll: DdlProtokolle, version: &str, prot_impl: &Rc<RefCell<dyn DdlProtokoll>>,
+    uid: u32, power: bool,
+  ) -> bool {
+    let max_adr = prot_impl.borrow().get_gl_max_adr();
+    //Noch nicht angemeldeter Dekoder gefunden.
+    //Wenn es die GL mit dieser UID schon gibt, dann wird dessen Adressen verwendet.
+    let bekannte_gl = self
+      .all_gl
+      .iter()
+      .filter(|(adr, gl)| (**adr <= max_adr) && (gl.protokoll_uid == Some(uid)))
+      .map(|(adr, gl)| (*adr, gl.protokoll_number_functions))
+      .min_by_key(|(adr, _)| *adr);
+    if let Some((adr, number_functions)) = bekannte_gl {
+      //Lok gibt es bereits, neue SID Zuordnung auslösen
+      info!("GL: bekannte Lok gefunden UID={}, Adr={}", uid, adr);
+      //Protokollabhängige Aktionen wie SID Zuordnung versenden auslösen
+      let init_tel = prot_impl.borrow_mut().init_gl(
+        adr,
+        &GlInitParams {
+          uid: Some(uid),
+          funk_anz: number_functions,
+          power,
+          trigger: self.trigger.contains(&adr),
         },
-        SRCPMessageDevice::GL,
-        parameter,
-      ))
-      .unwrap();
+      );
+      if let Some(mut ddl_tel) = init_tel {
+        self.send_tel(&mut ddl_tel);
+      }
+      return false;
+    }
+    //Ansonsten die erste freie GL Adresse zuweisen und Initialisieren.
+    let Some(adr) = (1..=max_adr).find(|adr| !self.all_gl.contains_key(adr)) else {
+      return false;
+    };
+    if self.is_gl_max_erreicht(adr) {
+      return false;
+    }
+    info!("GL: neue Lok gefunden UID={}, Adr={}", uid, adr);
+    let (init_tel, speed_steps, anz_f) = {
+      let mut p = prot_impl.borrow_mut();
+      //Es werden mal die im Basistel. enthalten Funktionen als vorhanden angenommen (bei MFX 16).
+      let anz_f_basis = p.get_gl_anz_f_basis();
+      //Freie Adresse gefunden, Protokollabhängige Aktionen wie SID Zuordnung versenden auslösen
+      let init_tel = p.init_gl(
+        adr,
+        &GlInitParams {
+          uid: Some(uid),
+          funk_anz: anz_f_basis,
+          power,
+          trigger: self.trigger.contains(&adr),
+        },
+      );
+      (init_tel, p.get_gl_max_speed_steps(), p.get_gl_anz_f())
+    };
+    if let Some(mut ddl_tel) = init_tel {
+      self.send_tel(&mut ddl_tel);
+    }
+    //GL mal anmelden, jeweils max. vom Protokoll unterstützte Parameter verwenden
+    self.register_new_gl(
+      adr,
+      &protokoll,
+      version,
+      speed_steps,
+      anz_f,
+      Some(uid),
+      &Vec::new(), //Noch keine weiteren Parameter bekannt.
+    );
+    //Neue GL ist mal angemeldet, kann prinzipiell verwendet werden.
+    //Bevor sie über SRCP INFO gemeldet wird, wird noch versucht optionale Parameter auszulesen.
+    self.gl_param_read = Some(adr);
+    true
   }
 }
 
@@ -438,166 +1396,228 @@ impl SRCPDeviceDDL for DdlGL<'_> {
   /// * cmd_msg - Empfangenes Kommando
   fn validate_cmd(&self, cmd_msg: &SRCPMessage) -> bool {
     let mut result = false;
-    //Für GL wird unterstützt: INIT, SET, GET
+    //Für GL wird unterstützt: INIT, TERM, SET, GET, VERIFY
     if let SRCPMessageID::Command { msg_type } = cmd_msg.message_id {
       match msg_type {
         SRCPMessageType::INIT => {
           //Format ist INIT <bus> GL <addr> <protocol> <optional further parameters>
           //Für <protocol> wird im Moment unterstützt:
-          // M <protocolversion> <decoderspeedsteps> <numberofdecoderfunctions> -> Märklin Motorola
-          // N <protocolversion> <decoderspeedsteps> <numberofdecoderfunctions> -> DCC
+          // M <protocolversion> <decoderspeedsteps> <numberofdecoderfunctions> [<name>] -> Märklin Motorola
+          // N <protocolversion> <decoderspeedsteps> <numberofdecoderfunctions> [<name>] -> DCC
           // X <protocolversion> <decoderspeedsteps> <numberofdecoderfunctions> <lokUid> <"lokname"> <mfxfunctioncode1> ... <mfxfunctioncode16> -> MFX
+          // Bei allen Protokollen optional als letzter Parameter Funktionsumleitung MAP=<logisch>:<physisch>,...
           //5 Parameter müssen vorhanden sein: <addr> <protocol> <protocolversion> <decoderspeedsteps> <numberofdecoderfunctions>
           if cmd_msg.parameter.len() >= 5 {
             //Zuerst das Protokoll
             if let Some(protokoll) = DdlProtokolle::from_str(cmd_msg.parameter[1].as_str()) {
               if let Some(protokolle_impl) = self.all_protokolle.get(&protokoll) {
                 if let Some(prot_impl) = protokolle_impl.get(cmd_msg.parameter[2].as_str()) {
-                  if prot_impl.borrow().uid() && (cmd_msg.parameter.len() < 6) {
-                    self
-                      .tx
-                      .send(SRCPMessage::new_err(cmd_msg, "419", "list too short"))
-                      .unwrap();
+                  let uid = prot_impl.borrow().uid();
+                  //Optionale Funktionsumleitung als letzter Parameter nach den zwingenden Parametern
+                  let fnkt_map = cmd_msg.parameter[5..]
+                    .last()
+                    .filter(|map| map.starts_with(GL_FNKT_MAP));
+                  let anz_param = cmd_msg.parameter.len() - usize::from(fnkt_map.is_some());
+                  if uid && (anz_param < 6) {
+                    self.send_err(cmd_msg, "419", "list too short", "lokUid".to_string());
+                  } else if !uid && (anz_param > 6) {
+                    //Ohne UID ist nach den zwingenden Parametern nur noch ein Name möglich
+                    self.send_err(
+                      cmd_msg,
+                      "418",
+                      "list too long",
+                      cmd_msg.parameter[6].clone(),
+                    );
                   } else {
                     //Adressprüfung
                     if let Ok(adr) = cmd_msg.parameter[0].parse::<u32>() {
-                      if (adr > 0) && (adr <= prot_impl.borrow_mut().get_gl_max_adr()) {
-                        //Alle weiteren Parameter ausser "lokname" bei MFX müssen Zahlen >=0 sein
+                      if self.is_protokollwechsel_in_fahrt(adr, protokoll) {
+                        //Wechsel des Gleisprotokolls während die Lok fährt
+                        self.tx.send(SRCPMessage::new_err(
+                          cmd_msg,
+                          "413",
+                          "temporarily prohibited",
+                        ));
+                      } else if !self.all_gl.contains_key(&adr) && self.is_gl_max_erreicht(adr) {
+                        //Neue GL, max. Anzahl GL's bereits erreicht
+                        self.tx.send(SRCPMessage::new_err(
+                          cmd_msg,
+                          "413",
+                          "temporarily prohibited",
+                        ));
+                      } else if (adr > 0) && (adr <= prot_impl.borrow_mut().get_gl_max_adr()) {
+                        //Alle weiteren Parameter ausser "lokname" bei MFX bzw. optionalem Name bei allen
+                        //anderen Protokollen müssen Zahlen >=0 sein
+                        let index_name = if uid { 6 } else { 5 };
                         result = true;
-                        for i in 3..cmd_msg.parameter.len() {
-                          if (i != 6) && (cmd_msg.parameter[i].parse::<u32>().is_err()) {
+                        for i in 3..anz_param {
+                          if (i != index_name) && (cmd_msg.parameter[i].parse::<u32>().is_err()) {
                             result = false;
-                            self
-                              .tx
-                              .send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"))
-                              .unwrap();
+                            self.send_err(
+                              cmd_msg,
+                              "412",
+                              "wrong value",
+                              format!(
+                                "{}={}",
+                                DdlGL::get_init_parameter_name(i, uid),
+                                cmd_msg.parameter[i]
+                              ),
+                            );
                             break;
                           }
                         }
+                        //Mehr Funktionen als SET übermitteln kann werden abgelehnt, zu viele für das Protokoll
+                        //werden beim INIT mit Warnung gekürzt (siehe "begrenze_funk_anz")
+                        if result
+                          && (cmd_msg.parameter[4].parse::<usize>().unwrap() > u64::BITS as usize)
+                        {
+                          result = false;
+                          self.send_err(
+                            cmd_msg,
+                            "412",
+                            "wrong value",
+                            format!("numberofdecoderfunctions={}", cmd_msg.parameter[4]),
+                          );
+                        }
+                        //Funktionsumleitung nur innerhalb der vorhandenen Funktionen
+                        if let Some(map) = fnkt_map.filter(|_| result) {
+                          let anz_f = cmd_msg.parameter[4].parse::<usize>().unwrap();
+                          if GLInit::eval_fnkt_map(map, anz_f).is_none() {
+                            result = false;
+                            self.send_err(cmd_msg, "412", "wrong value", map.clone());
+                          }
+                        }
                       } else {
-                        self
-                          .tx
-                          .send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"))
-                          .unwrap();
+                        self.send_err(cmd_msg, "412", "wrong value", format!("addr={}", adr));
                       }
                     } else {
-                      self
-                        .tx
-                        .send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"))
-                        .unwrap();
+                      self.send_err(
+                        cmd_msg,
+                        "412",
+                        "wrong value",
+                        format!("addr={}", cmd_msg.parameter[0]),
+                      );
                     }
                   }
                 } else {
-                  self
-                    .tx
-                    .send(SRCPMessage::new_err(
-                      cmd_msg,
-                      "420",
-                      "unsupported device protocol",
-                    ))
-                    .unwrap();
-                }
-              } else {
-                self
-                  .tx
-                  .send(SRCPMessage::new_err(
+                  self.send_err(
                     cmd_msg,
                     "420",
                     "unsupported device protocol",
-                  ))
-                  .unwrap();
-              }
-            } else {
-              self
-                .tx
-                .send(SRCPMessage::new_err(
+                    format!("protocolversion={}", cmd_msg.parameter[2]),
+                  );
+                }
+              } else {
+                self.send_err(
                   cmd_msg,
                   "420",
                   "unsupported device protocol",
-                ))
-                .unwrap();
+                  format!("protocol={}", cmd_msg.parameter[1]),
+                );
+              }
+            } else {
+              self.send_err(
+                cmd_msg,
+                "420",
+                "unsupported device protocol",
+                format!("protocol={}", cmd_msg.parameter[1]),
+              );
             }
           } else {
-            self
-              .tx
-              .send(SRCPMessage::new_err(cmd_msg, "419", "list too short"))
-              .unwrap();
+            self.send_err(
+              cmd_msg,
+              "419",
+              "list too short",
+              "numberofdecoderfunctions".to_string(),
+            );
           }
         }
         SRCPMessageType::TERM => {
           //Format ist TERM <bus> GL <addr>
           //Adressprüfung
-          if let Ok(adr) = cmd_msg.parameter[0].parse::<u32>() {
+          if cmd_msg.parameter.is_empty() {
+            self.send_err(cmd_msg, "419", "list too short", "addr".to_string());
+          } else if cmd_msg.parameter.len() > 1 {
+            self.send_err(
+              cmd_msg,
+              "418",
+              "list too long",
+              cmd_msg.parameter[1].clone(),
+            );
+          } else if let Ok(adr) = cmd_msg.parameter[0].parse::<u32>() {
             if self.all_gl.contains_key(&adr) {
               //OK an diese Session
-              self.tx.send(SRCPMessage::new_ok(cmd_msg, "200")).unwrap();
+              self.tx.send(SRCPMessage::new_ok(cmd_msg, "200"));
               result = true;
             } else {
-              self
-                .tx
-                .send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"))
-                .unwrap();
+              self.send_err(cmd_msg, "416", "no data", format!("addr={}", adr));
             }
           } else {
-            self
-              .tx
-              .send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"))
-              .unwrap();
+            self.send_err(
+              cmd_msg,
+              "412",
+              "wrong value",
+              format!("addr={}", cmd_msg.parameter[0]),
+            );
           }
         }
         SRCPMessageType::GET => {
-          //Format ist GET <bus> GL <addr>
+          //Format ist GET <bus> GL <addr> [STATS|INIT]
           if self.validate_get_set(cmd_msg, 1) {
-            result = true;
+            if cmd_msg.parameter.len() > 2 {
+              self.send_err(
+                cmd_msg,
+                "418",
+                "list too long",
+                cmd_msg.parameter[2].clone(),
+              );
+            } else if cmd_msg.parameter.len() > 1
+              && cmd_msg.parameter[1] != GL_STATISTIK
+              && cmd_msg.parameter[1] != GL_INIT
+            {
+              self.send_err(cmd_msg, "412", "wrong value", cmd_msg.parameter[1].clone());
+            } else {
+              result = true;
+            }
           }
         }
         SRCPMessageType::SET => {
           //Format ist SET <bus> GL <addr> <drivemode> <V> <V_max> <f0> . . <fn>
-          if self.validate_get_set(cmd_msg, 4) {
-            //Jetzt noch <drivemode> <V> <V_max> <f0> . . <fn>
-            if (cmd_msg.parameter[1] == "0"
-              || cmd_msg.parameter[1] == "1"
-              || cmd_msg.parameter[1] == "2")
-              && cmd_msg.parameter[2].parse::<u8>().is_ok()
-              && cmd_msg.parameter[3].parse::<u8>().is_ok()
-              && (cmd_msg.parameter[3].parse::<u8>().unwrap() > 0)
-            //vmax muss > 0 sein
-            {
+          //SET <bus> GL 0 2 <V> <V_max> ist Nothalt für alle GL's, weitere Parameter werden ignoriert
+          if cmd_msg.parameter.first().map(String::as_str) == Some("0") {
+            if cmd_msg.parameter.len() < 4 {
+              self.send_err(cmd_msg, "419", "list too short", "V_max".to_string());
+            } else if cmd_msg.parameter[1] != GLDriveMode::Nothalt.to_string() {
+              self.send_err(
+                cmd_msg,
+                "412",
+                "wrong value",
+                format!("drivemode={}", cmd_msg.parameter[1]),
+              );
+            } else {
               result = true;
-              //Wenn Funktionen vorhanden sind, dann müssen die alle 0 oder 1 sein
-              if cmd_msg.parameter.len() > 4 {
-                for i in 4..cmd_msg.parameter.len() {
-                  if (cmd_msg.parameter[i] != "0") && (cmd_msg.parameter[i] != "1") {
-                    result = false;
-                    self
-                      .tx
-                      .send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"))
-                      .unwrap();
-                  }
-                }
-              }
-              //OK wird bei SET bereits in Validate gesendet da SET Kommando bei Power Off zuerst in die Queue kommt.
-              if result {
-                self.tx.send(SRCPMessage::new_ok(cmd_msg, "200")).unwrap();
-              }
+              self.tx.send(SRCPMessage::new_ok(cmd_msg, "200"));
+            }
+          } else if self.validate_get_set(cmd_msg, 4) && self.validate_set_values(cmd_msg) {
+            if self.is_gl_gesperrt(cmd_msg) {
+              self.send_err(
+                cmd_msg,
+                "414",
+                "device locked",
+                format!("addr={}", cmd_msg.parameter[0]),
+              );
             } else {
-              self
-                .tx
-                .send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"))
-                .unwrap();
+              result = true;
+              //OK wird bei SET bereits in Validate gesendet da SET Kommando bei Power Off zuerst in die Queue kommt.
+              self.tx.send(SRCPMessage::new_ok(cmd_msg, "200"));
             }
           }
         }
         SRCPMessageType::VERIFY => {
-          //Verify wird für GL's nicht unterstützt
-          self
-            .tx
-            .send(SRCPMessage::new_err(
-              cmd_msg,
-              "423",
-              "unsupported operation",
-            ))
-            .unwrap();
+          //Format ist VERIFY <bus> GL <addr> <drivemode> <V> <V_max> <f0> . . <fn>
+          if self.validate_get_set(cmd_msg, 4) && self.validate_set_values(cmd_msg) {
+            result = true;
+          }
         }
       };
     }
@@ -608,18 +1628,22 @@ impl SRCPDeviceDDL for DdlGL<'_> {
   /// Das Kommando muss gültig sein (validate_cmd), es wird hier nicht mehr überprüft.
   /// # Arguments
   /// * cmd_msg - Empfangenes Kommando
-  /// * power - true wenn Power eingeschaltet, Booster On sind, hier nicht verwendet
-  fn execute_cmd(&mut self, cmd_msg: &SRCPMessage, _power: bool) {
+  /// * power - true wenn Power eingeschaltet, Booster On sind
+  fn execute_cmd(&mut self, cmd_msg: &SRCPMessage, power: bool) {
     let SRCPMessageID::Command { msg_type } = cmd_msg.message_id else {
       return;
     };
+    if power {
+      self.send_power_on_resync();
+    }
     match msg_type {
       SRCPMessageType::INIT => {
         //Format ist INIT <bus> GL <addr> <protocol> <optional further parameters>
         //Für <protocol> wird im Moment unterstützt:
-        // M <protocolversion> <decoderspeedsteps> <numberofdecoderfunctions> -> Märklin Motorola
-        // N <protocolversion> <decoderspeedsteps> <numberofdecoderfunctions> -> DCC
+        // M <protocolversion> <decoderspeedsteps> <numberofdecoderfunctions> [<name>] -> Märklin Motorola
+        // N <protocolversion> <decoderspeedsteps> <numberofdecoderfunctions> [<name>] -> DCC
         // X <protocolversion> <decoderspeedsteps> <numberofdecoderfunctions> <lokUid> <"lokname"> <mfxfunctioncode1> ... <mfxfunctioncode16> -> MFX
+        // Bei allen Protokollen optional als letzter Parameter Funktionsumleitung MAP=<logisch>:<physisch>,...
         //Adresse
         let adr = cmd_msg.parameter[0].parse::<u32>().unwrap();
         //Das Protokoll
@@ -647,15 +1671,20 @@ impl SRCPDeviceDDL for DdlGL<'_> {
           if protokoll.uid() {
             uid = Some(cmd_msg.parameter[5].parse::<u32>().unwrap());
           }
+          //Annahme Power Off, eventuell notwendiges Init-Tel kommt mit nächstem GL Tel.
           protokoll.init_gl(
             adr,
-            uid,
-            protokoll_number_functions,
-            false,
-            self.trigger.contains(&adr),
-          ); //Annahme Power Off, eventuell notwendiges Init-Tel kommt mit nächstem GL Tel.
+            &GlInitParams {
+              uid,
+              funk_anz: protokoll_number_functions,
+              power: false,
+              trigger: self.trigger.contains(&adr),
+            },
+          );
         }
 
+        //Bei erneutem INIT einer bestehenden GL bisheriges Protokoll
+        let alt_protokoll = self.all_gl.get(&adr).map(|gl| gl.protokoll);
         let new_gl = self
           .register_new_gl(
             adr,
@@ -667,9 +1696,9 @@ impl SRCPDeviceDDL for DdlGL<'_> {
             &cmd_msg.parameter[5..].to_vec(), //Alle Paramater ab UID
           )
           .clone();
-        self.srcp_info_new_gl(adr, &new_gl);
+        self.srcp_info_new_gl(None, adr, &new_gl);
         //OK an diese Session
-        self.tx.send(SRCPMessage::new_ok(cmd_msg, "200")).unwrap();
+        self.tx.send(SRCPMessage::new_ok(cmd_msg, "200"));
         //Das hier verwendete Protokoll ist nicht mehr Idle
         let index_used_prot = self
           .all_idle_protokolle
@@ -689,53 +1718,76 @@ impl SRCPDeviceDDL for DdlGL<'_> {
             self.all_idle_protokolle.remove(i);
           }
         }
+        if let Some(alt_protokoll) = alt_protokoll {
+          if alt_protokoll != protokoll {
+            //Protokollwechsel: noch verzögerte Telegramme im alten Protokoll verwerfen,
+            //das alte Protokoll könnte nun Idle sein
+            self.tel_buffer.retain(|ddl_tel| ddl_tel.adr != adr);
+            self.update_idle_protokoll(alt_protokoll);
+          }
+          //Zustand wurde durch erneutes INIT zurückgesetzt
+          self.send_info_msg(None, adr);
+        }
       }
       SRCPMessageType::TERM => {
         //Format ist TERM <bus> GL <addr>
         //Adresse
         let adr = cmd_msg.parameter[0].parse::<u32>().unwrap();
-        let protokoll = self.all_gl.remove(&adr).unwrap().protokoll;
-        //Ein Protokoll könnte wieder Idle geworden sein.
-        //Märklin & DCC bei < 2, siehe oben
-        let prot_count = self.count_protokoll(protokoll);
-        if (prot_count == 0)
-          || (((protokoll == DdlProtokolle::Maerklin) || (protokoll == DdlProtokolle::Dcc))
-            && (prot_count < IDLE_COUNT_MM_DCC))
-        {
-          //Es ist Idle
-          if !self.all_idle_protokolle.contains(&protokoll) {
-            self.all_idle_protokolle.push(protokoll);
-          }
-        }
+        let protokoll = self.remove_gl(adr).unwrap().protokoll;
+        self.update_idle_protokoll(protokoll);
       }
       SRCPMessageType::GET => {
-        //Format ist GET <bus> GL <addr>
+        //Format ist GET <bus> GL <addr> [STATS|INIT]
         let adr = cmd_msg.parameter[0].parse::<u32>().unwrap();
-        //INFO <bus> GL <addr> <drivemode> <V> <V_max> <f0> . . <fn>
-        self.send_info_msg(cmd_msg.session_id, adr);
+        if cmd_msg.parameter.get(1).map(String::as_str) == Some(GL_STATISTIK) {
+          self.send_info_statistik(cmd_msg.session_id, adr);
+        } else if cmd_msg.parameter.len() > 1 {
+          //INFO 190 <bus> GL <adr> INIT <protokoll> <protocolversion> <decoderspeedsteps> <numberofdecoderfunctions> .....
+          if let Some(gl) = self.all_gl.get(&adr) {
+            self.send_info_init(cmd_msg.session_id, adr, gl);
+          }
+        } else {
+          //INFO <bus> GL <addr> <drivemode> <V> <V_max> <f0> . . <fn>
+          self.send_info_msg(cmd_msg.session_id, adr);
+        }
       }
       SRCPMessageType::SET => {
         //Format ist SET <bus> GL <addr> <drivemode> <V> <V_max> <f0> . . <fn>
         let adr = cmd_msg.parameter[0].parse::<u32>().unwrap();
+        if adr == 0 {
+          self.nothalt_alle_gl();
+          return;
+        }
         //Da SET verzögert über Queue ausgeführt wird könnte ein TERM dazwischen gekommen sein, Adresse nochmals prüfen
-        if self.all_gl.contains_key(&adr) {
-          let drivemode = GLDriveMode::from_str(cmd_msg.parameter[1].as_str()).unwrap();
-          let v = cmd_msg.parameter[2].parse::<usize>().unwrap();
-          let v_max = cmd_msg.parameter[3].parse::<usize>().unwrap();
-          let mut funktionen: u64 = 0;
-          if cmd_msg.parameter.len() > 4 {
-            for i in 4..cmd_msg.parameter.len() {
-              if cmd_msg.parameter[i] == "1" {
-                funktionen |= 1 << (i - 4);
-              }
-            }
-          }
+        if self.all_gl.contains_key(&adr) && self.uebernahme_gl(cmd_msg, adr) {
+          let (drivemode, v, v_max, funktionen) = DdlGL::eval_set_values(cmd_msg);
           self.send_gl(adr, drivemode, v, v_max, funktionen, false);
           //OK an diese Session wurde bei Validate bereits gesendet da SET ohne POWER zuerst in Queue kommt.
         }
       }
       SRCPMessageType::VERIFY => {
-        //Verify wird für GL's nicht unterstützt, wurde bei Validate bereits abgelehnt
+        //Format ist VERIFY <bus> GL <addr> <drivemode> <V> <V_max> <f0> . . <fn>
+        //Vergleich mit aktuellem Zustand, bei Übereinstimmung INFO, sonst Error 412
+        let adr = cmd_msg.parameter[0].parse::<u32>().unwrap();
+        let (drivemode, v, v_max, funktionen) = DdlGL::eval_set_values(cmd_msg);
+        let gl = &self.all_gl[&adr];
+        //Nur die angegebenen Funktionen vergleichen
+        let anz_f = cmd_msg.parameter.len() - 4;
+        let maske: u64 = if anz_f >= 64 {
+          u64::MAX
+        } else {
+          (1 << anz_f) - 1
+        };
+        if (gl.direction == drivemode)
+          && (gl.speed == (gl.protokoll_speedsteps * v) / v_max)
+          && ((gl.fnkt & maske) == funktionen)
+        {
+          self.send_info_msg(cmd_msg.session_id, adr);
+        } else {
+          self
+            .tx
+            .send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"));
+        }
       }
     };
   }
@@ -745,8 +1797,13 @@ impl SRCPDeviceDDL for DdlGL<'_> {
   /// das Idle Telegramm gesendet.
   /// Sobald GL's vorhanden sind, wird Zyklisch jede GL wiederholt.
   /// Wenn alle GL durch sind, dann wird non jedem noch unbenutztem Protokoll das Idle Tel. gesendet.
+  /// Zusätzlich wird nach jeweils "IDLE_TEL_INTERVALL_GL" GL's reihum das Idle Tel. eines unbenutzten Protokolls
+  /// eingeschoben. Die Version des Protokolls für das Idle Tel. wechselt bei jedem Senden.
   /// Wenn es keine unbenutzten Protokolle mehr hat, dann wird bei diesem Aufruf nichts mehr gemacht.
-  fn send_refresh(&mut self) {
+  /// Liefert true zurück, wenn min. ein DDL Telegramm gesendet wurde.
+  fn send_refresh(&mut self) -> bool {
+    //Wird nur bei Power On aufgerufen
+    self.send_power_on_resync();
     for (adr, _) in &self.all_gl {
       if self.adr_refresh == 0 {
         //Nächste Refreshadr. gefunden
@@ -758,25 +1815,37 @@ impl SRCPDeviceDDL for DdlGL<'_> {
         self.adr_refresh = 0;
       }
     }
+    //Start eines neuen Refreshzyklus über alle GL's
+    if (self.adr_refresh == 0) && !self.all_gl.is_empty() {
+      self.refresh_zyklus_ende();
+    }
     //Wenn Refresh Adr. nun 0 ist, dann war das gerade die letzte (Überlauf) oder es gibt noch gar keine GL's.
     //Von allen vorhandenen Protokollen das Idle Telegramm senden, wenn das Protokoll nicht schon gebraucht
     //wurde. Wenn alle Protokolle bereits mit GL verwendet werden, dann machen wir hier einmal nichts, nächster Aufruf kommt wieder.
+    //Fällige verzögerte Telegramme auch dann senden, wenn in diesem Aufruf kein Refresh möglich ist
+    let mut tel_gesendet = self.send_buffer();
     if self.adr_refresh == 0 {
       for i in 0..self.all_idle_protokolle.len() {
-        //Immer erste vorhandene Version für Idle Tel. verwenden
-        let idle_protokoll = self.all_protokolle[&self.all_idle_protokolle[i]]
-          .values()
-          .next()
-          .unwrap();
-        let mut idle_tel = idle_protokoll.borrow_mut().get_idle_tel();
-        if let Some(tel) = idle_tel.as_mut() {
-          self.send_tel(tel);
+        if self.send_idle_tel(self.all_idle_protokolle[i]) {
+          tel_gesendet = true;
         }
       }
-    } else {
-      //Sobald eine Lok vorhanden ist, Refresh senden
+      self.gl_seit_idle = 0;
+    } else if !self.is_tel_buffer_adr(self.adr_refresh) {
+      //Sobald eine Lok vorhanden ist, Refresh senden.
+      //Ausser es wartet noch ein Telegramm dieser GL im Buffer (z.B. MM5 50ms), dann erst im nächsten Zyklus.
       self.send_gl_tel(self.adr_refresh, false, true);
+      tel_gesendet = true;
+      //Auch bei vielen GL's regelmässig Idle Telegramme unbenutzter Protokolle, reihum
+      self.gl_seit_idle += 1;
+      if (self.gl_seit_idle >= IDLE_TEL_INTERVALL_GL) && !self.all_idle_protokolle.is_empty() {
+        self.gl_seit_idle = 0;
+        let index = self.idle_naechstes_protokoll % self.all_idle_protokolle.len();
+        self.idle_naechstes_protokoll = index + 1;
+        self.send_idle_tel(self.all_idle_protokolle[index]);
+      }
     }
+    tel_gesendet
   }
 
   /// Alle internen zustände als Info Message versenden
@@ -784,8 +1853,9 @@ impl SRCPDeviceDDL for DdlGL<'_> {
   /// * session_id - SRCP Client Session ID an die die Zustände gesendet werden sollen.
   ///                None -> Info an alle SRCP Clients
   fn send_all_info(&self, session_id: Option<u32>) {
-    //Über alle initialisierten GA's
-    for (adr, _) in &self.all_gl {
+    //Über alle initialisierten GL's: INFO 101 mit den INIT Parametern, dann aktueller Zustand
+    for (adr, gl) in &self.all_gl {
+      self.srcp_info_new_gl(session_id, *adr, gl);
       self.send_info_msg(session_id, *adr);
     }
   }
@@ -801,19 +1871,47 @@ impl SRCPDeviceDDL for DdlGL<'_> {
     let mut tel_gesendet = false;
     //Ohne Power macht es auch keinen Sinn Telegramme zu senden
     if power {
+      self.send_power_on_resync();
+      //Momentfunktionen die ausgeschaltet werden müssen
+      if self.execute_fnkt_delay() {
+        tel_gesendet = true;
+      }
       'protLoop: for (protokoll, prot_versionen) in &self.all_protokolle.clone() {
         for (version, prot_impl) in prot_versionen {
-          let mut p: std::cell::RefMut<'_, dyn DdlProtokoll> = prot_impl.borrow_mut();
+          //Protokollimplementierungen werden immer nur für einen Aufruf ausgeliehen und nie während
+          //dem Senden (send_tel) oder der Verarbeitung der Resultate gehalten.
+          let protokoll_tel = prot_impl.borrow_mut().get_protokoll_telegrammme(power);
           let mut daten_rx = DdlTelRx::None;
-          if let Some(tel) = p.get_protokoll_telegrammme(power).as_mut() {
+          if let Some(mut tel) = protokoll_tel {
             tel_gesendet = true;
-            self.send_tel(tel);
-            daten_rx = tel.daten_rx.clone();
+            self.send_tel(&mut tel);
+            daten_rx = tel.daten_rx;
           }
           //Immer Neuanmeldung auswerten, auch wenn aktuell nicht verlangt.
           //Grund: Rückmeldungen über UDP sind nicht wie bei SPI Rx unmittelbar verfügbar.
           //Wenn bereits eine Neuanmeldung einer GL läuft, keine weitere Neuanmeldung parallel
-          match p.eval_neu_anmeldung(&daten_rx) {
+          let neu_anmeldung = prot_impl.borrow_mut().eval_neu_anmeldung(&daten_rx);
+          //Veränderungen der Anwesenheit von Dekodern (z.B. MFX Ping)
+          let anwesenheit = prot_impl.borrow_mut().get_gl_anwesenheit();
+          for gl_anwesenheit in anwesenheit {
+            self.eval_gl_anwesenheit(*protokoll, gl_anwesenheit);
+          }
+          //Diagnosemeldungen (z.B. MFX RDS Aufzeichnung) an die Session, die sie verlangt hat
+          let diagnose = prot_impl.borrow_mut().get_diagnose_meldungen();
+          for (session_id, meldung) in diagnose {
+            self.srcp_info_gm(
+              Some(session_id),
+              "SRCP_DIAGNOSE",
+              &protokoll.to_string(),
+              &meldung,
+            );
+          }
+          //Störungen von Protokollthreads (z.B. DCC Prog Thread, MFX RDS Thread) an alle Info Clients
+          let stoerungen = prot_impl.borrow_mut().get_stoerungen();
+          for stoerung in stoerungen {
+            self.srcp_info_gm(None, "SRCP_STOERUNG", &protokoll.to_string(), &stoerung);
+          }
+          match neu_anmeldung {
             ResultNeuAnmeldung::NotSupported => {} //Nichts machen
             ResultNeuAnmeldung::None => {
               self
@@ -829,58 +1927,8 @@ impl SRCPDeviceDDL for DdlGL<'_> {
               self.srcp_info_new_gl_state(&protokoll.to_string(), &err_text);
             }
             ResultNeuAnmeldung::Ok(uid) => {
-              //Noch nicht angemeldeter Dekoder gefunden.
-              //Wenn es die GL mit dieser UID schon gibt, dann wird dessen Adressen verwendet.
-              let mut gl_bekannt = false;
-              for adr in 1..=p.get_gl_max_adr() {
-                if let Some(gl) = self.all_gl.get(&adr) {
-                  if gl.protokoll_uid.is_some() && (gl.protokoll_uid.unwrap() == uid) {
-                    //Lok gibt es bereits, neue SID Zuordnung auslösen
-                    info!("GL: bekannte Lok gefunden UID={}, Adr={}", uid, adr);
-                    //Freie Adresse gefunden, Protokollabhängige Aktionen wie SID Zuordnung versenden auslösen
-                    if let Some(mut ddl_tel) = p.init_gl(
-                      adr,
-                      gl.protokoll_uid,
-                      gl.protokoll_number_functions,
-                      power,
-                      self.trigger.contains(&adr),
-                    ) {
-                      self.send_tel(&mut ddl_tel);
-                    }
-                    gl_bekannt = true;
-                    break;
-                  }
-                }
-              }
-              //Ansonsten die erste freie GL Adresse zuweisen und Initialisieren.
-              if !gl_bekannt {
-                for adr in 1..=p.get_gl_max_adr() {
-                  if !self.all_gl.contains_key(&adr) {
-                    info!("GL: neue Lok gefunden UID={}, Adr={}", uid, adr);
-                    //Es werden mal die im Basistel. enthalten Funktionen als vorhanden angenommen (bei MFX 16).
-                    let anz_f = p.get_gl_anz_f_basis();
-                    //Freie Adresse gefunden, Protokollabhängige Aktionen wie SID Zuordnung versenden auslösen
-                    if let Some(mut ddl_tel) =
-                      p.init_gl(adr, Some(uid), anz_f, power, self.trigger.contains(&adr))
-                    {
-                      self.send_tel(&mut ddl_tel);
-                    }
-                    //GL mal anmelden, jeweils max. vom Protokoll unterstützte Parameter verwenden
-                    self.register_new_gl(
-                      adr,
-                      &protokoll,
-                      version,
-                      p.get_gl_max_speed_steps(),
-                      p.get_gl_anz_f(),
-                      Some(uid),
-                      &Vec::new(), //Noch keine weiteren Parameter bekannt.
-                    );
-                    //Neue GL ist mal angemeldet, kann prinzipiell verwendet werden.
-                    //Bevor sie über SRCP INFO gemeldet wird, wird noch versucht optionale Parameter auszulesen.
-                    self.gl_param_read = Some(adr);
-                    break 'protLoop; //Keine weitere parallel Anmeldung
-                  }
-                }
+              if self.neu_anmeldung_gl(*protokoll, version, prot_impl, uid, power) {
+                break 'protLoop; //Keine weitere parallel Anmeldung
               }
             }
           }
@@ -888,64 +1936,161 @@ impl SRCPDeviceDDL for DdlGL<'_> {
       }
       //Optionale GL Parameter für automatisch neu angemeldete GL's lesen
       if let Some(adr) = self.gl_param_read {
-        let mut send_info = false;
         //Falls es die GL in der Zwischenzeit nicht mehr gibt
-        if let Some(gl) = self.all_gl.get_mut(&adr) {
-          //Passendes Protokoll / Version suchen
-          let mut protokoll = self
-            .all_protokolle
-            .get(&gl.protokoll)
-            .unwrap()
-            .get(gl.protokoll_version.as_str())
-            .unwrap()
-            .borrow_mut();
-          match protokoll.read_gl_parameter(adr) {
+        if let Some(gl) = self.all_gl.get(&adr) {
+          //Passendes Protokoll / Version suchen, Resultat erst nach Rückgabe der Ausleihe auswerten
+          let prot_impl = self.all_protokolle[&gl.protokoll][gl.protokoll_version.as_str()].clone();
+          let result_param = prot_impl.borrow_mut().read_gl_parameter(adr);
+          match result_param {
             ResultReadGlParameter::Busy => (), //In Arbeit, weiter machen
+            ResultReadGlParameter::Fortschritt(prozent) => {
+              self.srcp_info_new_gl_state(
+                &gl.protokoll.to_string(),
+                &format!("Lokparameter Adr {} {}%", adr, prozent),
+              );
+            }
             ResultReadGlParameter::Error => {
               warn!(
                 "GL Lokparameter können nicht gelesen werden für Adr {}",
                 adr
               );
               //Neue GL über SRCP Info ohne optionale Parameter melden
-              send_info = true;
               self.gl_param_read = None;
+              let gl = self.all_gl[&adr].clone();
+              self.srcp_info_new_gl(None, adr, &gl);
             }
-            ResultReadGlParameter::Ok(param) => {
+            ResultReadGlParameter::Ok(param, anz_f) => {
               //Ausgelesene Parameter in GL speichern
+              let gl = self.all_gl.get_mut(&adr).unwrap();
               gl.param.extend(param);
+              //Tatsächliche Anzahl Funktionen des Dekoders übernehmen und GL damit neu initialisieren
+              let anz_f = anz_f.filter(|anz_f| *anz_f != gl.protokoll_number_functions);
+              if let Some(anz_f) = anz_f {
+                info!("GL Adr {}: Anzahl Funktionen {}", adr, anz_f);
+                gl.protokoll_number_functions = anz_f;
+                let maske: u64 = if anz_f >= 64 {
+                  u64::MAX
+                } else {
+                  (1 << anz_f) - 1
+                };
+                gl.fnkt &= maske;
+                let init_tel = prot_impl.borrow_mut().init_gl(
+                  adr,
+                  &GlInitParams {
+                    uid: gl.protokoll_uid,
+                    funk_anz: anz_f,
+                    power,
+                    trigger: gl.trigger,
+                  },
+                );
+                if let Some(mut ddl_tel) = init_tel {
+                  self.send_tel(&mut ddl_tel);
+                }
+              }
               //Vollständige SRCP Info Meldung
-              send_info = true;
               self.gl_param_read = None;
+              let gl = self.all_gl[&adr].clone();
+              self.srcp_info_new_gl(None, adr, &gl);
+              //Zustand mit korrigierter Anzahl Funktionen
+              if anz_f.is_some() {
+                self.send_info_msg(None, adr);
+              }
             }
           }
         } else {
           //GL gibt es nicht mehr, kann hier auch weg.
           self.gl_param_read = None;
         }
-        if send_info {
-          let gl = self.all_gl[&adr].clone();
-          self.srcp_info_new_gl(adr, &gl);
-        }
       }
     } else {
+      self.power_on_resync = true;
+      //Ohne Power keine Refresh Statistik, Lücken würden bei Power On als Maximum erscheinen.
+      //Die Zähler bleiben erhalten.
+      for statistik in self.gl_statistik.values_mut() {
+        statistik.letztes_tel = None;
+        statistik.mittel = Duration::ZERO;
+        statistik.max = Duration::ZERO;
+      }
       //Power Off Idle Telegramm senden wenn vorhanden
       for (_protokoll, prot_versionen) in &self.all_protokolle.clone() {
         for (_version, prot_impl) in prot_versionen {
-          let mut p: std::cell::RefMut<'_, dyn DdlProtokoll> = prot_impl.borrow_mut();
-          //Den Protokollen die Chance geben Programmiergleis Telegramme zu senden
-          if let Some(tel) = p.get_protokoll_telegrammme(power).as_mut() {
+          //Den Protokollen die Chance geben Programmiergleis Telegramme zu senden,
+          //sonst Idle Telegramme für Programmiergleis wenn SM aktiviert
+          let tel = {
+            let mut p = prot_impl.borrow_mut();
+            p.get_protokoll_telegrammme(power)
+              .or_else(|| p.get_idle_tel_power_off())
+          };
+          if let Some(mut tel) = tel {
             tel_gesendet = true;
-            self.send_tel(tel);
-          } else {
-            //Idle Telegramme für Programmiergleis wenn SM aktiviert
-            if let Some(tel) = p.get_idle_tel_power_off().as_mut() {
-              tel_gesendet = true;
-              self.send_tel(tel);
-            }
+            self.send_tel(&mut tel);
           }
         }
       }
     }
     tel_gesendet
   }
+
+  /// Liefert den frühesten Zeitpunkt, an dem wieder etwas gesendet werden muss:
+  /// nächster Refresh, verzögerte Telegramme im Buffer, Momentfunktionen, periodische Protokolltelegramme.
+  /// # Arguments
+  /// * power - true: Power / Booster ist ein, Strom auf den Schienen
+  fn get_naechster_zeitpunkt(&self, power: bool) -> Option<Instant> {
+    if !power {
+      return None;
+    }
+    //Solange Idle Protokolle oder GL's ohne wartende Telegramme im Buffer vorhanden sind ist der nächste Refresh sofort fällig
+    if !self.all_idle_protokolle.is_empty()
+      || self.all_gl.keys().any(|adr| !self.is_tel_buffer_adr(*adr))
+    {
+      return Some(Instant::now());
+    }
+    let tel_buffer = self.tel_buffer.iter().filter_map(|tel| tel.instant_next);
+    let fnkt_delay = self.all_fnkt_delay.iter().map(|delay| delay.off_zeit);
+    let protokolle = self
+      .all_protokolle
+      .values()
+      .flat_map(|versionen| versionen.values())
+      .filter_map(|prot_impl| prot_impl.borrow().get_naechster_protokoll_zeitpunkt());
+    tel_buffer.chain(fnkt_delay).chain(protokolle).min()
+  }
+
+  /// Eine SRCP Kommando Session wurde beendet, alle GL's in deren Besitz werden freigegeben.
+  /// # Arguments
+  /// * session_id - Session ID der beendeten Session
+  fn session_end(&mut self, session_id: u32) {
+    self
+      .gl_besitzer
+      .retain(|_, besitzer| *besitzer != session_id);
+  }
+
+  /// Ein Protokoll mit allen Versionen zur Laufzeit hinzufügen.
+  /// Es ist zuerst nicht verwendet und sendet Idle Telegramme.
+  /// # Arguments
+  /// * protokoll - Das neue Protokoll
+  /// * versionen - Alle Versionen des neuen Protokolls
+  fn register_protokoll(&mut self, protokoll: DdlProtokolle, versionen: &HashMapVersion) {
+    self.all_protokolle.insert(protokoll, versionen.clone());
+    if !self.all_idle_protokolle.contains(&protokoll) {
+      self.all_idle_protokolle.push(protokoll);
+    }
+  }
+
+  /// Ein Protokoll zur Laufzeit entfernen.
+  /// Alle GL's mit diesem Protokoll werden entfernt und als INFO 102 gemeldet.
+  /// # Arguments
+  /// * protokoll - Das zu entfernende Protokoll
+  fn unregister_protokoll(&mut self, protokoll: DdlProtokolle) {
+    let adr_entfernen: Vec<u32> = self
+      .all_gl
+      .iter()
+      .filter(|(_, gl)| gl.protokoll == protokoll)
+      .map(|(adr, _)| *adr)
+      .collect();
+    for adr in adr_entfernen {
+      self.remove_gl(adr);
+    }
+    self.all_idle_protokolle.retain(|&prot| prot != protokoll);
+    self.all_protokolle.remove(&protokoll);
+  }
 }
@@ -1,13 +1,19 @@
 use std::{
-  sync::mpsc::Sender,
+  fmt, fs,
+  sync::Arc,
   time::{Duration, Instant},
 };
 
+use chrono::{DateTime, Local};
 use gpio_cdev::{Chip, LineHandle, LineRequestFlags};
+use log::{error, info, warn};
 
 use crate::{
-  srcp_devices_ddl::SRCPDeviceDDL,
-  srcp_server_types::{SRCPMessage, SRCPMessageDevice, SRCPMessageID, SRCPMessageType},
+  srcp_devices_ddl::{SRCPDeviceDDL, SpiFehler},
+  srcp_hardware,
+  srcp_server_types::{
+    SRCPMessage, SRCPMessageDevice, SRCPMessageID, SRCPMessageType, ThreadSender,
+  },
 };
 
 /// Auf dem Raspberry PI ab V2 werden folgende Ports verwendet:
@@ -27,6 +33,100 @@ const DELAY_POWER_ON_MELDUNG: Duration = Duration::from_millis(100);
 /// Leitungen zum Booster ON ist 0 wegen Invertierung durch RS232 Treiber 0V->12V / 3.3V->-12V
 const RS232_ON: u8 = 0;
 const RS232_OFF: u8 = 1;
+/// Kommando zur Aufhebung der Sperre nach Kurzschlussabschaltung: SET <bus> POWER RESET
+const POWER_RESET: &str = "RESET";
+/// Power Zustand beim Start srcpd
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PowerStart {
+  /// Immer aus
+  Aus,
+  /// Letzter gespeicherter Zustand, Einschaltung nach Verzögerung
+  Letzter(Duration),
+  /// Immer ein nach Verzögerung
+  Ein(Duration),
+}
+
+/// Auslöser der letzten Power Veränderung
+#[derive(Clone, Copy)]
+enum PowerAusloeser {
+  /// SET POWER Kommando der SRCP Session
+  Session(Option<u32>),
+  /// Abschaltung wegen Kurzschluss (nicht siggmode)
+  Kurzschluss,
+  /// Ein- Ausschaltung durch Booster (siggmode)
+  Booster,
+  /// Abschaltung wegen aufeinanderfolgenden SPI Schreibfehlern
+  SpiFehler,
+  /// Power Zustand beim Start srcpd
+  Start,
+}
+impl fmt::Display for PowerAusloeser {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      PowerAusloeser::Session(Some(session_id)) => write!(f, "session {}", session_id),
+      PowerAusloeser::Session(None) => write!(f, "session"),
+      PowerAusloeser::Kurzschluss => write!(f, "shortcut"),
+      PowerAusloeser::Booster => write!(f, "booster"),
+      PowerAusloeser::SpiFehler => write!(f, "spi error"),
+      PowerAusloeser::Start => write!(f, "startup"),
+    }
+  }
+}
+
+/// Sperre Power On nach Kurzschlussabschaltung (nicht siggmode)
+#[derive(Default)]
+pub struct KurzschlussSperre {
+  /// None: keine Sperre verlangt, sonst Dauer ab Kurzschlussabschaltung bis zur automatischen Aufhebung.
+  /// 0 = Aufhebung nur mit SET <bus> POWER RESET
+  lockout: Option<Duration>,
+  /// Zeitpunkt der Kurzschlussabschaltung, None wenn nicht gesperrt
+  gesperrt_seit: Option<Instant>,
+}
+impl KurzschlussSperre {
+  /// Neue Instanz erstellen
+  /// # Arguments
+  /// * lockout - None: keine Sperre, sonst Dauer bis zur automatischen Aufhebung, 0 = nie
+  pub fn new(lockout: Option<Duration>) -> KurzschlussSperre {
+    KurzschlussSperre {
+      lockout,
+      gesperrt_seit: None,
+    }
+  }
+
+  /// Nach Kurzschlussabschaltung Power On sperren, wenn verlangt.
+  /// Liefert true zurück, wenn gesperrt wurde.
+  /// # Arguments
+  /// * jetzt - Zeitpunkt der Kurzschlussabschaltung, ab dem die Sperrdauer läuft
+  pub fn sperren(&mut self, jetzt: Instant) -> bool {
+    if self.lockout.is_some() {
+      self.gesperrt_seit = Some(jetzt);
+    }
+    self.gesperrt_seit.is_some()
+  }
+
+  /// Liefert true, wenn Power On gesperrt ist
+  pub fn is_gesperrt(&self) -> bool {
+    self.gesperrt_seit.is_some()
+  }
+
+  /// Liefert true, wenn die Sperre besteht und die Sperrdauer abgelaufen ist
+  /// # Arguments
+  /// * jetzt - Aktueller Zeitpunkt
+  pub fn is_abgelaufen(&self, jetzt: Instant) -> bool {
+    match (self.gesperrt_seit, self.lockout) {
+      (Some(gesperrt_seit), Some(lockout)) => {
+        !lockout.is_zero() && (jetzt >= gesperrt_seit + lockout)
+      }
+      _ => false,
+    }
+  }
+
+  /// Sperre aufheben. Liefert true zurück, wenn sie bestanden hat.
+  pub fn aufheben(&mut self) -> bool {
+    self.gesperrt_seit.take().is_some()
+  }
+}
+
 /// Device Power für DDL
 /// Power On Off:
 /// - siggmode: Booster GO message on CTS Line, Booster GO / STOP Command impluse on RTS/DTR
@@ -35,7 +135,7 @@ pub struct DdlPower {
   //SRCP Bus auf dem gearbeitet wird
   bus: usize,
   //Sender für SRCP Antworten
-  tx: Sender<SRCPMessage>,
+  tx: ThreadSender<SRCPMessage>,
   //Konfiguration Power On/Off über Impulse
   siggmode: bool,
   //DSR Booster GO Meldung Invers (bei nicht siggmode)
@@ -45,8 +145,19 @@ pub struct DdlPower {
   //Zeit, die Booster On sein muss in uSekunden, damit bei Off (wegen Schluss) automatisch wieder eingeschaltet wird wenn Siggmode
   //0 = Ausgeschaltet, keine automatische Wiedereinschaltung.
   timeout_shortcut_power_off: Duration,
+  //Anzahl aufeinanderfolgend wegen SPI Schreibfehler nicht gesendeter Telegramme bis Power Off.
+  //0 = Ausgeschaltet, keine automatische Ausschaltung.
+  spi_max_fehler: u32,
+  //SPI Schreibfehler der Ausgabe an den Booster
+  spi_fehler: Arc<SpiFehler>,
   //Aktueller Power Zustand
   power_on: bool,
+  //File zur Speicherung des Power Zustandes, None wenn nicht gespeichert wird
+  path_power_file: Option<String>,
+  //Zeitpunkt automatisches Einschalten nach Start srcpd, None wenn nicht (mehr) verlangt
+  power_start_zeitpunkt: Option<Instant>,
+  //Zeitpunkt und Auslöser der letzten Power Veränderung für Freetext in INFO POWER
+  letzte_veraenderung: Option<(DateTime<Local>, PowerAusloeser)>,
   //Zeitpunkt Power On um On-Meldung verzögert zu liefern. Damit alle Dekoder Zeit haben zu starten.
   power_on_zeitpunkt: Instant,
   //Zeitpunkt Start/Stopimpulse wieder ausschalten siggmode
@@ -55,6 +166,8 @@ pub struct DdlPower {
   kein_shortcut: Instant,
   //Bei Siggmode: Zeitpunkt, ab dem eine automatische Wiedereinschaltung erlaubt ist
   sigg_mode_auto_power_on: Option<Instant>,
+  //Bei nicht Siggmode: Sperre Power On nach Kurzschlussabschaltung
+  sperre: KurzschlussSperre,
   //Booster Go Meldung siggmode
   gpio_cts_go_in: LineHandle,
   //Booster Go Meldung / Shortcut
@@ -75,12 +188,14 @@ impl DdlPower {
   /// * timeout_shortcut_power_off - Wenn Siggmode: minimale Power On Zeit damit einmalig bei Ausschaltung
   ///                                (wegen Kurzschluss) wieder versucht wird einzuschalten.
   ///                                0 = Ausgeschaltet, keine automatische Wiedereinschaltung.
+  /// * spi_max_fehler - Anzahl aufeinanderfolgend wegen SPI Schreibfehler nicht gesendeter Telegramme
+  ///                    bis Power Off. 0 = Ausgeschaltet, keine automatische Ausschaltung.
   pub fn new(
-    bus: usize, tx: Sender<SRCPMessage>, siggmode: bool, dsr_invers: bool, shortcut_delay: u64,
-    timeout_shortcut_power_off: u64,
+    bus: usize, tx: ThreadSender<SRCPMessage>, siggmode: bool, dsr_invers: bool,
+    shortcut_delay: u64, timeout_shortcut_power_off: u64, spi_max_fehler: u32,
   ) -> DdlPower {
     let mut chip =
-      Chip::new("/dev/gpiochip0").expect("/dev/gpiochip0 konnte nicht geöffnet werden");
+      Chip::new(srcp_hardware::get_gpiochip()).expect("GPIO Chip konnte nicht geöffnet werden");
     let result = DdlPower {
       bus: bus,
       tx: tx,
@@ -88,11 +203,17 @@ impl DdlPower {
       dsr_invers: dsr_invers,
       shortcut_delay: Duration::from_millis(shortcut_delay),
       timeout_shortcut_power_off: Duration::from_millis(timeout_shortcut_power_off),
+      spi_max_fehler,
+      spi_fehler: Arc::new(SpiFehler::default()),
       power_on: false,
+      path_power_file: None,
+      power_start_zeitpunkt: None,
+      letzte_veraenderung: None,
       power_on_zeitpunkt: Instant::now(),
       impuls_aus: Instant::now(),
       kein_shortcut: Instant::now(),
       sigg_mode_auto_power_on: None,
+      sperre: KurzschlussSperre::default(),
       gpio_cts_go_in: chip
         .get_line(CTS)
         .expect(format!("GPIO {} konnte nicht geöffnet werden", CTS).as_str())
@@ -115,17 +236,99 @@ impl DdlPower {
         .expect(format!("GPIO {} konnte nicht als Input geöffnet werden", DTR).as_str()),
     };
     log::debug!(
-      "New DdlPower siggmode={}, dsr_invers={}, shortcut_delay={}, timeout_shortcut_power_off={}",
+      "New DdlPower siggmode={}, dsr_invers={}, shortcut_delay={}, timeout_shortcut_power_off={}, spi_max_fehler={}",
       siggmode,
       dsr_invers,
       shortcut_delay,
-      timeout_shortcut_power_off
+      timeout_shortcut_power_off,
+      spi_max_fehler
     );
     //Default setzen, Ausgänge ausgeschaltet
     result.gpio_rts_go_out.set_value(RS232_OFF).unwrap();
     result.gpio_dtr_stop_out.set_value(RS232_OFF).unwrap();
     result
   }
+  /// SPI Schreibfehler der überwachten Ausgabe an den Booster festlegen, siehe "spi_max_fehler".
+  /// # Arguments
+  /// * spi_fehler - SPI Schreibfehler der Ausgabe
+  pub fn set_spi_fehler(&mut self, spi_fehler: Arc<SpiFehler>) {
+    self.spi_fehler = spi_fehler;
+  }
+  /// Sperre Power On nach Kurzschlussabschaltung festlegen (nur wenn nicht siggmode).
+  /// # Arguments
+  /// * shortcut_lockout - None: keine Sperre, sonst Dauer ab Kurzschlussabschaltung bis zur automatischen
+  ///                      Aufhebung der Sperre, 0 = Aufhebung nur mit SET <bus> POWER RESET
+  pub fn set_shortcut_lockout(&mut self, shortcut_lockout: Option<Duration>) {
+    if self.siggmode && shortcut_lockout.is_some() {
+      warn!("DDL: shortcut_lockout wird bei siggmode nicht unterstützt");
+      return;
+    }
+    self.sperre = KurzschlussSperre::new(shortcut_lockout);
+  }
+
+  /// Sperre Power On nach Kurzschlussabschaltung aufheben und mit INFO melden
+  /// # Arguments
+  /// * grund - Grund der Aufhebung für Log Ausgabe
+  fn reset_sperre(&mut self, grund: &str) {
+    if self.sperre.aufheben() {
+      info!(
+        "DDL: Sperre Power On nach Kurzschluss aufgehoben ({})",
+        grund
+      );
+      self.send_all_info(None);
+    }
+  }
+
+  /// Power Zustand beim Start srcpd festlegen und mit INFO melden.
+  /// Muss einmal nach "new" aufgerufen werden.
+  /// # Arguments
+  /// * power_start - Verhalten beim Start
+  /// * path_power_file - File zur Speicherung des Power Zustandes für "PowerStart::Letzter"
+  pub fn set_power_start(&mut self, power_start: PowerStart, path_power_file: &str) {
+    let delay = match power_start {
+      PowerStart::Aus => None,
+      PowerStart::Letzter(delay) => {
+        //Nur bei Wiederherstellung wird der Zustand gespeichert
+        self.path_power_file = Some(path_power_file.to_string());
+        match fs::read_to_string(path_power_file) {
+          Ok(zustand) => (zustand.trim() == "ON").then_some(delay),
+          Err(_) => {
+            warn!(
+              "DDL: Power Zustandsfile {} konnte nicht gelesen werden, Start mit Power Off.",
+              path_power_file
+            );
+            None
+          }
+        }
+      }
+      PowerStart::Ein(delay) => Some(delay),
+    };
+    self.power_start_zeitpunkt = delay.map(|delay| Instant::now() + delay);
+    match delay {
+      Some(delay) => info!("DDL: Start mit Power On nach {:?}", delay),
+      None => info!("DDL: Start mit Power Off"),
+    }
+    self.letzte_veraenderung = Some((Local::now(), PowerAusloeser::Start));
+    self.send_all_info(None);
+  }
+
+  /// Aktuellen Power Zustand speichern wenn verlangt.
+  /// Zuerst in ein temporäres File, dann umbenennen, damit bei einem Absturz während dem Schreiben
+  /// nie ein unvollständiges File zurückbleibt.
+  fn save_power(&self) {
+    if let Some(path_power_file) = &self.path_power_file {
+      let path_tmp = format!("{}.tmp", path_power_file);
+      if fs::write(&path_tmp, if self.power_on { "ON" } else { "OFF" }).is_err()
+        || fs::rename(&path_tmp, path_power_file).is_err()
+      {
+        warn!(
+          "DDL: Power Zustand konnte nicht in {} gespeichert werden.",
+          path_power_file
+        );
+      }
+    }
+  }
+
   /// Siggmode Start-Stopimpulsausgabe
   /// # Arguments
   /// * power - true: Startimpuls, false: Stopimpuls
@@ -150,10 +353,17 @@ impl DdlPower {
   /// Neuer Power Zustand übernehmen
   /// # Arguments
   /// * power - Neuer Power Zustand
-  fn set_power(&mut self, power: bool) {
+  /// * ausloeser - Wer hat die Veränderung ausgelöst
+  fn set_power(&mut self, power: bool, ausloeser: PowerAusloeser) {
     if self.power_on != power {
       self.power_on = power;
+      self.letzte_veraenderung = Some((Local::now(), ausloeser));
+      //Nach Kurzschlussabschaltung Power On sperren wenn verlangt
+      if matches!(ausloeser, PowerAusloeser::Kurzschluss) && self.sperre.sperren(Instant::now()) {
+        warn!("DDL: Power On nach Kurzschluss gesperrt");
+      }
       self.send_all_info(None);
+      self.save_power();
       if self.siggmode {
         self.start_stop_impuls(power);
       } else {
@@ -179,8 +389,8 @@ impl SRCPDeviceDDL for DdlPower {
   /// # Arguments
   /// * cmd_msg - Empfangenes Kommando
   fn validate_cmd(&self, cmd_msg: &SRCPMessage) -> bool {
-    //SET/GET <bus> POWER [ON|OFF] [freetext]
-    //Hier muss nur noch SET|GET & ON|OFF kontrolliert werden
+    //SET/GET <bus> POWER [ON|OFF|RESET] [freetext]
+    //Hier muss nur noch SET|GET & ON|OFF|RESET kontrolliert werden
     let mut cmd_get = false;
     if match &cmd_msg.message_id {
       SRCPMessageID::Command { msg_type } => {
@@ -189,21 +399,28 @@ impl SRCPDeviceDDL for DdlPower {
       }
       _ => false,
     } && (match cmd_msg.parameter.get(0) {
-      Some(para) => (para == "ON") || (para == "OFF"),
+      Some(para) => (para == "ON") || (para == "OFF") || (para == POWER_RESET),
       None => false,
     } || cmd_get)
     {
-      if cmd_get {
-        self.send_all_info(cmd_msg.session_id);
-      } else {
-        self.tx.send(SRCPMessage::new_ok(cmd_msg, "200")).unwrap();
+      //Die Antwort auf GET ist die Info, diese wird erst bei der Ausführung gesendet
+      if !cmd_get {
+        if self.sperre.is_gesperrt() && (cmd_msg.parameter[0] == "ON") {
+          //Power On gesperrt nach Kurzschlussabschaltung
+          self.tx.send(SRCPMessage::new_err(
+            cmd_msg,
+            "413",
+            "temporarily prohibited",
+          ));
+          return false;
+        }
+        self.tx.send(SRCPMessage::new_ok(cmd_msg, "200"));
       }
       true
     } else {
       self
         .tx
-        .send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"))
-        .unwrap();
+        .send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"));
       false
     }
   }
@@ -214,11 +431,22 @@ impl SRCPDeviceDDL for DdlPower {
   /// * cmd_msg - Empfangenes Kommando
   /// * power - true wenn Power eingeschaltet, Booster On sind, hier nicht verwendet
   fn execute_cmd(&mut self, cmd_msg: &SRCPMessage, _power: bool) {
-    //Nur das SET Kommando muss hier ausgeführt werden
+    //GET wird mit der Info beantwortet, sonst muss nur das SET Kommando ausgeführt werden
     match &cmd_msg.message_id {
       SRCPMessageID::Command { msg_type } => {
-        if *msg_type == SRCPMessageType::SET {
-          self.set_power(cmd_msg.parameter[0] == "ON");
+        if *msg_type == SRCPMessageType::GET {
+          self.send_all_info(cmd_msg.session_id);
+        } else if *msg_type == SRCPMessageType::SET {
+          if cmd_msg.parameter[0] == POWER_RESET {
+            self.reset_sperre("reset");
+            return;
+          }
+          //Ein SET POWER hat Vorrang vor dem automatischen Einschalten beim Start
+          self.power_start_zeitpunkt = None;
+          self.set_power(
+            cmd_msg.parameter[0] == "ON",
+            PowerAusloeser::Session(cmd_msg.session_id),
+          );
         }
       }
       _ => {}
@@ -231,22 +459,35 @@ impl SRCPDeviceDDL for DdlPower {
   ///                None -> Info an alle SRCP Clients
   fn send_all_info(&self, session_id: Option<u32>) {
     //Hier gibt es nur den aktuellen Power Zustand
-    self
-      .tx
-      .send(SRCPMessage::new(
-        session_id,
-        self.bus,
-        SRCPMessageID::Info {
-          info_code: "100".to_string(),
-        },
-        SRCPMessageDevice::Power,
-        vec![if self.power_on {
-          "ON".to_string()
-        } else {
-          "OFF".to_string()
-        }],
-      ))
-      .unwrap();
+    //INFO <bus> POWER ON|OFF [freetext], Freetext mit Zeitpunkt und Auslöser der letzten Veränderung
+    //und "locked" wenn Power On nach Kurzschlussabschaltung gesperrt ist
+    let mut param = vec![if self.power_on {
+      "ON".to_string()
+    } else {
+      "OFF".to_string()
+    }];
+    if let Some((zeitpunkt, ausloeser)) = &self.letzte_veraenderung {
+      param.push(format!(
+        "since {} by {}",
+        zeitpunkt.format("%H:%M:%S"),
+        ausloeser
+      ));
+    }
+    if self.sperre.is_gesperrt() {
+      match param.get_mut(1) {
+        Some(freetext) => freetext.push_str(", locked"),
+        None => param.push("locked".to_string()),
+      }
+    }
+    self.tx.send(SRCPMessage::new(
+      session_id,
+      self.bus,
+      SRCPMessageID::Info {
+        info_code: "100".to_string(),
+      },
+      SRCPMessageDevice::Power,
+      param,
+    ));
   }
   /// Abfrage eines Device spezifischen Wertes / Zustandes
   /// Liefert hier den Power Zustand.
@@ -255,15 +496,32 @@ impl SRCPDeviceDDL for DdlPower {
     self.power_on && (self.power_on_zeitpunkt + DELAY_POWER_ON_MELDUNG <= Instant::now())
   }
 
+  /// Liefert den Zeitpunkt für das verzögerte Einschalten beim Start srcpd, wenn noch ausstehend
+  /// # Arguments
+  /// * power - true: Power / Booster ist ein, hier nicht verwendet
+  fn get_naechster_zeitpunkt(&self, _power: bool) -> Option<Instant> {
+    self.power_start_zeitpunkt
+  }
+
   /// Hintergrundaktivität:
   /// - Ausschalten Start- Stopimpulse zu Booster wenn siggmode
   /// - Kontrolle Boosterrückmeldung On/Off (Shortcut)
+  /// - Automatische Aufhebung Sperre Power On nach Kurzschlussabschaltung
+  /// - Ausschaltung nach "spi_max_fehler" aufeinanderfolgend nicht gesendeten Telegrammen
+  /// - Verzögertes Einschalten beim Start srcpd
   /// Liefert immer false zurück, es wird hier nie ein Telegramm gesendet.
   /// # Arguments
   /// * power - true: Power / Booster ist ein, Strom auf den Schienen
   ///           false: Power / Booster ist aus
   ///           -> wird hier nicht verwendet, wir sind ja im DDL Device "Power"
   fn execute(&mut self, _power: bool) -> bool {
+    if self
+      .power_start_zeitpunkt
+      .is_some_and(|zeitpunkt| zeitpunkt <= Instant::now())
+    {
+      self.power_start_zeitpunkt = None;
+      self.set_power(true, PowerAusloeser::Start);
+    }
     if self.siggmode {
       //Wenn Start- Stop Impuls vorbei sind
       if Instant::now() > self.impuls_aus {
@@ -285,7 +543,7 @@ impl SRCPDeviceDDL for DdlPower {
           }
         }
         //Aus- und Einschalten vom Booster übernehmen
-        self.set_power(booster_on);
+        self.set_power(booster_on, PowerAusloeser::Booster);
       }
     } else {
       //Kurzschluss- Erkennung
@@ -294,13 +552,29 @@ impl SRCPDeviceDDL for DdlPower {
       //Aber auch, damit überhaupt eingeschaltet werden kann, wenn Booster aus ist -> kein Kurzschluss
       if booster_on || (!self.power_on) {
         self.kein_shortcut = Instant::now();
+        //Sperre aufheben, wenn die Sperrdauer seit der Kurzschlussabschaltung abgelaufen ist
+        if self.sperre.is_abgelaufen(Instant::now()) {
+          self.reset_sperre("Sperrdauer abgelaufen");
+        }
       } else {
         //Booster sollte ein sein, Rückmeldung ist aber aus -> nach Timeout ganz ausschalten
         if Instant::now() > (self.kein_shortcut + self.shortcut_delay) {
-          self.set_power(false);
+          self.set_power(false, PowerAusloeser::Kurzschluss);
         }
       }
     }
+    //Ohne funktionierende SPI Ausgabe erhält der Booster kein gültiges Gleissignal mehr
+    let (spi_fehler_folge, spi_fehler_total) = self.spi_fehler.get();
+    if (self.spi_max_fehler > 0) && (spi_fehler_folge >= self.spi_max_fehler) {
+      if self.power_on {
+        error!(
+          "DDL: {} Telegramme in Folge wegen SPI Schreibfehler nicht gesendet ({} Fehler total), Power Off",
+          spi_fehler_folge, spi_fehler_total
+        );
+        self.set_power(false, PowerAusloeser::SpiFehler);
+      }
+      self.spi_fehler.reset_folge();
+    }
     false
   }
 }
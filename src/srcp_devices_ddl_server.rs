@@ -0,0 +1,145 @@
+use crate::{
+  srcp_devices_ddl::SRCPDeviceDDL,
+  srcp_protocol_ddl::{DdlProtokolle, HashMapProtokollVersion, HashMapVersion},
+  srcp_server_types::{
+    SRCPMessage, SRCPMessageDevice, SRCPMessageID, SRCPMessageType, ThreadSender,
+  },
+};
+
+/// Parameter für Abfrage aller Protokolle: GET <bus> SERVER PROTOCOLS
+const SERVER_PROTOKOLLE: &str = "PROTOCOLS";
+
+/// Device SERVER für DDL (srcpd_rust Erweiterung).
+/// Erlaubt Clients die Abfrage der auf diesem Bus vorhandenen Protokolle, damit z.B. INIT Dialoge
+/// ohne Annahmen aufgebaut werden können:
+/// GET <bus> SERVER PROTOCOLS
+/// INFO <bus> SERVER PROTOCOLS <protokoll>:<version>:<default>:<uid>:<gl_max_adr>:<gl_speed_steps>:<gl_anz_f>:<ga_max_adr> ...
+/// - default: 1 wenn Default Version des Protokolles (INIT ohne Versionsangabe), sonst 0
+/// - uid: 1 wenn bei GL INIT eine UID angegeben werden muss, sonst 0
+/// - ga_max_adr: 0 wenn das Protokoll keine GA unterstützt
+pub struct DdlServer {
+  //SRCP Bus auf dem gearbeitet wird
+  bus: usize,
+  //Sender für SRCP Antworten
+  tx: ThreadSender<SRCPMessage>,
+  //Alle vorhandenen Protokolle
+  all_protokolle: HashMapProtokollVersion,
+}
+impl DdlServer {
+  /// Neue Instanz erstellen
+  /// # Arguments
+  /// * bus - SRCP Bus auf dem dieses Device arbeitet
+  /// * tx - Sender für Info Messages / Antworten an SRCP Clients
+  /// * all_protokolle - Alle vorhandenen Protokolle
+  pub fn new(
+    bus: usize, tx: ThreadSender<SRCPMessage>, all_protokolle: HashMapProtokollVersion,
+  ) -> DdlServer {
+    DdlServer {
+      bus,
+      tx,
+      all_protokolle,
+    }
+  }
+
+  /// Liefert die Beschreibung aller Protokolle in allen Versionen, sortiert nach Protokoll und Version.
+  /// Format pro Protokoll Version siehe "DdlServer".
+  fn get_protokoll_beschreibungen(&self) -> Vec<String> {
+    let mut beschreibungen: Vec<String> = Vec::new();
+    for (protokoll, versionen) in &self.all_protokolle {
+      for (version, implementierung) in versionen {
+        let implementierung = implementierung.borrow();
+        beschreibungen.push(format!(
+          "{}:{}:{}:{}:{}:{}:{}:{}",
+          protokoll.to_string(),
+          version,
+          implementierung.is_default() as u8,
+          implementierung.uid() as u8,
+          implementierung.get_gl_max_adr(),
+          implementierung.get_gl_max_speed_steps(),
+          implementierung.get_gl_anz_f(),
+          implementierung.get_ga_max_adr()
+        ));
+      }
+    }
+    beschreibungen.sort();
+    beschreibungen
+  }
+}
+impl SRCPDeviceDDL for DdlServer {
+  /// Empfangenes Kommando validieren
+  /// Return true wenn Ok.
+  /// Sendet die Antwort Message (Err) zurück, die Antwort auf GET erfolgt mit "execute_cmd".
+  /// # Arguments
+  /// * cmd_msg - Empfangenes Kommando
+  fn validate_cmd(&self, cmd_msg: &SRCPMessage) -> bool {
+    //Nur GET <bus> SERVER PROTOCOLS wird unterstützt
+    let SRCPMessageID::Command {
+      msg_type: SRCPMessageType::GET,
+    } = cmd_msg.message_id
+    else {
+      self.tx.send(SRCPMessage::new_err(
+        cmd_msg,
+        "423",
+        "unsupported operation",
+      ));
+      return false;
+    };
+    match cmd_msg.parameter.first() {
+      None => {
+        self
+          .tx
+          .send(SRCPMessage::new_err(cmd_msg, "419", "list too short"));
+        false
+      }
+      Some(para) if (para == SERVER_PROTOKOLLE) && (cmd_msg.parameter.len() == 1) => true,
+      Some(_) => {
+        self
+          .tx
+          .send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"));
+        false
+      }
+    }
+  }
+
+  /// Empfangenes Kommando ausführen.
+  /// Das Kommando muss gültig sein (validate_cmd), es wird hier nicht mehr überprüft.
+  /// # Arguments
+  /// * cmd_msg - Empfangenes Kommando
+  /// * power - true wenn Power eingeschaltet, Booster On sind, hier nicht verwendet
+  fn execute_cmd(&mut self, cmd_msg: &SRCPMessage, _power: bool) {
+    //INFO <bus> SERVER PROTOCOLS <beschreibung> ...
+    let mut parameter = vec![SERVER_PROTOKOLLE.to_string()];
+    parameter.extend(self.get_protokoll_beschreibungen());
+    self.tx.send(SRCPMessage::new(
+      cmd_msg.session_id,
+      self.bus,
+      SRCPMessageID::Info {
+        info_code: "100".to_string(),
+      },
+      SRCPMessageDevice::Server,
+      parameter,
+    ));
+  }
+
+  /// Alle internen zustände als Info Message versenden
+  /// Keine Zustände vorhanden, Protokolle werden nur auf Anfrage gemeldet.
+  /// # Arguments
+  /// * session_id - SRCP Client Session ID an die die Zustände gesendet werden sollen.
+  ///                None -> Info an alle SRCP Clients
+  fn send_all_info(&self, _session_id: Option<u32>) {}
+
+  /// Ein Protokoll mit allen Versionen zur Laufzeit hinzufügen.
+  /// # Arguments
+  /// * protokoll - Das neue Protokoll
+  /// * versionen - Alle Versionen des neuen Protokolls
+  fn register_protokoll(&mut self, protokoll: DdlProtokolle, versionen: &HashMapVersion) {
+    self.all_protokolle.insert(protokoll, versionen.clone());
+  }
+
+  /// Ein Protokoll zur Laufzeit entfernen.
+  /// # Arguments
+  /// * protokoll - Das zu entfernende Protokoll
+  fn unregister_protokoll(&mut self, protokoll: DdlProtokolle) {
+    self.all_protokolle.remove(&protokoll);
+  }
+}
@@ -1,19 +1,68 @@
-use std::{collections::HashMap, sync::mpsc::Sender};
+use std::{
+  collections::{HashMap, VecDeque},
+  time::{Duration, Instant},
+};
 
-use log::debug;
+use gpio_cdev::LineHandle;
+use log::{debug, warn};
 
 use crate::{
-  srcp_devices_ddl::SRCPDeviceDDL,
-  srcp_protocol_ddl::{DdlProtokolle, HashMapProtokollVersion, SmReadWrite, SmReadWriteType},
-  srcp_server_types::{SRCPMessage, SRCPMessageDevice, SRCPMessageID, SRCPMessageType},
+  srcp::CMD_SM_TIMEOUT,
+  srcp_devices_ddl::{ProgAusgabe, SRCPDeviceDDL},
+  srcp_protocol_ddl::{
+    DdlProtokolle, DdlTel, HashMapProtokollVersion, HashMapVersion, SmReadWrite, SmReadWriteType,
+  },
+  srcp_server_types::{
+    SRCPMessage, SRCPMessageDevice, SRCPMessageID, SRCPMessageType, ThreadSender,
+  },
 };
 
+/// Erweiterung SET <bus> SM <decoderaddress> <type> <values ...> <set value> READBACK:
+/// Nach dem Schreiben wird der Wert zurückgelesen, Ok nur wenn er stimmt.
+/// Nur DCC auf dem Programmiergleis, sonst wird das Kommando mit 412 abgelehnt.
+const SM_READBACK: &str = "READBACK";
+
+/// INFO <bus> SM 0 STARTED <protokoll> <version> / INFO <bus> SM 0 FINISHED <protokoll> <version>
+/// an alle Clients beim Start (INIT) und Ende (TERM) Servicemode, Programmiergleis ist solange belegt.
+const SM_STARTED: &str = "STARTED";
+const SM_FINISHED: &str = "FINISHED";
+
+/// Nächster Versuch ein von der Ausgabe separater Programmiergleis noch nicht übernommenes Telegramm
+/// zu übergeben. Kürzer als ein DCC Paket, damit auf dem Programmiergleis keine Lücke entsteht.
+const PROG_AUSGABE_WIEDERHOLUNG: Duration = Duration::from_millis(1);
+
+/// Konfiguration SM Device aus dem Konfigfile
+pub struct DdlSMKonfig {
+  /// Oszi Trigger Port
+  pub trigger_port: Option<String>,
+  /// Oszi Trigger Adressen
+  pub trigger_adr: Option<String>,
+  /// Eingang Bestätigung Programmiergleis ist Low aktiv
+  pub prog_gleis_invers: bool,
+  /// Nach jedem SET auf dem Programmiergleis den Wert zurücklesen
+  pub write_verify: bool,
+}
+
+/// Ein SM Auftrag eines SRCP Clients
+struct SmAuftrag {
+  /// Auftrag an das Protokoll
+  sm_read_write: SmReadWrite,
+  /// Zeitpunkt an dem das Kommando empfangen wurde
+  empfangen: Instant,
+}
+
 /// SM Device
-pub struct DdlSM {
+/// SM Aufträge aller Sessions werden in einer Warteschlange nacheinander an das Protokoll übergeben,
+/// erst wenn die Antwort auf den aktiven Auftrag vorhanden ist, wird der nächste übergeben.
+/// Mit separatem Programmiergleis Ausgang werden dessen Telegramme hier, unabhängig von Power und
+/// parallel zum Fahrbetrieb auf dem Hauptausgang, gesendet.
+/// Start und Ende des Servicemode (INIT / TERM SM) werden allen Clients gemeldet, damit z.B. Automatiken
+/// während dem Programmieren pausieren können.
+pub struct DdlSM<'a> {
   //SRCP Bus auf dem gearbeitet wird
   bus: usize,
   //Sender für SRCP Antworten
-  tx: Sender<SRCPMessage>,
+  tx: ThreadSender<SRCPMessage>,
   //Alle vorhandenen Protokollimplementierungen mit allen Versionen
   all_protokolle: HashMapProtokollVersion,
   //Konvertierung auf bei GL/GA verwendete (Protokollnamen, Version)
@@ -22,19 +71,40 @@ pub struct DdlSM {
   sm_protokoll: Option<(DdlProtokolle, String)>,
   ///Für welche SM's soll ein Oszi Trigger ausgegeben werden?
   trigger: Vec<u32>,
+  ///Noch nicht an das Protokoll übergebene SM Aufträge
+  warteschlange: VecDeque<SmAuftrag>,
+  ///An das Protokoll übergebener Auftrag, auf dessen Antwort gewartet wird
+  aktiver_auftrag: Option<SmAuftrag>,
+  ///ID für den nächsten Auftrag
+  naechste_auftrag_id: u32,
+  ///Eingang Bestätigung Programmiergleis (Booster strombegrenzt), None wenn nicht konfiguriert
+  prog_gleis_input: &'a Option<LineHandle>,
+  ///Eingang Bestätigung Programmiergleis ist Low aktiv
+  prog_gleis_invers: bool,
+  ///Ausgabe separater Programmiergleis, None wenn SM über den Hauptausgang
+  prog_ausgabe: &'a Option<ProgAusgabe>,
+  ///Von der Ausgabe separater Programmiergleis noch nicht übernommenes Telegramm
+  prog_tel_wartend: Option<DdlTel>,
+  ///Oszi Trigger Port für Telegramme auf dem separaten Programmiergleis Ausgang
+  trigger_port: Option<u32>,
+  ///Nach jedem SET auf dem Programmiergleis den Wert zurücklesen
+  write_verify: bool,
 }
 
-impl DdlSM {
+impl DdlSM<'_> {
   /// Neue Instanz erstellen
   /// # Arguments
   /// * bus - SRCP Bus auf dem dieses Device arbeitet
   /// * tx - Sender für Info Messages / Antworten an SRCP Clients
   /// * all_protokolle - Alle vorhandenen Protokollimplementierungen mit allen Versionen
-  /// * trigger_adr - Oszi Trigger Adressen aus Konfigfile
-  pub fn new(
-    bus: usize, tx: Sender<SRCPMessage>, all_protokolle: HashMapProtokollVersion,
-    trigger_adr: Option<String>,
-  ) -> DdlSM {
+  /// * prog_gleis_input - Eingang Bestätigung Programmiergleis, None wenn nicht vorhanden
+  /// * prog_ausgabe - Ausgabe separater Programmiergleis, None wenn nicht vorhanden
+  /// * konfig - Konfiguration aus Konfigfile
+  pub fn new<'a>(
+    bus: usize, tx: ThreadSender<SRCPMessage>, all_protokolle: HashMapProtokollVersion,
+    prog_gleis_input: &'a Option<LineHandle>, prog_ausgabe: &'a Option<ProgAusgabe>,
+    konfig: DdlSMKonfig,
+  ) -> DdlSM<'a> {
     let mut gl_ga_prot_names: HashMap<String, (String, String)> = HashMap::new();
     gl_ga_prot_names.insert(
       "NMRA".to_string(),
@@ -51,13 +121,236 @@ impl DdlSM {
       gl_ga_prot_names,
       sm_protokoll: None,
       trigger: vec![],
+      warteschlange: VecDeque::new(),
+      aktiver_auftrag: None,
+      naechste_auftrag_id: 0,
+      prog_gleis_input,
+      prog_gleis_invers: konfig.prog_gleis_invers,
+      prog_ausgabe,
+      prog_tel_wartend: None,
+      trigger_port: None,
+      write_verify: konfig.write_verify,
     };
-    result.trigger = result.eval_trigger_config(trigger_adr);
+    result.trigger = result.eval_trigger_config(konfig.trigger_adr);
+    result.trigger_port = result.eval_trigger_port_config(konfig.trigger_port);
     result
   }
+
+  /// Prüft ob ein SET Kommando mit "READBACK" als letztem Parameter das Zurücklesen verlangt
+  /// # Arguments
+  /// * cmd_msg - Empfangenes Kommando
+  fn is_readback(cmd_msg: &SRCPMessage) -> bool {
+    let SRCPMessageID::Command { msg_type } = cmd_msg.message_id else {
+      return false;
+    };
+    (msg_type == SRCPMessageType::SET)
+      && (cmd_msg.parameter.last().map(String::as_str) == Some(SM_READBACK))
+  }
+
+  /// Neuen SM Auftrag in die Warteschlange stellen.
+  /// # Arguments
+  /// * sm_read_write - Auftrag an das Protokoll, die Auftrags ID wird hier vergeben.
+  fn neuer_auftrag(&mut self, mut sm_read_write: SmReadWrite) {
+    sm_read_write.auftrag_id = self.naechste_auftrag_id;
+    self.naechste_auftrag_id = self.naechste_auftrag_id.wrapping_add(1);
+    self.warteschlange.push_back(SmAuftrag {
+      sm_read_write,
+      empfangen: Instant::now(),
+    });
+  }
+
+  /// Prüft über den Eingang Bestätigung Programmiergleis, ob der Booster im strombegrenzten
+  /// Programmiergleismodus ist und nicht mehr das ganze Hauptgleis versorgt.
+  /// Liefert immer true, wenn kein Eingang konfiguriert ist.
+  fn is_prog_gleis_bestaetigt(&self) -> bool {
+    let Some(input) = self.prog_gleis_input else {
+      return true;
+    };
+    match input.get_value() {
+      Ok(value) => (value != 0) != self.prog_gleis_invers,
+      Err(msg) => {
+        warn!(
+          "SM: Eingang Bestätigung Programmiergleis kann nicht gelesen werden: {}",
+          msg
+        );
+        false
+      }
+    }
+  }
+
+  /// Liefert true, wenn SM Aufträge auf dem Programmiergleis ausgeführt werden:
+  /// bei Power Off oder immer, wenn ein separater Programmiergleis Ausgang vorhanden ist.
+  /// # Arguments
+  /// * power - true wenn Power eingeschaltet, Booster On sind
+  fn is_prog_gleis(&self, power: bool) -> bool {
+    !power || self.prog_ausgabe.is_some()
+  }
+
+  /// Telegramme der Protokolle für den separaten Programmiergleis Ausgang an dessen Ausgabethread
+  /// übergeben, ohne auf die Ausgabe zu warten.
+  /// Ein neues Telegramm wird vom Protokoll erst geholt, wenn das vorherige übernommen wurde.
+  /// Liefert true zurück, wenn min. ein Telegramm übergeben wurde.
+  fn send_prog_gleis_telegramme(&mut self) -> bool {
+    let Some(prog_ausgabe) = self.prog_ausgabe else {
+      return false;
+    };
+    let mut tel_gesendet = false;
+    if let Some(tel) = self.prog_tel_wartend.take() {
+      if let Some(tel) = prog_ausgabe.try_send(tel, self.trigger_port) {
+        self.prog_tel_wartend = Some(tel);
+        return false;
+      }
+      tel_gesendet = true;
+    }
+    for prot_familie in self.all_protokolle.values() {
+      for prot in prot_familie.values() {
+        let tel = prot.borrow_mut().get_prog_gleis_telegramm();
+        if let Some(tel) = tel {
+          if let Some(tel) = prog_ausgabe.try_send(tel, self.trigger_port) {
+            self.prog_tel_wartend = Some(tel);
+            return tel_gesendet;
+          }
+          tel_gesendet = true;
+        }
+      }
+    }
+    tel_gesendet
+  }
+
+  /// Alle noch nicht beantworteten Aufträge mit Timeout beantworten und entfernen.
+  fn abbruch_alle_auftraege(&mut self) {
+    let offene_auftraege: Vec<SmAuftrag> = self
+      .aktiver_auftrag
+      .take()
+      .into_iter()
+      .chain(self.warteschlange.drain(..))
+      .collect();
+    for auftrag in offene_auftraege {
+      let mut ans = auftrag.sm_read_write;
+      ans.val = SmReadWriteType::ResultTimeout;
+      self.send_antwort(ans);
+    }
+  }
+
+  /// Start / Ende Servicemode mit aktuellem SM Protokoll als INFO versenden
+  /// # Arguments
+  /// * session_id - SRCP Client Session ID an die gesendet wird, None -> an alle SRCP Clients
+  /// * zustand - SM_STARTED oder SM_FINISHED
+  fn send_info_zustand(&self, session_id: Option<u32>, zustand: &str) {
+    let Some((prot, prot_ver)) = &self.sm_protokoll else {
+      return;
+    };
+    self.tx.send(SRCPMessage::new(
+      session_id,
+      self.bus,
+      SRCPMessageID::Info {
+        info_code: "100".to_string(),
+      },
+      SRCPMessageDevice::SM,
+      vec![
+        "0".to_string(),
+        zustand.to_string(),
+        prot.to_string(),
+        prot_ver.clone(),
+      ],
+    ));
+  }
+
+  /// Antwort eines Protokolls als SRCP Message versenden.
+  /// # Arguments
+  /// * ans - Antwort vom Protokoll
+  pub fn send_antwort(&self, ans: SmReadWrite) {
+    let mut srcp_para: Vec<String> = Vec::new();
+    //Paramater zu SM sind: adr sm_type <alle paramater> value
+    srcp_para.push(ans.adr.to_string());
+    srcp_para.push(ans.sm_type);
+    for p in ans.para {
+      srcp_para.push(p.to_string());
+    }
+    let srcp_message = if let SmReadWriteType::ResultInfo(val) = ans.val {
+      //Zwischenergebnis eines laufenden Auftrages (z.B. CV Scan) als Info an alle
+      srcp_para.push(val.to_string());
+      SRCPMessage::new(
+        None,
+        self.bus,
+        SRCPMessageID::Info {
+          info_code: "100".to_string(),
+        },
+        SRCPMessageDevice::SM,
+        srcp_para,
+      )
+    } else if let SmReadWriteType::ResultIdent(ident) = ans.val {
+      //OK Message mit Dekoderidentifikation
+      srcp_para.extend(ident.get_srcp_parameter());
+      SRCPMessage {
+        session_id: Some(ans.session_id),
+        bus: self.bus,
+        message_id: SRCPMessageID::Ok {
+          ok_code: "200".to_string(),
+        },
+        device: SRCPMessageDevice::SM,
+        parameter: srcp_para,
+        weitere_folgen: false,
+      }
+    } else if let SmReadWriteType::ResultText(text) = &ans.val {
+      //OK Message mit Text
+      srcp_para.push(format!("\"{}\"", text));
+      SRCPMessage {
+        session_id: Some(ans.session_id),
+        bus: self.bus,
+        message_id: SRCPMessageID::Ok {
+          ok_code: "200".to_string(),
+        },
+        device: SRCPMessageDevice::SM,
+        parameter: srcp_para,
+        weitere_folgen: false,
+      }
+    } else if let SmReadWriteType::ResultOk(val) = ans.val {
+      //OK Message
+      srcp_para.push(val.to_string());
+      SRCPMessage {
+        session_id: Some(ans.session_id),
+        bus: self.bus,
+        message_id: SRCPMessageID::Ok {
+          ok_code: "200".to_string(),
+        },
+        device: SRCPMessageDevice::SM,
+        parameter: srcp_para,
+        weitere_folgen: false,
+      }
+    } else if let SmReadWriteType::ResultTimeout = ans.val {
+      //Abgebrochen durch Timeout oder TERM
+      SRCPMessage {
+        session_id: Some(ans.session_id),
+        bus: self.bus,
+        message_id: SRCPMessageID::Err {
+          err_code: "417".to_string(),
+          err_text: "timeout".to_string(),
+        },
+        device: SRCPMessageDevice::SM,
+        parameter: srcp_para,
+        weitere_folgen: false,
+      }
+    } else {
+      //Error
+      SRCPMessage {
+        session_id: Some(ans.session_id),
+        bus: self.bus,
+        message_id: SRCPMessageID::Err {
+          err_code: "412".to_string(),
+          err_text: "wrong value".to_string(),
+        },
+        device: SRCPMessageDevice::SM,
+        parameter: srcp_para,
+        weitere_folgen: false,
+      }
+    };
+    debug!("SM Antwort: {}", srcp_message.to_string());
+    self.tx.send(srcp_message);
+  }
 }
 
-impl SRCPDeviceDDL for DdlSM {
+impl SRCPDeviceDDL for DdlSM<'_> {
   /// Empfangenes Kommando validieren.
   /// Return true wenn Kommando Ok.
   /// Sendet die Antwort Message (Ok / Err) an Sender zurück.
@@ -88,31 +381,25 @@ impl SRCPDeviceDDL for DdlSM {
                 } else {
                   self
                     .tx
-                    .send(SRCPMessage::new_err(cmd_msg, "415", "forbidden"))
-                    .unwrap();
+                    .send(SRCPMessage::new_err(cmd_msg, "415", "forbidden"));
                 }
               }
               else {
                 self
                   .tx
-                  .send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"))
-                  .unwrap();
+                  .send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"));
               }
             } else {
               self
                 .tx
-                .send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"))
-                .unwrap();
+                .send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"));
             }
           } else {
-            self
-              .tx
-              .send(SRCPMessage::new_err(
-                cmd_msg,
-                "420",
-                "unsupported device protocol",
-              ))
-              .unwrap();
+            self.tx.send(SRCPMessage::new_err(
+              cmd_msg,
+              "420",
+              "unsupported device protocol",
+            ));
           }
         }
         SRCPMessageType::TERM => {
@@ -125,8 +412,7 @@ impl SRCPDeviceDDL for DdlSM {
           } else {
             self
               .tx
-              .send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"))
-              .unwrap();
+              .send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"));
           }
         }
         SRCPMessageType::SET | SRCPMessageType::GET | SRCPMessageType::VERIFY => {
@@ -134,9 +420,12 @@ impl SRCPDeviceDDL for DdlSM {
           //<type> ist Protokollabhängig (z.B. bei NMRA CV, CVBIT, bei MFX CAMFX)
           //Anzahl weitere Parameter ist auch Protokollabhängig (z.B. NMRA CV: CV, Value, bei MFX CAMFX Block, CA, CAIndex, Index, Value)
           //<set value> nur bei SET und VERIFY, nicht bei GET
+          //Bei SET optional noch READBACK
           //Es muss ein Protokoll mit INIT für SM ausgewählt worden sein
           if let Some((prot, prot_ver)) = &self.sm_protokoll {
-            if cmd_msg.parameter.len() > 2 {
+            //Ohne optionales READBACK
+            let anz_para = cmd_msg.parameter.len() - usize::from(DdlSM::is_readback(cmd_msg));
+            if anz_para >= 2 {
               //Type prüfen. Protokoll muss "sm_get_all_types" != None liefern, sonst hätte es mit "INIT" nicht aktiviert werden können
               let protokoll = &self.all_protokolle[prot][prot_ver.as_str()];
               if let Some(para_count) = protokoll
@@ -147,7 +436,7 @@ impl SRCPDeviceDDL for DdlSM {
               {
                 //Protokoll ist initalisiert, für Protokoll gültiger Type ist angegeben
                 //Prüfung notwendige Anzahl Parameter
-                if cmd_msg.parameter.len()
+                if anz_para
                   == (2 //2+ ist Dekoderadr und Type
                     + para_count
                     + (if msg_type == SRCPMessageType::GET {
@@ -156,45 +445,43 @@ impl SRCPDeviceDDL for DdlSM {
                       1 //Bei SET und VERIFY braucht es noch den Value Wert zusätzlich
                     }))
                 {
-                  //Alles ausser Type müssen eine Zahl sein
+                  //Alles ausser Type müssen eine Zahl sein, ausser Value bei SET mit Text Type
+                  let text_value = (msg_type == SRCPMessageType::SET)
+                    && protokoll.borrow().sm_is_text_type(&cmd_msg.parameter[1]);
                   result = true;
-                  for i in 0..cmd_msg.parameter.len() {
-                    if (i != 1) && cmd_msg.parameter[i].parse::<u32>().is_err() {
+                  for i in 0..anz_para {
+                    if (i != 1)
+                      && !(text_value && (i == anz_para - 1))
+                      && cmd_msg.parameter[i].parse::<u32>().is_err()
+                    {
                       result = false;
                       self
                         .tx
-                        .send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"))
-                        .unwrap();
+                        .send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"));
                       break;
                     }
                   }
                 } else {
                   self
                     .tx
-                    .send(SRCPMessage::new_err(cmd_msg, "419", "list too short"))
-                    .unwrap();
+                    .send(SRCPMessage::new_err(cmd_msg, "419", "list too short"));
                 }
               } else {
                 self
                   .tx
-                  .send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"))
-                  .unwrap();
+                  .send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"));
               }
             } else {
               self
                 .tx
-                .send(SRCPMessage::new_err(cmd_msg, "419", "list too short"))
-                .unwrap();
+                .send(SRCPMessage::new_err(cmd_msg, "419", "list too short"));
             }
           } else {
-            self
-              .tx
-              .send(SRCPMessage::new_err(
-                cmd_msg,
-                "420",
-                "unsupported device protocol",
-              ))
-              .unwrap();
+            self.tx.send(SRCPMessage::new_err(
+              cmd_msg,
+              "420",
+              "unsupported device protocol",
+            ));
           }
         }
       }
@@ -237,18 +524,23 @@ impl SRCPDeviceDDL for DdlSM {
         protokoll.borrow_mut().sm_init(if cmd_msg.parameter.len() > 2 {Some(cmd_msg.parameter[2].as_str())} else {None});
         //SRCP Antwort OK zurücksenden
         let ok_msg = SRCPMessage::new_ok(cmd_msg, "200");
-        self.tx.send(ok_msg).unwrap();
+        self.tx.send(ok_msg);
+        //Alle Clients informieren, dass das Programmiergleis belegt ist
+        self.send_info_zustand(None, SM_STARTED);
       }
       SRCPMessageType::TERM => {
         //Protokoll SM TERM
         let (prot, prot_ver) = self.sm_protokoll.as_ref().unwrap();
         let protokoll = &self.all_protokolle[prot][prot_ver.as_str()];
         protokoll.borrow_mut().sm_term();
-        //Und kein aktives SM Protokoll mehr vorhanden
-        self.sm_protokoll = None;
+        self.abbruch_alle_auftraege();
         //SRCP Antwort OK zurücksenden
         let ok_msg = SRCPMessage::new_ok(cmd_msg, "200");
-        self.tx.send(ok_msg).unwrap();
+        self.tx.send(ok_msg);
+        //Alle Clients informieren, dass der Servicemode beendet ist
+        self.send_info_zustand(None, SM_FINISHED);
+        //Und kein aktives SM Protokoll mehr vorhanden
+        self.sm_protokoll = None;
       }
       SRCPMessageType::GET => {
         //Alle (nach Type bis Schluss) notwendigen Parameter zu Vec<u32> konvertieren.
@@ -256,42 +548,80 @@ impl SRCPDeviceDDL for DdlSM {
         for p_str in &cmd_msg.parameter[2..] {
           param.push(p_str.parse::<u32>().unwrap());
         }
-        //Protokoll für SM
-        let (prot, prot_ver) = self.sm_protokoll.as_ref().unwrap();
-        let protokoll = &self.all_protokolle[prot][prot_ver.as_str()];
-        protokoll.borrow_mut().sm_read_write(&SmReadWrite {
+        self.neuer_auftrag(SmReadWrite {
           adr: cmd_msg.get_adr().unwrap(),
-          prog_gleis: !power, //Prog.Gleismodus wenn Power aus
+          prog_gleis: self.is_prog_gleis(power),
           sm_type: cmd_msg.parameter[1].clone(),
           para: param,
           val: SmReadWriteType::Read,
           session_id: cmd_msg.session_id.unwrap(),
+          auftrag_id: 0,
           trigger: self.trigger.contains(cmd_msg.get_adr().as_ref().unwrap()),
+          write_verify: false,
         });
       }
       SRCPMessageType::SET | SRCPMessageType::VERIFY => {
+        //Zurücklesen ist nur mit DCC auf dem Programmiergleis möglich
+        if DdlSM::is_readback(cmd_msg)
+          && (!self.is_prog_gleis(power)
+            || !matches!(self.sm_protokoll, Some((DdlProtokolle::Dcc, _))))
+        {
+          warn!(
+            "SM: READBACK nur mit DCC auf dem Programmiergleis, abgelehnt: {}",
+            cmd_msg.to_string().trim_end()
+          );
+          self
+            .tx
+            .send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"));
+          return;
+        }
+        //Schreiben auf dem Programmiergleis nur wenn der Booster bestätigt strombegrenzt ist,
+        //sonst würden alle Dekoder auf dem Hauptgleis mit programmiert.
+        if msg_type == SRCPMessageType::SET
+          && self.is_prog_gleis(power)
+          && !self.is_prog_gleis_bestaetigt()
+        {
+          warn!(
+            "SM: Booster nicht im Programmiergleismodus, abgelehnt: {}",
+            cmd_msg.to_string().trim_end()
+          );
+          self.tx.send(SRCPMessage::new_err(
+            cmd_msg,
+            "413",
+            "temporarily prohibited",
+          ));
+          return;
+        }
+        //Ohne optionales READBACK
+        let anz_para = cmd_msg.parameter.len() - usize::from(DdlSM::is_readback(cmd_msg));
         //Alle (nach Type bis Schluss - 1) notwendigen Parameter zu Vec<u32> konvertieren.
         let mut param: Vec<u32> = Vec::new();
-        for p_str in &cmd_msg.parameter[2..cmd_msg.parameter.len() - 1] {
+        for p_str in &cmd_msg.parameter[2..anz_para - 1] {
           param.push(p_str.parse::<u32>().unwrap());
         }
-        //Der letzte Parameter ist der zu schreibende Wert
-        let value = cmd_msg.parameter.last().unwrap().parse::<u32>().unwrap();
-        //Protokoll für SM
+        //Der letzte Parameter ist der zu schreibende Wert, als Zahl oder Text
+        let value = &cmd_msg.parameter[anz_para - 1];
         let (prot, prot_ver) = self.sm_protokoll.as_ref().unwrap();
-        let protokoll = &self.all_protokolle[prot][prot_ver.as_str()];
-        protokoll.borrow_mut().sm_read_write(&SmReadWrite {
+        let text_type = self.all_protokolle[prot][prot_ver.as_str()]
+          .borrow()
+          .sm_is_text_type(&cmd_msg.parameter[1]);
+        self.neuer_auftrag(SmReadWrite {
           adr: cmd_msg.get_adr().unwrap(),
-          prog_gleis: !power, //Prog.Gleismodus wenn Power aus
+          prog_gleis: self.is_prog_gleis(power),
           sm_type: cmd_msg.parameter[1].clone(),
           para: param,
-          val: if msg_type == SRCPMessageType::SET {
-            SmReadWriteType::Write(value)
+          val: if msg_type == SRCPMessageType::SET && text_type {
+            SmReadWriteType::WriteText(value.clone())
+          } else if msg_type == SRCPMessageType::SET {
+            SmReadWriteType::Write(value.parse::<u32>().unwrap())
           } else {
-            SmReadWriteType::Verify(value)
+            SmReadWriteType::Verify(value.parse::<u32>().unwrap())
           },
           session_id: cmd_msg.session_id.unwrap(),
+          auftrag_id: 0,
           trigger: self.trigger.contains(cmd_msg.get_adr().as_ref().unwrap()),
+          write_verify: (msg_type == SRCPMessageType::SET)
+            && (self.write_verify || DdlSM::is_readback(cmd_msg)),
         });
       }
     }
@@ -301,58 +631,107 @@ impl SRCPDeviceDDL for DdlSM {
   /// # Arguments
   /// * session_id - SRCP Client Session ID an die die Zustände gesendet werden sollen.
   ///                None -> Info an alle SRCP Clients
-  fn send_all_info(&self, _session_id: Option<u32>) {
-    //SM hat keine internen Zustände die an alle SRCP Info Clients gensendet werden müssen
+  fn send_all_info(&self, session_id: Option<u32>) {
+    //Einziger Zustand ist ein laufender Servicemode
+    self.send_info_zustand(session_id, SM_STARTED);
   }
   /// Muss zyklisch aufgerufen werden. Erlaubt dem Device die Ausführung von
   /// von neuen Kommando oder refresh unabhängigen Aufgaben.
-  /// Liefert immer false, es wurde hier nie ein DDL Tel. versendet.
+  /// Liefert true zurück, wenn auf dem separaten Programmiergleis Ausgang min. ein DDL Tel. gesendet
+  /// wurde, sonst false.
   /// Hier: Wenn vorhanden SM Info-Antwort Messages als srcp Message zurück senden
   /// # Arguments
   /// * power - true: Power / Booster ist ein, Strom auf den Schienen
   ///           false: Power / Booster ist aus
   fn execute(&mut self, _power: bool) -> bool {
+    let tel_gesendet = self.send_prog_gleis_telegramme();
+    //Client hat bereits Timeout erhalten, eine spätere Antwort wird verworfen
+    if self
+      .aktiver_auftrag
+      .as_ref()
+      .is_some_and(|auftrag| auftrag.empfangen.elapsed() > CMD_SM_TIMEOUT)
+    {
+      warn!("SM Timeout Auftrag: {:?}", self.aktiver_auftrag.take().unwrap().sm_read_write);
+    }
+    //Nächsten Auftrag an das Protokoll übergeben
+    while self.aktiver_auftrag.is_none() {
+      let Some(auftrag) = self.warteschlange.pop_front() else {
+        break;
+      };
+      if auftrag.empfangen.elapsed() > CMD_SM_TIMEOUT {
+        //Zu lange in der Warteschlange, Client hat bereits Timeout erhalten
+        warn!("SM Auftrag verworfen: {:?}", auftrag.sm_read_write);
+        continue;
+      }
+      if let Some((prot, prot_ver)) = &self.sm_protokoll {
+        let protokoll = &self.all_protokolle[prot][prot_ver.as_str()];
+        protokoll.borrow_mut().sm_read_write(&auftrag.sm_read_write);
+        self.aktiver_auftrag = Some(auftrag);
+      }
+    }
     for (_, prot_familie) in &self.all_protokolle {
       for (_, prot) in prot_familie {
         if let Some(ans) = prot.borrow_mut().sm_get_answer() {
           debug!("SM RX Antwort: {:?}", ans);
-          let mut srcp_para: Vec<String> = Vec::new();
-          //Paramater zu SM sind: adr sm_type <alle paramater> value
-          srcp_para.push(ans.adr.to_string());
-          srcp_para.push(ans.sm_type);
-          for p in ans.para {
-            srcp_para.push(p.to_string());
-          }
-          let srcp_message = if let SmReadWriteType::ResultOk(val) = ans.val {
-            //OK Message
-            srcp_para.push(val.to_string());
-            SRCPMessage {
-              session_id: Some(ans.session_id),
-              bus: self.bus,
-              message_id: SRCPMessageID::Ok {
-                ok_code: "200".to_string(),
-              },
-              device: SRCPMessageDevice::SM,
-              parameter: srcp_para,
-            }
+          if matches!(ans.val, SmReadWriteType::ResultInfo(_)) {
+            //Zwischenergebnisse gehen an alle, unabhängig vom aktiven Auftrag
+            self.send_antwort(ans);
+          } else if self
+            .aktiver_auftrag
+            .as_ref()
+            .is_some_and(|auftrag| auftrag.sm_read_write.auftrag_id == ans.auftrag_id)
+          {
+            self.aktiver_auftrag = None;
+            self.send_antwort(ans);
           } else {
-            //Error
-            SRCPMessage {
-              session_id: Some(ans.session_id),
-              bus: self.bus,
-              message_id: SRCPMessageID::Err {
-                err_code: "412".to_string(),
-                err_text: "wrong value".to_string(),
-              },
-              device: SRCPMessageDevice::SM,
-              parameter: srcp_para,
-            }
-          };
-          debug!("SM Antwort: {}", srcp_message.to_string());
-          self.tx.send(srcp_message).unwrap();
+            warn!("SM verspätete Antwort verworfen: {:?}", ans);
+          }
         }
       }
     }
-    false
+    tel_gesendet
+  }
+
+  /// Liefert den Zeitpunkt für den nächsten Versuch, ein noch nicht übernommenes Telegramm an die Ausgabe
+  /// separater Programmiergleis zu übergeben.
+  /// # Arguments
+  /// * power - true: Power / Booster ist ein, Strom auf den Schienen
+  fn get_naechster_zeitpunkt(&self, _power: bool) -> Option<Instant> {
+    self
+      .prog_tel_wartend
+      .as_ref()
+      .map(|_| Instant::now() + PROG_AUSGABE_WIEDERHOLUNG)
+  }
+
+  /// Eine SRCP Kommando Session wurde beendet, alle Protokolle geben an diese Session gebundene
+  /// SM Zustände (z.B. MFX RDS Aufzeichnung) frei.
+  /// # Arguments
+  /// * session_id - Session ID der beendeten Session
+  fn session_end(&mut self, session_id: u32) {
+    for prot_familie in self.all_protokolle.values() {
+      for prot in prot_familie.values() {
+        prot.borrow_mut().session_end(session_id);
+      }
+    }
+  }
+
+  /// Ein Protokoll mit allen Versionen zur Laufzeit hinzufügen.
+  /// # Arguments
+  /// * protokoll - Das neue Protokoll
+  /// * versionen - Alle Versionen des neuen Protokolls
+  fn register_protokoll(&mut self, protokoll: DdlProtokolle, versionen: &HashMapVersion) {
+    self.all_protokolle.insert(protokoll, versionen.clone());
+  }
+
+  /// Ein Protokoll zur Laufzeit entfernen.
+  /// Wenn SM gerade mit diesem Protokoll aktiv ist, wird SM beendet.
+  /// # Arguments
+  /// * protokoll - Das zu entfernende Protokoll
+  fn unregister_protokoll(&mut self, protokoll: DdlProtokolle) {
+    if self.sm_protokoll.as_ref().is_some_and(|(prot, _)| *prot == protokoll) {
+      self.sm_protokoll = None;
+      self.abbruch_alle_auftraege();
+    }
+    self.all_protokolle.remove(&protokoll);
   }
 }
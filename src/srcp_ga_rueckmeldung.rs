@@ -0,0 +1,257 @@
+//! GA Rückmeldung: Verknüpfung GA Ports mit FB Kontakten (Endlagen Rückmeldung)
+//! Wird ein verknüpfter GA Port eingeschaltet, dann wird die INFO GA an die Clients zurückgehalten bis der
+//! zugeordnete FB aktiv ist. Meldet der FB nicht innerhalb des Timeouts, dann werden die zurückgehaltenen
+//! INFO GA verworfen und alle Clients mit "INFO <bus> GM 0 0 SRCP_GA_FEEDBACK_FAILED" informiert.
+//! Ist der FB beim Einschalten bereits aktiv, dann wird die INFO GA sofort weitergeleitet.
+//!
+//! INI File:
+//! [ga_feedback]
+//! timeout = <ms>, Default 2000
+//! ga_<n> = <ga bus>:<ga adr> <port> <fb bus>:<fb adr>
+
+use std::{
+  collections::HashMap,
+  time::{Duration, Instant},
+};
+
+use log::{info, warn};
+
+use crate::srcp_server_types::{SRCPMessage, SRCPMessageDevice, SRCPMessageID};
+
+/// Default Timeout für die Rückmeldung
+const TIMEOUT_DEFAULT: Duration = Duration::from_millis(2000);
+
+/// Eine Verknüpfung GA Port mit FB
+struct GaRueckmeldung {
+  /// Name aus Konfigfile (für Log)
+  name: String,
+  /// GA: Bus und Adresse
+  ga: (usize, u32),
+  /// Port der GA
+  port: usize,
+  /// Rückmeldender FB: Bus und Adresse
+  fb: (usize, u32),
+}
+
+/// Eine noch nicht bestätigte GA Schaltung
+struct Ausstehend {
+  /// Index der Verknüpfung
+  index: usize,
+  /// Bis zu diesem Zeitpunkt muss der FB aktiv werden
+  frist: Instant,
+  /// Zurückgehaltene INFO GA Messages in Empfangsreihenfolge
+  infos: Vec<SRCPMessage>,
+}
+
+/// Alle GA Rückmeldungen mit aktuellem Zustand
+pub struct GaRueckmeldungen {
+  /// Alle konfigurierten Verknüpfungen
+  verknuepfungen: Vec<GaRueckmeldung>,
+  /// Timeout für die Rückmeldung
+  timeout: Duration,
+  /// Aktueller Zustand der FB's aller Verknüpfungen gemäss INFO Messages, Key Bus und Adresse
+  fb_aktiv: HashMap<(usize, u32), bool>,
+  /// Alle noch nicht bestätigten Schaltungen
+  ausstehend: Vec<Ausstehend>,
+}
+
+impl GaRueckmeldungen {
+  /// GA Rückmeldungen aus Konfigfile erstellen.
+  /// Liefert Err zurück wenn die Konfiguration ungültig ist.
+  /// # Arguments
+  /// * config - Abschnitt [ga_feedback] aus Konfigfile, None wenn nicht vorhanden
+  pub fn from_config(
+    config: Option<&HashMap<String, Option<String>>>,
+  ) -> Result<GaRueckmeldungen, String> {
+    let mut verknuepfungen: Vec<GaRueckmeldung> = Vec::new();
+    let mut timeout = TIMEOUT_DEFAULT;
+    if let Some(config) = config {
+      for (name, wert) in config {
+        if name == "timeout" {
+          timeout = Duration::from_millis(
+            wert
+              .as_ref()
+              .ok_or("[ga_feedback] timeout ohne Wert")?
+              .parse::<u64>()
+              .ok()
+              .filter(|&ms| ms > 0)
+              .ok_or("[ga_feedback] timeout muss eine Zahl > 0 sein")?,
+          );
+          continue;
+        }
+        if !name.starts_with("ga_") {
+          continue;
+        }
+        let wert = wert
+          .as_ref()
+          .ok_or(format!("[ga_feedback] {} ohne Wert", name))?;
+        let mut teile = wert.split_whitespace();
+        let ga = GaRueckmeldungen::eval_bus_adr(teile.next().unwrap_or(""))
+          .ok_or(format!("[ga_feedback] {}: ungültige GA <bus>:<adr>", name))?;
+        let port = teile
+          .next()
+          .and_then(|port| port.parse::<usize>().ok())
+          .ok_or(format!("[ga_feedback] {}: ungültiger Port", name))?;
+        let fb = GaRueckmeldungen::eval_bus_adr(teile.next().unwrap_or(""))
+          .ok_or(format!("[ga_feedback] {}: ungültiger FB <bus>:<adr>", name))?;
+        info!(
+          "GA Rückmeldung {}: GA {:?} Port {} -> FB {:?}",
+          name, ga, port, fb
+        );
+        verknuepfungen.push(GaRueckmeldung {
+          name: name.clone(),
+          ga,
+          port,
+          fb,
+        });
+      }
+    }
+    Ok(GaRueckmeldungen {
+      verknuepfungen,
+      timeout,
+      fb_aktiv: HashMap::new(),
+      ausstehend: Vec::new(),
+    })
+  }
+
+  /// Auswerten <bus>:<adr>
+  /// # Arguments
+  /// * bus_adr - String im Format <bus>:<adr>
+  fn eval_bus_adr(bus_adr: &str) -> Option<(usize, u32)> {
+    let (bus, adr) = bus_adr.split_once(':')?;
+    Some((bus.parse::<usize>().ok()?, adr.parse::<u32>().ok()?))
+  }
+
+  /// Liefert den Zeitpunkt, bis zu dem die nächste ausstehende Rückmeldung eintreffen muss.
+  /// None wenn keine Rückmeldung ausstehend ist.
+  pub fn get_naechste_frist(&self) -> Option<Instant> {
+    self
+      .ausstehend
+      .iter()
+      .map(|ausstehend| ausstehend.frist)
+      .min()
+  }
+
+  /// Auswertung aller INFO Messages der SRCP Server.
+  /// Liefert alle Messages, die jetzt an die Clients weitergeleitet werden sollen:
+  /// leer wenn die Message bis zur Rückmeldung zurückgehalten wird, bei einer Rückmeldung zusätzlich
+  /// alle damit bestätigten INFO GA.
+  /// # Arguments
+  /// * msg - Empfangene Message eines SRCP Servers
+  pub fn eval_info(&mut self, msg: SRCPMessage) -> Vec<SRCPMessage> {
+    //Nur Zustandsveränderungen an alle, keine Antworten oder Zustände an einzelne Sessions
+    if self.verknuepfungen.is_empty()
+      || msg.session_id.is_some()
+      || !matches!(&msg.message_id, SRCPMessageID::Info { info_code } if info_code == "100")
+    {
+      return vec![msg];
+    }
+    match msg.device {
+      SRCPMessageDevice::GA => {
+        //INFO <bus> GA <adr> <port> <value>
+        let (Some(adr), Some(port), Some(value)) = (
+          msg.get_adr(),
+          msg
+            .parameter
+            .get(1)
+            .and_then(|port| port.parse::<usize>().ok()),
+          msg.parameter.get(2),
+        ) else {
+          return vec![msg];
+        };
+        let Some(index) = self
+          .verknuepfungen
+          .iter()
+          .position(|v| v.ga == (msg.bus, adr) && v.port == port)
+        else {
+          return vec![msg];
+        };
+        //Alle Veränderungen einer ausstehenden Schaltung in Reihenfolge zurückhalten
+        if let Some(ausstehend) = self.ausstehend.iter_mut().find(|a| a.index == index) {
+          ausstehend.infos.push(msg);
+          return vec![];
+        }
+        let fb = self.verknuepfungen[index].fb;
+        if value == "0" || self.fb_aktiv.get(&fb).copied().unwrap_or(false) {
+          return vec![msg];
+        }
+        self.ausstehend.push(Ausstehend {
+          index,
+          frist: Instant::now() + self.timeout,
+          infos: vec![msg],
+        });
+        vec![]
+      }
+      SRCPMessageDevice::FB => {
+        //INFO <bus> FB <adr> <value>
+        let (Some(adr), Some(value)) = (msg.get_adr(), msg.parameter.get(1)) else {
+          return vec![msg];
+        };
+        let aktiv = value != "0";
+        let fb = (msg.bus, adr);
+        self.fb_aktiv.insert(fb, aktiv);
+        let mut weiterleiten = vec![msg];
+        if aktiv {
+          let verknuepfungen = &self.verknuepfungen;
+          self.ausstehend.retain_mut(|ausstehend| {
+            let verknuepfung = &verknuepfungen[ausstehend.index];
+            if verknuepfung.fb != fb {
+              return true;
+            }
+            info!(
+              "GA Rückmeldung {}: GA {}:{} Port {} bestätigt",
+              verknuepfung.name, verknuepfung.ga.0, verknuepfung.ga.1, verknuepfung.port
+            );
+            weiterleiten.append(&mut ausstehend.infos);
+            false
+          });
+        }
+        weiterleiten
+      }
+      _ => vec![msg],
+    }
+  }
+
+  /// Alle ausstehenden Schaltungen mit abgelaufener Frist abbrechen.
+  /// Liefert für jede eine GM Info an alle Clients:
+  /// INFO <ga bus> GM 0 0 SRCP_GA_FEEDBACK_FAILED "GA <adr> <port> FB <fb bus>:<fb adr>"
+  pub fn eval_timeout(&mut self) -> Vec<SRCPMessage> {
+    let jetzt = Instant::now();
+    let mut fehler: Vec<SRCPMessage> = Vec::new();
+    let verknuepfungen = &self.verknuepfungen;
+    self.ausstehend.retain(|ausstehend| {
+      if ausstehend.frist > jetzt {
+        return true;
+      }
+      let verknuepfung = &verknuepfungen[ausstehend.index];
+      warn!(
+        "GA Rückmeldung {}: GA {}:{} Port {} ohne Rückmeldung FB {}:{}",
+        verknuepfung.name,
+        verknuepfung.ga.0,
+        verknuepfung.ga.1,
+        verknuepfung.port,
+        verknuepfung.fb.0,
+        verknuepfung.fb.1
+      );
+      fehler.push(SRCPMessage::new(
+        None,
+        verknuepfung.ga.0,
+        SRCPMessageID::Info {
+          info_code: "100".to_string(),
+        },
+        SRCPMessageDevice::GM,
+        vec![
+          "0".to_string(),
+          "0".to_string(),
+          "SRCP_GA_FEEDBACK_FAILED".to_string(),
+          format!(
+            "\"GA {} {} FB {}:{}\"",
+            verknuepfung.ga.1, verknuepfung.port, verknuepfung.fb.0, verknuepfung.fb.1
+          ),
+        ],
+      ));
+      false
+    });
+    fehler
+  }
+}
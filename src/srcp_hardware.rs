@@ -0,0 +1,182 @@
+//! Erkennung Raspberry PI Modell beim Start über /proc/device-tree/model.
+//! Liefert zum Modell passende Defaults für SPI Devices und GPIO Chip und warnt bei bekannten
+//! Timing Einschränkungen des Modells.
+//! Ohne erkanntes Modell (z.B. kein Raspberry PI) gelten die Defaults der Raspberry PI 1 bis 4.
+//!
+//! INI File (optional, überschreibt den Default):
+//! [hardware]
+//! gpiochip = <pfad>
+//! Die SPI Devices werden pro SRCP Server mit "spiport" konfiguriert, der Default gilt nur wenn
+//! "spiport" fehlt.
+
+use std::{collections::HashMap, fs, sync::Mutex};
+
+use log::{info, warn};
+
+/// File mit der Modellbezeichnung des Raspberry PI
+const PATH_MODEL: &str = "/proc/device-tree/model";
+/// Default GPIO Chip (BCM GPIO's)
+const GPIOCHIP_DEFAULT: &str = "/dev/gpiochip0";
+/// GPIO Chip Raspberry PI 5 (GPIO's über RP1). Bei neueren Kernel ein Link auf gpiochip0.
+const GPIOCHIP_PI5: &str = "/dev/gpiochip4";
+/// Default SPI Device für DDL (SPI0)
+const SPIPORT_DDL_DEFAULT: &str = "/dev/spidev0";
+/// Default SPI Device für S88 (SPI1)
+const SPIPORT_S88_DEFAULT: &str = "/dev/spidev1";
+/// Pfade (debugfs) zum Clock von dem der SPI Clock abgeleitet wird (core_freq).
+/// Raspberry PI 4 "vpu", ältere Modelle "core".
+const PATH_CORE_FREQ: [&str; 2] = [
+  "/sys/kernel/debug/clk/vpu/clk_rate",
+  "/sys/kernel/debug/clk/core/clk_rate",
+];
+/// Max. Wert des 12 Bit Clock Teilers "speed" der Auxiliary SPI (SPI1, SPI2)
+const SPI_AUX_MAX_SPEED: u64 = 4095;
+
+/// Raspberry PI Modelle mit unterschiedlichen Defaults oder Einschränkungen
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PiModell {
+  /// Raspberry PI Zero / Zero W, Einkern CPU
+  Zero,
+  /// Raspberry PI Zero 2 W
+  Zero2,
+  /// Raspberry PI 1, Einkern CPU
+  Pi1,
+  /// Raspberry PI 2 und 3
+  Pi2_3,
+  /// Raspberry PI 4 / 400 / CM4
+  Pi4,
+  /// Raspberry PI 5 / CM5
+  Pi5,
+  /// Kein oder unbekanntes Modell
+  Unbekannt,
+}
+impl PiModell {
+  /// Modell aus der Modellbezeichnung in /proc/device-tree/model ermitteln.
+  /// # Arguments
+  /// * model - Modellbezeichnung, z.B. "Raspberry Pi 4 Model B Rev 1.4"
+  pub fn from_model(model: &str) -> PiModell {
+    let Some(typ) = model.trim_end_matches('\0').strip_prefix("Raspberry Pi ") else {
+      return PiModell::Unbekannt;
+    };
+    if typ.starts_with("Zero 2") {
+      PiModell::Zero2
+    } else if typ.starts_with("Zero") {
+      PiModell::Zero
+    } else if typ.starts_with('5') || typ.starts_with("Compute Module 5") {
+      PiModell::Pi5
+    } else if typ.starts_with('4') || typ.starts_with("Compute Module 4") {
+      //Auch Raspberry Pi 400
+      PiModell::Pi4
+    } else if typ.starts_with(['2', '3']) || typ.starts_with("Compute Module 3") {
+      PiModell::Pi2_3
+    } else if typ.starts_with("Model") || typ.starts_with("Compute Module") {
+      //Raspberry Pi Model B Rev 2, Raspberry Pi Compute Module Rev 1.0
+      PiModell::Pi1
+    } else {
+      PiModell::Unbekannt
+    }
+  }
+
+  /// Liefert den Default GPIO Chip dieses Modells
+  pub fn get_gpiochip(&self) -> &'static str {
+    match self {
+      PiModell::Pi5 => GPIOCHIP_PI5,
+      _ => GPIOCHIP_DEFAULT,
+    }
+  }
+
+  /// Liefert die bekannten Timing Einschränkungen dieses Modells
+  pub fn get_einschraenkungen(&self) -> Vec<&'static str> {
+    match self {
+      PiModell::Zero | PiModell::Pi1 => vec![
+        "Einkern CPU: DDL Ausgabe, S88 und MFX RDS teilen sich eine CPU, Lücken im Gleissignal möglich. rt_priority für DDL verwenden.",
+        "core_freq in /boot/config.txt fixieren (core_freq=250 und core_freq_min=250).",
+      ],
+      PiModell::Zero2 | PiModell::Pi2_3 => vec![
+        "core_freq in /boot/config.txt fixieren (core_freq=250 und core_freq_min=250).",
+      ],
+      PiModell::Pi4 => vec![
+        "SPI Clock wird vom variablen VPU Clock abgeleitet, core_freq in /boot/config.txt fixieren (core_freq=250 und core_freq_min=250).",
+      ],
+      PiModell::Pi5 => vec![
+        "SPI und GPIO über RP1, SPI Clock ist nicht von core_freq abhängig. DDL Timing ungeprüft.",
+      ],
+      PiModell::Unbekannt => vec![],
+    }
+  }
+}
+
+/// Zu verwendender GPIO Chip, None solange "startup" nicht aufgerufen wurde
+static GPIOCHIP: Mutex<Option<String>> = Mutex::new(None);
+
+/// Raspberry PI Modell erkennen, Defaults festlegen und Einschränkungen als Warnung ausgeben.
+/// Muss vor dem Init der SRCP Server aufgerufen werden.
+/// Liefert Err zurück wenn die Konfiguration ungültig ist.
+/// # Arguments
+/// * config - Abschnitt [hardware] aus Konfigfile, None wenn nicht vorhanden
+pub fn startup(config: Option<&HashMap<String, Option<String>>>) -> Result<(), String> {
+  let modell = match fs::read_to_string(PATH_MODEL) {
+    Ok(model) => {
+      info!("Hardware: {}", model.trim_end_matches('\0'));
+      PiModell::from_model(&model)
+    }
+    Err(_) => {
+      warn!("Hardware: {} nicht lesbar, kein Raspberry PI?", PATH_MODEL);
+      PiModell::Unbekannt
+    }
+  };
+  for einschraenkung in modell.get_einschraenkungen() {
+    warn!("Hardware {:?}: {}", modell, einschraenkung);
+  }
+  let gpiochip = match config.and_then(|config| config.get("gpiochip")) {
+    Some(gpiochip) => gpiochip
+      .as_ref()
+      .ok_or("[hardware] gpiochip ohne Wert")?
+      .clone(),
+    None => modell.get_gpiochip().to_string(),
+  };
+  info!("Hardware: GPIO Chip {}", gpiochip);
+  *GPIOCHIP.lock().unwrap() = Some(gpiochip);
+  Ok(())
+}
+
+/// Liefert den zu verwendenden GPIO Chip
+pub fn get_gpiochip() -> String {
+  GPIOCHIP
+    .lock()
+    .unwrap()
+    .clone()
+    .unwrap_or(GPIOCHIP_DEFAULT.to_string())
+}
+
+/// Liefert das Default SPI Device für DDL, wenn "spiport" nicht konfiguriert ist
+pub fn get_spiport_ddl() -> &'static str {
+  SPIPORT_DDL_DEFAULT
+}
+
+/// Liefert das Default SPI Device für S88, wenn "spiport" nicht konfiguriert ist
+pub fn get_spiport_s88() -> &'static str {
+  SPIPORT_S88_DEFAULT
+}
+
+/// Liefert den Clock (core_freq) in Hz von dem der SPI Clock abgeleitet wird, über debugfs.
+/// None wenn nicht ermittelbar (debugfs nicht gemountet, kein Raspberry PI).
+pub fn get_core_freq() -> Option<u64> {
+  PATH_CORE_FREQ
+    .iter()
+    .find_map(|path| fs::read_to_string(path).ok()?.trim().parse::<u64>().ok())
+}
+
+/// Effektiver SPI Clock wie er durch den BCM2835 Auxiliary SPI Treiber (SPI1, S88) eingestellt wird:
+/// core_freq / (2 * (speed + 1)), speed aufgerundet und begrenzt auf 12 Bit.
+/// Kleinst möglicher Clock bei 250MHz core_freq ist damit 30.5kHz.
+/// # Arguments
+/// * core_freq - Clock in Hz von dem der SPI Clock abgeleitet wird
+/// * hz - Verlangte SPI Baudrate, > 0
+pub fn get_spi_aux_clock_effektiv(core_freq: u64, hz: u32) -> u64 {
+  let speed = core_freq
+    .div_ceil(2 * hz as u64)
+    .saturating_sub(1)
+    .min(SPI_AUX_MAX_SPEED);
+  core_freq / (2 * (speed + 1))
+}
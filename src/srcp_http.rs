@@ -0,0 +1,579 @@
+//! HTTP/WebSocket Bridge für Browser basierte Stellpulte ohne eigene SRCP Implementierung
+//! - GET /state: Aktueller Zustand aller GL, GA, FB und POWER als JSON
+//! - GET /events: WebSocket, alle INFO Messages als JSON Text Frames
+//! - GET /stats: Anzahl wegen voller Queues verworfene INFO Messages und abgelehnte Kommandos
+//! - POST /set/<bus>/<device>: Body mit den Parametern (durch Leerzeichen getrennt) wird als
+//!   "SET <bus> <device> <Parameter>" ausgeführt, Antwort als JSON
+//!
+//! JSON Format einer Message:
+//! {"bus":1,"device":"GL","code":"100","parameter":["3","1","50","100","0"]}
+//!
+//! INI File:
+//! [http]
+//! port = xxxxxx
+
+use std::{
+  collections::{BTreeMap, HashMap},
+  io::{BufRead, BufReader, Read, Write},
+  net::{IpAddr, Shutdown, TcpListener, TcpStream},
+  sync::{mpsc::SyncSender, Arc, Mutex},
+  thread,
+  time::Duration,
+};
+
+use log::{error, info, warn};
+
+use crate::{
+  srcp,
+  srcp_info_queue::{self, InfoQueueReceiver, InfoQueueSender},
+  srcp_server_types::{Message, SRCPMessage, SRCPMessageDevice, SRCPMessageID},
+};
+
+/// GUID für die Berechnung von "Sec-WebSocket-Accept" gemäss RFC 6455
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// Max. akzeptierte Länge eines Request Bodys
+const MAX_BODY_LEN: usize = 1024;
+/// Max. Wartezeit beim Senden an einen WebSocket Client, danach wird der Client getrennt
+const WEBSOCKET_WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Max. Anzahl wartende Messages pro WebSocket Client, danach wird jeweils die älteste verworfen
+const WEBSOCKET_QUEUE_MAX: usize = 1000;
+
+/// Von allen HTTP Verbindungen gemeinsam verwendeter Zustand
+struct HttpZustand {
+  /// Letzter Zustand aller GL, GA, FB und POWER, Key siehe "zustand_key"
+  alle: BTreeMap<String, SRCPMessage>,
+  /// Sender zu allen aktuell verbundenen WebSocket Clients
+  websockets: Vec<InfoQueueSender<String>>,
+}
+
+/// HTTP Request, nur soweit für diese Bridge notwendig
+struct HttpRequest {
+  methode: String,
+  pfad: String,
+  /// Alle Header, Name in Kleinbuchstaben
+  header: HashMap<String, String>,
+  body: String,
+}
+
+/// Liefert den Key für die Zustandsverwaltung einer Message.
+/// None wenn die Message keinen zu speichernden Zustand enthält.
+/// # Arguments
+/// * msg - INFO Message eines SRCP Servers
+fn zustand_key(msg: &SRCPMessage) -> Option<String> {
+  let bus = msg.bus;
+  match msg.device {
+    SRCPMessageDevice::Power => Some(format!("{} POWER", bus)),
+    SRCPMessageDevice::GL | SRCPMessageDevice::FB => Some(format!(
+      "{} {} {}",
+      bus,
+      msg.device.to_string(),
+      msg.get_adr()?
+    )),
+    SRCPMessageDevice::GA => Some(format!(
+      "{} GA {} {}",
+      bus,
+      msg.get_adr()?,
+      msg.parameter.get(1)?
+    )),
+    _ => None,
+  }
+}
+
+/// String als JSON String inkl. Anführungszeichen
+/// # Arguments
+/// * text - Zu konvertierender Text
+fn json_string(text: &str) -> String {
+  let mut result = String::from("\"");
+  for c in text.chars() {
+    match c {
+      '"' => result.push_str("\\\""),
+      '\\' => result.push_str("\\\\"),
+      c if (c as u32) < 0x20 => result.push_str(format!("\\u{:04x}", c as u32).as_str()),
+      c => result.push(c),
+    }
+  }
+  result.push('"');
+  result
+}
+
+/// SRCP Message als JSON Objekt
+/// # Arguments
+/// * msg - Zu konvertierende Message
+fn json_message(msg: &SRCPMessage) -> String {
+  let code = match &msg.message_id {
+    SRCPMessageID::Info { info_code } => info_code.clone(),
+    SRCPMessageID::Ok { ok_code } => ok_code.clone(),
+    SRCPMessageID::Err { err_code, .. } => err_code.clone(),
+    SRCPMessageID::Command { msg_type } => msg_type.to_string(),
+    SRCPMessageID::Ready | SRCPMessageID::Failed => msg.message_id.to_string(),
+  };
+  let parameter: Vec<String> = msg.parameter.iter().map(|p| json_string(p)).collect();
+  format!(
+    "{{\"bus\":{},\"device\":{},\"code\":{},\"parameter\":[{}]}}",
+    msg.bus,
+    json_string(msg.device.to_string().as_str()),
+    json_string(code.as_str()),
+    parameter.join(",")
+  )
+}
+
+/// SRCP Error als JSON Objekt
+/// # Arguments
+/// * err_code - SRCP Errorcode
+/// * err_text - SRCP Errortext
+fn json_error(err_code: &str, err_text: &str) -> String {
+  format!(
+    "{{\"error\":{},\"text\":{}}}",
+    json_string(err_code),
+    json_string(err_text)
+  )
+}
+
+/// SHA-1 Hash, wird nur für den WebSocket Handshake benötigt
+/// # Arguments
+/// * data - Daten über die der Hash berechnet wird
+fn sha1(data: &[u8]) -> [u8; 20] {
+  let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+  let mut msg = data.to_vec();
+  msg.push(0x80);
+  while msg.len() % 64 != 56 {
+    msg.push(0);
+  }
+  msg.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+  for block in msg.chunks(64) {
+    let mut w = [0u32; 80];
+    for (i, wort) in block.chunks(4).enumerate() {
+      w[i] = u32::from_be_bytes([wort[0], wort[1], wort[2], wort[3]]);
+    }
+    for i in 16..80 {
+      w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+    }
+    let [mut a, mut b, mut c, mut d, mut e] = h;
+    for (i, wi) in w.iter().enumerate() {
+      let (f, k) = match i {
+        0..=19 => ((b & c) | (!b & d), 0x5A827999),
+        20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+        40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+        _ => (b ^ c ^ d, 0xCA62C1D6),
+      };
+      let temp = a
+        .rotate_left(5)
+        .wrapping_add(f)
+        .wrapping_add(e)
+        .wrapping_add(k)
+        .wrapping_add(*wi);
+      e = d;
+      d = c;
+      c = b.rotate_left(30);
+      b = a;
+      a = temp;
+    }
+    for (hi, x) in h.iter_mut().zip([a, b, c, d, e]) {
+      *hi = hi.wrapping_add(x);
+    }
+  }
+  let mut result = [0u8; 20];
+  for (i, hi) in h.iter().enumerate() {
+    result[i * 4..i * 4 + 4].copy_from_slice(&hi.to_be_bytes());
+  }
+  result
+}
+
+/// Base64 Kodierung, wird nur für den WebSocket Handshake benötigt
+/// # Arguments
+/// * data - Zu kodierende Daten
+fn base64(data: &[u8]) -> String {
+  const ZEICHEN: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+  let mut result = String::new();
+  for chunk in data.chunks(3) {
+    let n = (u32::from(chunk[0]) << 16)
+      | (u32::from(*chunk.get(1).unwrap_or(&0)) << 8)
+      | u32::from(*chunk.get(2).unwrap_or(&0));
+    for i in 0..4 {
+      if i <= chunk.len() {
+        result.push(ZEICHEN[((n >> (18 - 6 * i)) & 0x3F) as usize] as char);
+      } else {
+        result.push('=');
+      }
+    }
+  }
+  result
+}
+
+/// Liefert "Sec-WebSocket-Accept" zu einem "Sec-WebSocket-Key" gemäss RFC 6455
+/// # Arguments
+/// * key - Sec-WebSocket-Key aus dem Upgrade Request
+pub fn websocket_accept(key: &str) -> String {
+  base64(&sha1(format!("{}{}", key, WEBSOCKET_GUID).as_bytes()))
+}
+
+/// HTTP Request lesen.
+/// Liefert Err bei Verbindungsabbruch oder ungültigem Request
+/// # Arguments
+/// * reader - Reader auf den TCP Stream des Clients
+fn read_request(reader: &mut BufReader<&TcpStream>) -> Result<HttpRequest, String> {
+  let mut line = String::new();
+  reader
+    .read_line(&mut line)
+    .or(Err("HTTP read_line Error"))?;
+  let mut teile = line.split_whitespace();
+  let methode = teile.next().ok_or("HTTP Request ohne Methode")?.to_string();
+  let pfad = teile.next().ok_or("HTTP Request ohne Pfad")?.to_string();
+  let mut header: HashMap<String, String> = HashMap::new();
+  loop {
+    line.clear();
+    reader
+      .read_line(&mut line)
+      .or(Err("HTTP read_line Error"))?;
+    let line = line.trim();
+    if line.is_empty() {
+      break;
+    }
+    if let Some((name, wert)) = line.split_once(':') {
+      header.insert(name.trim().to_ascii_lowercase(), wert.trim().to_string());
+    }
+  }
+  let len = match header.get("content-length") {
+    Some(len) => len
+      .parse::<usize>()
+      .ok()
+      .filter(|len| *len <= MAX_BODY_LEN)
+      .ok_or("HTTP ungültige Content-Length")?,
+    None => 0,
+  };
+  let mut body = vec![0u8; len];
+  reader
+    .read_exact(&mut body)
+    .or(Err("HTTP Body read Error"))?;
+  Ok(HttpRequest {
+    methode,
+    pfad,
+    header,
+    body: String::from_utf8_lossy(&body).to_string(),
+  })
+}
+
+/// HTTP Antwort mit JSON Body senden
+/// # Arguments
+/// * client_stream - TCP Stream zum Client
+/// * status - HTTP Status inkl. Text, z.B. "200 OK"
+/// * json - JSON Body
+fn send_response(mut client_stream: &TcpStream, status: &str, json: &str) -> Result<(), String> {
+  let antwort = format!(
+    "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+     Access-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n{}",
+    status,
+    json.len(),
+    json
+  );
+  client_stream
+    .write_all(antwort.as_bytes())
+    .or(Err("HTTP Write to client Error".to_string()))
+}
+
+/// WebSocket Text Frame senden.
+/// Der Stream ist blockierend, "write_all" sendet das Frame damit immer vollständig oder liefert Err.
+/// # Arguments
+/// * client_stream - TCP Stream zum Client
+/// * text - Zu sendender Text
+fn send_websocket_text(mut client_stream: &TcpStream, text: &str) -> Result<(), String> {
+  let len = text.len();
+  //FIN + Text Frame, Server Frames sind nicht maskiert
+  let mut frame: Vec<u8> = vec![0x81];
+  if len < 126 {
+    frame.push(len as u8);
+  } else if len <= 0xFFFF {
+    frame.push(126);
+    frame.extend_from_slice(&(len as u16).to_be_bytes());
+  } else {
+    frame.push(127);
+    frame.extend_from_slice(&(len as u64).to_be_bytes());
+  }
+  frame.extend_from_slice(text.as_bytes());
+  client_stream
+    .write_all(&frame)
+    .or(Err("WebSocket Write to client Error".to_string()))
+}
+
+/// WebSocket Client bedienen: Handshake, danach alle Zustände und alle neuen INFO Messages senden.
+/// Vom Client empfangene Frames (Ping, Close) werden in einem eigenen Thread gelesen und ignoriert,
+/// damit die Ausgabe hier blockierend erfolgen kann.
+/// # Arguments
+/// * client_stream - TCP Stream von/zu diesem Client
+/// * request - Der Upgrade Request
+/// * zustand - Gemeinsamer Zustand
+fn handle_websocket(
+  mut client_stream: &TcpStream, request: &HttpRequest, zustand: &Arc<Mutex<HttpZustand>>,
+) -> Result<(), String> {
+  let key = request
+    .header
+    .get("sec-websocket-key")
+    .ok_or("WebSocket ohne Sec-WebSocket-Key")?;
+  let accept = websocket_accept(key);
+  client_stream
+    .write_all(
+      format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {}\r\n\r\n",
+        accept
+      )
+      .as_bytes(),
+    )
+    .or(Err("WebSocket Handshake Write Error"))?;
+  //Ein nicht mehr lesender Client darf diesen Thread nicht endlos blockieren
+  client_stream
+    .set_write_timeout(Some(WEBSOCKET_WRITE_TIMEOUT))
+    .or(Err("WebSocket set_write_timeout fail"))?;
+  //Eingangsbuffer in eigenem Thread leeren, bei Verbindungsende auch die Ausgabe beenden
+  let mut client_read_stream = client_stream
+    .try_clone()
+    .or(Err("WebSocket try_clone fail"))?;
+  thread::Builder::new()
+    .name("HTTP_WebSocket_Read".to_string())
+    .spawn(move || {
+      let mut buf = [0u8; 256];
+      while matches!(client_read_stream.read(&mut buf), Ok(len) if len > 0) {}
+      let _ = client_read_stream.shutdown(Shutdown::Both);
+    })
+    .or(Err("WebSocket Read Thread start fail"))?;
+  //Anmelden und aktuellen Zustand senden, unter Lock damit keine Message verloren geht
+  let (tx, rx) = srcp_info_queue::info_queue(WEBSOCKET_QUEUE_MAX);
+  let alle: Vec<String> = {
+    let mut guard = zustand.lock().unwrap();
+    guard.websockets.push(tx);
+    guard.alle.values().map(json_message).collect()
+  };
+  for msg in alle {
+    send_websocket_text(client_stream, msg.as_str())?;
+  }
+  //Und ab jetzt einfach alle Info Meldungen weitersenden
+  for msg in rx.iter() {
+    send_websocket_text(client_stream, msg.as_str())?;
+  }
+  Ok(())
+}
+
+/// SET Kommando aus POST /set/<bus>/<device> ausführen
+/// Liefert HTTP Status und JSON Antwort
+/// # Arguments
+/// * request - Der POST Request
+/// * adresse - IP Adresse des HTTP Clients für die Zugriffskontrolle
+/// * session_id - Session ID des Command Mode Clients der Bridge
+/// * cmd_rx - Channel Receiver des Command Mode Clients der Bridge
+/// * all_cmd_tx - Alle Channel Sender für Kommandos zu den SRCP Servern. Key ist die Busnummer.
+fn handle_set(
+  request: &HttpRequest, adresse: IpAddr, session_id: u32,
+  cmd_rx: &Mutex<InfoQueueReceiver<SRCPMessage>>, all_cmd_tx: &HashMap<usize, SyncSender<Message>>,
+) -> (&'static str, String) {
+  let pfad = request.pfad.to_ascii_uppercase();
+  let teile: Vec<&str> = pfad.trim_matches('/').split('/').collect();
+  let [_, bus, device] = teile[..] else {
+    return ("404 Not Found", "{\"error\":\"404\"}".to_string());
+  };
+  let body = request.body.to_ascii_uppercase();
+  let mut cmd_parts: Vec<&str> = vec!["SET", bus, device];
+  cmd_parts.extend(body.split_whitespace());
+  let antwort = SRCPMessage::from(session_id, &cmd_parts).and_then(|srcp_msg| {
+    //Es wird immer nur ein Kommando um das andere abgearbeitet
+    let cmd_rx = cmd_rx.lock().unwrap();
+    srcp::execute_command(srcp_msg, Some(adresse), &cmd_rx, all_cmd_tx).map(|(_, antwort)| antwort)
+  });
+  match antwort {
+    Ok(msg) => {
+      if let SRCPMessageID::Err { err_code, err_text } = &msg.message_id {
+        ("400 Bad Request", json_error(err_code, err_text))
+      } else {
+        ("200 OK", json_message(&msg))
+      }
+    }
+    Err((err_code, err_text)) => ("400 Bad Request", json_error(err_code, err_text)),
+  }
+}
+
+/// HTTP Client bedienen
+/// # Arguments
+/// * client_stream - TCP Stream von/zu diesem Client
+/// * adresse - IP Adresse dieses Clients für die Zugriffskontrolle
+/// * zustand - Gemeinsamer Zustand
+/// * session_id - Session ID des Command Mode Clients der Bridge
+/// * cmd_rx - Channel Receiver des Command Mode Clients der Bridge
+/// * all_cmd_tx - Alle Channel Sender für Kommandos zu den SRCP Servern. Key ist die Busnummer.
+fn handle_http_connection(
+  client_stream: &TcpStream, adresse: IpAddr, zustand: &Arc<Mutex<HttpZustand>>, session_id: u32,
+  cmd_rx: &Mutex<InfoQueueReceiver<SRCPMessage>>, all_cmd_tx: &HashMap<usize, SyncSender<Message>>,
+) -> Result<(), String> {
+  let request = read_request(&mut BufReader::new(client_stream))?;
+  let websocket = request
+    .header
+    .get("upgrade")
+    .is_some_and(|upgrade| upgrade.eq_ignore_ascii_case("websocket"));
+  match (request.methode.as_str(), request.pfad.as_str()) {
+    ("GET", "/events") if websocket => handle_websocket(client_stream, &request, zustand),
+    ("GET", "/state") => {
+      let alle: Vec<String> = zustand
+        .lock()
+        .unwrap()
+        .alle
+        .values()
+        .map(json_message)
+        .collect();
+      send_response(
+        client_stream,
+        "200 OK",
+        format!("{{\"state\":[{}]}}", alle.join(",")).as_str(),
+      )
+    }
+    ("GET", "/stats") => {
+      let (info_verworfen, cmd_abgelehnt) = srcp::get_queue_statistik();
+      send_response(
+        client_stream,
+        "200 OK",
+        format!("{{\"info_dropped\":{info_verworfen},\"cmd_rejected\":{cmd_abgelehnt}}}").as_str(),
+      )
+    }
+    ("POST", pfad) if pfad.starts_with("/set/") => {
+      let (status, json) = handle_set(&request, adresse, session_id, cmd_rx, all_cmd_tx);
+      send_response(client_stream, status, json.as_str())
+    }
+    _ => send_response(client_stream, "404 Not Found", "{\"error\":\"404\"}"),
+  }
+}
+
+/// Empfang aller INFO Messages: Zustand nachführen und an alle WebSocket Clients weiterleiten
+/// # Arguments
+/// * info_rx - Channel Receiver des Info Mode Clients der Bridge
+/// * zustand - Gemeinsamer Zustand
+fn dispatcher_http(info_rx: InfoQueueReceiver<SRCPMessage>, zustand: Arc<Mutex<HttpZustand>>) {
+  for msg in info_rx.iter() {
+    let SRCPMessageID::Info { info_code } = &msg.message_id else {
+      continue;
+    };
+    let json = json_message(&msg);
+    let mut guard = zustand.lock().unwrap();
+    if info_code == "102" {
+      //Gelöscht (z.B. TERM GL), bei GA alle Ports
+      if let Some(adr) = msg.get_adr() {
+        let key = format!("{} {} {}", msg.bus, msg.device.to_string(), adr);
+        let key_port = format!("{} ", key);
+        guard
+          .alle
+          .retain(|alle_key, _| (*alle_key != key) && !alle_key.starts_with(key_port.as_str()));
+      }
+    } else if info_code == "100" {
+      //Nur Zustände, INFO 101 (INIT) enthält andere Parameter
+      if let Some(key) = zustand_key(&msg) {
+        guard.alle.insert(key, msg.clone());
+      }
+    }
+    //Nicht mehr verbundene WebSocket Clients entfernen, bei einem langsamen Client wird die älteste
+    //Message verworfen
+    guard
+      .websockets
+      .retain(|sender| match sender.send(json.clone()) {
+        Ok(verworfen) => {
+          if verworfen {
+            srcp::add_info_verworfen();
+          }
+          true
+        }
+        Err(_) => false,
+      });
+  }
+  error!("HTTP Dispatcher beendet");
+}
+
+/// HTTP Server der auf eingehende Verbindungen wartet und jede Verbindung in einem eigenen Thread bedient
+/// # Arguments
+/// * listener - TCP Listener des HTTP Servers
+/// * zustand - Gemeinsamer Zustand
+/// * all_cmd_tx - Alle Channel Sender für Kommandos zu den SRCP Servern. Key ist die Busnummer.
+fn http_server(
+  listener: TcpListener, zustand: Arc<Mutex<HttpZustand>>,
+  all_cmd_tx: HashMap<usize, SyncSender<Message>>,
+) {
+  //Alle SET Kommandos werden über eine gemeinsame Command Mode Session ausgeführt
+  let session_id = srcp::neue_session_id();
+  let cmd_rx = Arc::new(Mutex::new(srcp::anmelden_intern_command_client(session_id)));
+  let all_cmd_tx = Arc::new(all_cmd_tx);
+  loop {
+    let (client_stream, addr) = match listener.accept() {
+      Ok(client) => client,
+      Err(msg) => {
+        warn!("HTTP Server Accept fail: {}", msg);
+        continue;
+      }
+    };
+    let zustand = zustand.clone();
+    let cmd_rx = cmd_rx.clone();
+    let all_cmd_tx = all_cmd_tx.clone();
+    thread::Builder::new()
+      .name(format!("HTTP_Client_Thread Client={}", addr))
+      .spawn(move || {
+        if let Err(msg) = handle_http_connection(
+          &client_stream,
+          addr.ip(),
+          &zustand,
+          session_id,
+          &cmd_rx,
+          &all_cmd_tx,
+        ) {
+          info!("HTTP Client {}: {}", addr, msg);
+        }
+      })
+      .unwrap();
+  }
+}
+
+/// HTTP Port aus Konfigfile lesen.
+/// Liefert None wenn die HTTP/WebSocket Bridge nicht konfiguriert ist, Err wenn die Konfiguration ungültig ist.
+/// # Arguments
+/// * config - Abschnitt [http] aus Konfigfile, None wenn nicht vorhanden
+pub fn port_from_config(
+  config: Option<&HashMap<String, Option<String>>>,
+) -> Result<Option<u16>, String> {
+  let Some(port) = config.and_then(|config| config.get("port")) else {
+    return Ok(None);
+  };
+  Ok(Some(
+    port
+      .as_ref()
+      .ok_or("[http] port-Angabe ohne Wert")?
+      .parse::<u16>()
+      .ok()
+      .ok_or("[http] port muss eine Zahl sein")?,
+  ))
+}
+
+/// Startet die HTTP/WebSocket Bridge, wenn konfiguriert
+/// # Arguments
+/// * port - HTTP Port, None wenn nicht konfiguriert, siehe "port_from_config"
+/// * all_cmd_tx - Alle Channel Sender für Kommandos zu den SRCP Servern. Key ist die Busnummer.
+pub fn startup(
+  port: Option<u16>, all_cmd_tx: &HashMap<usize, SyncSender<Message>>,
+) -> Result<(), String> {
+  let Some(port) = port else {
+    return Ok(());
+  };
+  let listener = TcpListener::bind(("0.0.0.0", port)).or(Err(format!(
+    "HTTP Server konnte nicht auf Port {} gestartet werden",
+    port
+  )))?;
+  info!("Start HTTP Server: Port {}", port);
+  let zustand = Arc::new(Mutex::new(HttpZustand {
+    alle: BTreeMap::new(),
+    websockets: Vec::new(),
+  }));
+  //Als Info Client anmelden, alle Server senden danach ihren aktuellen Zustand
+  let info_rx = srcp::anmelden_intern_info_client(srcp::neue_session_id(), all_cmd_tx);
+  let zustand_dispatcher = zustand.clone();
+  thread::Builder::new()
+    .name("HTTP_Dispatcher".to_string())
+    .spawn(move || dispatcher_http(info_rx, zustand_dispatcher))
+    .unwrap();
+  let all_cmd_tx = all_cmd_tx.clone();
+  thread::Builder::new()
+    .name("HTTP".to_string())
+    .spawn(move || http_server(listener, zustand, all_cmd_tx))
+    .unwrap();
+  Ok(())
+}
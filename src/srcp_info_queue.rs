@@ -0,0 +1,145 @@
+//! Begrenzte Queue vom Info Dispatcher zu einem Client (SRCP Session, WebSocket).
+//! Ist die Queue voll, dann wird die älteste wartende Message verworfen (drop-oldest): ein langsamer
+//! Client verpasst Zwischenzustände, erhält aber immer die neusten. Der Sender blockiert nie.
+
+use std::{
+  collections::VecDeque,
+  sync::{
+    mpsc::{RecvError, RecvTimeoutError, TryRecvError},
+    Arc, Condvar, Mutex,
+  },
+  time::{Duration, Instant},
+};
+
+/// Inhalt der Queue, von Sender und Empfänger gemeinsam verwendet
+struct QueueInhalt<T> {
+  /// Wartende Messages, älteste zuerst
+  messages: VecDeque<T>,
+  /// Max. Anzahl wartende Messages
+  max: usize,
+  /// Sender nicht mehr vorhanden
+  sender_weg: bool,
+  /// Empfänger nicht mehr vorhanden
+  receiver_weg: bool,
+}
+
+/// Von Sender und Empfänger gemeinsam verwendete Queue
+struct Queue<T> {
+  inhalt: Mutex<QueueInhalt<T>>,
+  /// Signalisiert neue Messages und das Ende des Senders
+  neu: Condvar,
+}
+
+/// Neue Queue erstellen
+/// # Arguments
+/// * max - Max. Anzahl wartende Messages, mindestens 1
+pub fn info_queue<T>(max: usize) -> (InfoQueueSender<T>, InfoQueueReceiver<T>) {
+  let queue = Arc::new(Queue {
+    inhalt: Mutex::new(QueueInhalt {
+      messages: VecDeque::new(),
+      max: max.max(1),
+      sender_weg: false,
+      receiver_weg: false,
+    }),
+    neu: Condvar::new(),
+  });
+  (
+    InfoQueueSender {
+      queue: queue.clone(),
+    },
+    InfoQueueReceiver { queue },
+  )
+}
+
+/// Sender einer Info Queue
+pub struct InfoQueueSender<T> {
+  queue: Arc<Queue<T>>,
+}
+impl<T> InfoQueueSender<T> {
+  /// Message anhängen, blockiert nie. Ist die Queue voll, dann wird die älteste Message verworfen.
+  /// Liefert true wenn eine Message verworfen wurde, Err mit "msg" wenn der Empfänger nicht mehr vorhanden ist.
+  /// # Arguments
+  /// * msg - Zu sendende Message
+  pub fn send(&self, msg: T) -> Result<bool, T> {
+    let mut inhalt = self.queue.inhalt.lock().unwrap();
+    if inhalt.receiver_weg {
+      return Err(msg);
+    }
+    let verworfen = inhalt.messages.len() >= inhalt.max;
+    if verworfen {
+      inhalt.messages.pop_front();
+    }
+    inhalt.messages.push_back(msg);
+    self.queue.neu.notify_one();
+    Ok(verworfen)
+  }
+}
+impl<T> Drop for InfoQueueSender<T> {
+  fn drop(&mut self) {
+    self.queue.inhalt.lock().unwrap().sender_weg = true;
+    self.queue.neu.notify_one();
+  }
+}
+
+/// Empfänger einer Info Queue, gleiche Schnittstelle wie mpsc::Receiver
+pub struct InfoQueueReceiver<T> {
+  queue: Arc<Queue<T>>,
+}
+impl<T> InfoQueueReceiver<T> {
+  /// Älteste Message abholen ohne zu warten
+  pub fn try_recv(&self) -> Result<T, TryRecvError> {
+    let mut inhalt = self.queue.inhalt.lock().unwrap();
+    match inhalt.messages.pop_front() {
+      Some(msg) => Ok(msg),
+      None if inhalt.sender_weg => Err(TryRecvError::Disconnected),
+      None => Err(TryRecvError::Empty),
+    }
+  }
+
+  /// Auf die älteste Message warten.
+  /// Liefert Err wenn keine Message mehr wartet und der Sender nicht mehr vorhanden ist.
+  pub fn recv(&self) -> Result<T, RecvError> {
+    let mut inhalt = self.queue.inhalt.lock().unwrap();
+    loop {
+      if let Some(msg) = inhalt.messages.pop_front() {
+        return Ok(msg);
+      }
+      if inhalt.sender_weg {
+        return Err(RecvError);
+      }
+      inhalt = self.queue.neu.wait(inhalt).unwrap();
+    }
+  }
+
+  /// Max. "timeout" auf die älteste Message warten
+  /// # Arguments
+  /// * timeout - Max. Wartezeit
+  pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+    let ende = Instant::now() + timeout;
+    let mut inhalt = self.queue.inhalt.lock().unwrap();
+    loop {
+      if let Some(msg) = inhalt.messages.pop_front() {
+        return Ok(msg);
+      }
+      if inhalt.sender_weg {
+        return Err(RecvTimeoutError::Disconnected);
+      }
+      let Some(warten) = ende.checked_duration_since(Instant::now()) else {
+        return Err(RecvTimeoutError::Timeout);
+      };
+      inhalt = self.queue.neu.wait_timeout(inhalt, warten).unwrap().0;
+    }
+  }
+
+  /// Iterator über alle Messages, endet wenn der Sender nicht mehr vorhanden ist
+  pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+    std::iter::from_fn(|| self.recv().ok())
+  }
+}
+impl<T> Drop for InfoQueueReceiver<T> {
+  fn drop(&mut self) {
+    let mut inhalt = self.queue.inhalt.lock().unwrap();
+    inhalt.receiver_weg = true;
+    inhalt.messages.clear();
+  }
+}
@@ -0,0 +1,119 @@
+//! Journal aller akzeptierten SET Kommandos und Replay
+//! Jedes durch einen SRCP Server akzeptierte SET Kommando wird mit Timestamp und Session ID in das
+//! Journal File geschrieben:
+//! <sekunden>.<millisekunden> <session_id> SET <bus> <device> <Parameter>
+//!
+//! Mit "srcpd -r <journalfile>" werden alle Kommandos mit dem ursprünglichen zeitlichen Ablauf erneut
+//! an einen laufenden srcpd gesendet. Für jede ursprüngliche Session wird eine eigene Command Mode
+//! Session eröffnet. Damit können zeitabhängige Dekoderprobleme nachvollzogen werden.
+//!
+//! INI File:
+//! [srcp]
+//! journal = <journalfile>
+
+use std::{
+  collections::{hash_map::Entry, HashMap},
+  fs::{self, File, OpenOptions},
+  io::{BufReader, Write},
+  net::TcpStream,
+  sync::Mutex,
+  thread,
+  time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use log::{info, warn};
+
+use crate::{
+  srcp_client::{neue_session, send_srcp_line},
+  srcp_server_types::{SRCPMessage, SRCPMessageID, SRCPMessageType},
+};
+
+/// Journal File, None wenn kein Journal konfiguriert ist
+static JOURNAL: Mutex<Option<File>> = Mutex::new(None);
+
+/// Journal File öffnen, neue Kommandos werden angehängt
+/// # Arguments
+/// * path - Pfad des Journal Files
+pub fn start(path: &str) -> Result<(), String> {
+  let file = OpenOptions::new()
+    .create(true)
+    .append(true)
+    .open(path)
+    .or(Err(format!("Journal {} kann nicht geöffnet werden", path)))?;
+  info!("Journal: {}", path);
+  *JOURNAL.lock().unwrap() = Some(file);
+  Ok(())
+}
+
+/// Kommando in Journal schreiben, wenn es ein SET Kommando ist und akzeptiert wurde
+/// # Arguments
+/// * cmd - Ausgeführtes Kommando
+/// * antwort - Antwort des SRCP Servers auf das Kommando
+pub fn write(cmd: &SRCPMessage, antwort: &SRCPMessage) {
+  let SRCPMessageID::Command {
+    msg_type: SRCPMessageType::SET,
+  } = cmd.message_id
+  else {
+    return;
+  };
+  if let SRCPMessageID::Err { .. } = antwort.message_id {
+    return;
+  }
+  let mut guard = JOURNAL.lock().unwrap();
+  let Some(file) = guard.as_mut() else {
+    return;
+  };
+  let time = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .expect("Time went backwards");
+  if writeln!(
+    file,
+    "{}.{:0>3} {} {}",
+    time.as_secs(),
+    time.subsec_millis(),
+    cmd.session_id.unwrap_or(0),
+    cmd.to_string().trim_end()
+  )
+  .is_err()
+  {
+    warn!("Journal write fail");
+  }
+}
+
+/// Alle Kommandos eines Journal Files mit dem ursprünglichen zeitlichen Ablauf an einen laufenden
+/// srcpd senden. Jede Session des Journals erhält eine eigene Session.
+/// # Arguments
+/// * path - Pfad des Journal Files
+/// * port - TCP Port des srcpd auf localhost
+pub fn replay(path: &str, port: u16) -> Result<(), String> {
+  let journal =
+    fs::read_to_string(path).or(Err(format!("Journal {} kann nicht gelesen werden", path)))?;
+  //Key: Session ID aus Journal
+  let mut sessions: HashMap<u32, BufReader<TcpStream>> = HashMap::new();
+  //Start: Zeitpunkt jetzt und erster Timestamp im Journal
+  let mut start: Option<(Instant, f64)> = None;
+  for (nr, line) in journal.lines().enumerate() {
+    let mut teile = line.splitn(3, ' ');
+    let (Some(Ok(timestamp)), Some(Ok(session_id)), Some(cmd)) = (
+      teile.next().map(|t| t.parse::<f64>()),
+      teile.next().map(|s| s.parse::<u32>()),
+      teile.next(),
+    ) else {
+      warn!("Journal Zeile {} ungültig: {}", nr + 1, line);
+      continue;
+    };
+    //Warten bis zum ursprünglichen Zeitpunkt
+    let (start_instant, start_timestamp) = *start.get_or_insert((Instant::now(), timestamp));
+    let zeitpunkt = start_instant + Duration::from_secs_f64((timestamp - start_timestamp).max(0.0));
+    if let Some(warten) = zeitpunkt.checked_duration_since(Instant::now()) {
+      thread::sleep(warten);
+    }
+    let session = match sessions.entry(session_id) {
+      Entry::Occupied(session) => session.into_mut(),
+      Entry::Vacant(session) => session.insert(neue_session(port)?),
+    };
+    let antwort = send_srcp_line(session, cmd)?;
+    info!("Replay Session {}: {} -> {}", session_id, cmd, antwort);
+  }
+  Ok(())
+}
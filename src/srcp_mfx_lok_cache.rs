@@ -0,0 +1,165 @@
+//! Persistenter Cache der aus MFX Dekodern gelesenen Lokparameter (Name, Funktionen, Anzahl Funktionen).
+//! Schlüssel ist die UID des Dekoders, damit muss bei einer bekannten Lok nach einer Neuanmeldung
+//! nicht erneut über die langsamen RDS Rückmeldungen ausgelesen werden.
+//!
+//! File, pro Dekoder eine Zeile, Felder mit Tab getrennt:
+//! <uid>\t<anzahl funktionen>\t<"Lokname">\t<f0>\t<f1>...
+//! Tab, Zeilenumbruch (CR, LF) und Backslash in den Feldern werden als \t, \r, \n und \\ gespeichert.
+
+use std::{collections::HashMap, fs};
+
+use log::{info, warn};
+
+/// Aus einem Dekoder gelesene Lokparameter
+#[derive(Clone)]
+pub struct MfxLokDaten {
+  /// INIT Parameter: Lokname in Anführungszeichen und Funktionen
+  pub param: Vec<String>,
+  /// Anzahl Schaltfunktionen des Dekoders
+  pub anz_f: usize,
+}
+
+/// Cache aller bekannten Loks
+pub struct MfxLokCache {
+  /// File in dem der Cache gespeichert wird, None: Cache ausgeschaltet
+  path: Option<String>,
+  /// Alle bekannten Loks, Key ist die UID
+  eintraege: HashMap<u32, MfxLokDaten>,
+}
+
+impl MfxLokCache {
+  /// Neuer Cache, ohne File ausgeschaltet.
+  /// Ein vorhandenes File wird geladen, ungültige Zeilen werden ignoriert.
+  /// # Arguments
+  /// * path - File in dem der Cache gespeichert wird, None: kein Cache
+  pub fn new(path: Option<String>) -> MfxLokCache {
+    let mut eintraege = HashMap::new();
+    if let Some(path) = &path {
+      if let Ok(inhalt) = fs::read_to_string(path) {
+        for zeile in inhalt.lines() {
+          if let Some((uid, lok_daten)) = Self::parse_zeile(zeile) {
+            eintraege.insert(uid, lok_daten);
+          } else {
+            warn!("MFX Lokcache {}: ungültige Zeile {}", path, zeile);
+          }
+        }
+        info!("MFX Lokcache {} mit {} Loks geladen", path, eintraege.len());
+      } else {
+        info!("MFX Lokcache {} nicht vorhanden, wird neu erstellt", path);
+      }
+    }
+    MfxLokCache { path, eintraege }
+  }
+
+  /// Eine Zeile des Cachefiles auswerten
+  /// # Arguments
+  /// * zeile - <uid>\t<anzahl funktionen>\t<parameter>...
+  fn parse_zeile(zeile: &str) -> Option<(u32, MfxLokDaten)> {
+    let mut felder = zeile.split('\t');
+    let uid = felder.next()?.parse::<u32>().ok()?;
+    let anz_f = felder.next()?.parse::<usize>().ok()?;
+    let param: Vec<String> = felder.map(Self::demaskieren).collect();
+    //Mindestens der Lokname muss vorhanden sein
+    if param.is_empty() {
+      return None;
+    }
+    Some((uid, MfxLokDaten { param, anz_f }))
+  }
+
+  /// Ein Feld für das File maskieren, damit Tab und Zeilenumbruch (z.B. im Loknamen) die Zeile nicht
+  /// zerstören.
+  /// # Arguments
+  /// * feld - Zu speicherndes Feld
+  fn maskieren(feld: &str) -> String {
+    let mut result = String::with_capacity(feld.len());
+    for c in feld.chars() {
+      match c {
+        '\\' => result.push_str("\\\\"),
+        '\t' => result.push_str("\\t"),
+        '\n' => result.push_str("\\n"),
+        '\r' => result.push_str("\\r"),
+        c => result.push(c),
+      }
+    }
+    result
+  }
+
+  /// Ein mit "maskieren" maskiertes Feld aus dem File zurückwandeln
+  /// # Arguments
+  /// * feld - Feld aus dem File
+  fn demaskieren(feld: &str) -> String {
+    let mut result = String::with_capacity(feld.len());
+    let mut chars = feld.chars();
+    while let Some(c) = chars.next() {
+      if c != '\\' {
+        result.push(c);
+        continue;
+      }
+      match chars.next() {
+        Some('t') => result.push('\t'),
+        Some('n') => result.push('\n'),
+        Some('r') => result.push('\r'),
+        Some(c) => result.push(c),
+        None => result.push('\\'),
+      }
+    }
+    result
+  }
+
+  /// Liefert die gespeicherten Lokparameter zu einer UID, None wenn unbekannt oder Cache ausgeschaltet
+  /// # Arguments
+  /// * uid - UID des Dekoders
+  pub fn get(&self, uid: u32) -> Option<&MfxLokDaten> {
+    self.eintraege.get(&uid)
+  }
+
+  /// Lokparameter zu einer UID speichern
+  /// # Arguments
+  /// * uid - UID des Dekoders
+  /// * lok_daten - Aus dem Dekoder gelesene Lokparameter
+  pub fn set(&mut self, uid: u32, lok_daten: MfxLokDaten) {
+    if self.path.is_some() {
+      self.eintraege.insert(uid, lok_daten);
+      self.speichern();
+    }
+  }
+
+  /// Lokparameter zu einer UID entfernen, beim nächsten Mal wird wieder aus dem Dekoder gelesen.
+  /// # Arguments
+  /// * uid - UID des Dekoders
+  pub fn entfernen(&mut self, uid: u32) {
+    if self.eintraege.remove(&uid).is_some() {
+      info!("MFX Lokcache UID={} entfernt", uid);
+      self.speichern();
+    }
+  }
+
+  /// Cache speichern
+  /// Zuerst in ein temporäres File, dann umbenennen, damit bei einem Absturz während dem Schreiben
+  /// nie ein unvollständiges File zurückbleibt.
+  fn speichern(&self) {
+    if let Some(path) = &self.path {
+      let mut uids: Vec<&u32> = self.eintraege.keys().collect();
+      uids.sort();
+      let mut inhalt = String::new();
+      for uid in uids {
+        let lok_daten = &self.eintraege[uid];
+        inhalt.push_str(&format!(
+          "{}\t{}\t{}\n",
+          uid,
+          lok_daten.anz_f,
+          lok_daten
+            .param
+            .iter()
+            .map(|feld| Self::maskieren(feld))
+            .collect::<Vec<String>>()
+            .join("\t")
+        ));
+      }
+      let path_tmp = format!("{}.tmp", path);
+      if fs::write(&path_tmp, inhalt).is_err() || fs::rename(&path_tmp, path).is_err() {
+        warn!("MFX Lokcache {} konnte nicht gespeichert werden.", path);
+      }
+    }
+  }
+}
@@ -1,15 +1,27 @@
+use chrono::Local;
 use gpio_cdev::{Chip, LineHandle, LineRequestFlags};
 use log::{debug, info, warn};
 use std::{
   cmp::min,
-  collections::HashMap,
+  collections::{HashMap, HashSet},
+  fs::{File, OpenOptions},
+  io::Write,
   net::UdpSocket,
-  sync::mpsc::{Receiver, Sender},
+  sync::{
+    atomic::{AtomicBool, AtomicU8, Ordering},
+    mpsc::{Receiver, RecvTimeoutError, Sender},
+    Arc,
+  },
   thread,
   time::{Duration, Instant},
 };
 
-use crate::srcp_protocol_ddl::{SmReadWrite, SmReadWriteType};
+use crate::{
+  srcp_dekoder_ident::DekoderIdent,
+  srcp_hardware,
+  srcp_protocol_ddl::{SmReadWrite, SmReadWriteType},
+  srcp_server_types::ThreadSender,
+};
 
 /// Input RDS Qual Signal GPIO 23 (= Pin 16)
 const GPIO_MFX_RDS_QAL: u32 = 23;
@@ -21,6 +33,55 @@ const GPIO_MFX_RDS_DAT: u32 = 25;
 /// Anzahl MFX Funktionen
 const MFX_FX_COUNT: usize = 16;
 
+/// Max. Wartezeit auf einen neuen Auftrag, danach werden ohne Auftrag empfangene UDP Daten verworfen
+const RDS_AUFTRAG_TIMEOUT: Duration = Duration::from_secs(1);
+/// Default Timeout für eine RDS Rückmeldung
+const RDS_TIMEOUT: Duration = Duration::from_millis(500);
+/// Default Anzahl Versuche pro CV
+const RDS_VERSUCHE: usize = 5;
+/// Default Frist für das Auslesen aller Lokparameter nach einer Anmeldung
+const RDS_FRIST_ANMELDUNG: Duration = Duration::from_secs(60);
+/// Bei Wiederholungen wird der Timeout jeweils verdoppelt, max. bis zu diesem Faktor
+const RDS_TIMEOUT_FAKTOR_MAX: u32 = 4;
+/// Anzahl Indexe in einem MFX CV
+const MFX_CV_ANZ_INDEX: usize = 64;
+
+/// SM Type Zugriff auf 4 aufeinanderfolgende MFX Konfigvariabeln als 32 Bit Wert (erstes Byte = MSB)
+pub const SM_TYPE_CAMFX32: &str = "CAMFX32";
+/// SM Type Lokname (CaGrundLokname) als Text
+pub const SM_TYPE_LOKNAME: &str = "LOKNAME";
+/// SM Type Benutzername (CaGrundBenutzer) als Text
+pub const SM_TYPE_BENUTZER: &str = "BENUTZER";
+
+/// Konfiguration Timeouts und Wiederholungen RDS Rückmeldungen
+#[derive(Clone, Debug)]
+pub struct MfxRdsKonfig {
+  /// Timeout für eine RDS Rückmeldung beim ersten Versuch
+  pub timeout: Duration,
+  /// Anzahl Versuche pro CV
+  pub versuche: usize,
+  /// Frist für das Auslesen aller Lokparameter nach einer Anmeldung
+  pub frist: Duration,
+  /// File in das alle empfangenen RDS Rückmeldungen geschrieben werden, None: keine Aufzeichnung
+  pub capture_file: Option<String>,
+  /// Max. Anzahl Bytes in einem CV Write Telegramm (1, 2, 4 oder 8)
+  pub cv_write_bytes: usize,
+  /// GPIO Nummern QUAL, CLK, DATA bei RDS Rückmeldung über GPIO
+  pub gpio: [u32; 3],
+}
+impl Default for MfxRdsKonfig {
+  fn default() -> Self {
+    MfxRdsKonfig {
+      timeout: RDS_TIMEOUT,
+      versuche: RDS_VERSUCHE,
+      frist: RDS_FRIST_ANMELDUNG,
+      capture_file: None,
+      cv_write_bytes: 1,
+      gpio: [GPIO_MFX_RDS_QAL, GPIO_MFX_RDS_CLK, GPIO_MFX_RDS_DAT],
+    }
+  }
+}
+
 /// Alle möglichen MFX Dekoder Blocktypen
 #[derive(Debug, PartialEq, Clone)]
 #[allow(dead_code)]
@@ -176,8 +237,29 @@ impl BlockCaE {
   }
 }
 
+/// Aufzeichnung der empfangenen RDS Rückmeldungen zur Diagnose des RDS Empfängers
+#[derive(Clone)]
+pub struct MfxRdsCapture {
+  /// Aufzeichnung an SRCP Session eingeschaltet
+  pub aktiv: Arc<AtomicBool>,
+  /// Sender für die aufgezeichneten Rückmeldungen
+  pub tx: Sender<String>,
+}
+
+/// Channels zur Kommunikation zwischen MFX Protokoll (DDL Thread) und RDS Thread
+pub struct MfxRdsKanaele {
+  /// Empfang von Aufträgen
+  pub rx: Receiver<MfxRdsJob>,
+  /// Antworten auf "ReadCA"/"WriteCA"
+  pub tx: ThreadSender<SmReadWrite>,
+  /// Antworten auf "ReadAllInitParameter"
+  pub tx_lok_init: ThreadSender<Option<(Vec<String>, usize)>>,
+  /// Auszugebende Telegramme
+  pub tx_tel: ThreadSender<MfxCvTel>,
+}
+
 /// Zustände RDS Empfang
-#[derive(PartialEq)]
+#[derive(PartialEq, Debug)]
 enum StateRdsRx {
   StateStart1,   //Initiale 1 Folge
   StateStart010, //In Startkennung nach 1 Folge
@@ -192,6 +274,14 @@ pub enum MfxRdsJob {
   ReadAllInitParameter { adr: u32 },
   //Antwort ist gelesener/geschriebener Wert über "Sender "tx"
   ReadWriteCA { ca_parameter: SmReadWrite },
+  //Dekoderidentifikation, Antwort über "Sender "tx"
+  ReadIdent { ident_parameter: SmReadWrite },
+  //Lokname oder Benutzername lesen/schreiben, Antwort über "Sender "tx"
+  ReadWriteText { text_parameter: SmReadWrite },
+  //Konfiguration übernehmen, immer erster Auftrag nach dem Start des Threads, keine Antwort
+  Konfig { konfig: MfxRdsKonfig },
+  //Thread beenden, keine Antwort
+  Beenden,
 }
 impl MfxRdsJob {
   /// Liefert MfxRdsJob ReadAllInitParameter
@@ -203,8 +293,8 @@ impl MfxRdsJob {
 }
 
 /// Anzahl Bytes für MfxCvTel Read/Write
-/// Bei Write kann nur 1 Byte verwendet werden, alles andere scheint nicht zu funktionieren, siehe auch
-/// "Beschreibung des mfx®Schienenformats, Stefan Krauß"
+/// Bei Write funktioniert bei vielen Dekodern nur 1 Byte, siehe auch
+/// "Beschreibung des mfx®Schienenformats, Stefan Krauß". Mehr Bytes nur wenn konfiguriert, siehe "write_cv_bytes"
 #[derive(Clone)]
 pub enum MfxCvTelBytes {
   Cc1byte,
@@ -279,95 +369,148 @@ pub struct MfxRdsFeedbackThread {
   gpio_mfx_rds_qal: Option<LineHandle>,
   gpio_mfx_rds_clk: Option<LineHandle>,
   gpio_mfx_rds_dat: Option<LineHandle>,
+  /// true: Rückmeldung über GPIO's, false: über UDP
+  gpio_modus: bool,
   // oder UDP Socket
   socket_rds_data: Option<UdpSocket>,
   /// Receiver für Aufträge
   rx: Receiver<MfxRdsJob>,
   /// Sender für Ergenisse der Aufträge, siehe "MfxRdsJobType" als Antwort auf "ReadCA"/"WriteCA"
-  tx: Sender<SmReadWrite>,
+  tx: ThreadSender<SmReadWrite>,
   /// Sender für Ergebnisse der Aufträge, siehe "MfxRdsJobType" als Antwort auf "ReadAllInitParameter"
   /// None: Error
-  /// Some: Alle ausgelesenen Parameter (Lokname, Funktionen)
-  tx_lok_init: Sender<Option<Vec<String>>>,
+  /// Some: Alle ausgelesenen Parameter (Lokname, Funktionen) und Anzahl Schaltfunktionen des Dekoders
+  tx_lok_init: ThreadSender<Option<(Vec<String>, usize)>>,
   /// Sender für über SPI zu versendende Telegramme
-  tx_tel: Sender<MfxCvTel>,
+  tx_tel: ThreadSender<MfxCvTel>,
   /// Für welche Adresse ist der aktuelle Cache gültig?
   cv_cache_adr: u32,
   /// CV Cache für "cacheAdr" (CV_Index/Value)
   cv_cache: HashMap<u16, u8>,
+  /// Timeouts und Wiederholungen
+  konfig: MfxRdsKonfig,
+  /// Fortschritt 0..100% beim Auslesen aller Lokparameter nach einer Anmeldung
+  fortschritt: Arc<AtomicU8>,
+  /// Während dem Auslesen aller Lokparameter: Frist bis zu der alles ausgelesen sein muss
+  frist: Option<Instant>,
+  /// Statistik während dem Auslesen aller Lokparameter: Anzahl RDS Leseversuche und davon fehlgeschlagene
+  anz_versuche: usize,
+  anz_fehler: usize,
+  /// File für Aufzeichnung aller RDS Rückmeldungen
+  capture_file: Option<File>,
+  /// Aufzeichnung RDS Rückmeldungen an SRCP Session
+  capture: MfxRdsCapture,
+  /// Dekoder (Schienenadresse) bei denen CV Write mit mehreren Bytes nicht funktioniert hat
+  cv_write_1_byte: HashSet<u32>,
+  /// Thread soll beendet werden, laufende Aufträge abbrechen
+  beenden: Arc<AtomicBool>,
 }
 
 impl MfxRdsFeedbackThread {
-  /// Neue Instanz erstellen
+  /// Neue Instanz erstellen.
+  /// Die Konfiguration (und damit bei Rückmeldung über GPIO das Öffnen der GPIO's) erfolgt mit dem ersten
+  /// Auftrag "MfxRdsJob::Konfig".
   /// # Arguments
-  /// * rx - Empfang von Aufträge.
-  /// * tx - Sender zum versenden er eingelesen Rückmeldungen als Antwort auf "ReadCA"/"WriteCA"
-  /// * tx_lok_init - Sender zum versenden von Lok-Init Daten als Antwort auf "ReadAllInitParameter"
-  /// * tx_tel - Sender zum versenden von auszugebenden Telegrammen
+  /// * kanaele - Empfang von Aufträgen, Sender für Antworten und auszugebende Telegramme
   /// * udp_socket_rds_data - Wenn vorhanden: Socket über das die MFX RDS Daten von GNU RADIO mfxrds empfangen werden.
   ///                         Wenn nicht vorhanden: MFX RDS Daten werden über GPIO von MFX RDS Chip erwartet.
+  /// * fortschritt - Fortschritt beim Auslesen aller Lokparameter
+  /// * capture - Aufzeichnung RDS Rückmeldungen an SRCP Session
+  /// * beenden - Wird gesetzt, wenn der Thread beendet werden soll, laufende Aufträge werden abgebrochen
   pub fn new(
-    rx: Receiver<MfxRdsJob>, tx: Sender<SmReadWrite>, tx_lok_init: Sender<Option<Vec<String>>>,
-    tx_tel: Sender<MfxCvTel>, udp_port_rds_data: Option<u16>,
+    kanaele: MfxRdsKanaele, udp_port_rds_data: Option<u16>, fortschritt: Arc<AtomicU8>,
+    capture: MfxRdsCapture, beenden: Arc<AtomicBool>,
   ) -> MfxRdsFeedbackThread {
-    if let Some(udp_port) = udp_port_rds_data {
-      //Mit UDP Socket zum Empfang RDS Rückmeldung
-      MfxRdsFeedbackThread {
-        gpio_mfx_rds_qal: None,
-        gpio_mfx_rds_clk: None,
-        gpio_mfx_rds_dat: None,
-        socket_rds_data: if let Ok(socket) = UdpSocket::bind(format!("0.0.0.0:{}", udp_port)) {
-          socket
-            .set_nonblocking(true)
-            .expect("MFX RDS-data UDP set_nonblocking Error");
-          info!("UDP Socket Port {udp_port}");
-          Some(socket)
-        } else {
-          warn!("MfxProtokoll MFX RDS-data UDP Port {udp_port} konnte nicht geöffnet werden.");
-          None
-        },
-        rx,
-        tx,
-        tx_lok_init,
-        tx_tel,
-        cv_cache_adr: 0,
-        cv_cache: HashMap::new(),
+    //Mit UDP Socket zum Empfang RDS Rückmeldung
+    let socket_rds_data = udp_port_rds_data.and_then(|udp_port| {
+      if let Ok(socket) = UdpSocket::bind(format!("0.0.0.0:{}", udp_port)) {
+        socket
+          .set_nonblocking(true)
+          .expect("MFX RDS-data UDP set_nonblocking Error");
+        info!("UDP Socket Port {udp_port}");
+        Some(socket)
+      } else {
+        warn!("MfxProtokoll MFX RDS-data UDP Port {udp_port} konnte nicht geöffnet werden.");
+        None
       }
-    } else {
-      //Mit GPIO Ports zum einlöesen RDS Rückmeldung
-      let mut chip =
-        Chip::new("/dev/gpiochip0").expect("/dev/gpiochip0 konnte nicht geöffnet werden");
-      MfxRdsFeedbackThread {
-        gpio_mfx_rds_qal: Some(
-          chip
-            .get_line(GPIO_MFX_RDS_QAL)
-            .expect("GPIO_MFX_RDS_QAL konnte nicht geöffnet werden")
-            .request(LineRequestFlags::INPUT, 0, "input_mfx_rds_qal")
-            .expect("GPIO_MFX_RDS_QAL konnte nicht als Input geöffnet werden"),
-        ),
-        gpio_mfx_rds_clk: Some(
-          chip
-            .get_line(GPIO_MFX_RDS_CLK)
-            .expect("GPIO_MFX_RDS_CLK konnte nicht geöffnet werden")
-            .request(LineRequestFlags::INPUT, 0, "input_mfx_rds_clk")
-            .expect("GPIO_MFX_RDS_CLK konnte nicht als Input geöffnet werden"),
-        ),
-        gpio_mfx_rds_dat: Some(
-          chip
-            .get_line(GPIO_MFX_RDS_DAT)
-            .expect("GPIO_MFX_RDS_DAT konnte nicht geöffnet werden")
-            .request(LineRequestFlags::INPUT, 0, "input_mfx_rds_dat")
-            .expect("GPIO_MFX_RDS_DAT konnte nicht als Input geöffnet werden"),
-        ),
-        socket_rds_data: None,
-        rx,
-        tx,
-        tx_lok_init,
-        tx_tel,
-        cv_cache_adr: 0,
-        cv_cache: HashMap::new(),
+    });
+    MfxRdsFeedbackThread {
+      gpio_mfx_rds_qal: None,
+      gpio_mfx_rds_clk: None,
+      gpio_mfx_rds_dat: None,
+      gpio_modus: udp_port_rds_data.is_none(),
+      socket_rds_data,
+      rx: kanaele.rx,
+      tx: kanaele.tx,
+      tx_lok_init: kanaele.tx_lok_init,
+      tx_tel: kanaele.tx_tel,
+      cv_cache_adr: 0,
+      cv_cache: HashMap::new(),
+      konfig: MfxRdsKonfig::default(),
+      fortschritt,
+      frist: None,
+      anz_versuche: 0,
+      anz_fehler: 0,
+      capture_file: None,
+      capture,
+      cv_write_1_byte: HashSet::new(),
+      beenden,
+    }
+  }
+
+  /// Neue Konfiguration übernehmen.
+  /// Bei Rückmeldung über GPIO werden die GPIO's neu geöffnet, wenn sie sich verändert haben.
+  /// # Arguments
+  /// * konfig - Neue Konfiguration
+  fn set_konfig(&mut self, konfig: MfxRdsKonfig) {
+    if konfig.capture_file != self.konfig.capture_file || self.capture_file.is_none() {
+      self.capture_file = konfig.capture_file.as_ref().and_then(|path| {
+        let file = OpenOptions::new().create(true).append(true).open(path);
+        if file.is_err() {
+          warn!(
+            "MFX RDS Aufzeichnungsfile {} konnte nicht geöffnet werden.",
+            path
+          );
+        }
+        file.ok()
+      });
+    }
+    if self.gpio_modus && (konfig.gpio != self.konfig.gpio || self.gpio_mfx_rds_clk.is_none()) {
+      //Zuerst alte GPIO's freigeben
+      self.gpio_mfx_rds_qal = None;
+      self.gpio_mfx_rds_clk = None;
+      self.gpio_mfx_rds_dat = None;
+      if let Err(msg) = self.open_gpio(konfig.gpio) {
+        warn!("MFX RDS GPIO's {:?}: {}", konfig.gpio, msg);
       }
     }
+    self.konfig = konfig;
+  }
+
+  /// GPIO's zum Einlesen der RDS Rückmeldung öffnen
+  /// # Arguments
+  /// * gpio - GPIO Nummern QUAL, CLK, DATA
+  fn open_gpio(&mut self, gpio: [u32; 3]) -> Result<(), String> {
+    let mut chip = Chip::new(srcp_hardware::get_gpiochip())
+      .map_err(|_| "GPIO Chip konnte nicht geöffnet werden".to_string())?;
+    let mut lines = Vec::new();
+    for (nr, name) in gpio.into_iter().zip([
+      "input_mfx_rds_qal",
+      "input_mfx_rds_clk",
+      "input_mfx_rds_dat",
+    ]) {
+      lines.push(
+        chip
+          .get_line(nr)
+          .and_then(|line| line.request(LineRequestFlags::INPUT, 0, name))
+          .map_err(|_| format!("GPIO {} konnte nicht als Input geöffnet werden", nr))?,
+      );
+    }
+    self.gpio_mfx_rds_dat = lines.pop();
+    self.gpio_mfx_rds_clk = lines.pop();
+    self.gpio_mfx_rds_qal = lines.pop();
+    info!("MFX RDS GPIO's QUAL, CLK, DATA {:?}", gpio);
+    Ok(())
   }
 
   /// Einlesen RDS Rückmeldung
@@ -375,13 +518,14 @@ impl MfxRdsFeedbackThread {
   /// Wenn keine oder keine gültige Rückmeldung eingelesen werden konnte, wird "None" zurückgegeben.
   /// # Arguments
   /// * len - Anzahl erwarteter Bytes der Rückmeldung (1, 2, 4 oder 8)
-  fn read_rds(&mut self, len: usize) -> Option<Vec<u8>> {
+  /// * timeout - Max. Dauer bis die Rückmeldung vollständig eingelesen sein muss
+  fn read_rds(&mut self, len: usize, timeout: Duration) -> Option<Vec<u8>> {
     //Es muss nun eine RDS Rückmeldung erfolgen.
     // - Warten bis RDS QUAL Meldung vorliegt
     // - Max. 23 mal 1, dann 010 (Startkennung)
     // - Anzahl erwartetet Datenbits
     // - 8 Bit Checksumme
-    //Alles zusammen darf max. 200ms dauern, sonst wird abgebrochen
+    //Alles zusammen darf max. "timeout" dauern, sonst wird abgebrochen
     let time_start = Instant::now();
     let mut state = StateRdsRx::StateStart1; //Warten auf QUAL und Data 1
     let mut count: usize = 0;
@@ -437,9 +581,20 @@ impl MfxRdsFeedbackThread {
             break;
           }
         }
+        //ggf. Abbruch weil der Thread beendet werden soll
+        if self.beenden.load(Ordering::Relaxed) {
+          result_error = true;
+          break;
+        }
         //ggf. Abbruch wegen Timeout
-        if Instant::now() > (time_start + Duration::from_millis(500)) {
+        if Instant::now() > (time_start + timeout) {
           info!("MFX RDS thread Timeout.");
+          self.capture(format!(
+            "Timeout nach {}ms Zustand={:?} Len={}",
+            time_start.elapsed().as_millis(),
+            state,
+            len
+          ));
           result_error = true;
           break;
         }
@@ -562,6 +717,19 @@ impl MfxRdsFeedbackThread {
           checksum ^= 0x020E;
         }
       }
+      self.capture(format!(
+        "{}ms Len={} Daten={:02X?} Checksumme={:02X} berechnet={:02X} {}",
+        time_start.elapsed().as_millis(),
+        len,
+        &values[0..len],
+        rds_check_summe,
+        checksum as u8,
+        if checksum as u8 == rds_check_summe {
+          "OK"
+        } else {
+          "FEHLER"
+        }
+      ));
       if checksum as u8 == rds_check_summe {
         result = Some(values[0..len].to_vec());
         info!("RDS Checksumme OK. Len={}, values={:?}", len, values);
@@ -588,7 +756,9 @@ impl MfxRdsFeedbackThread {
   }
 
   /// CV einer Lok abrufen.
-  /// Bei einem Fehler wird bis zu 10 mal wiederholt.
+  /// Bei einem Fehler wird bis zu "konfig.versuche" mal probiert, bei jeder Wiederholung mit doppeltem
+  /// Timeout (max. RDS_TIMEOUT_FAKTOR_MAX). Während dem Auslesen aller Lokparameter wird nach Ablauf
+  /// der Frist nicht mehr wiederholt.
   /// Liefert die ausgelesen Bytes oder None bei Fehler zurück
   /// # Arguments
   /// * adr - Schienenadresse des Dekoders
@@ -621,20 +791,32 @@ impl MfxRdsFeedbackThread {
       result = Some(v);
     } else {
       //Im Fehlerfall mehrmals probieren bevor aufgegeben wird.
-      for _ in 0..5 {
-        self
-          .tx_tel
-          .send(MfxCvTel {
-            adr,
-            mfx_cv_type: MfxCvTelType::Read,
-            byte_count: byte_count.clone(),
-            cv,
-            index,
-            trigger,
-          })
-          .unwrap();
+      for versuch in 0..self.konfig.versuche {
+        if self.frist.is_some_and(|frist| Instant::now() > frist) {
+          warn!("MFX RDS Frist abgelaufen, SID={} CV={}.{}", adr, cv, index);
+          break;
+        }
+        if self.beenden.load(Ordering::Relaxed) {
+          break;
+        }
+        if !self.tx_tel.send(MfxCvTel {
+          adr,
+          mfx_cv_type: MfxCvTelType::Read,
+          byte_count: byte_count.clone(),
+          cv,
+          index,
+          trigger,
+        }) {
+          //Ohne Empfänger wird das Telegramm nie gesendet
+          break;
+        }
         //RDS Rückmeldung einlesen
-        result = self.read_rds(count);
+        let faktor = RDS_TIMEOUT_FAKTOR_MAX.min(1 << versuch.min(16));
+        result = self.read_rds(count, self.konfig.timeout * faktor);
+        self.anz_versuche += 1;
+        if result.is_none() {
+          self.anz_fehler += 1;
+        }
         if result.is_some() {
           //Gültige Rückmeldung in Cache aufnehmen
           let mut cv_index = cv_index;
@@ -654,24 +836,81 @@ impl MfxRdsFeedbackThread {
   /// * adr - Schienenadresse des Dekoders
   /// * cv - Nummer des CV's (10 Bit)
   /// * index - Index im CV (6 Bit)
-  /// * value - Die zu schreibenden Bytes.  Vorbereitet 1, 2, 4, 8, aktuelle Dekoder unterstützen aber nur 1 Byte!
+  /// * value - Die zu schreibenden Bytes (1, 2, 4, 8), mehr als 1 Byte unterstützen nicht alle Dekoder!
   /// * trigger - Oszi Trigger?
   fn write_cv(&mut self, adr: u32, cv: u16, index: u8, value: &Vec<u8>, trigger: bool) {
-    self
-      .tx_tel
-      .send(MfxCvTel {
-        adr,
-        mfx_cv_type: MfxCvTelType::Write(value.clone()),
-        byte_count: MfxCvTelBytes::from_count(value.len()).unwrap(),
-        cv,
-        index,
-        trigger,
-      })
-      .unwrap();
+    self.tx_tel.send(MfxCvTel {
+      adr,
+      mfx_cv_type: MfxCvTelType::Write(value.clone()),
+      byte_count: MfxCvTelBytes::from_count(value.len()).unwrap(),
+      cv,
+      index,
+      trigger,
+    });
     //Cache löschen damit ein Lesen als verify auch tatsächlich gemacht werden muss
     self.clear_cache(adr, cv, index, value.len() as u16);
   }
 
+  /// Mehrere Bytes ab einem CV Index schreiben und durch Zurücklesen prüfen.
+  /// Es werden Telegramme mit so vielen Bytes wie möglich verwendet, max. "konfig.cv_write_bytes".
+  /// Stimmen nach einem Telegramm mit mehreren Bytes die zurückgelesenen Bytes nicht, wird für diesen
+  /// Dekoder nur noch mit einem Byte pro Telegramm geschrieben.
+  /// Liefert true zurück wenn alle Bytes geschrieben und korrekt zurückgelesen wurden.
+  /// # Arguments
+  /// * adr - Schienenadresse des Dekoders
+  /// * cv - Nummer des CV's (10 Bit)
+  /// * index - Index im CV (6 Bit) des ersten Bytes
+  /// * values - Die zu schreibenden Bytes, alle im selben CV
+  /// * trigger - Oszi Trigger?
+  fn write_cv_bytes(&mut self, adr: u32, cv: u16, index: u8, values: &[u8], trigger: bool) -> bool {
+    if index as usize + values.len() > MFX_CV_ANZ_INDEX {
+      warn!(
+        "MFX CV Write {}.{} mit {} Bytes über CV Ende",
+        cv,
+        index,
+        values.len()
+      );
+      return false;
+    }
+    let mut pos = 0;
+    while pos < values.len() {
+      let max = if self.cv_write_1_byte.contains(&adr) {
+        1
+      } else {
+        self.konfig.cv_write_bytes
+      };
+      let anz = [8, 4, 2, 1]
+        .into_iter()
+        .find(|anz| (*anz <= max) && (pos + anz <= values.len()))
+        .unwrap_or(1);
+      let teil = values[pos..pos + anz].to_vec();
+      let index_teil = index + pos as u8;
+      self.write_cv(adr, cv, index_teil, &teil, trigger);
+      let byte_count = MfxCvTelBytes::from_count(anz).unwrap();
+      let ok = self
+        .read_cv(adr, cv, index_teil, byte_count, trigger)
+        .is_some_and(|gelesen| gelesen == teil);
+      if !ok && (anz > 1) {
+        //Nochmals mit einem Byte pro Telegramm
+        warn!(
+          "MFX SID={} CV Write mit {} Bytes fehlgeschlagen, weiter mit 1 Byte",
+          adr, anz
+        );
+        self.cv_write_1_byte.insert(adr);
+        continue;
+      }
+      if !ok {
+        warn!(
+          "MFX SID={} CV Write {}.{} fehlgeschlagen",
+          adr, cv, index_teil
+        );
+        return false;
+      }
+      pos += anz;
+    }
+    true
+  }
+
   /// Cahce löschen
   /// # Arguments
   /// * adr - Schienenadresse des Dekoders
@@ -864,13 +1103,46 @@ impl MfxRdsFeedbackThread {
     None
   }
 
+  /// Dekoderidentifikation aus Block Grundeinstellungen lesen.
+  /// Hersteller: erstes Byte CA Hersteller (gleiche Kennungen wie NMRA),
+  /// Version: erste zwei Bytes CA Version als "<Haupt>.<Neben>".
+  /// Liefert None zurück wenn ein Fehler aufgetreten ist.
+  /// # Arguments
+  /// * adr - Schienenadresse des Dekoders
+  /// * trigger - Oszi Trigger?
+  fn read_ident(&mut self, adr: u32, trigger: bool) -> Option<DekoderIdent> {
+    let (_cv, hersteller) = self.read_ca(
+      adr,
+      BlockTypenE::BlockGrundeinstellungen,
+      BlockCaE::CaGrundHersteller,
+      0,
+      trigger,
+    )?;
+    let (_cv, version) = self.read_ca(
+      adr,
+      BlockTypenE::BlockGrundeinstellungen,
+      BlockCaE::CaGrundVersiona,
+      0,
+      trigger,
+    )?;
+    Some(DekoderIdent {
+      hersteller_id: hersteller[0] as u32,
+      version: format!("{}.{}", version[0], version[1]),
+      protokoll: "MFX".to_string(),
+      faehigkeiten: vec![],
+    })
+  }
+
   /// Name und Funktionen einer Lok lesen.
-  /// Liefert None zurück wenn ein Fehler aufgetreten ist, sonst Name und die ersten 16 Funktionen.
+  /// Liefert None zurück wenn ein Fehler aufgetreten ist, sonst Name, die ersten 16 Funktionen und die
+  /// Anzahl Schaltfunktionen des Dekoders (Anzahl Einträge CaFunkSchaltfunktion).
   /// Jede Funktion 32 Bit, jedoch nur die 3 Unterbytes verwendet (Funktionsgruppe, Symbolinfo 1 und 2)
   /// # Arguments
   /// * adresse - Schienenadresse des Dekoders
   /// * trigger - Oszi Trigger?
-  fn read_lok_name_fx(&mut self, adr: u32, trigger: bool) -> Option<(String, [u32; MFX_FX_COUNT])> {
+  fn read_lok_name_fx(
+    &mut self, adr: u32, trigger: bool,
+  ) -> Option<(String, [u32; MFX_FX_COUNT], usize)> {
     //Lokname
     let name: String;
     if let Some((_cv, name_bin)) = self.read_ca(
@@ -880,10 +1152,10 @@ impl MfxRdsFeedbackThread {
       0,
       trigger,
     ) {
-      if let Ok(str) = String::from_utf8(name_bin) {
-        //Spaces am Schluss abschneiden falls vorhanden
-        name = str.trim().to_string();
+      if let Some(str) = Self::ca_to_text(name_bin) {
+        name = str;
         debug!("MFX SID={} Lokname: {}", adr, name);
+        self.set_fortschritt(10);
       } else {
         warn!("MFX SID={} Lokname ist ungültig!", adr);
         name = String::from("?");
@@ -907,6 +1179,7 @@ impl MfxRdsFeedbackThread {
       0,
       trigger,
     ) {
+      self.set_fortschritt(20);
       //Funktionstypen auslesen
       //Zuerst Start CV des Block BLOCK_FUNKTION_MAPPING ermitteln (in zurückgelesene Blockinfodaten werden nicht benötigt)
       if let Some((cv, _blockinfo)) = self.read_ca(
@@ -916,10 +1189,12 @@ impl MfxRdsFeedbackThread {
         0,
         trigger,
       ) {
+        self.set_fortschritt(30);
         //Für alle Funktionen die Funktionszuordnungen
         let mut fx: [u32; MFX_FX_COUNT] = [0; MFX_FX_COUNT];
+        let anz_fx = min(funktionen.len(), fx.len());
         //Nun haben wir in cv die CV Nummer, an der der BLOCK_FUNKTION_MAPPING startet
-        for i in 0..min(funktionen.len(), fx.len()) {
+        for i in 0..anz_fx {
           if let Some(funktion) = self.read_cv(
             adr,
             cv + funktionen[i] as u16,
@@ -928,6 +1203,7 @@ impl MfxRdsFeedbackThread {
             trigger,
           ) {
             fx[i] = ((funktion[1] as u32) << 16) | ((funktion[2] as u32) << 8) | funktion[3] as u32;
+            self.set_fortschritt(30 + (70 * (i + 1) / anz_fx) as u8);
             debug!(
               "F{} {} Gruppe=0{} S1=0{} S2={}",
               i,
@@ -947,7 +1223,7 @@ impl MfxRdsFeedbackThread {
           }
         }
         //Name und Funktionen konnten ausgelesen werden!
-        return Some((name, fx));
+        return Some((name, fx, funktionen.len()));
       } else {
         //Fehler, Abbruch
         warn!(
@@ -968,40 +1244,157 @@ impl MfxRdsFeedbackThread {
     }
   }
 
+  /// Eine empfangene RDS Rückmeldung aufzeichnen: mit Zeitstempel ins Aufzeichnungsfile und,
+  /// wenn eingeschaltet, an die SRCP Session.
+  /// # Arguments
+  /// * meldung - Beschreibung der Rückmeldung
+  fn capture(&mut self, meldung: String) {
+    if let Some(file) = &mut self.capture_file {
+      if writeln!(file, "{} {}", Local::now().format("%H:%M:%S%.3f"), meldung).is_err() {
+        warn!("MFX RDS Aufzeichnungsfile konnte nicht geschrieben werden.");
+        self.capture_file = None;
+      }
+    }
+    if self.capture.aktiv.load(Ordering::Relaxed) {
+      self.capture.tx.send(meldung).ok();
+    }
+  }
+
+  /// Text aus einem Text CA (z.B. Lokname).
+  /// Liefert None wenn der Text ungültig ist.
+  /// # Arguments
+  /// * bytes - Gelesene Bytes des CA's, am Schluss mit Spaces oder 0 aufgefüllt
+  pub fn ca_to_text(bytes: Vec<u8>) -> Option<String> {
+    String::from_utf8(bytes)
+      .ok()
+      .map(|text| text.trim_end_matches(['\0', ' ']).trim().to_string())
+  }
+
+  /// Bytes für einen Text CA (z.B. Lokname).
+  /// Liefert None wenn der Text zu lang ist oder nicht nur aus ASCII Zeichen besteht.
+  /// # Arguments
+  /// * text - Zu schreibender Text
+  /// * len - Anzahl Bytes des CA's, wird mit Spaces aufgefüllt
+  pub fn text_to_ca(text: &str, len: usize) -> Option<Vec<u8>> {
+    if !text.is_ascii() || text.len() > len {
+      return None;
+    }
+    let mut bytes = text.as_bytes().to_vec();
+    bytes.resize(len, b' ');
+    Some(bytes)
+  }
+
+  /// Lokname oder Benutzername lesen oder schreiben.
+  /// Liefert ResultText mit dem gelesenen / geschriebenen Text oder ResultErr.
+  /// # Arguments
+  /// * text_parameter - SM Read/WriteText Paramater mit Type LOKNAME oder BENUTZER
+  fn read_write_text(&mut self, text_parameter: &SmReadWrite) -> SmReadWriteType {
+    let ca = if text_parameter.sm_type == SM_TYPE_BENUTZER {
+      BlockCaE::CaGrundBenutzer
+    } else {
+      BlockCaE::CaGrundLokname
+    };
+    let adr = text_parameter.adr;
+    let trigger = text_parameter.trigger;
+    match &text_parameter.val {
+      SmReadWriteType::Read => self
+        .read_ca(adr, BlockTypenE::BlockGrundeinstellungen, ca, 0, trigger)
+        .and_then(|(_cv, bytes)| Self::ca_to_text(bytes))
+        .map_or(SmReadWriteType::ResultErr, SmReadWriteType::ResultText),
+      SmReadWriteType::WriteText(text) => {
+        let (ca_len, ca_id) = ca.value();
+        let Some(bytes) = Self::text_to_ca(text, ca_len as usize) else {
+          warn!("MFX SID={} ungültiger Text für {:?}: {}", adr, ca, text);
+          return SmReadWriteType::ResultErr;
+        };
+        let block = BlockTypenE::BlockGrundeinstellungen as u8;
+        let Some(cv) = self.find_ca(adr, block, ca_id, 0, trigger) else {
+          warn!("MFX SID={} {:?} nicht gefunden", adr, ca);
+          return SmReadWriteType::ResultErr;
+        };
+        //Daten ab Index 1 (nach CA Typ)
+        if self.write_cv_bytes(adr, cv, 1, &bytes, trigger) {
+          info!("MFX SID={} {:?} geschrieben: {}", adr, ca, text);
+          SmReadWriteType::ResultText(text.clone())
+        } else {
+          SmReadWriteType::ResultErr
+        }
+      }
+      _ => SmReadWriteType::ResultErr,
+    }
+  }
+
+  /// Gelesene Bytes als Wert, erstes Byte = MSB
+  /// # Arguments
+  /// * bytes - Gelesene Bytes, max. 4
+  fn bytes_to_u32(bytes: &[u8]) -> u32 {
+    bytes
+      .iter()
+      .fold(0, |wert, byte| (wert << 8) | *byte as u32)
+  }
+
+  /// Fortschritt beim Auslesen aller Lokparameter setzen
+  /// # Arguments
+  /// * prozent - Fortschritt 0..100%
+  fn set_fortschritt(&self, prozent: u8) {
+    self.fortschritt.store(prozent, Ordering::Relaxed);
+  }
+
   /// Als Thread ausführen
-  pub fn execute(&mut self) -> ! {
+  /// Wird beendet, wenn das MFX Protokoll entfernt wurde (Auftragschannel geschlossen).
+  pub fn execute(&mut self) {
     loop {
-      //Warten auf Arbeit
-      let auftrag = self.rx.recv().unwrap();
+      //Warten auf Arbeit, auch ohne Arbeit regelmässig UDP Rx Buffer leeren
+      let auftrag = self.rx.recv_timeout(RDS_AUFTRAG_TIMEOUT);
       //Wenn UDP Rückmeldungen -> Rx Buffer leeren
-      loop {
-        if let Some(socket) = &self.socket_rds_data {
-          let mut buf = [0u8; 256];
-          match socket.recv(&mut buf) {
-            Ok(..) => {}
-            Err(..) => {
-              break;
-            }
-          }
-        }
+      if let Some(socket) = &self.socket_rds_data {
+        let mut buf = [0u8; 256];
+        while socket.recv(&mut buf).is_ok() {}
       }
+      let auftrag = match auftrag {
+        Ok(MfxRdsJob::Beenden) | Err(RecvTimeoutError::Disconnected) => {
+          info!("MFX RDS Thread beendet");
+          return;
+        }
+        Err(RecvTimeoutError::Timeout) => continue,
+        Ok(auftrag) => auftrag,
+      };
       match auftrag {
+        MfxRdsJob::Konfig { konfig } => self.set_konfig(konfig),
+        MfxRdsJob::Beenden => {} //Bereits oben behandelt
         MfxRdsJob::ReadAllInitParameter { adr } => {
-          //Lokname und Funktionen lesen (nie ein Oszi Trigger, neue Adresse)
-          if let Some((name, fx)) = self.read_lok_name_fx(adr, false) {
+          //Lokname und Funktionen lesen (nie ein Oszi Trigger, neue Adresse), innerhalb der Frist
+          let start = Instant::now();
+          //Neu angemeldeter Dekoder, CV Write mit mehreren Bytes wieder probieren
+          self.cv_write_1_byte.remove(&adr);
+          self.set_fortschritt(0);
+          self.frist = Some(start + self.konfig.frist);
+          self.anz_versuche = 0;
+          self.anz_fehler = 0;
+          let lok_name_fx = self.read_lok_name_fx(adr, false);
+          self.frist = None;
+          info!(
+            "MFX SID={} Lokparameter gelesen in {:?}, {} RDS Leseversuche davon {} fehlgeschlagen",
+            adr,
+            start.elapsed(),
+            self.anz_versuche,
+            self.anz_fehler
+          );
+          if let Some((name, fx, anz_f)) = lok_name_fx {
+            self.set_fortschritt(100);
             //Alle Init Parameter als String, Lokname kommt in Anführungszeichen
             let mut para: Vec<String> = Vec::new();
             para.push(format!("\"{}\"", name.as_str()));
             for i in 0..fx.len() {
               para.push(fx[i].to_string());
             }
-            self.tx_lok_init.send(Some(para)).unwrap();
+            self.tx_lok_init.send(Some((para, anz_f)));
           } else {
             warn!(
               "MFX Lokname und Funktionen konnten nicht gelesen werden. SID={}",
               adr
             );
-            self.tx_lok_init.send(None).unwrap();
+            self.tx_lok_init.send(None);
           }
         }
         MfxRdsJob::ReadWriteCA { mut ca_parameter } => {
@@ -1009,6 +1402,13 @@ impl MfxRdsFeedbackThread {
           let ca = ca_parameter.para[1] as u8;
           let ca_index = ca_parameter.para[2] as u8;
           let index = ca_parameter.para[3] as u8;
+          //CAMFX32: 4 Bytes ab Index, sonst 1 Byte
+          let byte_count = if ca_parameter.sm_type == SM_TYPE_CAMFX32 {
+            MfxCvTelBytes::Cc4Byte
+          } else {
+            MfxCvTelBytes::Cc1byte
+          };
+          let anz_bytes = byte_count.byte_count();
           if let Some(cv) =
             self.find_ca(ca_parameter.adr, block, ca, ca_index, ca_parameter.trigger)
           {
@@ -1019,11 +1419,11 @@ impl MfxRdsFeedbackThread {
                   ca_parameter.adr,
                   cv,
                   index,
-                  MfxCvTelBytes::Cc1byte,
+                  byte_count,
                   ca_parameter.trigger,
                 ) {
                   //Alles OK, gelesener Wert als Antwort zurück senden
-                  ca_parameter.val = SmReadWriteType::ResultOk(val[0] as u32);
+                  ca_parameter.val = SmReadWriteType::ResultOk(Self::bytes_to_u32(&val));
                 } else {
                   warn!(
                     "MFX Error ReadCA read_cv {}.{} für SID={}",
@@ -1032,6 +1432,21 @@ impl MfxRdsFeedbackThread {
                   ca_parameter.val = SmReadWriteType::ResultErr;
                 }
               }
+              SmReadWriteType::Write(val) if anz_bytes > 1 => {
+                //Write mehrere Bytes, mit Prüfung
+                let bytes = val.to_be_bytes();
+                ca_parameter.val = if self.write_cv_bytes(
+                  ca_parameter.adr,
+                  cv,
+                  index,
+                  &bytes[bytes.len() - anz_bytes..],
+                  ca_parameter.trigger,
+                ) {
+                  SmReadWriteType::ResultOk(val)
+                } else {
+                  SmReadWriteType::ResultErr
+                };
+              }
               SmReadWriteType::Write(val) => {
                 //Write
                 self.write_cv(
@@ -1046,18 +1461,19 @@ impl MfxRdsFeedbackThread {
               }
               SmReadWriteType::Verify(val_ver) => {
                 //Ein Verify darf nie aus dem Cache kommen
-                self.clear_cache(ca_parameter.adr, cv, index, 1);
+                self.clear_cache(ca_parameter.adr, cv, index, anz_bytes as u16);
                 //Zuerst Read
                 if let Some(val) = self.read_cv(
                   ca_parameter.adr,
                   cv,
                   index,
-                  MfxCvTelBytes::Cc1byte,
+                  byte_count,
                   ca_parameter.trigger,
                 ) {
                   //Alles OK, gelesener Wert vergleichen
-                  ca_parameter.val = if val_ver == val[0] as u32 {
-                    SmReadWriteType::ResultOk(val[0] as u32)
+                  let val = Self::bytes_to_u32(&val);
+                  ca_parameter.val = if val_ver == val {
+                    SmReadWriteType::ResultOk(val)
                   } else {
                     SmReadWriteType::ResultErr
                   };
@@ -1083,7 +1499,21 @@ impl MfxRdsFeedbackThread {
             ca_parameter.val = SmReadWriteType::ResultErr;
           }
           //Antwort zurück senden, OK wenn ca_parameter.val vorhanden, sonst Error
-          self.tx.send(ca_parameter).unwrap();
+          self.tx.send(ca_parameter);
+        }
+        MfxRdsJob::ReadIdent { mut ident_parameter } => {
+          ident_parameter.val = if let SmReadWriteType::Read = ident_parameter.val {
+            self
+              .read_ident(ident_parameter.adr, ident_parameter.trigger)
+              .map_or(SmReadWriteType::ResultErr, SmReadWriteType::ResultIdent)
+          } else {
+            SmReadWriteType::ResultErr
+          };
+          self.tx.send(ident_parameter);
+        }
+        MfxRdsJob::ReadWriteText { mut text_parameter } => {
+          text_parameter.val = self.read_write_text(&text_parameter);
+          self.tx.send(text_parameter);
         }
       }
     }
@@ -0,0 +1,456 @@
+//! MQTT Anbindung für Hausautomation (z.B. Home Assistant)
+//! - Alle INFO Messages werden publiziert:
+//!   - <prefix>/<bus>/POWER: ON|OFF
+//!   - <prefix>/<bus>/GL/<adr>: <drivemode> <v> <v_max> <f0> ... <fn>
+//!   - <prefix>/<bus>/GA/<adr>/<port>: <value>
+//!   - <prefix>/<bus>/FB/<adr>: <value>
+//!   - <prefix>/<bus>/<device>: alle Parameter (übrige Devices, z.B. GM)
+//!
+//!   Zustände von POWER, GL, GA und FB werden mit "retain" publiziert, gelöschte (INFO 102) entfernt.
+//! - Subscribe auf <prefix>/<bus>/<device>/set: Payload mit den Parametern (durch Leerzeichen getrennt)
+//!   wird als "SET <bus> <device> <Parameter>" ausgeführt.
+//!
+//! Implementiert ist nur das notwendige Minimum von MQTT 3.1.1, QoS 0.
+//! Bei Verbindungsabbruch wird periodisch neu verbunden, danach werden alle Zustände neu publiziert.
+//!
+//! INI File:
+//! [mqtt]
+//! broker = <host>:<port>
+//! prefix = srcpd
+//! client_id = srcpd
+//! user = xxxxxx
+//! password = xxxxxx
+
+use std::{
+  collections::{BTreeMap, HashMap},
+  io::{Read, Write},
+  net::{Shutdown, TcpStream},
+  sync::{
+    mpsc::{RecvTimeoutError, SyncSender},
+    Arc, Mutex,
+  },
+  thread,
+  time::{Duration, Instant},
+};
+
+use log::{error, info, warn};
+
+use crate::{
+  srcp,
+  srcp_info_queue::InfoQueueReceiver,
+  srcp_server_types::{Message, SRCPMessage, SRCPMessageDevice, SRCPMessageID},
+};
+
+/// MQTT Keep Alive Zeit
+const KEEP_ALIVE: Duration = Duration::from_secs(60);
+/// Wartezeit bis zum nächsten Verbindungsversuch zum Broker
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+/// Default Topic Prefix
+const DEFAULT_PREFIX: &str = "srcpd";
+/// Default MQTT Client ID
+const DEFAULT_CLIENT_ID: &str = "srcpd";
+
+//MQTT Control Packet Typen
+const MQTT_CONNECT: u8 = 0x10;
+const MQTT_CONNACK: u8 = 0x20;
+const MQTT_PUBLISH: u8 = 0x30;
+const MQTT_SUBSCRIBE: u8 = 0x82;
+const MQTT_PINGREQ: u8 = 0xC0;
+
+/// Konfiguration aus [mqtt] Abschnitt
+pub struct MqttConfig {
+  broker: String,
+  prefix: String,
+  client_id: String,
+  user: Option<String>,
+  password: Option<String>,
+}
+
+impl MqttConfig {
+  /// MQTT Konfiguration aus Konfigfile lesen.
+  /// Liefert None wenn MQTT nicht konfiguriert ist, Err wenn die Konfiguration ungültig ist.
+  /// # Arguments
+  /// * config - Abschnitt [mqtt] aus Konfigfile, None wenn nicht vorhanden
+  pub fn from_config(
+    config: Option<&HashMap<String, Option<String>>>,
+  ) -> Result<Option<MqttConfig>, String> {
+    let Some(config) = config else {
+      return Ok(None);
+    };
+    let wert = |key: &str| config.get(key).and_then(|wert| wert.clone());
+    Ok(Some(MqttConfig {
+      broker: wert("broker").ok_or("[mqtt] broker-Angabe fehlt")?,
+      prefix: wert("prefix").unwrap_or(DEFAULT_PREFIX.to_string()),
+      client_id: wert("client_id").unwrap_or(DEFAULT_CLIENT_ID.to_string()),
+      user: wert("user"),
+      password: wert("password"),
+    }))
+  }
+}
+
+/// Zu publizierende Message
+struct MqttPublish {
+  topic: String,
+  payload: String,
+  retain: bool,
+}
+
+/// MQTT String: Länge (u16) und UTF-8 Bytes
+/// # Arguments
+/// * packet - Packet an das angehängt wird
+/// * text - Anzuhängender String
+fn push_mqtt_string(packet: &mut Vec<u8>, text: &str) {
+  packet.extend_from_slice(&(text.len() as u16).to_be_bytes());
+  packet.extend_from_slice(text.as_bytes());
+}
+
+/// Vollständiges MQTT Packet mit Fixed Header und "Remaining Length" erstellen
+/// # Arguments
+/// * typ - Erstes Byte des Fixed Headers (Packet Typ und Flags)
+/// * inhalt - Variable Header und Payload
+fn mqtt_packet(typ: u8, inhalt: &[u8]) -> Vec<u8> {
+  let mut packet = vec![typ];
+  let mut len = inhalt.len();
+  loop {
+    let mut byte = (len % 128) as u8;
+    len /= 128;
+    if len > 0 {
+      byte |= 0x80;
+    }
+    packet.push(byte);
+    if len == 0 {
+      break;
+    }
+  }
+  packet.extend_from_slice(inhalt);
+  packet
+}
+
+/// Ein MQTT Packet lesen, blockiert bis ein vollständiges Packet vorhanden ist.
+/// Liefert erstes Byte des Fixed Headers und Variable Header mit Payload oder Err bei Verbindungsabbruch.
+/// # Arguments
+/// * stream - TCP Stream zum Broker
+fn read_mqtt_packet(mut stream: &TcpStream) -> Result<(u8, Vec<u8>), String> {
+  let mut byte = [0u8; 1];
+  stream.read_exact(&mut byte).or(Err("MQTT read Error"))?;
+  let typ = byte[0];
+  let mut len: usize = 0;
+  let mut faktor: usize = 1;
+  loop {
+    stream.read_exact(&mut byte).or(Err("MQTT read Error"))?;
+    len += usize::from(byte[0] & 0x7F) * faktor;
+    if byte[0] & 0x80 == 0 {
+      break;
+    }
+    faktor *= 128;
+    if faktor > 128 * 128 * 128 {
+      return Err("MQTT ungültige Remaining Length".to_string());
+    }
+  }
+  let mut inhalt = vec![0u8; len];
+  stream.read_exact(&mut inhalt).or(Err("MQTT read Error"))?;
+  Ok((typ, inhalt))
+}
+
+/// Packet an den Broker senden
+/// # Arguments
+/// * stream - TCP Stream zum Broker
+/// * packet - Zu sendendes Packet
+fn send_mqtt_packet(mut stream: &TcpStream, packet: &[u8]) -> Result<(), String> {
+  stream
+    .write_all(packet)
+    .or(Err("MQTT write Error".to_string()))
+}
+
+/// Verbindung zum Broker aufbauen (CONNECT / CONNACK) und Kommando Topics abonnieren
+/// # Arguments
+/// * config - MQTT Konfiguration
+fn connect(config: &MqttConfig) -> Result<TcpStream, String> {
+  let stream = TcpStream::connect(config.broker.as_str()).or(Err(format!(
+    "MQTT Broker {} nicht erreichbar",
+    config.broker
+  )))?;
+  let mut inhalt: Vec<u8> = Vec::new();
+  push_mqtt_string(&mut inhalt, "MQTT");
+  //Protocol Level 4 = MQTT 3.1.1
+  inhalt.push(4);
+  //Connect Flags: Clean Session, optional User und Passwort
+  let mut flags = 0x02;
+  if config.user.is_some() {
+    flags |= 0x80;
+    if config.password.is_some() {
+      flags |= 0x40;
+    }
+  }
+  inhalt.push(flags);
+  inhalt.extend_from_slice(&(KEEP_ALIVE.as_secs() as u16).to_be_bytes());
+  push_mqtt_string(&mut inhalt, config.client_id.as_str());
+  if let Some(user) = &config.user {
+    push_mqtt_string(&mut inhalt, user);
+    if let Some(password) = &config.password {
+      push_mqtt_string(&mut inhalt, password);
+    }
+  }
+  send_mqtt_packet(&stream, &mqtt_packet(MQTT_CONNECT, &inhalt))?;
+  let (typ, connack) = read_mqtt_packet(&stream)?;
+  if typ != MQTT_CONNACK || connack.len() != 2 {
+    return Err("MQTT CONNACK erwartet".to_string());
+  }
+  if connack[1] != 0 {
+    return Err(format!(
+      "MQTT Verbindung durch Broker abgelehnt, Code {}",
+      connack[1]
+    ));
+  }
+  //Kommando Topics abonnieren, Packet ID 1, QoS 0
+  let mut inhalt: Vec<u8> = vec![0, 1];
+  push_mqtt_string(&mut inhalt, format!("{}/+/+/set", config.prefix).as_str());
+  inhalt.push(0);
+  send_mqtt_packet(&stream, &mqtt_packet(MQTT_SUBSCRIBE, &inhalt))?;
+  Ok(stream)
+}
+
+/// Message publizieren, QoS 0
+/// # Arguments
+/// * stream - TCP Stream zum Broker
+/// * publish - Zu publizierende Message
+fn publish(stream: &TcpStream, publish: &MqttPublish) -> Result<(), String> {
+  let mut inhalt: Vec<u8> = Vec::new();
+  push_mqtt_string(&mut inhalt, publish.topic.as_str());
+  inhalt.extend_from_slice(publish.payload.as_bytes());
+  let typ = if publish.retain {
+    MQTT_PUBLISH | 0x01
+  } else {
+    MQTT_PUBLISH
+  };
+  send_mqtt_packet(stream, &mqtt_packet(typ, &inhalt))
+}
+
+/// Aus INFO Message die zu publizierende MQTT Message erstellen.
+/// Liefert None wenn die Message nicht publiziert wird.
+/// # Arguments
+/// * prefix - Topic Prefix
+/// * msg - Message eines SRCP Servers
+fn to_mqtt_publish(prefix: &str, msg: &SRCPMessage) -> Option<MqttPublish> {
+  let SRCPMessageID::Info { info_code } = &msg.message_id else {
+    return None;
+  };
+  //Nur Zustände und Löschungen, INFO 101 (INIT) enthält andere Parameter
+  if info_code == "101" {
+    return None;
+  }
+  let device = msg.device.to_string();
+  //Anzahl Parameter die in den Topic kommen
+  let (topic_param, retain) = match msg.device {
+    SRCPMessageDevice::Power => (0, true),
+    SRCPMessageDevice::GL | SRCPMessageDevice::FB => (1, true),
+    SRCPMessageDevice::GA => (2, true),
+    _ => (0, false),
+  };
+  if msg.parameter.len() < topic_param {
+    return None;
+  }
+  let mut topic = format!("{}/{}/{}", prefix, msg.bus, device);
+  for p in &msg.parameter[..topic_param] {
+    topic.push('/');
+    topic.push_str(p);
+  }
+  //Gelöscht: retained Message mit leerem Payload löscht die Message im Broker
+  let payload = if info_code == "102" {
+    String::new()
+  } else {
+    msg.parameter[topic_param..].join(" ")
+  };
+  Some(MqttPublish {
+    topic,
+    payload,
+    retain,
+  })
+}
+
+/// Empfang vom Broker: alle empfangenen Kommandos ausführen, bis die Verbindung abbricht
+/// # Arguments
+/// * stream - TCP Stream zum Broker
+/// * prefix - Topic Prefix
+/// * session_id - Session ID des Command Mode Clients der MQTT Anbindung
+/// * cmd_rx - Channel Receiver des Command Mode Clients der MQTT Anbindung
+/// * all_cmd_tx - Alle Channel Sender für Kommandos zu den SRCP Servern. Key ist die Busnummer.
+fn mqtt_empfang(
+  stream: TcpStream, prefix: String, session_id: u32,
+  cmd_rx: Arc<Mutex<InfoQueueReceiver<SRCPMessage>>>,
+  all_cmd_tx: Arc<HashMap<usize, SyncSender<Message>>>,
+) {
+  //Für die Zugriffskontrolle gilt die Adresse des Brokers, der eigentliche Absender ist nicht bekannt
+  let Ok(adresse) = stream.peer_addr().map(|addr| addr.ip()) else {
+    error!("MQTT Broker Adresse unbekannt, Empfang beendet");
+    return;
+  };
+  while let Ok((typ, inhalt)) = read_mqtt_packet(&stream) {
+    //Nur PUBLISH auswerten (QoS 0 abonniert), alle anderen (SUBACK, PINGRESP) ignorieren
+    if typ & 0xF0 != MQTT_PUBLISH || inhalt.len() < 2 {
+      continue;
+    }
+    let topic_len = usize::from(u16::from_be_bytes([inhalt[0], inhalt[1]]));
+    let Some(topic) = inhalt.get(2..2 + topic_len) else {
+      continue;
+    };
+    let topic = String::from_utf8_lossy(topic).to_ascii_uppercase();
+    let payload = String::from_utf8_lossy(&inhalt[2 + topic_len..]).to_ascii_uppercase();
+    //<prefix>/<bus>/<device>/set
+    let Some(teile) = topic.strip_prefix(format!("{}/", prefix.to_ascii_uppercase()).as_str())
+    else {
+      continue;
+    };
+    let teile: Vec<&str> = teile.split('/').collect();
+    let [bus, device, "SET"] = teile[..] else {
+      continue;
+    };
+    let mut cmd_parts: Vec<&str> = vec!["SET", bus, device];
+    cmd_parts.extend(payload.split_whitespace());
+    let antwort = SRCPMessage::from(session_id, &cmd_parts).and_then(|srcp_msg| {
+      srcp::execute_command(
+        srcp_msg,
+        Some(adresse),
+        &cmd_rx.lock().unwrap(),
+        &all_cmd_tx,
+      )
+      .map(|(_, antwort)| antwort)
+    });
+    match antwort {
+      Ok(msg) => info!("MQTT {}: {}", cmd_parts.join(" "), msg.to_string()),
+      Err((err_code, err_text)) => warn!(
+        "MQTT {}: {} ERROR {}",
+        cmd_parts.join(" "),
+        err_code,
+        err_text
+      ),
+    }
+  }
+  info!("MQTT Empfang beendet");
+}
+
+/// MQTT Client Thread: Verbindung zum Broker halten, alle INFO Messages publizieren
+/// # Arguments
+/// * config - MQTT Konfiguration
+/// * info_rx - Channel Receiver des Info Mode Clients der MQTT Anbindung
+/// * all_cmd_tx - Alle Channel Sender für Kommandos zu den SRCP Servern. Key ist die Busnummer.
+fn mqtt_client(
+  config: MqttConfig, info_rx: InfoQueueReceiver<SRCPMessage>,
+  all_cmd_tx: HashMap<usize, SyncSender<Message>>,
+) {
+  //Alle SET Kommandos werden über eine gemeinsame Command Mode Session ausgeführt
+  let session_id = srcp::neue_session_id();
+  let cmd_rx = Arc::new(Mutex::new(srcp::anmelden_intern_command_client(session_id)));
+  let all_cmd_tx = Arc::new(all_cmd_tx);
+  //Letzter Zustand aller retained Topics, wird nach (Wieder-)Verbindung publiziert
+  let mut zustand: BTreeMap<String, MqttPublish> = BTreeMap::new();
+  loop {
+    match connect(&config) {
+      Ok(stream) => {
+        info!("MQTT verbunden mit Broker {}", config.broker);
+        let empfang_stream = stream.try_clone().expect("MQTT try_clone fail");
+        let prefix = config.prefix.clone();
+        let cmd_rx = cmd_rx.clone();
+        let all_cmd_tx = all_cmd_tx.clone();
+        thread::Builder::new()
+          .name("MQTT_Empfang".to_string())
+          .spawn(move || mqtt_empfang(empfang_stream, prefix, session_id, cmd_rx, all_cmd_tx))
+          .unwrap();
+        if let Err(msg) = mqtt_verbunden(&stream, &config, &info_rx, &mut zustand) {
+          warn!(
+            "MQTT Verbindung zu Broker {} unterbrochen: {}",
+            config.broker, msg
+          );
+        }
+        //Damit auch der Empfang Thread beendet wird
+        let _ = stream.shutdown(Shutdown::Both);
+      }
+      Err(msg) => warn!("{}", msg),
+    }
+    //Bis zum nächsten Verbindungsversuch nur Zustand nachführen
+    let ende = Instant::now() + RECONNECT_DELAY;
+    while let Some(timeout) = ende.checked_duration_since(Instant::now()) {
+      match info_rx.recv_timeout(timeout) {
+        Ok(msg) => {
+          if let Some(publish) = to_mqtt_publish(config.prefix.as_str(), &msg) {
+            update_zustand(&mut zustand, publish);
+          }
+        }
+        Err(RecvTimeoutError::Timeout) => break,
+        Err(RecvTimeoutError::Disconnected) => {
+          error!("MQTT info_rx Disconnected");
+          return;
+        }
+      }
+    }
+  }
+}
+
+/// Zustand eines retained Topics nachführen
+/// # Arguments
+/// * zustand - Letzter Zustand aller retained Topics
+/// * publish - Neue Message
+fn update_zustand(zustand: &mut BTreeMap<String, MqttPublish>, publish: MqttPublish) {
+  if !publish.retain {
+    return;
+  }
+  if publish.payload.is_empty() {
+    zustand.remove(&publish.topic);
+  } else {
+    zustand.insert(publish.topic.clone(), publish);
+  }
+}
+
+/// Bestehende Verbindung bedienen: alle Zustände publizieren, danach alle neuen INFO Messages.
+/// Liefert Err bei Verbindungsabbruch.
+/// # Arguments
+/// * stream - TCP Stream zum Broker
+/// * config - MQTT Konfiguration
+/// * info_rx - Channel Receiver des Info Mode Clients der MQTT Anbindung
+/// * zustand - Letzter Zustand aller retained Topics
+fn mqtt_verbunden(
+  stream: &TcpStream, config: &MqttConfig, info_rx: &InfoQueueReceiver<SRCPMessage>,
+  zustand: &mut BTreeMap<String, MqttPublish>,
+) -> Result<(), String> {
+  for publish_zustand in zustand.values() {
+    publish(stream, publish_zustand)?;
+  }
+  //Keep Alive: spätestens nach halber Keep Alive Zeit ohne Message ein PINGREQ senden
+  loop {
+    match info_rx.recv_timeout(KEEP_ALIVE / 2) {
+      Ok(msg) => {
+        if let Some(msg_publish) = to_mqtt_publish(config.prefix.as_str(), &msg) {
+          publish(stream, &msg_publish)?;
+          update_zustand(zustand, msg_publish);
+        }
+      }
+      Err(RecvTimeoutError::Timeout) => {
+        send_mqtt_packet(stream, &mqtt_packet(MQTT_PINGREQ, &[]))?;
+      }
+      Err(RecvTimeoutError::Disconnected) => {
+        return Err("MQTT info_rx Disconnected".to_string());
+      }
+    }
+  }
+}
+
+/// Startet die MQTT Anbindung, wenn konfiguriert
+/// # Arguments
+/// * mqtt_config - Konfiguration aus Konfigfile, None wenn nicht konfiguriert, siehe "MqttConfig::from_config"
+/// * all_cmd_tx - Alle Channel Sender für Kommandos zu den SRCP Servern. Key ist die Busnummer.
+pub fn startup(mqtt_config: Option<MqttConfig>, all_cmd_tx: &HashMap<usize, SyncSender<Message>>) {
+  let Some(mqtt_config) = mqtt_config else {
+    return;
+  };
+  info!(
+    "Start MQTT: Broker {} Prefix {}",
+    mqtt_config.broker, mqtt_config.prefix
+  );
+  //Als Info Client anmelden, alle Server senden danach ihren aktuellen Zustand
+  let info_rx = srcp::anmelden_intern_info_client(srcp::neue_session_id(), all_cmd_tx);
+  let all_cmd_tx = all_cmd_tx.clone();
+  thread::Builder::new()
+    .name("MQTT".to_string())
+    .spawn(move || mqtt_client(mqtt_config, info_rx, all_cmd_tx))
+    .unwrap();
+}
@@ -0,0 +1,257 @@
+//! Nothalt Zonen: Verknüpfung FB Eingänge mit GL Nothalt
+//! Wird ein konfigurierter FB aktiv während Power eingeschaltet ist, dann wird unabhängig von
+//! SRCP Clients für alle zugeordneten GL's sofort ein Nothalt ausgelöst. Der DDL Server führt diesen
+//! Nothalt sofort aus, an der Warteschlange der SET Kommandos vorbei (siehe "is_nothalt_cmd").
+//! Solange der FB aktiv ist, bleibt der Nothalt bestehen: SET Kommandos von Clients mit
+//! Geschwindigkeit > 0 an GL's der Zone werden mit "413 ERROR temporarily prohibited" abgelehnt.
+//!
+//! INI File:
+//! [estop]
+//! zone_<n> = <fb bus>:<fb adr> <gl bus>:<gl adr>,<gl bus>:<gl adr>,...
+//! GL Adresse 0 -> Nothalt für alle GL's dieses Busses
+
+use std::{
+  collections::HashMap,
+  sync::{
+    mpsc::{SyncSender, TrySendError},
+    Mutex,
+  },
+};
+
+use log::{error, info, warn};
+
+use crate::srcp_server_types::{
+  Message, SRCPMessage, SRCPMessageDevice, SRCPMessageID, SRCPMessageType,
+};
+
+/// Alle GL's von Zonen mit aktivem FB: Bus und Adresse, Adresse 0 für alle GL's des Busses.
+/// Wird vom Info Dispatcher nachgeführt und von allen Client Threads abgefragt.
+static GESPERRT: Mutex<Vec<(usize, u32)>> = Mutex::new(Vec::new());
+
+/// Eine Nothalt Zone
+struct NothaltZone {
+  /// Name aus Konfigfile (für Log)
+  name: String,
+  /// Auslösender FB: Bus und Adresse
+  fb: (usize, u32),
+  /// Alle GL's für Nothalt: Bus und Adresse, Adresse 0 für alle GL's des Busses
+  gl: Vec<(usize, u32)>,
+}
+
+/// Alle Nothalt Zonen mit aktuellem Zustand
+pub struct NothaltZonen {
+  /// Alle konfigurierten Zonen
+  zonen: Vec<NothaltZone>,
+  /// Aktueller Power Zustand pro Bus gemäss INFO Messages
+  power: HashMap<usize, bool>,
+  /// Aktueller Zustand der FB's aller Zonen gemäss INFO Messages, Key Bus und Adresse
+  fb_aktiv: HashMap<(usize, u32), bool>,
+}
+
+impl NothaltZonen {
+  /// Nothalt Zonen aus Konfigfile erstellen.
+  /// Liefert Err zurück wenn die Konfiguration ungültig ist.
+  /// # Arguments
+  /// * config - Abschnitt [estop] aus Konfigfile, None wenn nicht vorhanden
+  pub fn from_config(
+    config: Option<&HashMap<String, Option<String>>>,
+  ) -> Result<NothaltZonen, String> {
+    let mut zonen: Vec<NothaltZone> = Vec::new();
+    if let Some(config) = config {
+      for (name, wert) in config {
+        if !name.starts_with("zone_") {
+          continue;
+        }
+        let wert = wert.as_ref().ok_or(format!("[estop] {} ohne Wert", name))?;
+        let mut teile = wert.split_whitespace();
+        let fb = NothaltZonen::eval_bus_adr(teile.next().unwrap_or(""))
+          .ok_or(format!("[estop] {}: ungültiger FB <bus>:<adr>", name))?;
+        let mut gl: Vec<(usize, u32)> = Vec::new();
+        for gl_bus_adr in teile.flat_map(|t| t.split(',')).filter(|t| !t.is_empty()) {
+          gl.push(
+            NothaltZonen::eval_bus_adr(gl_bus_adr)
+              .ok_or(format!("[estop] {}: ungültige GL {}", name, gl_bus_adr))?,
+          );
+        }
+        if gl.is_empty() {
+          return Err(format!("[estop] {}: keine GL angegeben", name));
+        }
+        info!("Nothalt Zone {}: FB {:?} -> GL {:?}", name, fb, gl);
+        zonen.push(NothaltZone {
+          name: name.clone(),
+          fb,
+          gl,
+        });
+      }
+    }
+    Ok(NothaltZonen {
+      zonen,
+      power: HashMap::new(),
+      fb_aktiv: HashMap::new(),
+    })
+  }
+
+  /// Auswerten <bus>:<adr>
+  /// # Arguments
+  /// * bus_adr - String im Format <bus>:<adr>
+  fn eval_bus_adr(bus_adr: &str) -> Option<(usize, u32)> {
+    let (bus, adr) = bus_adr.split_once(':')?;
+    Some((bus.parse::<usize>().ok()?, adr.parse::<u32>().ok()?))
+  }
+
+  /// Auswertung aller INFO Messages der SRCP Server.
+  /// Verfolgt Power Zustand und FB's, bei neu aktivem FB einer Zone wird für alle GL's der Zone
+  /// ein Nothalt an den jeweiligen SRCP Server gesendet, wenn auf deren Bus Power eingeschaltet ist.
+  /// # Arguments
+  /// * msg - Empfangene Message eines SRCP Servers
+  /// * all_cmd_tx - Alle Channel Sender für Kommandos zu den SRCP Servern. Key ist die Busnummer.
+  pub fn eval_info(&mut self, msg: &SRCPMessage, all_cmd_tx: &HashMap<usize, SyncSender<Message>>) {
+    //Nur Zustandsveränderungen an alle, keine Antworten oder Zustände an einzelne Sessions
+    if self.zonen.is_empty() || msg.session_id.is_some() {
+      return;
+    }
+    let SRCPMessageID::Info { info_code } = &msg.message_id else {
+      return;
+    };
+    if info_code != "100" {
+      return;
+    }
+    match msg.device {
+      SRCPMessageDevice::Power => {
+        //INFO <bus> POWER ON|OFF
+        self.power.insert(
+          msg.bus,
+          msg.parameter.first().map(String::as_str) == Some("ON"),
+        );
+      }
+      SRCPMessageDevice::FB => {
+        //INFO <bus> FB <adr> <value>
+        let (Some(adr), Some(value)) = (msg.get_adr(), msg.parameter.get(1)) else {
+          return;
+        };
+        let aktiv = value != "0";
+        let war_aktiv = self.fb_aktiv.insert((msg.bus, adr), aktiv).unwrap_or(false);
+        if aktiv != war_aktiv {
+          self.update_gesperrt();
+        }
+        if aktiv && !war_aktiv {
+          for zone in self.zonen.iter().filter(|zone| zone.fb == (msg.bus, adr)) {
+            for (bus, gl_adr) in &zone.gl {
+              if !self.power.get(bus).copied().unwrap_or(false) {
+                continue;
+              }
+              warn!(
+                "Nothalt Zone {}: FB {}:{} aktiv -> Nothalt GL {}:{}",
+                zone.name, msg.bus, adr, bus, gl_adr
+              );
+              NothaltZonen::send_nothalt(*bus, *gl_adr, all_cmd_tx);
+            }
+          }
+        }
+      }
+      _ => {}
+    }
+  }
+
+  /// Alle GL's von Zonen mit aktivem FB für "pruefen" übernehmen
+  fn update_gesperrt(&self) {
+    let gesperrt: Vec<(usize, u32)> = self
+      .zonen
+      .iter()
+      .filter(|zone| self.fb_aktiv.get(&zone.fb).copied().unwrap_or(false))
+      .flat_map(|zone| zone.gl.iter().copied())
+      .collect();
+    *GESPERRT.lock().unwrap() = gesperrt;
+  }
+
+  /// Liefert true wenn "msg" ein durch eine Nothalt Zone ausgelöster Nothalt ist.
+  /// Dieser muss sofort ausgeführt werden und darf durch kein anderes Kommando für dieselbe GL verdrängt werden.
+  /// # Arguments
+  /// * msg - Zu prüfendes Kommando
+  pub fn is_nothalt_cmd(msg: &SRCPMessage) -> bool {
+    (msg.session_id == Some(0))
+      && (msg.device == SRCPMessageDevice::GL)
+      && matches!(
+        msg.message_id,
+        SRCPMessageID::Command {
+          msg_type: SRCPMessageType::SET
+        }
+      )
+      && (msg.parameter.get(1).map(String::as_str) == Some("2"))
+  }
+
+  /// Prüft ein Kommando eines Clients gegen alle Zonen mit aktivem FB.
+  /// Liefert Err 413 für SET GL mit Geschwindigkeit > 0 ohne Nothalt an eine GL einer solchen Zone.
+  /// # Arguments
+  /// * srcp_msg - Kommando des Clients
+  pub fn pruefen(srcp_msg: &SRCPMessage) -> Result<(), (&'static str, &'static str)> {
+    //SET <bus> GL <addr> <drivemode> <V> <V_max> <f1> .. <fn>
+    if (srcp_msg.device != SRCPMessageDevice::GL)
+      || !matches!(
+        srcp_msg.message_id,
+        SRCPMessageID::Command {
+          msg_type: SRCPMessageType::SET
+        }
+      )
+      || (srcp_msg.parameter.get(1).map(String::as_str) == Some("2"))
+      || (srcp_msg
+        .parameter
+        .get(2)
+        .and_then(|v| v.parse::<u32>().ok())
+        == Some(0))
+    {
+      return Ok(());
+    }
+    let Some(adr) = srcp_msg.get_adr() else {
+      return Ok(());
+    };
+    let gesperrt = GESPERRT.lock().unwrap();
+    if gesperrt
+      .iter()
+      .any(|(bus, gl_adr)| (*bus == srcp_msg.bus) && ((*gl_adr == adr) || (*gl_adr == 0)))
+    {
+      warn!("Nothalt aktiv, abgelehnt: {}", srcp_msg.to_string());
+      return Err(("413", "temporarily prohibited"));
+    }
+    Ok(())
+  }
+
+  /// Nothalt an eine GL senden.
+  /// SET <bus> GL <adr> 2 0 1, Session ID 0 = srcp Server selbst
+  /// # Arguments
+  /// * bus - SRCP Bus der GL
+  /// * adr - GL Adresse, 0 für alle GL's des Busses
+  /// * all_cmd_tx - Alle Channel Sender für Kommandos zu den SRCP Servern. Key ist die Busnummer.
+  fn send_nothalt(bus: usize, adr: u32, all_cmd_tx: &HashMap<usize, SyncSender<Message>>) {
+    let Some(sender) = all_cmd_tx.get(&bus) else {
+      warn!("Nothalt: Bus {} nicht vorhanden", bus);
+      return;
+    };
+    //Nie blockieren, der Info Dispatcher darf durch einen ausgelasteten Server nicht aufgehalten werden
+    match sender.try_send(Message::new_srcpmessage(SRCPMessage::new(
+      Some(0),
+      bus,
+      SRCPMessageID::Command {
+        msg_type: SRCPMessageType::SET,
+      },
+      SRCPMessageDevice::GL,
+      vec![
+        adr.to_string(),
+        "2".to_string(),
+        "0".to_string(),
+        "1".to_string(),
+      ],
+    ))) {
+      Ok(()) => {}
+      Err(TrySendError::Full(_)) => {
+        error!(
+          "Nothalt: Kommandoqueue Bus {} voll, Nothalt GL {} nicht gesendet",
+          bus, adr
+        );
+      }
+      Err(TrySendError::Disconnected(_)) => {
+        warn!("Nothalt: Send an Bus {} fail", bus);
+      }
+    }
+  }
+}
@@ -5,6 +5,10 @@ use std::{
   time::{Duration, Instant},
 };
 
+use log::warn;
+
+use crate::srcp_dekoder_ident::DekoderIdent;
+
 ///Varianten Rückmeldung Dekodersuche (MFX)
 #[derive(Debug, Clone)]
 pub enum DdlTelRx {
@@ -13,17 +17,84 @@ pub enum DdlTelRx {
   Udp,
 }
 
+/// Ein Abschnitt eines Telegrammes.
+/// Die Abschnitte werden erst beim Senden (siehe "DdlTel::get_spi_daten") in SPI Bytes aufgelöst.
+#[derive(Debug, Clone)]
+pub enum DdlTelSegment {
+  /// Nutzdaten, bereits als SPI Bytes für die Baudrate "baud" kodiert
+  Payload { baud: u32, daten: Vec<u8> },
+  /// Pause (0-Bytes) mit der Baudrate der umgebenden Nutzdaten
+  Pause { dauer: Duration },
+  /// Die vorhergehenden Nutzdaten werden insgesamt "n" mal gesendet, vor jeder Wiederholung "pause"
+  Repeat { n: usize, pause: Duration },
+  /// RailCom Cutout direkt nach dem Schlussbit eines vollständigen DCC Paketes, mit der Baudrate der
+  /// vorhergehenden Nutzdaten: SPI Byte "tcs" (letzte Flanke bis Start Cutout), danach "dauer" ohne
+  /// Signalwechsel. Wird bei "Repeat" nach jedem wiederholten Paket ebenfalls ausgegeben.
+  Cutout { tcs: u8, dauer: Duration },
+}
+
+/// Liefert die Dauer von "anz_bytes" SPI Bytes bei Baudrate "hz".
+/// Abgerundet, so dass die Pause beim Auflösen wieder genau "anz_bytes" ergibt.
+/// # Arguments
+/// * anz_bytes - Anzahl SPI Bytes
+/// * hz - Baudrate
+pub const fn spi_dauer(anz_bytes: usize, hz: u32) -> Duration {
+  Duration::from_nanos((anz_bytes as u64 * 8_000_000_000) / hz as u64)
+}
+
+/// Liefert die Anzahl 0-Bytes für eine Pause, aufgerundet.
+/// # Arguments
+/// * dauer - Dauer der Pause
+/// * hz - Baudrate
+fn spi_pause_bytes(dauer: Duration, hz: u32) -> usize {
+  (dauer.as_nanos() * hz as u128).div_ceil(8_000_000_000) as usize
+}
+
+/// Liefert die Bitmaske der Funktionen F0 bis F<funk_anz - 1>.
+/// Funktionsbits ausserhalb der mit INIT angegebenen Anzahl werden von allen Protokollen ignoriert.
+/// # Arguments
+/// * funk_anz - Anzahl verwendete Funktionen
+pub fn funktionen_maske(funk_anz: usize) -> u64 {
+  if funk_anz >= u64::BITS as usize {
+    u64::MAX
+  } else {
+    (1 << funk_anz) - 1
+  }
+}
+
+/// Begrenzt die mit INIT verlangte Anzahl Funktionen auf die vom Protokoll unterstützte Anzahl.
+/// Zu lange Listen werden auf SRCP Ebene mit "418 ERROR list too long" abgelehnt, eine INIT mit mehr
+/// Funktionen als das Protokoll kennt wird dagegen mit Warnung gekürzt, die zusätzlichen Funktionen
+/// werden nie gesendet.
+/// # Arguments
+/// * protokoll - Protokollname für Log
+/// * adr - Adresse der Lok
+/// * funk_anz - Mit INIT verlangte Anzahl Funktionen
+/// * max - Vom Protokoll unterstützte Anzahl Funktionen ("get_gl_anz_f")
+pub fn begrenze_funk_anz(protokoll: &str, adr: u32, funk_anz: usize, max: usize) -> usize {
+  if funk_anz > max {
+    warn!(
+      "{} GL {}: {} Funktionen verlangt, nur {} unterstützt, wird gekürzt",
+      protokoll, adr, funk_anz, max
+    );
+    max
+  } else {
+    funk_anz
+  }
+}
+
 /// Telegramm zum senden über SPI
 #[derive(Debug, Clone)]
 pub struct DdlTel {
-  /// Nur zu Debugzwecken: Adresse (GL oder GA)
-  pub _adr: u32,
+  /// Adresse (GL oder GA), 0 für Idle und Protokolltelegramme.
+  /// Wird für Debugzwecke und zur Erkennung noch verzögerter Telegramme einer GL verwendet.
+  pub adr: u32,
   /// Und auch zum debuggen: Triggerimpuls für Oszi bei senden dieses Telegrammes ausgeben
   pub trigger: bool,
   /// Wieviel mal wird ein Telegramm direkt hintereinander versendet.
   /// Bei neuen Kommandos >1 (typisch 2), bei Refresh Cycle einmal.
   pub tel_wiederholungen: usize,
-  /// Die Baudrate mit der gesendet werden muss
+  /// Die Baudrate mit der gesendet werden muss, gilt für alle mit "payload_mut" erzeugten Nutzdaten
   pub hz: u32,
   /// Die minimale Verzögerung in ms vom Start eines zum nächsten Telegramm wenn mehrere Telegramme in einem DdlTel sind.
   pub delay: Duration,
@@ -34,25 +105,27 @@ pub struct DdlTel {
   /// Zeitpunkt Ende Versenden letztes + delay
   pub instant_next: Option<Instant>,
   /// Pause die am Anfang des Telegrammes notwendig ist.
-  /// Dient nur der Information, die Pause selbst MUSS als 0-Bytes in den Daten enthalten sein!
+  /// Dient nur der Information, die Pause selbst MUSS als Pause Segment in den Daten enthalten sein!
   pub pause_start: Duration,
   /// Pause die am Ende des Telegrammes notwendig ist.
   /// Wenn auf ein Telegramm mit "pause_ende" eines mit "pause_start" start kommt, dann werden die Pausen kombiniert und nicht addiert!
   pub pause_ende: Duration,
-  /// Die Bytes die gesendet werden müssen
+  /// Die Segmente die gesendet werden müssen, pro Telegramm ein Vec.
   /// Es können hier mehrere unabhängige Telegramme zurückgegeben werden. Wenn diese nicht
   /// unmittelbar nacheinander gesendet werden dürfen. z.B. verlangt DCC 5ms zwischen 2 Telegrammen
   /// an die selbe Adresse, was für Fahren und F0-F5 immer der Fall ist.
   /// Wenn mehr als ein Telegramm zurückgegeben wird und ein Delay verlangt ist, dann erfolgt die Ausgabe immer abwechlungsweise
   /// mit einem Telegramm zu einer anderen Adresse (was auch ein anderes Protokoll sein kann).
-  pub daten: Vec<Vec<u8>>,
+  pub daten: Vec<Vec<DdlTelSegment>>,
   /// Rückmeldung bei Dekodersuche (MFX). Normalerweise nicht verwendete, None.
   /// Wird bei MFX verwendet. Wenn die Erkennung des RDS Signals über SPI IN erfolgt, dann SpiRx, wenn über UDP empfangen dann Udp.
   /// Wenn SpiRx verwendet sollte das Telegramm nur einmal gesendet werden.
   /// Ansonsten sind hier nur die mit der letzten Wiederholung empfangenen Daten enthalten.
   /// Wenn verwendet, dann wird es nur für das letzte Telegramm in "daten" angewandt und die Grösse hier muss genau gleich wie dieses
-  /// letzte Telegramm sein.
+  /// letzte Telegramm sein (siehe "get_len").
   pub daten_rx: DdlTelRx,
+  /// Initiale reservierte Grösse für neue Nutzdaten
+  payload_capacity: usize,
 }
 impl DdlTel {
   /// Neue Instanz Erstellen
@@ -69,7 +142,7 @@ impl DdlTel {
     tel_wiederholungen: usize, trigger: bool,
   ) -> DdlTel {
     DdlTel {
-      _adr: adr,
+      adr,
       trigger,
       tel_wiederholungen,
       hz,
@@ -78,9 +151,163 @@ impl DdlTel {
       pause_start: Duration::ZERO,
       pause_ende: Duration::ZERO,
       instant_next: None,
-      daten: vec![Vec::with_capacity(capacity)],
+      daten: vec![Vec::new()],
       daten_rx: DdlTelRx::None,
+      payload_capacity: capacity,
+    }
+  }
+
+  /// Neues, leeres Telegramm am Ende hinzufügen.
+  /// # Arguments
+  /// * capacity - Initiale reservierte Grösse für Nutzdaten im neuen Telegramm
+  pub fn neues_tel(&mut self, capacity: usize) {
+    self.daten.push(Vec::new());
+    self.payload_capacity = capacity;
+  }
+
+  /// Liefert die Nutzdaten am Ende des letzten Telegrammes zum Ergänzen.
+  /// Wenn das letzte Telegramm nicht mit Nutzdaten endet, werden neue mit Baudrate "hz" angelegt.
+  pub fn payload_mut(&mut self) -> &mut Vec<u8> {
+    let tel = self.daten.last_mut().unwrap();
+    if !matches!(tel.last(), Some(DdlTelSegment::Payload { .. })) {
+      tel.push(DdlTelSegment::Payload {
+        baud: self.hz,
+        daten: Vec::with_capacity(self.payload_capacity),
+      });
+    }
+    let Some(DdlTelSegment::Payload { daten, .. }) = tel.last_mut() else {
+      unreachable!()
+    };
+    daten
+  }
+
+  /// Liefert das letzte Byte des letzten Telegrammes, 0 wenn es leer ist oder mit einer Pause endet.
+  pub fn get_letztes_byte(&self) -> u8 {
+    match self.daten.last().unwrap().last() {
+      Some(DdlTelSegment::Payload { daten, .. }) => *daten.last().unwrap_or(&0),
+      _ => 0,
+    }
+  }
+
+  /// Pause am Ende des letzten Telegrammes hinzufügen
+  /// # Arguments
+  /// * dauer - Dauer der Pause
+  pub fn add_pause(&mut self, dauer: Duration) {
+    self
+      .daten
+      .last_mut()
+      .unwrap()
+      .push(DdlTelSegment::Pause { dauer });
+  }
+
+  /// Die letzten Nutzdaten des letzten Telegrammes wiederholen.
+  /// # Arguments
+  /// * n - Wie oft die Nutzdaten insgesamt gesendet werden
+  /// * pause - Pause vor jeder Wiederholung
+  pub fn add_repeat(&mut self, n: usize, pause: Duration) {
+    self
+      .daten
+      .last_mut()
+      .unwrap()
+      .push(DdlTelSegment::Repeat { n, pause });
+  }
+
+  /// RailCom Cutout am Ende des letzten Telegrammes hinzufügen, nur direkt nach dem Schlussbit eines DCC Paketes.
+  /// # Arguments
+  /// * tcs - SPI Byte von der letzten Flanke bis Start Cutout
+  /// * dauer - Dauer Cutout ohne Signalwechsel
+  pub fn add_cutout(&mut self, tcs: u8, dauer: Duration) {
+    self
+      .daten
+      .last_mut()
+      .unwrap()
+      .push(DdlTelSegment::Cutout { tcs, dauer });
+  }
+
+  /// Liefert die Länge des letzten Telegrammes in SPI Bytes
+  pub fn get_len(&self) -> usize {
+    self
+      .get_spi_daten(self.daten.len() - 1)
+      .iter()
+      .map(|(_, daten)| daten.len())
+      .sum()
+  }
+
+  /// Löst die Segmente eines Telegrammes in SPI Bytes auf.
+  /// Liefert pro Baudrate einen Abschnitt (Baudrate, SPI Bytes).
+  /// Pausen werden mit der Baudrate der vorhergehenden Nutzdaten aufgelöst, am Telegrammanfang
+  /// mit der Baudrate der ersten Nutzdaten.
+  /// # Arguments
+  /// * index - Index des Telegrammes in "daten"
+  pub fn get_spi_daten(&self, index: usize) -> Vec<(u32, Vec<u8>)> {
+    let segmente = &self.daten[index];
+    let mut baud = segmente
+      .iter()
+      .find_map(|segment| match segment {
+        DdlTelSegment::Payload { baud, .. } => Some(*baud),
+        _ => None,
+      })
+      .unwrap_or(self.hz);
+    let mut spi_daten: Vec<(u32, Vec<u8>)> = Vec::new();
+    let mut letzte_payload: &[u8] = &[];
+    //Cutout nach den letzten Nutzdaten, wird bei Repeat mit wiederholt
+    let mut letzter_cutout: Option<(u8, Duration)> = None;
+    for segment in segmente {
+      match segment {
+        DdlTelSegment::Payload { baud: hz, daten } => {
+          baud = *hz;
+          DdlTel::get_abschnitt(&mut spi_daten, baud).extend_from_slice(daten);
+          letzte_payload = daten;
+          letzter_cutout = None;
+        }
+        DdlTelSegment::Pause { dauer } => {
+          let abschnitt = DdlTel::get_abschnitt(&mut spi_daten, baud);
+          abschnitt.resize(abschnitt.len() + spi_pause_bytes(*dauer, baud), 0);
+        }
+        DdlTelSegment::Repeat { n, pause } => {
+          let abschnitt = DdlTel::get_abschnitt(&mut spi_daten, baud);
+          for _ in 1..*n {
+            abschnitt.resize(abschnitt.len() + spi_pause_bytes(*pause, baud), 0);
+            abschnitt.extend_from_slice(letzte_payload);
+            if let Some((tcs, dauer)) = letzter_cutout {
+              DdlTel::cutout_aufloesen(abschnitt, tcs, dauer, baud);
+            }
+          }
+        }
+        DdlTelSegment::Cutout { tcs, dauer } => {
+          DdlTel::cutout_aufloesen(
+            DdlTel::get_abschnitt(&mut spi_daten, baud),
+            *tcs,
+            *dauer,
+            baud,
+          );
+          letzter_cutout = Some((*tcs, *dauer));
+        }
+      }
+    }
+    spi_daten
+  }
+
+  /// RailCom Cutout in SPI Bytes auflösen und anhängen
+  /// # Arguments
+  /// * abschnitt - SPI Bytes, an die der Cutout angehängt wird
+  /// * tcs - SPI Byte von der letzten Flanke bis Start Cutout
+  /// * dauer - Dauer Cutout ohne Signalwechsel
+  /// * baud - Baudrate
+  fn cutout_aufloesen(abschnitt: &mut Vec<u8>, tcs: u8, dauer: Duration, baud: u32) {
+    abschnitt.push(tcs);
+    abschnitt.resize(abschnitt.len() + spi_pause_bytes(dauer, baud), 0);
+  }
+
+  /// Liefert den letzten Abschnitt mit Baudrate "baud", legt einen neuen an wenn die Baudrate ändert.
+  /// # Arguments
+  /// * spi_daten - Bisher aufgelöste Abschnitte
+  /// * baud - Baudrate
+  fn get_abschnitt(spi_daten: &mut Vec<(u32, Vec<u8>)>, baud: u32) -> &mut Vec<u8> {
+    if spi_daten.last().is_none_or(|(hz, _)| *hz != baud) {
+      spi_daten.push((baud, Vec::new()));
     }
+    &mut spi_daten.last_mut().unwrap().1
   }
 }
 
@@ -93,6 +320,8 @@ pub enum DdlProtokolle {
   Dcc,
   //MFX
   Mfx,
+  //Selectrix SX1
+  Selectrix,
 }
 impl DdlProtokolle {
   pub fn from_str(str: &str) -> Option<DdlProtokolle> {
@@ -100,6 +329,7 @@ impl DdlProtokolle {
       "M" => Some(DdlProtokolle::Maerklin),
       "N" => Some(DdlProtokolle::Dcc),
       "X" => Some(DdlProtokolle::Mfx),
+      "S" => Some(DdlProtokolle::Selectrix),
       _ => None,
     }
   }
@@ -110,6 +340,7 @@ impl ToString for DdlProtokolle {
       DdlProtokolle::Dcc => "N",
       DdlProtokolle::Maerklin => "M",
       DdlProtokolle::Mfx => "X",
+      DdlProtokolle::Selectrix => "S",
     }
     .to_string()
   }
@@ -147,7 +378,8 @@ impl ToString for GLDriveMode {
 pub enum ResultReadGlParameter {
   Error,
   Busy,
-  Ok(Vec<String>),
+  Fortschritt(u8),                //Auslesen im Gange, neuer Fortschritt in %
+  Ok(Vec<String>, Option<usize>), //Ausgelesene Parameter und, falls bekannt, tatsächliche Anzahl Funktionen
 }
 
 /// Ergebnis für "eval_neu_anmeldung"
@@ -159,6 +391,63 @@ pub enum ResultNeuAnmeldung {
   Error(String), //Neuanmeldung Fehler mit Errortext
 }
 
+/// Ergebnis für "get_gl_anwesenheit"
+pub enum GLAnwesenheit {
+  Vorhanden(u32),   //Dekoder dieser GL Adresse ist wieder vorhanden
+  Abwesend(u32),    //Dekoder dieser GL Adresse ist nicht mehr vorhanden
+  Freigegeben(u32), //Dekoder nicht mehr vorhanden, Adresse wurde freigegeben, die GL muss entfernt werden
+}
+
+/// Parameter für "DdlProtokoll::init_gl".
+/// Neue Parameter werden hier ergänzt, ohne dass die Signatur aller Protokolle angepasst werden muss.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GlInitParams {
+  /// UID des Dekoders, wenn vorhanden
+  pub uid: Option<u32>,
+  /// Anzahl tatsächlich verwendete Funktionen. Kann, je nach Protokoll, dazu
+  /// verwendet werden, nur Telegramme der verwendeten Funktionen zu senden.
+  pub funk_anz: usize,
+  /// true wenn Power ein, ein allfällig notwendiges Telegramm (z.B. MFX Schienenadr. Zuordung)
+  /// wird zum sofort senden zurückgegeben.
+  /// false wenn Power aus, nie direkte Ausgabe notwendiges Init Telegramm, dieses muss vor nächstem
+  /// GL Befehl ausgegeben werden.
+  pub power: bool,
+  /// Oszi Trigger bei Ausgabe?
+  pub trigger: bool,
+}
+
+/// Optionen für ein neues, leeres Telegramm ("DdlProtokoll::get_gl_new_tel" und "get_ga_new_tel").
+/// Neue Optionen werden hier ergänzt, ohne dass die Signatur aller Protokolle angepasst werden muss.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TelOptions {
+  /// Wenn true: Aufruf aus Refresh Cycle, einmalige Telegramm Versendung,
+  /// Wenn false: Aufruf wegen neuem Kommando, mehrmaliges Versenden.
+  /// Für GA nicht verwendet.
+  pub refresh: bool,
+  /// Oszi Trigger bei Ausgabe?
+  pub trigger: bool,
+}
+impl TelOptions {
+  /// Optionen für ein neues Kommando, mehrmaliges Versenden
+  /// # Arguments
+  /// * trigger - Oszi Trigger bei Ausgabe?
+  pub fn neu(trigger: bool) -> TelOptions {
+    TelOptions {
+      refresh: false,
+      trigger,
+    }
+  }
+  /// Optionen für den Refresh Cycle, einmaliges Versenden
+  /// # Arguments
+  /// * trigger - Oszi Trigger bei Ausgabe?
+  pub fn refresh(trigger: bool) -> TelOptions {
+    TelOptions {
+      refresh: true,
+      trigger,
+    }
+  }
+}
+
 /// Schnittstelle für alle Protokolle
 /// Wenn mehrere Versionen eines Protokolles vorhanden sind, dann muss dies bei
 /// der Implementierung berücksichtigt werden, schlussendlich eine Instanz pro
@@ -177,17 +466,8 @@ pub trait DdlProtokoll {
   /// Liefert, wenn "power" ein allfällg notwendiges Init-Telegramm (z.B. MFX SID Zurordnung) zurück.
   /// # Arguments
   /// * adr - Adresse der Lok
-  /// * uid - UID des Dekoders, wenn vorhanden
-  /// * funk_anz - Anzahl tatsächlich verwendete Funktionen. Kann, je nach Protokoll, dazu
-  ///              verwendet werden, nur Telegramme der verwendeten Funktionen zu senden.
-  /// * power - true wenn Power ein, ein allfällig notwendiges Telegramm (z.B. MFX Schienenadr. Zuordung)
-  ///           wird zum sofort senden zurückgegeben.
-  ///           false wenn Power aus, nie direkte Ausgabe notwendiges Init Telegramm, dieses muss vor nächstem
-  ///           GL Befehl ausgegeben werden.
-  /// * trigger - Oszi Trigger bei Ausgabe?
-  fn init_gl(
-    &mut self, adr: u32, uid: Option<u32>, funk_anz: usize, power: bool, trigger: bool,
-  ) -> Option<DdlTel>;
+  /// * params - UID, Anzahl Funktionen, Power und Oszi Trigger, siehe "GlInitParams"
+  fn init_gl(&mut self, adr: u32, params: &GlInitParams) -> Option<DdlTel>;
   /// Liefert die max. erlaubte Lokadresse
   fn get_gl_max_adr(&self) -> u32;
   /// Wieviele Speedsteps werden vom Protokoll unterstützt
@@ -202,10 +482,8 @@ pub trait DdlProtokoll {
   /// Liefert ein leeres GL Telegramm zur Verwendung in "get_gl_basis_tel" und / oder "get_gl_zusatz_tel".
   /// # Arguments
   /// * adr - Adresse der Lok, keine Verwendunbg, nur Debug Support
-  /// * refresh - Wenn true: Aufruf aus Refres Cycle, einmalige Telegramm Versendung,
-  ///             Wenn false: Aufruf wegen neuem Lokkommando, mehrmaliges Versenden
-  /// * trigger - Oszi Trigger bei Ausgabe?
-  fn get_gl_new_tel(&mut self, adr: u32, refresh: bool, trigger: bool) -> DdlTel;
+  /// * optionen - Refresh und Oszi Trigger, siehe "TelOptions"
+  fn get_gl_new_tel(&mut self, adr: u32, optionen: TelOptions) -> DdlTel;
   /// Erzeugt das Basis Telegramm für GL.
   /// - Fahren
   /// - Basisfunktionen F0 bis "get_Anz_F_Basis". Es wedren hier nur diese Funktionen übernommen!
@@ -233,8 +511,8 @@ pub trait DdlProtokoll {
   /// Liefert ein leeres GA Telegramm zur Verwendung in "get_ga_tel".
   /// # Arguments
   /// * adr - Adresse GA, keine Verwendunbg, nur Debug Support
-  /// * trigger - Oszi Trigger?
-  fn get_ga_new_tel(&self, adr: u32, trigger: bool) -> DdlTel;
+  /// * optionen - Oszi Trigger, siehe "TelOptions"
+  fn get_ga_new_tel(&self, adr: u32, optionen: TelOptions) -> DdlTel;
   /// Erzeugt ein GA Telegramm
   /// Liefert true zurück, wenn Timeout zu r automatischen Abschaltung durch Protokoll / Dekoder übernommen wird.
   /// # Arguments
@@ -246,8 +524,18 @@ pub trait DdlProtokoll {
   ///             Duration::ZERO = Port ignorieren, Value ist der zu sendende Begriff (z.B. Erweiterte Funktionsdekoder NMRA/DCC Signalbegriff)
   /// * ddl_tel - DDL Telegramm, bei dem des neue Telegramm hinzugefügt werden soll.
   fn get_ga_tel(
-    &self, adr: u32, port: usize, value: usize, timeout: Option<Duration>, ddl_tel: &mut DdlTel,
+    &mut self, adr: u32, port: usize, value: usize, timeout: Option<Duration>, ddl_tel: &mut DdlTel,
   ) -> bool;
+  /// Legt fest, ob das Protokoll einen Broadcast an alle Zubehördekoder (SET <bus> GA 0) unterstützt.
+  /// Return true wenn unterstützt.
+  fn ga_broadcast(&self) -> bool {
+    false
+  }
+  /// Erzeugt das / die GA Broadcast Telegramm(e) "alle Ausgänge aus" bzw. Nothalt an alle Zubehördekoder.
+  /// Wird nur aufgerufen, wenn "ga_broadcast" true liefert.
+  /// # Arguments
+  /// * ddl_tel - DDL Telegramm aus "get_ga_new_tel", bei dem die neuen Telegramme hinzugefügt werden sollen.
+  fn get_ga_broadcast_off_tel(&mut self, _ddl_tel: &mut DdlTel) {}
   /// Liefert das Idle Telegramm dieses Protokolles
   /// Return None wenn kein Idle Telegramm vorhanden ist
   fn get_idle_tel(&mut self) -> Option<DdlTel>;
@@ -257,6 +545,14 @@ pub trait DdlProtokoll {
   fn get_idle_tel_power_off(&self) -> Option<DdlTel> {
     None
   }
+  /// Power wurde eingeschaltet (Übergang von Power Off auf On).
+  /// Liefert true zurück, wenn alle GL's dieses Protokolles vor dem weiteren Refresh mit ihrem letzten
+  /// Zustand (Richtung, Geschwindigkeit, Funktionen) sofort neu gesendet werden sollen, z.B. MFX
+  /// Dekoder die nach einem Stromunterbruch ihre SID und Richtung verloren haben können.
+  /// Allfällig notwendige Init-Telegramme (z.B. MFX SID Zuordnung) werden dann über "get_gl_new_tel" geliefert.
+  fn power_on_resync(&mut self) -> bool {
+    false
+  }
   /// Liefert zusätzliche, Protokoll spezifische Telegramme (z.B. bei MFX die UID & Neuanmeldezähler der Zentrale)
   /// Liefert None, wenn es nichts zur versenden gibt
   /// # Arguments
@@ -266,6 +562,17 @@ pub trait DdlProtokoll {
   fn get_protokoll_telegrammme(&mut self, _power: bool) -> Option<DdlTel> {
     None
   }
+  /// Liefert Telegramme für einen separaten Programmiergleis Ausgang (SM parallel zum Fahrbetrieb).
+  /// Diese werden unabhängig von Power gesendet.
+  /// Liefert None, wenn es nichts zur versenden gibt oder kein separater Ausgang verwendet wird.
+  fn get_prog_gleis_telegramm(&mut self) -> Option<DdlTel> {
+    None
+  }
+  /// Liefert den Zeitpunkt, an dem "get_protokoll_telegrammme" das nächste periodische Telegramm liefert.
+  /// None wenn das Protokoll keine periodischen Telegramme hat.
+  fn get_naechster_protokoll_zeitpunkt(&self) -> Option<Instant> {
+    None
+  }
   /// Auswertung automatische Dekoderanmeldung (z.B. bei MFX).
   /// Notwendige Telegramme zur Suche müssen über "get_protokoll_telegrammme" ausgegeben und eine Rückmeldung
   /// verlangt werden.
@@ -278,6 +585,7 @@ pub trait DdlProtokoll {
   /// Auslesen optionale GL Parameter (z.B. MFX Lokname und Funktionen)
   /// Liefert ResultReadGlParameter::Error zurück, wenn ein Fehler aufgetreten ist oder vom Protokoll nicht untertsützt.
   /// Liefert ResultReadGlParameter::Busy zurück, wenn das Auslesen im Gange ist
+  /// Liefert ResultReadGlParameter::Fortschritt zurück, wenn das Auslesen im Gange ist und sich der Fortschritt verändert hat
   /// Liefert ResultReadGlParameter::Ok mit den Parametern zurück, wenn abgeschlossen.
   /// Falls "eval_neu_anmeldung" "Some" liefert, sollte hier nicht Error zurückgegeben werden.
   /// Falls vom Protokoll nicht unterstützt, dann Ok mit leerer Liste.
@@ -286,6 +594,29 @@ pub trait DdlProtokoll {
   fn read_gl_parameter(&mut self, _adr: u32) -> ResultReadGlParameter {
     ResultReadGlParameter::Error
   }
+  /// Liefert alle Veränderungen der Anwesenheit von Dekodern seit dem letzten Aufruf (z.B. MFX Ping).
+  /// Leer wenn vom Protokoll nicht unterstützt.
+  fn get_gl_anwesenheit(&mut self) -> Vec<GLAnwesenheit> {
+    vec![]
+  }
+  /// Liefert alle seit dem letzten Aufruf angefallenen Diagnosemeldungen (z.B. MFX RDS Aufzeichnung)
+  /// mit der SRCP Session ID an die sie gesendet werden sollen.
+  /// Leer wenn vom Protokoll nicht unterstützt.
+  fn get_diagnose_meldungen(&mut self) -> Vec<(u32, String)> {
+    vec![]
+  }
+  /// Eine SRCP Kommando Session wurde beendet, an diese Session gebundene Zustände
+  /// (z.B. MFX RDS Aufzeichnung) werden aufgehoben.
+  /// Default: Protokoll hat keine Session abhängigen Zustände.
+  /// # Arguments
+  /// * session_id - Session ID der beendeten Session
+  fn session_end(&mut self, _session_id: u32) {}
+  /// Liefert alle seit dem letzten Aufruf neu aufgetretenen Störungen von Protokollthreads
+  /// (z.B. DCC Prog Thread, MFX RDS Thread nicht mehr vorhanden), die allen SRCP Info Clients gemeldet werden.
+  /// Leer wenn keine Störung oder vom Protokoll nicht unterstützt.
+  fn get_stoerungen(&mut self) -> Vec<String> {
+    vec![]
+  }
   /// Dekoderkonfiguration (SM) Start
   /// # Arguments
   /// * smParameter : Optinal weiterer Protokollspezifischer Parameter
@@ -307,16 +638,27 @@ pub trait DdlProtokoll {
   fn sm_get_all_types(&self) -> Option<HashMap<String, usize>> {
     None
   }
+  /// Liefert true wenn der SM Type bei SET einen Text anstelle einer Zahl als Value erwartet.
+  /// # Arguments
+  /// * sm_type - SM Type aus "sm_get_all_types"
+  fn sm_is_text_type(&self, _sm_type: &str) -> bool {
+    false
+  }
 }
 
 /// Type für "SmReadWrite"
 #[derive(Clone, Debug)]
 pub enum SmReadWriteType {
   Read,
-  Write(u32),    //Value
-  Verify(u32),   //Value (Byte oder Bit gemäss sm_type)
-  ResultOk(u32), //Ergebnis Read, Write, Verify OK mit Value
-  ResultErr,     //Ergebnis Read, Write, Verify Fail
+  Write(u32),                //Value
+  Verify(u32),               //Value (Byte oder Bit gemäss sm_type)
+  WriteText(String),         //Value als Text (nur für Typen mit "sm_is_text_type")
+  ResultOk(u32),             //Ergebnis Read, Write, Verify OK mit Value
+  ResultErr,                 //Ergebnis Read, Write, Verify Fail
+  ResultInfo(u32), //Zwischenergebnis eines laufenden Auftrages mit Value, als INFO an alle SRCP Clients
+  ResultIdent(DekoderIdent), //Ergebnis Read Dekoderidentifikation
+  ResultText(String), //Ergebnis Read, Write mit Text
+  ResultTimeout,   //Auftrag wegen Timeout oder TERM abgebrochen
 }
 /// Parameter für SM Read/Write/Verify
 #[derive(Clone, Debug)]
@@ -335,8 +677,13 @@ pub struct SmReadWrite {
   pub val: SmReadWriteType,
   /// Session ID von der das Kommando kam um eine Antwort an diese zu senden.
   pub session_id: u32,
+  /// Eindeutige ID des Auftrages, wird unverändert in der Antwort zurück geliefert.
+  /// Damit werden verspätete Antworten (Client hat bereits Timeout erhalten) erkannt.
+  pub auftrag_id: u32,
   /// Oszi trigger?
   pub trigger: bool,
+  /// Nach Write auf dem Programmiergleis den Wert zurücklesen, Ok nur wenn der gelesene Wert stimmt
+  pub write_verify: bool,
 }
 
 /// Typen zu Verwaltung der Protokolle
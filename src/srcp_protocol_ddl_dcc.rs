@@ -1,16 +1,27 @@
 use std::{
   collections::HashMap,
-  sync::mpsc::{self, Receiver, Sender},
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc::{self, Receiver, Sender, TryRecvError},
+    Arc,
+  },
   thread,
   time::Duration,
 };
 
-use gpio_cdev::LineHandle;
-use log::{debug, info};
+use log::{debug, info, warn};
 
 use crate::{
-  srcp_dcc_prog::{DccCvTel, DccCvTelType, DccProgThread, DCC_SM_TYPE_CV, DCC_SM_TYPE_CVBIT},
-  srcp_protocol_ddl::{DdlProtokoll, DdlTel, GLDriveMode, SmReadWrite},
+  srcp_dcc_prog::{
+    DccCvTel, DccCvTelType, DccProgThread, ProgAckKonfig, DCC_SM_TYPE_CV, DCC_SM_TYPE_CVBIT,
+    DCC_SM_TYPE_CVSCAN,
+  },
+  srcp_dekoder_ident::SM_TYPE_IDENT,
+  srcp_protocol_ddl::{
+    begrenze_funk_anz, funktionen_maske, spi_dauer, DdlProtokoll, DdlTel, GLDriveMode,
+    GlInitParams, SmReadWrite, SmReadWriteType, TelOptions,
+  },
+  srcp_server_types::ThreadSender,
 };
 
 //SPI Baudrate für DCC/NMRA.
@@ -23,10 +34,15 @@ const SPI_BAUDRATE_NMRA: u32 = 68966;
 //Deshalb wird auch hier die doppelte Baudrate verwendet und dann wie folgt kodiert:
 //1: 0xFF, 0x00
 //0: 0xFF, 0xFF, 0x00, 0x00
-const SPI_BAUDRATE_NMRA_2: u32 = SPI_BAUDRATE_NMRA * 2;
+pub const SPI_BAUDRATE_NMRA_2: u32 = SPI_BAUDRATE_NMRA * 2;
 
 static DCC_BIT_1: &'static [u8] = &[0xFF, 0x00]; //1
 static DCC_BIT_0: &'static [u8] = &[0xFF, 0xFF, 0x00, 0x00]; //0
+/// RailCom Cutout Start nach dem Schlussbit (NMRA S-9.3.2: TCS 26 bis 32us): halbes Byte 1 = 29us, danach 0
+const RAILCOM_TCS: u8 = 0xF0;
+/// Dauer RailCom Cutout nach dem TCS Byte: 7 Bytes à 58us, Ende Cutout damit 464us nach dem Schlussbit
+/// (NMRA S-9.3.2: TCE 454 bis 488us).
+const RAILCOM_CUTOUT_DAUER: Duration = spi_dauer(7, SPI_BAUDRATE_NMRA_2);
 
 /// Max. erlaubte GL Kurz-Adresse (V1, 7 Bit)
 const MAX_DCC_GL_ADRESSE_KURZ: u32 = 127;
@@ -35,6 +51,14 @@ const MAX_DCC_GL_ADRESSE_LANG: u32 = 10239;
 /// Max. erlaubte GA Adresse gem. NMRA S-9.2.1 Dekoder mit 4*2 Ausgängen, 9 Bit für Dekoder Adresse, 2 Bit Adr auf Dekoder.
 /// Dekoderadresse 0x1FF mit Subadr 3 (alle Adr Bits = 1) ist reserviert für E-Stop, es bleiben also inkl. Adr 1 bis 2047
 const MAX_DCC_GA_ADRESSE: u32 = 2047;
+/// GA Broadcast Adresse (Dekoderadresse 0x1FF), 1. Byte 10AAAAAA
+const DCC_GA_BROADCAST_ADR: u8 = 0b10111111;
+/// GA Broadcast "Einfache Zubehördecoder", 2. Byte 1AAACDDD, AAA invertiert = 000, C = 0 für Ausgang aus
+const DCC_GA_BROADCAST_AUS: u8 = 0b10000000;
+/// GA Broadcast "Erweiterte Zubehördecoder", 2. Byte 0AAA0AA1, AAA invertiert = 000
+const DCC_GA_BROADCAST_ERWEITERT: u8 = 0b00000111;
+/// Signalbegriff 0 für "Erweiterte Zubehördecoder" ist absoluter Halt
+const DCC_GA_BEGRIFF_HALT: u8 = 0;
 /// Anzahl sync. Bits
 const ANZ_DCC_SYNC: usize = 16;
 /// Anzahl sync. Bits Prog Gleis
@@ -109,6 +133,11 @@ const DCC_PROG_KK_BIT: u8 = 0b00001000;
 //Grenzen für Speed Steps bis und mit diesem Wert
 const SPEED_STEP_4BIT: usize = 14;
 const SPEED_STEP_5BIT: usize = 29;
+/// SM Type Senden Rücksetzpakete an alle Dekoder: SET <bus> SM 0 RESET <anzahl>
+/// Nur auf dem Programmiergleis, auf dem Hauptgleis würden alle Dekoder zurückgesetzt.
+const SM_TYPE_RESET: &str = "RESET";
+/// Max. Anzahl Rücksetzpakete pro SET SM RESET (NMRA S-9.2.3 verlangt min. 3 vor Programmierbefehlen)
+const MAX_ANZ_RESET: u32 = 20;
 
 #[derive(Debug)]
 pub enum DccVersion {
@@ -136,19 +165,36 @@ pub struct DccProtokoll {
   /// Ist SM Mode auf diesem Protokoll aktiviert? Für was?
   sm_aktiv: ServiceMode,
   /// Channel für Aufträge an Prog Thread
-  tx_to_prog: Sender<SmReadWrite>,
+  tx_to_prog: ThreadSender<SmReadWrite>,
   /// Channel für Antworten von Prog Thread von SM Read/Write
   rx_from_prog_read_write_cv: Receiver<SmReadWrite>,
   /// Channel für Tel. Sendeaufträge vom Prog Thread
   rx_tel_from_prog: Receiver<DccCvTel>,
+  /// Abbruch aller Aufträge im Prog Thread verlangt (TERM SM)
+  prog_abbruch: Arc<AtomicBool>,
+  /// SM Telegramme werden über einen separaten Programmiergleis Ausgang gesendet
+  prog_separat: bool,
+  /// Anzahl noch zu sendender, mit SM RESET verlangter Rücksetzpakete
+  reset_anz: u32,
+  /// Direkt, ohne Prog Thread, erzeugte SM Antwort (RESET)
+  sm_antwort: Option<SmReadWrite>,
+  /// An den Prog Thread übergebener SM Auftrag, auf dessen Antwort gewartet wird
+  sm_auftrag_offen: Option<SmReadWrite>,
+  /// RailCom Cutout nach jedem Paket auf dem Hauptgleis
+  railcom: bool,
 }
 
 impl DccProtokoll {
   /// Neue Instanz erstellen
   /// # Arguments
   /// * version - V1 oder V2
-  /// * ack_line_handle - GPIO Handle über das der Programmier ACK Impuls eingelesen werden kann.
-  pub fn from(version: DccVersion, ack_line_handle: &'static LineHandle) -> DccProtokoll {
+  /// * prog_ack - Konfiguration der Erkennung des Programmier ACK Impulses.
+  ///              None: ohne Prog Thread, kein Servicemode möglich (Selbsttest ohne Hardware).
+  /// * prog_separat - true: SM Telegramme über separaten Programmiergleis Ausgang senden
+  /// * railcom - true: RailCom Cutout nach jedem Paket auf dem Hauptgleis
+  pub fn from(
+    version: DccVersion, prog_ack: Option<&ProgAckKonfig>, prog_separat: bool, railcom: bool,
+  ) -> DccProtokoll {
     //Channels zur Kommunikation mit Prog Thread
     //-> Aufträge zum Prog Thread
     let (tx_to_prog, rx_in_prog): (Sender<SmReadWrite>, Receiver<SmReadWrite>) = mpsc::channel();
@@ -160,29 +206,77 @@ impl DccProtokoll {
     //<- DCC Tel. Sendeaufträge vom Prog Thread
     let (tx_tel_from_prog, rx_tel_from_prog): (Sender<DccCvTel>, Receiver<DccCvTel>) =
       mpsc::channel();
+    //Abbruch Aufträge im Prog Thread
+    let prog_abbruch = Arc::new(AtomicBool::new(false));
+    let prog_abbruch_thread = prog_abbruch.clone();
     //DCC Programmier Servicemode Thread starten
-    thread::Builder::new()
-      .name("DCC Prog Thread".to_string())
-      .spawn(move || {
-        DccProgThread::new(
-          rx_in_prog,
-          tx_from_prog_read_write_cv,
-          tx_tel_from_prog,
-          ack_line_handle,
-        )
-        .execute()
-      })
-      .unwrap();
+    if let Some(prog_ack) = prog_ack {
+      let prog_ack = prog_ack
+        .open()
+        .expect("DCC Prog ACK Erkennung konnte nicht geöffnet werden");
+      thread::Builder::new()
+        .name("DCC Prog Thread".to_string())
+        .spawn(move || {
+          DccProgThread::new(
+            rx_in_prog,
+            ThreadSender::new(tx_from_prog_read_write_cv, "DCC Prog Thread Antworten"),
+            ThreadSender::new(tx_tel_from_prog, "DCC Prog Thread Telegramme"),
+            prog_ack,
+            prog_abbruch_thread,
+          )
+          .execute()
+        })
+        .unwrap();
+    }
     DccProtokoll {
       version,
       old_drive_mode: [GLDriveMode::Vorwaerts; MAX_DCC_GL_ADRESSE_LANG as usize + 1],
       old_funktionen: [0; MAX_DCC_GL_ADRESSE_LANG as usize + 1],
       funk_anz: [0; MAX_DCC_GL_ADRESSE_LANG as usize + 1],
       sm_aktiv: ServiceMode::None,
-      tx_to_prog,
+      tx_to_prog: ThreadSender::new(tx_to_prog, "DCC Prog Thread"),
       rx_from_prog_read_write_cv,
       rx_tel_from_prog,
+      prog_abbruch,
+      prog_separat,
+      reset_anz: 0,
+      sm_antwort: None,
+      sm_auftrag_offen: None,
+      railcom,
+    }
+  }
+
+  /// Liefert das DCC Rücksetz Telegramm für das Programmiergleis.
+  fn get_reset_tel(&self) -> DdlTel {
+    //DCC Rücksetz Telegramm: 1111111111111111 0 00000000 0 00000000 0 00000000 1
+    let mut ddl_reset_tel = DdlTel::new(
+      0,
+      SPI_BAUDRATE_NMRA_2,
+      Duration::ZERO, //Nicht notwendig für Rücksetz Tel.
+      false,
+      DCC_MAX_LEN_BASIS + 2 * DCC_MAX_LEN_PRO_BYTE,
+      1,
+      false,
+    );
+    self.add_sync(&mut ddl_reset_tel, false);
+    let mut xor: u8 = 0;
+    self.add_byte(&mut ddl_reset_tel, 0b00000000, &mut xor, false);
+    self.add_byte(&mut ddl_reset_tel, 0b00000000, &mut xor, false);
+    //Checksumme ergänzen
+    self.add_xor(&mut ddl_reset_tel, xor, true);
+    ddl_reset_tel
+  }
+
+  /// Liefert die mit SM RESET verlangten Rücksetzpakete als ein Telegramm mit entsprechend vielen
+  /// Wiederholungen. None wenn keine verlangt sind.
+  fn take_reset_tel(&mut self) -> Option<DdlTel> {
+    if self.reset_anz == 0 {
+      return None;
     }
+    let mut ddl_reset_tel = self.get_reset_tel();
+    ddl_reset_tel.tel_wiederholungen = self.reset_anz as usize;
+    self.reset_anz = 0;
+    Some(ddl_reset_tel)
   }
 
   /// Fügt Start Syncmuster 16*1 mit abschliessendem 0 zum letzten in ddl_tel enthaltenen Telegramm
@@ -195,17 +289,9 @@ impl DccProtokoll {
     } else {
       ANZ_DCC_SYNC
     } {
-      ddl_tel
-        .daten
-        .last_mut()
-        .unwrap()
-        .extend_from_slice(DCC_BIT_1);
+      ddl_tel.payload_mut().extend_from_slice(DCC_BIT_1);
     }
-    ddl_tel
-      .daten
-      .last_mut()
-      .unwrap()
-      .extend_from_slice(DCC_BIT_0);
+    ddl_tel.payload_mut().extend_from_slice(DCC_BIT_0);
   }
 
   /// Fügt ein Byte zum DDL DCC Telegramm hinzu und aktualisiert die Prüfsumme (exor)
@@ -218,9 +304,7 @@ impl DccProtokoll {
     for i in (0..8).rev() {
       //Geht von 7 bis 0
       ddl_tel
-        .daten
-        .last_mut()
-        .unwrap()
+        .payload_mut()
         .extend_from_slice(if (value & (1 << i)) == 0 {
           DCC_BIT_0
         } else {
@@ -230,9 +314,7 @@ impl DccProtokoll {
     *xor ^= value;
     //Byteendemarke, normalerweise 0, bei Telegrammende 1
     ddl_tel
-      .daten
-      .last_mut()
-      .unwrap()
+      .payload_mut()
       .extend_from_slice(if endbit { DCC_BIT_1 } else { DCC_BIT_0 });
   }
   /// Fügt die Addresse (1 Byte bis 127, 2 Byte wenn grösser) mit abschliessendem 0 zum letzten in
@@ -261,18 +343,22 @@ impl DccProtokoll {
   ///DCC Telegramm abschliessen.
   /// - xor
   /// - Doppelte 1 als Abschluss um sicher eine 1 gültig (letzte Flanke) zu haben
+  /// - Mit RailCom auf dem Hauptgleis statt dessen der Cutout, dessen TCS Byte die letzte Flanke liefert.
+  ///   Der Cutout folgt damit immer nur auf ein vollständiges DCC Paket, nie innerhalb eines Telegrammes
+  ///   und nie nach MM, MFX oder SX Telegrammen.
   /// # Arguments
   /// * ddl_tel - Telegramm, bei dem die XOR Checksumme ergänzt werden soll
   /// * xor - xor Prüfsumme
-  fn add_xor(&self, ddl_tel: &mut DdlTel, mut xor: u8) {
+  /// * prog_gleis - true: Paket für Prog. Gleis, nie mit RailCom Cutout
+  fn add_xor(&self, ddl_tel: &mut DdlTel, mut xor: u8, prog_gleis: bool) {
     //Checksumme ergänzen
     self.add_byte(ddl_tel, xor, &mut xor, true);
-    //Und nochmals ein 1 Bit damit noch ein korrekter Abschluss (letzte Flanke) da ist
-    ddl_tel
-      .daten
-      .last_mut()
-      .unwrap()
-      .extend_from_slice(DCC_BIT_1);
+    if self.railcom && !prog_gleis {
+      ddl_tel.add_cutout(RAILCOM_TCS, RAILCOM_CUTOUT_DAUER);
+    } else {
+      //Und nochmals ein 1 Bit damit noch ein korrekter Abschluss (letzte Flanke) da ist
+      ddl_tel.payload_mut().extend_from_slice(DCC_BIT_1);
+    }
   }
   /// Telegramm für 8 Funktionen aus dem Bereich F13 bis F68 erzeugen und hinzufügen wenn sich
   /// eine Funktion der Gruppe geändert hat oder Refresh verlangt wird.
@@ -291,15 +377,13 @@ impl DccProtokoll {
     //Auf Veränderungen prüfen
     if (((self.old_funktionen[adr as usize] ^ funktionen) & mask) != 0) || refresh {
       //Worst case Länge: 2 Bytes Adresse + 2 Nutzbytes
-      ddl_tel.daten.push(Vec::with_capacity(
-        DCC_MAX_LEN_BASIS + 4 * DCC_MAX_LEN_PRO_BYTE,
-      ));
+      ddl_tel.neues_tel(DCC_MAX_LEN_BASIS + 4 * DCC_MAX_LEN_PRO_BYTE);
       self.add_sync(ddl_tel, false);
       let mut xor = self.add_adr(ddl_tel, adr);
       self.add_byte(ddl_tel, ddl_cmd, &mut xor, false);
       let f = <u64 as TryInto<u8>>::try_into((funktionen & mask) >> shift).unwrap();
       self.add_byte(ddl_tel, f, &mut xor, false);
-      self.add_xor(ddl_tel, xor);
+      self.add_xor(ddl_tel, xor, false);
     }
   }
   /// Liefert ein DCC CV Read/Write Telegramm.
@@ -313,7 +397,7 @@ impl DccProtokoll {
     //Refresh = 1 mal senden.
     //Die für Write 2 oder 5 mal OHNE JEDE Pause gesendet werden muss, kann nicht mit Wiederholungen gearbeitet werden,
     //da dabei immer eine kurze Pause entsteht. Es werden alle Daten kopiert.
-    let mut tel = if matches!(self.sm_aktiv, ServiceMode::GL) {self.get_gl_new_tel(cvtel.adr, true, cvtel.trigger)} else {self.get_ga_new_tel(cvtel.adr, cvtel.trigger)};
+    let mut tel = if matches!(self.sm_aktiv, ServiceMode::GL) {self.get_gl_new_tel(cvtel.adr, TelOptions::refresh(cvtel.trigger))} else {self.get_ga_new_tel(cvtel.adr, TelOptions::neu(cvtel.trigger))};
     //Telegramme müssen direkt aufeinander folgen
     tel.delay = Duration::ZERO;
     match cvtel.dcc_cv_type {
@@ -355,16 +439,9 @@ impl DccProtokoll {
           false,
         );
         //XOR
-        self.add_xor(&mut tel, xor);
+        self.add_xor(&mut tel, xor, !haupt_gleis);
         //CV Write Telegramme auf Prog Gleis MÜSSEN 5 mal, bei Hauptgleis 2 mal hintereinander gesendet werden
-        let daten_1_tel = tel.daten.last().unwrap().clone();
-        for _ in 1..if haupt_gleis { 2 } else { 5 } {
-          tel
-            .daten
-            .last_mut()
-            .unwrap()
-            .extend_from_slice(daten_1_tel.as_slice());
-        }
+        tel.add_repeat(if haupt_gleis { 2 } else { 5 }, Duration::ZERO);
       }
       DccCvTelType::VerifyByte(val) | DccCvTelType::WriteByte(val, _) => {
         //Hauptgleisprog. nur bei Write ohne Prog Gleis, alles andere -> Prog Gleis
@@ -401,16 +478,9 @@ impl DccProtokoll {
         //Value
         self.add_byte(&mut tel, val, &mut xor, false);
         //XOR
-        self.add_xor(&mut tel, xor);
+        self.add_xor(&mut tel, xor, !haupt_gleis);
         //CV Write Telegramme auf Prog Gleis MÜSSEN 5 mal, bei Hauptgleis 2 mal hintereinander gesendet werden
-        let daten_1_tel = tel.daten.last().unwrap().clone();
-        for _ in 1..if haupt_gleis { 2 } else { 5 } {
-          tel
-            .daten
-            .last_mut()
-            .unwrap()
-            .extend_from_slice(daten_1_tel.as_slice());
-        }
+        tel.add_repeat(if haupt_gleis { 2 } else { 5 }, Duration::ZERO);
       }
     }
     tel
@@ -476,15 +546,10 @@ impl DdlProtokoll for DccProtokoll {
   /// Liefert immer None, kein GL Init Tel. notwendig
   /// # Arguments
   /// * adr - Adresse der Lok
-  /// * uid - nicht verwendet
-  /// * funk_anz - Anzahl tatsächlich verwendete Funktionen. Kann, je nach Protokoll, dazu
-  ///              verwendet werden, nur Telegramme der verwendeten Funktionen zu senden.
-  /// * power - nicht verwendet
-  /// * trigger - Oszi Trigger bei Ausgabe?
-  fn init_gl(
-    &mut self, adr: u32, _uid: Option<u32>, funk_anz: usize, _power: bool, _trigger: bool,
-  ) -> Option<DdlTel> {
-    self.funk_anz[adr as usize] = funk_anz;
+  /// * params - Nur Anzahl tatsächlich verwendete Funktionen verwendet
+  fn init_gl(&mut self, adr: u32, params: &GlInitParams) -> Option<DdlTel> {
+    self.funk_anz[adr as usize] =
+      begrenze_funk_anz("DCC", adr, params.funk_anz, self.get_gl_anz_f());
     None
   }
   /// Liefert die max. erlaubte Lokadresse
@@ -517,18 +582,16 @@ impl DdlProtokoll for DccProtokoll {
   /// Als Initiale Kapazitäte wird von einem 4 Byte (lange Adresse plus 2 Nutzbytes) DCC Telegramm ausgegangen.
   /// # Arguments
   /// * adr - Adresse der Lok, keine Verwendunbg, nur Debug Support
-  /// * refresh - Wenn true: Aufruf aus Refres Cycle, einmalige Telegramm Versendung,
-  ///             Wenn false: Aufruf wegen neuem Lokkommando, mehrmaliges Versenden
-  /// * trigger - Oszi Trigger bei Ausgabe?
-  fn get_gl_new_tel(&mut self, adr: u32, refresh: bool, trigger: bool) -> DdlTel {
+  /// * optionen - Refresh und Oszi Trigger, siehe "TelOptions"
+  fn get_gl_new_tel(&mut self, adr: u32, optionen: TelOptions) -> DdlTel {
     DdlTel::new(
       adr,
       SPI_BAUDRATE_NMRA_2,
       DCC_DELAY_GLEICHE_ADR,
       false,
       DCC_MAX_LEN_BASIS + 4 * DCC_MAX_LEN_PRO_BYTE,
-      if refresh { 1 } else { 2 }, //Neue Lokkommandos werden immer 2-fach gesendet
-      trigger,
+      if optionen.refresh { 1 } else { 2 }, //Neue Lokkommandos werden immer 2-fach gesendet
+      optionen.trigger,
     )
   }
 
@@ -550,6 +613,8 @@ impl DdlProtokoll for DccProtokoll {
     assert!(adr <= self.get_gl_max_adr(), "DCC GL Adresse zu gross");
     //Gültigkeit speed prüfen
     assert!(speed <= speed_steps, "DCC Speed > Speed Steps");
+    //Nur Funktionen gemäss INIT
+    let funktionen = funktionen & funktionen_maske(self.funk_anz[adr as usize]);
     //Drivemode für Richtung, wenn Nothalt dann der letzte
     let drive_mode_used: GLDriveMode = if drive_mode == GLDriveMode::Nothalt {
       self.old_drive_mode[adr as usize]
@@ -615,7 +680,7 @@ impl DdlProtokoll for DccProtokoll {
       }
       self.add_byte(ddl_tel, speed_byte, &mut xor, false);
     }
-    self.add_xor(ddl_tel, xor);
+    self.add_xor(ddl_tel, xor, false);
 
     //Nur wenn notwendig: F0..F4 Telegramm
     //Je nach Speedsteps muss F0 hier berücksichtigt werden oder nicht
@@ -625,9 +690,7 @@ impl DdlProtokoll for DccProtokoll {
       //Und nun noch F0 bis F5. Da 5ms Pause zwischen 2 DCC Telegrammen an selbe Adresse notwendig ist,
       //als 2. unabhängigs Telegramm.
       //Worst case Länge: 2 Bytes Adresse + 1 Nutzbyte
-      ddl_tel.daten.push(Vec::with_capacity(
-        DCC_MAX_LEN_BASIS + 3 * DCC_MAX_LEN_PRO_BYTE,
-      ));
+      ddl_tel.neues_tel(DCC_MAX_LEN_BASIS + 3 * DCC_MAX_LEN_PRO_BYTE);
       self.add_sync(ddl_tel, false);
       //Addresse in 1 oder 2 Bytes
       xor = self.add_adr(ddl_tel, adr);
@@ -640,7 +703,7 @@ impl DdlProtokoll for DccProtokoll {
         f0_f4_byte |= 0b00010000;
       }
       self.add_byte(ddl_tel, f0_f4_byte, &mut xor, false);
-      self.add_xor(ddl_tel, xor);
+      self.add_xor(ddl_tel, xor, false);
     }
     //F0..F4 übernehmen
     self.old_funktionen[adr as usize] &= !BIT_MASK_F0_F4;
@@ -656,36 +719,34 @@ impl DdlProtokoll for DccProtokoll {
   /// * funktionen - Die gewünschten Funktionen, berücksichtigt ab "get_Anz_F_Basis"
   /// * ddl_tel - DDL Telegramm, bei dem des neue Telegramm hinzugefügt werden soll.
   fn get_gl_zusatz_tel(&mut self, adr: u32, refresh: bool, funktionen: u64, ddl_tel: &mut DdlTel) {
+    //Nur Funktionen gemäss INIT
+    let funktionen = funktionen & funktionen_maske(self.funk_anz[adr as usize]);
     let funk_anz = self.funk_anz[adr as usize];
     //F5..F8 auf Veränderungen prüfen
     if ((((self.old_funktionen[adr as usize] ^ funktionen) & BIT_MASK_F5_F8) != 0) || refresh)
       && (funk_anz > 5)
     {
       //Worst case Länge: 2 Bytes Adresse + 1 Nutzbyte
-      ddl_tel.daten.push(Vec::with_capacity(
-        DCC_MAX_LEN_BASIS + 3 * DCC_MAX_LEN_PRO_BYTE,
-      ));
+      ddl_tel.neues_tel(DCC_MAX_LEN_BASIS + 3 * DCC_MAX_LEN_PRO_BYTE);
       self.add_sync(ddl_tel, false);
       let mut xor = self.add_adr(ddl_tel, adr);
       let mut f5_f8_byte = DCC_INST_F5_F8;
       f5_f8_byte |= <u64 as TryInto<u8>>::try_into((funktionen & BIT_MASK_F5_F8) >> 5).unwrap();
       self.add_byte(ddl_tel, f5_f8_byte, &mut xor, false);
-      self.add_xor(ddl_tel, xor);
+      self.add_xor(ddl_tel, xor, false);
     }
     //F9..F12 auf Veränderungen prüfen
     if ((((self.old_funktionen[adr as usize] ^ funktionen) & BIT_MASK_F9_F12) != 0) || refresh)
       && (funk_anz > 9)
     {
       //Worst case Länge: 2 Bytes Adresse + 1 Nutzbyte
-      ddl_tel.daten.push(Vec::with_capacity(
-        DCC_MAX_LEN_BASIS + 3 * DCC_MAX_LEN_PRO_BYTE,
-      ));
+      ddl_tel.neues_tel(DCC_MAX_LEN_BASIS + 3 * DCC_MAX_LEN_PRO_BYTE);
       self.add_sync(ddl_tel, false);
       let mut xor = self.add_adr(ddl_tel, adr);
       let mut f9_f12_byte = DCC_INST_F9_F12;
       f9_f12_byte |= <u64 as TryInto<u8>>::try_into((funktionen & BIT_MASK_F9_F12) >> 9).unwrap();
       self.add_byte(ddl_tel, f9_f12_byte, &mut xor, false);
-      self.add_xor(ddl_tel, xor);
+      self.add_xor(ddl_tel, xor, false);
     }
     if funk_anz > 13 {
       self.add_f13_f68(
@@ -771,8 +832,8 @@ impl DdlProtokoll for DccProtokoll {
   /// Liefert ein leeres GA Telegramm zur Verwendung in "get_ga_tel".
   /// # Arguments
   /// * adr - Adresse GA, keine Verwendunbg, nur Debug Support
-  /// * trigger - Oszi Trigger?
-  fn get_ga_new_tel(&self, adr: u32, trigger: bool) -> DdlTel {
+  /// * optionen - Oszi Trigger, siehe "TelOptions"
+  fn get_ga_new_tel(&self, adr: u32, optionen: TelOptions) -> DdlTel {
     DdlTel::new(
       adr,
       SPI_BAUDRATE_NMRA_2,
@@ -780,13 +841,15 @@ impl DdlProtokoll for DccProtokoll {
       false,
       DCC_MAX_LEN_BASIS + 2 * DCC_MAX_LEN_PRO_BYTE,
       2, //GA wird immer nur bei Bedarf gesendet, kein Refresh. Deshalb immer 2-fach senden
-      trigger,
+      optionen.trigger,
     )
   }
   /// Erzeugt ein GA Telegramm
   /// # Arguments
   /// Liefert true zurück, wenn Timeout zu r automatischen Abschaltung durch Protokoll / Dekoder übernommen wird.
-  /// Dies ist hier bei V2 (= erweiterte Dekoder) der Fall.
+  /// Dies ist hier nie der Fall: auch bei V2 (= erweiterte Dekoder), wo die Ausschaltzeit im Telegramm mitgegeben
+  /// wird, sendet die GA Verwaltung nach Ablauf ein explizites Ausschalttelegramm (V1: C=0), da sich nicht alle
+  /// Dekoder an die Ausschaltzeit halten.
   /// * adr - GA Adresse
   /// * port - Port auf dem Schaltdekoder 0 / 1
   /// * value - Gewünschter Zustand des Port Ein/Aus (0/1) oder Begriff (z.B. Erweiterte DCC Dekoder)
@@ -794,10 +857,10 @@ impl DdlProtokoll for DccProtokoll {
   ///             None = kein Timeout, dauerhaft schalten. 
   ///             Duration::ZERO = Port ignorieren, Value ist der zu sendende Begriff (z.B. Erweiterte Funktionsdekoder NMRA/DCC Signalbegriff)
   /// * ddl_tel - DDL Telegramm, bei dem des neue Telegramm hinzugefügt werden soll.
-  fn get_ga_tel(&self, adr: u32, port: usize, value: usize, timeout: Option<Duration>, ddl_tel: &mut DdlTel) -> bool {
-    let mut result = false;
+  fn get_ga_tel(&mut self, adr: u32, port: usize, value: usize, timeout: Option<Duration>, ddl_tel: &mut DdlTel) -> bool {
     self.add_sync(ddl_tel, false);
-    let mut xor = self.add_ga_adr(ddl_tel, adr, port, value == 0);
+    //V1: C=1 Einschalten, C=0 Ausschalten
+    let mut xor = self.add_ga_adr(ddl_tel, adr, port, value != 0);
     match self.version {
       DccVersion::V1 => {}, //Nichts
       DccVersion::V2 => {
@@ -825,11 +888,46 @@ impl DdlProtokoll for DccProtokoll {
           //3. Byte mit Port und Dauerhaft schalten oder ausschalten (wenn value=0) ergänzen
           self.add_byte(ddl_tel, ((port & 0b00000001) << 7 | if value == 0 {0} else {0b01111111}).try_into().unwrap(), &mut xor, false);
         }
-        result = true;
       }
     }
-    self.add_xor(ddl_tel, xor);
-    return result;
+    self.add_xor(ddl_tel, xor, false);
+    //Ausschalttelegramm nach Ablauf immer durch GA Verwaltung
+    return false;
+  }
+
+  /// GA Broadcast wird unterstützt
+  fn ga_broadcast(&self) -> bool {
+    true
+  }
+
+  /// Erzeugt die GA Broadcast Telegramme an alle Zubehördekoder:
+  /// V1 "Einfache Zubehördecoder": für alle 8 Ausgänge ein Telegramm Ausgang aus
+  /// V2 "Erweiterte Zubehördecoder": Signalbegriff absoluter Halt
+  /// # Arguments
+  /// * ddl_tel - DDL Telegramm aus "get_ga_new_tel", bei dem die neuen Telegramme hinzugefügt werden sollen.
+  fn get_ga_broadcast_off_tel(&mut self, ddl_tel: &mut DdlTel) {
+    match self.version {
+      DccVersion::V1 => {
+        for ausgang in 0..8 {
+          if ausgang > 0 {
+            ddl_tel.neues_tel(DCC_MAX_LEN_BASIS + 2 * DCC_MAX_LEN_PRO_BYTE);
+          }
+          self.add_sync(ddl_tel, false);
+          let mut xor: u8 = 0;
+          self.add_byte(ddl_tel, DCC_GA_BROADCAST_ADR, &mut xor, false);
+          self.add_byte(ddl_tel, DCC_GA_BROADCAST_AUS | ausgang, &mut xor, false);
+          self.add_xor(ddl_tel, xor, false);
+        }
+      }
+      DccVersion::V2 => {
+        self.add_sync(ddl_tel, false);
+        let mut xor: u8 = 0;
+        self.add_byte(ddl_tel, DCC_GA_BROADCAST_ADR, &mut xor, false);
+        self.add_byte(ddl_tel, DCC_GA_BROADCAST_ERWEITERT, &mut xor, false);
+        self.add_byte(ddl_tel, DCC_GA_BEGRIFF_HALT, &mut xor, false);
+        self.add_xor(ddl_tel, xor, false);
+      }
+    }
   }
 
   /// Liefert das Idle Telegramm dieses Protokolles
@@ -850,7 +948,7 @@ impl DdlProtokoll for DccProtokoll {
     self.add_byte(&mut ddl_idle_tel, 0b11111111, &mut xor, false);
     self.add_byte(&mut ddl_idle_tel, 0b00000000, &mut xor, false);
     //Checksumme ergänzen
-    self.add_xor(&mut ddl_idle_tel, xor);
+    self.add_xor(&mut ddl_idle_tel, xor, false);
     Some(ddl_idle_tel)
   }
 
@@ -869,21 +967,68 @@ impl DdlProtokoll for DccProtokoll {
   }
 
   /// Dekoderkonfiguration (SM) Ende
+  /// Alle noch laufenden oder wartenden Aufträge im Prog Thread werden abgebrochen.
   fn sm_term(&mut self) {
     self.sm_aktiv = ServiceMode::None;
+    self.prog_abbruch.store(true, Ordering::Relaxed);
   }
 
   /// Dekoderkonfiguration (SM) Read/Write Value.
   /// # Arguments
   /// * sm_para - Alle notwndigen Paramater für SM Read/Write
   fn sm_read_write(&mut self, sm_para: &SmReadWrite) {
-    self.tx_to_prog.send(sm_para.clone()).unwrap();
+    if sm_para.sm_type == SM_TYPE_RESET {
+      //Rücksetzpakete werden direkt hier erzeugt, kein Zugriff über Prog Thread
+      let mut antwort = sm_para.clone();
+      antwort.val = match sm_para.val {
+        SmReadWriteType::Write(_) if !sm_para.prog_gleis => {
+          warn!("DDL DCC Rücksetzpakete nur auf Programmiergleis, abgelehnt");
+          SmReadWriteType::ResultErr
+        }
+        SmReadWriteType::Write(anz) if (1..=MAX_ANZ_RESET).contains(&anz) => {
+          info!("DDL DCC {} Rücksetzpakete verlangt", anz);
+          self.reset_anz = anz;
+          SmReadWriteType::ResultOk(anz)
+        }
+        _ => SmReadWriteType::ResultErr,
+      };
+      self.sm_antwort = Some(antwort);
+    } else if self.tx_to_prog.send(sm_para.clone()) {
+      self.sm_auftrag_offen = Some(sm_para.clone());
+    } else {
+      //Ohne Prog Thread direkt mit Fehler antworten, nicht auf Timeout warten
+      let mut antwort = sm_para.clone();
+      antwort.val = SmReadWriteType::ResultErr;
+      self.sm_antwort = Some(antwort);
+    }
   }
 
   /// Liefert die Antwort sm_read_write zurück.
   /// None wenn keine Antwort verfügbar.
   fn sm_get_answer(&mut self) -> Option<SmReadWrite> {
-    self.rx_from_prog_read_write_cv.try_recv().ok()
+    if self.sm_antwort.is_some() {
+      return self.sm_antwort.take();
+    }
+    match self.rx_from_prog_read_write_cv.try_recv() {
+      Ok(antwort) => {
+        self.sm_auftrag_offen = None;
+        Some(antwort)
+      }
+      Err(TryRecvError::Empty) => None,
+      Err(TryRecvError::Disconnected) => {
+        self.tx_to_prog.set_gestoert();
+        //Ein bereits übergebener Auftrag wird nie mehr beantwortet, nicht auf Timeout warten
+        self.sm_auftrag_offen.take().map(|mut antwort| {
+          antwort.val = SmReadWriteType::ResultErr;
+          antwort
+        })
+      }
+    }
+  }
+
+  /// Liefert die Störung des Prog Threads, wenn dieser nicht mehr vorhanden ist.
+  fn get_stoerungen(&mut self) -> Vec<String> {
+    self.tx_to_prog.take_stoerung().into_iter().collect()
   }
 
   /// Liefert alle in "sm_read" und "sm_write" unterstützten Typen mit der Anzahl erwarteter Parameter
@@ -895,18 +1040,32 @@ impl DdlProtokoll for DccProtokoll {
     //2 Parameter bei CVBIT: CVNr, BitNr
     result.insert(DCC_SM_TYPE_CV.to_string(), 1);
     result.insert(DCC_SM_TYPE_CVBIT.to_string(), 2);
+    //2 Parameter bei CVSCAN: erste und letzte CV (beide 0: CV1 bis CV256)
+    result.insert(DCC_SM_TYPE_CVSCAN.to_string(), 2);
+    //Keine Parameter bei IDENT
+    result.insert(SM_TYPE_IDENT.to_string(), 0);
+    //Keine Parameter bei RESET, Value ist die Anzahl Rücksetzpakete, Dekoderadresse wird ignoriert
+    result.insert(SM_TYPE_RESET.to_string(), 0);
     Some(result)
   }
 
   /// Liefert zusätzliche, Protokoll spezifische Telegramme (z.B. bei MFX die UID & Neuanmeldezähler der Zentrale)
   /// Liefert None, wenn es nichts zur versenden gibt
-  /// Hier, wenn vorhanden, werden die CV Read/Write Telegramme erzeugt, wenn vom DCC Prog Thread verlangt.
+  /// Hier, wenn vorhanden, werden die mit SM RESET verlangten Rücksetzpakete und die CV Read/Write
+  /// Telegramme erzeugt, wenn vom DCC Prog Thread verlangt.
   /// # Arguments
   /// * power : true wenn Power (Booster) ein, sonst false.
   ///           Normalerweise werden Telegramme nur bei Power On gesendet.
   ///           Ausnahme: SM DCC auf Prog. Gleis.
   ///           Hier nicht verwendet, CV Telegramme werden auf Prog. und Hauptgleis verwendet.
+  ///           Mit separatem Programmiergleis Ausgang siehe "get_prog_gleis_telegramm".
   fn get_protokoll_telegrammme(&mut self, _power: bool) -> Option<DdlTel> {
+    if self.prog_separat {
+      return None;
+    }
+    if let Some(ddl_reset_tel) = self.take_reset_tel() {
+      return Some(ddl_reset_tel);
+    }
     let tel_from_prog = self.rx_tel_from_prog.try_recv();
     if let Ok(tel) = tel_from_prog {
       Some(self.get_cv_tel(&tel))
@@ -918,29 +1077,32 @@ impl DdlProtokoll for DccProtokoll {
   /// Return None wenn kein Idle Telegramm für Power Off vorhanden ist
   /// Wird hier für senden Rücksetzpaket bei aktiviertem SM verwendet.
   /// Damit wird bei Power Off im Leerlauf dauernd das Rücksetzpaket gesendet.
+  /// Mit separatem Programmiergleis Ausgang wird das Rücksetzpaket nur dort gesendet.
   fn get_idle_tel_power_off(&self) -> Option<DdlTel> {
-    if matches!(self.sm_aktiv, ServiceMode::None) {
+    if matches!(self.sm_aktiv, ServiceMode::None) || self.prog_separat {
       //Nichts zu senden wenn kein SM aktiv ist
       None
     }
     else {
-      //DCC Rücksetz Telegramm: 1111111111111111 0 00000000 0 00000000 0 00000000 1
-      let mut ddl_reset_tel = DdlTel::new(
-        0,
-        SPI_BAUDRATE_NMRA_2,
-        Duration::ZERO, //Nicht notwendig für Rücksetz Tel.
-        false,
-        DCC_MAX_LEN_BASIS + 2 * DCC_MAX_LEN_PRO_BYTE,
-        1,
-        false,
-      );
-      self.add_sync(&mut ddl_reset_tel, false);
-      let mut xor: u8 = 0;
-      self.add_byte(&mut ddl_reset_tel, 0b00000000, &mut xor, false);
-      self.add_byte(&mut ddl_reset_tel, 0b00000000, &mut xor, false);
-      //Checksumme ergänzen
-      self.add_xor(&mut ddl_reset_tel, xor);
+      Some(self.get_reset_tel())
+    }
+  }
+  /// Liefert, wenn ein separater Programmiergleis Ausgang verwendet wird, die mit SM RESET verlangten
+  /// Rücksetzpakete, die CV Read/Write Telegramme des DCC Prog Threads und im Leerlauf bei aktiviertem
+  /// SM das Rücksetzpaket.
+  /// Liefert None, wenn es nichts zur versenden gibt.
+  fn get_prog_gleis_telegramm(&mut self) -> Option<DdlTel> {
+    if !self.prog_separat {
+      return None;
+    }
+    if let Some(ddl_reset_tel) = self.take_reset_tel() {
       Some(ddl_reset_tel)
+    } else if let Ok(tel) = self.rx_tel_from_prog.try_recv() {
+      Some(self.get_cv_tel(&tel))
+    } else if matches!(self.sm_aktiv, ServiceMode::None) {
+      None
+    } else {
+      Some(self.get_reset_tel())
     }
   }
 }
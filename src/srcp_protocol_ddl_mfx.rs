@@ -1,20 +1,31 @@
 use std::{
-  collections::HashMap,
+  collections::{HashMap, HashSet},
   fs,
   net::UdpSocket,
-  sync::mpsc::{self, Receiver, Sender},
-  thread,
+  sync::{
+    atomic::{AtomicBool, AtomicU8, Ordering},
+    mpsc::{self, Receiver, Sender, TryRecvError},
+    Arc,
+  },
+  thread::{self, JoinHandle},
   time::{Duration, Instant},
 };
 
 use log::{info, warn};
 
 use crate::{
-  srcp_mfx_rds::{MfxCvTel, MfxCvTelType, MfxRdsFeedbackThread, MfxRdsJob},
+  srcp_dekoder_ident::SM_TYPE_IDENT,
+  srcp_mfx_lok_cache::{MfxLokCache, MfxLokDaten},
+  srcp_mfx_rds::{
+    MfxCvTel, MfxCvTelType, MfxRdsCapture, MfxRdsFeedbackThread, MfxRdsJob, MfxRdsKanaele,
+    MfxRdsKonfig, SM_TYPE_BENUTZER, SM_TYPE_CAMFX32, SM_TYPE_LOKNAME,
+  },
   srcp_protocol_ddl::{
-    DdlProtokoll, DdlTel, DdlTelRx, GLDriveMode, ResultNeuAnmeldung, ResultReadGlParameter,
-    SmReadWrite,
+    begrenze_funk_anz, funktionen_maske, spi_dauer, DdlProtokoll, DdlTel, DdlTelRx, GLAnwesenheit,
+    GLDriveMode, GlInitParams, ResultNeuAnmeldung, ResultReadGlParameter, SmReadWrite,
+    SmReadWriteType, TelOptions,
   },
+  srcp_server_types::ThreadSender,
 };
 
 //SPI Baudrate für MFX.
@@ -44,13 +55,18 @@ const SPI_BAUDRATE_MFX: u32 = 80000;
 // - Total        : 48 Bit
 //Und Glück gehabt, wir sind mit mal 2 gerade auf 96 gekommen...
 const SPI_BYTES_PRO_BIT: usize = 2;
-const SPI_BAUDRATE_MFX_2: u32 = SPI_BAUDRATE_MFX * (SPI_BYTES_PRO_BIT as u32);
+pub const SPI_BAUDRATE_MFX_2: u32 = SPI_BAUDRATE_MFX * (SPI_BYTES_PRO_BIT as u32);
+
+/// Max. Anzahl Funktionen (F0-F63). MFX kann total 128, die Funktionen werden aber überall in einem u64 verwaltet.
+pub const MFX_ANZ_F_MAX: usize = 64;
 
 /// Max. erlaubte GL Adresse (14 Bit)
 const MAX_MFX_GL_ADRESSE: u32 = 2_u32.pow(14) - 1;
 
 /// Pause vor und nach MFX Paket (Erfahrung aus MFX im orginal srcpd)
 const MFX_STARTSTOP_0_BIT: usize = 4;
+const MFX_PAUSE_STARTSTOP: Duration =
+  spi_dauer(MFX_STARTSTOP_0_BIT * SPI_BYTES_PRO_BIT, SPI_BAUDRATE_MFX_2);
 /// Max. Länge MFX Paket, mit SPI_BAUDRATE_MFX_2 braucht ein Bit immer 2 Byte
 /// Entweder
 ///   Adresse 14 Bit (18 Bit)
@@ -136,16 +152,41 @@ const MFX_CMD_CV_WRITE: MfxBits = (0b111001, 6);
 const MFX_CMD_SEARCH_NEW: MfxBits = (0b111010, 6);
 /// Kommando Konfiguration Schienenadresse
 const MFX_CMD_KONFIG_SID: MfxBits = (0b111011, 6);
+/// Kommando Ping (Existenzabfrage) eines angemeldeten Dekoders, Antwort mit 1 Bit Rückmeldung
+const MFX_CMD_PING: MfxBits = (0b111100, 6);
 /// Kommando UID und Neuanmeldezähler Zentrale
 const MFX_CMD_KONFIG_UID: MfxBits = (0b111101, 6);
 
 /// Intervall versenden UID Zentrale
 const INTERVALL_UID: Duration = Duration::from_millis(500);
 
+/// SM Type Zugriff Neuanmeldezähler Zentrale
+const SM_TYPE_REGCOUNT: &str = "REGCOUNT";
+/// SM Type Erzwingen Neuanmeldung aller Dekoder
+const SM_TYPE_REGNEW: &str = "REGNEW";
+/// SM Type Lokparameter eines Dekoders im Lokcache
+const SM_TYPE_LOKCACHE: &str = "LOKCACHE";
+/// SM Type Aufzeichnung RDS Rückmeldungen an die SRCP Session
+const SM_TYPE_RDSCAPTURE: &str = "RDSCAPTURE";
+/// Maximale Wartezeit auf das Ende des RDS Threads beim Entfernen des Protokolls.
+/// Das Entfernen erfolgt im DDL Thread, der nicht blockiert werden darf.
+const RDS_BEENDEN_TIMEOUT: Duration = Duration::from_millis(100);
+
 pub enum MfxVersion {
   V0, //Analog Implementierung im alten C srcpd
 }
 
+/// Zu welchem Kommando das offene 1 Bit Rückmeldefenster gehört
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Rds1BitFenster {
+  /// Kein Rückmeldefenster offen
+  Keines,
+  /// Dekodersuche
+  Suche,
+  /// Ping an die SID
+  Ping(u32),
+}
+
 pub struct MfxProtokoll {
   /// Version, aktuell nur 0, keine Verwendung.
   _version: MfxVersion,
@@ -177,21 +218,63 @@ pub struct MfxProtokoll {
   /// Anzahl gefundene UID
   search_new_dekoder_uid: u32,
   /// Wenn Rückmeldung über UDP vom MFXRDS erwartet, dann wird hier der Zeitpunkt ab dem gewartet wird gespeichert um mit Timeout abbrechen zu können
+  /// (Dekodersuche und Ping)
   search_new_dekoder_udp_rx_time: Option<Instant>,
   /// Anfangts und Endpositions 1 Bit Rückmeldung im SPI Bytebuffer
   rds_1_bit_start_pos: usize,
   /// Channel für Aufträge an RDS Thread
-  tx_to_rds: Sender<MfxRdsJob>,
+  tx_to_rds: ThreadSender<MfxRdsJob>,
+  /// RDS Thread, wird beim Entfernen des Protokolls beendet
+  rds_thread: Option<JoinHandle<()>>,
   /// Channel für Antworten von RDS Thread von SM Read/Write
   rx_from_rds_read_write_ca: Receiver<SmReadWrite>,
   /// Channel für Antworten von RDS Thread von neu angemeldeten GL Init Parametern
-  rx_from_rds_lok_init: Receiver<Option<Vec<String>>>,
+  rx_from_rds_lok_init: Receiver<Option<(Vec<String>, usize)>>,
   /// Channel für Tel. Sendeaufträge vom RDS Thread
   rx_tel_from_rds: Receiver<MfxCvTel>,
+  /// Vom RDS Thread gesetzter Fortschritt beim Auslesen der Lokparameter in %
+  rds_fortschritt: Arc<AtomicU8>,
+  /// Zuletzt mit "read_gl_parameter" gemeldeter Fortschritt
+  rds_fortschritt_gemeldet: u8,
+  /// Signal an RDS Thread, dass er beendet werden soll
+  rds_beenden: Arc<AtomicBool>,
+  /// Persistenter Cache der gelesenen Lokparameter
+  lok_cache: MfxLokCache,
+  /// Aufzeichnung RDS Rückmeldungen an SRCP Session eingeschaltet
+  rds_capture_aktiv: Arc<AtomicBool>,
+  /// Vom RDS Thread aufgezeichnete RDS Rückmeldungen
+  rx_capture_from_rds: Receiver<String>,
+  /// SRCP Session an welche die aufgezeichneten RDS Rückmeldungen gesendet werden
+  rds_capture_session: Option<u32>,
   /// Wenn das lesen von Lokparametern im Gange ist, ist hier die Adresse dieser Lok enthalten
   read_gl_parameter: Option<u32>,
+  /// Max. Anzahl Funktionen, die nach einer automatischen Anmeldung gemäss Dekoder übernommen wird
+  anz_f_auto_max: usize,
+  /// Anwesenheitsprüfung: ohne Antwort auf Ping während dieser Zeit gilt ein Dekoder als nicht mehr vorhanden.
+  /// None: keine Anwesenheitsprüfung
+  abwesend_timeout: Option<Duration>,
+  /// Nicht mehr vorhandene Dekoder: SID freigeben
+  abwesend_freigeben: bool,
+  /// Zeitpunkt letzte Antwort auf Ping (oder Anmeldung / Power On) für alle angemeldeten SID's
+  letzte_antwort: HashMap<u32, Instant>,
+  /// Als nicht mehr vorhanden gemeldete SID's
+  abwesend: HashSet<u32>,
+  /// Noch nicht mit "get_gl_anwesenheit" abgeholte Veränderungen der Anwesenheit
+  anwesenheit_neu: Vec<GLAnwesenheit>,
+  /// Gesendetes 1 Bit Rückmeldefenster, dessen Rückmeldung noch ausgewertet werden muss.
+  /// Bis dahin wird keine weitere Dekodersuche und kein Ping gesendet, damit eine (bei UDP verzögerte)
+  /// Rückmeldung nicht dem falschen Kommando zugeordnet wird.
+  rds_1_bit_fenster: Rds1BitFenster,
+  /// SID des letzten Pings, die SID's werden der Reihe nach abgefragt
+  ping_letzte_adr: u32,
+  /// Ping und Dekodersuche werden abwechselnd gesendet
+  ping_naechster: bool,
   /// Ist SM Mode auf diesem Protokoll aktiviert?
   sm_aktiv: bool,
+  /// Direkt, ohne RDS Thread, erzeugte SM Antwort (Neuanmeldezähler)
+  sm_antwort: Option<SmReadWrite>,
+  /// An den RDS Thread übergebener SM Auftrag, auf dessen Antwort gewartet wird
+  sm_auftrag_offen: Option<SmReadWrite>,
 }
 
 impl MfxProtokoll {
@@ -203,9 +286,11 @@ impl MfxProtokoll {
   /// * udp_baseport_rds - UDP Port für MFX RDS Rückmeldungen von Software "mfxrds".
   ///                      An diesem Port werden die Daten erwartet, an +1 die Meldungen RDS vorhanden.
   ///                      Wenn nicht vorhanden: Rückmeldung über GPIO von RDS Chip.
+  /// * rds - false: ohne RDS Thread, keine Rückmeldungen möglich (Selbsttest ohne Hardware).
+  /// * rds_konfig - Timeouts und Wiederholungen der RDS Rückmeldungen
   pub fn from(
     version: MfxVersion, uid_zentrale: u32, path_reg_counter_file: String,
-    udp_baseport_rds: Option<u16>,
+    udp_baseport_rds: Option<u16>, rds: bool, rds_konfig: MfxRdsKonfig,
   ) -> MfxProtokoll {
     //Neuanmeldezähler laden
     let mut reg_counter: u16 = 0;
@@ -237,8 +322,8 @@ impl MfxProtokoll {
     let (tx_to_rds, rx_in_rds): (Sender<MfxRdsJob>, Receiver<MfxRdsJob>) = mpsc::channel();
     //<- Antworten Lok Init vom RDS Thread
     let (tx_from_rds_lok_init, rx_from_rds_lok_init): (
-      Sender<Option<Vec<String>>>,
-      Receiver<Option<Vec<String>>>,
+      Sender<Option<(Vec<String>, usize)>>,
+      Receiver<Option<(Vec<String>, usize)>>,
     ) = mpsc::channel();
     //<- Antworten ReadCA/WriteCA vom RDS Thread
     let (tx_from_rds_read_write_ca, rx_from_rds_read_write_ca): (
@@ -248,20 +333,46 @@ impl MfxProtokoll {
     //<- MFX Tel. Sendeaufträge vom RDS Thread
     let (tx_tel_from_rds, rx_tel_from_rds): (Sender<MfxCvTel>, Receiver<MfxCvTel>) =
       mpsc::channel();
+    //Fortschritt Auslesen Lokparameter
+    let rds_fortschritt = Arc::new(AtomicU8::new(0));
+    let rds_fortschritt_thread = rds_fortschritt.clone();
+    //<- Aufzeichnung RDS Rückmeldungen vom RDS Thread
+    let (tx_capture_from_rds, rx_capture_from_rds): (Sender<String>, Receiver<String>) =
+      mpsc::channel();
+    let rds_capture = MfxRdsCapture {
+      aktiv: Arc::new(AtomicBool::new(false)),
+      tx: tx_capture_from_rds,
+    };
+    let rds_capture_aktiv = rds_capture.aktiv.clone();
+    let rds_beenden = Arc::new(AtomicBool::new(false));
+    let rds_beenden_thread = rds_beenden.clone();
     //RDS Einlesethread starten
-    thread::Builder::new()
-      .name("MFX RDS Feedbackthread".to_string())
-      .spawn(move || {
-        MfxRdsFeedbackThread::new(
-          rx_in_rds,
-          tx_from_rds_read_write_ca,
-          tx_from_rds_lok_init,
-          tx_tel_from_rds,
-          udp_baseport_rds,
-        )
-        .execute()
-      })
-      .unwrap();
+    let rds_thread = rds.then(|| {
+      thread::Builder::new()
+        .name("MFX RDS Feedbackthread".to_string())
+        .spawn(move || {
+          MfxRdsFeedbackThread::new(
+            MfxRdsKanaele {
+              rx: rx_in_rds,
+              tx: ThreadSender::new(tx_from_rds_read_write_ca, "MFX RDS Thread Antworten"),
+              tx_lok_init: ThreadSender::new(tx_from_rds_lok_init, "MFX RDS Thread Lokparameter"),
+              tx_tel: ThreadSender::new(tx_tel_from_rds, "MFX RDS Thread Telegramme"),
+            },
+            udp_baseport_rds,
+            rds_fortschritt_thread,
+            rds_capture,
+            rds_beenden_thread,
+          )
+          .execute()
+        })
+        .unwrap()
+    });
+
+    //Erster Auftrag an RDS Thread ist immer die Konfiguration
+    let tx_to_rds = ThreadSender::new(tx_to_rds, "MFX RDS Thread");
+    if rds_thread.is_some() {
+      tx_to_rds.send(MfxRdsJob::Konfig { konfig: rds_konfig });
+    }
 
     MfxProtokoll {
       _version: version,
@@ -281,21 +392,175 @@ impl MfxProtokoll {
       search_new_dekoder_udp_rx_time: None,
       rds_1_bit_start_pos: 0,
       tx_to_rds,
+      rds_thread,
       rx_from_rds_read_write_ca,
       rx_from_rds_lok_init,
       rx_tel_from_rds,
+      rds_fortschritt,
+      rds_fortschritt_gemeldet: 0,
+      rds_beenden,
+      lok_cache: MfxLokCache::new(None),
+      rds_capture_aktiv,
+      rx_capture_from_rds,
+      rds_capture_session: None,
       read_gl_parameter: None,
+      anz_f_auto_max: MFX_ANZ_F_MAX,
+      abwesend_timeout: None,
+      abwesend_freigeben: false,
+      letzte_antwort: HashMap::new(),
+      abwesend: HashSet::new(),
+      anwesenheit_neu: Vec::new(),
+      rds_1_bit_fenster: Rds1BitFenster::Keines,
+      ping_letzte_adr: 0,
+      ping_naechster: false,
       sm_aktiv: false,
+      sm_antwort: None,
+      sm_auftrag_offen: None,
     }
   }
 
+  /// Max. Anzahl Funktionen setzen, die nach einer automatischen Anmeldung gemäss den aus dem Dekoder
+  /// gelesenen Schaltfunktionen (CaFunkSchaltfunktion) verwendet wird.
+  /// # Arguments
+  /// * anz_f_auto_max - Max. Anzahl Funktionen, max. MFX_ANZ_F_MAX
+  pub fn set_anz_f_auto_max(&mut self, anz_f_auto_max: usize) {
+    self.anz_f_auto_max = anz_f_auto_max.min(MFX_ANZ_F_MAX);
+  }
+
+  /// Anwesenheitsprüfung der angemeldeten Dekoder konfigurieren.
+  /// Abwechselnd mit der Dekodersuche wird der Reihe nach an jeden angemeldeten Dekoder ein Ping gesendet.
+  /// # Arguments
+  /// * abwesend_timeout - Ohne Antwort während dieser Zeit gilt ein Dekoder als nicht mehr vorhanden.
+  ///                      None: keine Anwesenheitsprüfung
+  /// * freigeben - true: SID eines nicht mehr vorhandenen Dekoders freigeben
+  pub fn set_anwesenheit(&mut self, abwesend_timeout: Option<Duration>, freigeben: bool) {
+    self.abwesend_timeout = abwesend_timeout;
+    self.abwesend_freigeben = freigeben;
+  }
+
+  /// Persistenten Cache der gelesenen Lokparameter konfigurieren.
+  /// Bei einer Neuanmeldung einer bekannten UID werden die Lokparameter nicht mehr aus dem Dekoder gelesen.
+  /// # Arguments
+  /// * path - File in dem der Cache gespeichert wird, None: kein Cache
+  pub fn set_lok_cache(&mut self, path: Option<String>) {
+    self.lok_cache = MfxLokCache::new(path);
+  }
+
+  /// SM Zugriff auf den Lokcache.
+  /// Liefert die Antwort für "sm_get_answer".
+  /// - GET liefert 1 wenn die Lokparameter des Dekoders im Cache sind, sonst 0.
+  /// - SET mit Value 0 entfernt sie, bei der nächsten Anmeldung werden sie wieder aus dem Dekoder gelesen.
+  /// # Arguments
+  /// * sm_para - SM Read/Write Paramater mit Type LOKCACHE
+  fn sm_lok_cache(&mut self, sm_para: &SmReadWrite) -> SmReadWrite {
+    let mut antwort = sm_para.clone();
+    let uid = self
+      .uid
+      .get(sm_para.adr as usize)
+      .copied()
+      .unwrap_or_default();
+    antwort.val = match &sm_para.val {
+      SmReadWriteType::Read => SmReadWriteType::ResultOk(self.lok_cache.get(uid).is_some() as u32),
+      SmReadWriteType::Write(0) => {
+        self.lok_cache.entfernen(uid);
+        SmReadWriteType::ResultOk(0)
+      }
+      SmReadWriteType::Verify(value) if *value == self.lok_cache.get(uid).is_some() as u32 => {
+        SmReadWriteType::ResultOk(*value)
+      }
+      _ => SmReadWriteType::ResultErr,
+    };
+    antwort
+  }
+
+  /// SM Auftrag an den RDS Thread senden.
+  /// Ist dieser nicht mehr vorhanden, wird direkt mit Fehler geantwortet und nicht auf den Timeout gewartet.
+  /// # Arguments
+  /// * sm_para - SM Read/Write Paramater des Auftrages
+  /// * job - Auftrag an den RDS Thread
+  fn sm_to_rds(&mut self, sm_para: &SmReadWrite, job: MfxRdsJob) {
+    if self.tx_to_rds.send(job) {
+      self.sm_auftrag_offen = Some(sm_para.clone());
+    } else {
+      let mut antwort = sm_para.clone();
+      antwort.val = SmReadWriteType::ResultErr;
+      self.sm_antwort = Some(antwort);
+    }
+  }
+
+  /// SM Zugriff auf die Aufzeichnung der RDS Rückmeldungen.
+  /// Liefert die Antwort für "sm_get_answer".
+  /// - GET liefert 1 wenn die Aufzeichnung eingeschaltet ist, sonst 0.
+  /// - SET mit Value 1 schaltet die Aufzeichnung an die Session des Kommandos ein, mit 0 aus.
+  /// # Arguments
+  /// * sm_para - SM Read/Write Paramater mit Type RDSCAPTURE
+  fn sm_rds_capture(&mut self, sm_para: &SmReadWrite) -> SmReadWrite {
+    let mut antwort = sm_para.clone();
+    antwort.val = match &sm_para.val {
+      SmReadWriteType::Read => SmReadWriteType::ResultOk(self.rds_capture_session.is_some() as u32),
+      SmReadWriteType::Write(value) if *value <= 1 => {
+        self.rds_capture_session = (*value == 1).then_some(sm_para.session_id);
+        self
+          .rds_capture_aktiv
+          .store(self.rds_capture_session.is_some(), Ordering::Relaxed);
+        info!(
+          "MFX RDS Aufzeichnung an Session {:?}",
+          self.rds_capture_session
+        );
+        SmReadWriteType::ResultOk(*value)
+      }
+      _ => SmReadWriteType::ResultErr,
+    };
+    antwort
+  }
+
   /// Speichern des Neuanmeldezählers
+  /// Zuerst in ein temporäres File, dann umbenennen, damit bei einem Absturz während dem Schreiben
+  /// nie ein unvollständiges File zurückbleibt.
   fn save_registration_counter(&self) {
-    if fs::write(&self.path_reg_counter_file, self.reg_counter.to_string()).is_err() {
+    let path_tmp = format!("{}.tmp", self.path_reg_counter_file);
+    if fs::write(&path_tmp, self.reg_counter.to_string()).is_err()
+      || fs::rename(&path_tmp, &self.path_reg_counter_file).is_err()
+    {
       warn!("MFX Neuanmeldezähler konnte nicht gespeichert werden.");
     }
   }
 
+  /// SM Zugriff auf den Neuanmeldezähler der Zentrale.
+  /// Liefert die Antwort für "sm_get_answer".
+  /// - REGCOUNT: GET liefert den Neuanmeldezähler, SET setzt ihn, VERIFY vergleicht.
+  /// - REGNEW: SET mit Value 1 erhöht den Neuanmeldezähler um 1, dadurch melden sich alle Dekoder neu an.
+  /// # Arguments
+  /// * sm_para - SM Read/Write Paramater mit Type REGCOUNT oder REGNEW
+  fn sm_registration_counter(&mut self, sm_para: &SmReadWrite) -> SmReadWrite {
+    let mut antwort = sm_para.clone();
+    antwort.val = match (sm_para.sm_type.as_str(), &sm_para.val) {
+      (SM_TYPE_REGCOUNT, SmReadWriteType::Read) => {
+        SmReadWriteType::ResultOk(self.reg_counter as u32)
+      }
+      (SM_TYPE_REGCOUNT, SmReadWriteType::Write(value)) if *value <= u16::MAX as u32 => {
+        info!("MFX Neuanmeldezähler gesetzt auf {}", value);
+        self.reg_counter = *value as u16;
+        self.save_registration_counter();
+        SmReadWriteType::ResultOk(*value)
+      }
+      (SM_TYPE_REGCOUNT, SmReadWriteType::Verify(value)) if *value == self.reg_counter as u32 => {
+        SmReadWriteType::ResultOk(*value)
+      }
+      (SM_TYPE_REGNEW, SmReadWriteType::Write(1)) => {
+        self.reg_counter = self.reg_counter.wrapping_add(1);
+        info!(
+          "MFX Neuanmeldung aller Dekoder verlangt, Neuanmeldezähler={}",
+          self.reg_counter
+        );
+        self.save_registration_counter();
+        SmReadWriteType::ResultOk(1)
+      }
+      _ => SmReadWriteType::ResultErr,
+    };
+    antwort
+  }
+
   /// Berechnet den MFX CRC.
   /// # Arguments
   /// bits - Die (neuen) Bits
@@ -318,13 +583,12 @@ impl MfxProtokoll {
   /// * bit - true / false
   /// * ddl_tel - Telegramm, bei dem Bits hinzugefügt werden sollen
   fn add_bit(&mut self, bit: bool, ddl_tel: &mut DdlTel) {
-    let last = ddl_tel.daten.len() - 1;
-    let daten: &mut Vec<u8> = ddl_tel.daten[last].as_mut();
-    let values: (u8, u8) = if (*daten.last().unwrap() & 0x01) == 0 {
+    let values: (u8, u8) = if (ddl_tel.get_letztes_byte() & 0x01) == 0 {
       (0x00, 0xFF) //Letzter Pegel war 0 -> "normal"
     } else {
       (0xFF, 0x00) //Letzter Pegel war 1 -> invertiert arbeiten
     };
+    let daten = ddl_tel.payload_mut();
     //Jedes Bit startet mit einer Flanke
     daten.push(values.1);
     //Nun Flankenwechsel für eine 1, keiner für eine 0
@@ -360,13 +624,12 @@ impl MfxProtokoll {
   /// * ddl_tel - Telegramm, bei dem MFX Sync hinzugefügt werden soll
   /// * halb - wenn true wird nur ein halbes Sync.Muster ergänzt (=Pegeländerung)
   fn add_sync(&mut self, ddl_tel: &mut DdlTel, halb: bool) {
-    let last = ddl_tel.daten.len() - 1;
-    let daten: &mut Vec<u8> = ddl_tel.daten[last].as_mut();
-    let values: (u8, u8) = if (*daten.last().unwrap() & 1) == 0 {
+    let values: (u8, u8) = if (ddl_tel.get_letztes_byte() & 1) == 0 {
       (0x00, 0xFF) //Letzter Pegel war 0 -> "normal"
     } else {
       (0xFF, 0x00) //Letzter Pegel war 1 -> invertiert arbeiten
     };
+    let daten = ddl_tel.payload_mut();
     daten.push(values.1);
     daten.push(values.1); //0
     daten.push(values.0);
@@ -385,10 +648,7 @@ impl MfxProtokoll {
   /// # Arguments
   /// * ddl_tel - Telegramm, bei dem Startpegel / Pause und MFX Sync hinzugefügt werden soll
   fn add_start_sync(&mut self, ddl_tel: &mut DdlTel) {
-    let last = ddl_tel.daten.len() - 1;
-    for _ in 0..(MFX_STARTSTOP_0_BIT * SPI_BYTES_PRO_BIT) {
-      ddl_tel.daten[last].push(0);
-    }
+    ddl_tel.add_pause(MFX_PAUSE_STARTSTOP);
     self.add_sync(ddl_tel, false);
   }
   /// CRC zum Tel. hinzufügen.
@@ -414,10 +674,7 @@ impl MfxProtokoll {
     //- 3. Sync am Ende
     //- Pause nach Abschluss.
     self.add_sync(ddl_tel, false);
-    let last = ddl_tel.daten.len() - 1;
-    for _ in 0..(MFX_STARTSTOP_0_BIT * SPI_BYTES_PRO_BIT) {
-      ddl_tel.daten[last].push(0);
-    }
+    ddl_tel.add_pause(MFX_PAUSE_STARTSTOP);
   }
   /// Adresse zum MFX Tel. hinzufügen
   /// Anhänging von der Adresse wird 7, 9, 11, 14 Bit Adressierung verwendet
@@ -494,6 +751,38 @@ impl MfxProtokoll {
     self.add_bits((self.search_new_dekoder_bits, 6), ddl_tel, &mut crc);
     self.add_bits((self.search_new_dekoder_uid, 32), ddl_tel, &mut crc);
     self.add_crc(ddl_tel, crc);
+    if self.search_new_dekoder_bits > 0 {
+      info!(
+        "MFX Search UID={} Bits={}",
+        self.search_new_dekoder_uid, self.search_new_dekoder_bits
+      );
+    }
+    self.add_rds_1_bit(ddl_tel);
+    self.rds_1_bit_fenster = Rds1BitFenster::Suche;
+  }
+
+  /// MFX Paket Ping (Existenzabfrage) an einen angemeldeten Dekoder versenden
+  /// # Arguments
+  /// * ddl_tel: DDL Telegramm bei dem das Ping Telegramm hinzugefügt werden soll
+  /// * adr: SID des Dekoders
+  fn send_ping(&mut self, ddl_tel: &mut DdlTel, adr: u32) {
+    //Format des Bitstreams:
+    //10AAAAAAA111100CCCCCCCC
+    //A=SID des Dekoders
+    //C=Checksumme
+    self.add_start_sync(ddl_tel);
+    let mut crc = self.add_adr(adr, ddl_tel);
+    self.add_bits(MFX_CMD_PING, ddl_tel, &mut crc);
+    self.add_crc(ddl_tel, crc);
+    self.add_rds_1_bit(ddl_tel);
+    self.rds_1_bit_fenster = Rds1BitFenster::Ping(adr);
+  }
+
+  /// Rückmeldefenster für 1 Bit Rückmeldung (Dekodersuche, Ping) anhängen und Rückmeldung aktivieren.
+  /// Auswertung mit "eval_rds_1_bit".
+  /// # Arguments
+  /// * ddl_tel: DDL Telegramm mit Kommando, bei dem das Rückmeldefenster hinzugefügt werden soll
+  fn add_rds_1_bit(&mut self, ddl_tel: &mut DdlTel) {
     //Nun kommt noch der Platz für 1 Bit Rückmeldung
     //11 oder 11.5 Sync
     //Bitfolge "0011"
@@ -506,36 +795,24 @@ impl MfxProtokoll {
     }
     //Pause geht mit 0 weiter, sollte also hier mit 1 aufhören um letzte Flanke zu haben
     //Wenn nicht: 0.5 Sync ergänzen
-    if *ddl_tel.daten.last().unwrap().last().unwrap() == 0 {
+    if ddl_tel.get_letztes_byte() == 0 {
       self.add_sync(ddl_tel, true);
     }
     //Bitfolge 0011
+    let mut crc = 0;
     self.add_bits((0b0011, 4), ddl_tel, &mut crc);
     //Pause 6.4ms mit 0
-    self.rds_1_bit_start_pos = ddl_tel.daten.last().unwrap().len();
-    ddl_tel
-      .daten
-      .last_mut()
-      .unwrap()
-      .resize(self.rds_1_bit_start_pos + MFX_LEN_PAUSE_6_4_MS, 0);
+    self.rds_1_bit_start_pos = ddl_tel.get_len();
+    let daten = ddl_tel.payload_mut();
+    daten.resize(daten.len() + MFX_LEN_PAUSE_6_4_MS, 0);
     //Sync
     self.add_sync(ddl_tel, false);
     //Pause 6.4ms mit 1
-    let len = ddl_tel.daten.last().unwrap().len();
-    ddl_tel
-      .daten
-      .last_mut()
-      .unwrap()
-      .resize(len + MFX_LEN_PAUSE_6_4_MS, 0xFF);
+    let daten = ddl_tel.payload_mut();
+    daten.resize(daten.len() + MFX_LEN_PAUSE_6_4_MS, 0xFF);
     //2 Sync
     self.add_sync(ddl_tel, false);
     self.add_sync(ddl_tel, false);
-    if self.search_new_dekoder_bits > 0 {
-      info!(
-        "MFX Search UID={} Bits={}",
-        self.search_new_dekoder_uid, self.search_new_dekoder_bits
-      );
-    }
     //Falls die MFX RDS Rückmeldung zur Dekodersuche über UDP Socket erfolgt, wird der Empfangsbuffer jetzt geleert.
     if let Some(socket) = &self.udp_socket_rds_present {
       //Rückmeldung über UDP vom mfxrds Software -> Rx Buffer leeren
@@ -550,17 +827,15 @@ impl MfxProtokoll {
       ddl_tel.daten_rx = DdlTelRx::Udp;
     } else {
       //Und Rückmeldung über SPI In aktivieren.
-      ddl_tel.daten_rx = DdlTelRx::SpiRx(vec![0; ddl_tel.daten.last().unwrap().len()]);
+      ddl_tel.daten_rx = DdlTelRx::SpiRx(vec![0; ddl_tel.get_len()]);
     }
   }
 
-  /// Auswertung Ergebnis Dekodersuche.
-  /// Wenn positives Feedback: weiter mit nächstem Bit, zuerst 0, dann 1.
-  /// Wenn 32 Bit gefunden -> neuer Dekoder gefunden.
-  /// Ergebnis: siehe "ResultNeuAnmeldung".
+  /// Auswertung 1 Bit Rückmeldung aus dem mit "add_rds_1_bit" angehängten Rückmeldefenster.
+  /// Liefert None solange noch keine Rückmeldung vorhanden ist (UDP), sonst ob ein Dekoder geantwortet hat.
   /// # Arguments
   /// * daten_rx : Die Art der erwarteten Rückmeldung
-  fn eval_send_search_new_decoder(&mut self, daten_rx: &DdlTelRx) -> ResultNeuAnmeldung {
+  fn eval_rds_1_bit(&mut self, daten_rx: &DdlTelRx) -> Option<bool> {
     let mut pos_feedback: Option<bool> = None;
     match daten_rx {
       DdlTelRx::None => {}
@@ -609,7 +884,16 @@ impl MfxProtokoll {
         pos_feedback = Some(false);
       }
     }
+    pos_feedback
+  }
 
+  /// Auswertung Ergebnis Dekodersuche.
+  /// Wenn positives Feedback: weiter mit nächstem Bit, zuerst 0, dann 1.
+  /// Wenn 32 Bit gefunden -> neuer Dekoder gefunden.
+  /// Ergebnis: siehe "ResultNeuAnmeldung".
+  /// # Arguments
+  /// * pos_feedback : Rückmeldung auf die Dekodersuche, None wenn (noch) keine vorhanden
+  fn eval_send_search_new_decoder(&mut self, pos_feedback: Option<bool>) -> ResultNeuAnmeldung {
     let mut result: ResultNeuAnmeldung = if self.search_new_dekoder_bits > 0 {
       //Eine Suche ist im Gange, auch wenn wir aktuelle vielleicht noch auf eine UDP Rückmeldung warten
       ResultNeuAnmeldung::InProgress
@@ -634,7 +918,7 @@ impl MfxProtokoll {
             );
             result = ResultNeuAnmeldung::Ok(self.search_new_dekoder_uid);
             //Und Neuanmeldezähler inkrementieren
-            self.reg_counter += 1;
+            self.reg_counter = self.reg_counter.wrapping_add(1);
             self.save_registration_counter();
           }
           //Für neue Suche bereit machen
@@ -671,6 +955,69 @@ impl MfxProtokoll {
     result
   }
 
+  /// Liefert die SID für den nächsten Ping oder None wenn jetzt eine Dekodersuche gesendet werden soll.
+  /// Ping und Dekodersuche wechseln sich ab, während einer laufenden Dekodersuche wird kein Ping gesendet.
+  pub fn get_naechste_ping_adr(&mut self) -> Option<u32> {
+    if self.abwesend_timeout.is_none() || self.search_new_dekoder_bits > 0 {
+      return None;
+    }
+    self.ping_naechster = !self.ping_naechster;
+    if !self.ping_naechster {
+      return None;
+    }
+    //Der Reihe nach alle angemeldeten SID's
+    let adr = self
+      .letzte_antwort
+      .keys()
+      .filter(|adr| **adr > self.ping_letzte_adr)
+      .min()
+      .or_else(|| self.letzte_antwort.keys().min())
+      .copied()?;
+    self.ping_letzte_adr = adr;
+    Some(adr)
+  }
+
+  /// Auswertung Rückmeldung auf einen Ping.
+  /// Veränderungen der Anwesenheit werden für "get_gl_anwesenheit" gespeichert.
+  /// # Arguments
+  /// * adr : SID des abgefragten Dekoders
+  /// * vorhanden : true wenn der Dekoder geantwortet hat
+  pub fn eval_ping(&mut self, adr: u32, vorhanden: bool) {
+    let jetzt = Instant::now();
+    if vorhanden {
+      self.letzte_antwort.insert(adr, jetzt);
+      if self.abwesend.remove(&adr) {
+        info!(
+          "MFX SID={} UID={} wieder vorhanden",
+          adr, self.uid[adr as usize]
+        );
+        self.anwesenheit_neu.push(GLAnwesenheit::Vorhanden(adr));
+      }
+      return;
+    }
+    let (Some(timeout), Some(letzte_antwort)) =
+      (self.abwesend_timeout, self.letzte_antwort.get(&adr))
+    else {
+      return;
+    };
+    if (jetzt - *letzte_antwort < timeout) || self.abwesend.contains(&adr) {
+      return;
+    }
+    warn!(
+      "MFX SID={} UID={} seit {:?} ohne Antwort, nicht mehr vorhanden",
+      adr, self.uid[adr as usize], timeout
+    );
+    if self.abwesend_freigeben {
+      //SID wird nicht mehr verwendet, kein weiterer Ping
+      self.uid[adr as usize] = 0;
+      self.letzte_antwort.remove(&adr);
+      self.anwesenheit_neu.push(GLAnwesenheit::Freigegeben(adr));
+    } else {
+      self.abwesend.insert(adr);
+      self.anwesenheit_neu.push(GLAnwesenheit::Abwesend(adr));
+    }
+  }
+
   /// Liefert ein MFX CV Read/Write Telegramm.
   /// # Arguments
   /// * tel - Zu erzeugendes Telegramm
@@ -681,10 +1028,10 @@ impl MfxProtokoll {
     //A=Adresse
     //V=10 Bit CV Adresse
     //I=6 Bit Index
-    //B=2 Bit Anzahl Bytes 00=1, 01=2, 10=4, 11=8 (Beim Schreiben unterstützen Dekoder aber nur ein Byte, hier ist jedoch alles implementiert)
+    //B=2 Bit Anzahl Bytes 00=1, 01=2, 10=4, 11=8 (Beim Schreiben unterstützen nicht alle Dekoder mehr als ein Byte)
     //D=8(bis 64) Bit Daten zum Schreiben
     //C=Checksumme
-    let mut ddl_tel = self.get_gl_new_tel(tel.adr, true, tel.trigger); //Refresh->nur einmaliges Senden
+    let mut ddl_tel = self.get_gl_new_tel(tel.adr, TelOptions::refresh(tel.trigger)); //Refresh->nur einmaliges Senden
     self.add_start_sync(&mut ddl_tel);
     let mut crc = self.add_adr(tel.adr, &mut ddl_tel);
     self.add_bits(
@@ -717,7 +1064,7 @@ impl MfxProtokoll {
       }
       //Pause geht mit 0 weiter, sollte also hier mit 1 aufhören um letzte Flanke zu haben
       //Wenn nicht: 0.5 Sync ergänzen
-      if *ddl_tel.daten.last().unwrap().last().unwrap() == 0 {
+      if ddl_tel.get_letztes_byte() == 0 {
         self.add_sync(&mut ddl_tel, true);
       }
       //Bitfolge 0011
@@ -725,7 +1072,7 @@ impl MfxProtokoll {
       //23 Takte 25us alle 912us
       //Ab hier wird mit einzelnen Bits gearbeitet um das verlangte Timing möglichst genau einzuhalten
       //MSB wird zuerst ausgegeben
-      let daten = ddl_tel.daten.last_mut().unwrap();
+      let daten = ddl_tel.payload_mut();
       //Anzahl Bits die im letzten Bytes noch frei sind
       let mut anz_bit_frei: usize = 0;
       for _ in 0..23 {
@@ -786,6 +1133,30 @@ impl MfxProtokoll {
   }
 }
 
+impl Drop for MfxProtokoll {
+  /// Beim Entfernen des Protokolls (z.B. neue UID der Zentrale, Beenden srcpd) RDS Thread beenden,
+  /// damit dessen GPIO's / UDP Ports für eine neue Instanz wieder frei sind.
+  /// Auf das Ende wird höchstens "RDS_BEENDEN_TIMEOUT" gewartet.
+  fn drop(&mut self) {
+    //Laufenden Auftrag abbrechen und Thread beenden, Auftragschannel schliessen
+    self.rds_beenden.store(true, Ordering::Relaxed);
+    if let Some(rds_thread) = self.rds_thread.take() {
+      self.tx_to_rds.send(MfxRdsJob::Beenden);
+      self.tx_to_rds = ThreadSender::new(mpsc::channel().0, "MFX RDS Thread");
+      //Nur begrenzt warten, ein noch laufender Thread beendet sich selbständig
+      let start = Instant::now();
+      while !rds_thread.is_finished() && (start.elapsed() < RDS_BEENDEN_TIMEOUT) {
+        thread::sleep(Duration::from_millis(1));
+      }
+      if !rds_thread.is_finished() {
+        warn!("MFX RDS Thread noch nicht beendet, er wird im Hintergrund beendet.");
+      } else if rds_thread.join().is_err() {
+        warn!("MFX RDS Thread konnte nicht sauber beendet werden.");
+      }
+    }
+  }
+}
+
 impl DdlProtokoll for MfxProtokoll {
   /// Wenn von einem Protokoll mehrere Versionen (z.B. NMRA 1 und 2) instanziert werden,
   /// dann muss eines davon zum Default erklärt werden. Die Default Version wird dann verwendet,
@@ -803,25 +1174,20 @@ impl DdlProtokoll for MfxProtokoll {
   /// Liefert, wenn "power" ein allfällg notwendiges Init-Telegramm (z.B. MFX SID Zurordnung) zurück.
   /// # Arguments
   /// * adr - Adresse der Lok
-  /// * uid - UID des Dekoders, wenn vorhanden
-  /// * funk_anz - Anzahl tatsächlich verwendete Funktionen. Kann, je nach Protokoll, dazu
-  ///              verwendet werden, nur Telegramme der verwendeten Funktionen zu senden.
-  /// * power - true wenn Power ein, ein allfällig notwendiges Telegramm (z.B. MFX Schienenadr. Zuordung)
-  ///           wird zum sofort senden zurückgegeben.
-  ///           false wenn Power aus, nie direkte Ausgabe notwendiges Init Telegramm, dieses muss vor nächstem
-  ///           GL Befehl ausgegeben werden.
-  /// * trigger - Oszi Trigger?
-  fn init_gl(
-    &mut self, adr: u32, uid: Option<u32>, funk_anz: usize, power: bool, trigger: bool,
-  ) -> Option<DdlTel> {
-    self.uid[adr as usize] = uid.unwrap();
-    self.funk_anz[adr as usize] = funk_anz;
+  /// * params - UID (muss vorhanden sein), Anzahl Funktionen, Power und Oszi Trigger, siehe "GlInitParams"
+  fn init_gl(&mut self, adr: u32, params: &GlInitParams) -> Option<DdlTel> {
+    self.uid[adr as usize] = params.uid.unwrap();
+    self.funk_anz[adr as usize] =
+      begrenze_funk_anz("MFX", adr, params.funk_anz, self.get_gl_anz_f());
+    //Anwesenheitsprüfung ab jetzt
+    self.letzte_antwort.insert(adr, Instant::now());
+    self.abwesend.remove(&adr);
     //Merken, dass vor nächstem Lokbefehl noch neue Schienenadr. Zuordnung gesendet werden muss.
     //Wird nicht hier direkt gemacht, da Init auch bei Booster Stop ausgeführt wird.
     self.new_sid[adr as usize] = true;
-    if power {
+    if params.power {
       //SID Zuordnungstelegramm kann gleich ausgegeben werden
-      let mut ddl_tel = self.get_gl_new_tel(adr, false, trigger);
+      let mut ddl_tel = self.get_gl_new_tel(adr, TelOptions::neu(params.trigger));
       //Nur SID Telegramm ist relevant, kein weiteres notwendig
       ddl_tel.daten.truncate(1);
       Some(ddl_tel)
@@ -847,7 +1213,7 @@ impl DdlProtokoll for MfxProtokoll {
 
   /// Liefert die max. Anzahl der unterstützten Funktionen
   fn get_gl_anz_f(&self) -> usize {
-    64 //Eigentlich kann MFX Total 128 (F0-F127), im Moment reicht mir das aber, die Funktionen werden in ganz srcp_rust in einem u64 verwaltet
+    MFX_ANZ_F_MAX
   }
 
   /// Liefert die Anzahl Funktionen (inkl. F0) die im Basistelegramm enthalten sind
@@ -861,22 +1227,20 @@ impl DdlProtokoll for MfxProtokoll {
   /// enthalten, ansonsten ist noch nichts enthalten.
   /// # Arguments
   /// * adr - Adresse der Lok zur Erkennung, ob noch SID Zuordung gesendet werden muss
-  /// * refresh - Wenn true: Aufruf aus Refres Cycle, einmalige Telegramm Versendung,
-  ///             Wenn false: Aufruf wegen neuem Lokkommando, mehrmaliges Versenden
-  /// * trigger - Oszi Trigger?
-  fn get_gl_new_tel(&mut self, adr: u32, refresh: bool, trigger: bool) -> DdlTel {
+  /// * optionen - Refresh und Oszi Trigger, siehe "TelOptions"
+  fn get_gl_new_tel(&mut self, adr: u32, optionen: TelOptions) -> DdlTel {
     let mut ddl_tel = DdlTel::new(
       adr,
       SPI_BAUDRATE_MFX_2,
       Duration::ZERO,
       false,
       MFX_MAX_LEN,
-      if refresh { 1 } else { 2 }, //Neue Telegramme 2-fach senden
-      trigger,
+      if optionen.refresh { 1 } else { 2 }, //Neue Telegramme 2-fach senden
+      optionen.trigger,
     );
     if self.new_sid[adr as usize] {
       self.send_sid(&mut ddl_tel, adr);
-      ddl_tel.daten.push(Vec::with_capacity(MFX_MAX_LEN));
+      ddl_tel.neues_tel(MFX_MAX_LEN);
       self.new_sid[adr as usize] = false;
     }
     ddl_tel
@@ -890,23 +1254,29 @@ impl DdlProtokoll for MfxProtokoll {
   /// * drive_mode - Fahrtrichtung / Nothalt
   /// * speed - aktuelle Geschwindigkeit
   /// * speed_steps - Anzahl Speed Steps die verwendet werden soll. Protokoll abhängig.
-  ///                 Wird hier nicht verwendet, bei MFX wird immer mit 127 Stufen gefahren
+  ///                 MFX fährt immer mit 126 Fahrstufen (2 bis 127), "speed" wird linear
+  ///                 von 1..speed_steps auf diesen Bereich skaliert.
   /// * funktionen - Die gewünschten Funktionen, berücksichtigt bis "get_Anz_F_Basis"
   /// * ddl_tel - DDL Telegramm, bei dem des neue Telegramm hinzugefügt werden soll.
   fn get_gl_basis_tel(
-    &mut self, adr: u32, drive_mode: GLDriveMode, speed: usize, _speed_steps: usize,
+    &mut self, adr: u32, drive_mode: GLDriveMode, speed: usize, speed_steps: usize,
     funktionen: u64, ddl_tel: &mut DdlTel,
   ) {
+    //Nur Funktionen gemäss INIT
+    let funktionen = funktionen & funktionen_maske(self.funk_anz[adr as usize]);
     self.add_start_sync(ddl_tel);
-    //Speed 1 = Nothalt
+    //Speed 0 = Halt, 1 = Nothalt, 2 bis 127 = Fahrstufen
+    let speed_max = self.get_gl_max_speed_steps();
     let speed_used = if drive_mode == GLDriveMode::Nothalt {
       1
+    } else if speed == 0 {
+      0
+    } else if speed_steps <= 1 {
+      speed_max
     } else {
-      if speed == 1 {
-        2
-      } else {
-        speed
-      }
+      //Gerundet skaliert von 1..speed_steps auf 2..127
+      2 + ((speed.min(speed_steps) - 1) * (speed_max - 2) + (speed_steps - 1) / 2)
+        / (speed_steps - 1)
     };
     //Drivemode für Richtung, wenn Nothalt dann der letzte
     let drive_mode_used: GLDriveMode = if drive_mode == GLDriveMode::Nothalt {
@@ -985,6 +1355,8 @@ impl DdlProtokoll for MfxProtokoll {
   /// * funktionen - Die gewünschten Funktionen, berücksichtigt ab "get_Anz_F_Basis"
   /// * ddl_tel - DDL Telegramm, bei dem des neue Telegramm hinzugefügt werden soll.
   fn get_gl_zusatz_tel(&mut self, adr: u32, refresh: bool, funktionen: u64, ddl_tel: &mut DdlTel) {
+    //Nur Funktionen gemäss INIT
+    let funktionen = funktionen & funktionen_maske(self.funk_anz[adr as usize]);
     if self.funk_anz[adr as usize] <= self.get_gl_anz_f_basis() {
       //Hier gibt es nichts zu tun
       return;
@@ -1015,7 +1387,7 @@ impl DdlProtokoll for MfxProtokoll {
           &mut crc,
         );
         self.add_crc_ende_sync(ddl_tel, crc);
-        ddl_tel.daten.push(Vec::with_capacity(MFX_MAX_LEN));
+        ddl_tel.neues_tel(MFX_MAX_LEN);
       }
     }
     //Falls nun am Schluss noch ein leeres vorhanden ist kann dieses entfernt werden
@@ -1028,18 +1400,9 @@ impl DdlProtokoll for MfxProtokoll {
   /// Nicht verwendet, keine GA's in MFX
   /// # Arguments
   /// * adr - Adresse GA, keine Verwendunbg, nur Debug Support
-  /// * trigger - Oszi Trigger
-  fn get_ga_new_tel(&self, adr: u32, trigger: bool) -> DdlTel {
-    assert!(false, "MFX unterstützt keine GA, Aufruf get_ga_new_tel");
-    DdlTel::new(
-      adr,
-      SPI_BAUDRATE_MFX_2,
-      Duration::ZERO,
-      false,
-      0,
-      1,
-      trigger,
-    )
+  /// * optionen - Oszi Trigger, siehe "TelOptions"
+  fn get_ga_new_tel(&self, _adr: u32, _optionen: TelOptions) -> DdlTel {
+    unreachable!("MFX unterstützt keine GA, Aufruf get_ga_new_tel");
   }
 
   /// Erzeugt ein GA Telegramm
@@ -1056,11 +1419,10 @@ impl DdlProtokoll for MfxProtokoll {
   /// * ddl_tel - DDL Telegramm, bei dem des neue Telegramm hinzugefügt werden soll.
   /// Nicht verwendet, keine GA's in MFX
   fn get_ga_tel(
-    &self, _adr: u32, _port: usize, _value: usize, _timeout: Option<Duration>,
+    &mut self, _adr: u32, _port: usize, _value: usize, _timeout: Option<Duration>,
     _ddl_tel: &mut DdlTel,
   ) -> bool {
-    assert!(false, "MFX unterstützt keine GA, Aufruf get_ga_tel");
-    false
+    unreachable!("MFX unterstützt keine GA, Aufruf get_ga_tel");
   }
 
   /// Liefert das Idle Telegramm dieses Protokolles
@@ -1069,10 +1431,27 @@ impl DdlProtokoll for MfxProtokoll {
   /// Sobald eine GL vorhanden ist, wird keine Idle mehr gesendet, UID Zentrale wird dann periodisch
   /// über get_protokoll_telegrammme im Intervall INTERVALL_UID gesendet
   fn get_idle_tel(&mut self) -> Option<DdlTel> {
-    let mut ddl_tel = self.get_gl_new_tel(0, true, false); //Refresh->nur einmaliges Senden
+    let mut ddl_tel = self.get_gl_new_tel(0, TelOptions::refresh(false)); //Refresh->nur einmaliges Senden
     self.send_uid_regcounter(&mut ddl_tel);
     Some(ddl_tel)
   }
+  /// Power wurde eingeschaltet (Übergang von Power Off auf On).
+  /// Nach einem Stromunterbruch können MFX Dekoder ihre SID verloren haben und mit alter Richtung
+  /// losfahren. Für alle initialisierten GL's wird deshalb vor dem nächsten Lokbefehl wieder die
+  /// SID Zuordnung gesendet, und alle GL's sollen sofort mit ihrem letzten Zustand gesendet werden.
+  fn power_on_resync(&mut self) -> bool {
+    for adr in 1..=MAX_MFX_GL_ADRESSE as usize {
+      if self.uid[adr] != 0 {
+        self.new_sid[adr] = true;
+      }
+    }
+    //Ohne Power kann kein Dekoder antworten, Anwesenheitsprüfung beginnt neu
+    let jetzt = Instant::now();
+    for letzte_antwort in self.letzte_antwort.values_mut() {
+      *letzte_antwort = jetzt;
+    }
+    true
+  }
   /// Liefert zusätzliche, Protokoll spezifische Telegramme (z.B. bei MFX die UID & Neuanmeldezähler der Zentrale)
   /// Liefert None, wenn es nichts zur versenden gibt
   /// Hier für MFX wird periodisch die UID / Neuanmeldezähler der Zentrale und Suche
@@ -1100,11 +1479,18 @@ impl DdlProtokoll for MfxProtokoll {
         self.zeitpunkt_uid = now;
         //UID Zentrale und Neuanmeldezähler
         let mut tel = self.get_idle_tel().unwrap();
-        //Keine Suche wenn SM aktiv ist oder bereits eine Lokanmeldung läuft
-        if !self.sm_aktiv && self.read_gl_parameter.is_none() {
-          //Suche neue Dekoder, nächstes Telegramm
-          tel.daten.push(Vec::with_capacity(MFX_MAX_LEN_SEARCH_NEW));
-          self.send_search_new_decoder(&mut tel);
+        //Keine Suche wenn SM aktiv ist, bereits eine Lokanmeldung läuft oder die Rückmeldung auf die
+        //letzte Suche / Ping noch aussteht
+        if !self.sm_aktiv
+          && self.read_gl_parameter.is_none()
+          && (self.rds_1_bit_fenster == Rds1BitFenster::Keines)
+        {
+          //Suche neue Dekoder oder Ping, nächstes Telegramm
+          tel.neues_tel(MFX_MAX_LEN_SEARCH_NEW);
+          match self.get_naechste_ping_adr() {
+            Some(adr) => self.send_ping(&mut tel, adr),
+            None => self.send_search_new_decoder(&mut tel),
+          }
         }
         result = Some(tel);
       }
@@ -1112,6 +1498,11 @@ impl DdlProtokoll for MfxProtokoll {
     result
   }
 
+  /// Liefert den Zeitpunkt, an dem "get_protokoll_telegrammme" das nächste UID Telegramm liefert.
+  fn get_naechster_protokoll_zeitpunkt(&self) -> Option<Instant> {
+    Some(self.zeitpunkt_uid + INTERVALL_UID)
+  }
+
   /// Auswertung automatische Dekoderanmeldung (z.B. bei MFX).
   /// Notwendige Telegramme zur Suche müssen über "get_protokoll_telegrammme" ausgegeben und eine Rückmeldung
   /// verlangt werden.
@@ -1119,12 +1510,35 @@ impl DdlProtokoll for MfxProtokoll {
   /// # Arguments
   /// * daten_rx : Die Art der erwarteten Rückmeldung
   fn eval_neu_anmeldung(&mut self, daten_rx: &DdlTelRx) -> ResultNeuAnmeldung {
-    self.eval_send_search_new_decoder(daten_rx)
+    //Ohne offenes Rückmeldefenster (z.B. CV Telegramm) gibt es nichts auszuwerten
+    let pos_feedback = if self.rds_1_bit_fenster == Rds1BitFenster::Keines {
+      None
+    } else {
+      self.eval_rds_1_bit(daten_rx)
+    };
+    match (self.rds_1_bit_fenster, pos_feedback) {
+      (Rds1BitFenster::Ping(adr), Some(vorhanden)) => {
+        self.rds_1_bit_fenster = Rds1BitFenster::Keines;
+        self.eval_ping(adr, vorhanden);
+      }
+      (Rds1BitFenster::Suche, Some(_)) => {
+        self.rds_1_bit_fenster = Rds1BitFenster::Keines;
+        return self.eval_send_search_new_decoder(pos_feedback);
+      }
+      _ => {}
+    }
+    self.eval_send_search_new_decoder(None)
+  }
+
+  /// Liefert alle Veränderungen der Anwesenheit von Dekodern seit dem letzten Aufruf (Ping).
+  fn get_gl_anwesenheit(&mut self) -> Vec<GLAnwesenheit> {
+    std::mem::take(&mut self.anwesenheit_neu)
   }
 
   /// Auslesen optionale GL Parameter (z.B. MFX Lokname und Funktionen)
   /// Liefert ResultReadGlParameter::Error zurück, wenn ein Fehler aufgetreten ist oder vom Protokoll nicht untertsützt.
   /// Liefert ResultReadGlParameter::Busy zurück, wenn das Auslesen im Gange ist
+  /// Liefert ResultReadGlParameter::Fortschritt zurück, wenn das Auslesen im Gange ist und sich der Fortschritt verändert hat
   /// Liefert ResultReadGlParameter::Ok mit den Parametern zurück, wenn abgeschlossen.
   /// Falls "eval_neu_anmeldung" "Some" liefert, sollte hier nicht Error zurückgegeben werden.
   /// Falls vom Protokoll nicht unterstützt, dann Ok mit leerer Liste.
@@ -1135,12 +1549,25 @@ impl DdlProtokoll for MfxProtokoll {
     if let Some(adr_read_gl_parameter) = self.read_gl_parameter {
       if adr_read_gl_parameter == adr {
         //Ist ein Ergebnis vorhanden?
-        if let Ok(init_parameter) = self.rx_from_rds_lok_init.try_recv() {
+        let antwort = self.rx_from_rds_lok_init.try_recv();
+        if let Ok(init_parameter) = antwort {
           //Antwort vorhanden
-          if init_parameter.is_some() {
+          if let Some((init_parameter, anz_f)) = init_parameter {
             //Auslesen hat funktioniert
-            info!("MFX Start read GL Parameter fertig Adr={}", adr);
-            result = ResultReadGlParameter::Ok(init_parameter.unwrap());
+            //Für nächste Anmeldung dieser UID merken
+            self.lok_cache.set(
+              self.uid[adr as usize],
+              MfxLokDaten {
+                param: init_parameter.clone(),
+                anz_f,
+              },
+            );
+            let anz_f = anz_f.min(self.anz_f_auto_max);
+            info!(
+              "MFX Start read GL Parameter fertig Adr={} Anzahl Funktionen={}",
+              adr, anz_f
+            );
+            result = ResultReadGlParameter::Ok(init_parameter, Some(anz_f));
             //Fertig
             self.read_gl_parameter = None;
           } else {
@@ -1149,6 +1576,22 @@ impl DdlProtokoll for MfxProtokoll {
             result = ResultReadGlParameter::Error;
             self.read_gl_parameter = None;
           }
+        } else if let Err(TryRecvError::Disconnected) = antwort {
+          //RDS Thread nicht mehr vorhanden, es kommt nie ein Ergebnis
+          warn!(
+            "MFX Error read GL Parameter Adr={}, RDS Thread nicht vorhanden",
+            adr
+          );
+          self.tx_to_rds.set_gestoert();
+          result = ResultReadGlParameter::Error;
+          self.read_gl_parameter = None;
+        } else {
+          //Noch in Arbeit, veränderten Fortschritt melden
+          let fortschritt = self.rds_fortschritt.load(Ordering::Relaxed);
+          if fortschritt != self.rds_fortschritt_gemeldet {
+            self.rds_fortschritt_gemeldet = fortschritt;
+            result = ResultReadGlParameter::Fortschritt(fortschritt);
+          }
         }
       } else {
         //Auslesen im Gange, es kann nicht gleichzeitig eine andere Adresse ausgelesen werden
@@ -1158,18 +1601,74 @@ impl DdlProtokoll for MfxProtokoll {
         );
         result = ResultReadGlParameter::Error;
       }
+    } else if let Some(lok_daten) = self.lok_cache.get(self.uid[adr as usize]) {
+      //Bekannte Lok, Lokparameter aus Cache
+      let anz_f = lok_daten.anz_f.min(self.anz_f_auto_max);
+      info!(
+        "MFX read GL Parameter Adr={} aus Lokcache Anzahl Funktionen={}",
+        adr, anz_f
+      );
+      result = ResultReadGlParameter::Ok(lok_daten.param.clone(), Some(anz_f));
     } else {
       //Auslesen Lokparameter starten
       info!("MFX Start read GL Parameter Adr={}", adr);
       self.read_gl_parameter = Some(adr);
-      self
+      self.rds_fortschritt.store(0, Ordering::Relaxed);
+      self.rds_fortschritt_gemeldet = 0;
+      if !self
         .tx_to_rds
         .send(MfxRdsJob::new_read_all_init_parameter(adr))
-        .unwrap();
+      {
+        warn!(
+          "MFX Error read GL Parameter Adr={}, RDS Thread nicht vorhanden",
+          adr
+        );
+        result = ResultReadGlParameter::Error;
+        self.read_gl_parameter = None;
+      }
     }
     result
   }
 
+  /// Liefert die Störung des RDS Threads, wenn dieser nicht mehr vorhanden ist (z.B. Panic).
+  /// Ohne RDS Thread (nicht konfiguriert) gibt es keine Störung.
+  fn get_stoerungen(&mut self) -> Vec<String> {
+    let Some(rds_thread) = &self.rds_thread else {
+      return vec![];
+    };
+    if rds_thread.is_finished() {
+      self.tx_to_rds.set_gestoert();
+    }
+    self.tx_to_rds.take_stoerung().into_iter().collect()
+  }
+
+  /// Liefert alle seit dem letzten Aufruf aufgezeichneten RDS Rückmeldungen für die SRCP Session,
+  /// welche die Aufzeichnung eingeschaltet hat.
+  fn get_diagnose_meldungen(&mut self) -> Vec<(u32, String)> {
+    let meldungen = self.rx_capture_from_rds.try_iter();
+    if let Some(session_id) = self.rds_capture_session {
+      meldungen
+        .map(|meldung| (session_id, format!("RDS {}", meldung)))
+        .collect()
+    } else {
+      //Verspätete Meldungen nach dem Ausschalten verwerfen
+      meldungen.for_each(drop);
+      vec![]
+    }
+  }
+
+  /// Eine SRCP Kommando Session wurde beendet.
+  /// War an diese Session die RDS Aufzeichnung eingeschaltet, dann wird sie ausgeschaltet.
+  /// # Arguments
+  /// * session_id - Session ID der beendeten Session
+  fn session_end(&mut self, session_id: u32) {
+    if self.rds_capture_session == Some(session_id) {
+      self.rds_capture_session = None;
+      self.rds_capture_aktiv.store(false, Ordering::Relaxed);
+      info!("MFX RDS Aufzeichnung aus, Session {} beendet", session_id);
+    }
+  }
+
   /// Dekoderkonfiguration (SM) Start
   /// # Arguments
   /// * smParameter : Optinal weiterer Protokollspezifischer Parameter -> hier nicht verwendet
@@ -1185,12 +1684,49 @@ impl DdlProtokoll for MfxProtokoll {
   /// # Arguments
   /// * sm_para - Alle notwndigen Paramater für SM Read/Write
   fn sm_read_write(&mut self, sm_para: &SmReadWrite) {
-    self
-      .tx_to_rds
-      .send(MfxRdsJob::ReadWriteCA {
-        ca_parameter: sm_para.clone(),
-      })
-      .unwrap();
+    if (sm_para.sm_type == SM_TYPE_REGCOUNT) || (sm_para.sm_type == SM_TYPE_REGNEW) {
+      //Neuanmeldezähler wird direkt hier verwaltet, kein Zugriff auf Dekoder
+      self.sm_antwort = Some(self.sm_registration_counter(sm_para));
+    } else if sm_para.sm_type == SM_TYPE_LOKCACHE {
+      //Lokcache wird direkt hier verwaltet, kein Zugriff auf Dekoder
+      self.sm_antwort = Some(self.sm_lok_cache(sm_para));
+    } else if sm_para.sm_type == SM_TYPE_RDSCAPTURE {
+      //Aufzeichnung wird direkt hier ein-/ausgeschaltet, kein Zugriff auf Dekoder
+      self.sm_antwort = Some(self.sm_rds_capture(sm_para));
+    } else if (sm_para.sm_type == SM_TYPE_LOKNAME) || (sm_para.sm_type == SM_TYPE_BENUTZER) {
+      //Ein neuer Lokname muss bei der nächsten Anmeldung auch aus dem Dekoder gelesen werden
+      if let SmReadWriteType::WriteText(_) = sm_para.val {
+        if let Some(uid) = self.uid.get(sm_para.adr as usize) {
+          self.lok_cache.entfernen(*uid);
+        }
+      }
+      self.sm_to_rds(
+        sm_para,
+        MfxRdsJob::ReadWriteText {
+          text_parameter: sm_para.clone(),
+        },
+      );
+    } else if sm_para.sm_type == SM_TYPE_IDENT {
+      self.sm_to_rds(
+        sm_para,
+        MfxRdsJob::ReadIdent {
+          ident_parameter: sm_para.clone(),
+        },
+      );
+    } else {
+      //Geschriebene CA's können Lokname oder Funktionen verändern
+      if let SmReadWriteType::Write(_) = sm_para.val {
+        if let Some(uid) = self.uid.get(sm_para.adr as usize) {
+          self.lok_cache.entfernen(*uid);
+        }
+      }
+      self.sm_to_rds(
+        sm_para,
+        MfxRdsJob::ReadWriteCA {
+          ca_parameter: sm_para.clone(),
+        },
+      );
+    }
   }
   /// Liefert alle in "sm_read" und "sm_write" unterstützten Typen mit der Anzahl erwarteter Parameter
   /// ohne Value für SET.
@@ -1201,11 +1737,48 @@ impl DdlProtokoll for MfxProtokoll {
     let mut result: HashMap<String, usize> = HashMap::new();
     //4 Parameter bei Zugriff auf MFX Konfigvariabeln: Block, CA, CA_Index, Index
     result.insert("CAMFX".to_string(), 4);
+    //Dito, 4 Bytes ab Index als 32 Bit Wert
+    result.insert(SM_TYPE_CAMFX32.to_string(), 4);
+    //Lokname und Benutzername als Text, keine Parameter
+    result.insert(SM_TYPE_LOKNAME.to_string(), 0);
+    result.insert(SM_TYPE_BENUTZER.to_string(), 0);
+    //Neuanmeldezähler der Zentrale, keine Parameter, Dekoderadresse wird ignoriert
+    result.insert(SM_TYPE_REGCOUNT.to_string(), 0);
+    result.insert(SM_TYPE_REGNEW.to_string(), 0);
+    //Lokparameter des Dekoders im Lokcache, keine Parameter
+    result.insert(SM_TYPE_LOKCACHE.to_string(), 0);
+    //Aufzeichnung RDS Rückmeldungen, keine Parameter, Dekoderadresse wird ignoriert
+    result.insert(SM_TYPE_RDSCAPTURE.to_string(), 0);
+    //Dekoderidentifikation aus Block Grundeinstellungen, keine Parameter
+    result.insert(SM_TYPE_IDENT.to_string(), 0);
     Some(result)
   }
+  /// LOKNAME und BENUTZER erwarten bei SET einen Text (max. 16 ASCII Zeichen)
+  /// # Arguments
+  /// * sm_type - SM Type aus "sm_get_all_types"
+  fn sm_is_text_type(&self, sm_type: &str) -> bool {
+    (sm_type == SM_TYPE_LOKNAME) || (sm_type == SM_TYPE_BENUTZER)
+  }
   /// Liefert die Antwort sm_read_write zurück.
   /// None wenn keine Antwort verfügbar.
   fn sm_get_answer(&mut self) -> Option<SmReadWrite> {
-    self.rx_from_rds_read_write_ca.try_recv().ok()
+    if self.sm_antwort.is_some() {
+      return self.sm_antwort.take();
+    }
+    match self.rx_from_rds_read_write_ca.try_recv() {
+      Ok(antwort) => {
+        self.sm_auftrag_offen = None;
+        Some(antwort)
+      }
+      Err(TryRecvError::Empty) => None,
+      Err(TryRecvError::Disconnected) => {
+        self.tx_to_rds.set_gestoert();
+        //Ein bereits übergebener Auftrag wird nie mehr beantwortet, nicht auf Timeout warten
+        self.sm_auftrag_offen.take().map(|mut antwort| {
+          antwort.val = SmReadWriteType::ResultErr;
+          antwort
+        })
+      }
+    }
   }
 }
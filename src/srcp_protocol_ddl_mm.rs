@@ -1,6 +1,9 @@
 use std::time::Duration;
 
-use crate::srcp_protocol_ddl::{DdlProtokoll, DdlTel, GLDriveMode};
+use crate::srcp_protocol_ddl::{
+  begrenze_funk_anz, funktionen_maske, spi_dauer, DdlProtokoll, DdlTel, DdlTelSegment, GLDriveMode,
+  GlInitParams, TelOptions,
+};
 
 /// SPI Baudrate für Märklin / Motorola Protokoll.
 /// Diese wäre eigentlich genau 38461 Baud (1 Bit=26us, 1Byte=208us)
@@ -14,7 +17,7 @@ const SPI_BAUDRATE_MAERKLIN_LOCO: u32 = 38461;
 /// - die Wiederholung ins selbe Paket gepackt.
 /// - Pause am Anfang und vor Wiederholung mit 0 Bytes gefüllt.
 pub const SPI_BAUDRATE_MAERKLIN_LOCO_2: u32 = 2 * SPI_BAUDRATE_MAERKLIN_LOCO;
-const SPI_BAUDRATE_MAERKLIN_FUNC_2: u32 = 2 * SPI_BAUDRATE_MAERKLIN_LOCO_2;
+pub const SPI_BAUDRATE_MAERKLIN_FUNC_2: u32 = 2 * SPI_BAUDRATE_MAERKLIN_LOCO_2;
 /// - 0 Bytes für Pause vor Paket: 4.2ms (Lok), resp. 2.1ms (Schaltdekoder) -> wegen bei doppleter Baudrate 1 Byte 104us (Lok). 62us (Schalt) = 42 Bytes
 const MM_LEN_PAUSE_START: usize = 42;
 /// -  Pause vor Paket: 4.2ms (Lok)
@@ -39,12 +42,18 @@ const MM_PAUSE_ENDE_GA: Duration = Duration::from_micros(900);
 const MM_LEN_PAKET: usize = 18 * 2;
 /// - 0 Bytes für Pause zwischen Paketen: 3 * (t 2 Bit, 208us / 416us) -> wegen doppelter Baudrate also 2 * 3 * 2 = 12 Bytes 0x00
 const MM_LEN_PAUSE_BETWEEN: usize = 2 * 3 * 2;
-/// - Paket Wiederholung
-/// Total also 42 + 36 + 12 + 36 = 126 Bytes -> DMA Mode!
-const MM_LEN: usize = MM_LEN_PAUSE_START + MM_LEN_PAKET + MM_LEN_PAUSE_BETWEEN + MM_LEN_PAKET;
+// - Paket Wiederholung
+// Total nach Auflösen der Pausen und Wiederholung also 42 + 36 + 12 + 36 = 126 Bytes -> DMA Mode!
+
 /// Mit doppelter Baudrate je die beiden Bytes für 0 und 1 Übertragung
 const MM_BIT_0_0: u8 = 0xC0;
 const MM_BIT_0_0_GA: u8 = 0xE0; //Eigentlich wäre das obige 0xC0 korrekt, habe aber Schaltdekoder die damit nicht funktionieren....
+/// Default Breite Impuls 0 für GA in SPI Bits (von 8) des ersten Bytes, ergibt MM_BIT_0_0_GA
+pub const MM_GA_IMPULS_0_DEFAULT: u32 = MM_BIT_0_0_GA.leading_ones();
+/// Erlaubte Breite Impuls 0 für GA in SPI Bits, muss kürzer als der Impuls 1 sein
+pub const MM_GA_IMPULS_0_BEREICH: std::ops::RangeInclusive<u32> = 1..=7;
+/// Default Anzahl Wiederholungen GA Telegramm (jedes Telegramm enthält das Paket bereits doppelt)
+pub const MM_GA_WIEDERHOLUNGEN_DEFAULT: usize = 2;
 const MM_BIT_0_1: u8 = 0x00;
 const MM_BIT_1_0: u8 = 0xFF;
 const MM_BIT_1_1: u8 = 0xFC;
@@ -54,12 +63,15 @@ static MM_BIT_L: &'static [u8] = &[MM_BIT_0_0, MM_BIT_0_1, MM_BIT_0_0, MM_BIT_0_
 static MM_BIT_H: &'static [u8] = &[MM_BIT_1_0, MM_BIT_1_1, MM_BIT_1_0, MM_BIT_1_1]; //11
 static MM_BIT_O: &'static [u8] = &[MM_BIT_1_0, MM_BIT_1_1, MM_BIT_0_0, MM_BIT_0_1]; //10
 static MM_BIT_U: &'static [u8] = &[MM_BIT_0_0, MM_BIT_0_1, MM_BIT_1_0, MM_BIT_1_1]; //01
-static MM_BIT_L_GA: &'static [u8] = &[MM_BIT_0_0_GA, MM_BIT_0_1, MM_BIT_0_0_GA, MM_BIT_0_1]; //00 für GA, siehe oben
-static MM_BIT_O_GA: &'static [u8] = &[MM_BIT_1_0, MM_BIT_1_1, MM_BIT_0_0_GA, MM_BIT_0_1]; //10 für GA, siehe oben
 
 /// MM2 & 3 Bitmuster für F1-4, Bit 3 ist jeweils der Zustand der Funktion
 static MM_F1_4: &'static [u8] = &[0b0011, 0b0100, 0b0110, 0b0111];
 
+/// Default Anzahl Richtungsimpulse (MM Doppelpakete mit Fahrstufe 1) bei MM1 Richtungswechsel mit Pause
+pub const MM_RICHTUNGSWECHSEL_IMPULSE_DEFAULT: usize = 2;
+/// Maximale Pause bei MM1 Richtungswechsel in ms. Die Pause wird im DDL Thread abgewartet,
+/// während dieser Zeit werden keine anderen Kommandos bearbeitet.
+pub const MM_RICHTUNGSWECHSEL_PAUSE_MAX: u64 = 500;
 /// Pause zwischen zwei Speed Paketen für MM5 (nur für MM5 relevant)
 const MM_PAUSE_MM5: Duration = Duration::from_millis(50);
 /// Max. erlaubte Dekoder Adresse (GA und GL)
@@ -85,6 +97,16 @@ pub struct MMProtokoll {
   old_speed_for_f1_f4: [usize; MAX_MM_ADRESSE as usize + 1],
   /// Anzahl Initialisierte Funktionen
   funk_anz: [usize; MAX_MM_ADRESSE as usize + 1],
+  /// 00 für GA, Impuls 0 verbreitert, siehe MM_BIT_0_0_GA
+  mm_bit_l_ga: [u8; 4],
+  /// 10 für GA, Impuls 0 verbreitert, siehe MM_BIT_0_0_GA
+  mm_bit_o_ga: [u8; 4],
+  /// Anzahl Wiederholungen GA Telegramm
+  ga_wiederholungen: usize,
+  /// MM1 Richtungswechsel: Pause nach Halt und nach Richtungsimpuls, 0: Richtungsimpuls ohne Halt und Pause
+  richtungswechsel_pause: Duration,
+  /// MM1 Richtungswechsel: Anzahl Richtungsimpulse (MM Doppelpakete)
+  richtungswechsel_impulse: usize,
 }
 impl MMProtokoll {
   /// Neue Instanz erstellen
@@ -97,8 +119,33 @@ impl MMProtokoll {
       old_funktionen: [0; MAX_MM_ADRESSE as usize + 1],
       old_speed_for_f1_f4: [0; MAX_MM_ADRESSE as usize + 1],
       funk_anz: [0; MAX_MM_ADRESSE as usize + 1],
+      mm_bit_l_ga: [MM_BIT_0_0_GA, MM_BIT_0_1, MM_BIT_0_0_GA, MM_BIT_0_1],
+      mm_bit_o_ga: [MM_BIT_1_0, MM_BIT_1_1, MM_BIT_0_0_GA, MM_BIT_0_1],
+      ga_wiederholungen: MM_GA_WIEDERHOLUNGEN_DEFAULT,
+      richtungswechsel_pause: Duration::ZERO,
+      richtungswechsel_impulse: MM_RICHTUNGSWECHSEL_IMPULSE_DEFAULT,
     }
   }
+  /// GA Timing für ältere Schaltdekoder (z.B. k83) anpassen
+  /// # Arguments
+  /// * impuls_0 - Breite Impuls 0 in SPI Bits (MM_GA_IMPULS_0_BEREICH), Norm ist 2, Default MM_GA_IMPULS_0_DEFAULT
+  /// * wiederholungen - Anzahl Wiederholungen GA Telegramm, min. 1
+  pub fn set_ga_timing(&mut self, impuls_0: u32, wiederholungen: usize) {
+    let bit_0_0_ga = !(0xFFu8 >> impuls_0);
+    self.mm_bit_l_ga = [bit_0_0_ga, MM_BIT_0_1, bit_0_0_ga, MM_BIT_0_1];
+    self.mm_bit_o_ga = [MM_BIT_1_0, MM_BIT_1_1, bit_0_0_ga, MM_BIT_0_1];
+    self.ga_wiederholungen = wiederholungen.max(1);
+  }
+  /// MM1 Richtungswechsel für ältere Dekoder, die nur zuverlässig umschalten, wenn sie vorher
+  /// angehalten haben und den Richtungsimpuls mehrfach erhalten:
+  /// Halt, Pause, Richtungsimpuls(e), Pause, neue Fahrstufe.
+  /// # Arguments
+  /// * pause - Pause nach Halt und nach Richtungsimpuls, 0: nur Richtungsimpuls wie bisher, max. MM_RICHTUNGSWECHSEL_PAUSE_MAX
+  /// * impulse - Anzahl Richtungsimpulse (MM Doppelpakete), min. 1
+  pub fn set_richtungswechsel(&mut self, pause: Duration, impulse: usize) {
+    self.richtungswechsel_pause = pause.min(Duration::from_millis(MM_RICHTUNGSWECHSEL_PAUSE_MAX));
+    self.richtungswechsel_impulse = impulse.max(1);
+  }
   /// Pause am MM Anfang und MM 4 Adressbits (trinär codiert)
   /// # Arguments
   /// * ddl_tel - Telegramm, zu dessen letztem Telegramm die Adressbits hinzugefügtw erden sollen
@@ -107,39 +154,31 @@ impl MMProtokoll {
   fn add_mm_pause_adr(&self, ddl_tel: &mut DdlTel, mut adr_dekoder: u32, ga_timing: bool) {
     assert!(adr_dekoder < 81, "MM Max Lokadresse ist 80");
     //Pause am Anfang
-    ddl_tel
-      .daten
-      .last_mut()
-      .unwrap()
-      .resize(MM_LEN_PAUSE_START, 0);
-    let mm_bit_l = if ga_timing { MM_BIT_L_GA } else { MM_BIT_L };
-    let mm_bit_o = if ga_timing { MM_BIT_O_GA } else { MM_BIT_O };
+    ddl_tel.add_pause(ddl_tel.pause_start);
+    let mm_bit_l = if ga_timing {
+      &self.mm_bit_l_ga
+    } else {
+      MM_BIT_L
+    };
+    let mm_bit_o = if ga_timing {
+      &self.mm_bit_o_ga
+    } else {
+      MM_BIT_O
+    };
     for _ in 0..4 {
       let adr_trit = adr_dekoder % 3;
       adr_dekoder /= 3;
       match adr_trit {
         0 => {
-          ddl_tel
-            .daten
-            .last_mut()
-            .unwrap()
-            .extend_from_slice(mm_bit_l);
+          ddl_tel.payload_mut().extend_from_slice(mm_bit_l);
         }
         1 => {
-          ddl_tel
-            .daten
-            .last_mut()
-            .unwrap()
-            .extend_from_slice(MM_BIT_H);
+          ddl_tel.payload_mut().extend_from_slice(MM_BIT_H);
         }
         2 => {
-          ddl_tel
-            .daten
-            .last_mut()
-            .unwrap()
-            .extend_from_slice(mm_bit_o);
+          ddl_tel.payload_mut().extend_from_slice(mm_bit_o);
         }
-        _ => assert!(false), //Kann nicht vorkommen da Rest der Division mit 3
+        _ => unreachable!(), //Kann nicht vorkommen da Rest der Division mit 3
       }
     }
   }
@@ -152,36 +191,24 @@ impl MMProtokoll {
   fn add_mm1_fnkt_value(
     &self, ddl_tel: &mut DdlTel, fnkt: bool, mut value: usize, ga_timing: bool,
   ) {
-    let mm_bit_l = if ga_timing { MM_BIT_L_GA } else { MM_BIT_L };
+    let mm_bit_l = if ga_timing {
+      &self.mm_bit_l_ga
+    } else {
+      MM_BIT_L
+    };
     //Zuerst kommt die Funktion
     if fnkt {
-      ddl_tel
-        .daten
-        .last_mut()
-        .unwrap()
-        .extend_from_slice(MM_BIT_H);
+      ddl_tel.payload_mut().extend_from_slice(MM_BIT_H);
     } else {
-      ddl_tel
-        .daten
-        .last_mut()
-        .unwrap()
-        .extend_from_slice(mm_bit_l);
+      ddl_tel.payload_mut().extend_from_slice(mm_bit_l);
     }
     //Dann Value, 4 Bit, LSB als erstes
     assert!(value <= 0x0F);
     for _ in 0..4 {
       if (value & 0x01) == 0 {
-        ddl_tel
-          .daten
-          .last_mut()
-          .unwrap()
-          .extend_from_slice(mm_bit_l);
+        ddl_tel.payload_mut().extend_from_slice(mm_bit_l);
       } else {
-        ddl_tel
-          .daten
-          .last_mut()
-          .unwrap()
-          .extend_from_slice(MM_BIT_H);
+        ddl_tel.payload_mut().extend_from_slice(MM_BIT_H);
       }
       value >>= 1;
     }
@@ -196,7 +223,7 @@ impl MMProtokoll {
     &self, ddl_tel: &mut DdlTel, fnkt: &[u8], mut speed: usize, dir: GLDriveMode,
   ) {
     //Zuerst kommt die Funktion
-    ddl_tel.daten.last_mut().unwrap().extend_from_slice(fnkt);
+    ddl_tel.payload_mut().extend_from_slice(fnkt);
     //Bei MM2 wird nur je ein Bit des Paares für die Geschwindigkeit verwendet,
     //das andere für die absolute Richtungsinfo.
     //Parallel zum Speed kodieren wir hier die abs. Richtungsbits
@@ -221,31 +248,15 @@ impl MMProtokoll {
     for _ in 0..4 {
       if (speed & 0x01) == 0 {
         if (abs_dir & 0x01) == 0 {
-          ddl_tel
-            .daten
-            .last_mut()
-            .unwrap()
-            .extend_from_slice(MM_BIT_L);
+          ddl_tel.payload_mut().extend_from_slice(MM_BIT_L);
         } else {
-          ddl_tel
-            .daten
-            .last_mut()
-            .unwrap()
-            .extend_from_slice(MM_BIT_U);
+          ddl_tel.payload_mut().extend_from_slice(MM_BIT_U);
         }
       } else {
         if (abs_dir & 0x01) == 0 {
-          ddl_tel
-            .daten
-            .last_mut()
-            .unwrap()
-            .extend_from_slice(MM_BIT_O);
+          ddl_tel.payload_mut().extend_from_slice(MM_BIT_O);
         } else {
-          ddl_tel
-            .daten
-            .last_mut()
-            .unwrap()
-            .extend_from_slice(MM_BIT_H);
+          ddl_tel.payload_mut().extend_from_slice(MM_BIT_H);
         }
       }
       speed >>= 1;
@@ -287,16 +298,30 @@ impl MMProtokoll {
         if speed_used > 15 {
           speed_used = 15;
         }
+        let f0 = (funktionen & 0x01) != 0;
         //Richtungswechsel
         if drive_mode_used != self.old_drive_mode[adr as usize] {
-          speed_used = 1;
+          if self.richtungswechsel_pause.is_zero() {
+            speed_used = 1;
+          } else {
+            //Halt, danach Pause
+            self.add_mm1_fnkt_value(ddl_tel, f0, 0, false);
+            ddl_tel.delay = self.richtungswechsel_pause;
+            //Richtungsimpulse, jeder als MM Doppelpaket. Da das Telegramm mit einer Wiederholung endet,
+            //ergänzt "complete_mm_paket" hier nichts mehr. Danach Pause.
+            ddl_tel.neues_tel(MM_LEN_PAKET);
+            let pause_between = spi_dauer(MM_LEN_PAUSE_BETWEEN, ddl_tel.hz);
+            for _ in 0..self.richtungswechsel_impulse {
+              self.add_mm_pause_adr(ddl_tel, adr_mm_tel, false);
+              self.add_mm1_fnkt_value(ddl_tel, f0, 1, false);
+              ddl_tel.add_repeat(2, pause_between);
+            }
+            //Neue Fahrstufe
+            ddl_tel.neues_tel(MM_LEN_PAKET);
+            self.add_mm_pause_adr(ddl_tel, adr_mm_tel, false);
+          }
         }
-        self.add_mm1_fnkt_value(
-          ddl_tel,
-          (funktionen & 0x01) != 0, //F0
-          speed_used,
-          false,
-        );
+        self.add_mm1_fnkt_value(ddl_tel, f0, speed_used, false);
       }
       MmVersion::V2 => {
         //14 Speeds, F0-4, abs. Richtung
@@ -425,7 +450,7 @@ impl MMProtokoll {
             drive_mode_used,
           );
           //2. Telegramm vorbereiten
-          ddl_tel.daten.push(Vec::with_capacity(MM_LEN));
+          ddl_tel.neues_tel(MM_LEN_PAKET);
           self.add_mm_pause_adr(ddl_tel, adr_mm_tel, false);
         }
         self.add_mm2_fnkt_value(
@@ -457,12 +482,17 @@ impl MMProtokoll {
   /// - Pause zwischen den beiden Paketen
   /// - Paketwiederholung
   fn complete_mm_paket(&self, ddl_tel: &mut DdlTel) {
+    let pause_between = spi_dauer(MM_LEN_PAUSE_BETWEEN, ddl_tel.hz);
     for ddl_daten in ddl_tel.daten.iter_mut() {
-      //Pause zwischen den beiden Paketen ergänzen
-      ddl_daten.resize(ddl_daten.len() + MM_LEN_PAUSE_BETWEEN, 0);
-      //Wiederholung
-      let tel: Vec<u8> = ddl_daten[MM_LEN_PAUSE_START..MM_LEN_PAKET + MM_LEN_PAUSE_START].to_vec();
-      ddl_daten.extend(&tel);
+      //Telegramme, die ihre Wiederholungen bereits enthalten (MM1 Richtungsimpulse), bleiben unverändert
+      if matches!(ddl_daten.last(), Some(DdlTelSegment::Repeat { .. })) {
+        continue;
+      }
+      //Wiederholung mit Pause zwischen den beiden Paketen
+      ddl_daten.push(DdlTelSegment::Repeat {
+        n: 2,
+        pause: pause_between,
+      });
     }
   }
 }
@@ -478,15 +508,10 @@ impl DdlProtokoll for MMProtokoll {
   /// Liefert immer None, kein GL Init Tel. notwendig
   /// # Arguments
   /// * adr - Adresse der Lok
-  /// * uid - nicht verwendet
-  /// * funk_anz - Anzahl tatsächlich verwendete Funktionen. Kann, je nach Protokoll, dazu
-  ///              verwendet werden, nur Telegramme der verwendeten Funktionen zu senden.
-  /// * power - nicht verwendet
-  /// * trigger - Oszi Trigger?
-  fn init_gl(
-    &mut self, adr: u32, _uid: Option<u32>, funk_anz: usize, _power: bool, _trigger: bool,
-  ) -> Option<DdlTel> {
-    self.funk_anz[adr as usize] = funk_anz;
+  /// * params - Nur Anzahl tatsächlich verwendete Funktionen verwendet
+  fn init_gl(&mut self, adr: u32, params: &GlInitParams) -> Option<DdlTel> {
+    self.funk_anz[adr as usize] =
+      begrenze_funk_anz("MM", adr, params.funk_anz, self.get_gl_anz_f());
     None
   }
   /// Liefert die max. erlaubte Lokadresse
@@ -525,18 +550,16 @@ impl DdlProtokoll for MMProtokoll {
   /// Liefert ein leeres GL Telegramm zur Verwendung in "get_gl_basis_tel" und / oder "get_gl_zusatz_tel".
   /// # Arguments
   /// * adr - Adresse der Lok, keine Verwendunbg, nur Debug Support
-  /// * refresh - Wenn true: Aufruf aus Refres Cycle, einmalige Telegramm Versendung,
-  ///             Wenn false: Aufruf wegen neuem Lokkommando, mehrmaliges Versenden
-  /// * trigger - Oszi Trigger?
-  fn get_gl_new_tel(&mut self, adr: u32, refresh: bool, trigger: bool) -> DdlTel {
+  /// * optionen - Refresh und Oszi Trigger, siehe "TelOptions"
+  fn get_gl_new_tel(&mut self, adr: u32, optionen: TelOptions) -> DdlTel {
     let mut tel = DdlTel::new(
       adr,
       SPI_BAUDRATE_MAERKLIN_LOCO_2,
       Duration::ZERO,
       true,
-      MM_LEN,
-      if refresh { 1 } else { 2 }, //Neue Kommandos 2-fach senden
-      trigger,
+      MM_LEN_PAKET,
+      if optionen.refresh { 1 } else { 2 }, //Neue Kommandos 2-fach senden
+      optionen.trigger,
     );
     tel.pause_start = MM_PAUSE_START_GL;
     tel.pause_ende = MM_PAUSE_ENDE_GL;
@@ -563,6 +586,8 @@ impl DdlProtokoll for MMProtokoll {
     &mut self, adr: u32, drive_mode: GLDriveMode, speed: usize, _speed_steps: usize,
     funktionen: u64, ddl_tel: &mut DdlTel,
   ) {
+    //Nur Funktionen gemäss INIT
+    let funktionen = funktionen & funktionen_maske(self.funk_anz[adr as usize]);
     self.get_gl_basis_tel_raw(adr, drive_mode, speed, funktionen, ddl_tel, self.version);
   }
   /// Erzeugt das / die Fx Zusatztelegramm(e) für GL.
@@ -575,6 +600,8 @@ impl DdlProtokoll for MMProtokoll {
   /// * funktionen - Die gewünschten Funktionen, berücksichtigt ab "get_Anz_F_Basis"
   /// * ddl_tel - DDL Telegramm, bei dem des neue Telegramm hinzugefügt werden soll.
   fn get_gl_zusatz_tel(&mut self, adr: u32, refresh: bool, funktionen: u64, ddl_tel: &mut DdlTel) {
+    //Nur Funktionen gemäss INIT
+    let funktionen = funktionen & funktionen_maske(self.funk_anz[adr as usize]);
     //Für MM1 gibt es hier nichts zu tun, nur F0 im Basistelegramm
     if self.version != MmVersion::V1 {
       let funk_anz = self.funk_anz[adr as usize];
@@ -587,7 +614,7 @@ impl DdlProtokoll for MMProtokoll {
         if (((self.old_funktionen[adr as usize] ^ funktionen) & mask) != 0) || refresh {
           //Veränderung oder immer verlangt
           //Neues Telegramm erzeugen
-          ddl_tel.daten.push(Vec::with_capacity(MM_LEN));
+          ddl_tel.neues_tel(MM_LEN_PAKET);
           //Als Basis Standard Fahren Telegramm verwenden und dieses dann auf F1-4 ändern
           self.get_gl_basis_tel_raw(
             adr,
@@ -606,8 +633,7 @@ impl DdlProtokoll for MMProtokoll {
             //Bit 11 13 15. Da wegen doppelter Baurate 2 Byte pro Bit nochmals * 2
             let faktor_baudrate = MM_BIT_0.len();
             for j in 0..faktor_baudrate {
-              ddl_tel.daten.last_mut().unwrap()
-                [MM_LEN_PAUSE_START + faktor_baudrate * (11 + bit * 2) + j] =
+              ddl_tel.payload_mut()[faktor_baudrate * (11 + bit * 2) + j] =
                 if (fx_bits & 0b0001) == 0 {
                   MM_BIT_0[j]
                 } else {
@@ -625,17 +651,17 @@ impl DdlProtokoll for MMProtokoll {
   /// Liefert ein leeres GA Telegramm zur Verwendung in "get_ga_tel".
   /// # Arguments
   /// * adr - Adresse GA, keine Verwendunbg, nur Debug Support
-  /// * trigger - Oszi Trigger?
-  fn get_ga_new_tel(&self, adr: u32, trigger: bool) -> DdlTel {
-    //Neue neue Kommandos, kein Refresh -> 2-fach senden
+  /// * optionen - Oszi Trigger, siehe "TelOptions"
+  fn get_ga_new_tel(&self, adr: u32, optionen: TelOptions) -> DdlTel {
+    //Neue neue Kommandos, kein Refresh -> mehrfach senden, Default 2-fach
     let mut tel = DdlTel::new(
       adr,
       SPI_BAUDRATE_MAERKLIN_FUNC_2,
       Duration::ZERO,
       false,
-      MM_LEN,
-      2,
-      trigger,
+      MM_LEN_PAKET,
+      self.ga_wiederholungen,
+      optionen.trigger,
     );
     tel.pause_start = MM_PAUSE_START_GA;
     tel.pause_ende = MM_PAUSE_ENDE_GA;
@@ -652,7 +678,7 @@ impl DdlProtokoll for MMProtokoll {
   ///             Duration::ZERO = Port ignorieren, Value ist der zu sendende Begriff (z.B. Erweiterte Funktionsdekoder NMRA/DCC Signalbegriff)
   ///             Hier nicht verwendet, keine Untesrtützung im MM Protokoll.
   /// * ddl_tel - DDL Telegramm, bei dem des neue Telegramm hinzugefügt werden soll.
-  fn get_ga_tel(&self, adr: u32, port: usize, value: usize, _timeout: Option<Duration>, ddl_tel: &mut DdlTel) -> bool {
+  fn get_ga_tel(&mut self, adr: u32, port: usize, value: usize, _timeout: Option<Duration>, ddl_tel: &mut DdlTel) -> bool {
     //Dekoderadresse: 4 Ausgangspaare auf Dekoder, deshalb adr/4
     //Überlauf auf 81 für Adressen 312 bis 324 ergibt dann die 0, was OK ist.
     let adr_dekoder = (((adr - 1) >> 2) + 1) % 81;
@@ -673,15 +699,11 @@ impl DdlProtokoll for MMProtokoll {
   /// Return None wenn kein Idle Telegramm vorhanden ist
   fn get_idle_tel(&mut self) -> Option<DdlTel> {
     //Idle Telegramm MM ist Telegramm an nie verwendete Lok Adresse 80 (GL Adresse 80 wird als eigentliche Adr 0 ausgegeben)
-    let mut ddl_idle_tel = self.get_gl_new_tel(80, false, false);
+    let mut ddl_idle_tel = self.get_gl_new_tel(80, TelOptions::neu(false));
     //Pause am Anfang
-    ddl_idle_tel
-      .daten
-      .last_mut()
-      .unwrap()
-      .resize(MM_LEN_PAUSE_START, 0);
+    ddl_idle_tel.add_pause(ddl_idle_tel.pause_start);
     {
-      let ddl_daten = ddl_idle_tel.daten.last_mut().unwrap();
+      let ddl_daten = ddl_idle_tel.payload_mut();
       //Adr 80 ist 4 * "O" Trit
       ddl_daten.extend_from_slice(MM_BIT_O);
       ddl_daten.extend_from_slice(MM_BIT_O);
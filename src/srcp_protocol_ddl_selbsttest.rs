@@ -0,0 +1,195 @@
+//! Selbsttest der DDL Protokolle: erzeugt Referenztelegramme mit festen Parametern und gibt deren
+//! SPI Bytes hex kodiert mit einer Timing Zusammenfassung aus.
+//! Damit kann die Ausgabestufe mit einem Logic Analyzer gegen bekannte Bitmuster geprüft werden.
+//! Es wird keine Hardware verwendet und kein Konfigfile gelesen.
+//!
+//! Aufruf: srcpd -t
+
+use std::time::Duration;
+
+use crate::{
+  srcp_mfx_rds::MfxRdsKonfig,
+  srcp_protocol_ddl::{DdlProtokoll, DdlTel, GLDriveMode, GlInitParams, TelOptions},
+  srcp_protocol_ddl_dcc::{DccProtokoll, DccVersion},
+  srcp_protocol_ddl_mfx::{MfxProtokoll, MfxVersion},
+  srcp_protocol_ddl_mm::{MMProtokoll, MmVersion},
+};
+
+/// UID der Zentrale für die MFX Referenztelegramme
+const SELBSTTEST_UID_ZENTRALE: u32 = 0x12345678;
+/// UID der Lok für die MFX Referenztelegramme
+const SELBSTTEST_UID_LOK: u32 = 0x7F000001;
+/// Anzahl ausgegebene Bytes pro Zeile
+const BYTES_PRO_ZEILE: usize = 32;
+
+/// GL initialisieren und Referenztelegramm (Fahren und Funktionen) als neues Kommando liefern.
+/// Ein allfälliges Init Telegramm (MFX SID Zuordnung) wird vorher als eigenes Telegramm geliefert.
+/// # Arguments
+/// * beschreibung - Beschreibung des Telegrammes
+/// * protokoll - Protokoll mit dem das Telegramm erzeugt wird
+/// * adr - Adresse der Lok
+/// * uid - UID der Lok, nur für Protokolle mit UID
+/// * speed - Geschwindigkeit
+/// * speed_steps - Anzahl Speed Steps
+/// * funktionen - Funktionen, Bit 0 ist F0
+fn get_gl_tel(
+  beschreibung: &str, protokoll: &mut dyn DdlProtokoll, adr: u32, uid: Option<u32>, speed: usize,
+  speed_steps: usize, funktionen: u64,
+) -> Vec<(String, DdlTel)> {
+  let mut telegramme = Vec::new();
+  let anz_f = protokoll.get_gl_anz_f();
+  let params = GlInitParams {
+    uid,
+    funk_anz: anz_f,
+    power: true,
+    trigger: false,
+  };
+  if let Some(init_tel) = protokoll.init_gl(adr, &params) {
+    telegramme.push((format!("{} Init", beschreibung), init_tel));
+  }
+  let mut ddl_tel = protokoll.get_gl_new_tel(adr, TelOptions::neu(false));
+  protokoll.get_gl_basis_tel(
+    adr,
+    GLDriveMode::Vorwaerts,
+    speed,
+    speed_steps,
+    funktionen,
+    &mut ddl_tel,
+  );
+  protokoll.get_gl_zusatz_tel(adr, false, funktionen, &mut ddl_tel);
+  telegramme.push((beschreibung.to_string(), ddl_tel));
+  telegramme
+}
+
+/// GA Referenztelegramm hinzufügen
+/// # Arguments
+/// * telegramme - Referenztelegramme, bei denen das neue Telegramm hinzugefügt wird
+/// * beschreibung - Beschreibung des Telegrammes
+/// * protokoll - Protokoll mit dem das Telegramm erzeugt wird
+/// * adr - Adresse des Schaltdekoders
+/// * port - Port auf dem Schaltdekoder
+/// * value - Zustand des Ports
+fn add_ga_tel(
+  telegramme: &mut Vec<(String, DdlTel)>, beschreibung: &str, protokoll: &mut dyn DdlProtokoll,
+  adr: u32, port: usize, value: usize,
+) {
+  let mut ddl_tel = protokoll.get_ga_new_tel(adr, TelOptions::neu(false));
+  protokoll.get_ga_tel(adr, port, value, None, &mut ddl_tel);
+  telegramme.push((beschreibung.to_string(), ddl_tel));
+}
+
+/// Liefert alle Referenztelegramme mit ihrer Beschreibung.
+/// Jedes Protokoll wird neu instanziert, damit die Telegramme immer gleich sind.
+pub fn get_referenz_telegramme() -> Vec<(String, DdlTel)> {
+  let mut telegramme: Vec<(String, DdlTel)> = Vec::new();
+  //DCC ohne Prog Thread, kein Servicemode
+  let mut dcc = DccProtokoll::from(DccVersion::V2, None, false, false);
+  telegramme.push(("DCC Idle".to_string(), dcc.get_idle_tel().unwrap()));
+  telegramme.extend(get_gl_tel(
+    "DCC GL 3 vorwärts Speed 10/28 F0 F4",
+    &mut dcc,
+    3,
+    None,
+    10,
+    28,
+    0b10001,
+  ));
+  telegramme.extend(get_gl_tel(
+    "DCC GL 1234 vorwärts Speed 100/128",
+    &mut dcc,
+    1234,
+    None,
+    100,
+    128,
+    0,
+  ));
+  add_ga_tel(&mut telegramme, "DCC GA 1 Port 1 Ein", &mut dcc, 1, 1, 1);
+  let mut mm1 = MMProtokoll::from(MmVersion::V1);
+  telegramme.push(("MM Idle".to_string(), mm1.get_idle_tel().unwrap()));
+  telegramme.extend(get_gl_tel(
+    "MM1 GL 1 Speed 5/14 F0",
+    &mut mm1,
+    1,
+    None,
+    5,
+    14,
+    0b1,
+  ));
+  let mut mm2 = MMProtokoll::from(MmVersion::V2);
+  telegramme.extend(get_gl_tel(
+    "MM2 GL 24 vorwärts Speed 7/14 F0 F1",
+    &mut mm2,
+    24,
+    None,
+    7,
+    14,
+    0b11,
+  ));
+  add_ga_tel(&mut telegramme, "MM GA 1 Port 0 Ein", &mut mm2, 1, 0, 1);
+  //MFX ohne RDS Rückmeldung, ohne Neuanmeldezähler File
+  let mut mfx = MfxProtokoll::from(
+    MfxVersion::V0,
+    SELBSTTEST_UID_ZENTRALE,
+    "/dev/null".to_string(),
+    None,
+    false,
+    MfxRdsKonfig::default(),
+  );
+  telegramme.push((
+    format!("MFX UID Zentrale 0x{:08X}", SELBSTTEST_UID_ZENTRALE),
+    mfx.get_idle_tel().unwrap(),
+  ));
+  telegramme.extend(get_gl_tel(
+    &format!(
+      "MFX GL 5 UID 0x{:08X} vorwärts Speed 64/127 F0",
+      SELBSTTEST_UID_LOK
+    ),
+    &mut mfx,
+    5,
+    Some(SELBSTTEST_UID_LOK),
+    64,
+    127,
+    0b1,
+  ));
+  telegramme
+}
+
+/// Liefert die Dauer für die Übertragung von SPI Bytes
+/// # Arguments
+/// * anz_bytes - Anzahl Bytes
+/// * hz - Baudrate
+fn get_dauer(anz_bytes: usize, hz: u32) -> Duration {
+  Duration::from_micros((anz_bytes as u64 * 8 * 1000000) / hz as u64)
+}
+
+/// Alle Referenztelegramme auf stdout ausgeben.
+pub fn ausgeben() {
+  println!("DDL Selbsttest: Referenztelegramme (SPI MOSI, MSB zuerst)");
+  for (beschreibung, ddl_tel) in get_referenz_telegramme() {
+    println!();
+    println!(
+      "{}: {} Telegramm(e), {} Wiederholung(en), Pause Start {:?}, Pause Ende {:?}, Delay {:?}",
+      beschreibung,
+      ddl_tel.daten.len(),
+      ddl_tel.tel_wiederholungen,
+      ddl_tel.pause_start,
+      ddl_tel.pause_ende,
+      ddl_tel.delay
+    );
+    for index in 0..ddl_tel.daten.len() {
+      for (hz, daten) in ddl_tel.get_spi_daten(index) {
+        println!(
+          "  Tel. {}: {} Hz, {} Bytes, {:?}",
+          index + 1,
+          hz,
+          daten.len(),
+          get_dauer(daten.len(), hz)
+        );
+        for zeile in daten.chunks(BYTES_PRO_ZEILE) {
+          let hex: Vec<String> = zeile.iter().map(|byte| format!("{:02X}", byte)).collect();
+          println!("    {}", hex.join(" "));
+        }
+      }
+    }
+  }
+}
@@ -0,0 +1,263 @@
+use std::time::Duration;
+
+use crate::srcp_protocol_ddl::{DdlProtokoll, DdlTel, GLDriveMode, GlInitParams, TelOptions};
+
+/// SPI Baudrate für Selectrix, 1 SPI Bit = 10us
+pub const SPI_BAUDRATE_SX: u32 = 100000;
+/// Gleisformat: Bitzeit 50us, also 5 SPI Bits pro SX Bit.
+/// Am Anfang jedes Bits wechselt die Polarität, bei einer 1 nach 10us nochmals.
+const SX_SPI_BITS_PRO_BIT: usize = 5;
+/// Ein Grundrahmen besteht aus 12 Bit Synchronisation und 7 Datenworten à 12 Bit:
+/// - Sync: 0 0 0 1 S 1 A0 A1 1 A2 A3 1 (S: Gleisspannung ein, A: Nummer Grundrahmen)
+/// - Datenwort: D0 D1 1 D2 D3 1 D4 D5 1 D6 D7 1
+const SX_BITS_GRUNDRAHMEN: usize = 96;
+/// Länge eines Grundrahmens in Bytes über SPI: 96 * 5 / 8 = 60 Bytes
+const SX_LEN: usize = (SX_BITS_GRUNDRAHMEN * SX_SPI_BITS_PRO_BIT) / 8;
+/// Anzahl Grundrahmen, nach 16 Grundrahmen sind alle Kanäle einmal übertragen
+const SX_ANZ_GRUNDRAHMEN: usize = 16;
+/// Anzahl Kanäle (Datenworte) pro Grundrahmen
+const SX_KANAELE_PRO_GRUNDRAHMEN: usize = 7;
+/// Max. erlaubte Kanal Adresse (GL), Kanäle 1 bis 111.
+/// Kanal 0 wird über SRCP nicht als GL adressiert, da GL Adresse 0 ungültig ist (nur GA auf Kanal 0).
+const MAX_SX_ADRESSE: u32 = (SX_ANZ_GRUNDRAHMEN * SX_KANAELE_PRO_GRUNDRAHMEN) as u32 - 1;
+/// Max. erlaubte GA Adresse, jedes der 8 Bits eines Kanals ist eine GA
+const MAX_SX_GA_ADRESSE: u32 = (MAX_SX_ADRESSE + 1) * 8;
+/// Lok Datenwort: Bit 0-4 Fahrstufe, Bit 5 Richtung (1 = rückwärts), Bit 6 Licht (F0), Bit 7 Horn (F1)
+const SX_MAX_SPEED: usize = 31;
+const SX_BIT_RUECKWAERTS: u8 = 0x20;
+const SX_BIT_F0: u8 = 0x40;
+const SX_BIT_F1: u8 = 0x80;
+
+/// Implementierung Selectrix (SX1) Gleisformat.
+/// Anders als bei MM und DCC wird keine Adresse gesendet, sondern zyklisch der Inhalt aller 112 Kanäle (8 Bit Daten).
+/// Die Adresse ergibt sich aus der Position im Grundrahmen: Adresse = Datenwort * 16 + Nummer Grundrahmen.
+/// Es wird deshalb der Zustand aller Kanäle gespeichert und immer ein ganzer Grundrahmen versendet.
+pub struct SxProtokoll {
+  /// Aktueller Inhalt aller Kanäle
+  kanaele: [u8; MAX_SX_ADRESSE as usize + 1],
+  /// Grundrahmen der als nächstes als Idle Telegramm versendet wird
+  idle_grundrahmen: usize,
+}
+impl SxProtokoll {
+  /// Neue Instanz erstellen
+  pub fn new() -> SxProtokoll {
+    SxProtokoll {
+      kanaele: [0; MAX_SX_ADRESSE as usize + 1],
+      idle_grundrahmen: 0,
+    }
+  }
+
+  /// Ein SX Bit im Gleisformat zu einem Telegramm hinzufügen
+  /// # Arguments
+  /// * daten - Telegramm, Bits werden ab "bit_pos" gesetzt, der Buffer muss bereits gross genug sein
+  /// * bit_pos - Position des nächsten SPI Bits, wird nachgeführt
+  /// * pegel - Aktueller Pegel am Gleis, wird nachgeführt
+  /// * bit - Zu sendendes SX Bit
+  fn add_sx_bit(daten: &mut [u8], bit_pos: &mut usize, pegel: &mut bool, bit: bool) {
+    //Polaritätswechsel am Anfang jedes Bits
+    *pegel = !*pegel;
+    for i in 0..SX_SPI_BITS_PRO_BIT {
+      //Bei einer 1 nach 10us nochmals Wechsel
+      let spi_bit = if bit && (i > 0) { !*pegel } else { *pegel };
+      if spi_bit {
+        daten[*bit_pos / 8] |= 0x80 >> (*bit_pos % 8);
+      }
+      *bit_pos += 1;
+    }
+    if bit {
+      *pegel = !*pegel;
+    }
+  }
+
+  /// Einen ganzen Grundrahmen mit dem aktuellen Inhalt seiner Kanäle als neues Telegramm hinzufügen
+  /// # Arguments
+  /// * grundrahmen - Nummer des Grundrahmens 0..15
+  /// * ddl_tel - DDL Telegramm, bei dem des neue Telegramm hinzugefügt werden soll.
+  fn add_grundrahmen(&self, grundrahmen: usize, ddl_tel: &mut DdlTel) {
+    let mut bits: Vec<bool> = Vec::with_capacity(SX_BITS_GRUNDRAHMEN);
+    //Sync mit Gleisspannung ein und Nummer Grundrahmen
+    bits.extend_from_slice(&[false, false, false, true, true, true]);
+    bits.extend_from_slice(&[
+      (grundrahmen & 0x01) != 0,
+      (grundrahmen & 0x02) != 0,
+      true,
+      (grundrahmen & 0x04) != 0,
+      (grundrahmen & 0x08) != 0,
+      true,
+    ]);
+    //Die 7 Datenworte, nach je 2 Datenbits ein Trennbit 1
+    for wort in 0..SX_KANAELE_PRO_GRUNDRAHMEN {
+      let wert = self.kanaele[wort * SX_ANZ_GRUNDRAHMEN + grundrahmen];
+      for bit in 0..8 {
+        bits.push((wert & (1 << bit)) != 0);
+        if (bit % 2) == 1 {
+          bits.push(true);
+        }
+      }
+    }
+    //Das erste Telegramm ist beim Erzeugen des DdlTel bereits vorhanden
+    if !ddl_tel.daten.last().unwrap().is_empty() {
+      ddl_tel.neues_tel(SX_LEN);
+    }
+    let daten = ddl_tel.payload_mut();
+    daten.resize(SX_LEN, 0);
+    let mut bit_pos = 0;
+    let mut pegel = false;
+    for bit in bits {
+      SxProtokoll::add_sx_bit(daten, &mut bit_pos, &mut pegel, bit);
+    }
+  }
+}
+impl DdlProtokoll for SxProtokoll {
+  /// Wenn von einem Protokoll mehrere Versionen (z.B. NMRA 1 und 2) instanziert werden,
+  /// dann muss eines davon zum Default erklärt werden. Die Default Version wird dann verwendet,
+  /// wenn keine Version durch den Benutzer angegeben wird.
+  fn is_default(&self) -> bool {
+    //Es gibt nur SX1
+    true
+  }
+  /// GL Init Daten setzen. Welche Daten verwendet werden ist Protokollabhängig.
+  /// Liefert immer None, kein GL Init Tel. notwendig
+  /// # Arguments
+  /// * adr - Adresse der Lok
+  /// * params - nicht verwendet, es gibt immer Licht und Horn
+  fn init_gl(&mut self, adr: u32, _params: &GlInitParams) -> Option<DdlTel> {
+    //Kanal könnte vorher als GA verwendet worden sein
+    self.kanaele[adr as usize] = 0;
+    None
+  }
+  /// Liefert die max. erlaubte Lokadresse
+  fn get_gl_max_adr(&self) -> u32 {
+    MAX_SX_ADRESSE
+  }
+  /// Wieviele Speedsteps werden vom Protokoll unterstützt
+  fn get_gl_max_speed_steps(&self) -> usize {
+    SX_MAX_SPEED
+  }
+  /// Liefert die max. erlaubte Schaltmoduladdresse
+  fn get_ga_max_adr(&self) -> u32 {
+    MAX_SX_GA_ADRESSE
+  }
+  /// Liefert die max. Anzahl der unterstützten Funktionen
+  fn get_gl_anz_f(&self) -> usize {
+    //Licht (F0) und Horn (F1)
+    2
+  }
+  /// Liefert die Anzahl Funktionen (inkl. F0) die im Basistelegramm enthalten sind
+  /// Muss immer <= "get_Anz_F" sein.
+  fn get_gl_anz_f_basis(&self) -> usize {
+    //Alles ist im einen Datenwort der Lok
+    2
+  }
+  /// Liefert ein leeres GL Telegramm zur Verwendung in "get_gl_basis_tel" und / oder "get_gl_zusatz_tel".
+  /// # Arguments
+  /// * adr - Adresse der Lok, keine Verwendunbg, nur Debug Support
+  /// * optionen - Refresh und Oszi Trigger, siehe "TelOptions"
+  fn get_gl_new_tel(&mut self, adr: u32, optionen: TelOptions) -> DdlTel {
+    DdlTel::new(
+      adr,
+      SPI_BAUDRATE_SX,
+      Duration::ZERO,
+      false,
+      SX_LEN,
+      if optionen.refresh { 1 } else { 2 }, //Neue Kommandos 2-fach senden
+      optionen.trigger,
+    )
+  }
+  /// Erzeugt das Basis Telegramm für GL.
+  /// - Fahren
+  /// - Licht (F0) und Horn (F1)
+  /// Es wird der ganze Grundrahmen, in dem der Kanal der Lok enthalten ist, gesendet.
+  /// # Arguments
+  /// * adr - Adresse der Lok
+  /// * drive_mode - Fahrtrichtung / Nothalt
+  /// * speed - aktuelle Geschwindigkeit
+  /// * speed_steps - Anzahl Speed Steps die verwendet werden soll.
+  ///                 Hier nicht verwendet da durch Protokoll gegeben.
+  /// * funktionen - Die gewünschten Funktionen, berücksichtigt bis "get_Anz_F_Basis"
+  /// * ddl_tel - DDL Telegramm, bei dem des neue Telegramm hinzugefügt werden soll.
+  fn get_gl_basis_tel(
+    &mut self, adr: u32, drive_mode: GLDriveMode, speed: usize, _speed_steps: usize,
+    funktionen: u64, ddl_tel: &mut DdlTel,
+  ) {
+    let alt = self.kanaele[adr as usize];
+    //SX kennt keinen Nothalt, Fahrstufe 0 mit bisheriger Richtung
+    let mut wert = match drive_mode {
+      GLDriveMode::Vorwaerts => 0,
+      GLDriveMode::Rueckwaerts => SX_BIT_RUECKWAERTS,
+      GLDriveMode::Nothalt => alt & SX_BIT_RUECKWAERTS,
+    };
+    if drive_mode != GLDriveMode::Nothalt {
+      wert |= speed.min(SX_MAX_SPEED) as u8;
+    }
+    if (funktionen & 0x01) != 0 {
+      wert |= SX_BIT_F0;
+    }
+    if (funktionen & 0x02) != 0 {
+      wert |= SX_BIT_F1;
+    }
+    self.kanaele[adr as usize] = wert;
+    self.add_grundrahmen(adr as usize % SX_ANZ_GRUNDRAHMEN, ddl_tel);
+  }
+  /// Erzeugt das / die Fx Zusatztelegramm(e) für GL.
+  /// Bei SX sind alle Funktionen im Basistelegramm, es wird nichts hinzugefügt.
+  /// # Arguments
+  /// * adr - Adresse der Lok
+  /// * refresh - Wenn false werden nur Telegramme für Funktionen, die geändert haben, erzeugt
+  /// * funktionen - Die gewünschten Funktionen, berücksichtigt ab "get_Anz_F_Basis"
+  /// * ddl_tel - DDL Telegramm, bei dem des neue Telegramm hinzugefügt werden soll.
+  fn get_gl_zusatz_tel(
+    &mut self, _adr: u32, _refresh: bool, _funktionen: u64, _ddl_tel: &mut DdlTel,
+  ) {
+  }
+  /// Liefert ein leeres GA Telegramm zur Verwendung in "get_ga_tel".
+  /// # Arguments
+  /// * adr - Adresse GA, keine Verwendunbg, nur Debug Support
+  /// * optionen - Oszi Trigger, siehe "TelOptions"
+  fn get_ga_new_tel(&self, adr: u32, optionen: TelOptions) -> DdlTel {
+    //Neue neue Kommandos, kein Refresh -> 2-fach senden
+    DdlTel::new(
+      adr,
+      SPI_BAUDRATE_SX,
+      Duration::ZERO,
+      false,
+      SX_LEN,
+      2,
+      optionen.trigger,
+    )
+  }
+  /// Erzeugt ein GA Telegramm
+  /// GA Adresse 1 ist Bit 0 von Kanal 0, Adresse 8 Bit 7 von Kanal 0, Adresse 9 Bit 0 von Kanal 1 usw.
+  /// Port 1 setzt das Bit, Port 0 löscht es. Der Dekoder hält den Zustand, Ausschalten (value 0) wird ignoriert.
+  /// Liefert true zurück, wenn Timeout zur automatischen Abschaltung durch Protokoll / Dekoder übernommen wird.
+  /// # Arguments
+  /// * adr - Adresse des Schaltdekoders
+  /// * port - Port auf dem Schaltdekoder
+  /// * value - Gewünschter Zustand des Port Ein/Aus (0/1)
+  /// * timeout - Hier nicht verwendet, keine Untesrtützung im SX Protokoll.
+  /// * ddl_tel - DDL Telegramm, bei dem des neue Telegramm hinzugefügt werden soll.
+  fn get_ga_tel(
+    &mut self, adr: u32, port: usize, value: usize, _timeout: Option<Duration>,
+    ddl_tel: &mut DdlTel,
+  ) -> bool {
+    let kanal = (adr as usize - 1) / 8;
+    let bit = 1 << ((adr - 1) % 8);
+    if value != 0 {
+      if port == 0 {
+        self.kanaele[kanal] &= !bit;
+      } else {
+        self.kanaele[kanal] |= bit;
+      }
+    }
+    self.add_grundrahmen(kanal % SX_ANZ_GRUNDRAHMEN, ddl_tel);
+    false
+  }
+  /// Liefert das Idle Telegramm dieses Protokolles
+  /// Reihum der nächste Grundrahmen, damit alle Kanäle regelmässig wiederholt werden.
+  fn get_idle_tel(&mut self) -> Option<DdlTel> {
+    let mut ddl_idle_tel = self.get_gl_new_tel(0, TelOptions::refresh(false));
+    self.add_grundrahmen(self.idle_grundrahmen, &mut ddl_idle_tel);
+    self.idle_grundrahmen = (self.idle_grundrahmen + 1) % SX_ANZ_GRUNDRAHMEN;
+    Some(ddl_idle_tel)
+  }
+}
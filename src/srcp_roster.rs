@@ -0,0 +1,159 @@
+//! Roster: im Konfigfile vordefinierte GL's und GA's
+//! Alle Einträge werden beim Start über eine interne Command Mode Session mit INIT an die SRCP Server
+//! gesendet. Diese melden die neuen Devices wie bei jedem INIT über INFO, die Anlage ist damit sofort
+//! ohne INIT durch einen Client bedienbar.
+//!
+//! INI File:
+//! [roster]
+//! gl_<n> = <name> <bus>:<adr> <protocol> <protocolversion> <decoderspeedsteps> <numberofdecoderfunctions> [<weitere INIT Parameter>]
+//! ga_<n> = <name> <bus>:<adr> <protocol> [<weitere INIT Parameter>]
+//! Name: ein Wort, darf keine Zahl sein. GL Namen können anstelle der Adresse verwendet werden (siehe srcp_alias).
+
+use std::{collections::HashMap, sync::mpsc::SyncSender, thread};
+
+use log::{info, warn};
+
+use crate::{
+  srcp, srcp_alias,
+  srcp_server_types::{Message, SRCPMessage, SRCPMessageDevice, SRCPMessageID, SRCPMessageType},
+};
+
+/// Ein vordefiniertes Device
+struct RosterEintrag {
+  /// Name aus Konfigfile
+  name: String,
+  /// SRCP Bus
+  bus: usize,
+  /// GL oder GA
+  device: SRCPMessageDevice,
+  /// INIT Parameter ab Adresse
+  parameter: Vec<String>,
+}
+
+/// Alle vordefinierten Devices
+pub struct Roster {
+  /// Alle Einträge in der Reihenfolge der Schlüssel im Konfigfile
+  eintraege: Vec<RosterEintrag>,
+}
+
+impl Roster {
+  /// Roster aus Konfigfile erstellen.
+  /// Liefert Err zurück wenn die Konfiguration ungültig ist.
+  /// # Arguments
+  /// * config - Abschnitt [roster] aus Konfigfile, None wenn nicht vorhanden
+  pub fn from_config(config: Option<&HashMap<String, Option<String>>>) -> Result<Roster, String> {
+    let mut eintraege: Vec<(&String, RosterEintrag)> = Vec::new();
+    if let Some(config) = config {
+      for (key, wert) in config {
+        let (device, min_parameter) = if key.starts_with("gl_") {
+          //<adr> <protocol> <protocolversion> <decoderspeedsteps> <numberofdecoderfunctions>
+          (SRCPMessageDevice::GL, 5)
+        } else if key.starts_with("ga_") {
+          //<adr> <protocol>
+          (SRCPMessageDevice::GA, 2)
+        } else {
+          continue;
+        };
+        let wert = wert.as_ref().ok_or(format!("[roster] {} ohne Wert", key))?;
+        let mut teile = wert.split_whitespace();
+        let name = teile
+          .next()
+          .filter(|name| name.parse::<u32>().is_err())
+          .ok_or(format!("[roster] {}: ungültiger Name", key))?;
+        //SRCP Kommandos werden in Grossbuchstaben empfangen, Namen müssen auch so eindeutig sein
+        if eintraege
+          .iter()
+          .any(|(_, eintrag)| eintrag.name.eq_ignore_ascii_case(name))
+        {
+          return Err(format!("[roster] {}: Name {} doppelt", key, name));
+        }
+        let (bus, adr) = teile
+          .next()
+          .and_then(|bus_adr| bus_adr.split_once(':'))
+          .and_then(|(bus, adr)| Some((bus.parse::<usize>().ok()?, adr.parse::<u32>().ok()?)))
+          .ok_or(format!("[roster] {}: ungültige <bus>:<adr>", key))?;
+        let mut parameter = vec![adr.to_string()];
+        parameter.extend(teile.map(str::to_string));
+        if parameter.len() < min_parameter {
+          return Err(format!("[roster] {}: zu wenig INIT Parameter", key));
+        }
+        //Namen für GL's auch anstelle der Adresse verwendbar
+        if device == SRCPMessageDevice::GL {
+          srcp_alias::set(bus, name, adr, true);
+        }
+        eintraege.push((
+          key,
+          RosterEintrag {
+            name: name.to_string(),
+            bus,
+            device,
+            parameter,
+          },
+        ));
+      }
+    }
+    //Reproduzierbare Reihenfolge, z.B. falls die max. Anzahl GL's begrenzt ist
+    eintraege.sort_by_key(|(key, _)| *key);
+    Ok(Roster {
+      eintraege: eintraege.into_iter().map(|(_, eintrag)| eintrag).collect(),
+    })
+  }
+
+  /// Alle Einträge über eine eigene Command Mode Session mit INIT an die SRCP Server senden.
+  /// Fehler werden nur geloggt.
+  /// # Arguments
+  /// * all_cmd_tx - Alle Channel Sender für Kommandos zu den SRCP Servern. Key ist die Busnummer.
+  fn init_all(&self, all_cmd_tx: &HashMap<usize, SyncSender<Message>>) {
+    let session_id = srcp::neue_session_id();
+    let cmd_rx = srcp::anmelden_intern_command_client(session_id);
+    for eintrag in &self.eintraege {
+      let cmd = SRCPMessage::new(
+        Some(session_id),
+        eintrag.bus,
+        SRCPMessageID::Command {
+          msg_type: SRCPMessageType::INIT,
+        },
+        eintrag.device.clone(),
+        eintrag.parameter.clone(),
+      );
+      match srcp::execute_command(cmd.clone(), None, &cmd_rx, all_cmd_tx)
+        .map(|(_, antwort)| antwort)
+      {
+        Ok(antwort) if !matches!(antwort.message_id, SRCPMessageID::Err { .. }) => info!(
+          "Roster {}: {}-> {}",
+          eintrag.name,
+          cmd.to_string(),
+          antwort.to_string()
+        ),
+        Ok(antwort) => warn!(
+          "Roster {}: {}-> {}",
+          eintrag.name,
+          cmd.to_string(),
+          antwort.to_string()
+        ),
+        Err((err_code, err_text)) => warn!(
+          "Roster {}: {}-> {} ERROR {}",
+          eintrag.name,
+          cmd.to_string(),
+          err_code,
+          err_text
+        ),
+      }
+    }
+  }
+}
+
+/// Alle Einträge des Roster in eigenem Thread initialisieren.
+/// # Arguments
+/// * roster - Roster aus Konfigfile, siehe "Roster::from_config"
+/// * all_cmd_tx - Alle Channel Sender für Kommandos zu den SRCP Servern. Key ist die Busnummer.
+pub fn startup(roster: Roster, all_cmd_tx: &HashMap<usize, SyncSender<Message>>) {
+  if roster.eintraege.is_empty() {
+    return;
+  }
+  let all_cmd_tx = all_cmd_tx.clone();
+  thread::Builder::new()
+    .name("Roster".to_string())
+    .spawn(move || roster.init_all(&all_cmd_tx))
+    .unwrap();
+}
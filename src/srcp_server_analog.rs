@@ -0,0 +1,566 @@
+use std::{
+  collections::HashMap,
+  fs,
+  path::Path,
+  sync::mpsc::{Receiver, SyncSender},
+  thread,
+  time::Duration,
+};
+
+use crate::{
+  srcp_hardware,
+  srcp_protocol_ddl::GLDriveMode,
+  srcp_server_types::{
+    spawn_server_thread, Message, SRCPMessage, SRCPMessageDevice, SRCPMessageID, SRCPMessageType,
+    SRCPServer, ThreadSender,
+  },
+};
+use gpio_cdev::{Chip, LineHandle, LineRequestFlags};
+use log::{info, warn};
+
+/// SRCP Protokollkennung für analoge Loks
+const PROTOKOLL_ANALOG: &str = "A";
+/// Anzahl Fahrstufen, entspricht der Auflösung in % der PWM
+const ANALOG_SPEED_STEPS: usize = 100;
+/// PWM Chip wenn nicht konfiguriert (Raspberry PI mit dtoverlay=pwm: Kanal 0 auf GPIO18)
+const PWM_CHIP_DEFAULT: &str = "/sys/class/pwm/pwmchip0";
+/// PWM Frequenz in Hz wenn nicht konfiguriert
+const PWM_FREQUENCY_DEFAULT: u32 = 100;
+/// Wartezeit nach dem Export eines PWM Kanals bis udev die Zugriffsrechte gesetzt hat
+const PWM_EXPORT_DELAY: Duration = Duration::from_millis(100);
+
+/// Hardware PWM Kanal über sysfs
+struct HwPwm {
+  /// Pfad zum Kanal, z.B. /sys/class/pwm/pwmchip0/pwm0
+  pfad: String,
+  /// Periodendauer in ns
+  period: u32,
+}
+impl HwPwm {
+  /// PWM Kanal exportieren und Periode setzen, Ausgang bleibt ausgeschaltet.
+  /// # Arguments
+  /// * chip - Pfad zum PWM Chip
+  /// * channel - Kanal auf dem Chip
+  /// * frequency - PWM Frequenz in Hz
+  fn open(chip: &str, channel: u32, frequency: u32) -> Result<HwPwm, String> {
+    let pfad = format!("{}/pwm{}", chip, channel);
+    if !Path::new(&pfad).exists() {
+      fs::write(format!("{}/export", chip), channel.to_string())
+        .map_err(|err| format!("{}/export {}", chip, err))?;
+      thread::sleep(PWM_EXPORT_DELAY);
+    }
+    let pwm = HwPwm {
+      pfad,
+      period: 1_000_000_000 / frequency,
+    };
+    pwm.enable(false)?;
+    //Duty Cycle darf nie grösser als die Periode sein, deshalb zuerst auf 0
+    pwm.write("duty_cycle", 0)?;
+    pwm.write("period", pwm.period)?;
+    Ok(pwm)
+  }
+
+  /// Wert in ein Attribut des Kanals schreiben
+  /// # Arguments
+  /// * attribut - Name des Attributes, z.B. "period"
+  /// * value - Zu schreibender Wert
+  fn write(&self, attribut: &str, value: u32) -> Result<(), String> {
+    fs::write(format!("{}/{}", self.pfad, attribut), value.to_string())
+      .map_err(|err| format!("{}/{} {}", self.pfad, attribut, err))
+  }
+
+  /// Ausgang ein- oder ausschalten
+  /// # Arguments
+  /// * on - true: PWM aktiv
+  fn enable(&self, on: bool) -> Result<(), String> {
+    self.write("enable", on as u32)
+  }
+
+  /// Tastverhältnis setzen
+  /// # Arguments
+  /// * promille - Tastverhältnis 0 bis 1000
+  fn set_duty(&self, promille: u32) -> Result<(), String> {
+    self.write(
+      "duty_cycle",
+      ((self.period as u64 * promille.min(1000) as u64) / 1000) as u32,
+    )
+  }
+}
+
+/// Zustand der analogen Lok
+struct AnalogGL {
+  /// SRCP Adresse unter der die Lok initialisiert wurde
+  adr: u32,
+  /// Fahrtrichtung / Nothalt
+  direction: GLDriveMode,
+  /// Aktuelle Fahrstufe 0 bis ANALOG_SPEED_STEPS
+  speed: usize,
+}
+
+/// Eine analoge Lok (Gleichstrom) auf einem eigenen Gleisabschnitt. Ausgabe über Hardware PWM
+/// und eine H-Brücke (z.B. L298), Richtung über ein oder zwei GPIO's. GL Protokoll "A".
+#[derive(Clone)]
+pub struct AnalogServer {
+  //SRCP Busnr
+  busnr: usize,
+  //PWM Chip im sysfs
+  pwm_chip: String,
+  //PWM Kanal
+  pwm_channel: u32,
+  //PWM Frequenz in Hz
+  pwm_frequency: u32,
+  //Tastverhältnis in Promille bei Fahrstufe 1, Anfahrspannung des Motors
+  pwm_min: u32,
+  //Tastverhältnis in Promille bei max. Fahrstufe
+  pwm_max: u32,
+  //GPIO's der H-Brücke für die Richtung: einer (1=vorwärts) oder zwei (IN1/IN2)
+  dir_gpios: Vec<u32>,
+}
+
+impl AnalogServer {
+  ///Neue Instanz erstellen
+  pub fn new() -> AnalogServer {
+    AnalogServer {
+      busnr: 0,
+      pwm_chip: PWM_CHIP_DEFAULT.to_string(),
+      pwm_channel: 0,
+      pwm_frequency: PWM_FREQUENCY_DEFAULT,
+      pwm_min: 0,
+      pwm_max: 1000,
+      dir_gpios: Vec::new(),
+    }
+  }
+
+  /// Tastverhältnis zu einer Fahrstufe
+  /// # Arguments
+  /// * speed - Fahrstufe 0 bis ANALOG_SPEED_STEPS
+  fn get_duty(&self, speed: usize) -> u32 {
+    if speed == 0 {
+      0
+    } else {
+      self.pwm_min + ((self.pwm_max - self.pwm_min) * speed as u32) / ANALOG_SPEED_STEPS as u32
+    }
+  }
+
+  /// Zustand der Lok auf PWM und Richtungs GPIO's ausgeben.
+  /// Bei Richtungswechsel wird zuerst die Spannung weggenommen.
+  /// # Arguments
+  /// * pwm - PWM Kanal
+  /// * dir_lines - Richtungs GPIO's
+  /// * gl - Auszugebende Lok, None: keine Lok initialisiert
+  /// * power - Power Zustand des Busses
+  fn ausgabe(
+    &self, pwm: &HwPwm, dir_lines: &[LineHandle], gl: Option<&AnalogGL>, power: bool,
+  ) -> Result<(), String> {
+    let Some(gl) = gl.filter(|_| power) else {
+      pwm.set_duty(0)?;
+      return pwm.enable(false);
+    };
+    if gl.direction == GLDriveMode::Nothalt {
+      return pwm.set_duty(0);
+    }
+    let vorwaerts = gl.direction == GLDriveMode::Vorwaerts;
+    let pegel = if dir_lines.len() == 1 {
+      vec![vorwaerts]
+    } else {
+      vec![vorwaerts, !vorwaerts]
+    };
+    if dir_lines
+      .iter()
+      .zip(&pegel)
+      .any(|(line, pegel)| line.get_value().ok() != Some(*pegel as u8))
+    {
+      pwm.set_duty(0)?;
+      for (line, pegel) in dir_lines.iter().zip(&pegel) {
+        line
+          .set_value(*pegel as u8)
+          .map_err(|err| format!("GPIO {}", err))?;
+      }
+    }
+    pwm.set_duty(self.get_duty(gl.speed))?;
+    pwm.enable(true)
+  }
+
+  /// Power INFO Message versenden
+  /// # Arguments
+  /// * tx - Sender für INFO Messages
+  /// * session_id - None: an alle SRCP Info Clients, sonst nur an den mit SessionID
+  /// * power - Power Zustand
+  fn send_info_power(&self, tx: &ThreadSender<SRCPMessage>, session_id: Option<u32>, power: bool) {
+    //INFO <bus> POWER ON|OFF
+    tx.send(SRCPMessage::new(
+      session_id,
+      self.busnr,
+      SRCPMessageID::Info {
+        info_code: "100".to_string(),
+      },
+      SRCPMessageDevice::Power,
+      vec![(if power { "ON" } else { "OFF" }).to_string()],
+    ));
+  }
+
+  /// GL INFO Message versenden
+  /// # Arguments
+  /// * tx - Sender für INFO Messages
+  /// * session_id - None: an alle SRCP Info Clients, sonst nur an den mit SessionID
+  /// * info_code - "101" für neue GL, "100" für Zustand
+  /// * gl - Zu meldende Lok
+  fn send_info_gl(
+    &self, tx: &ThreadSender<SRCPMessage>, session_id: Option<u32>, info_code: &str, gl: &AnalogGL,
+  ) {
+    let parameter = if info_code == "101" {
+      //INFO <bus> GL <addr> A
+      vec![gl.adr.to_string(), PROTOKOLL_ANALOG.to_string()]
+    } else {
+      //INFO <bus> GL <addr> <drivemode> <V> <V_max>
+      vec![
+        gl.adr.to_string(),
+        gl.direction.to_string(),
+        gl.speed.to_string(),
+        ANALOG_SPEED_STEPS.to_string(),
+      ]
+    };
+    tx.send(SRCPMessage::new(
+      session_id,
+      self.busnr,
+      SRCPMessageID::Info {
+        info_code: info_code.to_string(),
+      },
+      SRCPMessageDevice::GL,
+      parameter,
+    ));
+  }
+
+  /// POWER Kommando ausführen.
+  /// Liefert den neuen Power Zustand zurück.
+  /// # Arguments
+  /// * msg_type - Kommandotyp
+  /// * cmd_msg - Empfangenes Kommando
+  /// * power - Aktueller Power Zustand
+  /// * tx - Sender für Antworten und INFO Messages
+  fn execute_power_cmd(
+    &self, msg_type: SRCPMessageType, cmd_msg: &SRCPMessage, power: bool,
+    tx: &ThreadSender<SRCPMessage>,
+  ) -> bool {
+    match msg_type {
+      SRCPMessageType::GET => {
+        //Format ist GET <bus> POWER
+        self.send_info_power(tx, cmd_msg.session_id, power);
+        power
+      }
+      SRCPMessageType::SET => {
+        //Format ist SET <bus> POWER ON|OFF [freetext]
+        let neu = match cmd_msg.parameter.first().map(String::as_str) {
+          Some("ON") => true,
+          Some("OFF") => false,
+          _ => {
+            tx.send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"));
+            return power;
+          }
+        };
+        tx.send(SRCPMessage::new_ok(cmd_msg, "200"));
+        if neu != power {
+          self.send_info_power(tx, None, neu);
+        }
+        neu
+      }
+      _ => {
+        tx.send(SRCPMessage::new_err(
+          cmd_msg,
+          "423",
+          "unsupported operation",
+        ));
+        power
+      }
+    }
+  }
+
+  /// GL Kommando ausführen.
+  /// # Arguments
+  /// * msg_type - Kommandotyp
+  /// * cmd_msg - Empfangenes Kommando
+  /// * gl - Initialisierte Lok, None wenn keine
+  /// * tx - Sender für Antworten und INFO Messages
+  fn execute_gl_cmd(
+    &self, msg_type: SRCPMessageType, cmd_msg: &SRCPMessage, gl: &mut Option<AnalogGL>,
+    tx: &ThreadSender<SRCPMessage>,
+  ) {
+    //Alle Kommandos beginnen mit der GL Adresse
+    let Some(adr) = cmd_msg.get_adr().filter(|adr| *adr > 0) else {
+      tx.send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"));
+      return;
+    };
+    if msg_type == SRCPMessageType::INIT {
+      //Format ist INIT <bus> GL <addr> A, weitere Parameter werden ignoriert
+      if cmd_msg.parameter.get(1).map(String::as_str) != Some(PROTOKOLL_ANALOG) {
+        tx.send(SRCPMessage::new_err(
+          cmd_msg,
+          "420",
+          "unsupported device protocol",
+        ));
+        return;
+      }
+      //Es gibt nur eine Lok pro Gleisabschnitt
+      if gl.as_ref().is_some_and(|gl| gl.adr != adr) {
+        tx.send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"));
+        return;
+      }
+      let neue_gl = gl.get_or_insert(AnalogGL {
+        adr,
+        direction: GLDriveMode::Vorwaerts,
+        speed: 0,
+      });
+      self.send_info_gl(tx, None, "101", neue_gl);
+      tx.send(SRCPMessage::new_ok(cmd_msg, "200"));
+      return;
+    }
+    //Alle anderen Kommandos nur für die initialisierte Lok
+    let Some(akt_gl) = gl.as_mut().filter(|gl| gl.adr == adr) else {
+      tx.send(SRCPMessage::new_err(cmd_msg, "416", "no data"));
+      return;
+    };
+    match msg_type {
+      SRCPMessageType::TERM => {
+        //Format ist TERM <bus> GL <addr>
+        *gl = None;
+        tx.send(SRCPMessage::new_ok(cmd_msg, "200"));
+      }
+      SRCPMessageType::GET => {
+        //Format ist GET <bus> GL <addr> [INIT]
+        //Mit INIT werden die INIT Parameter wie bei INFO 101 geliefert (srcpd_rust Erweiterung)
+        let info_code = if cmd_msg.parameter.get(1).map(String::as_str) == Some("INIT") {
+          "101"
+        } else {
+          "100"
+        };
+        self.send_info_gl(tx, cmd_msg.session_id, info_code, akt_gl);
+      }
+      SRCPMessageType::SET | SRCPMessageType::VERIFY => {
+        //Format ist SET <bus> GL <addr> <drivemode> <V> <V_max> <f0> . . <fn>
+        //Funktionen werden akzeptiert, eine analoge Lok hat aber keine
+        if cmd_msg.parameter.len() < 4 {
+          tx.send(SRCPMessage::new_err(cmd_msg, "419", "list too short"));
+          return;
+        }
+        let drivemode = GLDriveMode::from_str(cmd_msg.parameter[1].as_str());
+        let v = cmd_msg.parameter[2].parse::<usize>().ok();
+        let v_max = cmd_msg.parameter[3]
+          .parse::<usize>()
+          .ok()
+          .filter(|v_max| *v_max > 0);
+        let (Some(drivemode), Some(v), Some(v_max)) = (drivemode, v, v_max) else {
+          tx.send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"));
+          return;
+        };
+        if (v > v_max)
+          || !cmd_msg.parameter[4..]
+            .iter()
+            .all(|f| (f == "0") || (f == "1"))
+        {
+          tx.send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"));
+          return;
+        }
+        let speed = (ANALOG_SPEED_STEPS * v) / v_max;
+        if msg_type == SRCPMessageType::VERIFY {
+          //Vergleich mit aktuellem Zustand, bei Übereinstimmung INFO, sonst Error 412
+          if (akt_gl.direction == drivemode) && (akt_gl.speed == speed) {
+            self.send_info_gl(tx, cmd_msg.session_id, "100", akt_gl);
+          } else {
+            tx.send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"));
+          }
+          return;
+        }
+        akt_gl.direction = drivemode;
+        akt_gl.speed = speed;
+        tx.send(SRCPMessage::new_ok(cmd_msg, "200"));
+        self.send_info_gl(tx, None, "100", akt_gl);
+      }
+      SRCPMessageType::INIT => {}
+    }
+  }
+
+  /// Ausführung als Thread
+  /// # Arguments
+  /// * rx - Channel Receiver über denn Kommandos empfangen werden
+  /// * tx - Channel Sender über den Info Messages zurück gesendet werden können
+  fn execute(&self, rx: Receiver<Message>, tx: ThreadSender<SRCPMessage>) -> Result<(), String> {
+    let pwm = match HwPwm::open(&self.pwm_chip, self.pwm_channel, self.pwm_frequency) {
+      Ok(pwm) => pwm,
+      Err(msg) => {
+        return Err(format!("Analog: PWM konnte nicht geöffnet werden. {}", msg));
+      }
+    };
+    let gpiochip = srcp_hardware::get_gpiochip();
+    let mut chip = match Chip::new(&gpiochip) {
+      Ok(chip) => chip,
+      Err(msg) => {
+        return Err(format!(
+          "Analog: {} konnte nicht geöffnet werden. {}",
+          gpiochip, msg
+        ));
+      }
+    };
+    let mut dir_lines: Vec<LineHandle> = Vec::new();
+    for gpio in &self.dir_gpios {
+      match chip
+        .get_line(*gpio)
+        .and_then(|line| line.request(LineRequestFlags::OUTPUT, 0, "output_analog_dir"))
+      {
+        Ok(line) => dir_lines.push(line),
+        Err(msg) => {
+          return Err(format!(
+            "Analog: GPIO {} konnte nicht als Output geöffnet werden. {}",
+            gpio, msg
+          ));
+        }
+      }
+    }
+    let mut gl: Option<AnalogGL> = None;
+    let mut power = false;
+    //PWM und GPIO's offen, bereit für Kommandos
+    tx.send(SRCPMessage::new_ready(self.busnr));
+    loop {
+      match rx.recv() {
+        Ok(Message::NewInfoClient { session_id }) => {
+          //Neuer Info Client, alle Zustände senden
+          self.send_info_power(&tx, Some(session_id), power);
+          if let Some(gl) = &gl {
+            self.send_info_gl(&tx, Some(session_id), "101", gl);
+            self.send_info_gl(&tx, Some(session_id), "100", gl);
+          }
+        }
+        Ok(Message::SRCPMessage { srcp_message }) => {
+          let SRCPMessageID::Command { msg_type } = srcp_message.message_id else {
+            continue;
+          };
+          match srcp_message.device {
+            SRCPMessageDevice::Power => {
+              power = self.execute_power_cmd(msg_type, &srcp_message, power, &tx);
+            }
+            SRCPMessageDevice::GL => {
+              self.execute_gl_cmd(msg_type, &srcp_message, &mut gl, &tx);
+            }
+            _ => {
+              tx.send(SRCPMessage::new_err(
+                &srcp_message,
+                "421",
+                "unsupported device",
+              ));
+              continue;
+            }
+          }
+          if let Err(msg) = self.ausgabe(&pwm, &dir_lines, gl.as_ref(), power) {
+            warn!("Analog: {}", msg);
+          }
+        }
+        Ok(Message::SessionEnd { .. }) => {} //Keine Session abhängigen Zustände
+        Ok(Message::Beenden) => {}           //Keine Hilfsthreads
+        Ok(Message::Pruefen { srcp_message }) => {
+          //Keine Validierung ohne Ausführung
+          tx.send(SRCPMessage::new_err(
+            &srcp_message,
+            "423",
+            "unsupported operation",
+          ));
+        }
+        Err(_) => {
+          warn!("Analog: Kommandochannel geschlossen, wird beendet");
+          if let Err(msg) = self.ausgabe(&pwm, &dir_lines, None, false) {
+            warn!("Analog: {}", msg);
+          }
+          break;
+        }
+      }
+    }
+    Ok(())
+  }
+}
+
+impl SRCPServer for AnalogServer {
+  /// Liefert den Name des SRCP Servers zurück
+  /// Im Konfigfile muss für jeden verwendeten SRCP Server minimal ein Abschnitt mit diesem Name und dem zu verwenden Bus enthalten sein:
+  /// [SRCPServerName]
+  /// bus = x
+  fn get_name(&self) -> &'static str {
+    "analog"
+  }
+
+  /// Liefert die Busnummer des SRCP Servers zurück, 0=nicht benutzt, konfiguriert
+  fn get_busnr(&self) -> usize {
+    self.busnr
+  }
+
+  /// Init dieses Servers
+  /// Liefert Err zurück wenn ein Fehler aufgetreten ist (z.B. fehlender Konfig Parameter)
+  ///
+  /// Analog Server hat folgende Konfigparameter:
+  /// dir_gpio GPIO Nummer(n) der H-Brücke für die Richtung: "23" (1 = vorwärts) oder "23,24" (IN1/IN2)
+  /// Optional:
+  /// pwmchip PWM Chip im sysfs, Default /sys/class/pwm/pwmchip0
+  /// pwm_channel PWM Kanal, Default 0
+  /// pwm_frequency PWM Frequenz in Hz, Default 100
+  /// pwm_min Tastverhältnis in % bei Fahrstufe 1 (Anfahrspannung), Default 0
+  /// pwm_max Tastverhältnis in % bei max. Fahrstufe, Default 100
+  /// # Arguments
+  /// * busnr - Die SRCP Busnummers die diesem Server zugeordner ist.
+  /// * config_file_bus - Der diesen Bus betreffende Teil des Konfigfiles
+  fn init(
+    &mut self, busnr: usize, config_file_bus: &HashMap<String, Option<String>>,
+  ) -> Result<(), String> {
+    self.busnr = busnr;
+    self.dir_gpios = config_file_bus
+      .get("dir_gpio")
+      .ok_or("Analog: dir_gpio Parameter nicht vorhanden")?
+      .as_ref()
+      .ok_or("Analog: dir_gpio Parameter ohne Wert")?
+      .split(',')
+      .map(|gpio| gpio.trim().parse::<u32>().ok())
+      .collect::<Option<Vec<u32>>>()
+      .filter(|gpios| !gpios.is_empty() && (gpios.len() <= 2))
+      .ok_or("Analog: dir_gpio muss 1 oder 2 GPIO Nummern enthalten")?;
+    if let Some(pwm_chip) = config_file_bus.get("pwmchip") {
+      self.pwm_chip = pwm_chip
+        .as_ref()
+        .ok_or("Analog: pwmchip ohne Wert")?
+        .clone();
+    }
+    let get_zahl = |name: &str, default: u32| -> Result<u32, String> {
+      match config_file_bus.get(name) {
+        Some(value) => value
+          .as_ref()
+          .and_then(|value| value.parse::<u32>().ok())
+          .ok_or(format!("Analog: {} muss eine Zahl sein", name)),
+        None => Ok(default),
+      }
+    };
+    self.pwm_channel = get_zahl("pwm_channel", 0)?;
+    self.pwm_frequency = get_zahl("pwm_frequency", PWM_FREQUENCY_DEFAULT)?;
+    if self.pwm_frequency == 0 {
+      Err("Analog: pwm_frequency muss > 0 sein")?;
+    }
+    self.pwm_min = get_zahl("pwm_min", 0)? * 10;
+    self.pwm_max = get_zahl("pwm_max", 100)? * 10;
+    if (self.pwm_min > self.pwm_max) || (self.pwm_max > 1000) {
+      Err("Analog: es muss gelten pwm_min <= pwm_max <= 100")?;
+    }
+    info!(
+      "Analog: PWM {}/pwm{} {}Hz, Richtung GPIO {:?}",
+      self.pwm_chip, self.pwm_channel, self.pwm_frequency, self.dir_gpios
+    );
+    Ok(())
+  }
+
+  /// Start dieses Servers
+  /// # Arguments
+  /// * rx - Channel Receiver über denn Kommandos empfangen werden
+  /// * tx - Channel Sender über den Info Messages zurück gesendet werden können
+  fn start(&self, rx: Receiver<Message>, tx: SyncSender<SRCPMessage>) {
+    let instanz = self.clone();
+    spawn_server_thread(
+      "Analog_Thread",
+      self.busnr..self.busnr + self.get_srcp_bus_count(),
+      tx,
+      move |tx| instanz.execute(rx, ThreadSender::new(tx, "SRCP Dispatcher")),
+    );
+  }
+}
@@ -1,50 +1,79 @@
 use std::{
   cell::RefCell,
+  collections::hash_map::RandomState,
   collections::HashMap,
+  fs,
+  hash::{BuildHasher, Hasher},
   rc::Rc,
-  sync::mpsc::{Receiver, Sender},
-  thread,
+  sync::mpsc::{Receiver, SyncSender},
   time::{Duration, Instant},
 };
 
 use gpio_cdev::{Chip, LineHandle, LineRequestFlags};
-use log::{error, warn};
+use log::{info, warn};
+use nix::libc;
 use spidev::{SpiModeFlags, Spidev, SpidevOptions};
 
 use crate::{
-  srcp_devices_ddl::{self},
-  srcp_devices_ddl_gl::DdlGL,
-  srcp_devices_ddl_sm::DdlSM,
+  srcp_dcc_prog::ProgAckKonfig,
+  srcp_devices_ddl::{self, DdlAusgabe, ProgAusgabe},
+  srcp_devices_ddl_gl::{DdlGL, DdlGLKonfig, GLUebernahme},
+  srcp_devices_ddl_server::DdlServer,
+  srcp_devices_ddl_sm::{DdlSM, DdlSMKonfig},
+  srcp_hardware,
+  srcp_mfx_rds::MfxRdsKonfig,
+  srcp_nothalt::NothaltZonen,
   srcp_protocol_ddl::{HashMapProtokollVersion, HashMapVersion},
-  srcp_protocol_ddl_dcc::{DccProtokoll, DccVersion},
-  srcp_protocol_ddl_mfx::{MfxProtokoll, MfxVersion},
-  srcp_protocol_ddl_mm::{MMProtokoll, MmVersion},
+  srcp_protocol_ddl_dcc::{DccProtokoll, DccVersion, SPI_BAUDRATE_NMRA_2},
+  srcp_protocol_ddl_mfx::{MfxProtokoll, MfxVersion, MFX_ANZ_F_MAX, SPI_BAUDRATE_MFX_2},
+  srcp_protocol_ddl_mm::{
+    MMProtokoll, MmVersion, MM_GA_IMPULS_0_BEREICH, MM_GA_IMPULS_0_DEFAULT,
+    MM_GA_WIEDERHOLUNGEN_DEFAULT, MM_RICHTUNGSWECHSEL_IMPULSE_DEFAULT,
+    MM_RICHTUNGSWECHSEL_PAUSE_MAX, SPI_BAUDRATE_MAERKLIN_FUNC_2,
+  },
+  srcp_protocol_ddl_sx::{SxProtokoll, SPI_BAUDRATE_SX},
   srcp_server_types::{
-    self, Message, SRCPMessage, SRCPMessageDevice, SRCPMessageID, SRCPMessageType, SRCPServer,
+    spawn_server_thread, Message, SRCPMessage, SRCPMessageDevice, SRCPMessageID, SRCPMessageType,
+    SRCPServer, ThreadSender,
   },
 };
-use crate::{srcp_devices_ddl_ga::DdlGA, srcp_protocol_ddl_mm::SPI_BAUDRATE_MAERKLIN_LOCO_2};
-use crate::{srcp_devices_ddl_power::DdlPower, srcp_protocol_ddl::DdlProtokolle};
+use crate::{
+  srcp_devices_ddl_ga::{DdlGA, DdlGAKonfig, GAKollision, GAPulsKonfig, GARestoreKonfig},
+  srcp_protocol_ddl_mm::SPI_BAUDRATE_MAERKLIN_LOCO_2,
+};
+use crate::{
+  srcp_devices_ddl_power::{DdlPower, PowerStart},
+  srcp_protocol_ddl::DdlProtokolle,
+};
+
+/// Alle Devices eines DDL Servers
+type HashMapDevices<'a> =
+  HashMap<SRCPMessageDevice, Rc<RefCell<dyn srcp_devices_ddl::SRCPDeviceDDL + 'a>>>;
+
+/// SM Type zum Setzen / Abfragen der MFX UID der Zentrale zur Laufzeit
+const SM_TYPE_MFXUID: &str = "MFXUID";
 
 /// Watchdog Timeout für Power Off
 const WATCHDOG_TIMEOUT: Duration = Duration::from_secs(2);
+/// Default Anzahl aufeinanderfolgend wegen SPI Schreibfehler nicht gesendeter Telegramme bis Power Off
+const SPI_MAX_FEHLER_DEFAULT: u32 = 5;
 /// Defaultpfad zum File für Speicherung Neuanmeldezähler
 const PATH_REG_COUNTER_FILE: &str = "/etc/srcpd.regcount";
-/// Thread Sleep wenn Power Off ist damit nicht 100% CPU Last vorhanden ist
-const POWER_OFF_CPU_PAUSE: Duration = Duration::from_millis(10);
-/// Input Prog Ack Signal GPIO 22 (= Pin 15, RI von RS232)
-const GPIO_PROG_ACK: u32 = 22;
-
-use lazy_static::lazy_static;
-//Wegen V1 und 2 zwei Instanzen, beide brauchen ACK GPIO Input -> wird einmal hier erstellt.
-lazy_static! {
-  static ref GPIO_PROG_ACK_LINE_HANDLE: LineHandle = Chip::new("/dev/gpiochip0")
-    .expect("/dev/gpiochip0 konnte nicht geöffnet werden")
-    .get_line(GPIO_PROG_ACK)
-    .expect("GPIO_MFX_RDS_QAL konnte nicht geöffnet werden")
-    .request(LineRequestFlags::INPUT, 0, "input_dcc_prog_ack")
-    .expect("GPIO_MFX_RDS_QAL konnte nicht als Input geöffnet werden");
-}
+/// Defaultpfad zum File für Speicherung des Power Zustandes (power_start = restore)
+const PATH_POWER_FILE: &str = "/etc/srcpd.power";
+/// Default Verzögerung Power On beim Start (power_start = on / restore)
+const POWER_START_DELAY_DEFAULT: Duration = Duration::from_secs(2);
+/// Defaultpfad zum File für Speicherung einer zufällig erzeugten MFX UID der Zentrale (mfx = auto)
+const PATH_MFX_UID_FILE: &str = "/etc/srcpd.mfxuid";
+/// File mit der Seriennummer des Raspberry PI
+const PATH_CPUINFO: &str = "/proc/cpuinfo";
+/// Max. Wartezeit wenn nichts gesendet wurde damit nicht 100% CPU Last vorhanden ist.
+/// Begrenzt die Reaktionszeit auf nicht zeitgesteuerte Ereignisse (z.B. Kurzschlusserkennung, MFX RDS).
+const MAX_CPU_PAUSE: Duration = Duration::from_millis(10);
+/// Max. SPI Clock Teiler des BCM2835 SPI, der Teiler ist immer gerade
+const SPI_MAX_TEILER: u64 = 65536;
+/// Max. erlaubte Abweichung des effektiven SPI Clocks von der Protokoll Baudrate in Promille
+const SPI_CLOCK_TOLERANZ_PROMILLE: u64 = 30;
 
 pub struct DDL {
   //Konfiguration
@@ -52,10 +81,16 @@ pub struct DDL {
   busnr: usize,
   //SPI Port
   spiport: String,
+  //SPI Port separater Programmiergleis Ausgang, None wenn SM über den Hauptausgang
+  prog_spiport: Option<String>,
   //Märklin Mototrola Protokoll aktiv
   maerklin_enabled: bool,
   //DCC Protokoll aktiv
   dcc_enabled: bool,
+  //DCC mit RailCom Cutout nach jedem Paket auf dem Hauptgleis
+  dcc_railcom: bool,
+  //Selectrix Protokoll aktiv
+  selectrix_enabled: bool,
   //MFX Protokoll aktiv wenn UID > 0
   mfx_enabled_uid: u32,
   //UDP Basisportnummer für MFX RDS Rückmeldungen, wenn diese von GNU RADIO "mfxrds" kommen.
@@ -63,6 +98,8 @@ pub struct DDL {
   udp_mfxrds_port: Option<u16>,
   //Pfad zu File zur Speicherung Neuanmeldezähler
   mfx_reg_count_file: String,
+  //Pfad zu File zur Speicherung einer zufällig erzeugten MFX UID (mfx = auto)
+  mfx_uid_file: String,
   //Booster mit On/Off mit "Siggmode" (Impuls auf RTS für On, Impuls auf DTR für Off)
   siggmode: bool,
   //DSR Booster GO Meldung Invers (bei nicht siggmode)
@@ -72,38 +109,131 @@ pub struct DDL {
   //Wenn Siggmode: minimale Power On Zeit damit einmalig bei Ausschaltung
   //(wegen Kurzschluss) wieder versucht wird einzuschalten.
   timeout_shortcut_power_off: u64,
+  //Wenn nicht Siggmode: Sperre Power On nach Kurzschlussabschaltung, None: keine Sperre
+  //Aufhebung wenn während dieser Zeit kein Kurzschluss mehr gemeldet wird (0 = nie) oder mit SET <bus> POWER RESET
+  shortcut_lockout: Option<Duration>,
   //Watchdog aktiviert, automatische Power Ausschaltung wenn 2s lang keine Kommando empfangen wurde
   watchdog: bool,
+  //Anzahl aufeinanderfolgend wegen SPI Schreibfehler nicht gesendeter Telegramme bis Power Off, 0 = nie
+  spi_max_fehler: u32,
+  //MM GA: Breite Impuls 0 in SPI Bits und Anzahl Wiederholungen Telegramm
+  mm_ga_impuls_0: u32,
+  mm_ga_wiederholungen: usize,
+  //MM1 GL Richtungswechsel: Pause nach Halt und Richtungsimpuls (0: ohne) und Anzahl Richtungsimpulse
+  mm_richtungswechsel_pause: Duration,
+  mm_richtungswechsel_impulse: usize,
+  //MFX: Max. Anzahl Funktionen nach automatischer Anmeldung gemäss Dekoder
+  mfx_fx_max: usize,
+  //MFX: Dekoder gilt als abwesend wenn er so lange nicht auf Ping antwortet, None: kein Ping
+  mfx_absent: Option<Duration>,
+  //MFX: Abwesende Dekoder entfernen (SID freigeben)
+  mfx_absent_term: bool,
+  //MFX: Timeouts und Wiederholungen RDS Rückmeldungen
+  mfx_rds_konfig: MfxRdsKonfig,
+  //MFX: File für persistenten Cache der gelesenen Lokparameter, None: kein Cache
+  mfx_lok_cache_file: Option<String>,
+  //Power Zustand beim Start
+  power_start: PowerStart,
+  //Pfad zu File zur Speicherung des Power Zustandes
+  power_file: String,
   //Oszi Triggerkonfiguration aus Konfigfile
   trigger_port: Option<String>,
   trigger_gl: Option<String>,
   trigger_ga: Option<String>,
   trigger_sm: Option<String>,
+  //Grenzen für die Einschaltdauer der GA's
+  ga_puls: GAPulsKonfig,
+  //Verhalten bei INIT GA auf einem Dekoder mit GA's eines anderen Protokolls
+  ga_collision: GAKollision,
+  //Wiederherstellen GA Zustände nach Power On
+  ga_restore: GARestoreKonfig,
+  //Momentfunktionen GL aus Konfigfile
+  gl_momentary: Option<String>,
+  //Verhalten bei SET GL durch eine andere Session als den bisherigen Besitzer
+  gl_takeover: GLUebernahme,
+  //Geschwindigkeitskennlinien GL aus Konfigfile, Key GL Adresse
+  gl_speedcurve: HashMap<u32, Vec<u8>>,
+  //Max. Fahrstufe GL aus Konfigfile, Key GL Adresse
+  gl_speedmax: HashMap<u32, usize>,
+  //Max. Anzahl initialisierter GL's, None wenn unbegrenzt
+  gl_max: Option<usize>,
+  //GPIO Eingang Bestätigung Programmiergleis (Booster strombegrenzt), None wenn nicht vorhanden
+  prog_gleis_gpio: Option<u32>,
+  //GPIO Eingang Bestätigung Programmiergleis ist Low aktiv
+  prog_gleis_invers: bool,
+  //Nach jedem SET SM auf dem Programmiergleis den Wert zurücklesen
+  sm_write_verify: bool,
+  //Erkennung Quittierungsimpuls Programmiergleis
+  prog_ack: ProgAckKonfig,
+  //Echtzeit Priorität (SCHED_FIFO 1..99) für den DDL Ausgabethread, None wenn normales Scheduling
+  rt_priority: Option<i32>,
+  //CPU auf die der DDL Ausgabethread fixiert wird, None wenn keine Fixierung
+  cpu_affinity: Option<usize>,
+  //core_freq in Hz aus Konfigfile, None wenn über debugfs ermittelt
+  core_freq: Option<u64>,
 
   //Daten, werden nicht geklont
-  //SPI Bus
-  spidev: Option<Spidev>,
+  //Ausgabe über SPI Bus
+  ausgabe: Option<DdlAusgabe>,
+  //Ausgabe über SPI Bus separater Programmiergleis Ausgang in eigenem Thread
+  prog_ausgabe: Option<ProgAusgabe>,
+  //GPIO Eingang Bestätigung Programmiergleis
+  prog_gleis_input: Option<LineHandle>,
 }
 impl Clone for DDL {
   fn clone(&self) -> DDL {
     DDL {
       busnr: self.busnr,
       spiport: self.spiport.clone(),
+      prog_spiport: self.prog_spiport.clone(),
       maerklin_enabled: self.maerklin_enabled,
       dcc_enabled: self.dcc_enabled,
+      dcc_railcom: self.dcc_railcom,
+      selectrix_enabled: self.selectrix_enabled,
       mfx_enabled_uid: self.mfx_enabled_uid,
       udp_mfxrds_port: self.udp_mfxrds_port,
       mfx_reg_count_file: self.mfx_reg_count_file.clone(),
+      mfx_uid_file: self.mfx_uid_file.clone(),
       siggmode: self.siggmode,
       dsr_invers: self.dsr_invers,
       shortcut_delay: self.shortcut_delay,
       timeout_shortcut_power_off: self.timeout_shortcut_power_off,
+      shortcut_lockout: self.shortcut_lockout,
       watchdog: self.watchdog,
-      spidev: None, //Wird nie geklont
+      spi_max_fehler: self.spi_max_fehler,
+      mm_ga_impuls_0: self.mm_ga_impuls_0,
+      mm_ga_wiederholungen: self.mm_ga_wiederholungen,
+      mm_richtungswechsel_pause: self.mm_richtungswechsel_pause,
+      mm_richtungswechsel_impulse: self.mm_richtungswechsel_impulse,
+      mfx_fx_max: self.mfx_fx_max,
+      mfx_absent: self.mfx_absent,
+      mfx_absent_term: self.mfx_absent_term,
+      mfx_rds_konfig: self.mfx_rds_konfig.clone(),
+      mfx_lok_cache_file: self.mfx_lok_cache_file.clone(),
+      power_start: self.power_start,
+      power_file: self.power_file.clone(),
+      ausgabe: None,      //Wird nie geklont
+      prog_ausgabe: None, //Wird nie geklont
       trigger_port: self.trigger_port.clone(),
       trigger_gl: self.trigger_gl.clone(),
       trigger_ga: self.trigger_ga.clone(),
       trigger_sm: self.trigger_sm.clone(),
+      ga_puls: self.ga_puls.clone(),
+      ga_collision: self.ga_collision,
+      ga_restore: self.ga_restore.clone(),
+      gl_momentary: self.gl_momentary.clone(),
+      gl_takeover: self.gl_takeover,
+      gl_speedcurve: self.gl_speedcurve.clone(),
+      gl_speedmax: self.gl_speedmax.clone(),
+      gl_max: self.gl_max,
+      prog_gleis_gpio: self.prog_gleis_gpio,
+      prog_gleis_invers: self.prog_gleis_invers,
+      sm_write_verify: self.sm_write_verify,
+      prog_ack: self.prog_ack.clone(),
+      rt_priority: self.rt_priority,
+      cpu_affinity: self.cpu_affinity,
+      core_freq: self.core_freq,
+      prog_gleis_input: None, //Wird nie geklont
     }
   }
 }
@@ -114,21 +244,178 @@ impl DDL {
     DDL {
       busnr: 0,
       spiport: "".to_string(),
+      prog_spiport: None,
       maerklin_enabled: false,
       dcc_enabled: false,
+      dcc_railcom: false,
+      selectrix_enabled: false,
       mfx_enabled_uid: 0,
       udp_mfxrds_port: None,
       mfx_reg_count_file: PATH_REG_COUNTER_FILE.to_string(),
+      mfx_uid_file: PATH_MFX_UID_FILE.to_string(),
       siggmode: false,
       dsr_invers: false,
       shortcut_delay: 0,
       timeout_shortcut_power_off: 0,
+      shortcut_lockout: None,
       watchdog: false,
-      spidev: None,
+      spi_max_fehler: SPI_MAX_FEHLER_DEFAULT,
+      mm_ga_impuls_0: MM_GA_IMPULS_0_DEFAULT,
+      mm_ga_wiederholungen: MM_GA_WIEDERHOLUNGEN_DEFAULT,
+      mm_richtungswechsel_pause: Duration::ZERO,
+      mm_richtungswechsel_impulse: MM_RICHTUNGSWECHSEL_IMPULSE_DEFAULT,
+      mfx_fx_max: MFX_ANZ_F_MAX,
+      mfx_absent: None,
+      mfx_absent_term: false,
+      mfx_rds_konfig: MfxRdsKonfig::default(),
+      mfx_lok_cache_file: None,
+      power_start: PowerStart::Aus,
+      power_file: PATH_POWER_FILE.to_string(),
+      ausgabe: None,
+      prog_ausgabe: None,
       trigger_port: None,
       trigger_gl: None,
       trigger_ga: None,
       trigger_sm: None,
+      ga_puls: GAPulsKonfig::default(),
+      ga_collision: GAKollision::Warnen,
+      ga_restore: GARestoreKonfig::default(),
+      gl_momentary: None,
+      gl_takeover: GLUebernahme::Keine,
+      gl_speedcurve: HashMap::new(),
+      gl_speedmax: HashMap::new(),
+      gl_max: None,
+      prog_gleis_gpio: None,
+      prog_gleis_invers: false,
+      sm_write_verify: false,
+      prog_ack: ProgAckKonfig::Gpio,
+      rt_priority: None,
+      cpu_affinity: None,
+      core_freq: None,
+      prog_gleis_input: None,
+    }
+  }
+
+  /// Liefert alle durch die aktiven Protokolle verwendeten SPI Baudraten
+  fn get_spi_baudraten(&self) -> Vec<(DdlProtokolle, u32)> {
+    let mut result: Vec<(DdlProtokolle, u32)> = Vec::new();
+    if self.maerklin_enabled {
+      result.push((DdlProtokolle::Maerklin, SPI_BAUDRATE_MAERKLIN_LOCO_2));
+      result.push((DdlProtokolle::Maerklin, SPI_BAUDRATE_MAERKLIN_FUNC_2));
+    }
+    if self.dcc_enabled {
+      result.push((DdlProtokolle::Dcc, SPI_BAUDRATE_NMRA_2));
+    }
+    if self.selectrix_enabled {
+      result.push((DdlProtokolle::Selectrix, SPI_BAUDRATE_SX));
+    }
+    if self.mfx_enabled_uid > 0 {
+      result.push((DdlProtokolle::Mfx, SPI_BAUDRATE_MFX_2));
+    }
+    result
+  }
+
+  /// Liefert den Clock (core_freq) in Hz von dem der SPI Clock abgeleitet wird.
+  /// Aus Konfigfile wenn angegeben, sonst über debugfs. None wenn nicht ermittelbar.
+  fn get_core_freq(&self) -> Option<u64> {
+    self.core_freq.or_else(srcp_hardware::get_core_freq)
+  }
+
+  /// Effektiver SPI Clock wie er durch den BCM2835 SPI Treiber eingestellt wird:
+  /// Teiler aufgerundet auf eine gerade Zahl, max. "SPI_MAX_TEILER".
+  /// Liefert None zurück, wenn die Baudrate mit dieser core_freq nicht erreicht werden kann (zu langsam).
+  /// # Arguments
+  /// * core_freq - Clock in Hz von dem der SPI Clock abgeleitet wird
+  /// * hz - Verlangte SPI Baudrate
+  fn get_spi_clock_effektiv(core_freq: u64, hz: u32) -> Option<u64> {
+    let hz = hz as u64;
+    let teiler = if hz >= core_freq / 2 {
+      2
+    } else {
+      let teiler = core_freq.div_ceil(hz);
+      teiler + (teiler % 2)
+    };
+    if teiler > SPI_MAX_TEILER {
+      None
+    } else {
+      Some(core_freq / teiler)
+    }
+  }
+
+  /// Prüfung des effektiven SPI Clocks aller aktiven Protokolle bei der aktuellen core_freq.
+  /// Liefert Err zurück, wenn eine Baudrate nicht genau genug erreicht werden kann (core_freq falsch konfiguriert).
+  fn check_spi_clock(&self) -> Result<(), String> {
+    let Some(core_freq) = self.get_core_freq() else {
+      warn!("DDL: core_freq kann nicht ermittelt werden (debugfs nicht gemountet?), SPI Clock ungeprüft. Angabe mit core_freq = <Hz> möglich.");
+      return Ok(());
+    };
+    for (protokoll, hz) in self.get_spi_baudraten() {
+      let effektiv = DDL::get_spi_clock_effektiv(core_freq, hz).ok_or(format!(
+        "DDL: SPI Clock {} Hz für {} bei core_freq {} Hz nicht möglich. In /boot/config.txt core_freq=250 und core_freq_min=250 setzen!",
+        hz,
+        protokoll.to_string(),
+        core_freq
+      ))?;
+      let abweichung = effektiv.abs_diff(hz as u64) * 1000 / hz as u64;
+      if abweichung > SPI_CLOCK_TOLERANZ_PROMILLE {
+        return Err(format!(
+          "DDL: SPI Clock für {} effektiv {} Hz statt {} Hz bei core_freq {} Hz. In /boot/config.txt core_freq=250 und core_freq_min=250 setzen!",
+          protokoll.to_string(),
+          effektiv,
+          hz,
+          core_freq
+        ));
+      }
+      info!(
+        "DDL: SPI Clock {} effektiv {} Hz (Soll {} Hz, core_freq {} Hz)",
+        protokoll.to_string(),
+        effektiv,
+        hz,
+        core_freq
+      );
+    }
+    Ok(())
+  }
+
+  /// Für den aktuellen Thread (DDL Ausgabe) CPU Affinität und Echtzeit Scheduling (SCHED_FIFO) setzen,
+  /// wenn konfiguriert. Damit wird die Ausgabe nicht durch andere Threads (S88, TCP) verzögert.
+  /// Fehlende Berechtigungen (root oder CAP_SYS_NICE) werden geloggt, es wird mit normalem Scheduling weitergearbeitet.
+  fn set_thread_scheduling(&self) {
+    if let Some(cpu) = self.cpu_affinity {
+      let result = unsafe {
+        let mut cpu_set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(cpu, &mut cpu_set);
+        //pid 0 = aktueller Thread
+        libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &cpu_set)
+      };
+      if result == 0 {
+        info!("DDL: Ausgabethread auf CPU {} fixiert", cpu);
+      } else {
+        warn!(
+          "DDL: Ausgabethread konnte nicht auf CPU {} fixiert werden: {}",
+          cpu,
+          std::io::Error::last_os_error()
+        );
+      }
+    }
+    if let Some(prio) = self.rt_priority {
+      let result = unsafe {
+        let mut param: libc::sched_param = std::mem::zeroed();
+        param.sched_priority = prio;
+        libc::pthread_setschedparam(libc::pthread_self(), libc::SCHED_FIFO, &param)
+      };
+      match result {
+        0 => info!("DDL: Ausgabethread mit SCHED_FIFO Priorität {}", prio),
+        libc::EPERM => warn!(
+          "DDL: SCHED_FIFO Priorität {} nicht erlaubt (root oder CAP_SYS_NICE notwendig), normales Scheduling",
+          prio
+        ),
+        err => warn!(
+          "DDL: SCHED_FIFO Priorität {} konnte nicht gesetzt werden: {}, normales Scheduling",
+          prio,
+          std::io::Error::from_raw_os_error(err)
+        ),
+      }
     }
   }
 
@@ -140,14 +427,24 @@ impl DDL {
     if self.maerklin_enabled {
       //MM
       let mut mm_protocols: HashMapVersion = HashMap::new();
+      //Alle Versionen mit konfiguriertem GA Timing und MM1 Richtungswechsel
+      let mm = |version: MmVersion| {
+        let mut mm = MMProtokoll::from(version);
+        mm.set_ga_timing(self.mm_ga_impuls_0, self.mm_ga_wiederholungen);
+        mm.set_richtungswechsel(
+          self.mm_richtungswechsel_pause,
+          self.mm_richtungswechsel_impulse,
+        );
+        Rc::new(RefCell::new(mm))
+      };
       //MM V1
-      mm_protocols.insert("1", Rc::new(RefCell::new(MMProtokoll::from(MmVersion::V1))));
+      mm_protocols.insert("1", mm(MmVersion::V1));
       //MM V2
-      mm_protocols.insert("2", Rc::new(RefCell::new(MMProtokoll::from(MmVersion::V2))));
+      mm_protocols.insert("2", mm(MmVersion::V2));
       //MM V3
-      mm_protocols.insert("3", Rc::new(RefCell::new(MMProtokoll::from(MmVersion::V3))));
+      mm_protocols.insert("3", mm(MmVersion::V3));
       //MM V5
-      mm_protocols.insert("5", Rc::new(RefCell::new(MMProtokoll::from(MmVersion::V5))));
+      mm_protocols.insert("5", mm(MmVersion::V5));
       all_protocols.insert(DdlProtokolle::Maerklin, mm_protocols);
     }
     if self.dcc_enabled {
@@ -158,7 +455,9 @@ impl DDL {
         "1",
         Rc::new(RefCell::new(DccProtokoll::from(
           DccVersion::V1,
-          &GPIO_PROG_ACK_LINE_HANDLE,
+          Some(&self.prog_ack),
+          self.prog_spiport.is_some(),
+          self.dcc_railcom,
         ))),
       );
       //DCC V2
@@ -166,66 +465,162 @@ impl DDL {
         "2",
         Rc::new(RefCell::new(DccProtokoll::from(
           DccVersion::V2,
-          &GPIO_PROG_ACK_LINE_HANDLE,
+          Some(&self.prog_ack),
+          self.prog_spiport.is_some(),
+          self.dcc_railcom,
         ))),
       );
       all_protocols.insert(DdlProtokolle::Dcc, dcc_protocols);
     }
+    if self.selectrix_enabled {
+      //Selectrix, nur SX1
+      let mut sx_protocols: HashMapVersion = HashMap::new();
+      sx_protocols.insert("1", Rc::new(RefCell::new(SxProtokoll::new())));
+      all_protocols.insert(DdlProtokolle::Selectrix, sx_protocols);
+    }
     if self.mfx_enabled_uid > 0 {
       //MFX
-      let mut mfx_protocols: HashMapVersion = HashMap::new();
-      //MFX V0
-      mfx_protocols.insert(
-        "0",
-        Rc::new(RefCell::new(MfxProtokoll::from(
-          MfxVersion::V0,
-          self.mfx_enabled_uid,
-          self.mfx_reg_count_file.clone(),
-          self.udp_mfxrds_port,
-        ))),
+      all_protocols.insert(
+        DdlProtokolle::Mfx,
+        self.get_mfx_protocols(self.mfx_enabled_uid),
       );
-      all_protocols.insert(DdlProtokolle::Mfx, mfx_protocols);
     }
     all_protocols
   }
 
+  /// Liefert alle Versionen des MFX Protokolls.
+  /// # Arguments
+  /// * uid - MFX UID der Zentrale
+  fn get_mfx_protocols(&self, uid: u32) -> HashMapVersion {
+    let mut mfx_protocols: HashMapVersion = HashMap::new();
+    //MFX V0
+    let mut mfx = MfxProtokoll::from(
+      MfxVersion::V0,
+      uid,
+      self.mfx_reg_count_file.clone(),
+      self.udp_mfxrds_port,
+      true,
+      self.mfx_rds_konfig.clone(),
+    );
+    mfx.set_anz_f_auto_max(self.mfx_fx_max);
+    mfx.set_anwesenheit(self.mfx_absent, self.mfx_absent_term);
+    mfx.set_lok_cache(self.mfx_lok_cache_file.clone());
+    mfx_protocols.insert("0", Rc::new(RefCell::new(mfx)));
+    mfx_protocols
+  }
+
+  /// Automatisch erzeugte MFX UID der Zentrale (Konfiguration mfx = auto).
+  /// Damit nicht mehrere Zentralen mit der selben UID arbeiten wird verwendet:
+  /// - Seriennummer des Raspberry PI (untere 32 Bit), wenn vorhanden
+  /// - sonst eine einmal zufällig erzeugte und in "mfx_uid_file" gespeicherte UID
+  fn get_mfx_uid_auto(&self) -> u32 {
+    //Seriennummer Raspberry PI, Zeile "Serial          : 10000000abcdef12"
+    if let Ok(cpuinfo) = fs::read_to_string(PATH_CPUINFO) {
+      for line in cpuinfo.lines() {
+        if let Some((key, value)) = line.split_once(':') {
+          if key.trim() == "Serial" {
+            if let Ok(serial) = u64::from_str_radix(value.trim(), 16) {
+              let uid = serial as u32;
+              if uid > 0 {
+                info!("DDL: MFX UID Zentrale aus Seriennummer {}", value.trim());
+                return uid;
+              }
+            }
+          }
+        }
+      }
+    }
+    //Bereits früher erzeugte UID
+    if let Ok(uid_str) = fs::read_to_string(&self.mfx_uid_file) {
+      if let Ok(uid) = uid_str.trim().parse::<u32>() {
+        if uid > 0 {
+          return uid;
+        }
+      }
+    }
+    //Neue, zufällige UID erzeugen und speichern
+    let mut uid: u32 = 0;
+    while uid == 0 {
+      let mut hasher = RandomState::new().build_hasher();
+      hasher.write_u32(std::process::id());
+      uid = hasher.finish() as u32;
+    }
+    info!("DDL: MFX UID Zentrale zufällig erzeugt");
+    if fs::write(&self.mfx_uid_file, uid.to_string()).is_err() {
+      warn!(
+        "DDL: MFX UID konnte nicht in {} gespeichert werden",
+        self.mfx_uid_file
+      );
+    }
+    uid
+  }
+
+  /// Verwendete MFX UID der Zentrale als SRCP Info melden
+  /// # Arguments
+  /// * tx - Channel Sender für Info Messages
+  /// * session_id - SRCP Client Session ID an die gemeldet werden soll, None: an alle
+  /// * uid - MFX UID der Zentrale
+  fn send_info_mfx_uid(&self, tx: &ThreadSender<SRCPMessage>, session_id: Option<u32>, uid: u32) {
+    //INFO <bus> GM <send_to> <reply_to> SRCP_MFX_UID <MESSAGE>
+    // - bus: aktueller Bus, Abweichung von SRCP Spezifikation, die GM nur für Bus 0 erlaubt!
+    // - send_to, reply_to: immer 0
+    // - MESSAGE: "<protokoll_id>:<UID>"
+    tx.send(SRCPMessage::new(
+      session_id,
+      self.busnr,
+      SRCPMessageID::Info {
+        info_code: "100".to_string(),
+      },
+      SRCPMessageDevice::GM,
+      vec![
+        "0".to_string(),
+        "0".to_string(),
+        "SRCP_MFX_UID".to_string(),
+        format!("{}:\"{}\"", DdlProtokolle::Mfx.to_string(), uid),
+      ],
+    ));
+  }
+
   /// Liefert alle unterstützten Devices zurück
   /// # Arguments
   /// * tx - Channel Sender über den Info Messages zurück gesendet werden können
-  fn get_all_devices(
-    &self, tx: &Sender<SRCPMessage>,
-  ) -> HashMap<
-    srcp_server_types::SRCPMessageDevice,
-    Rc<RefCell<dyn srcp_devices_ddl::SRCPDeviceDDL + '_>>,
-  > {
+  fn get_all_devices(&self, tx: &ThreadSender<SRCPMessage>) -> HashMapDevices<'_> {
     let all_protokolle = self.get_all_protocols();
-    let mut all_devices: HashMap<
-      SRCPMessageDevice,
-      Rc<RefCell<dyn srcp_devices_ddl::SRCPDeviceDDL>>,
-    > = HashMap::new();
+    let mut all_devices: HashMapDevices = HashMap::new();
+    let ausgabe = self
+      .ausgabe
+      .as_ref()
+      .expect("DDL Devices erst nach Öffnen SPI Bus");
 
     //Power Device
-    all_devices.insert(
-      SRCPMessageDevice::Power,
-      Rc::new(RefCell::new(DdlPower::new(
-        self.busnr,
-        tx.clone(),
-        self.siggmode,
-        self.dsr_invers,
-        self.shortcut_delay,
-        self.timeout_shortcut_power_off,
-      ))),
+    let mut power = DdlPower::new(
+      self.busnr,
+      tx.clone(),
+      self.siggmode,
+      self.dsr_invers,
+      self.shortcut_delay,
+      self.timeout_shortcut_power_off,
+      self.spi_max_fehler,
     );
+    power.set_shortcut_lockout(self.shortcut_lockout);
+    power.set_spi_fehler(ausgabe.get_spi_fehler());
+    power.set_power_start(self.power_start, &self.power_file);
+    all_devices.insert(SRCPMessageDevice::Power, Rc::new(RefCell::new(power)));
     //GA Device
     all_devices.insert(
       SRCPMessageDevice::GA,
       Rc::new(RefCell::new(DdlGA::new(
         self.busnr,
         tx.clone(),
-        &self.spidev,
+        ausgabe,
         all_protokolle.clone(),
-        self.trigger_port.clone(),
-        self.trigger_ga.clone(),
+        DdlGAKonfig {
+          trigger_port: self.trigger_port.clone(),
+          trigger_adr: self.trigger_ga.clone(),
+          puls_konfig: self.ga_puls.clone(),
+          kollision: self.ga_collision,
+          restore_konfig: self.ga_restore.clone(),
+        },
       ))),
     );
     //GL Device
@@ -234,10 +629,17 @@ impl DDL {
       Rc::new(RefCell::new(DdlGL::new(
         self.busnr,
         tx.clone(),
-        &self.spidev,
+        ausgabe,
         all_protokolle.clone(),
-        self.trigger_port.clone(),
-        self.trigger_gl.clone(),
+        DdlGLKonfig {
+          trigger_port: self.trigger_port.clone(),
+          trigger_adr: self.trigger_gl.clone(),
+          momentary: self.gl_momentary.clone(),
+          speedcurve: self.gl_speedcurve.clone(),
+          speedmax: self.gl_speedmax.clone(),
+          uebernahme: self.gl_takeover,
+          gl_max: self.gl_max,
+        },
       ))),
     );
     //SM Device
@@ -247,42 +649,166 @@ impl DDL {
         self.busnr,
         tx.clone(),
         all_protokolle.clone(),
-        self.trigger_sm.clone(),
+        &self.prog_gleis_input,
+        &self.prog_ausgabe,
+        DdlSMKonfig {
+          trigger_port: self.trigger_port.clone(),
+          trigger_adr: self.trigger_sm.clone(),
+          prog_gleis_invers: self.prog_gleis_invers,
+          write_verify: self.sm_write_verify,
+        },
+      ))),
+    );
+    //Server Device für Abfrage der vorhandenen Protokolle
+    all_devices.insert(
+      SRCPMessageDevice::Server,
+      Rc::new(RefCell::new(DdlServer::new(
+        self.busnr,
+        tx.clone(),
+        all_protokolle,
       ))),
     );
     all_devices
   }
 
+  /// Liefert true, wenn es sich um ein Kommando für die MFX UID der Zentrale handelt:
+  /// GET <bus> SM 0 MFXUID
+  /// SET <bus> SM 0 MFXUID <uid>
+  /// # Arguments
+  /// * cmd_msg - Empfangenes Kommando
+  fn is_mfx_uid_cmd(cmd_msg: &SRCPMessage) -> bool {
+    (cmd_msg.device == SRCPMessageDevice::SM)
+      && (cmd_msg.parameter.get(1).map(String::as_str) == Some(SM_TYPE_MFXUID))
+  }
+
+  /// Kommando für die MFX UID der Zentrale ausführen.
+  /// Mit SET wird das MFX Protokoll zur Laufzeit mit der neuen UID in allen Devices ersetzt,
+  /// mit UID 0 wird es entfernt.
+  /// Liefert die nun gültige UID zurück, 0 wenn MFX nicht aktiv ist.
+  /// # Arguments
+  /// * cmd_msg - Empfangenes Kommando
+  /// * mfx_uid - Aktuelle UID, 0 wenn MFX nicht aktiv ist
+  /// * all_devices - Alle Devices die das Protokoll verwenden
+  /// * tx - Channel Sender für Antworten und Info Messages
+  fn execute_mfx_uid_cmd(
+    &self, cmd_msg: &SRCPMessage, mfx_uid: u32, all_devices: &HashMapDevices,
+    tx: &ThreadSender<SRCPMessage>,
+  ) -> u32 {
+    let SRCPMessageID::Command { msg_type } = cmd_msg.message_id else {
+      return mfx_uid;
+    };
+    let mut result = mfx_uid;
+    let neue_uid = cmd_msg
+      .parameter
+      .get(2)
+      .and_then(|uid| uid.parse::<u32>().ok());
+    match (msg_type, cmd_msg.parameter.len(), neue_uid) {
+      (SRCPMessageType::GET, 2, _) => {
+        tx.send(SRCPMessage::new(
+          cmd_msg.session_id,
+          self.busnr,
+          SRCPMessageID::Ok {
+            ok_code: "200".to_string(),
+          },
+          SRCPMessageDevice::SM,
+          vec![
+            cmd_msg.parameter[0].clone(),
+            SM_TYPE_MFXUID.to_string(),
+            mfx_uid.to_string(),
+          ],
+        ));
+      }
+      (SRCPMessageType::SET, 3, Some(uid)) => {
+        if uid != mfx_uid {
+          //Zuerst altes Protokoll überall entfernen, damit dessen RDS Thread beendet ist
+          if mfx_uid > 0 {
+            for dev in all_devices.values() {
+              dev.borrow_mut().unregister_protokoll(DdlProtokolle::Mfx);
+            }
+          }
+          if uid > 0 {
+            let mfx_protocols = self.get_mfx_protocols(uid);
+            for dev in all_devices.values() {
+              dev
+                .borrow_mut()
+                .register_protokoll(DdlProtokolle::Mfx, &mfx_protocols);
+            }
+            self.send_info_mfx_uid(tx, None, uid);
+          }
+          info!("DDL: MFX UID Zentrale neu={}", uid);
+          result = uid;
+        }
+        tx.send(SRCPMessage::new_ok(cmd_msg, "200"));
+      }
+      (SRCPMessageType::GET, _, _) | (SRCPMessageType::SET, _, _) => {
+        tx.send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"));
+      }
+      _ => {
+        tx.send(SRCPMessage::new_err(
+          cmd_msg,
+          "423",
+          "unsupported operation",
+        ));
+      }
+    }
+    result
+  }
+
+  /// SPI Device öffnen und für DDL Ausgabe konfigurieren.
+  /// Liefert Err mit Fehlermeldung wenn das nicht möglich ist.
+  /// # Arguments
+  /// * spiport - SPI Port aus Konfigfile, z.B. /dev/spidev0
+  fn open_spidev(spiport: &str) -> Result<Spidev, String> {
+    let mut dev = Spidev::open(format!("{}.0", spiport)).map_err(|msg| {
+      format!(
+        "DDL: SPI Device {} konnte nicht geöffnet werden. {}",
+        spiport, msg
+      )
+    })?;
+    let options = SpidevOptions::new()
+      .bits_per_word(8)
+      .max_speed_hz(SPI_BAUDRATE_MAERKLIN_LOCO_2) //Spielt hier keine Rolle, wird bei jedem Transfer individuell gesetzt
+      .mode(SpiModeFlags::SPI_MODE_1)
+      .build();
+    dev.configure(&options).map_err(|_| {
+      format!(
+        "DDL: SPI Device {} konnte nicht konfiguriert werden.",
+        spiport
+      )
+    })?;
+    Ok(dev)
+  }
+
   /// Ausführung als Thread
   /// # Arguments
   /// * rx - Channel Receiver über denn Kommandos empfangen werden
   /// * tx - Channel Sender über den Info Messages zurück gesendet werden können
-  fn execute(&mut self, rx: Receiver<Message>, tx: Sender<SRCPMessage>) {
+  fn execute(
+    &mut self, rx: Receiver<Message>, tx: ThreadSender<SRCPMessage>,
+  ) -> Result<(), String> {
+    //Ohne genügend genauen SPI Clock können keine gültigen Telegramme erzeugt werden
+    self.check_spi_clock()?;
     //SPI Bus öffnen
-    match Spidev::open(format!("{}.0", self.spiport)) {
-      Ok(mut dev) => {
-        let options = SpidevOptions::new()
-          .bits_per_word(8)
-          .max_speed_hz(SPI_BAUDRATE_MAERKLIN_LOCO_2) //Spielt hier keine Rolle, wird bei jedem Transfer individuell gesetzt
-          .mode(SpiModeFlags::SPI_MODE_1)
-          .build();
-        if let Ok(()) = dev.configure(&options) {
-          self.spidev = Some(dev);
-        } else {
-          error!(
-            "DDL: SPI Device {} konnte nicht konfiguriert werden. Abbruch.",
-            self.spiport
-          );
-          return;
+    self.ausgabe = Some(DdlAusgabe::new(Self::open_spidev(&self.spiport)?));
+    //SPI Bus separater Programmiergleis Ausgang öffnen wenn konfiguriert
+    if let Some(prog_spiport) = &self.prog_spiport {
+      self.prog_ausgabe = Some(ProgAusgabe::start(Self::open_spidev(prog_spiport)?));
+    }
+    //GPIO Eingang Bestätigung Programmiergleis öffnen wenn konfiguriert
+    if let Some(gpio) = self.prog_gleis_gpio {
+      match Chip::new(srcp_hardware::get_gpiochip()).and_then(|mut chip| {
+        chip
+          .get_line(gpio)?
+          .request(LineRequestFlags::INPUT, 0, "input_prog_gleis")
+      }) {
+        Ok(line) => self.prog_gleis_input = Some(line),
+        Err(msg) => {
+          return Err(format!(
+            "DDL: Programmiergleis GPIO {} konnte nicht geöffnet werden. {}",
+            gpio, msg
+          ));
         }
       }
-      Err(msg) => {
-        error!(
-          "DDL: SPI Device {} konnte nicht geöffnet werden. Abbruch. {}",
-          self.spiport, msg
-        );
-        return;
-      }
     }
     //Warteschlange für alle SET ausser Power
     let mut queue: Vec<SRCPMessage> = Vec::new();
@@ -291,33 +817,99 @@ impl DDL {
 
     //Alle unterstützten Devices
     let all_devices = self.get_all_devices(&tx);
+    //MFX UID der Zentrale, kann zur Laufzeit geändert werden
+    let mut mfx_uid = self.mfx_enabled_uid;
+    //Erst nach dem Start der Hilfsthreads der Protokolle (DCC Prog, MFX RDS), damit diese das
+    //Echtzeit Scheduling nicht erben
+    self.set_thread_scheduling();
+    //Während dem Warten auf den nächsten Zeitpunkt empfangene Message
+    let mut msg_empfangen: Option<Message> = None;
+    //SPI und GPIO's sind offen, ab jetzt können Kommandos ausgeführt werden
+    tx.send(SRCPMessage::new_ready(self.busnr));
     loop {
       //Power Device muss vorhanden sein, is_dev_spezifisch() liefert den Power Zustand
       let power_on = all_devices[&SRCPMessageDevice::Power]
         .borrow()
         .is_dev_spezifisch();
+      //Wurde in diesem Durchgang etwas empfangen oder gesendet?
+      let mut tel_gesendet = false;
       //Immer alle ankommenden Kommandos auslesen
       loop {
-        if let Ok(msg) = rx.try_recv() {
+        if let Some(msg) = msg_empfangen.take().or_else(|| rx.try_recv().ok()) {
+          tel_gesendet = true;
           match msg {
             Message::NewInfoClient { session_id } => {
               //Alle Devices müssen alle Zustände an neuen Info Client senden
               for (_key, device) in &all_devices {
                 device.borrow().send_all_info(Some(session_id));
               }
+              //Und die verwendete MFX UID der Zentrale
+              if mfx_uid > 0 {
+                self.send_info_mfx_uid(&tx, Some(session_id), mfx_uid);
+              }
+            }
+            Message::SessionEnd { session_id } => {
+              //Alle Devices können Session abhängige Zustände freigeben
+              for (_key, device) in &all_devices {
+                device.borrow_mut().session_end(session_id);
+              }
+            }
+            Message::Beenden => {
+              //Alle Devices und Protokolle entfernen, damit beenden sich auch deren Hilfsthreads
+              //(DCC Prog, MFX RDS, separater Programmiergleis Ausgang)
+              info!("DDL Bus {} wird beendet", self.busnr);
+              return Ok(());
+            }
+            Message::Pruefen { srcp_message } => {
+              //Nur validieren, validate_cmd sendet bei einem Fehler die Antwort selbst
+              match &all_devices.get(&srcp_message.device) {
+                Some(device) if !Self::is_mfx_uid_cmd(&srcp_message) => {
+                  if device.borrow().validate_cmd(&srcp_message) {
+                    tx.send(SRCPMessage::new_pruefen_ok(&srcp_message));
+                  }
+                }
+                Some(_) => {
+                  tx.send(SRCPMessage::new_err(
+                    &srcp_message,
+                    "423",
+                    "unsupported operation",
+                  ));
+                }
+                None => {
+                  tx.send(SRCPMessage::new_err(
+                    &srcp_message,
+                    "421",
+                    "unsupported device",
+                  ));
+                }
+              }
             }
             Message::SRCPMessage { srcp_message } => {
-              if let SRCPMessageID::Command { msg_type } = srcp_message.message_id {
+              if Self::is_mfx_uid_cmd(&srcp_message) {
+                instant_kommando = Instant::now();
+                mfx_uid = self.execute_mfx_uid_cmd(&srcp_message, mfx_uid, &all_devices, &tx);
+              } else if let SRCPMessageID::Command { msg_type } = srcp_message.message_id {
                 instant_kommando = Instant::now();
                 match &all_devices.get(&srcp_message.device) {
                   //Nur Kommandomessages können (oder sollen) hier ankommen
                   Some(device) => {
                     if device.borrow().validate_cmd(&srcp_message) {
-                      //SET Kommandos (ausser für Power Device und SM) kommen in die Warteschlange da sie
+                      //SET Kommandos (ausser Power, SM und Nothalt Zonen) kommen in die Warteschlange da sie
                       //1. nur bei Power On ausgegeben werden
                       //2. Lok Kommandos für die selbe Lok überholen sich, sprich wenn ein neues empfangen wurde ist
                       //   ein altes, noch nicht ausgegebenes, für diese Lok immer hinfällig
-                      if (srcp_message.device == SRCPMessageDevice::Power)
+                      if NothaltZonen::is_nothalt_cmd(&srcp_message) {
+                        //Nothalt einer Zone sofort, noch wartende Kommandos für diese GL's sind hinfällig
+                        let adr = srcp_message.get_adr();
+                        queue.retain(|queue_msg| {
+                          (queue_msg.device != SRCPMessageDevice::GL)
+                            || ((adr != Some(0)) && (queue_msg.get_adr() != adr))
+                        });
+                        device
+                          .try_borrow_mut()
+                          .unwrap()
+                          .execute_cmd(&srcp_message, power_on);
+                      } else if (srcp_message.device == SRCPMessageDevice::Power)
                         || (srcp_message.device == SRCPMessageDevice::SM)
                         || (msg_type != SRCPMessageType::SET)
                       {
@@ -350,8 +942,7 @@ impl DDL {
                       &srcp_message,
                       "421",
                       "unsupported device",
-                    ))
-                    .unwrap();
+                    ));
                   }
                 }
               } else {
@@ -382,13 +973,17 @@ impl DDL {
               ),
               power_on,
             );
+          tel_gesendet = true;
         } else {
           if queue.is_empty() {
             //Nicht zu tun -> Refresh für GL wenn vorhanden
             if let Some(dev) = all_devices.get(&SRCPMessageDevice::GL) {
-              dev.try_borrow_mut().unwrap().send_refresh();
+              if dev.try_borrow_mut().unwrap().send_refresh() {
+                tel_gesendet = true;
+              }
             }
           } else {
+            tel_gesendet = true;
             //Alles was in Warteschlange ist, ist gültig, Device vorhanden und validiert
             //Erstes, ältestes Kommando ausführen
             let msg = queue.remove(0);
@@ -402,17 +997,27 @@ impl DDL {
         }
       }
       //Allen Devices die Möglichkeit geben Hintergrundaufgaben abzuarbeiten, wenn vorhanden SM Antwort zurück senden
-      let mut tel_gesendet = false;
       for (_, dev) in &all_devices {
         if dev.borrow_mut().execute(power_on) {
           tel_gesendet = true;
         }
       }
-      if !power_on && !tel_gesendet {
-        //Wenn Power On ist wird dauernd etwas gesendet. Die CPU "Pausen" kommen durch das SPI senden zu stande.
-        //Wenn Power Off ist, wird (ausser SM verlangt etwas) nichts gesendet. Damit machen wir in diesem Loop 100% CPU Last für nichts.
-        //Deshalb der CPU etwas Pausen gönnen, fallse bei Power Off wircklich nichts gesendet wurde.
-        thread::sleep(POWER_OFF_CPU_PAUSE);
+      if !tel_gesendet {
+        //Solange gesendet wird kommen die CPU "Pausen" durch das SPI senden zu stande.
+        //Wurde nichts gesendet (z.B. Power Off, keine GL's und keine Idle Protokolle), dann wird bis zum
+        //frühesten Zeitpunkt, an dem ein Device wieder etwas senden muss, gewartet, max. MAX_CPU_PAUSE.
+        //Ein neues Kommando beendet das Warten sofort.
+        let jetzt = Instant::now();
+        let mut naechster_zeitpunkt = all_devices
+          .values()
+          .filter_map(|dev| dev.borrow().get_naechster_zeitpunkt(power_on))
+          .fold(jetzt + MAX_CPU_PAUSE, Instant::min);
+        if power_on && self.watchdog {
+          naechster_zeitpunkt = naechster_zeitpunkt.min(instant_kommando + WATCHDOG_TIMEOUT);
+        }
+        if let Ok(msg) = rx.recv_timeout(naechster_zeitpunkt.saturating_duration_since(jetzt)) {
+          msg_empfangen = Some(msg);
+        }
       }
     }
   }
@@ -441,27 +1046,49 @@ impl SRCPServer for DDL {
     &mut self, busnr: usize, config_file_bus: &HashMap<String, Option<String>>,
   ) -> Result<(), String> {
     self.busnr = busnr;
-    self.spiport = config_file_bus
-      .get("spiport")
-      .ok_or("S88: spiport Parameter nicht vorhanden")?
-      .clone()
-      .ok_or("S88: spiport Parameter ohne Wert")?;
+    //Ohne Angabe Default SPI Device der erkannten Hardware
+    self.spiport = match config_file_bus.get("spiport") {
+      Some(spiport) => spiport.clone().ok_or("DDL: spiport Parameter ohne Wert")?,
+      None => srcp_hardware::get_spiport_ddl().to_string(),
+    };
+    if let Some(prog_spiport) = config_file_bus.get("prog_spiport") {
+      self.prog_spiport = Some(
+        prog_spiport
+          .as_ref()
+          .ok_or("DDL: prog_spiport ohne Wert")?
+          .clone(),
+      );
+    }
     self.maerklin_enabled = config_file_bus.get("maerklin").is_some();
     self.dcc_enabled = config_file_bus.get("dcc").is_some();
-    if let Some(uid) = config_file_bus.get("mfx") {
-      self.mfx_enabled_uid = uid
-        .as_ref()
-        .ok_or("DDL: MFX enable mit UID > 0 notwendig")?
-        .parse::<u32>()
-        .ok()
-        .ok_or("MFX UID muss eine Zahl > 0 sein")?;
-    }
+    self.dcc_railcom = config_file_bus.get("railcom").is_some();
+    self.selectrix_enabled = config_file_bus.get("selectrix").is_some();
     if let Some(mfx_reg_count_file) = config_file_bus.get("mfx_reg_count_file") {
       self.mfx_reg_count_file = mfx_reg_count_file
         .as_ref()
         .ok_or("DDL: zu mfx_reg_count_file muss ein Pfad angegegben werden.")?
         .clone();
     }
+    if let Some(mfx_uid_file) = config_file_bus.get("mfx_uid_file") {
+      self.mfx_uid_file = mfx_uid_file
+        .as_ref()
+        .ok_or("DDL: zu mfx_uid_file muss ein Pfad angegegben werden.")?
+        .clone();
+    }
+    if let Some(uid) = config_file_bus.get("mfx") {
+      let uid = uid
+        .as_ref()
+        .ok_or("DDL: MFX enable mit UID > 0 oder auto notwendig")?;
+      self.mfx_enabled_uid = if uid == "auto" {
+        self.get_mfx_uid_auto()
+      } else {
+        uid
+          .parse::<u32>()
+          .ok()
+          .ok_or("MFX UID muss eine Zahl > 0 oder auto sein")?
+      };
+      info!("DDL: MFX UID Zentrale={}", self.mfx_enabled_uid);
+    }
     if let Some(port) = config_file_bus.get("mfx_rds_port") {
       self.udp_mfxrds_port = Some(
         port
@@ -472,6 +1099,98 @@ impl SRCPServer for DDL {
           .ok_or("MFX RDS Port muss eine Zahl > 0 sein")?,
       );
     }
+    if let Some(mfx_fx_max) = config_file_bus.get("mfx_fx_max") {
+      self.mfx_fx_max = mfx_fx_max
+        .as_ref()
+        .ok_or("DDL: mfx_fx_max ohne Wert")?
+        .parse::<usize>()
+        .ok()
+        .filter(|mfx_fx_max| (1..=MFX_ANZ_F_MAX).contains(mfx_fx_max))
+        .ok_or(format!(
+          "DDL: mfx_fx_max muss eine Zahl von 1 bis {} sein",
+          MFX_ANZ_F_MAX
+        ))?;
+    }
+    if let Some(mfx_absent) = config_file_bus.get("mfx_absent") {
+      self.mfx_absent = Some(Duration::from_secs(
+        mfx_absent
+          .as_ref()
+          .ok_or("DDL: mfx_absent ohne Wert")?
+          .parse::<u64>()
+          .ok()
+          .filter(|mfx_absent| *mfx_absent > 0)
+          .ok_or("DDL: mfx_absent muss eine Zahl > 0 sein")?,
+      ));
+    }
+    self.mfx_absent_term = config_file_bus.get("mfx_absent_term").is_some();
+    if let Some(mfx_lok_cache_file) = config_file_bus.get("mfx_lok_cache_file") {
+      self.mfx_lok_cache_file = Some(
+        mfx_lok_cache_file
+          .as_ref()
+          .ok_or("DDL: mfx_lok_cache_file ohne Wert")?
+          .clone(),
+      );
+    }
+    if let Some(mfx_rds_capture_file) = config_file_bus.get("mfx_rds_capture_file") {
+      self.mfx_rds_konfig.capture_file = Some(
+        mfx_rds_capture_file
+          .as_ref()
+          .ok_or("DDL: mfx_rds_capture_file ohne Wert")?
+          .clone(),
+      );
+    }
+    if let Some(mfx_cv_write_bytes) = config_file_bus.get("mfx_cv_write_bytes") {
+      self.mfx_rds_konfig.cv_write_bytes = mfx_cv_write_bytes
+        .as_ref()
+        .ok_or("DDL: mfx_cv_write_bytes ohne Wert")?
+        .parse::<usize>()
+        .ok()
+        .filter(|mfx_cv_write_bytes| [1, 2, 4, 8].contains(mfx_cv_write_bytes))
+        .ok_or("DDL: mfx_cv_write_bytes muss 1, 2, 4 oder 8 sein")?;
+    }
+    if let Some(mfx_rds_timeout) = config_file_bus.get("mfx_rds_timeout") {
+      self.mfx_rds_konfig.timeout = Duration::from_millis(
+        mfx_rds_timeout
+          .as_ref()
+          .ok_or("DDL: mfx_rds_timeout ohne Wert")?
+          .parse::<u64>()
+          .ok()
+          .filter(|mfx_rds_timeout| *mfx_rds_timeout > 0)
+          .ok_or("DDL: mfx_rds_timeout muss eine Zahl > 0 sein")?,
+      );
+    }
+    if let Some(mfx_rds_retry) = config_file_bus.get("mfx_rds_retry") {
+      self.mfx_rds_konfig.versuche = mfx_rds_retry
+        .as_ref()
+        .ok_or("DDL: mfx_rds_retry ohne Wert")?
+        .parse::<usize>()
+        .ok()
+        .filter(|mfx_rds_retry| *mfx_rds_retry > 0)
+        .ok_or("DDL: mfx_rds_retry muss eine Zahl > 0 sein")?;
+    }
+    if let Some(mfx_rds_deadline) = config_file_bus.get("mfx_rds_deadline") {
+      self.mfx_rds_konfig.frist = Duration::from_secs(
+        mfx_rds_deadline
+          .as_ref()
+          .ok_or("DDL: mfx_rds_deadline ohne Wert")?
+          .parse::<u64>()
+          .ok()
+          .filter(|mfx_rds_deadline| *mfx_rds_deadline > 0)
+          .ok_or("DDL: mfx_rds_deadline muss eine Zahl > 0 sein")?,
+      );
+    }
+    if let Some(mfx_rds_gpio) = config_file_bus.get("mfx_rds_gpio") {
+      let gpio: Vec<u32> = mfx_rds_gpio
+        .as_ref()
+        .ok_or("DDL: mfx_rds_gpio ohne Wert")?
+        .split(',')
+        .map(|gpio| gpio.trim().parse::<u32>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| "DDL: mfx_rds_gpio ungültige GPIO Nummer")?;
+      self.mfx_rds_konfig.gpio = gpio
+        .try_into()
+        .map_err(|_| "DDL: mfx_rds_gpio muss 3 GPIO Nummern QUAL,CLK,DATA enthalten")?;
+    }
     self.siggmode = config_file_bus.get("siggmode").is_some();
     self.dsr_invers = config_file_bus.get("dsr_invers").is_some();
     self.shortcut_delay = config_file_bus
@@ -490,7 +1209,88 @@ impl SRCPServer for DDL {
         .ok()
         .ok_or("DDL: timeout_shortcut_power_off muss eine Zahl >= 0 sein")?;
     }
+    if let Some(shortcut_lockout) = config_file_bus.get("shortcut_lockout") {
+      self.shortcut_lockout = Some(Duration::from_millis(
+        shortcut_lockout
+          .as_ref()
+          .ok_or("DDL: shortcut_lockout ohne Wert")?
+          .parse::<u64>()
+          .ok()
+          .ok_or("DDL: shortcut_lockout muss eine Zahl >= 0 sein")?,
+      ));
+    }
     self.watchdog = config_file_bus.get("watchdog").is_some();
+    let power_start_delay = match config_file_bus.get("power_start_delay") {
+      Some(delay) => Duration::from_millis(
+        delay
+          .as_ref()
+          .ok_or("DDL: power_start_delay ohne Wert")?
+          .parse::<u64>()
+          .ok()
+          .ok_or("DDL: power_start_delay muss eine Zahl >= 0 (ms) sein")?,
+      ),
+      None => POWER_START_DELAY_DEFAULT,
+    };
+    if let Some(power_start) = config_file_bus.get("power_start") {
+      self.power_start = match power_start.as_deref() {
+        Some("off") => PowerStart::Aus,
+        Some("restore") => PowerStart::Letzter(power_start_delay),
+        Some("on") => PowerStart::Ein(power_start_delay),
+        _ => Err("DDL: power_start muss off, restore oder on sein")?,
+      };
+    }
+    if let Some(power_file) = config_file_bus.get("power_file") {
+      self.power_file = power_file
+        .as_ref()
+        .ok_or("DDL: zu power_file muss ein Pfad angegegben werden.")?
+        .clone();
+    }
+    if let Some(spi_max_fehler) = config_file_bus.get("spi_max_fehler") {
+      self.spi_max_fehler = spi_max_fehler
+        .as_ref()
+        .ok_or("DDL: spi_max_fehler ohne Wert")?
+        .parse::<u32>()
+        .ok()
+        .ok_or("DDL: spi_max_fehler muss eine Zahl >= 0 sein")?;
+    }
+    if let Some(mm_ga_impuls_0) = config_file_bus.get("mm_ga_impuls0") {
+      self.mm_ga_impuls_0 = mm_ga_impuls_0
+        .as_ref()
+        .ok_or("DDL: mm_ga_impuls0 ohne Wert")?
+        .parse::<u32>()
+        .ok()
+        .filter(|impuls_0| MM_GA_IMPULS_0_BEREICH.contains(impuls_0))
+        .ok_or("DDL: mm_ga_impuls0 muss eine Zahl von 1 bis 7 sein")?;
+    }
+    if let Some(mm_ga_wiederholungen) = config_file_bus.get("mm_ga_repeat") {
+      self.mm_ga_wiederholungen = mm_ga_wiederholungen
+        .as_ref()
+        .ok_or("DDL: mm_ga_repeat ohne Wert")?
+        .parse::<usize>()
+        .ok()
+        .filter(|wiederholungen| *wiederholungen > 0)
+        .ok_or("DDL: mm_ga_repeat muss eine Zahl > 0 sein")?;
+    }
+    if let Some(mm_dir_pause) = config_file_bus.get("mm_dir_pause") {
+      self.mm_richtungswechsel_pause = Duration::from_millis(
+        mm_dir_pause
+          .as_ref()
+          .ok_or("DDL: mm_dir_pause ohne Wert")?
+          .parse::<u64>()
+          .ok()
+          .filter(|pause| *pause <= MM_RICHTUNGSWECHSEL_PAUSE_MAX)
+          .ok_or("DDL: mm_dir_pause muss eine Zahl von 0 bis 500 (ms) sein")?,
+      );
+    }
+    if let Some(mm_dir_repeat) = config_file_bus.get("mm_dir_repeat") {
+      self.mm_richtungswechsel_impulse = mm_dir_repeat
+        .as_ref()
+        .ok_or("DDL: mm_dir_repeat ohne Wert")?
+        .parse::<usize>()
+        .ok()
+        .filter(|impulse| *impulse > 0)
+        .ok_or("DDL: mm_dir_repeat muss eine Zahl > 0 sein")?;
+    }
     if let Some(trigger_port) = config_file_bus.get("trigger_port") {
       self.trigger_port = trigger_port.clone();
     }
@@ -503,6 +1303,136 @@ impl SRCPServer for DDL {
     if let Some(trigger_sm) = config_file_bus.get("trigger_sm") {
       self.trigger_sm = trigger_sm.clone();
     }
+    if let Some(gl_momentary) = config_file_bus.get("gl_momentary") {
+      self.gl_momentary = Some(
+        gl_momentary
+          .as_ref()
+          .ok_or("DDL: gl_momentary ohne Wert")?
+          .clone(),
+      );
+    }
+    if let Some(ga_collision) = config_file_bus.get("ga_collision") {
+      self.ga_collision = match ga_collision.as_deref() {
+        Some("warn") => GAKollision::Warnen,
+        Some("reject") => GAKollision::Ablehnen,
+        _ => Err("DDL: ga_collision muss warn oder reject sein")?,
+      };
+    }
+    if let Some(gl_takeover) = config_file_bus.get("gl_takeover") {
+      self.gl_takeover = match gl_takeover.as_deref() {
+        Some("advisory") => GLUebernahme::Melden,
+        Some("enforced") => GLUebernahme::Sperren,
+        _ => Err("DDL: gl_takeover muss advisory oder enforced sein")?,
+      };
+    }
+    if let Some(gl_max) = config_file_bus.get("gl_max") {
+      self.gl_max = Some(
+        gl_max
+          .as_ref()
+          .ok_or("DDL: gl_max ohne Wert")?
+          .parse::<usize>()
+          .ok()
+          .filter(|gl_max| *gl_max > 0)
+          .ok_or("DDL: gl_max muss eine Zahl > 0 sein")?,
+      );
+    }
+    if let Some(prog_gleis_gpio) = config_file_bus.get("prog_gleis_gpio") {
+      self.prog_gleis_gpio = Some(
+        prog_gleis_gpio
+          .as_ref()
+          .ok_or("DDL: prog_gleis_gpio ohne GPIO")?
+          .parse::<u32>()
+          .ok()
+          .ok_or("DDL: prog_gleis_gpio GPIO muss eine Zahl sein")?,
+      );
+    }
+    self.prog_gleis_invers = config_file_bus.get("prog_gleis_invers").is_some();
+    self.sm_write_verify = config_file_bus.get("sm_write_verify").is_some();
+    if let Some(prog_ack) = config_file_bus.get("prog_ack") {
+      self.prog_ack =
+        ProgAckKonfig::from_config(prog_ack.as_ref().ok_or("DDL: prog_ack ohne Wert")?)?;
+      //Hardware prüfen, damit Fehler beim Start und nicht erst beim Starten der DCC Prog Threads erkannt werden
+      if self.dcc_enabled && (self.prog_ack != ProgAckKonfig::Gpio) {
+        self.prog_ack.open()?;
+      }
+    }
+    if let Some(core_freq) = config_file_bus.get("core_freq") {
+      self.core_freq = Some(
+        core_freq
+          .as_ref()
+          .ok_or("DDL: core_freq ohne Wert")?
+          .parse::<u64>()
+          .ok()
+          .filter(|core_freq| *core_freq > 0)
+          .ok_or("DDL: core_freq muss eine Zahl > 0 (Hz) sein")?,
+      );
+    }
+    if let Some(rt_priority) = config_file_bus.get("rt_priority") {
+      self.rt_priority = Some(
+        rt_priority
+          .as_ref()
+          .ok_or("DDL: rt_priority ohne Wert")?
+          .parse::<i32>()
+          .ok()
+          .filter(|prio| (1..=99).contains(prio))
+          .ok_or("DDL: rt_priority muss eine Zahl 1..99 sein")?,
+      );
+    }
+    if let Some(cpu_affinity) = config_file_bus.get("cpu_affinity") {
+      self.cpu_affinity = Some(
+        cpu_affinity
+          .as_ref()
+          .ok_or("DDL: cpu_affinity ohne Wert")?
+          .parse::<usize>()
+          .ok()
+          .filter(|cpu| *cpu < libc::CPU_SETSIZE as usize)
+          .ok_or("DDL: cpu_affinity muss eine CPU Nummer >= 0 sein")?,
+      );
+    }
+    //Grenzen für die Einschaltdauer der GA's
+    self.ga_puls = GAPulsKonfig::from_config(config_file_bus)?;
+    //Wiederherstellen GA Zustände nach Power On
+    self.ga_restore = GARestoreKonfig::from_config(config_file_bus)?;
+    //Geschwindigkeitskennlinien gl_speedcurve_<adr> = <Stützpunkt 0..255>, ...
+    for (name, wert) in config_file_bus {
+      if let Some(adr) = name.strip_prefix("gl_speedcurve_") {
+        let adr = adr
+          .parse::<u32>()
+          .ok()
+          .ok_or(format!("DDL: {}: ungültige GL Adresse", name))?;
+        let kurve = wert
+          .as_ref()
+          .ok_or(format!("DDL: {} ohne Wert", name))?
+          .split(',')
+          .map(|punkt| punkt.trim().parse::<u8>())
+          .collect::<Result<Vec<u8>, _>>()
+          .ok()
+          .ok_or(format!(
+            "DDL: {}: Stützpunkte müssen Zahlen 0..255 sein",
+            name
+          ))?;
+        if kurve.len() < 2 {
+          return Err(format!("DDL: {}: min. 2 Stützpunkte notwendig", name));
+        }
+        self.gl_speedcurve.insert(adr, kurve);
+      }
+      //Max. Fahrstufe gl_speedmax_<adr> = <Fahrstufe>
+      if let Some(adr) = name.strip_prefix("gl_speedmax_") {
+        let adr = adr
+          .parse::<u32>()
+          .ok()
+          .ok_or(format!("DDL: {}: ungültige GL Adresse", name))?;
+        let speed_max = wert
+          .as_ref()
+          .ok_or(format!("DDL: {} ohne Wert", name))?
+          .trim()
+          .parse::<usize>()
+          .ok()
+          .filter(|speed_max| *speed_max > 0)
+          .ok_or(format!("DDL: {}: Fahrstufe muss eine Zahl > 0 sein", name))?;
+        self.gl_speedmax.insert(adr, speed_max);
+      }
+    }
     Ok(())
   }
 
@@ -510,11 +1440,13 @@ impl SRCPServer for DDL {
   /// # Arguments
   /// * rx - Channel Receiver über denn Kommandos empfangen werden
   /// * tx - Channel Sender über den Info Messages zurück gesendet werden können
-  fn start(&self, rx: Receiver<Message>, tx: Sender<SRCPMessage>) {
+  fn start(&self, rx: Receiver<Message>, tx: SyncSender<SRCPMessage>) {
     let mut instanz = self.clone();
-    thread::Builder::new()
-      .name("DDL_Thread".to_string())
-      .spawn(move || instanz.execute(rx, tx))
-      .unwrap();
+    spawn_server_thread(
+      "DDL_Thread",
+      self.busnr..self.busnr + self.get_srcp_bus_count(),
+      tx,
+      move |tx| instanz.execute(rx, ThreadSender::new(tx, "SRCP Dispatcher")),
+    );
   }
 }
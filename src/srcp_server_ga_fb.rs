@@ -0,0 +1,448 @@
+//! Gemeinsame GA und FB Behandlung der Server die Ausgänge und Eingänge direkt ohne Schienendekoder
+//! bzw. S88 ansteuern (GPIO, I2C Port Expander).
+//! Die Server liefern nur den Hardwarezugriff über "GaFbHardware", Kommandoausführung, Impulszeiten,
+//! Entprellung und INFO Messages sind hier für alle gemeinsam.
+
+use std::{
+  collections::{BTreeMap, HashMap},
+  sync::mpsc::{Receiver, RecvTimeoutError},
+  time::{Duration, Instant},
+};
+
+use crate::srcp_server_types::{
+  Message, SRCPMessage, SRCPMessageDevice, SRCPMessageID, SRCPMessageType, ThreadSender,
+};
+use log::warn;
+
+/// Max. Wartezeit auf Kommandos, bestimmt die Genauigkeit der Impulszeiten und das Abtastintervall der Eingänge
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+/// Entprellzeit FB Eingänge wenn nicht konfiguriert
+pub const FB_DEBOUNCE_DEFAULT: Duration = Duration::from_millis(20);
+
+/// Konfiguration einer GA Adresse
+#[derive(Clone)]
+pub struct GaKonfig<P> {
+  /// Kanal pro Port (Port 0 und optional Port 1)
+  pub pins: Vec<P>,
+  /// Max. Einschaltzeit (z.B. für Entkuppler), None für Dauerbetrieb (z.B. Relais)
+  pub pulse: Option<Duration>,
+}
+
+/// Hardwarezugriff auf Ausgänge und Eingänge eines Servers
+pub trait GaFbHardware {
+  /// Geöffneter Kanal eines Ausgangs oder Eingangs
+  type Pin;
+
+  /// Name für Logmeldungen, z.B. "GPIO"
+  fn get_name(&self) -> &'static str;
+
+  /// Einen Ausgang schalten.
+  /// Liefert Err wenn der Ausgang nicht geschrieben werden konnte.
+  /// # Arguments
+  /// * pin - Zu schaltender Ausgang
+  /// * value - Neuer logischer Zustand 0 oder 1, aktiv low muss die Implementierung berücksichtigen
+  fn set_output(&mut self, pin: &Self::Pin, value: usize) -> Result<(), String>;
+
+  /// Alle Eingänge abfragen, wird vor jedem Abtasten der einzelnen Eingänge mit "get_input" aufgerufen.
+  /// Für Hardware die Eingänge nur gesammelt lesen kann.
+  fn poll_inputs(&mut self) {}
+
+  /// Einen Eingang lesen, liefert den logischen, noch nicht entprellten Zustand 0 oder 1
+  /// # Arguments
+  /// * pin - Zu lesender Eingang
+  fn get_input(&mut self, pin: &Self::Pin) -> Result<usize, String>;
+}
+
+/// Zustand eines Ausgangs zur Laufzeit
+struct GaOutput<P> {
+  /// Kanal des Ausgangs
+  pin: P,
+  /// Aktueller Zustand
+  value: usize,
+  /// Zeitpunkt zum automatischen Ausschalten
+  ausschalten: Option<Instant>,
+}
+
+/// Zustand eines FB Eingangs zur Laufzeit
+struct FbInput<P> {
+  /// Kanal des Eingangs
+  pin: P,
+  /// Entprellter Zustand
+  value: usize,
+  /// Zuletzt gelesener, noch nicht entprellter Zustand
+  roh_value: usize,
+  /// Zeitpunkt der letzten Änderung des gelesenen Zustandes
+  roh_seit: Instant,
+}
+
+/// GA's und FB's eines Servers zur Laufzeit
+pub struct GaFb<H: GaFbHardware> {
+  /// SRCP Busnr
+  busnr: usize,
+  /// Hardwarezugriff
+  hardware: H,
+  /// Max. Einschaltzeit aller GA, Key Adresse
+  all_pulse: BTreeMap<u32, Option<Duration>>,
+  /// Alle Ausgänge, Key (Adresse, Port)
+  outputs: HashMap<(u32, usize), GaOutput<H::Pin>>,
+  /// Alle Eingänge, Index 0 ist FB 1
+  inputs: Vec<FbInput<H::Pin>>,
+  /// Entprellzeit FB Eingänge
+  fb_debounce: Duration,
+}
+
+impl<H: GaFbHardware> GaFb<H> {
+  /// Neue Instanz erstellen, alle Ausgänge müssen bereits ausgeschaltet sein.
+  /// # Arguments
+  /// * busnr - SRCP Busnr
+  /// * hardware - Hardwarezugriff
+  /// * all_ga - Alle GA mit geöffneten Kanälen, Key Adresse
+  /// * all_fb - Alle geöffneten Eingänge, Index 0 ist FB 1
+  /// * fb_debounce - Entprellzeit FB Eingänge
+  pub fn new(
+    busnr: usize, hardware: H, all_ga: BTreeMap<u32, GaKonfig<H::Pin>>, all_fb: Vec<H::Pin>,
+    fb_debounce: Duration,
+  ) -> GaFb<H> {
+    let mut all_pulse = BTreeMap::new();
+    let mut outputs = HashMap::new();
+    for (adr, ga) in all_ga {
+      all_pulse.insert(adr, ga.pulse);
+      for (port, pin) in ga.pins.into_iter().enumerate() {
+        outputs.insert(
+          (adr, port),
+          GaOutput {
+            pin,
+            value: 0,
+            ausschalten: None,
+          },
+        );
+      }
+    }
+    let inputs = all_fb
+      .into_iter()
+      .map(|pin| FbInput {
+        pin,
+        value: 0,
+        roh_value: 0,
+        roh_seit: Instant::now(),
+      })
+      .collect();
+    GaFb {
+      busnr,
+      hardware,
+      all_pulse,
+      outputs,
+      inputs,
+      fb_debounce,
+    }
+  }
+
+  /// Einen Ausgang schalten, bei Fehler wird nur gewarnt.
+  /// Liefert false wenn der Ausgang nicht geschrieben werden konnte.
+  /// # Arguments
+  /// * adr - GA Adresse
+  /// * port - GA Port
+  /// * value - Neuer Zustand 0 oder 1
+  fn set_output(&mut self, adr: u32, port: usize, value: usize) -> bool {
+    let output = self.outputs.get_mut(&(adr, port)).unwrap();
+    match self.hardware.set_output(&output.pin, value) {
+      Ok(()) => {
+        output.value = value;
+        true
+      }
+      Err(msg) => {
+        warn!(
+          "{} GA Adr {} Port {}: {}",
+          self.hardware.get_name(),
+          adr,
+          port,
+          msg
+        );
+        false
+      }
+    }
+  }
+
+  /// INFO Message versenden
+  /// # Arguments
+  /// * tx - Sender für INFO Messages
+  /// * session_id - None: an alle SRCP Info Clients, sonst nur an den mit SessionID
+  /// * adr - GA Adresse
+  /// * port - GA Port
+  /// * value - GA Port Zustand
+  fn send_info_msg(
+    &self, tx: &ThreadSender<SRCPMessage>, session_id: Option<u32>, adr: u32, port: usize,
+    value: usize,
+  ) {
+    //INFO <bus> GA <adr> <port> <value>
+    tx.send(SRCPMessage::new(
+      session_id,
+      self.busnr,
+      SRCPMessageID::Info {
+        info_code: "100".to_string(),
+      },
+      SRCPMessageDevice::GA,
+      vec![adr.to_string(), port.to_string(), value.to_string()],
+    ));
+  }
+
+  /// FB INFO Message versenden
+  /// # Arguments
+  /// * tx - Sender für INFO Messages
+  /// * session_id - None: an alle SRCP Info Clients, sonst nur an den mit SessionID
+  /// * fb_nr - FB Nummer, beginnend bei 1
+  /// * value - FB Zustand
+  fn send_info_msg_fb(
+    &self, tx: &ThreadSender<SRCPMessage>, session_id: Option<u32>, fb_nr: usize, value: usize,
+  ) {
+    //INFO <bus> FB <nr> <value>
+    tx.send(SRCPMessage::new(
+      session_id,
+      self.busnr,
+      SRCPMessageID::Info {
+        info_code: "100".to_string(),
+      },
+      SRCPMessageDevice::FB,
+      vec![fb_nr.to_string(), value.to_string()],
+    ));
+  }
+
+  /// Kommando ausführen, Antwort an den Client senden.
+  /// # Arguments
+  /// * cmd_msg - Empfangenes Kommando
+  /// * tx - Sender für Antworten und INFO Messages
+  fn execute_cmd(&mut self, cmd_msg: &SRCPMessage, tx: &ThreadSender<SRCPMessage>) {
+    let SRCPMessageID::Command { msg_type } = cmd_msg.message_id else {
+      return;
+    };
+    match cmd_msg.device {
+      SRCPMessageDevice::GA => self.execute_ga_cmd(msg_type, cmd_msg, tx),
+      SRCPMessageDevice::FB => self.execute_fb_cmd(msg_type, cmd_msg, tx),
+      _ => {
+        tx.send(SRCPMessage::new_err(cmd_msg, "421", "unsupported device"));
+      }
+    }
+  }
+
+  /// FB Kommando ausführen, nur GET wird unterstützt.
+  /// # Arguments
+  /// * msg_type - Kommandotyp
+  /// * cmd_msg - Empfangenes Kommando
+  /// * tx - Sender für Antworten und INFO Messages
+  fn execute_fb_cmd(
+    &self, msg_type: SRCPMessageType, cmd_msg: &SRCPMessage, tx: &ThreadSender<SRCPMessage>,
+  ) {
+    if msg_type != SRCPMessageType::GET {
+      tx.send(SRCPMessage::new_err(
+        cmd_msg,
+        "423",
+        "unsupported operation",
+      ));
+      return;
+    }
+    //Format ist GET <bus> FB <nr>, SRCP Nummern beginnen bei 1
+    match cmd_msg
+      .get_adr()
+      .and_then(|fb_nr| self.inputs.get((fb_nr as usize).wrapping_sub(1)))
+    {
+      Some(input) => {
+        let fb_nr = cmd_msg.get_adr().unwrap() as usize;
+        self.send_info_msg_fb(tx, cmd_msg.session_id, fb_nr, input.value);
+      }
+      None => {
+        tx.send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"));
+      }
+    }
+  }
+
+  /// GA Kommando ausführen.
+  /// # Arguments
+  /// * msg_type - Kommandotyp
+  /// * cmd_msg - Empfangenes Kommando
+  /// * tx - Sender für Antworten und INFO Messages
+  fn execute_ga_cmd(
+    &mut self, msg_type: SRCPMessageType, cmd_msg: &SRCPMessage, tx: &ThreadSender<SRCPMessage>,
+  ) {
+    //Alle Kommandos beginnen mit der GA Adresse, diese muss konfiguriert sein
+    let Some(adr) = cmd_msg
+      .get_adr()
+      .filter(|adr| self.all_pulse.contains_key(adr))
+    else {
+      tx.send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"));
+      return;
+    };
+    match msg_type {
+      SRCPMessageType::INIT | SRCPMessageType::TERM => {
+        //Format ist INIT <bus> GA <addr> <protocol> bzw. TERM <bus> GA <addr>
+        //GA's sind durch Konfiguration immer vorhanden, bei TERM werden alle Ports ausgeschaltet
+        if msg_type == SRCPMessageType::TERM {
+          let mut port = 0;
+          while let Some(output) = self.outputs.get_mut(&(adr, port)) {
+            if output.value != 0 {
+              output.ausschalten = None;
+              self.set_output(adr, port, 0);
+              self.send_info_msg(tx, None, adr, port, 0);
+            }
+            port += 1;
+          }
+        }
+        tx.send(SRCPMessage::new_ok(cmd_msg, "200"));
+      }
+      SRCPMessageType::GET | SRCPMessageType::SET => {
+        //Format ist GET <bus> GA <addr> <port>
+        //Format ist SET <bus> GA <addr> <port> <value> <time>
+        let Some(port) = cmd_msg
+          .parameter
+          .get(1)
+          .and_then(|port| port.parse::<usize>().ok())
+          .filter(|port| self.outputs.contains_key(&(adr, *port)))
+        else {
+          tx.send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"));
+          return;
+        };
+        if msg_type == SRCPMessageType::GET {
+          self.send_info_msg(
+            tx,
+            cmd_msg.session_id,
+            adr,
+            port,
+            self.outputs[&(adr, port)].value,
+          );
+          return;
+        }
+        let value = cmd_msg
+          .parameter
+          .get(2)
+          .and_then(|value| value.parse::<usize>().ok());
+        let zeit = cmd_msg
+          .parameter
+          .get(3)
+          .and_then(|zeit| zeit.parse::<i32>().ok());
+        let (Some(value @ 0..=1), Some(zeit)) = (value, zeit) else {
+          tx.send(SRCPMessage::new_err(cmd_msg, "412", "wrong value"));
+          return;
+        };
+        if !self.set_output(adr, port, value) {
+          tx.send(SRCPMessage::new_err(cmd_msg, "417", "timeout"));
+          return;
+        }
+        //Automatisch Ausschalten nach Zeitangabe, bei Impulsausgängen höchstens nach Impulszeit
+        let mut ausschalten = None;
+        if value != 0 {
+          let zeit = (zeit > 0).then(|| Duration::from_millis(zeit as u64));
+          ausschalten = match (zeit, self.all_pulse[&adr]) {
+            (Some(zeit), Some(pulse)) => Some(zeit.min(pulse)),
+            (zeit, pulse) => zeit.or(pulse),
+          }
+          .map(|dauer| Instant::now() + dauer);
+        }
+        self.outputs.get_mut(&(adr, port)).unwrap().ausschalten = ausschalten;
+        tx.send(SRCPMessage::new_ok(cmd_msg, "200"));
+        self.send_info_msg(tx, None, adr, port, value);
+      }
+      SRCPMessageType::VERIFY => {
+        tx.send(SRCPMessage::new_err(
+          cmd_msg,
+          "423",
+          "unsupported operation",
+        ));
+      }
+    }
+  }
+
+  /// Ausgänge mit abgelaufener Einschaltzeit ausschalten
+  /// # Arguments
+  /// * tx - Sender für INFO Messages
+  fn ausschalten(&mut self, tx: &ThreadSender<SRCPMessage>) {
+    let jetzt = Instant::now();
+    let abgelaufen: Vec<(u32, usize)> = self
+      .outputs
+      .iter()
+      .filter(|(_, output)| {
+        output
+          .ausschalten
+          .is_some_and(|zeitpunkt| jetzt >= zeitpunkt)
+      })
+      .map(|(key, _)| *key)
+      .collect();
+    for (adr, port) in abgelaufen {
+      let output = self.outputs.get_mut(&(adr, port)).unwrap();
+      output.ausschalten = None;
+      self.set_output(adr, port, 0);
+      self.send_info_msg(tx, None, adr, port, 0);
+    }
+  }
+
+  /// Eingänge abtasten, Veränderung erst melden wenn für Entprellzeit stabil
+  /// # Arguments
+  /// * tx - Sender für INFO Messages
+  fn abtasten(&mut self, tx: &ThreadSender<SRCPMessage>) {
+    self.hardware.poll_inputs();
+    for fb_index in 0..self.inputs.len() {
+      let input = &mut self.inputs[fb_index];
+      let roh_value = match self.hardware.get_input(&input.pin) {
+        Ok(roh_value) => roh_value,
+        Err(msg) => {
+          warn!("{} FB {}: {}", self.hardware.get_name(), fb_index + 1, msg);
+          continue;
+        }
+      };
+      if roh_value != input.roh_value {
+        input.roh_value = roh_value;
+        input.roh_seit = Instant::now();
+      } else if (roh_value != input.value) && (input.roh_seit.elapsed() >= self.fb_debounce) {
+        input.value = roh_value;
+        self.send_info_msg_fb(tx, None, fb_index + 1, roh_value);
+      }
+    }
+  }
+
+  /// Ausführung im Server Thread bis der Kommandochannel geschlossen wird.
+  /// Die Hardware muss geöffnet und initialisiert sein, "ready" wurde noch nicht gemeldet.
+  /// # Arguments
+  /// * rx - Channel Receiver über denn Kommandos empfangen werden
+  /// * tx - Channel Sender über den Info Messages zurück gesendet werden können
+  pub fn execute(&mut self, rx: Receiver<Message>, tx: ThreadSender<SRCPMessage>) {
+    //Hardware offen, bereit für Kommandos
+    tx.send(SRCPMessage::new_ready(self.busnr));
+    loop {
+      match rx.recv_timeout(POLL_INTERVAL) {
+        Ok(Message::NewInfoClient { session_id }) => {
+          //Neuer Info Client, alle Zustände senden
+          for ((adr, port), output) in &self.outputs {
+            self.send_info_msg(&tx, Some(session_id), *adr, *port, output.value);
+          }
+          //Bei FB's wie bei S88 nur die belegten
+          for (fb_index, input) in self.inputs.iter().enumerate() {
+            if input.value != 0 {
+              self.send_info_msg_fb(&tx, Some(session_id), fb_index + 1, input.value);
+            }
+          }
+        }
+        Ok(Message::SRCPMessage { srcp_message }) => {
+          self.execute_cmd(&srcp_message, &tx);
+        }
+        Ok(Message::SessionEnd { .. }) => {} //Keine Session abhängigen Zustände
+        Ok(Message::Beenden) => {}           //Keine Hilfsthreads
+        Ok(Message::Pruefen { srcp_message }) => {
+          //Keine Validierung ohne Ausführung
+          tx.send(SRCPMessage::new_err(
+            &srcp_message,
+            "423",
+            "unsupported operation",
+          ));
+        }
+        Err(RecvTimeoutError::Timeout) => {}
+        Err(RecvTimeoutError::Disconnected) => {
+          warn!(
+            "{}: Kommandochannel geschlossen, wird beendet",
+            self.hardware.get_name()
+          );
+          break;
+        }
+      }
+      self.ausschalten(&tx);
+      self.abtasten(&tx);
+    }
+  }
+}
@@ -0,0 +1,269 @@
+use std::{
+  collections::{BTreeMap, HashMap},
+  sync::mpsc::{Receiver, SyncSender},
+  time::Duration,
+};
+
+use crate::{
+  srcp_hardware,
+  srcp_server_ga_fb::{GaFb, GaFbHardware, GaKonfig, FB_DEBOUNCE_DEFAULT},
+  srcp_server_types::{spawn_server_thread, Message, SRCPMessage, SRCPServer, ThreadSender},
+};
+use gpio_cdev::{Chip, LineHandle, LineRequestFlags};
+use log::info;
+
+/// Hardwarezugriff über geöffnete GPIO's
+struct GpioHardware {
+  /// Ausgänge sind aktiv low
+  active_low: bool,
+}
+impl GaFbHardware for GpioHardware {
+  type Pin = LineHandle;
+
+  /// Name für Logmeldungen
+  fn get_name(&self) -> &'static str {
+    "GPIO"
+  }
+
+  /// Einen Ausgang schalten.
+  /// Liefert Err wenn der GPIO nicht geschrieben werden konnte.
+  /// # Arguments
+  /// * pin - Zu schaltender Ausgang
+  /// * value - Neuer Zustand 0 oder 1
+  fn set_output(&mut self, pin: &LineHandle, value: usize) -> Result<(), String> {
+    let pegel = (value != 0) ^ self.active_low;
+    pin
+      .set_value(pegel as u8)
+      .map_err(|err| format!("GPIO {}", err))
+  }
+
+  /// Einen Eingang lesen, Eingänge mit Pull-Up sind mit ACTIVE_LOW geöffnet
+  /// # Arguments
+  /// * pin - Zu lesender Eingang
+  fn get_input(&mut self, pin: &LineHandle) -> Result<usize, String> {
+    pin
+      .get_value()
+      .map(|value| value as usize)
+      .map_err(|err| err.to_string())
+  }
+}
+
+/// GA's direkt über GPIO Ausgänge des Raspberry PI (Relais, Entkuppler) ohne Schienendekoder,
+/// FB's direkt über GPIO Eingänge (Taster, Reedkontakte, Weichenrückmeldung) ohne S88
+#[derive(Clone)]
+pub struct GpioServer {
+  //SRCP Busnr
+  busnr: usize,
+  //Alle konfigurierten GA, Key Adresse
+  all_ga: BTreeMap<u32, GaKonfig<u32>>,
+  //Ausgänge sind aktiv low
+  active_low: bool,
+  //GPIO Nummern aller FB's, Index 0 ist FB 1
+  all_fb: Vec<u32>,
+  //Eingänge mit Pull-Up, Kontakt schaltet gegen GND -> aktiv low
+  fb_pullup: bool,
+  //Entprellzeit FB Eingänge
+  fb_debounce: Duration,
+}
+
+impl GpioServer {
+  ///Neue Instanz erstellen
+  pub fn new() -> GpioServer {
+    GpioServer {
+      busnr: 0,
+      all_ga: BTreeMap::new(),
+      active_low: false,
+      all_fb: Vec::new(),
+      fb_pullup: false,
+      fb_debounce: FB_DEBOUNCE_DEFAULT,
+    }
+  }
+
+  /// Ausführung als Thread
+  /// # Arguments
+  /// * rx - Channel Receiver über denn Kommandos empfangen werden
+  /// * tx - Channel Sender über den Info Messages zurück gesendet werden können
+  fn execute(&self, rx: Receiver<Message>, tx: ThreadSender<SRCPMessage>) -> Result<(), String> {
+    //Alle GPIO's öffnen, ausgeschaltet
+    let gpiochip = srcp_hardware::get_gpiochip();
+    let mut chip = match Chip::new(&gpiochip) {
+      Ok(chip) => chip,
+      Err(msg) => {
+        return Err(format!(
+          "GPIO: {} konnte nicht geöffnet werden. {}",
+          gpiochip, msg
+        ));
+      }
+    };
+    let mut all_ga: BTreeMap<u32, GaKonfig<LineHandle>> = BTreeMap::new();
+    for (adr, ga) in &self.all_ga {
+      let mut pins = Vec::new();
+      for gpio in &ga.pins {
+        match chip.get_line(*gpio).and_then(|line| {
+          line.request(
+            LineRequestFlags::OUTPUT,
+            self.active_low as u8,
+            "output_ga_gpio",
+          )
+        }) {
+          Ok(line) => pins.push(line),
+          Err(msg) => {
+            return Err(format!(
+              "GPIO GA: GPIO {} konnte nicht als Output geöffnet werden. {}",
+              gpio, msg
+            ));
+          }
+        }
+      }
+      all_ga.insert(
+        *adr,
+        GaKonfig {
+          pins,
+          pulse: ga.pulse,
+        },
+      );
+    }
+    let mut all_fb: Vec<LineHandle> = Vec::new();
+    let input_flags = if self.fb_pullup {
+      LineRequestFlags::INPUT | LineRequestFlags::ACTIVE_LOW
+    } else {
+      LineRequestFlags::INPUT
+    };
+    for gpio in &self.all_fb {
+      match chip
+        .get_line(*gpio)
+        .and_then(|line| line.request(input_flags.clone(), 0, "input_fb_gpio"))
+      {
+        Ok(line) => all_fb.push(line),
+        Err(msg) => {
+          return Err(format!(
+            "GPIO FB: GPIO {} konnte nicht als Input geöffnet werden. {}",
+            gpio, msg
+          ));
+        }
+      }
+    }
+    //Alle GPIO's offen, bereit für Kommandos
+    let hardware = GpioHardware {
+      active_low: self.active_low,
+    };
+    GaFb::new(self.busnr, hardware, all_ga, all_fb, self.fb_debounce).execute(rx, tx);
+    Ok(())
+  }
+}
+
+impl SRCPServer for GpioServer {
+  /// Liefert den Name des SRCP Servers zurück
+  /// Im Konfigfile muss für jeden verwendeten SRCP Server minimal ein Abschnitt mit diesem Name und dem zu verwenden Bus enthalten sein:
+  /// [SRCPServerName]
+  /// bus = x
+  fn get_name(&self) -> &'static str {
+    "gpio"
+  }
+
+  /// Liefert die Busnummer des SRCP Servers zurück, 0=nicht benutzt, konfiguriert
+  fn get_busnr(&self) -> usize {
+    self.busnr
+  }
+
+  /// Init dieses Servers
+  /// Liefert Err zurück wenn ein Fehler aufgetreten ist (z.B. fehlender Konfig Parameter)
+  ///
+  /// GPIO Server hat folgende Konfigparameter, minimal eine GA oder ein FB muss vorhanden sein:
+  /// ga_1 .. ga_n fortlaufend für alle GA Adressen: GPIO Nummer für Port 0 und optional Port 1, "17" oder "17,27"
+  /// fb_1 .. fb_n fortlaufend für alle FB's: GPIO Nummer des Eingangs
+  /// Optional:
+  /// pulse_n Max. Einschaltzeit in ms für GA Adresse n (z.B. Entkuppler), ohne Angabe Dauerbetrieb
+  /// active_low Ausgänge sind aktiv low (z.B. Relaismodule)
+  /// fb_pullup Eingänge mit Pull-Up, Kontakt schaltet gegen GND (belegt = low). Der Pull-Up selbst muss
+  /// im Device Tree aktiviert werden (/boot/config.txt "gpio=<n>=ip,pu"), gpiochip Zugriff kann das nicht.
+  /// fb_debounce Entprellzeit Eingänge in ms, Default 20ms
+  /// # Arguments
+  /// * busnr - Die SRCP Busnummers die diesem Server zugeordner ist.
+  /// * config_file_bus - Der diesen Bus betreffende Teil des Konfigfiles
+  fn init(
+    &mut self, busnr: usize, config_file_bus: &HashMap<String, Option<String>>,
+  ) -> Result<(), String> {
+    self.busnr = busnr;
+    self.active_low = config_file_bus.contains_key("active_low");
+    self.all_ga.clear();
+    let mut adr: u32 = 1;
+    while let Some(gpios) = config_file_bus.get(&format!("ga_{}", adr)) {
+      let gpios = gpios
+        .as_ref()
+        .ok_or(format!("GPIO: ga_{} ohne Wert", adr))?
+        .split(',')
+        .map(|gpio| gpio.trim().parse::<u32>().ok())
+        .collect::<Option<Vec<u32>>>()
+        .ok_or(format!(
+          "GPIO: ga_{} muss 1 oder 2 GPIO Nummern enthalten",
+          adr
+        ))?;
+      if gpios.is_empty() || (gpios.len() > 2) {
+        Err(format!(
+          "GPIO: ga_{} muss 1 oder 2 GPIO Nummern enthalten",
+          adr
+        ))?;
+      }
+      let pulse = match config_file_bus.get(&format!("pulse_{}", adr)) {
+        Some(pulse) => Some(Duration::from_millis(
+          pulse
+            .as_ref()
+            .ok_or(format!("GPIO: pulse_{} ohne Wert", adr))?
+            .parse::<u64>()
+            .ok()
+            .ok_or(format!("GPIO: pulse_{} muss eine Zahl sein", adr))?,
+        )),
+        None => None,
+      };
+      self.all_ga.insert(adr, GaKonfig { pins: gpios, pulse });
+      adr += 1;
+    }
+    self.all_fb.clear();
+    while let Some(gpio) = config_file_bus.get(&format!("fb_{}", self.all_fb.len() + 1)) {
+      let fb_nr = self.all_fb.len() + 1;
+      self.all_fb.push(
+        gpio
+          .as_ref()
+          .and_then(|gpio| gpio.trim().parse::<u32>().ok())
+          .ok_or(format!(
+            "GPIO: fb_{} muss eine GPIO Nummer enthalten",
+            fb_nr
+          ))?,
+      );
+    }
+    self.fb_pullup = config_file_bus.contains_key("fb_pullup");
+    self.fb_debounce = match config_file_bus.get("fb_debounce") {
+      Some(debounce) => Duration::from_millis(
+        debounce
+          .as_ref()
+          .and_then(|debounce| debounce.parse::<u64>().ok())
+          .ok_or("GPIO: fb_debounce muss eine Zahl sein")?,
+      ),
+      None => FB_DEBOUNCE_DEFAULT,
+    };
+    if self.all_ga.is_empty() && self.all_fb.is_empty() {
+      Err("GPIO: keine GA (ga_1 ..) und keine FB (fb_1 ..) konfiguriert")?;
+    }
+    info!(
+      "GPIO: {} GA und {} FB konfiguriert",
+      self.all_ga.len(),
+      self.all_fb.len()
+    );
+    Ok(())
+  }
+
+  /// Start dieses Servers
+  /// # Arguments
+  /// * rx - Channel Receiver über denn Kommandos empfangen werden
+  /// * tx - Channel Sender über den Info Messages zurück gesendet werden können
+  fn start(&self, rx: Receiver<Message>, tx: SyncSender<SRCPMessage>) {
+    let instanz = self.clone();
+    spawn_server_thread(
+      "GPIO_GA_Thread",
+      self.busnr..self.busnr + self.get_srcp_bus_count(),
+      tx,
+      move |tx| instanz.execute(rx, ThreadSender::new(tx, "SRCP Dispatcher")),
+    );
+  }
+}
@@ -0,0 +1,654 @@
+use std::{
+  collections::{BTreeMap, HashMap, HashSet},
+  fs::{File, OpenOptions},
+  io::{Read, Write},
+  os::fd::AsRawFd,
+  sync::mpsc::{Receiver, SyncSender},
+  time::{Duration, Instant},
+};
+
+use crate::{
+  srcp_hardware,
+  srcp_server_ga_fb::{GaFb, GaFbHardware, GaKonfig, FB_DEBOUNCE_DEFAULT},
+  srcp_server_types::{spawn_server_thread, Message, SRCPMessage, SRCPServer, ThreadSender},
+};
+use gpio_cdev::{Chip, LineHandle, LineRequestFlags};
+use log::{info, warn};
+use nix::libc;
+
+/// Abfrageintervall der Eingänge von Expandern ohne Interrupt Pin wenn nicht konfiguriert
+const FB_REFRESH_DEFAULT: Duration = Duration::from_millis(20);
+/// I2C Device wenn nicht konfiguriert
+const I2C_PORT_DEFAULT: &str = "/dev/i2c-1";
+/// ioctl zum Setzen der Slave Adresse, siehe linux/i2c-dev.h
+const I2C_SLAVE: libc::c_ulong = 0x0703;
+
+/// MCP23017 Register bei IOCON.BANK = 0, jeweils Port A, Port B folgt an der nächsten Adresse
+const MCP23017_IODIR: u8 = 0x00;
+const MCP23017_GPINTEN: u8 = 0x04;
+const MCP23017_IOCON: u8 = 0x0A;
+const MCP23017_GPPU: u8 = 0x0C;
+const MCP23017_GPIO: u8 = 0x12;
+const MCP23017_OLAT: u8 = 0x14;
+/// IOCON: INTA und INTB verbunden, Open Drain damit mehrere Expander einen Interrupt Pin teilen können
+const MCP23017_IOCON_MIRROR_ODR: u8 = 0x44;
+
+/// Unterstützte Port Expander
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ExpanderTyp {
+  /// 16 Kanäle, Richtung pro Kanal, interne Pull-Ups
+  MCP23017,
+  /// 8 quasi-bidirektionale Kanäle, Eingänge müssen auf 1 geschrieben werden
+  PCF8574,
+}
+impl ExpanderTyp {
+  /// Anzahl Kanäle des Expanders
+  fn anzahl_pins(&self) -> u8 {
+    match self {
+      ExpanderTyp::MCP23017 => 16,
+      ExpanderTyp::PCF8574 => 8,
+    }
+  }
+}
+
+/// Konfiguration eines Port Expanders
+#[derive(Clone)]
+struct I2cExpander {
+  /// Typ des Expanders
+  typ: ExpanderTyp,
+  /// I2C Adresse (7 Bit)
+  adresse: u16,
+  /// GPIO Nummer an dem der Interrupt Ausgang angeschlossen ist, None: Eingänge werden gepollt
+  int_gpio: Option<u32>,
+}
+
+/// Ein Kanal auf einem Port Expander
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct I2cPin {
+  /// Index des Expanders, 0 ist expander_1
+  expander: usize,
+  /// Kanal auf dem Expander, 0..15 bzw. 0..7
+  pin: u8,
+}
+
+/// Zustand eines Expanders zur Laufzeit
+struct ExpanderZustand {
+  /// Zuletzt geschriebene Ausgänge (Pegel, nicht logischer Zustand)
+  olat: u16,
+  /// Zuletzt gelesene Eingänge (Pegel)
+  eingaenge: u16,
+  /// Expander hat FB Eingänge
+  hat_fb: bool,
+  /// Geöffneter Interrupt GPIO
+  int_line: Option<LineHandle>,
+  /// Zeitpunkt der letzten Abfrage der Eingänge
+  gelesen: Instant,
+}
+
+/// Zugriff auf einen I2C Bus über /dev/i2c-x
+pub struct I2cBus {
+  file: File,
+}
+impl I2cBus {
+  /// I2C Device öffnen
+  /// # Arguments
+  /// * port - I2C Device, z.B. /dev/i2c-1
+  pub fn open(port: &str) -> Result<I2cBus, String> {
+    let file = OpenOptions::new()
+      .read(true)
+      .write(true)
+      .open(port)
+      .map_err(|err| format!("{} {}", port, err))?;
+    Ok(I2cBus { file })
+  }
+
+  /// Slave Adresse für folgende Zugriffe setzen
+  /// # Arguments
+  /// * adresse - I2C Adresse (7 Bit)
+  fn set_slave(&mut self, adresse: u16) -> Result<(), String> {
+    let result = unsafe {
+      libc::ioctl(
+        self.file.as_raw_fd(),
+        I2C_SLAVE as _,
+        adresse as libc::c_ulong,
+      )
+    };
+    if result < 0 {
+      return Err(format!(
+        "I2C Adresse {:#04x}: {}",
+        adresse,
+        std::io::Error::last_os_error()
+      ));
+    }
+    Ok(())
+  }
+
+  /// Bytes an einen Slave schreiben
+  /// # Arguments
+  /// * adresse - I2C Adresse (7 Bit)
+  /// * daten - Zu schreibende Bytes
+  pub fn write(&mut self, adresse: u16, daten: &[u8]) -> Result<(), String> {
+    self.set_slave(adresse)?;
+    self
+      .file
+      .write_all(daten)
+      .map_err(|err| format!("I2C Adresse {:#04x}: {}", adresse, err))
+  }
+
+  /// Bytes von einem Slave lesen
+  /// # Arguments
+  /// * adresse - I2C Adresse (7 Bit)
+  /// * daten - Buffer für die gelesenen Bytes
+  pub fn read(&mut self, adresse: u16, daten: &mut [u8]) -> Result<(), String> {
+    self.set_slave(adresse)?;
+    self
+      .file
+      .read_exact(daten)
+      .map_err(|err| format!("I2C Adresse {:#04x}: {}", adresse, err))
+  }
+}
+
+/// Hardwarezugriff über den geöffneten I2C Bus
+struct I2cHardware<'a> {
+  /// Konfiguration
+  server: &'a I2cServer,
+  /// Geöffneter I2C Bus
+  i2c: I2cBus,
+  /// Zustände aller Expander, Index 0 ist expander_1
+  zustaende: Vec<ExpanderZustand>,
+}
+impl GaFbHardware for I2cHardware<'_> {
+  type Pin = I2cPin;
+
+  /// Name für Logmeldungen
+  fn get_name(&self) -> &'static str {
+    "I2C"
+  }
+
+  /// Einen Ausgang schalten.
+  /// Liefert Err wenn der Expander nicht geschrieben werden konnte.
+  /// # Arguments
+  /// * pin - Zu schaltender Ausgang
+  /// * value - Neuer Zustand 0 oder 1
+  fn set_output(&mut self, pin: &I2cPin, value: usize) -> Result<(), String> {
+    let zustand = &mut self.zustaende[pin.expander];
+    let bit = 1 << pin.pin;
+    let olat = if (value != 0) ^ self.server.active_low {
+      zustand.olat | bit
+    } else {
+      zustand.olat & !bit
+    };
+    self
+      .server
+      .write_outputs(&mut self.i2c, &self.server.all_expander[pin.expander], olat)?;
+    zustand.olat = olat;
+    Ok(())
+  }
+
+  /// Eingänge aller Expander abfragen
+  fn poll_inputs(&mut self) {
+    self
+      .server
+      .poll_expander(&mut self.i2c, &mut self.zustaende);
+  }
+
+  /// Einen Eingang aus den zuletzt abgefragten Eingängen des Expanders lesen
+  /// # Arguments
+  /// * pin - Zu lesender Eingang
+  fn get_input(&mut self, pin: &I2cPin) -> Result<usize, String> {
+    let pegel = (self.zustaende[pin.expander].eingaenge >> pin.pin) & 1 != 0;
+    Ok((pegel ^ self.server.fb_pullup) as usize)
+  }
+}
+
+/// GA's über I2C Port Expander (MCP23017, PCF8574) Ausgänge (Relais, Entkuppler) ohne Schienendekoder,
+/// FB's über I2C Port Expander Eingänge ohne S88
+#[derive(Clone)]
+pub struct I2cServer {
+  //SRCP Busnr
+  busnr: usize,
+  //I2C Device
+  i2c_port: String,
+  //Alle konfigurierten Expander, Index 0 ist expander_1
+  all_expander: Vec<I2cExpander>,
+  //Alle konfigurierten GA, Key Adresse
+  all_ga: BTreeMap<u32, GaKonfig<I2cPin>>,
+  //Ausgänge sind aktiv low
+  active_low: bool,
+  //Kanäle aller FB's, Index 0 ist FB 1
+  all_fb: Vec<I2cPin>,
+  //Eingänge mit Pull-Up, Kontakt schaltet gegen GND -> aktiv low
+  fb_pullup: bool,
+  //Entprellzeit FB Eingänge
+  fb_debounce: Duration,
+  //Abfrageintervall Eingänge von Expandern ohne Interrupt Pin
+  fb_refresh: Duration,
+}
+
+impl I2cServer {
+  ///Neue Instanz erstellen
+  pub fn new() -> I2cServer {
+    I2cServer {
+      busnr: 0,
+      i2c_port: I2C_PORT_DEFAULT.to_string(),
+      all_expander: Vec::new(),
+      all_ga: BTreeMap::new(),
+      active_low: false,
+      all_fb: Vec::new(),
+      fb_pullup: false,
+      fb_debounce: FB_DEBOUNCE_DEFAULT,
+      fb_refresh: FB_REFRESH_DEFAULT,
+    }
+  }
+
+  /// Parsen eines Kanals im Format "<expander>:<pin>", z.B. "1:7"
+  /// Liefert None wenn das Format ungültig ist oder Expander bzw. Kanal nicht existieren.
+  /// # Arguments
+  /// * text - Zu parsender Text
+  fn parse_pin(&self, text: &str) -> Option<I2cPin> {
+    let (expander, pin) = text.trim().split_once(':')?;
+    let expander = expander.trim().parse::<usize>().ok()?.checked_sub(1)?;
+    let pin = pin.trim().parse::<u8>().ok()?;
+    (pin < self.all_expander.get(expander)?.typ.anzahl_pins()).then_some(I2cPin { expander, pin })
+  }
+
+  /// Initialisierung aller Expander: Richtung, Pull-Ups, Interrupts, alle Ausgänge aus.
+  /// Liefert Err wenn ein Expander nicht angesprochen werden konnte.
+  /// # Arguments
+  /// * i2c - Geöffneter I2C Bus
+  /// * zustaende - Zustände aller Expander, Index 0 ist expander_1
+  fn init_expander(
+    &self, i2c: &mut I2cBus, zustaende: &mut [ExpanderZustand],
+  ) -> Result<(), String> {
+    for (index, (expander, zustand)) in self.all_expander.iter().zip(zustaende).enumerate() {
+      //Alle Kanäle die nicht Ausgang sind werden als Eingang betrieben
+      let mut output_mask: u16 = 0;
+      for ga in self.all_ga.values() {
+        for pin in ga.pins.iter().filter(|pin| pin.expander == index) {
+          output_mask |= 1 << pin.pin;
+        }
+      }
+      let input_mask = !output_mask;
+      //Ausgänge ausgeschaltet
+      zustand.olat = if self.active_low { output_mask } else { 0 };
+      match expander.typ {
+        ExpanderTyp::MCP23017 => {
+          let fb_mask = if zustand.hat_fb { input_mask } else { 0 };
+          let pullup = if self.fb_pullup { fb_mask } else { 0 };
+          let int_enable = if expander.int_gpio.is_some() {
+            fb_mask
+          } else {
+            0
+          };
+          i2c.write(
+            expander.adresse,
+            &[MCP23017_IOCON, MCP23017_IOCON_MIRROR_ODR],
+          )?;
+          //Ausgänge vor der Richtung setzen, damit beim Umschalten nichts kurz einschaltet
+          self.write_outputs(i2c, expander, zustand.olat)?;
+          i2c.write(
+            expander.adresse,
+            &[MCP23017_GPPU, pullup as u8, (pullup >> 8) as u8],
+          )?;
+          i2c.write(
+            expander.adresse,
+            &[MCP23017_IODIR, input_mask as u8, (input_mask >> 8) as u8],
+          )?;
+          i2c.write(
+            expander.adresse,
+            &[MCP23017_GPINTEN, int_enable as u8, (int_enable >> 8) as u8],
+          )?;
+        }
+        ExpanderTyp::PCF8574 => {
+          //Eingänge müssen auf 1 stehen
+          zustand.olat |= input_mask;
+          self.write_outputs(i2c, expander, zustand.olat)?;
+        }
+      }
+      //Erste Abfrage, setzt auch einen anstehenden Interrupt zurück
+      if zustand.hat_fb {
+        zustand.eingaenge = self.read_inputs(i2c, expander)?;
+        zustand.gelesen = Instant::now();
+      }
+    }
+    Ok(())
+  }
+
+  /// Ausgänge eines Expanders schreiben
+  /// # Arguments
+  /// * i2c - Geöffneter I2C Bus
+  /// * expander - Zu schreibender Expander
+  /// * olat - Pegel aller Ausgänge
+  fn write_outputs(
+    &self, i2c: &mut I2cBus, expander: &I2cExpander, olat: u16,
+  ) -> Result<(), String> {
+    match expander.typ {
+      ExpanderTyp::MCP23017 => i2c.write(
+        expander.adresse,
+        &[MCP23017_OLAT, olat as u8, (olat >> 8) as u8],
+      ),
+      ExpanderTyp::PCF8574 => i2c.write(expander.adresse, &[olat as u8]),
+    }
+  }
+
+  /// Eingänge eines Expanders lesen, liefert die Pegel aller Kanäle
+  /// # Arguments
+  /// * i2c - Geöffneter I2C Bus
+  /// * expander - Zu lesender Expander
+  fn read_inputs(&self, i2c: &mut I2cBus, expander: &I2cExpander) -> Result<u16, String> {
+    match expander.typ {
+      ExpanderTyp::MCP23017 => {
+        let mut daten = [0; 2];
+        i2c.write(expander.adresse, &[MCP23017_GPIO])?;
+        i2c.read(expander.adresse, &mut daten)?;
+        Ok(u16::from_le_bytes(daten))
+      }
+      ExpanderTyp::PCF8574 => {
+        let mut daten = [0; 1];
+        i2c.read(expander.adresse, &mut daten)?;
+        Ok(daten[0] as u16)
+      }
+    }
+  }
+
+  /// Eingänge aller Expander abfragen, bei Expandern mit Interrupt Pin nur wenn dieser aktiv ist,
+  /// sonst nach Ablauf des Abfrageintervalls.
+  /// # Arguments
+  /// * i2c - Geöffneter I2C Bus
+  /// * zustaende - Zustände aller Expander, Index 0 ist expander_1
+  fn poll_expander(&self, i2c: &mut I2cBus, zustaende: &mut [ExpanderZustand]) {
+    for (expander, zustand) in self.all_expander.iter().zip(zustaende) {
+      if !zustand.hat_fb {
+        continue;
+      }
+      let lesen = match &zustand.int_line {
+        //Interrupt Ausgang ist aktiv low, Line ist mit ACTIVE_LOW geöffnet
+        Some(line) => line.get_value().map_or_else(
+          |msg| {
+            warn!("I2C Expander {:#04x} Interrupt: {}", expander.adresse, msg);
+            true
+          },
+          |value| value != 0,
+        ),
+        None => zustand.gelesen.elapsed() >= self.fb_refresh,
+      };
+      if lesen {
+        match self.read_inputs(i2c, expander) {
+          Ok(eingaenge) => zustand.eingaenge = eingaenge,
+          Err(msg) => warn!("I2C Expander: {}", msg),
+        }
+        zustand.gelesen = Instant::now();
+      }
+    }
+  }
+
+  /// Ausführung als Thread
+  /// # Arguments
+  /// * rx - Channel Receiver über denn Kommandos empfangen werden
+  /// * tx - Channel Sender über den Info Messages zurück gesendet werden können
+  fn execute(&self, rx: Receiver<Message>, tx: ThreadSender<SRCPMessage>) -> Result<(), String> {
+    let mut i2c = match I2cBus::open(&self.i2c_port) {
+      Ok(i2c) => i2c,
+      Err(msg) => {
+        return Err(format!("I2C: konnte nicht geöffnet werden. {}", msg));
+      }
+    };
+    //Interrupt GPIO's werden nur geöffnet wenn konfiguriert
+    let mut chip: Option<Chip> = None;
+    let mut zustaende: Vec<ExpanderZustand> = Vec::new();
+    for (index, expander) in self.all_expander.iter().enumerate() {
+      let int_line = match expander.int_gpio {
+        Some(gpio) => {
+          if chip.is_none() {
+            let gpiochip = srcp_hardware::get_gpiochip();
+            match Chip::new(&gpiochip) {
+              Ok(gpio_chip) => chip = Some(gpio_chip),
+              Err(msg) => {
+                return Err(format!(
+                  "I2C: {} konnte nicht geöffnet werden. {}",
+                  gpiochip, msg
+                ));
+              }
+            }
+          }
+          match chip.as_mut().unwrap().get_line(gpio).and_then(|line| {
+            line.request(
+              LineRequestFlags::INPUT | LineRequestFlags::ACTIVE_LOW,
+              0,
+              "input_int_i2c",
+            )
+          }) {
+            Ok(line) => Some(line),
+            Err(msg) => {
+              return Err(format!(
+                "I2C: Interrupt GPIO {} konnte nicht als Input geöffnet werden. {}",
+                gpio, msg
+              ));
+            }
+          }
+        }
+        None => None,
+      };
+      zustaende.push(ExpanderZustand {
+        olat: 0,
+        eingaenge: 0,
+        hat_fb: self.all_fb.iter().any(|pin| pin.expander == index),
+        int_line,
+        gelesen: Instant::now(),
+      });
+    }
+    if let Err(msg) = self.init_expander(&mut i2c, &mut zustaende) {
+      return Err(format!(
+        "I2C: Expander Initialisierung fehlgeschlagen. {}",
+        msg
+      ));
+    }
+    //I2C Bus und Interrupt GPIO's offen, bereit für Kommandos
+    let hardware = I2cHardware {
+      server: self,
+      i2c,
+      zustaende,
+    };
+    GaFb::new(
+      self.busnr,
+      hardware,
+      self.all_ga.clone(),
+      self.all_fb.clone(),
+      self.fb_debounce,
+    )
+    .execute(rx, tx);
+    Ok(())
+  }
+}
+
+impl SRCPServer for I2cServer {
+  /// Liefert den Name des SRCP Servers zurück
+  /// Im Konfigfile muss für jeden verwendeten SRCP Server minimal ein Abschnitt mit diesem Name und dem zu verwenden Bus enthalten sein:
+  /// [SRCPServerName]
+  /// bus = x
+  fn get_name(&self) -> &'static str {
+    "i2c"
+  }
+
+  /// Liefert die Busnummer des SRCP Servers zurück, 0=nicht benutzt, konfiguriert
+  fn get_busnr(&self) -> usize {
+    self.busnr
+  }
+
+  /// Init dieses Servers
+  /// Liefert Err zurück wenn ein Fehler aufgetreten ist (z.B. fehlender Konfig Parameter)
+  ///
+  /// I2C Server hat folgende Konfigparameter, minimal ein Expander und eine GA oder ein FB muss vorhanden sein:
+  /// expander_1 .. expander_n fortlaufend für alle Expander: Typ und I2C Adresse, "mcp23017,0x20" oder "pcf8574,0x38"
+  /// ga_1 .. ga_n fortlaufend für alle GA Adressen: Kanal für Port 0 und optional Port 1 als <expander>:<pin>, "1:0" oder "1:0,1:1"
+  /// fb_1 .. fb_n fortlaufend für alle FB's: Kanal des Eingangs als <expander>:<pin>
+  /// Optional:
+  /// i2cport I2C Device, Default /dev/i2c-1
+  /// int_n GPIO Nummer an dem der Interrupt Ausgang von Expander n angeschlossen ist (aktiv low, mehrere
+  /// Expander können einen GPIO teilen). Eingänge werden dann nur bei aktivem Interrupt gelesen.
+  /// pulse_n Max. Einschaltzeit in ms für GA Adresse n (z.B. Entkuppler), ohne Angabe Dauerbetrieb
+  /// active_low Ausgänge sind aktiv low (z.B. Relaismodule)
+  /// fb_pullup Eingänge mit Pull-Up, Kontakt schaltet gegen GND (belegt = low). Beim MCP23017 werden die
+  /// internen Pull-Ups eingeschaltet, der PCF8574 hat sie immer.
+  /// fb_debounce Entprellzeit Eingänge in ms, Default 20ms
+  /// fb_refresh Abfrageintervall Eingänge von Expandern ohne Interrupt Pin in ms, Default 20ms
+  /// # Arguments
+  /// * busnr - Die SRCP Busnummers die diesem Server zugeordner ist.
+  /// * config_file_bus - Der diesen Bus betreffende Teil des Konfigfiles
+  fn init(
+    &mut self, busnr: usize, config_file_bus: &HashMap<String, Option<String>>,
+  ) -> Result<(), String> {
+    self.busnr = busnr;
+    self.i2c_port = match config_file_bus.get("i2cport") {
+      Some(port) => port.as_ref().ok_or("I2C: i2cport ohne Wert")?.to_string(),
+      None => I2C_PORT_DEFAULT.to_string(),
+    };
+    self.active_low = config_file_bus.contains_key("active_low");
+    self.all_expander.clear();
+    while let Some(expander) =
+      config_file_bus.get(&format!("expander_{}", self.all_expander.len() + 1))
+    {
+      let nr = self.all_expander.len() + 1;
+      let fehler = format!(
+        "I2C: expander_{} muss Typ und Adresse enthalten, z.B. mcp23017,0x20",
+        nr
+      );
+      let (typ, adresse) = expander
+        .as_ref()
+        .and_then(|expander| expander.split_once(','))
+        .ok_or(fehler.clone())?;
+      let typ = match typ.trim().to_lowercase().as_str() {
+        "mcp23017" => ExpanderTyp::MCP23017,
+        "pcf8574" => ExpanderTyp::PCF8574,
+        _ => Err(fehler.clone())?,
+      };
+      let adresse = adresse.trim();
+      let adresse = match adresse.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => adresse.parse::<u16>().ok(),
+      }
+      .filter(|adresse| *adresse < 0x80)
+      .ok_or(fehler)?;
+      let int_gpio = match config_file_bus.get(&format!("int_{}", nr)) {
+        Some(gpio) => Some(
+          gpio
+            .as_ref()
+            .and_then(|gpio| gpio.trim().parse::<u32>().ok())
+            .ok_or(format!("I2C: int_{} muss eine GPIO Nummer enthalten", nr))?,
+        ),
+        None => None,
+      };
+      self.all_expander.push(I2cExpander {
+        typ,
+        adresse,
+        int_gpio,
+      });
+    }
+    if self.all_expander.is_empty() {
+      Err("I2C: kein Expander (expander_1 ..) konfiguriert")?;
+    }
+    //Jeder Kanal darf nur einmal verwendet werden
+    let mut belegt: HashSet<I2cPin> = HashSet::new();
+    self.all_ga.clear();
+    let mut adr: u32 = 1;
+    while let Some(pins) = config_file_bus.get(&format!("ga_{}", adr)) {
+      let fehler = format!("ga_{} muss 1 oder 2 Kanäle <expander>:<pin> enthalten", adr);
+      let pins = pins
+        .as_ref()
+        .ok_or(format!("I2C: ga_{} ohne Wert", adr))?
+        .split(',')
+        .map(|pin| self.parse_pin(pin))
+        .collect::<Option<Vec<I2cPin>>>()
+        .ok_or(format!("I2C: {}", fehler))?;
+      if pins.is_empty() || (pins.len() > 2) {
+        Err(format!("I2C: {}", fehler))?;
+      }
+      for pin in &pins {
+        if !belegt.insert(*pin) {
+          Err(format!(
+            "I2C: ga_{} Kanal {}:{} mehrfach verwendet",
+            adr,
+            pin.expander + 1,
+            pin.pin
+          ))?;
+        }
+      }
+      let pulse = match config_file_bus.get(&format!("pulse_{}", adr)) {
+        Some(pulse) => Some(Duration::from_millis(
+          pulse
+            .as_ref()
+            .ok_or(format!("I2C: pulse_{} ohne Wert", adr))?
+            .parse::<u64>()
+            .ok()
+            .ok_or(format!("I2C: pulse_{} muss eine Zahl sein", adr))?,
+        )),
+        None => None,
+      };
+      self.all_ga.insert(adr, GaKonfig { pins, pulse });
+      adr += 1;
+    }
+    self.all_fb.clear();
+    while let Some(pin) = config_file_bus.get(&format!("fb_{}", self.all_fb.len() + 1)) {
+      let fb_nr = self.all_fb.len() + 1;
+      let pin = pin
+        .as_ref()
+        .and_then(|pin| self.parse_pin(pin))
+        .ok_or(format!(
+          "I2C: fb_{} muss einen Kanal <expander>:<pin> enthalten",
+          fb_nr
+        ))?;
+      if !belegt.insert(pin) {
+        Err(format!(
+          "I2C: fb_{} Kanal {}:{} mehrfach verwendet",
+          fb_nr,
+          pin.expander + 1,
+          pin.pin
+        ))?;
+      }
+      self.all_fb.push(pin);
+    }
+    self.fb_pullup = config_file_bus.contains_key("fb_pullup");
+    self.fb_debounce = match config_file_bus.get("fb_debounce") {
+      Some(debounce) => Duration::from_millis(
+        debounce
+          .as_ref()
+          .and_then(|debounce| debounce.parse::<u64>().ok())
+          .ok_or("I2C: fb_debounce muss eine Zahl sein")?,
+      ),
+      None => FB_DEBOUNCE_DEFAULT,
+    };
+    self.fb_refresh = match config_file_bus.get("fb_refresh") {
+      Some(refresh) => Duration::from_millis(
+        refresh
+          .as_ref()
+          .and_then(|refresh| refresh.parse::<u64>().ok())
+          .ok_or("I2C: fb_refresh muss eine Zahl sein")?,
+      ),
+      None => FB_REFRESH_DEFAULT,
+    };
+    if self.all_ga.is_empty() && self.all_fb.is_empty() {
+      Err("I2C: keine GA (ga_1 ..) und keine FB (fb_1 ..) konfiguriert")?;
+    }
+    info!(
+      "I2C: {} Expander, {} GA und {} FB konfiguriert",
+      self.all_expander.len(),
+      self.all_ga.len(),
+      self.all_fb.len()
+    );
+    Ok(())
+  }
+
+  /// Start dieses Servers
+  /// # Arguments
+  /// * rx - Channel Receiver über denn Kommandos empfangen werden
+  /// * tx - Channel Sender über den Info Messages zurück gesendet werden können
+  fn start(&self, rx: Receiver<Message>, tx: SyncSender<SRCPMessage>) {
+    let instanz = self.clone();
+    spawn_server_thread(
+      "I2C_Thread",
+      self.busnr..self.busnr + self.get_srcp_bus_count(),
+      tx,
+      move |tx| instanz.execute(rx, ThreadSender::new(tx, "SRCP Dispatcher")),
+    );
+  }
+}
@@ -1,49 +1,302 @@
 use std::{
+  cmp::Reverse,
   collections::HashMap,
   io::Read,
-  sync::mpsc::{Receiver, Sender},
+  mem,
+  sync::mpsc::{self, Receiver, Sender, SyncSender},
   thread,
-  time::Duration,
+  time::{Duration, Instant},
 };
 
-use crate::srcp_server_types::{
-  Message, SRCPMessage, SRCPMessageDevice, SRCPMessageID, SRCPMessageType, SRCPServer,
+use crate::{
+  srcp_hardware,
+  srcp_server_types::{
+    spawn_server_thread, Message, SRCPMessage, SRCPMessageDevice, SRCPMessageID, SRCPMessageType,
+    SRCPServer, INFO_ERWEITERUNG,
+  },
 };
 use gpio_cdev::{Chip, LineHandle, LineRequestFlags};
-use log::warn;
-use spidev::{SpiModeFlags, Spidev, SpidevOptions};
+use log::{info, warn};
+use spidev::{SpiModeFlags, Spidev, SpidevOptions, SpidevTransfer};
 
-/// Max. Anzahl unterstützer S88 Busse (= Anzahl belegter SRCP Busse)
-const MAX_S88: usize = 4;
-/// Frequenz SPI Bus für S88
+/// Default Frequenz SPI Bus für S88, konfigurierbar mit "spi_hz"
 /// Leider ist bei allen SPI interfaces die kleinst mögliche Taktrate core_freq / (2 * (speed + 1)).
 /// speed ist je 12 Bit, max. also 4095.
 /// Bei 250MHz sind wir also bei 30.5kHz. Das geht bei mir noch stabil, 50 kHz geht nicht mehr stabil.
 /// -> core_freq darf nicht grösser als 250MHz gesetzt werden!
 /// Die 20 kHz sind ein Wunsch, der auf SPI des Broadcom Chips nicht in Erfüllung geht .... :-(
-const SPI_HZ: u32 = 20_000;
+/// Mit "spi_soft" wird er durch Pausen zwischen den Bytes im Mittel erreicht.
+const SPI_HZ_DEFAULT: u32 = 20_000;
+/// Max. konfigurierbare Frequenz SPI Bus für S88
+const SPI_HZ_MAX: u32 = 100_000;
+/// Ab dieser Frequenz ist der S88 Bus erfahrungsgemäss nicht mehr stabil
+const SPI_HZ_STABIL_MAX: u64 = 40_000;
+/// Angenommene core_freq in Hz wenn sie weder konfiguriert noch über debugfs ermittelbar ist
+const CORE_FREQ_DEFAULT: u64 = 250_000_000;
 /// maximal number of bytes read from one s88-bus
 const S88_MAXPORTSB: usize = 64;
 /// Pause zwischen 2 SPI Transfers damit alle CE Leitungen sicher minimale Zeit auf 1 sind
 const PAUSE_SPI_TRANSFER: Duration = Duration::from_micros(500);
+/// Erweiterung GET <bus> FB <addr> STATS: Abfrage der Lesestatistik eines FB
+const FB_STATISTIK: &str = "STATS";
+/// Erweiterung SET <bus> FB TRIGGER <gpio> [<fb>,<fb>..]: Oszi Trigger zur Laufzeit ändern
+const FB_TRIGGER: &str = "TRIGGER";
+/// Intervall für Log Ausgabe der Lesestatistik
+const STATISTIK_INTERVALL: Duration = Duration::from_secs(600);
+/// Max. Anzahl FB's pro S88 Bus mit Glitches in der Log Ausgabe der Lesestatistik
+const STATISTIK_LOG_MAX_FB: usize = 10;
+/// Intervall für die Begrenzung der INFO Meldungen pro FB (info_max)
+const INFO_MAX_INTERVALL: Duration = Duration::from_secs(1);
+/// Testmuster für den Verdrahtungstest (VERIFY <bus> FB), wird zyklisch über die ganze Buslänge ausgegeben
+const DIAG_PATTERN: [u8; 4] = [0x55, 0xAA, 0x00, 0xFF];
+
+/// Verknüpfung der FB's eines logischen Blockes
+#[derive(Clone, Copy, PartialEq)]
+enum FbBlockVerknuepfung {
+  /// Block belegt wenn mindestens ein FB belegt ist
+  Oder,
+  /// Block belegt wenn alle FB's belegt sind
+  Und,
+}
+
+/// Logischer Block aus mehreren FB's eines S88 Busses, wird als zusätzlicher, virtueller FB gemeldet
+#[derive(Clone)]
+struct FbBlock {
+  /// Verknüpfung der FB's
+  verknuepfung: FbBlockVerknuepfung,
+  /// Die FB's des Blockes, Nummerierung ab 0
+  fbs: Vec<usize>,
+}
+
+/// Begrenzung der INFO Meldungen eines FB, damit ein prellender Kontakt nicht alle Info Clients flutet.
+/// Meldungen über dem Maximum werden zusammengefasst, der letzte Zustand wird immer gemeldet.
+#[derive(Clone)]
+struct FbInfoDrossel {
+  /// Start aktuelles Intervall
+  intervall_start: Instant,
+  /// Anzahl im aktuellen Intervall gesendete INFO
+  anzahl: u32,
+  /// Zuletzt mit INFO gemeldeter Zustand
+  gemeldet: bool,
+}
+impl FbInfoDrossel {
+  fn new() -> FbInfoDrossel {
+    FbInfoDrossel {
+      intervall_start: Instant::now(),
+      anzahl: 0,
+      gemeldet: false,
+    }
+  }
+
+  /// Liefert true wenn der Zustand jetzt mit INFO gemeldet werden muss.
+  /// # Arguments
+  /// * state - Aktueller Zustand des FB
+  /// * info_max - Max. Anzahl INFO pro Intervall, None wenn unbegrenzt
+  /// * jetzt - Aktueller Zeitpunkt
+  fn melden(&mut self, state: bool, info_max: Option<u32>, jetzt: Instant) -> bool {
+    if jetzt >= self.intervall_start + INFO_MAX_INTERVALL {
+      self.intervall_start = jetzt;
+      self.anzahl = 0;
+    }
+    if (state == self.gemeldet) || info_max.is_some_and(|info_max| self.anzahl >= info_max) {
+      return false;
+    }
+    self.anzahl += 1;
+    self.gemeldet = state;
+    true
+  }
+}
+
+/// Lesestatistik eines FB, zur Erkennung defekter Rückmeldemodule
+#[derive(Clone)]
+pub struct FbStatistik {
+  /// Anzahl gefilterte Zustandsänderungen seit Start
+  pub aenderungen: u64,
+  /// Anzahl durch den Filter unterdrückte Störungen (Glitches) seit Start
+  pub glitches: u64,
+  /// Anzahl Glitches im aktuellen Statistikintervall
+  pub glitches_intervall: u64,
+  /// Zeitpunkt der letzten gefilterten Zustandsänderung, None wenn seit Start keine
+  pub letzte_aenderung: Option<Instant>,
+  /// Ungefilterter Wert weicht vom gefilterten Zustand ab, Ausgang noch offen
+  abweichung: bool,
+}
+
+impl FbStatistik {
+  /// Neue, leere Statistik
+  pub fn new() -> FbStatistik {
+    FbStatistik {
+      aenderungen: 0,
+      glitches: 0,
+      glitches_intervall: 0,
+      letzte_aenderung: None,
+      abweichung: false,
+    }
+  }
+
+  /// Statistik nach einem Lesezyklus nachführen.
+  /// Eine Abweichung des ungefilterten Wertes, die ohne gefilterte Zustandsänderung wieder verschwindet,
+  /// ist ein Glitch.
+  /// # Arguments
+  /// * geaendert - Gefilterter Zustand hat sich in diesem Zyklus verändert
+  /// * abweichung - Ungefilterter Wert weicht vom gefilterten Zustand ab
+  pub fn update(&mut self, geaendert: bool, abweichung: bool) {
+    if geaendert {
+      self.aenderungen += 1;
+      self.letzte_aenderung = Some(Instant::now());
+      self.abweichung = false;
+    } else if abweichung {
+      self.abweichung = true;
+    } else if self.abweichung {
+      self.glitches += 1;
+      self.glitches_intervall += 1;
+      self.abweichung = false;
+    }
+  }
+}
+
+impl FbBlock {
+  /// Block Konfiguration "or:1,2,3" oder "and:4,5" auswerten.
+  /// # Arguments
+  /// * config - Konfigurationswert
+  /// * anz_fb - Anzahl FB's des S88 Busses, gültig sind 1 bis anz_fb
+  fn from(config: &str, anz_fb: usize) -> Result<FbBlock, String> {
+    let (verknuepfung, fbs) = config.split_once(':').ok_or(format!(
+      "S88 Block {}: Format or:FB,.. oder and:FB,..",
+      config
+    ))?;
+    let verknuepfung = match verknuepfung.trim().to_lowercase().as_str() {
+      "or" => FbBlockVerknuepfung::Oder,
+      "and" => FbBlockVerknuepfung::Und,
+      _ => Err(format!("S88 Block {}: nur or oder and erlaubt", config))?,
+    };
+    let mut block = FbBlock {
+      verknuepfung,
+      fbs: vec![],
+    };
+    for fb in fbs.split(',') {
+      let fb_nr = fb
+        .trim()
+        .parse::<usize>()
+        .ok()
+        .ok_or(format!("S88 Block {}: ungültige FB Nummer {}", config, fb))?;
+      //Auf SRCP beginnen die FB Nummern bei 1
+      if (fb_nr == 0) || (fb_nr > anz_fb) {
+        Err(format!(
+          "S88 Block {}: FB Nummer {} ungültig. Erlaubt 1 bis {}.",
+          config, fb_nr, anz_fb
+        ))?;
+      }
+      block.fbs.push(fb_nr - 1);
+    }
+    Ok(block)
+  }
+
+  /// Liefert den Zustand des Blockes
+  /// # Arguments
+  /// * states - Alle FB Zustände des S88 Busses
+  fn get_state(&self, states: &[bool]) -> bool {
+    match self.verknuepfung {
+      FbBlockVerknuepfung::Oder => self.fbs.iter().any(|fb| states[*fb]),
+      FbBlockVerknuepfung::Und => self.fbs.iter().all(|fb| states[*fb]),
+    }
+  }
+}
+
+/// Auftrag an den Lesethread eines S88 Busses
+enum S88LeseAuftrag {
+  /// Bus in den übergebenen Buffer einlesen
+  Lesen(Vec<u8>),
+  /// Verdrahtungstest mit Anzahl Bytes des Busses
+  Diagnose(usize),
+}
+
+/// Antwort des Lesethreads eines S88 Busses
+enum S88LeseAntwort {
+  /// Eingelesener Buffer
+  Gelesen(Vec<u8>),
+  /// Resultat Verdrahtungstest
+  Diagnose(Result<Vec<usize>, String>),
+}
+
+/// Lesethread eines S88 Busses. Läuft während der ganzen Laufzeit, damit nicht für jeden Lesezyklus
+/// ein Thread erzeugt werden muss. Alle S88 Busse werden so parallel eingelesen.
+struct S88Leser {
+  /// Aufträge an den Lesethread
+  tx: Sender<S88LeseAuftrag>,
+  /// Antworten vom Lesethread
+  rx: Receiver<S88LeseAntwort>,
+}
+
+impl S88Leser {
+  /// Lesethread starten
+  /// # Arguments
+  /// * spidev - SPI Device des S88 Busses, gehört ab jetzt dem Lesethread
+  /// * pause - Pause in us nach jedem Byte, None für Einlesen mit einem Transfer.
+  /// * name - Threadname
+  fn start(mut spidev: Spidev, pause: Option<u16>, name: String) -> S88Leser {
+    let (tx, rx_auftrag) = mpsc::channel();
+    let (tx_antwort, rx) = mpsc::channel();
+    thread::Builder::new()
+      .name(name)
+      .spawn(move || {
+        for auftrag in rx_auftrag {
+          let antwort = match auftrag {
+            S88LeseAuftrag::Lesen(mut buffer) => {
+              S88::read(&mut spidev, buffer.as_mut_slice(), pause);
+              S88LeseAntwort::Gelesen(buffer)
+            }
+            S88LeseAuftrag::Diagnose(number_bytes) => {
+              S88LeseAntwort::Diagnose(S88::diagnose(&mut spidev, number_bytes))
+            }
+          };
+          //Damit sicher alle CE Leitungen eine minimale Zeit auf 1 zurück sind bis zum nächsten Transfer etwas warten
+          thread::sleep(PAUSE_SPI_TRANSFER);
+          if tx_antwort.send(antwort).is_err() {
+            break;
+          }
+        }
+      })
+      .expect("S88 Lesethread konnte nicht gestartet werden");
+    S88Leser { tx, rx }
+  }
+}
 
 #[derive(Clone)]
 pub struct S88 {
   //SRCP Busnr
   busnr: usize,
-  //Refreshzeit in ms
+  //Refreshzeit in ms wenn keine Veränderungen erkannt werden
   refresh: u64,
-  //Anzahl Wiederholungen für Filterung, sollte ungerade sein.
+  //Minimale Refreshzeit in ms solange Veränderungen erkannt werden
+  refresh_min: u64,
+  //Anzahl Wiederholungen für Filterung, sollte ungerade sein. Default für alle S88 Busse.
   repeat: usize,
+  //Anzahl Wiederholungen für Filterung pro S88 Bus
+  repeats: Vec<usize>,
   //SPI Port
   spiport: String,
   //SPI Mode
   spimode: u32,
-  //Anzahl einzulesende Bytes Bus1..4
-  number_bytes: [usize; MAX_S88],
+  //Verlangte Frequenz SPI Bus
+  spi_hz: u32,
+  //core_freq in Hz aus Konfigfile, None wenn über debugfs ermittelt
+  core_freq: Option<u64>,
+  //Verlangte Frequenz mit Pausen zwischen den Bytes erreichen, wenn sie unter dem Hardware Minimum liegt
+  spi_soft: bool,
+  //Anzahl einzulesende Bytes pro konfiguriertem S88 Bus (= Anzahl belegter SRCP Busse)
+  number_bytes: Vec<usize>,
   //Konfiguration Oszi Trigger pro S88 Bus und Feedbacknummer
   trigger_port: Option<u32>,
-  trigger: [Vec<usize>; MAX_S88],
+  trigger: Vec<Vec<usize>>,
+  //Logische Blöcke pro S88 Bus, FB Nummern im Anschluss an die realen FB's
+  blocks: Vec<Vec<FbBlock>>,
+  //Belegte FB's ohne Veränderung während dieser Zeit werden in der Lesestatistik als mögliches "stuck-at" gemeldet
+  stuck_warn: Option<Duration>,
+  //Max. Anzahl INFO pro FB und Sekunde, None wenn unbegrenzt. Default für alle S88 Busse.
+  info_max: Option<u32>,
+  //Max. Anzahl INFO pro FB und Sekunde pro S88 Bus
+  info_max_bus: Vec<Option<u32>>,
 }
 
 impl S88 {
@@ -52,13 +305,282 @@ impl S88 {
     S88 {
       busnr: 0,
       refresh: 50,
+      refresh_min: 50,
       repeat: 3,
+      repeats: Vec::new(),
       spiport: "".to_string(),
       spimode: 1,
-      //Für alle 4 S88 Busse
-      number_bytes: [0; MAX_S88],
+      spi_hz: SPI_HZ_DEFAULT,
+      core_freq: None,
+      spi_soft: false,
+      //Anzahl S88 Busse kommt aus der Konfiguration
+      number_bytes: Vec::new(),
       trigger_port: None,
-      trigger: [vec![], vec![], vec![], vec![]],
+      trigger: Vec::new(),
+      blocks: Vec::new(),
+      stuck_warn: None,
+      info_max: None,
+      info_max_bus: Vec::new(),
+    }
+  }
+
+  /// Verdrahtungstest eines S88 Busses.
+  /// Voraussetzung: MOSI ist auf den Dateneingang des letzten S88 Moduls (Ende der Kette) geführt.
+  /// Es wird die doppelte Buslänge eingelesen und dabei ein Testmuster ausgegeben. Nachdem der Inhalt
+  /// der Kette hinausgeschoben wurde, muss das Testmuster unverändert zurückgelesen werden.
+  /// Liefert die Bitpositionen (beginnend bei 1, wie FB Nummern) mit falsch gelesenem Wert zurück,
+  /// leer wenn alles in Ordnung ist.
+  /// # Arguments
+  /// * spidev - SPI Device des zu testenden S88 Busses
+  /// * number_bytes - Anzahl Bytes des S88 Busses
+  fn diagnose(spidev: &mut Spidev, number_bytes: usize) -> Result<Vec<usize>, String> {
+    let mut tx_buf: Vec<u8> = vec![0; number_bytes * 2];
+    for (i, byte) in tx_buf.iter_mut().take(number_bytes).enumerate() {
+      *byte = DIAG_PATTERN[i % DIAG_PATTERN.len()];
+    }
+    let mut rx_buf: Vec<u8> = vec![0; number_bytes * 2];
+    {
+      let mut transfer = SpidevTransfer::read_write(tx_buf.as_slice(), rx_buf.as_mut_slice());
+      spidev
+        .transfer(&mut transfer)
+        .map_err(|err| format!("S88 Diagnose SPI transfer fail: {}", err))?;
+    }
+    let mut fehler: Vec<usize> = Vec::new();
+    for byte_nr in 0..number_bytes {
+      let diff = tx_buf[byte_nr] ^ rx_buf[number_bytes + byte_nr];
+      for bit_nr in 0..8 {
+        //Bit Order wie von S88 -> MSB ist erster FB
+        if (diff & (1 << (7 - bit_nr))) != 0 {
+          fehler.push(byte_nr * 8 + bit_nr + 1);
+        }
+      }
+    }
+    Ok(fehler)
+  }
+
+  /// GPIO für den Oszi Trigger als Output öffnen
+  /// # Arguments
+  /// * port - GPIO Nummer
+  fn open_trigger_port(port: u32) -> Result<LineHandle, String> {
+    Chip::new(srcp_hardware::get_gpiochip())
+      .map_err(|err| format!("GPIO Chip konnte nicht geöffnet werden: {}", err))?
+      .get_line(port)
+      .map_err(|err| {
+        format!(
+          "GPIO {} für S88 Oszi Trigger konnte nicht geöffnet werden: {}",
+          port, err
+        )
+      })?
+      .request(LineRequestFlags::OUTPUT, 1, "output_trigger_s88")
+      .map_err(|err| {
+        format!(
+          "GPIO {} für S88 Oszi Trigger konnte nicht als Output geöffnet werden: {}",
+          port, err
+        )
+      })
+  }
+
+  /// Parameter von "SET <bus> FB TRIGGER <gpio> [<fb>,<fb>..]" auswerten.
+  /// Liefert GPIO Nummer und die FB's (Nummerierung ab 0) zurück, Err bei ungültigen Parametern.
+  /// Es ist nur der im Konfigfile als "trigger_port" angegebene GPIO erlaubt, damit kein Client
+  /// beliebige GPIO's schalten kann.
+  /// # Arguments
+  /// * parameter - Parameter nach "TRIGGER"
+  /// * anz_fb - Anzahl reale FB's des S88 Busses, gültig sind 1 bis anz_fb
+  /// * trigger_port - Konfigurierter Oszi Trigger GPIO, None wenn nicht konfiguriert
+  pub fn parse_trigger_cmd(
+    parameter: &[String], anz_fb: usize, trigger_port: Option<u32>,
+  ) -> Result<(u32, Vec<usize>), String> {
+    let port = parameter
+      .first()
+      .and_then(|port| port.parse::<u32>().ok())
+      .ok_or("S88 Trigger: GPIO Nummer fehlt oder ungültig")?;
+    if trigger_port != Some(port) {
+      return Err(format!(
+        "S88 Trigger: GPIO {} ist nicht als trigger_port konfiguriert",
+        port
+      ));
+    }
+    let mut fbs: Vec<usize> = Vec::new();
+    if let Some(liste) = parameter.get(1) {
+      for fb in liste.split(',') {
+        let fb_nr = fb
+          .parse::<usize>()
+          .ok()
+          .filter(|fb_nr| (1..=anz_fb).contains(fb_nr))
+          .ok_or(format!(
+            "S88 Trigger: ungültige FB Nummer {}. Erlaubt 1 bis {}.",
+            fb, anz_fb
+          ))?;
+        //Auf SRCP beginnen die FB Nummern bei 1
+        fbs.push(fb_nr - 1);
+      }
+    }
+    Ok((port, fbs))
+  }
+
+  /// Lesestatistik eines FB an eine Session senden
+  /// 190 INFO <bus> FB <addr> STATS <änderungen> <glitches> <s seit letzter Änderung>
+  /// s seit letzter Änderung ist -1, wenn seit Start keine Änderung erkannt wurde.
+  /// Liefert false zurück, wenn nicht gesendet werden konnte.
+  /// # Arguments
+  /// * srcp_message - GET Kommando
+  /// * fb_nr - FB Nummer, beginnend bei 1
+  /// * statistik - Statistik des FB
+  /// * tx - Channel Sender für die Antwort
+  fn send_info_statistik(
+    srcp_message: &SRCPMessage, fb_nr: usize, statistik: &FbStatistik, tx: &SyncSender<SRCPMessage>,
+  ) -> bool {
+    let msg = SRCPMessage::new(
+      srcp_message.session_id,
+      srcp_message.bus,
+      SRCPMessageID::Info {
+        info_code: INFO_ERWEITERUNG.to_string(),
+      },
+      SRCPMessageDevice::FB,
+      vec![
+        fb_nr.to_string(),
+        FB_STATISTIK.to_string(),
+        statistik.aenderungen.to_string(),
+        statistik.glitches.to_string(),
+        statistik
+          .letzte_aenderung
+          .map_or("-1".to_string(), |aenderung| {
+            aenderung.elapsed().as_secs().to_string()
+          }),
+      ],
+    );
+    if let Err(msg) = tx.send(msg) {
+      warn!("S88 execute send Error, wird beendet: {}", msg);
+      return false;
+    }
+    true
+  }
+
+  /// Periodische Log Ausgabe der Lesestatistik aller S88 Busse:
+  /// FB's mit Glitches im abgelaufenen Intervall und belegte FB's ohne Veränderung seit "stuck_warn".
+  /// Startet ein neues Statistikintervall.
+  /// # Arguments
+  /// * statistik - Lesestatistik pro S88 Bus und FB
+  /// * s88_states - Gefilterte Zustände pro S88 Bus und FB
+  /// * start - Zeitpunkt Start des Programmes, für FB's ohne Änderung seit Start
+  fn log_statistik(
+    &self, statistik: &mut [Vec<FbStatistik>], s88_states: &[Vec<bool>], start: Instant,
+  ) {
+    for (spi_bus, fb_statistik) in statistik.iter_mut().enumerate() {
+      let bus = self.busnr + spi_bus;
+      //FB's mit Glitches, meiste zuerst
+      let mut glitches: Vec<(usize, u64)> = fb_statistik
+        .iter()
+        .enumerate()
+        .filter(|(_, fb)| fb.glitches_intervall > 0)
+        .map(|(fb_nr, fb)| (fb_nr + 1, fb.glitches_intervall))
+        .collect();
+      if !glitches.is_empty() {
+        glitches.sort_by_key(|(_, anzahl)| Reverse(*anzahl));
+        let summe: u64 = glitches.iter().map(|(_, anzahl)| anzahl).sum();
+        let liste: Vec<String> = glitches
+          .iter()
+          .take(STATISTIK_LOG_MAX_FB)
+          .map(|(fb_nr, anzahl)| format!("FB {} ({})", fb_nr, anzahl))
+          .collect();
+        warn!(
+          "S88 Bus {}: {} Glitches in {} s bei {} FB's: {}",
+          bus,
+          summe,
+          STATISTIK_INTERVALL.as_secs(),
+          glitches.len(),
+          liste.join(", ")
+        );
+      }
+      if let Some(stuck_warn) = self.stuck_warn {
+        for (fb_nr, fb) in fb_statistik.iter().enumerate() {
+          let seit = fb.letzte_aenderung.unwrap_or(start).elapsed();
+          if s88_states[spi_bus][fb_nr] && (seit >= stuck_warn) {
+            warn!(
+              "S88 Bus {}: FB {} seit {} s ohne Veränderung belegt (stuck-at?)",
+              bus,
+              fb_nr + 1,
+              seit.as_secs()
+            );
+          }
+        }
+      }
+      for fb in fb_statistik.iter_mut() {
+        fb.glitches_intervall = 0;
+      }
+    }
+  }
+
+  /// Prüfung des effektiven SPI Clocks bei der aktuellen core_freq, Resultat wird geloggt.
+  /// Liefert die Pause in us nach jedem Byte zurück, wenn die verlangte Frequenz unter dem Hardware Minimum
+  /// liegt und "spi_soft" konfiguriert ist. Sonst None.
+  fn check_spi_clock(&self) -> Option<u16> {
+    let core_freq = match self.core_freq.or_else(srcp_hardware::get_core_freq) {
+      Some(core_freq) => core_freq,
+      None => {
+        warn!(
+          "S88: core_freq kann nicht ermittelt werden (debugfs nicht gemountet?), {} Hz angenommen. Angabe mit core_freq = <Hz> möglich.",
+          CORE_FREQ_DEFAULT
+        );
+        CORE_FREQ_DEFAULT
+      }
+    };
+    let effektiv = srcp_hardware::get_spi_aux_clock_effektiv(core_freq, self.spi_hz);
+    info!(
+      "S88: SPI Clock effektiv {} Hz (Soll {} Hz, core_freq {} Hz)",
+      effektiv, self.spi_hz, core_freq
+    );
+    if effektiv > SPI_HZ_STABIL_MAX {
+      warn!(
+        "S88: SPI Clock {} Hz ist zu hoch für einen stabilen S88 Bus. In /boot/config.txt core_freq=250 und core_freq_min=250 setzen!",
+        effektiv
+      );
+    }
+    if effektiv <= self.spi_hz as u64 {
+      return None;
+    }
+    if !self.spi_soft {
+      warn!(
+        "S88: SPI Clock {} Hz unter Hardware Minimum, es wird {} Hz verwendet. Mit spi_soft wird er im Mittel erreicht.",
+        self.spi_hz, effektiv
+      );
+      return None;
+    }
+    //Pro Byte Differenz der Übertragungszeit mit verlangter und effektiver Frequenz
+    let pause = 8_000_000 / self.spi_hz as u64 - 8_000_000 / effektiv;
+    let pause = pause.min(u16::MAX as u64) as u16;
+    info!(
+      "S88: spi_soft, Pause {} us nach jedem Byte für im Mittel {} Hz",
+      pause, self.spi_hz
+    );
+    Some(pause)
+  }
+
+  /// Alle Bytes eines S88 Busses einlesen.
+  /// # Arguments
+  /// * spidev - SPI Device des S88 Busses
+  /// * buffer - Buffer für die eingelesenen Bytes, bestimmt die Anzahl Bytes
+  /// * pause - Pause in us nach jedem Byte, None für Einlesen mit einem Transfer.
+  ///   Während den Pausen bleibt CE aktiv, der S88 Bus wird also nicht neu geladen.
+  fn read(spidev: &mut Spidev, buffer: &mut [u8], pause: Option<u16>) {
+    match pause {
+      None => {
+        spidev.read(buffer).expect("S88 SPI read fail");
+      }
+      Some(pause) => {
+        let mut transfers: Vec<SpidevTransfer> = buffer
+          .chunks_mut(1)
+          .map(|byte| {
+            let mut transfer = SpidevTransfer::read(byte);
+            transfer.delay_usecs = pause;
+            transfer
+          })
+          .collect();
+        spidev
+          .transfer_multiple(&mut transfers)
+          .expect("S88 SPI read fail");
+      }
     }
   }
 
@@ -66,7 +588,7 @@ impl S88 {
   /// # Arguments
   /// * rx - Channel Receiver über denn Kommandos empfangen werden
   /// * tx - Channel Sender über den Info Messages zurück gesendet werden können
-  fn execute(&self, rx: Receiver<Message>, tx: Sender<SRCPMessage>) {
+  fn execute(&self, rx: Receiver<Message>, tx: SyncSender<SRCPMessage>) {
     let mut spidevs: Vec<Option<Spidev>> = Vec::new();
     //SPI Interfaces für alle Konfigurierten S88 Busse (number_bytes>0) öffnen
     for (i, number) in self.number_bytes.iter().enumerate() {
@@ -76,7 +598,7 @@ impl S88 {
           Ok(mut dev) => {
             let options = SpidevOptions::new()
               .bits_per_word(8)
-              .max_speed_hz(SPI_HZ)
+              .max_speed_hz(self.spi_hz)
               .mode(SpiModeFlags::from_bits_truncate(self.spimode))
               .build();
             if let Ok(()) = dev.configure(&options) {
@@ -98,36 +620,68 @@ impl S88 {
       }
     }
 
-    let mut akt_wiederhol_index: usize = 0;
+    let spi_pause = self.check_spi_clock();
+    //Lesethreads für alle geöffneten S88 Busse
+    let leser: Vec<Option<S88Leser>> = spidevs
+      .into_iter()
+      .enumerate()
+      .map(|(i, spidev)| {
+        spidev.map(|dev| S88Leser::start(dev, spi_pause, format!("S88Leser{}", i)))
+      })
+      .collect();
+    //Aktueller Filterplatz pro S88 Bus
+    let mut akt_wiederhol_index: Vec<usize> = vec![0; self.number_bytes.len()];
+    //Aktuelle Refreshzeit in ms, zwischen "refresh_min" und "refresh"
+    let mut refresh_akt = self.refresh;
     //SPI Buffer [SPIBus][Wiederholung][Byte]
-    let mut s88_input_buffer: Vec<Vec<Vec<u8>>> = vec![vec![vec![]; self.repeat]; MAX_S88];
+    let mut s88_input_buffer: Vec<Vec<Vec<u8>>> = self
+      .repeats
+      .iter()
+      .map(|repeat| vec![vec![]; *repeat])
+      .collect();
     //Für jeden Bus die aktuellen und letzten S88 Zustände
-    let mut s88_states: Vec<Vec<bool>> = vec![vec![]; MAX_S88];
+    let mut s88_states: Vec<Vec<bool>> = vec![vec![]; self.number_bytes.len()];
     //Anzahl Byte pro Bus gemäss Konfiguration setzen
-    for spi_bus in 0..MAX_S88 {
-      s88_states[spi_bus].resize(self.number_bytes[spi_bus] * 8, false);
-      for repeat in 0..self.repeat {
+    for spi_bus in 0..self.number_bytes.len() {
+      //Reale FB's und im Anschluss die logischen Blöcke
+      s88_states[spi_bus].resize(
+        self.number_bytes[spi_bus] * 8 + self.blocks[spi_bus].len(),
+        false,
+      );
+      for repeat in 0..self.repeats[spi_bus] {
         s88_input_buffer[spi_bus][repeat].resize(self.number_bytes[spi_bus], 0);
         s88_input_buffer[spi_bus][repeat].shrink_to_fit();
       }
     }
+    //Begrenzung INFO Meldungen für alle FB's inkl. logische Blöcke pro Bus
+    let mut fb_info_drossel: Vec<Vec<FbInfoDrossel>> = s88_states
+      .iter()
+      .map(|states| vec![FbInfoDrossel::new(); states.len()])
+      .collect();
+    //Lesestatistik für alle realen FB's pro Bus
+    let mut fb_statistik: Vec<Vec<FbStatistik>> = self
+      .number_bytes
+      .iter()
+      .map(|number| vec![FbStatistik::new(); number * 8])
+      .collect();
+    let start = Instant::now();
+    let mut statistik_start = start;
     //Damit nur einmal gerechnet werden muss
-    let filter_grenzwert = self.repeat / 2;
+    let filter_grenzwert: Vec<usize> = self.repeats.iter().map(|repeat| repeat / 2).collect();
+    //Oszi Trigger FB's, können zur Laufzeit mit "SET <bus> FB TRIGGER" geändert werden
+    let mut trigger = self.trigger.clone();
     //Wenn Oszi Trigger konfiguriert sind: IO Port öffnen
     let mut trigger_port: Option<LineHandle> = None;
     if let Some(port) = self.trigger_port {
-      for trigger in &self.trigger {
-        if !trigger.is_empty() {
-          trigger_port = Some(
-            Chip::new("/dev/gpiochip0")
-              .expect("/dev/gpiochip0 konnte nicht geöffnet werden")
-              .get_line(port)
-              .expect("GPIO für S88 Oszi Trigger konnte nicht geöffnet werden")
-              .request(LineRequestFlags::OUTPUT, 1, "output_trigger_s88")
-              .expect("GPIO für S88 Oszi Trigger konnte nicht als Output geöffnet werden"),
-          );
-          break;
-        }
+      if trigger.iter().any(|fbs| !fbs.is_empty()) {
+        trigger_port = Some(S88::open_trigger_port(port).unwrap_or_else(|msg| panic!("{}", msg)));
+      }
+    }
+    //Bereit für alle belegten S88 Busse
+    for i in 0..self.number_bytes.len() {
+      if let Err(msg) = tx.send(SRCPMessage::new_ready(self.busnr + i)) {
+        warn!("S88 execute send Error, wird beendet: {}", msg);
+        return;
       }
     }
     //Und ab an die Arbeit, einlesen, auswerten, Veränderungen melden, warten und wieder von vorn ...
@@ -136,19 +690,34 @@ impl S88 {
       if trigger_port.is_some() {
         trigger_port.as_mut().unwrap().set_value(0).unwrap();
       }
-      //SPI Einlesen
-      for spi_bus in 0..MAX_S88 {
-        if spidevs[spi_bus].is_some() {
-          //Bus geöffnet vorhanden
-          spidevs[spi_bus]
-            .as_mut()
-            .unwrap()
-            .read(s88_input_buffer[spi_bus][akt_wiederhol_index].as_mut_slice())
-            .expect("S88 SPI read fail");
-          //Damit sicher alle CE Leitungen gemeinsam eine minimale Zeit auf 1 zurück sind zwischen den beiden Transfers etwas warten
-          thread::sleep(PAUSE_SPI_TRANSFER);
+      //SPI Einlesen, alle S88 Busse parallel in ihren Lesethreads
+      for ((leser, buffer), wiederhol_index) in leser
+        .iter()
+        .zip(s88_input_buffer.iter_mut())
+        .zip(akt_wiederhol_index.iter())
+      {
+        if let Some(leser) = leser {
+          let buffer = mem::take(&mut buffer[*wiederhol_index]);
+          leser
+            .tx
+            .send(S88LeseAuftrag::Lesen(buffer))
+            .expect("S88 Lesethread beendet");
         }
       }
+      for ((leser, buffer), wiederhol_index) in leser
+        .iter()
+        .zip(s88_input_buffer.iter_mut())
+        .zip(akt_wiederhol_index.iter())
+      {
+        if let Some(leser) = leser {
+          match leser.rx.recv() {
+            Ok(S88LeseAntwort::Gelesen(gelesen)) => buffer[*wiederhol_index] = gelesen,
+            _ => panic!("S88 Lesethread beendet"),
+          }
+        }
+      }
+      //Wird auf true gesetzt, wenn in diesem Zyklus (ungefilterte) Veränderungen erkannt wurden
+      let mut aktivitaet = false;
       //Mehrheitsentscheid über alle verlangten Wiederholungen
       //Damit nicht jedes mal geschoben werden muss, Bit Order wie von S88 -> LSB kommt zuerst
       const BIT_VALUES: [u8; 8] = [
@@ -163,51 +732,70 @@ impl S88 {
       ];
 
       //Über alle S88 Busse
-      for spi_bus in 0..MAX_S88 {
+      for spi_bus in 0..self.number_bytes.len() {
         //Über alle Bytes des Busses
         for byte_nr in 0..self.number_bytes[spi_bus] {
           //Über alle Bits im Byte
           for bit_nr in 0..8 {
             //Und noch die Wiederholungen
             let mut count: usize = 0;
-            for w in 0..self.repeat {
+            for w in 0..self.repeats[spi_bus] {
               if (s88_input_buffer[spi_bus][w][byte_nr] & BIT_VALUES[bit_nr]) != 0 {
                 count += 1;
               }
             }
-            let state = count > filter_grenzwert;
+            let state = count > filter_grenzwert[spi_bus];
             let fb_nr = byte_nr * 8 + bit_nr;
-            if state != s88_states[spi_bus][fb_nr] {
-              //Veränderung, senden
+            let geaendert = state != s88_states[spi_bus][fb_nr];
+            if geaendert {
+              //Veränderung, wird nach Auswertung aller FB's gemeldet
               s88_states[spi_bus][fb_nr] = state;
-              let msg = SRCPMessage::new(
-                None,
-                self.busnr + spi_bus, //die S88 Busse gehen auf unterschiedliche SRCP Busnummern
-                SRCPMessageID::Info {
-                  info_code: "100".to_string(),
-                },
-                SRCPMessageDevice::FB,
-                vec![(fb_nr + 1).to_string(), (state as usize).to_string()], //Nummerierung bei SRCP beginnt bei 1
-              );
-              match tx.send(msg) {
-                Err(msg) => {
-                  warn!("S88 execute send Error, wird beendet: {}", msg);
-                  break;
-                }
-                Ok(_) => {}
-              }
             }
-            //Wenn ein Trigger für diesen FB konfiguriert ist: bei jeder Veränderung (ohne Filter) gegenüber gespeichertem (gefiltertem) Wert senden.
-            if trigger_port.is_some()
-              && self.trigger[spi_bus].contains(&fb_nr)
-              && (s88_states[spi_bus][fb_nr]
-                != ((s88_input_buffer[spi_bus][akt_wiederhol_index][byte_nr] & BIT_VALUES[bit_nr])
-                  != 0))
-            {
+            //Veränderung (ohne Filter) gegenüber gespeichertem (gefiltertem) Wert?
+            let veraenderung = s88_states[spi_bus][fb_nr]
+              != ((s88_input_buffer[spi_bus][akt_wiederhol_index[spi_bus]][byte_nr]
+                & BIT_VALUES[bit_nr])
+                != 0);
+            if veraenderung {
+              aktivitaet = true;
+            }
+            fb_statistik[spi_bus][fb_nr].update(geaendert, veraenderung);
+            //Wenn ein Trigger für diesen FB konfiguriert ist: bei jeder Veränderung (ohne Filter) senden.
+            if trigger_port.is_some() && trigger[spi_bus].contains(&fb_nr) && veraenderung {
               trigger_port.as_mut().unwrap().set_value(1).unwrap();
             }
           }
         }
+        //Logische Blöcke aus den gefilterten Zuständen
+        let anz_fb = self.number_bytes[spi_bus] * 8;
+        for (block_nr, block) in self.blocks[spi_bus].iter().enumerate() {
+          let state = block.get_state(&s88_states[spi_bus]);
+          s88_states[spi_bus][anz_fb + block_nr] = state;
+        }
+        //Veränderungen melden, begrenzt auf info_max pro FB und Sekunde.
+        //Unterdrückte Veränderungen werden im nächsten Intervall mit dem dann aktuellen Zustand gemeldet.
+        let jetzt = Instant::now();
+        for (fb_nr, (state, drossel)) in s88_states[spi_bus]
+          .iter()
+          .zip(fb_info_drossel[spi_bus].iter_mut())
+          .enumerate()
+        {
+          if drossel.melden(*state, self.info_max_bus[spi_bus], jetzt) {
+            let msg = SRCPMessage::new(
+              None,
+              self.busnr + spi_bus, //die S88 Busse gehen auf unterschiedliche SRCP Busnummern
+              SRCPMessageID::Info {
+                info_code: "100".to_string(),
+              },
+              SRCPMessageDevice::FB,
+              vec![(fb_nr + 1).to_string(), (*state as usize).to_string()], //Nummerierung bei SRCP beginnt bei 1
+            );
+            if let Err(msg) = tx.send(msg) {
+              warn!("S88 execute send Error, wird beendet: {}", msg);
+              break;
+            }
+          }
+        }
       }
 
       //Prüfen ob neuer Info Client alle Daten haben muss
@@ -216,7 +804,7 @@ impl S88 {
           match msg {
             Message::NewInfoClient { session_id } => {
               //Neuer Info Client, alle Zustände senden, alle FB die true sind
-              for spi_bus in 0..MAX_S88 {
+              for spi_bus in 0..self.number_bytes.len() {
                 for fb_nr in 0..s88_states[spi_bus].len() {
                   let state = s88_states[spi_bus][fb_nr];
                   if state {
@@ -247,8 +835,20 @@ impl S88 {
                     && (srcp_message.parameter.len() > 0)
                   {
                     if let Ok(fb_nr) = srcp_message.parameter[0].parse::<usize>() {
-                      //SRCP Nummern beginnen bei 1
-                      if (fb_nr > 0) && (s88_states[srcp_message.bus - self.busnr].len() >= fb_nr) {
+                      let spi_bus = srcp_message.bus - self.busnr;
+                      //Lesestatistik, nur für reale FB's
+                      if srcp_message.parameter.get(1).map(String::as_str) == Some(FB_STATISTIK) {
+                        if let Some(statistik) = fb_nr
+                          .checked_sub(1)
+                          .and_then(|index| fb_statistik[spi_bus].get(index))
+                        {
+                          send_error = false;
+                          if !S88::send_info_statistik(&srcp_message, fb_nr, statistik, &tx) {
+                            break;
+                          }
+                        }
+                      } else if (fb_nr > 0) && (s88_states[spi_bus].len() >= fb_nr) {
+                        //SRCP Nummern beginnen bei 1
                         send_error = false;
                         if let Err(msg) = tx.send(SRCPMessage {
                           session_id: Some(srcp_message.session_id.unwrap()),
@@ -264,12 +864,95 @@ impl S88 {
                               "0".to_string()
                             },
                           ],
+                          weitere_folgen: false,
                         }) {
                           warn!("S88 execute send Error, wird beendet: {}", msg);
                           break;
                         }
                       }
                     }
+                  } else if (msg_type == SRCPMessageType::SET)
+                    && (srcp_message.device == SRCPMessageDevice::FB)
+                    && (srcp_message.parameter.first().map(String::as_str) == Some(FB_TRIGGER))
+                  {
+                    //Oszi Trigger dieses S88 Busses zur Laufzeit ändern, der GPIO gilt für alle S88 Busse
+                    send_error = false;
+                    let spi_bus = srcp_message.bus - self.busnr;
+                    let antwort = match S88::parse_trigger_cmd(
+                      &srcp_message.parameter[1..],
+                      self.number_bytes[spi_bus] * 8,
+                      self.trigger_port,
+                    ) {
+                      Ok((port, fbs)) => {
+                        info!(
+                          "S88 Bus {}: Oszi Trigger GPIO {} FB's {:?}",
+                          srcp_message.bus,
+                          port,
+                          fbs.iter().map(|fb_nr| fb_nr + 1).collect::<Vec<usize>>()
+                        );
+                        trigger[spi_bus] = fbs;
+                        //GPIO freigeben, wenn keine Trigger mehr vorhanden sind, nur öffnen wenn Trigger vorhanden sind
+                        if trigger.iter().all(|fbs| fbs.is_empty()) {
+                          trigger_port = None;
+                        } else if trigger_port.is_none() {
+                          trigger_port = S88::open_trigger_port(port)
+                            .inspect_err(|msg| warn!("{}", msg))
+                            .ok();
+                        }
+                        if trigger_port.is_some() || trigger[spi_bus].is_empty() {
+                          SRCPMessage::new_ok(&srcp_message, "200")
+                        } else {
+                          SRCPMessage::new_err(&srcp_message, "412", "wrong value")
+                        }
+                      }
+                      Err(msg) => {
+                        warn!("{}", msg);
+                        SRCPMessage::new_err(&srcp_message, "412", "wrong value")
+                      }
+                    };
+                    if let Err(msg) = tx.send(antwort) {
+                      warn!("S88 execute send Error, wird beendet: {}", msg);
+                      break;
+                    }
+                  } else if (msg_type == SRCPMessageType::VERIFY)
+                    && (srcp_message.device == SRCPMessageDevice::FB)
+                    && srcp_message.parameter.is_empty()
+                  {
+                    //Verdrahtungstest des S88 Busses
+                    let spi_bus = srcp_message.bus - self.busnr;
+                    if let Some(leser) = &leser[spi_bus] {
+                      send_error = false;
+                      leser
+                        .tx
+                        .send(S88LeseAuftrag::Diagnose(self.number_bytes[spi_bus]))
+                        .expect("S88 Lesethread beendet");
+                      let resultat = match leser.rx.recv() {
+                        Ok(S88LeseAntwort::Diagnose(resultat)) => resultat,
+                        _ => panic!("S88 Lesethread beendet"),
+                      };
+                      let antwort = match resultat {
+                        Ok(fehler) => {
+                          if fehler.is_empty() {
+                            info!("S88 Bus {}: Verdrahtungstest ok", srcp_message.bus);
+                            SRCPMessage::new_ok(&srcp_message, "200")
+                          } else {
+                            warn!(
+                              "S88 Bus {}: Verdrahtungstest fehlerhafte Bits: {:?}",
+                              srcp_message.bus, fehler
+                            );
+                            SRCPMessage::new_err(&srcp_message, "412", "wrong value")
+                          }
+                        }
+                        Err(msg) => {
+                          warn!("{}", msg);
+                          SRCPMessage::new_err(&srcp_message, "417", "timeout")
+                        }
+                      };
+                      if let Err(msg) = tx.send(antwort) {
+                        warn!("S88 execute send Error, wird beendet: {}", msg);
+                        break;
+                      }
+                    }
                   }
                 }
                 _ => {}
@@ -284,22 +967,49 @@ impl S88 {
                   },
                   device: SRCPMessageDevice::FB,
                   parameter: vec![],
+                  weitere_folgen: false,
                 }) {
                   warn!("S88 execute send Error, wird beendet: {}", msg);
                   break;
                 }
               }
             }
+            Message::SessionEnd { .. } => {} //Keine Session abhängigen Zustände
+            Message::Beenden => {}           //Lesethreads müssen nicht speziell beendet werden
+            Message::Pruefen { srcp_message } => {
+              //Keine Validierung ohne Ausführung
+              if let Err(msg) = tx.send(SRCPMessage::new_err(
+                &srcp_message,
+                "423",
+                "unsupported operation",
+              )) {
+                warn!("S88 execute send Error, wird beendet: {}", msg);
+                break;
+              }
+            }
           }
         }
         Err(_) => {} //Nichts empfangen
       }
+      //Periodische Log Ausgabe der Lesestatistik
+      if statistik_start.elapsed() >= STATISTIK_INTERVALL {
+        self.log_statistik(&mut fb_statistik, &s88_states, start);
+        statistik_start = Instant::now();
+      }
       //Nächster Filterplatz
-      akt_wiederhol_index += 1;
-      if akt_wiederhol_index >= self.repeat {
-        akt_wiederhol_index = 0;
+      for (wiederhol_index, repeat) in akt_wiederhol_index.iter_mut().zip(self.repeats.iter()) {
+        *wiederhol_index += 1;
+        if *wiederhol_index >= *repeat {
+          *wiederhol_index = 0;
+        }
       }
-      thread::sleep(Duration::from_millis(self.refresh));
+      //Adaptives Polling: bei Veränderungen schnell, sonst schrittweise bis auf "refresh" verlangsamen
+      refresh_akt = if aktivitaet {
+        self.refresh_min
+      } else {
+        (refresh_akt * 2).min(self.refresh)
+      };
+      thread::sleep(Duration::from_millis(refresh_akt));
     }
   }
 }
@@ -319,9 +1029,9 @@ impl SRCPServer for S88 {
   }
 
   /// Liefert die Anzahl SRCP Busse, die durch diesen Server belegt werden
-  /// S88: es werden 4 S88 Busse unterstützt.
+  /// S88: pro konfiguriertem S88 Bus ein SRCP Bus, erst nach "init" bekannt.
   fn get_srcp_bus_count(&self) -> usize {
-    MAX_S88
+    self.number_bytes.len()
   }
 
   /// Init dieses Servers
@@ -329,17 +1039,35 @@ impl SRCPServer for S88 {
   /// # Arguments
   /// * busnr - Die SRCP Busnummers die diesem Server zugeordner ist.
   /// * config_file_bus - Der diesen Bus betreffende Teil des Konfigfiles
+  ///
   /// S88 Bus hat folgende Konfigparameter:
-  /// refresh Refreshzeit in ms
-  /// spiport SPI Portname
-  /// spimode SPI Mode (1 wenn möglich, 2 mit Zusatzschaltung)
-  /// number_fb_1 Anzahl S88 Module (=16 Bit) an 1. S88 Bus
-  /// number_fb_2 Anzahl S88 Module (=16 Bit) an 2. S88 Bus
-  /// number_fb_3 Anzahl S88 Module (=16 Bit) an 3. S88 Bus
-  /// number_fb_4 Anzahl S88 Module (=16 Bit) an 4. S88 Bus
-  /// Optional:
-  /// trigger_fb_1, trigger_fb_2, trigger_fb_3, trigger_fb_4
-  /// mit Liste der FB's bei deren veränderung ein Oszi Triggerimpuls ausgegeben werden soll.
+  /// * refresh - Refreshzeit in ms
+  /// * refresh_min - Optional minimale Refreshzeit in ms solange Veränderungen erkannt werden
+  ///   (Default = refresh). Ohne Veränderungen wird die Refreshzeit schrittweise bis auf "refresh" verlängert.
+  /// * spiport - SPI Portname
+  /// * spimode - SPI Mode (1 wenn möglich, 2 mit Zusatzschaltung)
+  /// * spi_hz - Optional Frequenz SPI Bus in Hz (Default 20000), der effektive Clock wird beim Start geloggt.
+  /// * core_freq - Optional core_freq in Hz, ohne Angabe über debugfs ermittelt.
+  /// * spi_soft - Optional: liegt spi_hz unter dem Hardware Minimum, wird die Frequenz durch Pausen zwischen
+  ///   den Bytes im Mittel erreicht (lange S88 Ketten).
+  /// * number_fb_1 - Anzahl S88 Module (=16 Bit) an 1. S88 Bus
+  /// * number_fb_2 - Anzahl S88 Module (=16 Bit) an 2. S88 Bus
+  /// * ... number_fb_n - fortlaufend für alle weiteren S88 Busse (n = SPI Chip Select).
+  ///   Pro S88 Bus wird ein SRCP Bus belegt. S88 Busse ohne Module am Ende der Liste belegen keinen SRCP Bus.
+  /// * repeat_1 .. repeat_n - Optional Anzahl Wiederholungen für Filterung pro S88 Bus (Default "repeat")
+  /// * trigger_fb_1 .. trigger_fb_n - Optional Liste der FB's bei deren veränderung ein Oszi Triggerimpuls
+  ///   ausgegeben werden soll.
+  /// * block_fb_n_1, block_fb_n_2 .. - Optional logische Blöcke auf S88 Bus n, "or:FB,FB,.." oder "and:FB,FB,..".
+  ///   Diese werden als zusätzliche FB's im Anschluss an die realen FB's des Busses gemeldet
+  ///   (block_fb_n_1 = Anzahl FB's + 1, ..).
+  /// * stuck_warn - Optional Zeit in s, belegte FB's ohne Veränderung während dieser Zeit werden periodisch
+  ///   geloggt.
+  ///
+  /// Mit "VERIFY <bus> FB" kann ein Verdrahtungstest ausgeführt werden, siehe "diagnose".
+  /// Mit "SET <bus> FB TRIGGER <trigger_port> [<fb>,<fb>..]" werden die Oszi Trigger FB's eines S88 Busses
+  /// zur Laufzeit geändert, ohne FB's wird der Trigger des Busses ausgeschaltet.
+  /// Mit "GET <bus> FB <addr> STATS" wird die Lesestatistik eines FB abgefragt, FB's mit Glitches werden
+  /// periodisch geloggt.
   fn init(
     &mut self, busnr: usize, config_file_bus: &HashMap<String, Option<String>>,
   ) -> Result<(), String> {
@@ -352,6 +1080,18 @@ impl SRCPServer for S88 {
       .parse::<u64>()
       .ok()
       .ok_or("S88 refresh muss eine Zahl sein")?;
+    self.refresh_min = match config_file_bus.get("refresh_min") {
+      Some(refresh_min) => refresh_min
+        .clone()
+        .ok_or("S88: refresh_min Parameter ohne Wert")?
+        .parse::<u64>()
+        .ok()
+        .ok_or("S88 refresh_min muss eine Zahl sein")?,
+      None => self.refresh,
+    };
+    if (self.refresh_min == 0) || (self.refresh_min > self.refresh) {
+      Err("S88 refresh_min muss > 0 und <= refresh sein")?;
+    }
     self.repeat = config_file_bus
       .get("repeat")
       .ok_or("S88: repeat Parameter nicht vorhanden")?
@@ -360,11 +1100,11 @@ impl SRCPServer for S88 {
       .parse::<usize>()
       .ok()
       .ok_or("S88 repeat muss eine Zahl sein")?;
-    self.spiport = config_file_bus
-      .get("spiport")
-      .ok_or("S88: spiport Parameter nicht vorhanden")?
-      .clone()
-      .ok_or("S88: spiport Parameter ohne Wert")?;
+    //Ohne Angabe Default SPI Device der erkannten Hardware
+    self.spiport = match config_file_bus.get("spiport") {
+      Some(spiport) => spiport.clone().ok_or("S88: spiport Parameter ohne Wert")?,
+      None => srcp_hardware::get_spiport_s88().to_string(),
+    };
     self.spimode = config_file_bus
       .get("spimode")
       .ok_or("S88: spimode Parameter nicht vorhanden")?
@@ -378,18 +1118,76 @@ impl SRCPServer for S88 {
     {
       Err("S88 spimode muss 1 oder 2 sein")?;
     }
-    for i in 0..self.number_bytes.len() {
-      //Anzahl S88 Module pro S88 Bus
-      let name = format!("number_fb_{}", i + 1);
-      self.number_bytes[i] = config_file_bus
-        .get(&name)
-        .ok_or(format!("S88: {} Parameter nicht vorhanden", name))?
+    self.spi_hz = match config_file_bus.get("spi_hz") {
+      Some(spi_hz) => spi_hz
         .clone()
-        .ok_or(format!("S88: {} Parameter ohne Wert", name))?
-        .parse::<usize>()
+        .ok_or("S88: spi_hz Parameter ohne Wert")?
+        .parse::<u32>()
         .ok()
-        .ok_or(format!("S88 {} muss eine Zahl sein", name))?
-        * 2; //16 Bit pro S88 Modul
+        .filter(|spi_hz| (1..=SPI_HZ_MAX).contains(spi_hz))
+        .ok_or(format!(
+          "S88 spi_hz muss eine Zahl von 1 bis {} sein",
+          SPI_HZ_MAX
+        ))?,
+      None => SPI_HZ_DEFAULT,
+    };
+    self.core_freq = match config_file_bus.get("core_freq") {
+      Some(core_freq) => Some(
+        core_freq
+          .clone()
+          .ok_or("S88: core_freq Parameter ohne Wert")?
+          .parse::<u64>()
+          .ok()
+          .filter(|core_freq| *core_freq > 0)
+          .ok_or("S88 core_freq muss eine Zahl > 0 (Hz) sein")?,
+      ),
+      None => None,
+    };
+    self.spi_soft = config_file_bus.get("spi_soft").is_some();
+    self.stuck_warn = match config_file_bus.get("stuck_warn") {
+      Some(stuck_warn) => Some(Duration::from_secs(
+        stuck_warn
+          .clone()
+          .ok_or("S88: stuck_warn Parameter ohne Wert")?
+          .parse::<u64>()
+          .ok()
+          .filter(|stuck_warn| *stuck_warn > 0)
+          .ok_or("S88 stuck_warn muss eine Zahl > 0 (s) sein")?,
+      )),
+      None => None,
+    };
+    self.info_max = match config_file_bus.get("info_max") {
+      Some(info_max) => Some(
+        info_max
+          .clone()
+          .ok_or("S88: info_max Parameter ohne Wert")?
+          .parse::<u32>()
+          .ok()
+          .filter(|info_max| *info_max > 0)
+          .ok_or("S88 info_max muss eine Zahl > 0 sein")?,
+      ),
+      None => None,
+    };
+    self.number_bytes.clear();
+    self.repeats.clear();
+    self.info_max_bus.clear();
+    self.trigger.clear();
+    self.blocks.clear();
+    //Alle fortlaufend konfigurierten S88 Busse
+    let mut i: usize = 0;
+    while let Some(number_fb) = config_file_bus.get(&format!("number_fb_{}", i + 1)) {
+      //Anzahl S88 Module pro S88 Bus
+      let name = format!("number_fb_{}", i + 1);
+      self.trigger.push(vec![]);
+      self.number_bytes.push(
+        number_fb
+          .clone()
+          .ok_or(format!("S88: {} Parameter ohne Wert", name))?
+          .parse::<usize>()
+          .ok()
+          .ok_or(format!("S88 {} muss eine Zahl sein", name))?
+          * 2, //16 Bit pro S88 Modul
+      );
       if self.number_bytes[i] > S88_MAXPORTSB {
         warn!(
           "S88: Max. {} pro Bus wird unterstützt. Konfiguriert für Bus {} sind {}.",
@@ -399,6 +1197,32 @@ impl SRCPServer for S88 {
         );
         self.number_bytes[i] = S88_MAXPORTSB;
       }
+      //Optionale Anzahl Wiederholungen pro S88 Bus
+      let name = format!("repeat_{}", i + 1);
+      self.repeats.push(match config_file_bus.get(&name) {
+        Some(repeat) => repeat
+          .clone()
+          .ok_or(format!("S88: {} Parameter ohne Wert", name))?
+          .parse::<usize>()
+          .ok()
+          .filter(|repeat| *repeat > 0)
+          .ok_or(format!("S88 {} muss eine Zahl > 0 sein", name))?,
+        None => self.repeat,
+      });
+      //Optionale Begrenzung INFO Meldungen pro S88 Bus
+      let name = format!("info_max_{}", i + 1);
+      self.info_max_bus.push(match config_file_bus.get(&name) {
+        Some(info_max) => Some(
+          info_max
+            .clone()
+            .ok_or(format!("S88: {} Parameter ohne Wert", name))?
+            .parse::<u32>()
+            .ok()
+            .filter(|info_max| *info_max > 0)
+            .ok_or(format!("S88 {} muss eine Zahl > 0 sein", name))?,
+        ),
+        None => self.info_max,
+      });
       //Optionale Oszi Trigger pro S88 Bus
       if let Some(trigger_port_option) = config_file_bus.get("trigger_port") {
         if let Some(trigger_port_port) = trigger_port_option {
@@ -411,14 +1235,14 @@ impl SRCPServer for S88 {
                 for trigger in trigger_fb.split(",") {
                   if let Ok(fb_nr) = trigger.parse::<usize>() {
                     //Auf SRCP beginnen die FB Nummern bei 1
-                    if (fb_nr > 0) && (fb_nr <= self.number_bytes[i] * 16) {
+                    if (fb_nr > 0) && (fb_nr <= self.number_bytes[i] * 8) {
                       self.trigger[i].push(fb_nr - 1);
                     } else {
                       warn!(
                         "S88 Bus {}: Ungültige Trigger Konfiguration FB Nummer: {}. Erlaubt 1 bis {}.",
                         i + 1,
                         trigger,
-                        self.number_bytes[i] * 16
+                        self.number_bytes[i] * 8
                       );
                     }
                   } else {
@@ -436,6 +1260,31 @@ impl SRCPServer for S88 {
           }
         }
       }
+      //Optionale logische Blöcke pro S88 Bus
+      let mut blocks: Vec<FbBlock> = Vec::new();
+      while let Some(block_option) =
+        config_file_bus.get(&format!("block_fb_{}_{}", i + 1, blocks.len() + 1))
+      {
+        let block_config = block_option.clone().ok_or(format!(
+          "S88: block_fb_{}_{} ohne Wert",
+          i + 1,
+          blocks.len() + 1
+        ))?;
+        blocks.push(FbBlock::from(&block_config, self.number_bytes[i] * 8)?);
+      }
+      self.blocks.push(blocks);
+      i += 1;
+    }
+    //S88 Busse ohne Module am Ende belegen keine SRCP Busse
+    while self.number_bytes.last() == Some(&0) {
+      self.number_bytes.pop();
+      self.repeats.pop();
+      self.info_max_bus.pop();
+      self.trigger.pop();
+      self.blocks.pop();
+    }
+    if self.number_bytes.is_empty() {
+      Err("S88: kein S88 Bus konfiguriert (number_fb_1 ..)")?;
     }
     Ok(())
   }
@@ -444,11 +1293,16 @@ impl SRCPServer for S88 {
   /// # Arguments
   /// * rx - Channel Receiver über denn Kommandos empfangen werden
   /// * tx - Channel Sender über den Info Messages zurück gesendet werden können
-  fn start(&self, rx: Receiver<Message>, tx: Sender<SRCPMessage>) {
+  fn start(&self, rx: Receiver<Message>, tx: SyncSender<SRCPMessage>) {
     let instanz = self.clone();
-    thread::Builder::new()
-      .name("S88_Thread".to_string())
-      .spawn(move || instanz.execute(rx, tx))
-      .unwrap();
+    spawn_server_thread(
+      "S88_Thread",
+      self.busnr..self.busnr + self.get_srcp_bus_count(),
+      tx,
+      move |tx| {
+        instanz.execute(rx, tx);
+        Ok(())
+      },
+    );
   }
 }
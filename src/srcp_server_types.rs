@@ -1,11 +1,29 @@
 //! globale Definitionen für alle SRCP-Server
 use std::{
   collections::HashMap,
-  sync::mpsc::{Receiver, Sender},
+  ops::Range,
+  panic::{self, AssertUnwindSafe},
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc::{Receiver, Sender, SyncSender},
+    Arc,
+  },
+  thread,
 };
 
+use log::error;
+use splitty::split_unquoted_char;
+
+/// Präfix für Kommandos, die nur validiert und nicht ausgeführt werden (srcpd_rust Erweiterung):
+/// CHECK <cmd> <busnr> <dev_group> [<param1> [<param2> ....]]
+pub const PRUEFEN: &str = "CHECK";
+/// INFO Code für Antworten auf srcpd_rust Erweiterungen von bestehenden Devices (z.B. "GET <bus> GL <addr> STATS").
+/// Damit werden diese nie mit Zuständen (INFO 100) oder INIT Parametern (INFO 101) verwechselt.
+pub const INFO_ERWEITERUNG: &str = "190";
+
 ///SRCP Message
 #[derive(Clone, Debug, PartialEq, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum SRCPMessageType {
   GET,
   SET,
@@ -28,6 +46,7 @@ impl ToString for SRCPMessageType {
 
 /// SRCP Message Angaben, Kommando oder Info (an einen oder alle)
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum SRCPMessageID {
   Info {
     //Info an einen oder alle SRCP Info Clients
@@ -46,6 +65,12 @@ pub enum SRCPMessageID {
     err_code: String,
     err_text: String,
   },
+  //SRCP Server ist nach dem Öffnen der Hardware bereit Kommandos auszuführen.
+  //Nur intern an srcp, wird nicht an Clients gesendet.
+  Ready,
+  //SRCP Server Thread ist durch panic ausgefallen, Grund im ersten Parameter.
+  //Nur intern an srcp, Clients erhalten eine GM Info.
+  Failed,
 }
 impl ToString for SRCPMessageID {
   fn to_string(&self) -> String {
@@ -54,6 +79,8 @@ impl ToString for SRCPMessageID {
       SRCPMessageID::Command { msg_type } => msg_type.to_string(),
       SRCPMessageID::Ok { ok_code } => ok_code.to_owned() + " OK",
       SRCPMessageID::Err { err_code, err_text } => err_code.to_owned() + " ERROR " + err_text,
+      SRCPMessageID::Ready => "READY".to_string(),
+      SRCPMessageID::Failed => "FAILED".to_string(),
     }
   }
 }
@@ -69,11 +96,14 @@ impl SRCPMessageID {
         err_code: _,
         err_text: _,
       } => true,
+      SRCPMessageID::Ready => true,
+      SRCPMessageID::Failed => true,
     }
   }
 }
 
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum SRCPMessageDevice {
   //Generic Accessory
   GA,
@@ -87,6 +117,8 @@ pub enum SRCPMessageDevice {
   Power,
   //Generic Message (für Zustandsmeldungen über Lokanmeldung vom Server, ansonsten wird GM nicht unterstützt)
   GM,
+  //Abfrage Serverfähigkeiten (srcpd_rust Erweiterung, z.B. vorhandene Protokolle)
+  Server,
 }
 impl ToString for SRCPMessageDevice {
   fn to_string(&self) -> String {
@@ -97,18 +129,40 @@ impl ToString for SRCPMessageDevice {
       SRCPMessageDevice::SM => "SM".to_string(),
       SRCPMessageDevice::Power => "POWER".to_string(),
       SRCPMessageDevice::GM => "GM".to_string(),
+      SRCPMessageDevice::Server => "SERVER".to_string(),
+    }
+  }
+}
+impl SRCPMessageDevice {
+  /// Liefert true, wenn es sich um eine srcpd_rust spezifische Erweiterung handelt, die über SRCP 0.8.4
+  /// hinausgeht. Diese werden nur für Clients unterstützt, die mit "SET PROTOCOL SRCP <version>" die
+  /// aktuelle Version verlangt haben. Damit werden ältere Clients nicht durch unbekannte INFO's verwirrt.
+  pub fn is_erweiterung(&self) -> bool {
+    match &self {
+      SRCPMessageDevice::GA
+      | SRCPMessageDevice::GL
+      | SRCPMessageDevice::FB
+      | SRCPMessageDevice::SM
+      | SRCPMessageDevice::Power
+      | SRCPMessageDevice::GM => false,
+      SRCPMessageDevice::Server => true,
     }
   }
 }
 
 /// Eigentliche SRCP Message
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SRCPMessage {
   pub session_id: Option<u32>, //Von, an Client mit dieser Session ID, wenn bei Info nicht angegeben: an alle Info Clients
   pub bus: usize,
   pub message_id: SRCPMessageID,
   pub device: SRCPMessageDevice,
   pub parameter: Vec<String>,
+  /// true: Zwischenantwort auf ein Kommando, es folgen weitere Antworten.
+  /// Die letzte Antwort auf ein Kommando hat immer false.
+  #[cfg_attr(feature = "serde", serde(default))]
+  pub weitere_folgen: bool,
 }
 impl SRCPMessage {
   /// Neue SRCPMessage erstellen
@@ -122,6 +176,7 @@ impl SRCPMessage {
       message_id,
       device,
       parameter,
+      weitere_folgen: false,
     }
   }
   /// Neue SRCPMessage Ok erstellen
@@ -137,6 +192,7 @@ impl SRCPMessage {
       },
       device: msg.device.clone(),
       parameter: vec![],
+      weitere_folgen: false,
     }
   }
   /// Neue SRCPMessage Error erstellen
@@ -154,6 +210,81 @@ impl SRCPMessage {
       },
       device: msg.device.clone(),
       parameter: vec![],
+      weitere_folgen: false,
+    }
+  }
+  /// Neue SRCPMessage Error mit ergänztem Freitext erstellen, z.B. mit dem fehlerhaften Parameter.
+  /// Format: <err_code> ERROR <err_text> (<detail>)
+  /// # Arguments
+  /// * msg - Kommandomessage aus der Session, Bus, Device kopiert werden.
+  /// * err_code - Zu verwendender Error Code
+  /// * err_text - Zu verwendender Errortext
+  /// * detail - Ergänzung zum Errortext
+  pub fn new_err_detail(
+    msg: &SRCPMessage, err_code: &'static str, err_text: &'static str, detail: String,
+  ) -> SRCPMessage {
+    let mut err = SRCPMessage::new_err(msg, err_code, err_text);
+    err.message_id = SRCPMessageID::Err {
+      err_code: err_code.to_string(),
+      err_text: format!("{} ({})", err_text, detail),
+    };
+    err
+  }
+  /// Neue SRCPMessage Ready erstellen, damit meldet ein SRCP Server, dass er nach dem Start bereit ist.
+  /// # Arguments
+  /// * bus - Der bereite SRCP Bus
+  pub fn new_ready(bus: usize) -> SRCPMessage {
+    SRCPMessage {
+      session_id: None,
+      bus,
+      message_id: SRCPMessageID::Ready,
+      device: SRCPMessageDevice::Server,
+      parameter: vec![],
+      weitere_folgen: false,
+    }
+  }
+  /// Neue SRCPMessage Failed erstellen, damit wird der Ausfall eines SRCP Servers gemeldet.
+  /// # Arguments
+  /// * bus - Der ausgefallene SRCP Bus
+  /// * grund - Grund des Ausfalls
+  pub fn new_failed(bus: usize, grund: String) -> SRCPMessage {
+    SRCPMessage {
+      session_id: None,
+      bus,
+      message_id: SRCPMessageID::Failed,
+      device: SRCPMessageDevice::Server,
+      parameter: vec![grund],
+      weitere_folgen: false,
+    }
+  }
+  /// Liefert true, wenn es sich um eine srcpd_rust spezifische Erweiterung handelt (siehe
+  /// "SRCPMessageDevice::is_erweiterung"). Das ist der Fall für Erweiterungsdevices, für
+  /// Kommandos, die SRCP 0.8.4 für ein bestehendes Device nicht kennt (z.B. "VERIFY <bus> FB"),
+  /// und für deren Antworten mit "INFO_ERWEITERUNG".
+  pub fn is_erweiterung(&self) -> bool {
+    if self.device.is_erweiterung() {
+      return true;
+    }
+    let parameter = |index: usize| self.parameter.get(index).map(String::as_str);
+    match (&self.message_id, &self.device) {
+      (SRCPMessageID::Info { info_code }, _) => info_code == INFO_ERWEITERUNG,
+      //VERIFY <bus> FB, GET <bus> FB <addr> STATS, SET <bus> FB TRIGGER ..
+      (SRCPMessageID::Command { msg_type }, SRCPMessageDevice::FB) => {
+        matches!(msg_type, SRCPMessageType::VERIFY)
+          || (matches!(msg_type, SRCPMessageType::GET) && (parameter(1) == Some("STATS")))
+          || (matches!(msg_type, SRCPMessageType::SET) && (parameter(0) == Some("TRIGGER")))
+      }
+      //GET|TERM <bus> GA 0 DELAYED
+      (SRCPMessageID::Command { msg_type }, SRCPMessageDevice::GA) => {
+        matches!(msg_type, SRCPMessageType::GET | SRCPMessageType::TERM)
+          && (parameter(0) == Some("0"))
+          && (parameter(1) == Some("DELAYED"))
+      }
+      //GET <bus> GL <addr> STATS|INIT
+      (SRCPMessageID::Command { msg_type }, SRCPMessageDevice::GL) => {
+        matches!(msg_type, SRCPMessageType::GET) && matches!(parameter(1), Some("STATS" | "INIT"))
+      }
+      _ => false,
     }
   }
   /// Neue SRCPMessage Command aus String erstellen.
@@ -188,11 +319,46 @@ impl SRCPMessage {
         "FB" => SRCPMessageDevice::FB,
         "SM" => SRCPMessageDevice::SM,
         "POWER" => SRCPMessageDevice::Power,
+        "SERVER" => SRCPMessageDevice::Server,
         &_ => return Err(("421", "unsupported device")),
       },
       parameter: cmd[3..].iter().map(|s| s.to_string()).collect(),
+      weitere_folgen: false,
     })
   }
+  /// Neue SRCPMessage Command aus einer empfangenen Kommandozeile erstellen.
+  /// Jedes Kommando muss folgendes Format haben:
+  /// <cmd> <busnr> <dev_group> [<param1> [<param2> ....]]
+  /// Die Teile werden an Spaces getrennt, Teile in Anführungszeichen bleiben ohne diese zusammen.
+  /// Return Err wie "from".
+  /// # Arguments
+  /// * session_id - Die Session, über die dieses Kommando empfangen wurde
+  /// * line - Empfangene Kommandozeile ohne \n
+  pub fn parse(session_id: u32, line: &str) -> Result<SRCPMessage, (&'static str, &'static str)> {
+    let cmd_parts: Vec<&str> = split_unquoted_char(line, ' ').unwrap_quotes(true).collect();
+    SRCPMessage::from(session_id, &cmd_parts)
+  }
+  /// Neue SRCPMessage Ok für ein erfolgreich validiertes, nicht ausgeführtes Kommando (Message::Pruefen)
+  /// erstellen. Damit schliesst der SRCP Server die Prüfung ab, auch wenn die Validierung selbst bereits
+  /// ein Ok gesendet hat.
+  /// # Arguments
+  /// * msg - Geprüftes Kommando aus dem Session, Bus, Device kopiert werden.
+  pub fn new_pruefen_ok(msg: &SRCPMessage) -> SRCPMessage {
+    let mut ok = SRCPMessage::new_ok(msg, "200");
+    ok.parameter.push(PRUEFEN.to_string());
+    ok
+  }
+  /// Liefert true wenn dies der Abschluss einer erfolgreichen Prüfung ist (siehe "new_pruefen_ok")
+  pub fn is_pruefen_ok(&self) -> bool {
+    matches!(self.message_id, SRCPMessageID::Ok { .. })
+      && (self.parameter.first().map(String::as_str) == Some(PRUEFEN))
+  }
+  /// Diese Message als Zwischenantwort markieren, auf das Kommando folgen weitere Antworten.
+  /// Wird von SRCP Servern verwendet, die auf ein Kommando mehrere Zeilen antworten.
+  pub fn mit_weiteren_antworten(mut self) -> SRCPMessage {
+    self.weitere_folgen = true;
+    self
+  }
   /// Liefert die Adresse des Kommandos.
   /// Das ist, egal ob GA, GL immer der erste Parameter
   /// Return Err wenn keine Adresse vorhanden
@@ -236,24 +402,185 @@ pub enum Message {
   SRCPMessage { srcp_message: SRCPMessage },
   //Information an SRCP Server dass ein neuer Info Client vorhanden ist -> allen aktuellen Zustände and diesen senden
   NewInfoClient { session_id: u32 },
+  //Information an SRCP Server dass eine Kommando Session beendet wurde -> z.B. Besitz an GL's freigeben
+  SessionEnd { session_id: u32 },
+  //SRCP Message nur validieren, nicht ausführen. Antwort ist der Fehler der Validierung oder "new_pruefen_ok".
+  //Server ohne Validierung antworten mit "423 ERROR unsupported operation".
+  Pruefen { srcp_message: SRCPMessage },
+  //Information an SRCP Server dass der srcpd beendet wird -> Hilfsthreads (z.B. MFX RDS) beenden
+  Beenden,
 }
 impl Message {
   pub fn new_info_client(session_id: u32) -> Message {
     Message::NewInfoClient { session_id }
   }
+  pub fn new_session_end(session_id: u32) -> Message {
+    Message::SessionEnd { session_id }
+  }
   pub fn new_srcpmessage(srcp_message: SRCPMessage) -> Message {
     Message::SRCPMessage { srcp_message }
   }
+  pub fn new_pruefen(srcp_message: SRCPMessage) -> Message {
+    Message::Pruefen { srcp_message }
+  }
+  pub fn new_beenden() -> Message {
+    Message::Beenden
+  }
 }
 impl ToString for Message {
   fn to_string(&self) -> String {
     match self {
       Message::SRCPMessage { srcp_message } => srcp_message.to_string(),
       Message::NewInfoClient { session_id } => format!("NewInfoClient session_id={}", session_id),
+      Message::SessionEnd { session_id } => format!("SessionEnd session_id={}", session_id),
+      Message::Pruefen { srcp_message } => format!("{} {}", PRUEFEN, srcp_message.to_string()),
+      Message::Beenden => "Beenden".to_string(),
     }
   }
 }
 
+/// Sender eines unbegrenzten (mpsc::channel) oder begrenzten (mpsc::sync_channel) Kanals
+pub enum KanalSender<T> {
+  Unbegrenzt(Sender<T>),
+  /// Ist der Kanal voll, dann wartet "send" bis wieder Platz vorhanden ist
+  Begrenzt(SyncSender<T>),
+}
+impl<T> Clone for KanalSender<T> {
+  fn clone(&self) -> Self {
+    match self {
+      KanalSender::Unbegrenzt(tx) => KanalSender::Unbegrenzt(tx.clone()),
+      KanalSender::Begrenzt(tx) => KanalSender::Begrenzt(tx.clone()),
+    }
+  }
+}
+impl<T> From<Sender<T>> for KanalSender<T> {
+  fn from(tx: Sender<T>) -> Self {
+    KanalSender::Unbegrenzt(tx)
+  }
+}
+impl<T> From<SyncSender<T>> for KanalSender<T> {
+  fn from(tx: SyncSender<T>) -> Self {
+    KanalSender::Begrenzt(tx)
+  }
+}
+
+/// Sender für die Kommunikation zwischen Threads (z.B. DCC Prog Thread, MFX RDS Thread, DDL Bus an
+/// SRCP Dispatcher), in beide Richtungen verwendet.
+/// Ist der Empfänger nicht mehr vorhanden (Thread abgestürzt oder beendet), wird nicht der ganze
+/// Daemon beendet, sondern der Kanal als gestört markiert. Die Störung wird einmal geloggt und kann
+/// mit "take_stoerung" einmal abgeholt werden, um sie den SRCP Info Clients zu melden.
+/// Alle Kopien (clone) teilen sich den Störungszustand.
+pub struct ThreadSender<T> {
+  /// Sender des Kanals
+  tx: KanalSender<T>,
+  /// Name des Kanals für Log und Störungsmeldung
+  name: &'static str,
+  /// Empfänger nicht mehr vorhanden
+  gestoert: Arc<AtomicBool>,
+  /// Störung wurde mit "take_stoerung" bereits abgeholt
+  gemeldet: Arc<AtomicBool>,
+}
+impl<T> Clone for ThreadSender<T> {
+  fn clone(&self) -> Self {
+    ThreadSender {
+      tx: self.tx.clone(),
+      name: self.name,
+      gestoert: self.gestoert.clone(),
+      gemeldet: self.gemeldet.clone(),
+    }
+  }
+}
+impl<T> ThreadSender<T> {
+  /// Neue Instanz
+  /// # Arguments
+  /// * tx - Sender des Kanals, unbegrenzt oder begrenzt
+  /// * name - Name des Kanals für Log und Störungsmeldung
+  pub fn new(tx: impl Into<KanalSender<T>>, name: &'static str) -> ThreadSender<T> {
+    ThreadSender {
+      tx: tx.into(),
+      name,
+      gestoert: Arc::new(AtomicBool::new(false)),
+      gemeldet: Arc::new(AtomicBool::new(false)),
+    }
+  }
+
+  /// Sendet "msg", liefert false wenn der Empfänger nicht mehr vorhanden ist.
+  /// # Arguments
+  /// * msg - Zu sendende Nachricht
+  pub fn send(&self, msg: T) -> bool {
+    let result = match &self.tx {
+      KanalSender::Unbegrenzt(tx) => tx.send(msg),
+      KanalSender::Begrenzt(tx) => tx.send(msg),
+    };
+    if result.is_err() {
+      self.set_gestoert();
+    }
+    !self.is_gestoert()
+  }
+
+  /// Kanal als gestört markieren, z.B. auch wenn der zugehörige Empfangskanal geschlossen wurde.
+  pub fn set_gestoert(&self) {
+    if !self.gestoert.swap(true, Ordering::Relaxed) {
+      error!("{}: Thread nicht mehr vorhanden, Kanal gestört", self.name);
+    }
+  }
+
+  /// Liefert true wenn der Empfänger nicht mehr vorhanden ist
+  pub fn is_gestoert(&self) -> bool {
+    self.gestoert.load(Ordering::Relaxed)
+  }
+
+  /// Liefert einmalig nach Auftreten einer Störung die Störungsmeldung, sonst None.
+  pub fn take_stoerung(&self) -> Option<String> {
+    if self.is_gestoert() && !self.gemeldet.swap(true, Ordering::Relaxed) {
+      Some(format!("{} gestört", self.name))
+    } else {
+      None
+    }
+  }
+}
+
+/// Thread eines SRCP Servers starten.
+/// Ein panic im Thread oder ein Abbruch beim Start (execute liefert Err) wird für alle belegten Busse mit
+/// "SRCPMessage::new_failed" gemeldet.
+/// Damit werden Kommandos an diese Busse sofort mit Error beantwortet, alle anderen Busse bleiben in Betrieb.
+/// # Arguments
+/// * name - Name des Threads
+/// * busse - Alle durch den Server belegten SRCP Busse
+/// * tx - Channel Sender über den Info Messages zurück gesendet werden können
+/// * execute - Hauptschleife des Servers, erhält tx. Err mit Grund wenn der Server nicht gestartet werden konnte.
+pub fn spawn_server_thread(
+  name: &str, busse: Range<usize>, tx: SyncSender<SRCPMessage>,
+  execute: impl FnOnce(SyncSender<SRCPMessage>) -> Result<(), String> + Send + 'static,
+) {
+  let thread_name = name.to_string();
+  thread::Builder::new()
+    .name(thread_name.clone())
+    .spawn(move || {
+      let tx_failed = tx.clone();
+      let grund = match panic::catch_unwind(AssertUnwindSafe(|| execute(tx))) {
+        Ok(Ok(())) => return,
+        Ok(Err(text)) => {
+          error!("{} Abbruch.", text);
+          text
+        }
+        Err(panic) => panic
+          .downcast_ref::<&str>()
+          .map(|text| text.to_string())
+          .or_else(|| panic.downcast_ref::<String>().cloned())
+          .unwrap_or_default(),
+      };
+      for bus in busse {
+        //Wenn auch srcp nicht mehr vorhanden ist gibt es niemanden mehr zu informieren
+        let _ = tx_failed.send(SRCPMessage::new_failed(
+          bus,
+          format!("{}: {}", thread_name, grund),
+        ));
+      }
+    })
+    .unwrap();
+}
+
 /// Schnittstelle, die alle SRCP Server implementieren müssen
 pub trait SRCPServer {
   /// Liefert den Name des SRCP Servers zurück
@@ -275,9 +602,14 @@ pub trait SRCPServer {
   fn init(
     &mut self, busnr: usize, config_file_bus: &HashMap<String, Option<String>>,
   ) -> Result<(), String>;
-  /// Start dieses Servers
+  /// Start dieses Servers, der Thread des Servers muss mit "spawn_server_thread" gestartet werden.
+  /// Sobald der Server bereit ist Kommandos auszuführen (z.B. Hardware geöffnet), muss er für jeden
+  /// belegten SRCP Bus "SRCPMessage::new_ready" über tx senden. Bis dahin werden Kommandos an diesen Bus
+  /// verzögert und nach Ablauf der Startfrist mit 417 abgelehnt. Kann der Server nicht gestartet werden,
+  /// dann muss seine Hauptschleife Err liefern, damit der Ausfall der Busse gemeldet wird.
   /// # Arguments
   /// * rx - Channel Receiver über denn Kommandos empfangen werden
-  /// * tx - Channel Sender über den Info Messages zurück gesendet werden können
-  fn start(&self, rx: Receiver<Message>, tx: Sender<SRCPMessage>);
+  /// * tx - Channel Sender über den Info Messages zurück gesendet werden können, begrenzt auf
+  ///   srcp::SERVER_INFO_QUEUE_MAX. Ist er voll, dann wartet "send" bis der Dispatcher wieder Platz hat.
+  fn start(&self, rx: Receiver<Message>, tx: SyncSender<SRCPMessage>);
 }